@@ -7,6 +7,7 @@ export RUST_BACKTRACE=1
 
 cd nwind
 cargo test --features local-unwinding --verbose
+cargo test --features serialization --verbose
 cargo test --features local-unwinding --release --verbose
 cargo build --verbose
 cargo test --verbose
@@ -21,18 +22,28 @@ cd ..
 
 cd proc-maps
 cargo test --verbose
+cargo check --features serialization --verbose
 cd ..
 
 cargo test --verbose
+cargo test --features scripting --verbose
 
 cd cli
 cargo build --verbose
 cd ..
 
+cd python
+cargo build --verbose
+cd ..
+
 cargo check --no-default-features --verbose
+cargo check --no-default-features --features native --verbose
+cargo check --features serialization --verbose
 rustup target add mips64-unknown-linux-gnuabi64
 rustup target add armv7-unknown-linux-gnueabihf
 rustup target add aarch64-unknown-linux-gnu
+rustup target add wasm32-unknown-unknown
 cargo check --target=mips64-unknown-linux-gnuabi64
 cargo check --target=armv7-unknown-linux-gnueabihf
 cargo check --target=aarch64-unknown-linux-gnu
+cargo check --target=wasm32-unknown-unknown --no-default-features --features inferno
@@ -3,30 +3,106 @@ extern crate log;
 
 use std::env;
 use std::error::Error;
+use std::ffi::OsString;
 use std::process::exit;
+#[cfg(feature = "env_logger")]
+use std::io::Write;
 use structopt::StructOpt;
 
 use nperf_core::{
     args,
+    cmd_analyze_core,
+    cmd_annotate,
+    cmd_archive,
+    cmd_backtrace,
+    cmd_bench,
+    cmd_buildid_list,
     cmd_collate,
     cmd_csv,
+    cmd_diff,
+    cmd_dump_syms,
+    cmd_dump_unwind_tables,
+    cmd_export,
+    cmd_info,
+    cmd_merge,
     cmd_metadata,
     cmd_record,
-    cmd_trace_events
+    cmd_report,
+    cmd_reprocess,
+    cmd_resolve,
+    cmd_show_cfi,
+    cmd_stat,
+    cmd_symbolize_server,
+    cmd_symbols,
+    cmd_timeline,
+    cmd_top,
+    cmd_trace_events,
+    cmd_trim,
+    cmd_verify_unwind_tables,
+    cmd_watchdog,
+    config,
+    quality
 };
 
 #[cfg(feature = "inferno")]
 use nperf_core::cmd_flamegraph;
 
-fn main_impl() -> Result< (), Box< dyn Error > > {
+#[cfg(feature = "tui")]
+use nperf_core::cmd_tui;
+
+// Counting the allocations slows down everything else, so it's opt-in.
+#[cfg(feature = "counting-allocator")]
+#[global_allocator]
+static ALLOCATOR: cmd_bench::CountingAllocator = cmd_bench::CountingAllocator;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum LogFormat {
+    Text,
+    Json
+}
+
+/// The log format has to be known before the logger is initialized, which happens
+/// before the rest of the arguments are parsed, so it's handled separately.
+fn take_log_format( args: &mut Vec< OsString > ) -> Result< LogFormat, Box< dyn Error > > {
+    match config::take_option( args, "--log-format" )? {
+        None => Ok( LogFormat::Text ),
+        Some( ref format ) if format == "text" => Ok( LogFormat::Text ),
+        Some( ref format ) if format == "json" => Ok( LogFormat::Json ),
+        Some( format ) => Err( format!( "invalid log format: {:?}; expected either 'text' or 'json'", format ).into() )
+    }
+}
+
+#[cfg(feature = "env_logger")]
+fn initialize_logger( log_format: LogFormat ) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if log_format == LogFormat::Json {
+        builder.format( |fp, record| {
+            let line = serde_json::json!({
+                "timestamp": fp.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string()
+            });
+
+            writeln!( fp, "{}", line )
+        });
+    }
+
+    builder.init();
+}
+
+fn main_impl( args: Vec< OsString >, log_format: LogFormat ) -> Result< (), Box< dyn Error > > {
     if env::var( "RUST_LOG" ).is_err() {
         env::set_var( "RUST_LOG", "nperf=info" );
     }
 
     #[cfg(feature = "env_logger")]
-    env_logger::init();
+    initialize_logger( log_format );
+
+    #[cfg(not(feature = "env_logger"))]
+    let _ = log_format;
 
-    let opt = args::Opt::from_args();
+    let opt = args::Opt::from_iter( config::apply_config_file( args )? );
     match opt {
         args::Opt::Record( args ) => {
             if args.profiler_args.panic_on_partial_backtrace {
@@ -38,6 +114,9 @@ fn main_impl() -> Result< (), Box< dyn Error > > {
 
             cmd_record::main( args )?;
         },
+        args::Opt::Top( args ) => {
+            cmd_top::main( args )?;
+        },
         #[cfg(feature = "inferno")]
         args::Opt::Flamegraph( args ) => {
             cmd_flamegraph::main( args )?;
@@ -45,12 +124,85 @@ fn main_impl() -> Result< (), Box< dyn Error > > {
         args::Opt::Csv( args ) => {
             cmd_csv::main( args )?;
         },
+        args::Opt::Timeline( args ) => {
+            cmd_timeline::main( args )?;
+        },
         args::Opt::Collate( args ) => {
             cmd_collate::main( args )?;
         },
+        args::Opt::Report( args ) => {
+            cmd_report::main( args )?;
+        },
+        args::Opt::Annotate( args ) => {
+            cmd_annotate::main( args )?;
+        },
+        args::Opt::Diff( args ) => {
+            cmd_diff::main( args )?;
+        },
+        #[cfg(feature = "tui")]
+        args::Opt::Tui( args ) => {
+            cmd_tui::main( args )?;
+        },
+        args::Opt::Export( args ) => {
+            cmd_export::main( args )?;
+        },
+        args::Opt::Merge( args ) => {
+            cmd_merge::main( args )?;
+        },
+        args::Opt::Trim( args ) => {
+            cmd_trim::main( args )?;
+        },
+        args::Opt::Reprocess( args ) => {
+            cmd_reprocess::main( args )?;
+        },
+        args::Opt::BuildidList( args ) => {
+            cmd_buildid_list::main( args )?;
+        },
+        args::Opt::Archive( args ) => {
+            cmd_archive::main( args )?;
+        },
         args::Opt::Metadata( args ) => {
             cmd_metadata::main( args )?;
         },
+        args::Opt::Info( args ) => {
+            cmd_info::main( args )?;
+        },
+        args::Opt::AnalyzeCore( args ) => {
+            cmd_analyze_core::main( args )?;
+        },
+        args::Opt::DumpSyms( args ) => {
+            cmd_dump_syms::main( args )?;
+        },
+        args::Opt::ShowCfi( args ) => {
+            cmd_show_cfi::main( args )?;
+        },
+        args::Opt::DumpUnwindTables( args ) => {
+            cmd_dump_unwind_tables::main( args )?;
+        },
+        args::Opt::VerifyUnwindTables( args ) => {
+            cmd_verify_unwind_tables::main( args )?;
+        },
+        args::Opt::Symbols( args ) => {
+            cmd_symbols::main( args )?;
+        },
+        args::Opt::Resolve( args ) => {
+            cmd_resolve::main( args )?;
+        },
+        args::Opt::SymbolizeServer( args ) => {
+            cmd_symbolize_server::main( args )?;
+        },
+        args::Opt::Backtrace( args ) => {
+            cmd_backtrace::main( args )?;
+        },
+        args::Opt::Watchdog( args ) => {
+            cmd_watchdog::main( args )?;
+        },
+        args::Opt::Stat( args ) => {
+            cmd_stat::main( args )?;
+        },
+        args::Opt::Bench( args ) => {
+            cmd_bench::main( args )?;
+        },
         args::Opt::TraceEvents( args ) => {
             cmd_trace_events::main( args )?;
         }
@@ -60,8 +212,26 @@ fn main_impl() -> Result< (), Box< dyn Error > > {
 }
 
 fn main() {
-    if let Err( error ) = main_impl() {
-        eprintln!( "error: {}", error );
+    let mut args: Vec< OsString > = env::args_os().collect();
+    let log_format = match take_log_format( &mut args ) {
+        Ok( log_format ) => log_format,
+        Err( error ) => {
+            eprintln!( "error: {}", error );
+            exit( 1 );
+        }
+    };
+
+    if let Err( error ) = main_impl( args, log_format ) {
+        if log_format == LogFormat::Json {
+            eprintln!( "{}", serde_json::json!({ "level": "ERROR", "message": error.to_string() }) );
+        } else {
+            eprintln!( "error: {}", error );
+        }
+
+        if error.is::< quality::QualityError >() {
+            exit( quality::QUALITY_EXIT_CODE );
+        }
+
         exit( 1 );
     }
 }
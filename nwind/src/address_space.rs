@@ -6,7 +6,9 @@ use std::fmt;
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::sync::Mutex;
+use std::cell::Cell;
 use std::str;
+use std::io;
 
 use byteorder::{self, ByteOrder};
 use cpp_demangle;
@@ -20,11 +22,13 @@ use proc_maps::Region;
 use crate::arch::{Architecture, Registers, Endianity};
 use crate::dwarf_regs::DwarfRegs;
 use crate::range_map::RangeMap;
-use crate::unwind_context::UnwindContext;
+use crate::unwind_context::{UnwindContext, UnwindHandle};
 use crate::binary::{BinaryData, LoadHeader, BinaryDataReader};
 use crate::symbols::Symbols;
+use crate::gopclntab::{GoLineTable, GoFrame};
 use crate::frame_descriptions::{DynamicFdeRegistry, FrameDescriptions, ContextCache, UnwindInfo, AddressMapping, LoadHint};
-use crate::types::{Inode, UserFrame, Endianness, BinaryId};
+use crate::types::{Inode, UserFrame, UnwindFailure, Endianness, BinaryId};
+use crate::snapshot::{AddressSpaceSnapshot, BinarySnapshot};
 
 #[cfg(not(feature = "addr2line"))]
 mod addr2line {
@@ -151,9 +155,12 @@ pub struct Binary< A: Architecture > {
     mappings: Vec< AddressMapping >,
     data: Option< Arc< BinaryData > >,
     debug_data: Option< Arc< BinaryData > >,
+    // How much the addresses in the `debug_data` differ from the ones in `load_headers`.
+    debug_address_offset: u64,
     symbols: Vec< Symbols >,
     frame_descriptions: Option< FrameDescriptions< A::Endianity > >,
     context: Option< Mutex< addr2line::Context< BinaryDataReader > > >,
+    go_line_table: Option< GoLineTable >,
     symbol_decode_cache: Option< Mutex< SymbolDecodeCache > >
 }
 
@@ -187,6 +194,22 @@ pub fn lookup_binary< 'a, A: Architecture, M: MemoryReader< A > >( nth_frame: us
     Some( &region.binary() )
 }
 
+/// Looks up the frame of the Go function which is executing in a given frame, if there's one.
+pub fn lookup_go_frame< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, regs: &A::Regs ) -> Option< GoFrame > {
+    let address: u64 = regs.get( A::INSTRUCTION_POINTER_REG )?.into();
+    let region = memory.get_region_at_address( address )?;
+    let binary = region.binary();
+    let go_line_table = binary.go_line_table.as_ref()?;
+
+    let address = if nth_frame == 0 { address } else { address.checked_sub( 1 )? };
+    let relative_address = translate_address( &binary.mappings, address );
+    let mut frame = go_line_table.lookup_frame( relative_address )?;
+    frame.entry = address.checked_sub( relative_address.checked_sub( frame.entry )? )?;
+
+    debug!( "Frame #{} is in a Go function at 0x{:016X} with a stack pointer offset of {}", nth_frame, frame.entry, frame.sp_offset );
+    Some( frame )
+}
+
 fn process_frame< R: gimli::Reader >( raw_frame: addr2line::Frame< R >, frame: &mut Frame ) {
     frame.file = None;
     frame.line = None;
@@ -245,7 +268,7 @@ fn pick_symbol( symbol: &str, cpp_name: &str, rust_name: &str ) -> SymbolKind {
     SymbolKind::Cpp
 }
 
-fn demangle( symbol: &str ) -> Option< String > {
+pub(crate) fn demangle( symbol: &str ) -> Option< String > {
     if !symbol.starts_with( "_" ) {
         return None;
     }
@@ -351,6 +374,12 @@ impl< A: Architecture > Binary< A > {
         self.frame_descriptions.as_ref().and_then( move |fde| fde.find_unwind_info( ctx_cache, &self.mappings, address ) )
     }
 
+    /// The binary whose `.ARM.exidx` and `.ARM.extab` should be used to unwind through this one;
+    /// `arm_exidx_address` and `arm_extab_address` are the addresses of those tables.
+    pub fn arm_unwind_data( &self ) -> Option< &Arc< BinaryData > > {
+        arm_unwind_data( self.data.as_ref(), self.debug_data.as_ref() )
+    }
+
     pub fn arm_exidx_address( &self ) -> Option< u64 > {
         self.virtual_addresses.arm_exidx
     }
@@ -366,7 +395,8 @@ impl< A: Architecture > Binary< A > {
 
         let mut found = false;
         if let Some( context ) = self.context.as_ref() {
-            if let Ok( mut raw_frames ) = context.lock().unwrap().find_frames( relative_address ) {
+            let debug_address = relative_address.wrapping_add( self.debug_address_offset );
+            if let Ok( mut raw_frames ) = context.lock().unwrap().find_frames( debug_address ) {
                 if let Ok( Some( raw_frame ) ) = raw_frames.next() {
                     found = true;
                     process_frame( raw_frame, &mut frame );
@@ -415,6 +445,11 @@ impl< A: Architecture > Binary< A > {
             frame.demangled_name = demangled_name;
         }
 
+        if let Some( (file, line) ) = self.go_line_table.as_ref().and_then( |table| table.lookup_location( relative_address ) ) {
+            frame.file = Some( file.to_owned() );
+            frame.line = Some( line );
+        }
+
         callback( &mut frame );
     }
 
@@ -444,6 +479,16 @@ impl< A: Architecture > Binary< A > {
         None
     }
 
+    /// Looks up the raw symbol for a given address without demangling it nor touching the cache,
+    /// and returns it along with the offset of the address from the start of the symbol.
+    #[cfg(feature = "local-unwinding")]
+    pub(crate) fn lookup_raw_symbol( &self, address: u64 ) -> Option< (&str, u64) > {
+        let relative_address = translate_address( &self.mappings, address );
+        self.symbols.iter()
+            .find_map( |symbols| symbols.get_symbol( relative_address ) )
+            .map( |(range, name)| (name, relative_address - range.start) )
+    }
+
     pub(crate) fn decode_symbol_once( &self, address: u64 ) -> Frame {
         let mut output = Frame::from_address( address, address );
         self.decode_symbol_while( address, &mut |frame| {
@@ -480,7 +525,8 @@ impl< A: Architecture > BinaryRegion< A > {
 struct Memory< 'a, A: Architecture + 'a, T: ?Sized + BufferReader + 'a > {
     regions: &'a RangeMap< BinaryRegion< A > >,
     stack_address: u64,
-    stack: &'a T
+    stack: &'a T,
+    read_past_stack: Cell< bool >
 }
 
 #[cold]
@@ -543,6 +589,10 @@ impl< 'a, A: Architecture, T: ?Sized + BufferReader + 'a > Memory< 'a, A, T > {
             let value = V::read_from_slice( endianness, slice );
             Some( value )
         } else {
+            if address >= self.stack_address {
+                self.read_past_stack.set( true );
+            }
+
             None
         }
     }
@@ -705,6 +755,24 @@ fn calculate_virtual_addr( region: &Region, physical_section_offset: u64 ) -> Op
     }
 }
 
+/// Same as `calculate_virtual_addr`, but for a section of a separate debug binary, whose file offsets
+/// have nothing to do with the file which is actually mapped, so the section's address is translated instead.
+fn calculate_debug_virtual_addr( load_headers: &[LoadHeader], debug_load_headers: &[LoadHeader], region: &Region, debug_address: u64 ) -> Option< u64 > {
+    let address = debug_address.wrapping_sub( debug_address_offset( load_headers, debug_load_headers ) );
+    let mapping = match_mapping( load_headers, region )?;
+    if address >= mapping.declared_address && address < (mapping.declared_address + mapping.size) {
+        Some( address - mapping.declared_address + mapping.actual_address )
+    } else {
+        None
+    }
+}
+
+/// Picks the binary whose ARM unwind tables are used; these are usually in the binary itself,
+/// but when it was stripped of them they can still be in its separate debug binary.
+fn arm_unwind_data< 'a >( data: Option< &'a Arc< BinaryData > >, debug_data: Option< &'a Arc< BinaryData > > ) -> Option< &'a Arc< BinaryData > > {
+    data.into_iter().chain( debug_data ).find( |binary_data| binary_data.arm_exidx_range().is_some() )
+}
+
 pub struct LoadHandle {
     binary: Option< Arc< BinaryData > >,
     debug_binary: Option< Arc< BinaryData > >,
@@ -760,6 +828,7 @@ impl LoadHandle {
     }
 }
 
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame< 'a > {
     pub absolute_address: u64,
     pub relative_address: u64,
@@ -806,20 +875,47 @@ impl< 'a > Frame< 'a > {
 
 pub trait IAddressSpace {
     fn reload( &mut self, regions: Vec< Region >, try_load: &mut dyn FnMut( &Region, &mut LoadHandle ) ) -> Reloaded;
-    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > );
+    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) -> Result< (), UnwindFailure >;
     fn decode_symbol_while< 'a >( &'a self, address: u64, callback: &mut dyn FnMut( &mut Frame< 'a > ) -> bool );
     fn decode_symbol_once( &self, address: u64 ) -> Frame;
     fn set_panic_on_partial_backtrace( &mut self, value: bool );
+    /// Captures everything which was loaded into the address space so that it can be restored later with `restore`.
+    fn snapshot( &self ) -> AddressSpaceSnapshot;
+    /// Replaces the contents of the address space with a snapshot; the binaries from the snapshot
+    /// are then kept as-is by `reload` as long as they're still mapped.
+    fn restore( &mut self, snapshot: AddressSpaceSnapshot ) -> io::Result< () >;
 }
 
 #[derive(Clone, Default)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub struct Reloaded {
     pub binaries_unmapped: Vec< (Option< Inode >, String) >,
+    /// When serialized the binaries' data is left out, and it's `None` when deserialized.
+    #[cfg_attr(feature = "serialization", serde(with = "serialize_binaries_mapped"))]
     pub binaries_mapped: Vec< (Option< Inode >, String, Option< Arc< BinaryData > >) >,
     pub regions_unmapped: Vec< Range< u64 > >,
     pub regions_mapped: Vec< Region >
 }
 
+#[cfg(feature = "serialization")]
+mod serialize_binaries_mapped {
+    use std::sync::Arc;
+    use serde::{Serializer, Deserialize, Deserializer};
+    use crate::binary::BinaryData;
+    use crate::types::Inode;
+
+    type MappedBinary = (Option< Inode >, String, Option< Arc< BinaryData > >);
+
+    pub fn serialize< S: Serializer >( binaries: &[MappedBinary], serializer: S ) -> Result< S::Ok, S::Error > {
+        serializer.collect_seq( binaries.iter().map( |(inode, name, _)| (inode, name) ) )
+    }
+
+    pub fn deserialize< 'de, D: Deserializer< 'de > >( deserializer: D ) -> Result< Vec< MappedBinary >, D::Error > {
+        let binaries: Vec< (Option< Inode >, String) > = Deserialize::deserialize( deserializer )?;
+        Ok( binaries.into_iter().map( |(inode, name)| (inode, name, None) ).collect() )
+    }
+}
+
 pub struct AddressSpace< A: Architecture > {
     pub(crate) ctx: UnwindContext< A >,
     pub(crate) regions: RangeMap< BinaryRegion< A > >,
@@ -885,6 +981,51 @@ fn match_mapping( load_headers: &[LoadHeader], region: &Region ) -> Option< Addr
     matched.map( |(mapping, _)| mapping )
 }
 
+fn base_address( load_headers: &[LoadHeader] ) -> Option< u64 > {
+    load_headers.iter().map( |header| header.address & !(header.alignment.max( 1 ) - 1) ).min()
+}
+
+/// Calculates by how much the addresses in a debug binary differ from the ones in the binary itself.
+///
+/// This is only non-zero when the binary was prelinked after its debug info was split off, since
+/// prelink moves the binary to a new base address but leaves the separate debug info as it was.
+fn debug_address_offset( load_headers: &[LoadHeader], debug_load_headers: &[LoadHeader] ) -> u64 {
+    match (base_address( load_headers ), base_address( debug_load_headers )) {
+        (Some( base ), Some( debug_base )) => debug_base.wrapping_sub( base ),
+        _ => 0
+    }
+}
+
+fn create_context( name: &str, binary_data: &Arc< BinaryData > ) -> Option< Mutex< addr2line::Context< BinaryDataReader > > > {
+    if cfg!( not( feature = "addr2line" ) ) {
+        debug!( "Not compiled with the `addr2line` feature; skipping addr2line context creation" );
+        return None;
+    }
+
+    debug!( "Creating addr2line context for '{}' from '{}'...", name, binary_data.name() );
+    let ctx = addr2line::Context::from_sections(
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_section_or_empty( binary_data ),
+        BinaryData::get_empty_section( binary_data )
+    );
+
+    match ctx {
+        Ok( ctx ) => Some( Mutex::new( ctx ) ),
+        Err( error ) => {
+            warn!( "Failed to create addr2line context: {:?}", error );
+            None
+        }
+    }
+}
+
 pub fn reload< A: Architecture >(
     current_binary_map: &mut HashMap< BinaryId, BinaryHandle< A > >,
     current_regions: &mut RangeMap< BinaryRegion< A > >,
@@ -909,7 +1050,8 @@ pub fn reload< A: Architecture >(
         load_debug_frame: bool,
         load_frame_descriptions: bool,
         is_old: bool,
-        context: Option< Mutex< addr2line::Context< BinaryDataReader > > >
+        context: Option< Mutex< addr2line::Context< BinaryDataReader > > >,
+        go_line_table: Option< GoLineTable >
     }
 
     let mut reloaded = Reloaded::default();
@@ -947,8 +1089,8 @@ pub fn reload< A: Architecture >(
 
         if !new_binary_map.contains_key( &id ) {
             if let Some( binary ) = old_binary_map.remove( &id ) {
-                let (binary_data, debug_binary_data, symbols, frame_descriptions, load_headers, context) = match Arc::try_unwrap( binary ) {
-                    Ok( binary ) => (binary.data, binary.debug_data, binary.symbols, binary.frame_descriptions, binary.load_headers, binary.context),
+                let (binary_data, debug_binary_data, symbols, frame_descriptions, load_headers, context, go_line_table) = match Arc::try_unwrap( binary ) {
+                    Ok( binary ) => (binary.data, binary.debug_data, binary.symbols, binary.frame_descriptions, binary.load_headers, binary.context, binary.go_line_table),
                     Err( _ ) => {
                         unimplemented!();
                     }
@@ -970,7 +1112,8 @@ pub fn reload< A: Architecture >(
                     load_debug_frame: true,
                     load_frame_descriptions: false,
                     is_old: true,
-                    context
+                    context,
+                    go_line_table
                 });
             } else if !tried_to_load.contains( &id ) {
                 tried_to_load.insert( id.clone() );
@@ -1033,7 +1176,8 @@ pub fn reload< A: Architecture >(
                     load_debug_frame: handle.load_debug_frame,
                     load_frame_descriptions: handle.load_frame_descriptions,
                     is_old: false,
-                    context: None
+                    context: None,
+                    go_line_table: None
                 });
             } else {
                 continue;
@@ -1060,10 +1204,26 @@ pub fn reload< A: Architecture >(
         macro_rules! section {
             ($name:expr, $section_range_getter:ident, $output_addr:expr) => {
                 if $output_addr.is_none() {
-                    if let Some( binary_data ) = data.binary_data.as_ref() {
+                    if let Some( binary_data ) = arm_unwind_data( data.binary_data.as_ref(), data.debug_binary_data.as_ref() ) {
                         if let Some( section_range ) = binary_data.$section_range_getter() {
-                            if let Some( addr ) = calculate_virtual_addr( &region, section_range.start as u64 ) {
-                                debug!( "'{}': found {} section at 0x{:016X} (+0x{:08X})", region.name, $name, addr, addr - region.start );
+                            let is_from_debug_binary = data.binary_data.as_ref().map( |main| !Arc::ptr_eq( main, binary_data ) ).unwrap_or( true );
+                            let addr = if is_from_debug_binary {
+                                binary_data.file_offset_to_address( section_range.start as u64 ).and_then( |debug_address| {
+                                    calculate_debug_virtual_addr( &data.load_headers, binary_data.load_headers(), &region, debug_address )
+                                })
+                            } else {
+                                calculate_virtual_addr( &region, section_range.start as u64 )
+                            };
+
+                            if let Some( addr ) = addr {
+                                debug!(
+                                    "'{}': found {} section{} at 0x{:016X} (+0x{:08X})",
+                                    region.name,
+                                    $name,
+                                    if is_from_debug_binary { " in the debug binary" } else { "" },
+                                    addr,
+                                    addr - region.start
+                                );
                                 *$output_addr = Some( addr );
                             }
                         }
@@ -1084,39 +1244,46 @@ pub fn reload< A: Architecture >(
             reloaded.binaries_mapped.push( (id.to_inode(), data.name.clone(), data.binary_data.clone()) );
         }
 
+        let debug_address_offset = data.debug_binary_data.as_ref()
+            .map( |debug_binary_data| debug_address_offset( &data.load_headers, debug_binary_data.load_headers() ) )
+            .unwrap_or( 0 );
+
+        if debug_address_offset != 0 && !data.is_old {
+            debug!( "'{}': the debug info is offset by 0x{:016X}; was the binary prelinked?", data.name, debug_address_offset );
+        }
+
         let mut symbols = data.symbols;
         let mut context = data.context;
+        let mut go_line_table = data.go_line_table;
+        if go_line_table.is_none() {
+            // Go binaries are usually stripped, but they always carry their own symbol table,
+            // which is also what we need to unwind through them.
+            go_line_table = data.binary_data.as_ref().and_then( GoLineTable::load );
+            if go_line_table.is_none() {
+                go_line_table = data.debug_binary_data.as_ref().and_then( GoLineTable::load );
+            }
+
+            if data.load_symbols {
+                if let Some( go_line_table ) = go_line_table.as_ref() {
+                    symbols.push( Symbols::load_from_gopclntab( go_line_table ) );
+                }
+            }
+        }
+
         if data.load_symbols {
             let binary_data = data.debug_binary_data.as_ref().or( data.binary_data.as_ref() );
             if let Some( binary_data ) = binary_data {
                 if symbols.is_empty() {
-                    symbols.push( Symbols::load_from_binary_data( &binary_data ) );
-                }
+                    let mut loaded = Symbols::load_from_binary_data( &binary_data );
+                    if debug_address_offset != 0 && data.debug_binary_data.is_some() {
+                        loaded = loaded.with_offset( 0_u64.wrapping_sub( debug_address_offset ) );
+                    }
 
-                if cfg!( not( feature = "addr2line" ) ) {
-                    debug!( "Not compiled with the `addr2line` feature; skipping addr2line context creation" );
-                } else if context.is_none() {
-                    debug!( "Creating addr2line context for '{}' from '{}'...", data.name, binary_data.name() );
-                    let ctx = addr2line::Context::from_sections(
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_section_or_empty( &binary_data ),
-                        BinaryData::get_empty_section( &binary_data )
-                    );
+                    symbols.push( loaded );
+                }
 
-                    match ctx {
-                        Ok( ctx ) => context = Some( Mutex::new( ctx ) ),
-                        Err( error ) => {
-                            warn!( "Failed to create addr2line context: {:?}", error );
-                        }
-                    }
+                if context.is_none() {
+                    context = create_context( &data.name, binary_data );
                 }
             }
         }
@@ -1141,12 +1308,14 @@ pub fn reload< A: Architecture >(
             name: data.name,
             data: data.binary_data,
             debug_data: data.debug_binary_data,
+            debug_address_offset,
             virtual_addresses: data.addresses,
             load_headers: data.load_headers,
             mappings: data.mappings,
             symbols,
             frame_descriptions,
             context,
+            go_line_table,
             symbol_decode_cache: if data.load_symbols { Some( Mutex::new( SymbolDecodeCache::new() ) ) } else { None }
         });
 
@@ -1188,41 +1357,13 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > where A::RegTy: Prim
         reload( &mut self.binary_map, &mut self.regions, regions, try_load )
     }
 
-    fn unwind( &mut self, dwarf_regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) {
+    fn unwind( &mut self, dwarf_regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) -> Result< (), UnwindFailure > {
         output.clear();
-
-        let stack_address = match dwarf_regs.get( A::STACK_POINTER_REG ) {
-            Some( address ) => address,
-            None => return
-        };
-
-        let memory = Memory {
-            regions: &self.regions,
-            stack,
-            stack_address
-        };
-
-        self.ctx.set_panic_on_partial_backtrace( self.panic_on_partial_backtrace );
-
-        let mut ctx = self.ctx.start( &memory, |regs: &mut A::Regs| {
-            use crate::arch::TryInto;
-
-            regs.clear();
-            for (register, value) in dwarf_regs.iter() {
-                regs.append( register, value.try_into().unwrap() );
-            }
-        });
-
-        loop {
-            let frame = UserFrame {
-                address: ctx.current_address().into(),
-                initial_address: ctx.current_initial_address().map( |value| value.into() )
-            };
-            output.push( frame );
-            if !ctx.unwind( &memory ) {
-                break;
-            }
+        for frame in self.unwind_iter( dwarf_regs, stack ) {
+            output.push( frame? );
         }
+
+        Ok(())
     }
 
     fn decode_symbol_while< 'a >( &'a self, address: u64, callback: &mut dyn FnMut( &mut Frame< 'a > ) -> bool ) {
@@ -1245,6 +1386,97 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > where A::RegTy: Prim
     fn set_panic_on_partial_backtrace( &mut self, value: bool ) {
         self.panic_on_partial_backtrace = value;
     }
+
+    fn snapshot( &self ) -> AddressSpaceSnapshot {
+        let mut regions_by_binary: HashMap< *const Binary< A >, Vec< Region > > = HashMap::new();
+        for region in self.regions.values() {
+            regions_by_binary.entry( Arc::as_ptr( &region.binary ) ).or_default().push( region.memory_region.clone() );
+        }
+
+        let binaries = self.binary_map.iter().map( |(id, binary)| BinarySnapshot {
+            id: id.clone(),
+            name: binary.name.clone(),
+            arm_exidx: binary.virtual_addresses.arm_exidx,
+            arm_extab: binary.virtual_addresses.arm_extab,
+            load_headers: binary.load_headers.clone(),
+            mappings: binary.mappings.clone(),
+            data: binary.data.clone(),
+            debug_data: binary.debug_data.clone(),
+            symbols: binary.symbols.iter().map( |symbols| {
+                symbols.iter().map( |(range, name)| (range, name.to_owned()) ).collect()
+            }).collect(),
+            frame_descriptions: binary.frame_descriptions.as_ref().map( |frame_descriptions| frame_descriptions.save_index() ),
+            has_context: binary.context.is_some(),
+            has_symbol_decode_cache: binary.symbol_decode_cache.is_some(),
+            regions: regions_by_binary.remove( &Arc::as_ptr( binary ) ).unwrap_or_default()
+        }).collect();
+
+        AddressSpaceSnapshot {
+            architecture: A::NAME.to_owned(),
+            binaries
+        }
+    }
+
+    fn restore( &mut self, snapshot: AddressSpaceSnapshot ) -> io::Result< () > {
+        if snapshot.architecture != A::NAME {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "the snapshot is for '{}' while the address space is for '{}'", snapshot.architecture, A::NAME ) ) );
+        }
+
+        self.ctx.clear_cache();
+        self.binary_map.clear();
+
+        let mut regions = Vec::new();
+        for mut binary in snapshot.binaries {
+            let go_line_table = binary.data.as_ref().and_then( GoLineTable::load )
+                .or_else( || binary.debug_data.as_ref().and_then( GoLineTable::load ) );
+
+            let context = if binary.has_context {
+                binary.debug_data.as_ref().or( binary.data.as_ref() ).and_then( |binary_data| create_context( &binary.name, binary_data ) )
+            } else {
+                None
+            };
+
+            let frame_descriptions = match (binary.frame_descriptions.take(), binary.data.as_ref()) {
+                (Some( index ), Some( binary_data )) => FrameDescriptions::new( binary_data ).load_from_saved_index( index ),
+                _ => None
+            };
+
+            let debug_address_offset = binary.debug_data.as_ref()
+                .map( |debug_data| debug_address_offset( &binary.load_headers, debug_data.load_headers() ) )
+                .unwrap_or( 0 );
+
+            let handle = Arc::new( Binary {
+                name: binary.name,
+                virtual_addresses: BinaryAddresses {
+                    arm_exidx: binary.arm_exidx,
+                    arm_extab: binary.arm_extab
+                },
+                load_headers: binary.load_headers,
+                mappings: binary.mappings,
+                data: binary.data,
+                debug_data: binary.debug_data,
+                debug_address_offset,
+                symbols: binary.symbols.into_iter().map( Symbols::from_list ).collect(),
+                frame_descriptions,
+                context,
+                go_line_table,
+                symbol_decode_cache: if binary.has_symbol_decode_cache { Some( Mutex::new( SymbolDecodeCache::new() ) ) } else { None }
+            });
+
+            for region in binary.regions {
+                let range = region.start..region.end;
+                regions.push( (range, BinaryRegion {
+                    binary: handle.clone(),
+                    memory_region: region
+                }));
+            }
+
+            self.binary_map.insert( binary.id, handle );
+        }
+
+        self.regions = RangeMap::from_vec( regions );
+        Ok(())
+    }
 }
 
 impl< A: Architecture > AddressSpace< A > {
@@ -1258,6 +1490,104 @@ impl< A: Architecture > AddressSpace< A > {
     }
 }
 
+impl< A: Architecture > AddressSpace< A > where A::RegTy: Primitive {
+    /// Unwinds the stack lazily, one frame at a time, so that the unwinding can be stopped early;
+    /// if the unwinding stops prematurely the last item will be the reason why.
+    pub fn unwind_iter< 'a >( &'a mut self, dwarf_regs: &DwarfRegs, stack: &'a dyn BufferReader ) -> UnwindIter< 'a, A > {
+        let stack_address = dwarf_regs.get( A::STACK_POINTER_REG );
+        let memory = Memory {
+            regions: &self.regions,
+            stack,
+            stack_address: stack_address.unwrap_or( 0 ),
+            read_past_stack: Cell::new( false )
+        };
+
+        if stack_address.is_none() {
+            return UnwindIter {
+                handle: None,
+                memory,
+                last_address: 0,
+                state: UnwindIterState::Failed
+            };
+        }
+
+        self.ctx.set_panic_on_partial_backtrace( self.panic_on_partial_backtrace );
+
+        let handle = self.ctx.start( &memory, |regs: &mut A::Regs| {
+            use crate::arch::TryInto;
+
+            regs.clear();
+            for (register, value) in dwarf_regs.iter() {
+                regs.append( register, value.try_into().unwrap() );
+            }
+        });
+
+        UnwindIter {
+            handle: Some( handle ),
+            memory,
+            last_address: 0,
+            state: UnwindIterState::Unwinding
+        }
+    }
+}
+
+enum UnwindIterState {
+    Unwinding,
+    Finished,
+    Failed,
+    Done
+}
+
+/// An iterator over the frames of a stack; created by `AddressSpace::unwind_iter`.
+pub struct UnwindIter< 'a, A: Architecture + 'a > {
+    handle: Option< UnwindHandle< 'a, A > >,
+    memory: Memory< 'a, A, dyn BufferReader + 'a >,
+    last_address: u64,
+    state: UnwindIterState
+}
+
+impl< 'a, A: Architecture > Iterator for UnwindIter< 'a, A > where A::RegTy: Primitive {
+    type Item = Result< UserFrame, UnwindFailure >;
+
+    fn next( &mut self ) -> Option< Self::Item > {
+        match self.state {
+            UnwindIterState::Unwinding => {
+                let handle = self.handle.as_mut().unwrap();
+                let frame = UserFrame {
+                    address: handle.current_address().into(),
+                    initial_address: handle.current_initial_address().map( |value| value.into() )
+                };
+
+                self.last_address = frame.address;
+                if !handle.unwind( &self.memory ) {
+                    self.state = UnwindIterState::Finished;
+                }
+
+                Some( Ok( frame ) )
+            },
+            UnwindIterState::Finished => {
+                self.state = UnwindIterState::Done;
+                if !self.handle.as_ref().unwrap().is_partial() {
+                    return None;
+                }
+
+                if self.memory.read_past_stack.get() {
+                    Some( Err( UnwindFailure::StackTruncated ) )
+                } else if self.memory.regions.get_value( self.last_address ).is_none() {
+                    Some( Err( UnwindFailure::UnknownAddress ) )
+                } else {
+                    Some( Err( UnwindFailure::MissingUnwindInfo ) )
+                }
+            },
+            UnwindIterState::Failed => {
+                self.state = UnwindIterState::Done;
+                Some( Err( UnwindFailure::MissingRegisters ) )
+            },
+            UnwindIterState::Done => None
+        }
+    }
+}
+
 #[test]
 fn test_reload() {
     use std::env;
@@ -1350,6 +1680,119 @@ fn test_reload() {
     assert_eq!( res.regions_mapped.len(), 0 );
 }
 
+#[cfg(feature = "serialization")]
+#[test]
+fn test_serialization() {
+    use std::env;
+    use std::fs;
+
+    let binary = BinaryData::load_from_owned_bytes( "libfoo.so", fs::read( env::current_exe().unwrap() ).unwrap() ).unwrap();
+    let inode = Inode { inode: 1, dev_major: 8, dev_minor: 1 };
+    let region = Region {
+        start: 0x1000,
+        end: 0x2000,
+        is_read: true,
+        is_write: false,
+        is_executable: true,
+        is_shared: false,
+        file_offset: 0,
+        major: 8,
+        minor: 1,
+        inode: 1,
+        name: "/usr/lib/libfoo.so".to_owned()
+    };
+
+    let reloaded = Reloaded {
+        binaries_unmapped: vec![ (None, "/usr/lib/libbar.so".to_owned()) ],
+        binaries_mapped: vec![ (Some( inode ), region.name.clone(), Some( Arc::new( binary ) )) ],
+        regions_unmapped: vec![ 0x3000..0x4000, 0x5000..0x6000 ],
+        regions_mapped: vec![ region.clone() ]
+    };
+
+    let reloaded: Reloaded = serde_json::from_str( &serde_json::to_string( &reloaded ).unwrap() ).unwrap();
+    assert_eq!( reloaded.binaries_unmapped, vec![ (None, "/usr/lib/libbar.so".to_owned()) ] );
+    assert_eq!( reloaded.binaries_mapped.len(), 1 );
+    assert_eq!( reloaded.binaries_mapped[ 0 ].0, Some( inode ) );
+    assert_eq!( reloaded.binaries_mapped[ 0 ].1, region.name );
+    assert!( reloaded.binaries_mapped[ 0 ].2.is_none() );
+    assert_eq!( reloaded.regions_unmapped, vec![ 0x3000..0x4000, 0x5000..0x6000 ] );
+    assert_eq!( reloaded.regions_mapped, vec![ region ] );
+
+    let mut frame = Frame::from_address( 0x1234, 0x234 );
+    frame.name = Some( "foo".into() );
+    frame.line = Some( 10 );
+    let frame: Frame = serde_json::from_str( &serde_json::to_string( &frame ).unwrap() ).unwrap();
+    assert_eq!( frame.absolute_address, 0x1234 );
+    assert_eq!( frame.name.as_deref(), Some( "foo" ) );
+    assert_eq!( frame.line, Some( 10 ) );
+
+    let frames = vec![ UserFrame { address: 0x1000, initial_address: Some( 0x1004 ) } ];
+    let frames: Vec< UserFrame > = serde_json::from_str( &serde_json::to_string( &frames ).unwrap() ).unwrap();
+    assert_eq!( frames[ 0 ].initial_address, Some( 0x1004 ) );
+}
+
+#[test]
+fn test_debug_address_offset() {
+    let header = |address, file_offset| LoadHeader {
+        address,
+        file_offset,
+        file_size: 0x1000,
+        memory_size: 0x1000,
+        alignment: 0x1000,
+        is_readable: true,
+        is_writable: false,
+        is_executable: true
+    };
+
+    let debug_load_headers = [ header( 0, 0 ), header( 0x201e28, 0x1e28 ) ];
+    assert_eq!( debug_address_offset( &debug_load_headers, &debug_load_headers ), 0 );
+    assert_eq!( debug_address_offset( &debug_load_headers, &[] ), 0 );
+
+    // Prelinked to 0x4a000000.
+    let load_headers = [ header( 0x4a000000, 0 ), header( 0x4a201e28, 0x1e28 ) ];
+    let offset = debug_address_offset( &load_headers, &debug_load_headers );
+    assert_eq!( 0x4a000123_u64.wrapping_add( offset ), 0x123 );
+
+    let symbols = Symbols::from_list( vec![ (0x100..0x200, "foo".to_owned()) ] ).with_offset( 0_u64.wrapping_sub( offset ) );
+    assert_eq!( symbols.get_symbol( 0x4a000123 ), Some( (0x4a000100..0x4a000200, "foo") ) );
+}
+
+#[test]
+fn test_calculate_debug_virtual_addr() {
+    let header = |address| LoadHeader {
+        address,
+        file_offset: 0,
+        file_size: 0x1000,
+        memory_size: 0x1000,
+        alignment: 0x1000,
+        is_readable: true,
+        is_writable: false,
+        is_executable: true
+    };
+
+    let region = Region {
+        start: 0xb6f00000,
+        end: 0xb6f01000,
+        is_read: true,
+        is_write: false,
+        is_executable: true,
+        is_shared: false,
+        file_offset: 0,
+        major: 0,
+        minor: 0,
+        inode: 1,
+        name: "libfoo.so".to_owned()
+    };
+
+    let debug_load_headers = [ header( 0 ) ];
+    assert_eq!( calculate_debug_virtual_addr( &debug_load_headers, &debug_load_headers, &region, 0x800 ), Some( 0xb6f00800 ) );
+    assert_eq!( calculate_debug_virtual_addr( &debug_load_headers, &debug_load_headers, &region, 0x1800 ), None );
+
+    // Prelinked to 0x4a000000 after the debug info was split off.
+    let load_headers = [ header( 0x4a000000 ) ];
+    assert_eq!( calculate_debug_virtual_addr( &load_headers, &debug_load_headers, &region, 0x800 ), Some( 0xb6f00800 ) );
+}
+
 #[test]
 fn test_match_mapping_1() {
     let load_headers = [
@@ -1815,3 +2258,101 @@ fn test_match_mapping_6() {
         size: 8192
     }));
 }
+
+#[test]
+fn test_unwind_iter() {
+    use crate::arch::{self, Architecture};
+
+    type Arch = arch::native::Arch;
+    let mut address_space = AddressSpace::< Arch >::new();
+    let stack: Vec< u8 > = Vec::new();
+
+    let regs = DwarfRegs::new();
+    let frames: Vec< _ > = address_space.unwind_iter( &regs, &stack ).map( |frame| frame.map( |frame| frame.address ) ).collect();
+    assert_eq!( frames, vec![ Err( UnwindFailure::MissingRegisters ) ] );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( Arch::INSTRUCTION_POINTER_REG, 0x1234 );
+    regs.append( Arch::STACK_POINTER_REG, 0x8000 );
+    let frames: Vec< _ > = address_space.unwind_iter( &regs, &stack ).map( |frame| frame.map( |frame| frame.address ) ).collect();
+    assert_eq!( frames, vec![ Ok( 0x1234 ), Err( UnwindFailure::UnknownAddress ) ] );
+
+    let mut output = Vec::new();
+    assert_eq!( address_space.unwind( &mut regs, &stack, &mut output ), Err( UnwindFailure::UnknownAddress ) );
+    assert_eq!( output.len(), 1 );
+    assert_eq!( output[ 0 ].address, 0x1234 );
+}
+
+#[test]
+fn test_snapshot() {
+    use std::env;
+    use std::fs;
+    use crate::arch;
+    use crate::snapshot::{write_snapshots, read_snapshots};
+
+    let raw_data = fs::read( env::current_exe().unwrap() ).unwrap();
+    let binary_data = Arc::new( BinaryData::load_from_owned_bytes( "file_1", raw_data ).unwrap() );
+    let symbols = Symbols::load_from_binary_data( &binary_data );
+    let (symbol_range, _) = symbols.iter().next().unwrap();
+
+    let region = Region {
+        start: 0x10000000,
+        end: 0x20000000,
+        is_read: true,
+        is_write: false,
+        is_executable: false,
+        is_shared: false,
+        file_offset: 0,
+        major: 0,
+        minor: 0,
+        inode: 1,
+        name: "file_1".to_owned()
+    };
+
+    let mut address_space = AddressSpace::< arch::native::Arch >::new();
+    address_space.reload( vec![ region.clone() ], &mut |_, handle| {
+        handle.set_binary( binary_data.clone() );
+    });
+
+    let address = region.start + symbol_range.start;
+    let expected_frame = format!( "{:?}", address_space.decode_symbol_once( address ) );
+
+    let mut bytes = Vec::new();
+    write_snapshots( &[(1, address_space.snapshot()), (2, address_space.snapshot())], &mut bytes ).unwrap();
+    let mut snapshots = read_snapshots( &bytes ).unwrap();
+    assert_eq!( snapshots.len(), 2 );
+    assert_eq!( snapshots[ 1 ].0, 2 );
+
+    let (_, snapshot) = snapshots.remove( 0 );
+    assert_eq!( snapshot.binary_names().collect::< Vec< _ > >(), vec![ "file_1" ] );
+
+    let mut restored = AddressSpace::< arch::native::Arch >::new();
+    restored.restore( snapshot ).unwrap();
+    assert_eq!( format!( "{:?}", restored.decode_symbol_once( address ) ), expected_frame );
+    assert_eq!( restored.regions.len(), 1 );
+
+    let binary = restored.regions.get_value( address ).unwrap().binary();
+    let original = address_space.regions.get_value( address ).unwrap().binary();
+    assert_eq!( binary.mappings, original.mappings );
+    assert_eq!( binary.frame_descriptions.is_some(), original.frame_descriptions.is_some() );
+    if let (Some( restored ), Some( original )) = (binary.frame_descriptions.as_ref(), original.frame_descriptions.as_ref()) {
+        let (restored, original) = (restored.save_index(), original.save_index());
+        assert_eq!( restored.use_eh_frame_hdr, original.use_eh_frame_hdr );
+        assert_eq!( restored.eh_frame, original.eh_frame );
+        assert_eq!( restored.debug_frame, original.debug_frame );
+    }
+    assert_eq!( binary.symbols[ 0 ].iter().count(), symbols.iter().count() );
+
+    let data = binary.data().unwrap();
+    assert_eq!( data.as_bytes(), binary_data.as_bytes() );
+    assert_eq!( data.build_id(), binary_data.build_id() );
+    assert_eq!( data.eh_frame_range(), binary_data.eh_frame_range() );
+    assert_eq!( data.has_debug_info(), binary_data.has_debug_info() );
+
+    // Nothing is loaded again if the same regions are still mapped.
+    let reloaded = restored.reload( vec![ region ], &mut |_, _| panic!() );
+    assert!( reloaded.binaries_mapped.is_empty() );
+
+    let (_, snapshot) = snapshots.remove( 0 );
+    assert!( AddressSpace::< arch::mips64::Arch >::new().restore( snapshot ).is_err() );
+}
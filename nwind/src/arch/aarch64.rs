@@ -1,6 +1,6 @@
 use gimli::LittleEndian;
 use crate::arch::{Architecture, Registers, UnwindStatus};
-use crate::address_space::MemoryReader;
+use crate::address_space::{MemoryReader, lookup_go_frame};
 use crate::frame_descriptions::{ContextCache, UnwindInfoCache};
 use crate::types::{Endianness, Bitness};
 use crate::dwarf::dwarf_unwind;
@@ -129,6 +129,86 @@ impl_regs_debug!( Regs, REGS, Arch );
 #[allow(dead_code)]
 pub struct Arch {}
 
+fn go_unwind< M: MemoryReader< Arch > >(
+    nth_frame: usize,
+    memory: &M,
+    regs: &mut <Arch as Architecture>::Regs,
+    initial_address: &mut Option< u64 >,
+    ra_address: &mut Option< u64 >
+) -> Option< UnwindStatus > {
+    // Go binaries usually have no `.eh_frame`, but the Go toolchain always emits a table
+    // of stack pointer offsets for its own traceback; every function with a stack frame
+    // saves the link register at the bottom of its frame, and the caller's frame pointer
+    // right below that.
+    let frame = lookup_go_frame( nth_frame, memory, regs )?;
+    *initial_address = Some( frame.entry );
+    if frame.is_outermost {
+        debug!( "Frame #{} is the outermost Go frame", nth_frame );
+        return Some( UnwindStatus::Finished );
+    }
+
+    let sp = regs.get( dwarf::X31 )?;
+    let return_address = if frame.sp_offset == 0 {
+        // The link register wasn't saved yet, which is only possible in the innermost frame.
+        if nth_frame != 0 {
+            debug!( "Previous frame not found: frame #{} has no saved link register", nth_frame );
+            return None;
+        }
+
+        regs.get( dwarf::X30 )?
+    } else {
+        let return_address = memory.get_pointer_at_address( sp )?;
+        if let Some( fp ) = sp.checked_sub( 8 ).and_then( |location| memory.get_pointer_at_address( location ) ) {
+            regs.append( dwarf::X29, fp );
+        }
+
+        *ra_address = Some( sp );
+        return_address
+    };
+
+    // The offset comes straight from the binary, so a garbage table could make this overflow.
+    let cfa = sp.checked_add( frame.sp_offset )?;
+    regs.append( dwarf::X30, return_address );
+    regs.append( dwarf::PC, return_address );
+    regs.append( dwarf::X31, cfa );
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( dwarf::X31 ), nth_frame + 1, cfa );
+    Some( UnwindStatus::InProgress )
+}
+
+fn jit_unwind< M: MemoryReader< Arch > >(
+    nth_frame: usize,
+    memory: &M,
+    regs: &mut <Arch as Architecture>::Regs,
+    ra_address: &mut Option< u64 >
+) -> Option< UnwindStatus > {
+    // The code generated by JIT compilers isn't backed by any binary and has no unwind info,
+    // but they can usually be told to keep the frame records (e.g. the JVM with `-XX:+PreserveFramePointer`).
+    let address = regs.get( dwarf::PC )?;
+    if memory.get_region_at_address( address ).is_some() {
+        return None;
+    }
+
+    let sp = regs.get( dwarf::X31 )?;
+    let fp = regs.get( dwarf::X29 )?;
+    if fp < sp || fp % 16 != 0 || !memory.is_stack_address( fp ) {
+        debug!( "Frame #{} is not in any binary and has no valid frame record", nth_frame );
+        return None;
+    }
+
+    let return_address = memory.get_pointer_at_address( fp + 8 )?;
+    let previous_fp = memory.get_pointer_at_address( fp )?;
+
+    *ra_address = Some( fp + 8 );
+    regs.append( dwarf::X29, previous_fp );
+    regs.append( dwarf::X30, return_address );
+    regs.append( dwarf::PC, return_address );
+    regs.append( dwarf::X31, fp + 16 );
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( dwarf::X31 ), nth_frame + 1, fp + 16 );
+    Some( UnwindStatus::InProgress )
+}
+
 #[doc(hidden)]
 pub struct State {
     ctx_cache: ContextCache< LittleEndian >,
@@ -204,6 +284,10 @@ impl Architecture for Arch {
         state.unwind_cache.clear();
     }
 
+    fn disable_cache( state: &mut Self::State ) {
+        state.unwind_cache.disable();
+    }
+
     #[inline]
     fn unwind< M: MemoryReader< Self > >(
         nth_frame: usize,
@@ -213,7 +297,13 @@ impl Architecture for Arch {
         initial_address: &mut Option< u64 >,
         ra_address: &mut Option< u64 >
     ) -> Option< UnwindStatus > {
-        let result = dwarf_unwind( nth_frame, memory, &mut state.ctx_cache, &mut state.unwind_cache, regs, &mut state.new_regs )?;
+        let result = match dwarf_unwind( nth_frame, memory, &mut state.ctx_cache, &mut state.unwind_cache, regs, &mut state.new_regs ) {
+            Some( result ) => result,
+            None => {
+                return go_unwind( nth_frame, memory, regs, initial_address, ra_address )
+                    .or_else( || jit_unwind( nth_frame, memory, regs, ra_address ) );
+            }
+        };
         *initial_address = Some( result.initial_address );
         *ra_address = result.ra_address;
         let cfa = result.cfa?;
@@ -1,7 +1,7 @@
 use gimli::{RegisterRule, CfaRule, LittleEndian};
 
 use crate::arch::{Architecture, Registers, UnwindStatus};
-use crate::address_space::{MemoryReader, Binary, lookup_binary};
+use crate::address_space::{MemoryReader, Binary, lookup_binary, lookup_go_frame};
 use crate::frame_descriptions::{ContextCache, UnwindInfoCache};
 use crate::types::{Endianness, Bitness};
 use crate::dwarf::dwarf_unwind;
@@ -135,6 +135,74 @@ fn guess_ebp< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, ctx_cache
     None
 }
 
+fn go_unwind< M: MemoryReader< Arch > >(
+    nth_frame: usize,
+    memory: &M,
+    regs: &mut <Arch as Architecture>::Regs,
+    initial_address: &mut Option< u64 >,
+    ra_address: &mut Option< u64 >
+) -> Option< UnwindStatus > {
+    // Go binaries usually have no `.eh_frame`, but the Go toolchain always emits a table
+    // of stack pointer offsets for its own traceback, and since Go 1.7 every function
+    // with a stack frame keeps the caller's frame pointer right below the return address.
+    let frame = lookup_go_frame( nth_frame, memory, regs )?;
+    *initial_address = Some( frame.entry );
+    if frame.is_outermost {
+        debug!( "Frame #{} is the outermost Go frame", nth_frame );
+        return Some( UnwindStatus::Finished );
+    }
+
+    // The offsets come straight from the binary, so a garbage table could make these overflow.
+    let rsp = regs.get( dwarf::RSP )?;
+    let return_address_location = rsp.checked_add( frame.sp_offset )?;
+    let caller_rsp = return_address_location.checked_add( 8 )?;
+    let return_address = memory.get_pointer_at_address( return_address_location )?;
+    if frame.sp_offset > 0 {
+        if let Some( rbp ) = return_address_location.checked_sub( 8 ).and_then( |location| memory.get_pointer_at_address( location ) ) {
+            regs.append( dwarf::RBP, rbp );
+        }
+    }
+
+    *ra_address = Some( return_address_location );
+    regs.append( dwarf::RETURN_ADDRESS, return_address );
+    regs.append( dwarf::RSP, caller_rsp );
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( dwarf::RSP ), nth_frame + 1, caller_rsp );
+    Some( UnwindStatus::InProgress )
+}
+
+fn jit_unwind< M: MemoryReader< Arch > >(
+    nth_frame: usize,
+    memory: &M,
+    regs: &mut <Arch as Architecture>::Regs,
+    ra_address: &mut Option< u64 >
+) -> Option< UnwindStatus > {
+    // The code generated by JIT compilers isn't backed by any binary and has no unwind info,
+    // but they can usually be told to keep the frame pointers (e.g. the JVM with `-XX:+PreserveFramePointer`).
+    let address = regs.get( dwarf::RETURN_ADDRESS )?;
+    if memory.get_region_at_address( address ).is_some() {
+        return None;
+    }
+
+    let rsp = regs.get( dwarf::RSP )?;
+    let rbp = regs.get( dwarf::RBP )?;
+    if rbp < rsp || rbp % 8 != 0 || !memory.is_stack_address( rbp ) {
+        debug!( "Frame #{} is not in any binary and has no valid frame pointer", nth_frame );
+        return None;
+    }
+
+    let return_address = memory.get_pointer_at_address( rbp + 8 )?;
+    let previous_rbp = memory.get_pointer_at_address( rbp )?;
+
+    *ra_address = Some( rbp + 8 );
+    regs.append( dwarf::RBP, previous_rbp );
+    regs.append( dwarf::RETURN_ADDRESS, return_address );
+    regs.append( dwarf::RSP, rbp + 16 );
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( dwarf::RSP ), nth_frame + 1, rbp + 16 );
+    Some( UnwindStatus::InProgress )
+}
+
 #[doc(hidden)]
 pub struct State {
     ctx_cache: ContextCache< LittleEndian >,
@@ -198,6 +266,10 @@ impl Architecture for Arch {
         state.unwind_cache.clear();
     }
 
+    fn disable_cache( state: &mut Self::State ) {
+        state.unwind_cache.disable();
+    }
+
     fn unwind< M: MemoryReader< Self > >(
         nth_frame: usize,
         memory: &M,
@@ -213,7 +285,13 @@ impl Architecture for Arch {
             }
         }
 
-        let result = dwarf_unwind( nth_frame, memory, &mut state.ctx_cache, &mut state.unwind_cache, regs, &mut state.new_regs )?;
+        let result = match dwarf_unwind( nth_frame, memory, &mut state.ctx_cache, &mut state.unwind_cache, regs, &mut state.new_regs ) {
+            Some( result ) => result,
+            None => {
+                return go_unwind( nth_frame, memory, regs, initial_address, ra_address )
+                    .or_else( || jit_unwind( nth_frame, memory, regs, ra_address ) );
+            }
+        };
         *initial_address = Some( result.initial_address );
         *ra_address = result.ra_address;
         let cfa = result.cfa?;
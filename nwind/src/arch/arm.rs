@@ -129,6 +129,10 @@ impl Architecture for Arch {
         state.unwind_cache.clear();
     }
 
+    fn disable_cache( state: &mut Self::State ) {
+        state.unwind_cache.disable();
+    }
+
     fn unwind< M: MemoryReader< Self > >(
         nth_frame: usize,
         memory: &M,
@@ -156,16 +160,16 @@ impl Architecture for Arch {
         }
 
         let binary = lookup_binary( nth_frame, memory, regs )?;
-        let binary_data = binary.data()?;
-
-        let exidx_range = match binary_data.arm_exidx_range() {
-            Some( exidx_range ) => exidx_range,
+        let binary_data = match binary.arm_unwind_data() {
+            Some( binary_data ) => binary_data,
             None => {
-                debug!( "Previous frame not found: binary '{}' is missing .ARM.exidx section", binary_data.name() );
+                debug!( "Previous frame not found: binary '{}' is missing .ARM.exidx section", binary.name() );
                 return None;
             }
         };
 
+        let exidx_range = binary_data.arm_exidx_range()?;
+
         let exidx_base = match binary.arm_exidx_address() {
             Some( exidx_address ) => exidx_address,
             None => {
@@ -202,6 +202,10 @@ impl Architecture for Arch {
         state.unwind_cache.clear();
     }
 
+    fn disable_cache( state: &mut Self::State ) {
+        state.unwind_cache.disable();
+    }
+
     #[inline]
     fn unwind< M: MemoryReader< Self > >(
         nth_frame: usize,
@@ -1,6 +1,7 @@
 use std::slice;
 use std::mem;
 use std::fmt;
+use std::io::{self, Write};
 use std::ops::Range;
 
 use byteorder::{ByteOrder, LittleEndian, BigEndian};
@@ -292,6 +293,29 @@ enum Instruction {
     RefuseToUnwind
 }
 
+impl fmt::Display for Instruction {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> Result< (), fmt::Error > {
+        match *self {
+            Instruction::VspAdd( offset ) if offset < 0 => write!( fmt, "vsp = vsp - {}", -offset ),
+            Instruction::VspAdd( offset ) => write!( fmt, "vsp = vsp + {}", offset ),
+            Instruction::VspSet( reg ) => write!( fmt, "vsp = {:?}", reg ),
+            Instruction::PopRegs( reg_mask ) => write!( fmt, "pop {:?}", reg_mask ),
+            Instruction::PopFpRegs( reg_mask ) => write!( fmt, "vpop {:?}", reg_mask ),
+            Instruction::Finish => write!( fmt, "finish" ),
+            Instruction::RefuseToUnwind => write!( fmt, "refuse to unwind" )
+        }
+    }
+}
+
+#[test]
+fn test_display_instruction() {
+    assert_eq!( Instruction::VspAdd( 16 ).to_string(), "vsp = vsp + 16" );
+    assert_eq!( Instruction::VspAdd( -4 ).to_string(), "vsp = vsp - 4" );
+    assert_eq!( Instruction::VspSet( Reg( 7 ) ).to_string(), "vsp = r7" );
+    assert_eq!( Instruction::PopRegs( RegMask( 0b0100_0000_0011_0000 ) ).to_string(), "pop {r4, r5, r14}" );
+    assert_eq!( Instruction::PopFpRegs( FpRegMask( 0b1_0000_0000 ) ).to_string(), "vpop {d8}" );
+}
+
 // See section 9.3 of ARM's EHABI docs for details.
 struct Decoder< I: Iterator< Item = u8 > > {
     bytecode: I,
@@ -803,19 +827,26 @@ struct UnwindInfo {
 }
 
 pub struct UnwindInfoCache {
-    cache: LruCache< u32, UnwindInfo >
+    cache: LruCache< u32, UnwindInfo >,
+    is_disabled: bool
 }
 
 impl UnwindInfoCache {
     pub fn new() -> Self {
         UnwindInfoCache {
-            cache: LruCache::new( 4096 )
+            cache: LruCache::new( 4096 ),
+            is_disabled: false
         }
     }
 
     pub fn clear( &mut self ) {
         self.cache.clear();
     }
+
+    /// Stops putting new entries into the cache, so that unwinding won't allocate.
+    pub fn disable( &mut self ) {
+        self.is_disabled = true;
+    }
 }
 
 fn interpret_bytecode( output: &mut UnwindInfo, bytecode: impl IntoIterator< Item = u8 > ) -> Result< (), Error > {
@@ -1087,7 +1118,7 @@ pub fn unwind< M >(
         }
     }
 
-    {
+    if !unwind_cache.is_disabled {
         let mut rules = Vec::new();
         if unwind_cache.cache.len() == unwind_cache.cache.cap() {
             rules = unwind_cache.cache.pop_lru().map( |(_, old)| old.rules ).unwrap();
@@ -1114,6 +1145,50 @@ pub fn unwind< M >(
     Ok( link_register_addr )
 }
 
+/// Writes out every entry of the `.ARM.exidx` section along with its decoded unwinding instructions.
+pub(crate) fn write_exidx( exidx: &[u8], extab: &[u8], exidx_base: u32, extab_base: u32, fp: &mut dyn Write ) -> io::Result< () > {
+    let exidx: &[IndexEntry] = unsafe {
+        slice::from_raw_parts( exidx.as_ptr() as *const IndexEntry, exidx.len() / mem::size_of::< IndexEntry >() )
+    };
+
+    for (index, entry) in exidx.iter().enumerate() {
+        let function_start = exidx_offset( exidx_base, index as u32, entry.offset_to_function() );
+        write!( fp, "0x{:08X}:", function_start )?;
+        if entry.value() == EXIDX_CANTUNWIND {
+            writeln!( fp, " cantunwind" )?;
+            continue;
+        }
+
+        let iter = match get_bytecode_iter( function_start, index, entry, exidx_base, extab_base, extab ) {
+            Ok( iter ) => iter,
+            Err( error ) => {
+                writeln!( fp, " <error: {:?}>", error )?;
+                continue;
+            }
+        };
+
+        let mut is_first = true;
+        for instruction in Decoder::new( iter ) {
+            if !is_first {
+                write!( fp, ";" )?;
+            }
+            is_first = false;
+
+            match instruction {
+                Ok( instruction ) => write!( fp, " {}", instruction )?,
+                Err( error ) => {
+                    write!( fp, " <error: {:?}>", error )?;
+                    break;
+                }
+            }
+        }
+
+        writeln!( fp )?;
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_decode_everything_from_a_binary() {
     use crate::binary::BinaryData;
@@ -0,0 +1,361 @@
+//! A backtrace of the current thread, in the spirit of `std::backtrace::Backtrace`,
+//! which is captured with nwind's local unwinding and symbolized only when needed.
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::sync::{Once, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::local_unwinding::{LocalAddressSpace, LocalUnwindContext, UnwindControl};
+
+static INIT: Once = Once::new();
+static mut ADDRESS_SPACE: Option< RwLock< LocalAddressSpace > > = None;
+
+thread_local! {
+    static CONTEXT: RefCell< LocalUnwindContext > = RefCell::new( LocalUnwindContext::new() );
+}
+
+fn address_space() -> Option< &'static RwLock< LocalAddressSpace > > {
+    INIT.call_once( || {
+        match LocalAddressSpace::new() {
+            Ok( mut address_space ) => {
+                // The shadow stack patches the return addresses on the stack, which is
+                // not something we'd want to do to whoever just wants a backtrace.
+                address_space.use_shadow_stack( false );
+                unsafe {
+                    ADDRESS_SPACE = Some( RwLock::new( address_space ) );
+                }
+            },
+            Err( error ) => {
+                warn!( "Failed to load the address space of the current process: {}", error );
+            }
+        }
+    });
+
+    unsafe { (*std::ptr::addr_of!( ADDRESS_SPACE )).as_ref() }
+}
+
+fn is_enabled() -> bool {
+    // 0 = not checked yet, 1 = disabled, 2 = enabled
+    static ENABLED: AtomicUsize = AtomicUsize::new( 0 );
+    match ENABLED.load( Ordering::Relaxed ) {
+        0 => {},
+        value => return value == 2
+    }
+
+    let is_enabled = match env::var_os( "RUST_LIB_BACKTRACE" ).or_else( || env::var_os( "RUST_BACKTRACE" ) ) {
+        Some( value ) => value != "0",
+        None => false
+    };
+
+    ENABLED.store( if is_enabled { 2 } else { 1 }, Ordering::Relaxed );
+    is_enabled
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BacktraceStatus {
+    /// Capturing a backtrace is not supported, e.g. the binaries of the current process couldn't be loaded.
+    Unsupported,
+    /// Capturing a backtrace was disabled through the `RUST_LIB_BACKTRACE` or the `RUST_BACKTRACE` environment variables.
+    Disabled,
+    Captured
+}
+
+#[derive(Clone, Debug)]
+pub struct BacktraceSymbol {
+    /// The demangled name of the function, or the raw one if it couldn't be demangled.
+    pub name: Option< String >,
+    /// The path of the binary to which the function belongs.
+    pub library: Option< String >,
+    pub file: Option< String >,
+    pub line: Option< u64 >,
+    pub column: Option< u64 >,
+    /// Whether the function was inlined into the next symbol of the frame.
+    pub is_inline: bool
+}
+
+#[derive(Clone, Debug)]
+pub struct BacktraceFrame {
+    address: usize,
+    symbols: Option< Vec< BacktraceSymbol > >
+}
+
+impl BacktraceFrame {
+    /// The address at which the frame was executing; for every frame except the innermost one
+    /// this is the return address.
+    pub fn address( &self ) -> usize {
+        self.address
+    }
+
+    /// The symbols of the frame, innermost first; there are more than one only if
+    /// functions were inlined. Empty if the backtrace wasn't resolved yet.
+    pub fn symbols( &self ) -> &[BacktraceSymbol] {
+        self.symbols.as_ref().map( |symbols| symbols.as_slice() ).unwrap_or( &[] )
+    }
+}
+
+fn resolve_symbols( address_space: &LocalAddressSpace, address: usize ) -> Vec< BacktraceSymbol > {
+    // Look up the call instruction instead of whatever's after it.
+    let address = address.saturating_sub( 1 );
+    let mut symbols = Vec::new();
+    address_space.decode_symbol_while( address, &mut |frame| {
+        symbols.push( BacktraceSymbol {
+            name: frame.demangled_name.as_ref().or( frame.name.as_ref() ).map( |name| name.clone().into_owned() ),
+            library: frame.library.as_ref().map( |library| library.clone().into_owned() ),
+            file: frame.file.clone(),
+            line: frame.line,
+            column: frame.column,
+            is_inline: frame.is_inline
+        });
+
+        true
+    });
+
+    symbols
+}
+
+/// A captured backtrace of the current thread.
+///
+/// It's cheap to capture since the frames are only symbolized when it's
+/// either displayed or when `resolve` is called.
+#[derive(Clone)]
+pub struct Backtrace {
+    status: BacktraceStatus,
+    frames: Vec< BacktraceFrame >
+}
+
+impl Backtrace {
+    /// Captures a backtrace of the current thread if it's enabled through the `RUST_LIB_BACKTRACE`
+    /// or the `RUST_BACKTRACE` environment variables, just as `std::backtrace::Backtrace::capture` does.
+    #[inline(never)]
+    pub fn capture() -> Self {
+        if !is_enabled() {
+            return Backtrace {
+                status: BacktraceStatus::Disabled,
+                frames: Vec::new()
+            };
+        }
+
+        Self::capture_impl()
+    }
+
+    /// Captures a backtrace of the current thread regardless of the environment variables.
+    #[inline(never)]
+    pub fn force_capture() -> Self {
+        Self::capture_impl()
+    }
+
+    pub fn disabled() -> Self {
+        Backtrace {
+            status: BacktraceStatus::Disabled,
+            frames: Vec::new()
+        }
+    }
+
+    #[inline(always)]
+    fn capture_impl() -> Self {
+        let address_space = match address_space() {
+            Some( address_space ) => address_space,
+            None => {
+                return Backtrace {
+                    status: BacktraceStatus::Unsupported,
+                    frames: Vec::new()
+                };
+            }
+        };
+
+        // We can't unwind from within the closure passed to `with` since we
+        // don't know how many frames it'd introduce.
+        let ctx = CONTEXT.with( |ctx| ctx as *const RefCell< LocalUnwindContext > );
+        let ctx = unsafe { &*ctx };
+
+        let mut addresses = Vec::new();
+        match ctx.try_borrow_mut() {
+            Ok( mut ctx ) => unwind( address_space, &mut ctx, &mut addresses ),
+            Err( _ ) => unwind( address_space, &mut LocalUnwindContext::new(), &mut addresses )
+        }
+
+        // Skip the frame of the function which captured the backtrace.
+        let frames = addresses.into_iter().skip( 1 ).map( |address| BacktraceFrame { address, symbols: None } ).collect();
+        Backtrace {
+            status: BacktraceStatus::Captured,
+            frames
+        }
+    }
+
+    pub fn status( &self ) -> BacktraceStatus {
+        self.status
+    }
+
+    pub fn frames( &self ) -> &[BacktraceFrame] {
+        &self.frames
+    }
+
+    /// Symbolizes every frame which wasn't symbolized yet.
+    pub fn resolve( &mut self ) {
+        if self.frames.iter().all( |frame| frame.symbols.is_some() ) {
+            return;
+        }
+
+        let address_space = match address_space() {
+            Some( address_space ) => address_space.read().unwrap(),
+            None => return
+        };
+
+        for frame in &mut self.frames {
+            if frame.symbols.is_none() {
+                frame.symbols = Some( resolve_symbols( &address_space, frame.address ) );
+            }
+        }
+    }
+
+    fn for_each_symbol( &self, mut callback: impl FnMut( usize, &BacktraceSymbol ) -> fmt::Result ) -> fmt::Result {
+        let address_space = address_space().map( |address_space| address_space.read().unwrap() );
+        for (index, frame) in self.frames.iter().enumerate() {
+            let resolved;
+            let symbols = match (frame.symbols.as_ref(), address_space.as_ref()) {
+                (Some( symbols ), _) => symbols,
+                (None, Some( address_space )) => {
+                    resolved = resolve_symbols( address_space, frame.address );
+                    &resolved
+                },
+                (None, None) => continue
+            };
+
+            for symbol in symbols {
+                callback( index, symbol )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// This has to be always inlined so that the unwinding starts in the function which captures the backtrace.
+#[inline(always)]
+fn unwind( address_space: &RwLock< LocalAddressSpace >, ctx: &mut LocalUnwindContext, addresses: &mut Vec< usize > ) {
+    {
+        let address_space = address_space.read().unwrap();
+        address_space.unwind( ctx, |address| {
+            addresses.push( address );
+            UnwindControl::Continue
+        });
+
+        // If the unwinding stopped in a binary we don't know about (e.g. it was `dlopen`ed
+        // after we've loaded the address space) then reload and try again.
+        let is_unknown = addresses.last().map( |&address| address_space.regions.get_value( address as u64 ).is_none() ).unwrap_or( false );
+        if !is_unknown {
+            return;
+        }
+    }
+
+    let mut address_space = address_space.write().unwrap();
+    if let Err( error ) = address_space.reload() {
+        warn!( "Failed to reload the address space of the current process: {}", error );
+        return;
+    }
+
+    addresses.clear();
+    address_space.unwind( ctx, |address| {
+        addresses.push( address );
+        UnwindControl::Continue
+    });
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match self.status {
+            BacktraceStatus::Unsupported => return write!( fmt, "unsupported backtrace" ),
+            BacktraceStatus::Disabled => return write!( fmt, "disabled backtrace" ),
+            BacktraceStatus::Captured => {}
+        }
+
+        let mut last_index = None;
+        self.for_each_symbol( |index, symbol| {
+            if last_index != Some( index ) {
+                write!( fmt, "{:>4}: ", index )?;
+                last_index = Some( index );
+            } else {
+                write!( fmt, "      " )?;
+            }
+
+            match symbol.name {
+                Some( ref name ) => writeln!( fmt, "{}", name )?,
+                None => writeln!( fmt, "<unknown>" )?
+            }
+
+            if let Some( ref file ) = symbol.file {
+                write!( fmt, "             at {}", file )?;
+                if let Some( line ) = symbol.line {
+                    write!( fmt, ":{}", line )?;
+                    if let Some( column ) = symbol.column {
+                        write!( fmt, ":{}", column )?;
+                    }
+                }
+                writeln!( fmt )?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl fmt::Debug for Backtrace {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match self.status {
+            BacktraceStatus::Unsupported => return write!( fmt, "<unsupported>" ),
+            BacktraceStatus::Disabled => return write!( fmt, "<disabled>" ),
+            BacktraceStatus::Captured => {}
+        }
+
+        write!( fmt, "Backtrace [" )?;
+        let mut is_first = true;
+        self.for_each_symbol( |_, symbol| {
+            if !is_first {
+                write!( fmt, ", " )?;
+            }
+            is_first = false;
+
+            write!( fmt, "{{ fn: {:?}", symbol.name.as_ref().map( |name| name.as_str() ).unwrap_or( "<unknown>" ) )?;
+            if let Some( ref file ) = symbol.file {
+                write!( fmt, ", file: {:?}", file )?;
+            }
+            if let Some( line ) = symbol.line {
+                write!( fmt, ", line: {}", line )?;
+            }
+            write!( fmt, " }}" )
+        })?;
+        write!( fmt, "]" )
+    }
+}
+
+#[inline(never)]
+#[cfg(test)]
+fn capture_here() -> Backtrace {
+    let backtrace = Backtrace::force_capture();
+
+    // Make sure this won't be turned into a tail call.
+    let local = 0;
+    unsafe {
+        std::ptr::read_volatile( &local );
+    }
+
+    backtrace
+}
+
+#[test]
+fn test_backtrace() {
+    let mut backtrace = capture_here();
+    assert_eq!( backtrace.status(), BacktraceStatus::Captured );
+    assert!( backtrace.frames().len() > 3 );
+    assert!( backtrace.frames()[ 0 ].symbols().is_empty() );
+
+    let output = backtrace.to_string();
+    assert!( output.starts_with( "   0: nwind::backtrace::capture_here\n" ), "unexpected output: {}", output );
+
+    backtrace.resolve();
+    let symbol = &backtrace.frames()[ 0 ].symbols()[ 0 ];
+    assert_eq!( symbol.name.as_ref().unwrap(), "nwind::backtrace::capture_here" );
+    assert!( symbol.library.is_some() );
+}
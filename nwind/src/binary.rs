@@ -1,5 +1,7 @@
 use std::str;
 use std::io;
+use std::borrow::Cow;
+#[cfg(unix)]
 use std::fs::File;
 use std::ops::{Range, Deref, Index};
 #[cfg(unix)]
@@ -8,18 +10,23 @@ use std::path::Path;
 use std::sync::Arc;
 use std::fmt;
 
+#[cfg(unix)]
 use memmap::Mmap;
 use goblin::elf::header as elf_header;
-use goblin::elf::section_header::{SHT_SYMTAB, SHT_DYNSYM, SHT_STRTAB};
+use goblin::elf::section_header::{SHT_SYMTAB, SHT_DYNSYM, SHT_STRTAB, SHT_NOBITS};
 use goblin::elf::program_header::PT_LOAD;
 use gimli;
 use speedy::{Readable, Writable};
 
 use crate::elf::{self, Endian};
-use crate::utils::{StableIndex, get_major, get_minor};
+use crate::malformed::report_malformed;
+use crate::utils::StableIndex;
+#[cfg(unix)]
+use crate::utils::{get_major, get_minor};
 use crate::types::{Inode, Bitness, Endianness};
 
 enum Blob {
+    #[cfg(unix)]
     Mmap( Mmap ),
     StaticSlice( &'static [u8] ),
     Owned( Vec< u8 > )
@@ -31,6 +38,7 @@ impl Deref for Blob {
     #[inline]
     fn deref( &self ) -> &Self::Target {
         match *self {
+            #[cfg(unix)]
             Blob::Mmap( ref mmap ) => &mmap,
             Blob::StaticSlice( slice ) => slice,
             Blob::Owned( ref bytes ) => &bytes
@@ -38,7 +46,7 @@ impl Deref for Blob {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Readable, Writable)]
 pub struct SymbolTable {
     pub range: Range< u64 >,
     pub strtab_range: Range< u64 >,
@@ -69,6 +77,8 @@ pub struct BinaryData {
     gnu_debuglink_range: Option< Range< usize > >,
     arm_extab_range: Option< Range< usize > >,
     arm_exidx_range: Option< Range< usize > >,
+    gopclntab_range: Option< Range< usize > >,
+    sections: Vec< (String, Range< usize >) >,
     is_shared_object: bool,
     symbol_tables: Vec< SymbolTable >,
     load_headers: Vec< LoadHeader >,
@@ -103,7 +113,7 @@ impl BinaryData {
 
     #[cfg(not(unix))]
     pub fn load_from_fs< P: AsRef< Path > >( _: P ) -> io::Result< Self > {
-        unimplemented!();
+        Err( io::Error::new( io::ErrorKind::Other, "loading binaries from the filesystem is not supported on this platform" ) )
     }
 
     pub fn load_from_static_slice( name: &str, slice: &'static [u8] ) -> io::Result< Self > {
@@ -141,8 +151,10 @@ impl BinaryData {
         let mut gnu_debuglink_range = None;
         let mut arm_extab_range = None;
         let mut arm_exidx_range = None;
+        let mut gopclntab_range = None;
         let mut build_id_range = None;
         let mut build_id = None;
+        let mut sections = Vec::new();
         let mut is_shared_object = false;
         let mut symbol_tables = Vec::new();
         let mut load_headers = Vec::new();
@@ -189,6 +201,17 @@ impl BinaryData {
                     }
                 };
 
+                let header = elf.header();
+                let section_headers_end = (header.e_shoff as u64).saturating_add( header.e_shnum as u64 * header.e_shentsize as u64 );
+                if section_headers_end > blob.len() as u64 {
+                    report_malformed( path, "section header table", header.e_shoff as u64, format_args!( "ends at 0x{:X}, past the end of the file", section_headers_end ) )?;
+                }
+
+                let program_headers_end = (header.e_phoff as u64).saturating_add( header.e_phnum as u64 * header.e_phentsize as u64 );
+                if program_headers_end > blob.len() as u64 {
+                    report_malformed( path, "program header table", header.e_phoff as u64, format_args!( "ends at 0x{:X}, past the end of the file", program_headers_end ) )?;
+                }
+
                 let name_strtab_header = elf.get_section_header( elf.header().e_shstrndx as usize )
                     .ok_or_else( || io::Error::new( io::ErrorKind::Other, format!( "missing section header for section names strtab for {:?}", path ) ) )?;
 
@@ -204,11 +227,16 @@ impl BinaryData {
                             if strtab_header.sh_type as u32 == SHT_STRTAB {
                                 let strtab_range = elf.get_section_body_range( &strtab_header );
                                 let symtab_range = elf.get_section_body_range( &header );
-                                symbol_tables.push( SymbolTable {
-                                    range: symtab_range,
-                                    strtab_range,
-                                    is_dynamic
-                                });
+                                if symtab_range.end > blob.len() as u64 || strtab_range.end > blob.len() as u64 {
+                                    let section_name = if is_dynamic { ".dynsym" } else { ".symtab" };
+                                    report_malformed( path, section_name, symtab_range.start, "the symbol table or its string table extends past the end of the file" )?;
+                                } else {
+                                    symbol_tables.push( SymbolTable {
+                                        range: symtab_range,
+                                        strtab_range,
+                                        is_dynamic
+                                    });
+                                }
                             }
                         }
                     }
@@ -218,6 +246,12 @@ impl BinaryData {
                         _ => continue
                     };
 
+                    // These are read directly by the unwinder, and a separate debug file made with
+                    // `objcopy --only-keep-debug` keeps their headers but not their contents.
+                    if ty == SHT_NOBITS && (section_name == ".ARM.exidx" || section_name == ".ARM.extab") {
+                        continue;
+                    }
+
                     let out_range = match section_name {
                         ".data" => Some( &mut data_range ),
                         ".text" => Some( &mut text_range ),
@@ -227,17 +261,22 @@ impl BinaryData {
                         ".gnu_debuglink" => Some( &mut gnu_debuglink_range ),
                         ".ARM.extab" => Some( &mut arm_extab_range ),
                         ".ARM.exidx" => Some( &mut arm_exidx_range ),
+                        ".gopclntab" | ".data.rel.ro.gopclntab" => Some( &mut gopclntab_range ),
                         ".note.gnu.build-id" => Some( &mut build_id_range ),
                         _ => None
                     };
 
                     let offset = header.sh_offset as usize;
                     let length = header.sh_size as usize;
-                    let range = offset..offset + length;
+                    let range = offset..offset.saturating_add( length );
                     if let Some( _ ) = blob.get( range.clone() ) {
                         if let Some( out_range ) = out_range {
                             *out_range = Some( range.clone() );
                         }
+
+                        sections.push( (section_name.to_owned(), range) );
+                    } else if ty != SHT_NOBITS {
+                        report_malformed( path, section_name, offset as u64, format_args!( "the section's 0x{:X} bytes extend past the end of the file", length ) )?;
                     }
                 }
 
@@ -250,6 +289,8 @@ impl BinaryData {
 
                     if let Some( note ) = note {
                         build_id = Some( note.desc.into() );
+                    } else {
+                        report_malformed( path, ".note.gnu.build-id", range.start as u64, "failed to parse the note" )?;
                     }
                 }
 
@@ -288,6 +329,8 @@ impl BinaryData {
             gnu_debuglink_range,
             arm_extab_range,
             arm_exidx_range,
+            gopclntab_range,
+            sections,
             is_shared_object,
             symbol_tables,
             load_headers,
@@ -300,6 +343,84 @@ impl BinaryData {
         Ok( binary )
     }
 
+    /// Restores a binary from a snapshot without parsing the ELF file again.
+    pub(crate) fn from_raw( raw: RawBinaryData ) -> io::Result< Self > {
+        let architecture = match raw.architecture.as_str() {
+            "amd64" => "amd64",
+            "x86" => "x86",
+            "arm" => "arm",
+            "mips" => "mips",
+            "mips64" => "mips64",
+            "aarch64" => "aarch64",
+            architecture => {
+                return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "unknown architecture '{}' for {:?}", architecture, raw.name ) ) );
+            }
+        };
+
+        let blob = Blob::Owned( raw.bytes.into_owned() );
+        let mut sections = Vec::with_capacity( raw.sections.len() );
+        for section in raw.sections {
+            let range = section.range.start as usize..section.range.end as usize;
+            if blob.get( range.clone() ).is_none() {
+                return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "section '{}' of {:?} is out of bounds", section.name, raw.name ) ) );
+            }
+
+            sections.push( (section.name, range) );
+        }
+
+        // Same as when loading, so the last section with a given name wins.
+        let find_section = |names: &[&str]| {
+            sections.iter().rev().find( |(name, _)| names.contains( &name.as_str() ) ).map( |(_, range)| range.clone() )
+        };
+
+        Ok( BinaryData {
+            inode: raw.inode,
+            name: raw.name,
+            data_range: find_section( &[".data"] ),
+            text_range: find_section( &[".text"] ),
+            eh_frame_range: find_section( &[".eh_frame"] ),
+            eh_frame_hdr_range: find_section( &[".eh_frame_hdr"] ),
+            debug_frame_range: find_section( &[".debug_frame"] ),
+            gnu_debuglink_range: find_section( &[".gnu_debuglink"] ),
+            arm_extab_range: find_section( &[".ARM.extab"] ),
+            arm_exidx_range: find_section( &[".ARM.exidx"] ),
+            gopclntab_range: find_section( &[".gopclntab", ".data.rel.ro.gopclntab"] ),
+            blob,
+            sections,
+            is_shared_object: raw.is_shared_object,
+            symbol_tables: raw.symbol_tables,
+            load_headers: raw.load_headers,
+            architecture,
+            endianness: raw.endianness,
+            bitness: raw.bitness,
+            build_id: raw.build_id
+        })
+    }
+
+    /// Converts the binary into its snapshot form, which contains the whole file along with everything which was parsed out of it.
+    pub(crate) fn to_raw( &self ) -> RawBinaryData< '_ > {
+        RawBinaryData {
+            inode: self.inode,
+            name: self.name.clone(),
+            bytes: Cow::Borrowed( &self.blob ),
+            sections: self.sections.iter().map( |(name, range)| RawSection {
+                name: name.clone(),
+                range: range.start as u64..range.end as u64
+            }).collect(),
+            is_shared_object: self.is_shared_object,
+            symbol_tables: self.symbol_tables.iter().map( |table| SymbolTable {
+                range: table.range.clone(),
+                strtab_range: table.strtab_range.clone(),
+                is_dynamic: table.is_dynamic
+            }).collect(),
+            load_headers: self.load_headers.clone(),
+            architecture: self.architecture.to_owned(),
+            endianness: self.endianness,
+            bitness: self.bitness,
+            build_id: self.build_id.clone()
+        }
+    }
+
     #[inline]
     pub fn inode( &self ) -> Option< Inode > {
         self.inode
@@ -310,6 +431,11 @@ impl BinaryData {
         self.inode = Some( inode );
     }
 
+    #[inline]
+    pub fn set_name( &mut self, name: &str ) {
+        self.name = name.to_owned();
+    }
+
     #[inline]
     pub fn name( &self ) -> &str {
         &self.name
@@ -385,32 +511,19 @@ impl BinaryData {
         self.arm_exidx_range.clone()
     }
 
-    fn get_section_range( &self, name: &str ) -> Option< Range< usize > > {
-        let elf = elf::parse( &self.blob ).map_err( |err| io::Error::new( io::ErrorKind::Other, err ) ).unwrap();
-        parse_elf!( elf, |elf| {
-            let name_strtab_header = elf.get_section_header( elf.header().e_shstrndx as usize ).unwrap();
-            let name_strtab = elf.get_strtab( &name_strtab_header ).unwrap();
-
-            for header in elf.section_headers() {
-                let section_name = match name_strtab.get( header.sh_name ) {
-                    Some( Ok( name ) ) => name,
-                    _ => continue
-                };
-
-                if section_name != name {
-                    continue;
-                }
+    /// The range of the `.gopclntab` section, which is only present in binaries built by the Go toolchain.
+    #[inline]
+    pub fn gopclntab_range( &self ) -> Option< Range< usize > > {
+        self.gopclntab_range.clone()
+    }
 
-                let offset = header.sh_offset as usize;
-                let length = header.sh_size as usize;
-                let range = offset..offset + length;
-                if let Some( _ ) = self.blob.get( range.clone() ) {
-                    return Some( range );
-                }
-            }
+    /// Whether the binary contains DWARF debug info.
+    pub fn has_debug_info( &self ) -> bool {
+        self.get_section_range( ".debug_info" ).map( |range| !range.is_empty() ).unwrap_or( false )
+    }
 
-            None
-        })
+    fn get_section_range( &self, name: &str ) -> Option< Range< usize > > {
+        self.sections.iter().find( |(section_name, _)| section_name == name ).map( |(_, range)| range.clone() )
     }
 
     #[inline]
@@ -431,6 +544,12 @@ impl BinaryData {
         Self::get_range_reader( data, range ).into()
     }
 
+    #[inline]
+    pub(crate) fn get_section_by_name_or_empty( data: &Arc< BinaryData >, name: &str ) -> BinaryDataReader {
+        let range = data.get_section_range( name ).unwrap_or( 0..0 );
+        Self::get_range_reader( data, range )
+    }
+
     #[inline]
     fn get_range_reader( data: &Arc< BinaryData >, range: Range< usize > ) -> BinaryDataReader {
         let endianness = match data.endianness() {
@@ -446,6 +565,13 @@ impl BinaryData {
         &self.load_headers
     }
 
+    /// Returns the address at which a given file offset is supposed to be loaded, according to the `PT_LOAD` headers.
+    pub fn file_offset_to_address( &self, file_offset: u64 ) -> Option< u64 > {
+        self.load_headers.iter()
+            .find( |header| file_offset >= header.file_offset && file_offset < (header.file_offset + header.file_size) )
+            .map( |header| header.address + (file_offset - header.file_offset) )
+    }
+
     #[inline]
     pub fn build_id( &self ) -> Option< &[u8] > {
         self.build_id.as_ref().map( |id| id.as_slice() )
@@ -491,6 +617,27 @@ impl Index< Range< u64 > > for BinaryData {
     }
 }
 
+#[derive(Readable, Writable)]
+struct RawSection {
+    name: String,
+    range: Range< u64 >
+}
+
+#[derive(Readable, Writable)]
+pub(crate) struct RawBinaryData< 'a > {
+    inode: Option< Inode >,
+    name: String,
+    bytes: Cow< 'a, [u8] >,
+    sections: Vec< RawSection >,
+    is_shared_object: bool,
+    symbol_tables: Vec< SymbolTable >,
+    load_headers: Vec< LoadHeader >,
+    architecture: String,
+    endianness: Endianness,
+    bitness: Bitness,
+    build_id: Option< Vec< u8 > >
+}
+
 #[derive(Clone)]
 pub struct BinaryDataSlice {
     data: Arc< BinaryData >,
@@ -0,0 +1,595 @@
+// Support for writing out the symbols, the line tables, the inline frames and the CFI
+// of a binary as a Breakpad symbol file, as described here:
+//     https://chromium.googlesource.com/breakpad/breakpad/+/master/docs/symbol_files.md
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::ops::Range;
+use std::path::Path;
+use std::collections::HashMap;
+
+use gimli::{self, Reader};
+
+use crate::arch::Endianity;
+use crate::address_space::demangle;
+use crate::binary::{BinaryData, BinaryDataReader};
+use crate::frame_descriptions::{FrameDescriptions, LoadHint};
+use crate::symbols::Symbols;
+use crate::types::Endianness;
+
+type Dwarf = gimli::Dwarf< BinaryDataReader >;
+type Unit = gimli::Unit< BinaryDataReader >;
+
+struct LineRecord {
+    address: u64,
+    size: u64,
+    line: u64,
+    file: usize
+}
+
+struct InlineRecord {
+    depth: usize,
+    call_line: u64,
+    call_file: usize,
+    origin: usize,
+    ranges: Vec< Range< u64 > >
+}
+
+#[derive(Default)]
+struct DebugInfo {
+    files: Vec< String >,
+    file_index: HashMap< String, usize >,
+    origins: Vec< String >,
+    origin_index: HashMap< String, usize >,
+    lines: Vec< LineRecord >,
+    inlines: Vec< InlineRecord >
+}
+
+impl DebugInfo {
+    fn intern_file( &mut self, path: String ) -> usize {
+        if let Some( &index ) = self.file_index.get( &path ) {
+            return index;
+        }
+
+        let index = self.files.len();
+        self.files.push( path.clone() );
+        self.file_index.insert( path, index );
+        index
+    }
+
+    fn intern_origin( &mut self, name: String ) -> usize {
+        if let Some( &index ) = self.origin_index.get( &name ) {
+            return index;
+        }
+
+        let index = self.origins.len();
+        self.origins.push( name.clone() );
+        self.origin_index.insert( name, index );
+        index
+    }
+}
+
+fn breakpad_architecture( architecture: &str ) -> &str {
+    match architecture {
+        "amd64" => "x86_64",
+        "aarch64" => "arm64",
+        architecture => architecture
+    }
+}
+
+/// Converts a GNU build ID into a Breakpad module ID, which is the first
+/// 16 bytes of the build ID treated as a little endian GUID plus an age of zero.
+fn module_id( binary: &BinaryData ) -> String {
+    let mut guid = [0; 16];
+    if let Some( build_id ) = binary.build_id() {
+        let length = std::cmp::min( build_id.len(), guid.len() );
+        guid[ ..length ].copy_from_slice( &build_id[ ..length ] );
+    } else if let Some( range ) = binary.text_range() {
+        // This is what Breakpad does for binaries without a build ID.
+        let text = &binary.as_bytes()[ range ];
+        for (index, byte) in text.iter().take( 4096 ).enumerate() {
+            guid[ index % 16 ] ^= byte;
+        }
+    }
+
+    guid[ 0..4 ].reverse();
+    guid[ 4..6 ].reverse();
+    guid[ 6..8 ].reverse();
+
+    let mut output: String = guid.iter().map( |byte| format!( "{:02X}", byte ) ).collect();
+    output.push( '0' );
+    output
+}
+
+/// The address at which Breakpad considers the module to be loaded;
+/// every address in the symbol file is relative to it.
+fn load_address( binary: &BinaryData ) -> u64 {
+    binary.load_headers().iter()
+        .filter( |header| header.file_offset == 0 )
+        .map( |header| header.address )
+        .min()
+        .unwrap_or( 0 )
+}
+
+fn path_push( path: &mut String, component: &str ) {
+    if component.starts_with( '/' ) {
+        *path = component.to_owned();
+    } else {
+        if !path.is_empty() && !path.ends_with( '/' ) {
+            path.push( '/' );
+        }
+        path.push_str( component );
+    }
+}
+
+fn render_file( dwarf: &Dwarf, unit: &Unit, header: &gimli::LineProgramHeader< BinaryDataReader >, index: u64 ) -> Result< Option< String >, gimli::Error > {
+    let file = match header.file( index ) {
+        Some( file ) => file,
+        None => return Ok( None )
+    };
+
+    let mut path = match unit.comp_dir {
+        Some( ref comp_dir ) => comp_dir.to_string_lossy()?.into_owned(),
+        None => String::new()
+    };
+
+    if let Some( directory ) = file.directory( header ) {
+        path_push( &mut path, &dwarf.attr_string( unit, directory )?.to_string_lossy()? );
+    }
+
+    path_push( &mut path, &dwarf.attr_string( unit, file.path_name() )?.to_string_lossy()? );
+    Ok( Some( path ) )
+}
+
+fn load_lines( dwarf: &Dwarf, unit: &Unit, info: &mut DebugInfo, files: &mut HashMap< u64, usize > ) -> Result< (), gimli::Error > {
+    let program = match unit.line_program {
+        Some( ref program ) => program.clone(),
+        None => return Ok(())
+    };
+
+    let mut previous: Option< (u64, u64, u64) > = None;
+    let mut rows = program.rows();
+    while let Some( (header, row) ) = rows.next_row()? {
+        if let Some( (address, file_index, line) ) = previous.take() {
+            if row.address() > address {
+                let file = match files.get( &file_index ) {
+                    Some( &file ) => Some( file ),
+                    None => render_file( dwarf, unit, header, file_index )?.map( |path| {
+                        let file = info.intern_file( path );
+                        files.insert( file_index, file );
+                        file
+                    })
+                };
+
+                if let Some( file ) = file {
+                    info.lines.push( LineRecord { address, size: row.address() - address, line, file } );
+                }
+            }
+        }
+
+        if !row.end_sequence() {
+            previous = Some( (row.address(), row.file_index(), row.line().map( |line| line.get() ).unwrap_or( 0 )) );
+        }
+    }
+
+    Ok(())
+}
+
+fn origin_name( dwarf: &Dwarf, unit: &Unit, offset: gimli::UnitOffset ) -> Result< Option< String >, gimli::Error > {
+    let mut offset = offset;
+    let mut name = None;
+
+    // Follow the chain of abstract origins and declarations, preferring the linkage name.
+    for _ in 0..8 {
+        let entry = unit.entry( offset )?;
+        if let Some( value ) = entry.attr_value( gimli::DW_AT_linkage_name )?.or( entry.attr_value( gimli::DW_AT_MIPS_linkage_name )? ) {
+            let raw_name = dwarf.attr_string( unit, value )?.to_string_lossy()?.into_owned();
+            return Ok( Some( demangle( &raw_name ).unwrap_or( raw_name ) ) );
+        }
+
+        if name.is_none() {
+            if let Some( value ) = entry.attr_value( gimli::DW_AT_name )? {
+                name = Some( dwarf.attr_string( unit, value )?.to_string_lossy()?.into_owned() );
+            }
+        }
+
+        let next = entry.attr_value( gimli::DW_AT_abstract_origin )?.or( entry.attr_value( gimli::DW_AT_specification )? );
+        match next {
+            Some( gimli::AttributeValue::UnitRef( next ) ) => offset = next,
+            _ => break
+        }
+    }
+
+    Ok( name )
+}
+
+fn load_inlines( dwarf: &Dwarf, unit: &Unit, info: &mut DebugInfo, files: &mut HashMap< u64, usize > ) -> Result< (), gimli::Error > {
+    let header = unit.line_program.as_ref().map( |program| program.header() );
+
+    // The tree depths of the inlined subroutines enclosing the current entry.
+    let mut parents: Vec< isize > = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some( (delta, entry) ) = entries.next_dfs()? {
+        depth += delta;
+        while parents.last().map( |&parent| parent >= depth ).unwrap_or( false ) {
+            parents.pop();
+        }
+
+        if entry.tag() == gimli::DW_TAG_subprogram {
+            parents.clear();
+            continue;
+        }
+
+        if entry.tag() != gimli::DW_TAG_inlined_subroutine {
+            continue;
+        }
+
+        let inline_depth = parents.len();
+        parents.push( depth );
+
+        let name = match entry.attr_value( gimli::DW_AT_abstract_origin )? {
+            Some( gimli::AttributeValue::UnitRef( offset ) ) => origin_name( dwarf, unit, offset )?,
+            _ => None
+        };
+
+        let call_file = match (entry.attr_value( gimli::DW_AT_call_file )?, header) {
+            (Some( gimli::AttributeValue::FileIndex( index ) ), Some( header )) |
+            (Some( gimli::AttributeValue::Udata( index ) ), Some( header )) => {
+                match files.get( &index ) {
+                    Some( &file ) => Some( file ),
+                    None => render_file( dwarf, unit, header, index )?.map( |path| {
+                        let file = info.intern_file( path );
+                        files.insert( index, file );
+                        file
+                    })
+                }
+            },
+            _ => None
+        };
+
+        let call_line = entry.attr_value( gimli::DW_AT_call_line )?.and_then( |value| value.udata_value() ).unwrap_or( 0 );
+
+        let mut ranges = Vec::new();
+        let mut iter = dwarf.die_ranges( unit, entry )?;
+        while let Some( range ) = iter.next()? {
+            if range.end > range.begin {
+                ranges.push( range.begin..range.end );
+            }
+        }
+
+        let (name, call_file) = match (name, call_file) {
+            (Some( name ), Some( call_file )) if !ranges.is_empty() => (name, call_file),
+            _ => continue
+        };
+
+        ranges.sort_by_key( |range| range.start );
+        let origin = info.intern_origin( name );
+        info.inlines.push( InlineRecord { depth: inline_depth, call_line, call_file, origin, ranges } );
+    }
+
+    Ok(())
+}
+
+fn load_debug_info( binary: &Arc< BinaryData > ) -> DebugInfo {
+    let mut info = DebugInfo::default();
+    if !binary.has_debug_info() {
+        return info;
+    }
+
+    let dwarf: Dwarf = match gimli::Dwarf::load( |id| -> Result< _, gimli::Error > { Ok( BinaryData::get_section_by_name_or_empty( binary, id.name() ) ) } ) {
+        Ok( dwarf ) => dwarf,
+        Err( error ) => {
+            warn!( "Failed to load the debug info from '{}': {}", binary.name(), error );
+            return info;
+        }
+    };
+
+    let mut headers = dwarf.units();
+    loop {
+        let header = match headers.next() {
+            Ok( Some( header ) ) => header,
+            Ok( None ) => break,
+            Err( error ) => {
+                warn!( "Failed to iterate over the compilation units of '{}': {}", binary.name(), error );
+                break;
+            }
+        };
+
+        let unit = match dwarf.unit( header ) {
+            Ok( unit ) => unit,
+            Err( error ) => {
+                warn!( "Failed to parse a compilation unit of '{}': {}", binary.name(), error );
+                continue;
+            }
+        };
+
+        let mut files = HashMap::new();
+        if let Err( error ) = load_lines( &dwarf, &unit, &mut info, &mut files ) {
+            warn!( "Failed to load the line table of a compilation unit of '{}': {}", binary.name(), error );
+        }
+
+        if let Err( error ) = load_inlines( &dwarf, &unit, &mut info, &mut files ) {
+            warn!( "Failed to load the inline frames of a compilation unit of '{}': {}", binary.name(), error );
+        }
+    }
+
+    info.lines.sort_by_key( |line| line.address );
+    info.inlines.sort_by_key( |inline| (inline.ranges[ 0 ].start, inline.depth) );
+    info
+}
+
+/// Loads the function symbols sorted by their address, with the end of every function
+/// clipped to the start of the next one. The flag is set when there were multiple
+/// symbols at the same address.
+fn load_functions( binary: &BinaryData ) -> Vec< (Range< u64 >, String, bool) > {
+    let mut symbols = Vec::new();
+    Symbols::each_from_binary_data( binary, |range, name| {
+        symbols.push( (range, name.to_owned()) );
+    });
+
+    symbols.sort_by( |a, b| a.0.start.cmp( &b.0.start ).then_with( || b.0.end.cmp( &a.0.end ) ) );
+
+    let mut functions: Vec< (Range< u64 >, String, bool) > = Vec::with_capacity( symbols.len() );
+    for (range, name) in symbols {
+        if let Some( last ) = functions.last_mut() {
+            if last.0.start == range.start {
+                last.2 |= last.1 != name;
+                continue;
+            }
+
+            if last.0.end > range.start {
+                last.0.end = range.start;
+            }
+        }
+
+        let name = demangle( &name ).unwrap_or( name );
+        functions.push( (range, name, false) );
+    }
+
+    functions
+}
+
+fn breakpad_register_name( architecture: &str, register: u16 ) -> Option< String > {
+    const AMD64_REGISTERS: &[&str] = &[
+        "$rax", "$rdx", "$rcx", "$rbx", "$rsi", "$rdi", "$rbp", "$rsp",
+        "$r8", "$r9", "$r10", "$r11", "$r12", "$r13", "$r14", "$r15"
+    ];
+
+    match (architecture, register) {
+        ("amd64", register) => AMD64_REGISTERS.get( register as usize ).map( |name| (*name).to_owned() ),
+        ("aarch64", 0..=30) => Some( format!( "x{}", register ) ),
+        ("aarch64", 31) => Some( "sp".to_owned() ),
+        ("arm", 0..=12) => Some( format!( "r{}", register ) ),
+        ("arm", 13) => Some( "sp".to_owned() ),
+        ("arm", 14) => Some( "lr".to_owned() ),
+        ("arm", 15) => Some( "pc".to_owned() ),
+        _ => None
+    }
+}
+
+/// Converts a single row of an unwind table into a list of Breakpad CFI rules.
+/// Returns `None` if the CFA's rule cannot be expressed by Breakpad.
+fn cfi_rules< R: gimli::Reader >( architecture: &str, return_address_register: gimli::Register, row: &gimli::UnwindTableRow< R > ) -> Option< Vec< (String, String) > > {
+    let register_name = |register: gimli::Register| {
+        if register == return_address_register {
+            Some( ".ra".to_owned() )
+        } else {
+            breakpad_register_name( architecture, register.0 )
+        }
+    };
+
+    let mut rules = Vec::new();
+    match *row.cfa() {
+        gimli::CfaRule::RegisterAndOffset { register, offset } => {
+            let register = breakpad_register_name( architecture, register.0 )?;
+            rules.push( (".cfa".to_owned(), format!( "{} {} +", register, offset )) );
+        },
+        gimli::CfaRule::Expression( _ ) => return None
+    }
+
+    let mut has_return_address = false;
+    for &(register, ref rule) in row.registers() {
+        let name = match register_name( register ) {
+            Some( name ) => name,
+            None => continue
+        };
+
+        let rule = match *rule {
+            gimli::RegisterRule::SameValue => breakpad_register_name( architecture, register.0 ),
+            gimli::RegisterRule::Offset( offset ) => Some( format!( ".cfa {} + ^", offset ) ),
+            gimli::RegisterRule::ValOffset( offset ) => Some( format!( ".cfa {} +", offset ) ),
+            gimli::RegisterRule::Register( other ) => breakpad_register_name( architecture, other.0 ),
+            _ => None
+        };
+
+        has_return_address |= register == return_address_register;
+        if let Some( rule ) = rule {
+            rules.push( (name, rule) );
+        }
+    }
+
+    // If it's not described then the return address is still in its register, e.g. in the link register on ARM.
+    if !has_return_address {
+        if let Some( name ) = breakpad_register_name( architecture, return_address_register.0 ) {
+            rules.push( (".ra".to_owned(), name) );
+        }
+    }
+
+    Some( rules )
+}
+
+fn write_rules( fp: &mut dyn Write, rules: &[&(String, String)] ) -> io::Result< () > {
+    for (name, rule) in rules {
+        write!( fp, " {}: {}", name, rule )?;
+    }
+    writeln!( fp )
+}
+
+fn write_cfi< E: Endianity >( binary: &Arc< BinaryData >, base: u64, fp: &mut dyn Write ) -> io::Result< () > {
+    let frame_descriptions = match FrameDescriptions::< E >::new( binary ).should_use_eh_frame_hdr( false ).should_load_eh_frame( LoadHint::Always ).load() {
+        Some( frame_descriptions ) => frame_descriptions,
+        None => return Ok(())
+    };
+
+    let architecture = binary.architecture();
+    let mut result = Ok(());
+    let mut previous_rules: Option< Vec< (String, String) > > = None;
+    frame_descriptions.each_unwind_row( |range, return_address_register, row| {
+        if result.is_err() || range.start < base {
+            return;
+        }
+
+        let is_first_row = row.start_address() == range.start;
+        if !is_first_row && previous_rules.is_none() {
+            // The initial rules were unrepresentable, so the whole entry is skipped.
+            return;
+        }
+
+        let rules = match cfi_rules( architecture, return_address_register, row ) {
+            Some( rules ) => rules,
+            None => {
+                if is_first_row {
+                    previous_rules = None;
+                }
+                return;
+            }
+        };
+
+        result = if is_first_row {
+            write!( fp, "STACK CFI INIT {:x} {:x}", range.start - base, range.end - range.start )
+                .and_then( |_| write_rules( fp, &rules.iter().collect::< Vec< _ > >() ) )
+        } else {
+            // A register whose rule was dropped is back to its original value.
+            let previous = previous_rules.as_ref().unwrap();
+            let restored: Vec< _ > = previous.iter()
+                .filter( |(name, _)| name.starts_with( |ch: char| ch != '.' ) && !rules.iter().any( |rule| rule.0 == *name ) )
+                .map( |(name, _)| (name.clone(), name.clone()) )
+                .collect();
+
+            let changed: Vec< _ > = rules.iter().filter( |rule| !previous.contains( rule ) ).chain( restored.iter() ).collect();
+            if changed.is_empty() {
+                Ok(())
+            } else {
+                write!( fp, "STACK CFI {:x}", row.start_address() - base ).and_then( |_| write_rules( fp, &changed ) )
+            }
+        };
+
+        previous_rules = Some( rules );
+    });
+
+    result
+}
+
+/// Writes out the Breakpad symbol file for a given binary.
+///
+/// The symbols and the debug info are taken from the `debug_binary` if one is given,
+/// while the CFI is taken from the binary itself.
+pub fn write_breakpad_symbols( binary: &Arc< BinaryData >, debug_binary: Option< &Arc< BinaryData > >, fp: &mut dyn Write ) -> io::Result< () > {
+    let name = Path::new( binary.name() ).file_name().map( |name| name.to_string_lossy().into_owned() ).unwrap_or_else( || binary.name().to_owned() );
+    writeln!( fp, "MODULE Linux {} {} {}", breakpad_architecture( binary.architecture() ), module_id( binary ), name )?;
+    if let Some( build_id ) = binary.build_id() {
+        let build_id: String = build_id.iter().map( |byte| format!( "{:02X}", byte ) ).collect();
+        writeln!( fp, "INFO CODE_ID {}", build_id )?;
+    }
+
+    let base = load_address( binary );
+    let symbol_binary = debug_binary.filter( |debug_binary| !debug_binary.symbol_tables().is_empty() ).unwrap_or( binary );
+    let functions = load_functions( symbol_binary );
+    let info = load_debug_info( debug_binary.unwrap_or( binary ) );
+
+    for (index, path) in info.files.iter().enumerate() {
+        writeln!( fp, "FILE {} {}", index, path )?;
+    }
+
+    for (index, name) in info.origins.iter().enumerate() {
+        writeln!( fp, "INLINE_ORIGIN {} {}", index, name )?;
+    }
+
+    let mut line_index = 0;
+    let mut inline_index = 0;
+    for (range, name, is_multiple) in functions {
+        if range.start < base {
+            continue;
+        }
+
+        while line_index < info.lines.len() && info.lines[ line_index ].address + info.lines[ line_index ].size <= range.start {
+            line_index += 1;
+        }
+
+        let lines = info.lines[ line_index.. ].iter()
+            .take_while( |line| line.address < range.end )
+            .map( |line| {
+                let start = std::cmp::max( line.address, range.start );
+                let end = std::cmp::min( line.address + line.size, range.end );
+                (start..end, line)
+            });
+
+        while inline_index < info.inlines.len() && info.inlines[ inline_index ].ranges[ 0 ].start < range.start {
+            inline_index += 1;
+        }
+
+        let inlines = info.inlines[ inline_index.. ].iter()
+            .take_while( |inline| inline.ranges[ 0 ].start < range.end );
+
+        let mut lines = lines.peekable();
+        let m = if is_multiple { "m " } else { "" };
+        if lines.peek().is_none() {
+            writeln!( fp, "PUBLIC {}{:x} 0 {}", m, range.start - base, name )?;
+            continue;
+        }
+
+        writeln!( fp, "FUNC {}{:x} {:x} 0 {}", m, range.start - base, range.end - range.start, name )?;
+        for inline in inlines {
+            write!( fp, "INLINE {} {} {} {}", inline.depth, inline.call_line, inline.call_file, inline.origin )?;
+            for range in &inline.ranges {
+                write!( fp, " {:x} {:x}", range.start - base, range.end - range.start )?;
+            }
+            writeln!( fp )?;
+        }
+
+        for (range, line) in lines {
+            writeln!( fp, "{:x} {:x} {} {}", range.start - base, range.end - range.start, line.line, line.file )?;
+        }
+    }
+
+    match binary.endianness() {
+        Endianness::LittleEndian => write_cfi::< gimli::LittleEndian >( binary, base, fp ),
+        Endianness::BigEndian => write_cfi::< gimli::BigEndian >( binary, base, fp )
+    }
+}
+
+#[test]
+fn test_write_breakpad_symbols() {
+    use std::path::PathBuf;
+    let _ = ::env_logger::try_init();
+
+    let path = PathBuf::from( env!( "CARGO_MANIFEST_DIR" ) )
+        .join( ".." )
+        .join( "test-data" )
+        .join( "bin" )
+        .join( "amd64-inline_functions" );
+
+    let binary = Arc::new( BinaryData::load_from_fs( path ).unwrap() );
+    let mut output = Vec::new();
+    write_breakpad_symbols( &binary, None, &mut output ).unwrap();
+    let output = String::from_utf8( output ).unwrap();
+
+    let lines: Vec< _ > = output.lines().collect();
+    assert_eq!( lines[ 0 ], "MODULE Linux x86_64 8BB3A3721620B2F922C962197601EADD0 amd64-inline_functions" );
+    assert_eq!( lines[ 1 ], "INFO CODE_ID 72A3B38B2016F9B222C962197601EADD49F01A35" );
+    assert!( lines.contains( &"FILE 2 /data/dev/nperf/test-data/src/inline_functions.c" ) );
+    assert!( lines.contains( &"INLINE_ORIGIN 0 inline_function_1st" ) );
+
+    let main = lines.iter().position( |&line| line == "FUNC 410 19 0 main" ).unwrap();
+    assert_eq!( &lines[ main + 1..main + 5 ], &[
+        "INLINE 0 19 2 0 420 9",
+        "INLINE 1 15 2 1 420 9",
+        "410 10 18 2",
+        "420 9 11 2"
+    ]);
+
+    assert!( lines.contains( &"STACK CFI INIT 410 19 .cfa: $rsp 8 + .ra: .cfa -8 + ^" ) );
+    assert!( lines.contains( &"STACK CFI 414 .cfa: $rsp 16 +" ) );
+    assert!( lines.contains( &"STACK CFI INIT 430 2b .cfa: $rsp 8 +" ) );
+}
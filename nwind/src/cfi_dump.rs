@@ -0,0 +1,217 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::Arc;
+
+use gimli::{self, CfaRule, ReaderOffset, Register, RegisterRule};
+
+use crate::arch::{self, Architecture, Endianity};
+use crate::arm_extab;
+use crate::binary::BinaryData;
+use crate::frame_descriptions::{FrameDescriptions, LoadHint};
+use crate::types::Endianness;
+
+pub(crate) fn register_name_str( architecture: &str, register: u16 ) -> Option< &'static str > {
+    match architecture {
+        "amd64" => arch::amd64::Arch::register_name_str( register ),
+        "aarch64" => arch::aarch64::Arch::register_name_str( register ),
+        "arm" => arch::arm::Arch::register_name_str( register ),
+        "mips64" => arch::mips64::Arch::register_name_str( register ),
+        _ => None
+    }
+}
+
+pub(crate) fn register_name( architecture: &str, register: Register ) -> String {
+    match register_name_str( architecture, register.0 ) {
+        Some( name ) => name.to_owned(),
+        None => format!( "#{}", register.0 )
+    }
+}
+
+pub(crate) fn with_offset( base: &str, offset: i64 ) -> String {
+    if offset < 0 {
+        format!( "{} - {}", base, -(offset as i128) )
+    } else {
+        format!( "{} + {}", base, offset )
+    }
+}
+
+fn cfa_rule< R: gimli::Reader >( architecture: &str, rule: &CfaRule< R > ) -> String {
+    match *rule {
+        CfaRule::RegisterAndOffset { register, offset } => with_offset( &register_name( architecture, register ), offset ),
+        CfaRule::Expression( ref expression ) => format!( "<DWARF expression, {} bytes>", expression.0.len().into_u64() )
+    }
+}
+
+pub(crate) fn register_rule< R: gimli::Reader >( architecture: &str, rule: &RegisterRule< R > ) -> String {
+    match *rule {
+        RegisterRule::Undefined => "undefined".to_owned(),
+        RegisterRule::SameValue => "same value".to_owned(),
+        RegisterRule::Offset( offset ) => format!( "[{}]", with_offset( "CFA", offset ) ),
+        RegisterRule::ValOffset( offset ) => with_offset( "CFA", offset ),
+        RegisterRule::Register( register ) => register_name( architecture, register ),
+        RegisterRule::Expression( ref expression ) => format!( "[<DWARF expression, {} bytes>]", expression.0.len().into_u64() ),
+        RegisterRule::ValExpression( ref expression ) => format!( "<DWARF expression, {} bytes>", expression.0.len().into_u64() ),
+        RegisterRule::Architectural => "architectural".to_owned()
+    }
+}
+
+fn write_cfi_for_address_impl< E: Endianity >( binary: &Arc< BinaryData >, address: u64, fp: &mut dyn Write ) -> io::Result< bool > {
+    let frame_descriptions = match FrameDescriptions::< E >::new( binary ).load() {
+        Some( frame_descriptions ) => frame_descriptions,
+        None => return Ok( false )
+    };
+
+    let architecture = binary.architecture();
+    let result = frame_descriptions.lookup_row( address, |source, fde, row| -> io::Result< () > {
+        let cie = fde.cie();
+        writeln!( fp, "Section: {}", source.name() )?;
+        writeln!( fp, "FDE at offset 0x{:X}:", fde.offset() )?;
+        writeln!( fp, "  Range: 0x{:016X}-0x{:016X} ({} bytes)", fde.initial_address(), fde.initial_address() + fde.len(), fde.len() )?;
+        writeln!( fp, "  Signal trampoline: {}", if fde.is_signal_trampoline() { "yes" } else { "no" } )?;
+        writeln!( fp, "CIE at offset 0x{:X}:", cie.offset() )?;
+        writeln!( fp, "  Version: {}", cie.version() )?;
+        writeln!( fp, "  Code alignment factor: {}", cie.code_alignment_factor() )?;
+        writeln!( fp, "  Data alignment factor: {}", cie.data_alignment_factor() )?;
+        writeln!( fp, "  Return address register: {}", register_name( architecture, cie.return_address_register() ) )?;
+        writeln!( fp, "  Has LSDA: {}", if cie.has_lsda() { "yes" } else { "no" } )?;
+        writeln!( fp, "Row: 0x{:016X}-0x{:016X}", row.start_address(), row.end_address() )?;
+        writeln!( fp, "  CFA = {}", cfa_rule( architecture, row.cfa() ) )?;
+
+        let mut is_return_address_described = false;
+        for &(register, ref rule) in row.registers() {
+            is_return_address_described |= register == cie.return_address_register();
+            writeln!( fp, "  {} = {}", register_name( architecture, register ), register_rule( architecture, rule ) )?;
+        }
+
+        if !is_return_address_described {
+            writeln!( fp, "  {} = same value (not described)", register_name( architecture, cie.return_address_register() ) )?;
+        }
+
+        Ok(())
+    });
+
+    match result {
+        Some( result ) => result.map( |_| true ),
+        None => Ok( false )
+    }
+}
+
+/// Writes out a human readable description of the CFI which would be used to unwind
+/// through a given address of a binary: the FDE and CIE which describe it, which section
+/// they were found in, and the CFA and register rules for the given address.
+///
+/// Returns `false` if the address isn't covered by any FDE.
+pub fn write_cfi_for_address( binary: &Arc< BinaryData >, address: u64, fp: &mut dyn Write ) -> io::Result< bool > {
+    match binary.endianness() {
+        Endianness::LittleEndian => write_cfi_for_address_impl::< gimli::LittleEndian >( binary, address, fp ),
+        Endianness::BigEndian => write_cfi_for_address_impl::< gimli::BigEndian >( binary, address, fp )
+    }
+}
+
+fn write_unwind_tables_impl< E: Endianity >( binary: &Arc< BinaryData >, fp: &mut dyn Write ) -> io::Result< () > {
+    let frame_descriptions = FrameDescriptions::< E >::new( binary )
+        .should_use_eh_frame_hdr( false )
+        .should_load_eh_frame( LoadHint::Always )
+        .load();
+
+    let frame_descriptions = match frame_descriptions {
+        Some( frame_descriptions ) => frame_descriptions,
+        None => return Ok(())
+    };
+
+    let architecture = binary.architecture();
+    let mut current_source = None;
+    let mut current_fde = None;
+    let mut result = Ok(());
+    frame_descriptions.each_fde_row( |source, fde, row| {
+        if result.is_err() {
+            return;
+        }
+
+        result = (|| -> io::Result< () > {
+            if current_source != Some( source ) {
+                if current_source.is_some() {
+                    writeln!( fp )?;
+                }
+
+                writeln!( fp, "Contents of the {} section:", source.name() )?;
+                current_source = Some( source );
+                current_fde = None;
+            }
+
+            if current_fde != Some( fde.offset() ) {
+                writeln!( fp )?;
+                writeln!(
+                    fp,
+                    "FDE at offset 0x{:X}, CIE at offset 0x{:X}, pc=0x{:016X}..0x{:016X}{}",
+                    fde.offset(),
+                    fde.cie().offset(),
+                    fde.initial_address(),
+                    fde.initial_address() + fde.len(),
+                    if fde.is_signal_trampoline() { " (signal trampoline)" } else { "" }
+                )?;
+                current_fde = Some( fde.offset() );
+            }
+
+            write!( fp, "  0x{:016X}: CFA = {}", row.start_address(), cfa_rule( architecture, row.cfa() ) )?;
+            for &(register, ref rule) in row.registers() {
+                write!( fp, ", {} = {}", register_name( architecture, register ), register_rule( architecture, rule ) )?;
+            }
+
+            writeln!( fp )
+        })();
+    });
+
+    result?;
+    if current_source.is_some() {
+        writeln!( fp )?;
+    }
+
+    Ok(())
+}
+
+fn section_address( binary: &BinaryData, range: &Range< usize > ) -> u32 {
+    binary.file_offset_to_address( range.start as u64 ).unwrap_or( range.start as u64 ) as u32
+}
+
+fn write_arm_exidx( binary: &BinaryData, fp: &mut dyn Write ) -> io::Result< () > {
+    let exidx_range = match binary.arm_exidx_range() {
+        Some( exidx_range ) => exidx_range,
+        None => return Ok(())
+    };
+
+    let exidx_base = section_address( binary, &exidx_range );
+    let (extab, extab_base) = match binary.arm_extab_range() {
+        Some( extab_range ) => (&binary.as_bytes()[ extab_range.clone() ], section_address( binary, &extab_range )),
+        None => (&b""[..], 0)
+    };
+
+    writeln!( fp, "Contents of the .ARM.exidx section:" )?;
+    writeln!( fp )?;
+    arm_extab::write_exidx( &binary.as_bytes()[ exidx_range ], extab, exidx_base, extab_base, fp )?;
+    writeln!( fp )
+}
+
+/// Writes out the contents of every unwind table of a binary (`.debug_frame`, `.eh_frame`
+/// and `.ARM.exidx`) in a human readable form, similar to what `readelf --debug-dump=frames-interp` prints.
+///
+/// This uses the same parsers which are used when unwinding, so it shows how nwind itself sees those tables.
+pub fn write_unwind_tables( binary: &Arc< BinaryData >, fp: &mut dyn Write ) -> io::Result< () > {
+    match binary.endianness() {
+        Endianness::LittleEndian => write_unwind_tables_impl::< gimli::LittleEndian >( binary, fp )?,
+        Endianness::BigEndian => write_unwind_tables_impl::< gimli::BigEndian >( binary, fp )?
+    }
+
+    write_arm_exidx( binary, fp )
+}
+
+#[test]
+fn test_rule_formatting() {
+    type R = gimli::EndianSlice< 'static, gimli::LittleEndian >;
+
+    assert_eq!( cfa_rule( "amd64", &CfaRule::RegisterAndOffset::< R > { register: Register( 7 ), offset: 16 } ), "RSP + 16" );
+    assert_eq!( register_rule( "amd64", &RegisterRule::< R >::Offset( -8 ) ), "[CFA - 8]" );
+    assert_eq!( register_rule( "amd64", &RegisterRule::< R >::ValOffset( 0 ) ), "CFA + 0" );
+    assert_eq!( register_rule( "amd64", &RegisterRule::< R >::Register( Register( 6 ) ) ), "RBP" );
+    assert_eq!( register_rule( "unknown", &RegisterRule::< R >::Register( Register( 6 ) ) ), "#6" );
+}
@@ -0,0 +1,293 @@
+//! Checks ahead of time whether the DWARF unwind tables of a binary only use what the unwinder
+//! supports, by walking every FDE and evaluating all of its rows.
+//!
+//! This doesn't look at `.ARM.exidx`; those entries are either understood by the unwinder or
+//! skipped when they're loaded, which `--strict` already reports.
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use gimli::{self, CfaRule, Operation, ReaderOffset, Register, RegisterRule};
+
+use crate::arch::{self, Architecture, Endianity};
+use crate::binary::BinaryData;
+use crate::cfi_dump::{register_name, register_name_str, register_rule, with_offset};
+use crate::frame_descriptions::{FrameDescriptions, LoadHint};
+use crate::types::{Bitness, Endianness};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UnwindTableIssueKind {
+    /// The rule is valid, but the unwinder can't follow it.
+    Unsupported,
+    /// The rule can be parsed, but doesn't make sense for the function it describes.
+    Inconsistent,
+    /// The rows of the FDE can't be evaluated at all.
+    Malformed
+}
+
+impl UnwindTableIssueKind {
+    pub fn name( self ) -> &'static str {
+        match self {
+            UnwindTableIssueKind::Unsupported => "unsupported",
+            UnwindTableIssueKind::Inconsistent => "inconsistent",
+            UnwindTableIssueKind::Malformed => "malformed"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnwindTableIssue {
+    pub section: &'static str,
+    pub fde_offset: u64,
+    /// The first address for which the issue was found; the same issue isn't reported
+    /// again for the later rows of the same FDE.
+    pub address: u64,
+    pub kind: UnwindTableIssueKind,
+    pub message: String
+}
+
+impl fmt::Display for UnwindTableIssue {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        write!(
+            fmt,
+            "0x{:016X}: {} (FDE at offset 0x{:X} in {}): {}",
+            self.address,
+            self.kind.name(),
+            self.fde_offset,
+            self.section,
+            self.message
+        )
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct UnwindTableSummary {
+    pub fde_count: u64,
+    pub row_count: u64
+}
+
+type Issue = (UnwindTableIssueKind, String);
+
+fn stack_pointer_register( architecture: &str ) -> Option< u16 > {
+    match architecture {
+        "amd64" => Some( arch::amd64::Arch::STACK_POINTER_REG ),
+        "aarch64" => Some( arch::aarch64::Arch::STACK_POINTER_REG ),
+        "arm" => Some( arch::arm::Arch::STACK_POINTER_REG ),
+        "mips64" => Some( arch::mips64::Arch::STACK_POINTER_REG ),
+        _ => None
+    }
+}
+
+fn check_expression< R: gimli::Reader >( architecture: &str, address_size: u8, expression: &gimli::Expression< R > ) -> Option< Issue > {
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 0,
+        address_size
+    };
+
+    let mut operations = expression.clone().operations( encoding );
+    loop {
+        let operation = match operations.next() {
+            Ok( Some( operation ) ) => operation,
+            Ok( None ) => return None,
+            Err( error ) => return Some( (UnwindTableIssueKind::Malformed, format!( "cannot parse a DWARF expression: {}", error )) )
+        };
+
+        // These are the only operations which can be evaluated with nothing but the registers and the memory.
+        let is_supported = match operation {
+            Operation::Deref { base_type, size, space } => base_type.0.into_u64() == 0 && size == address_size && !space,
+            Operation::RegisterOffset { register, base_type, .. } => {
+                if register_name_str( architecture, register.0 ).is_none() {
+                    let message = format!( "DWARF expression uses an unknown register {}", register_name( architecture, register ) );
+                    return Some( (UnwindTableIssueKind::Inconsistent, message) );
+                }
+
+                base_type.0.into_u64() == 0
+            },
+            Operation::Drop |
+            Operation::Pick { .. } |
+            Operation::Swap |
+            Operation::Rot |
+            Operation::Abs |
+            Operation::And |
+            Operation::Div |
+            Operation::Minus |
+            Operation::Mod |
+            Operation::Mul |
+            Operation::Neg |
+            Operation::Not |
+            Operation::Or |
+            Operation::Plus |
+            Operation::PlusConstant { .. } |
+            Operation::Shl |
+            Operation::Shr |
+            Operation::Shra |
+            Operation::Xor |
+            Operation::Bra { .. } |
+            Operation::Eq |
+            Operation::Ge |
+            Operation::Gt |
+            Operation::Le |
+            Operation::Lt |
+            Operation::Ne |
+            Operation::Skip { .. } |
+            Operation::UnsignedConstant { .. } |
+            Operation::SignedConstant { .. } |
+            Operation::Nop => true,
+            _ => false
+        };
+
+        if !is_supported {
+            return Some( (UnwindTableIssueKind::Unsupported, format!( "unsupported DWARF operation: {:?}", operation )) );
+        }
+    }
+}
+
+fn check_cfa_rule< R: gimli::Reader >( architecture: &str, address_size: u8, rule: &CfaRule< R > ) -> Option< Issue > {
+    match *rule {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            if register_name_str( architecture, register.0 ).is_none() {
+                let message = format!( "CFA is based on an unknown register {}", register_name( architecture, register ) );
+                Some( (UnwindTableIssueKind::Inconsistent, message) )
+            } else if Some( register.0 ) == stack_pointer_register( architecture ) && offset < 0 {
+                let message = format!( "CFA is below the stack pointer: {}", with_offset( &register_name( architecture, register ), offset ) );
+                Some( (UnwindTableIssueKind::Inconsistent, message) )
+            } else {
+                None
+            }
+        },
+        CfaRule::Expression( ref expression ) => check_expression( architecture, address_size, expression )
+    }
+}
+
+fn check_register_rule< R: gimli::Reader >( architecture: &str, address_size: u8, register: Register, rule: &RegisterRule< R > ) -> Option< Issue > {
+    match *rule {
+        RegisterRule::Undefined | RegisterRule::Offset( _ ) => None,
+        RegisterRule::Expression( ref expression ) => check_expression( architecture, address_size, expression ),
+        _ => {
+            let message = format!( "unsupported rule for {}: {}", register_name( architecture, register ), register_rule( architecture, rule ) );
+            Some( (UnwindTableIssueKind::Unsupported, message) )
+        }
+    }
+}
+
+fn verify_unwind_tables_impl< E: Endianity >( binary: &Arc< BinaryData >, callback: &mut dyn FnMut( UnwindTableIssue ) ) -> Option< UnwindTableSummary > {
+    let architecture = binary.architecture();
+    stack_pointer_register( architecture )?;
+
+    let frame_descriptions = FrameDescriptions::< E >::new( binary )
+        .should_use_eh_frame_hdr( false )
+        .should_load_eh_frame( LoadHint::Always )
+        .load()?;
+
+    let address_size = match binary.bitness() {
+        Bitness::B32 => 4,
+        Bitness::B64 => 8
+    };
+
+    let mut summary = UnwindTableSummary::default();
+    let current_fde = Cell::new( None );
+    let mut seen = HashSet::new();
+    let mut failures = Vec::new();
+    frame_descriptions.try_each_fde_row( |source, fde, row| {
+        let fde_offset = fde.offset().into_u64();
+        if current_fde.get() != Some( (source.name(), fde_offset) ) {
+            current_fde.set( Some( (source.name(), fde_offset) ) );
+            summary.fde_count += 1;
+            seen.clear();
+        }
+
+        summary.row_count += 1;
+
+        let mut issues = Vec::new();
+        let fde_end = fde.initial_address() + fde.len();
+        if row.start_address() < fde.initial_address() || row.end_address() > fde_end {
+            let message = format!(
+                "row 0x{:016X}-0x{:016X} is outside of its FDE's range 0x{:016X}-0x{:016X}",
+                row.start_address(),
+                row.end_address(),
+                fde.initial_address(),
+                fde_end
+            );
+            issues.push( (UnwindTableIssueKind::Inconsistent, message) );
+        }
+
+        issues.extend( check_cfa_rule( architecture, address_size, row.cfa() ) );
+        for &(register, ref rule) in row.registers() {
+            issues.extend( check_register_rule( architecture, address_size, register, rule ) );
+        }
+
+        for (kind, message) in issues {
+            if !seen.insert( message.clone() ) {
+                continue;
+            }
+
+            callback( UnwindTableIssue {
+                section: source.name(),
+                fde_offset,
+                address: row.start_address(),
+                kind,
+                message
+            });
+        }
+    }, |source, fde, error| {
+        let fde_offset = fde.offset().into_u64();
+        let is_already_counted = current_fde.get() == Some( (source.name(), fde_offset) );
+        failures.push( (is_already_counted, UnwindTableIssue {
+            section: source.name(),
+            fde_offset,
+            address: fde.initial_address(),
+            kind: UnwindTableIssueKind::Malformed,
+            message: format!( "cannot evaluate the rows: {}", error )
+        }));
+    });
+
+    for (is_already_counted, issue) in failures {
+        if !is_already_counted {
+            summary.fde_count += 1;
+        }
+
+        callback( issue );
+    }
+
+    Some( summary )
+}
+
+/// Walks every FDE in the `.debug_frame` and `.eh_frame` sections of a binary, evaluates all
+/// of their rows and calls `callback` for every rule which the unwinder wouldn't be able to follow.
+///
+/// Returns `None` if the binary's architecture isn't supported, or if its unwind tables
+/// couldn't be loaded in the strict mode.
+pub fn verify_unwind_tables( binary: &Arc< BinaryData >, callback: &mut dyn FnMut( UnwindTableIssue ) ) -> Option< UnwindTableSummary > {
+    match binary.endianness() {
+        Endianness::LittleEndian => verify_unwind_tables_impl::< gimli::LittleEndian >( binary, callback ),
+        Endianness::BigEndian => verify_unwind_tables_impl::< gimli::BigEndian >( binary, callback )
+    }
+}
+
+#[test]
+fn test_rule_checks() {
+    type R = gimli::EndianSlice< 'static, gimli::LittleEndian >;
+    let expression = |bytes: &'static [u8]| gimli::Expression( R::new( bytes, gimli::LittleEndian ) );
+    let kind = |issue: Option< Issue >| issue.map( |(kind, _)| kind );
+
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::RegisterAndOffset::< R > { register: Register( 7 ), offset: 16 } ) ), None );
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::RegisterAndOffset::< R > { register: Register( 7 ), offset: -8 } ) ), Some( UnwindTableIssueKind::Inconsistent ) );
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::RegisterAndOffset::< R > { register: Register( 99 ), offset: 8 } ) ), Some( UnwindTableIssueKind::Inconsistent ) );
+
+    // DW_OP_breg7 (RSP) 8; DW_OP_deref
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::Expression( expression( &[0x77, 0x08, 0x06] ) ) ) ), None );
+    // DW_OP_breg7 (RSP) 8; DW_OP_stack_value
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::Expression( expression( &[0x77, 0x08, 0x9f] ) ) ) ), Some( UnwindTableIssueKind::Unsupported ) );
+    // DW_OP_breg7 (RSP) with a missing operand
+    assert_eq!( kind( check_cfa_rule( "amd64", 8, &CfaRule::Expression( expression( &[0x77] ) ) ) ), Some( UnwindTableIssueKind::Malformed ) );
+
+    assert_eq!( kind( check_register_rule( "amd64", 8, Register( 6 ), &RegisterRule::< R >::Offset( -16 ) ) ), None );
+    assert_eq!( kind( check_register_rule( "amd64", 8, Register( 6 ), &RegisterRule::< R >::Undefined ) ), None );
+    assert_eq!(
+        check_register_rule( "amd64", 8, Register( 6 ), &RegisterRule::< R >::SameValue ),
+        Some( (UnwindTableIssueKind::Unsupported, "unsupported rule for RBP: same value".to_owned()) )
+    );
+}
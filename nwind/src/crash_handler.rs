@@ -0,0 +1,421 @@
+//! A crash handler which prints symbolized backtraces when the process receives a fatal signal.
+//!
+//! Everything which needs to allocate (loading the binaries, their unwind tables
+//! and their symbol tables) is done when the handler is installed; the signal handler
+//! itself only unwinds the stack from the `ucontext_t` it's given and writes out
+//! the frames through a fixed-size buffer, so it's safe to run in a signal context.
+//!
+//! Since C++ demangling requires allocations only Rust symbols are demangled,
+//! and the file names and line numbers from the debug info are not printed.
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::address_space::{BinaryRegion, MemoryReader};
+use crate::arch::{self, Architecture, Registers};
+use crate::frame_descriptions::DynamicFdeRegistry;
+use crate::kernel_regs::load_ucontext;
+use crate::local_unwinding::{LocalAddressSpace, LocalAddressSpaceOptions};
+use crate::range_map::RangeMap;
+use crate::unwind_context::UnwindContext;
+
+type NativeRegs = <arch::native::Arch as Architecture>::Regs;
+type NativeRegTy = <arch::native::Arch as Architecture>::RegTy;
+
+const ALTERNATE_STACK_SIZE: usize = 64 * 1024;
+
+static HANDLER: AtomicPtr< CrashHandler > = AtomicPtr::new( ptr::null_mut() );
+static IS_HANDLING: AtomicBool = AtomicBool::new( false );
+
+#[derive(Clone, Debug)]
+pub struct CrashHandlerOptions {
+    signals: Vec< libc::c_int >,
+    max_frames: usize
+}
+
+impl CrashHandlerOptions {
+    pub fn new() -> Self {
+        CrashHandlerOptions {
+            signals: vec![ libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE, libc::SIGABRT ],
+            max_frames: 256
+        }
+    }
+
+    /// Sets the signals on which the backtrace will be printed.
+    pub fn signals( mut self, signals: &[libc::c_int] ) -> Self {
+        self.signals = signals.to_vec();
+        self
+    }
+
+    pub fn max_frames( mut self, value: usize ) -> Self {
+        self.max_frames = value;
+        self
+    }
+}
+
+struct CrashHandler {
+    address_space: LocalAddressSpace,
+    ctx: UnwindContext< arch::native::Arch >,
+    frames: Box< [usize] >,
+    previous_actions: Vec< (libc::c_int, libc::sigaction) >,
+    readable_regions: RangeMap< () >,
+    _alternate_stack: Vec< u8 >
+}
+
+/// Installs the crash handler with the default options.
+pub fn install_crash_handler() -> Result< (), io::Error > {
+    install_crash_handler_with_opts( CrashHandlerOptions::new() )
+}
+
+/// Installs the crash handler.
+///
+/// The binaries are only loaded once, so this should be called after every library
+/// which might crash was already loaded; calling it again will reload them.
+///
+/// An alternate signal stack is set up only for the calling thread, so a stack overflow
+/// on any other thread won't be caught unless it has one set up by itself.
+///
+/// If `process_vm_readv` is not available (e.g. it's blocked by seccomp) then only
+/// the memory which was readable when the handler was installed will be accessed,
+/// so the stacks of threads started afterwards won't be unwound.
+pub fn install_crash_handler_with_opts( opts: CrashHandlerOptions ) -> Result< (), io::Error > {
+    let address_space = LocalAddressSpace::new_with_opts( LocalAddressSpaceOptions::new().should_load_symbols( true ) )?;
+    let mut ctx = UnwindContext::new();
+    ctx.disable_cache();
+
+    let readable_regions = load_readable_regions()?;
+
+    let mut alternate_stack = vec![ 0; ALTERNATE_STACK_SIZE ];
+    let stack = libc::stack_t {
+        ss_sp: alternate_stack.as_mut_ptr() as *mut libc::c_void,
+        ss_flags: 0,
+        ss_size: alternate_stack.len()
+    };
+
+    if unsafe { libc::sigaltstack( &stack, ptr::null_mut() ) } < 0 {
+        return Err( io::Error::last_os_error() );
+    }
+
+    // If we're reinstalling then we want to chain to whatever was there before us.
+    let old_handler = HANDLER.load( Ordering::Acquire );
+    let old_previous_actions = if old_handler.is_null() {
+        Vec::new()
+    } else {
+        unsafe { (*old_handler).previous_actions.clone() }
+    };
+
+    let mut handler = Box::new( CrashHandler {
+        address_space,
+        ctx,
+        frames: vec![ 0; opts.max_frames ].into_boxed_slice(),
+        previous_actions: Vec::with_capacity( opts.signals.len() ),
+        readable_regions,
+        _alternate_stack: alternate_stack
+    });
+
+    for &signal in &opts.signals {
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        let on_signal: extern "C" fn( libc::c_int, *mut libc::siginfo_t, *mut libc::c_void ) = on_signal;
+        action.sa_sigaction = on_signal as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        unsafe {
+            libc::sigemptyset( &mut action.sa_mask );
+        }
+
+        let mut previous_action: libc::sigaction = unsafe { mem::zeroed() };
+        if unsafe { libc::sigaction( signal, &action, &mut previous_action ) } < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        let previous_action = old_previous_actions.iter()
+            .find( |&&(old_signal, _)| old_signal == signal )
+            .map( |&(_, action)| action )
+            .unwrap_or( previous_action );
+
+        handler.previous_actions.push( (signal, previous_action) );
+    }
+
+    // The old handler is leaked on purpose since another thread might be still using it.
+    HANDLER.store( Box::into_raw( handler ), Ordering::Release );
+    Ok(())
+}
+
+fn load_readable_regions() -> Result< RangeMap< () >, io::Error > {
+    let maps = fs::read( "/proc/self/maps" )?;
+    let maps = String::from_utf8_lossy( &maps );
+    let regions = proc_maps::parse( &maps ).into_iter()
+        // Parts of the vvar mapping can fault even though it's marked as readable.
+        .filter( |region| region.is_read && !region.name.starts_with( "[vvar" ) )
+        .map( |region| (region.start..region.end, ()) )
+        .collect();
+
+    Ok( RangeMap::from_vec( regions ) )
+}
+
+extern "C" fn on_signal( signal: libc::c_int, _: *mut libc::siginfo_t, ucontext: *mut libc::c_void ) {
+    let handler = HANDLER.load( Ordering::Acquire );
+    if handler.is_null() {
+        return;
+    }
+
+    let handler = unsafe { &mut *handler };
+    if !IS_HANDLING.swap( true, Ordering::AcqRel ) {
+        unsafe {
+            handler.print_backtrace( signal, &*(ucontext as *const libc::ucontext_t) );
+        }
+    }
+
+    // Restore the previous handler and let it run once we return.
+    unsafe {
+        match handler.previous_actions.iter().find( |&&(previous_signal, _)| previous_signal == signal ) {
+            Some( &(_, ref action) ) => {
+                libc::sigaction( signal, action, ptr::null_mut() );
+            },
+            None => {
+                libc::signal( signal, libc::SIG_DFL );
+            }
+        }
+
+        libc::raise( signal );
+    }
+}
+
+struct CrashMemory< 'a > {
+    regions: &'a RangeMap< BinaryRegion< arch::native::Arch > >,
+    readable_regions: &'a RangeMap< () >,
+    dynamic_fde_registry: &'a DynamicFdeRegistry< gimli::NativeEndian >
+}
+
+impl< 'a > MemoryReader< arch::native::Arch > for CrashMemory< 'a > {
+    fn get_region_at_address( &self, address: u64 ) -> Option< &BinaryRegion< arch::native::Arch > > {
+        self.regions.get_value( address )
+    }
+
+    fn get_pointer_at_address( &self, address: NativeRegTy ) -> Option< NativeRegTy > {
+        // The stack might be corrupted, so we can't just dereference the pointer.
+        let mut value: usize = 0;
+        let local = libc::iovec {
+            iov_base: &mut value as *mut usize as *mut libc::c_void,
+            iov_len: mem::size_of::< usize >()
+        };
+
+        let remote = libc::iovec {
+            iov_base: address as usize as *mut libc::c_void,
+            iov_len: mem::size_of::< usize >()
+        };
+
+        let count = unsafe { libc::process_vm_readv( libc::getpid(), &local, 1, &remote, 1, 0 ) };
+        if count == mem::size_of::< usize >() as isize {
+            return Some( value as _ );
+        }
+
+        if io::Error::last_os_error().raw_os_error() == Some( libc::EFAULT ) {
+            return None;
+        }
+
+        // The syscall is not available (e.g. it's blocked by seccomp), so we can only
+        // dereference the pointer if it's within memory which we know is readable.
+        let end = (address as u64).checked_add( mem::size_of::< usize >() as u64 )?;
+        let (range, _) = self.readable_regions.get( address as u64 )?;
+        if end > range.end {
+            return None;
+        }
+
+        let value = unsafe {
+            std::ptr::read_unaligned( address as usize as *const usize )
+        };
+
+        Some( value as _ )
+    }
+
+    fn is_stack_address( &self, _: u64 ) -> bool {
+        false
+    }
+
+    fn dynamic_fde_registry( &self ) -> Option< &DynamicFdeRegistry< gimli::NativeEndian > > {
+        Some( self.dynamic_fde_registry )
+    }
+}
+
+fn signal_name( signal: libc::c_int ) -> &'static str {
+    match signal {
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGILL => "SIGILL",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGSYS => "SIGSYS",
+        _ => "unknown signal"
+    }
+}
+
+/// Writes into the stderr through a fixed-size buffer, without allocating.
+struct Output {
+    buffer: [u8; 512],
+    length: usize
+}
+
+impl Output {
+    fn new() -> Self {
+        Output {
+            buffer: [0; 512],
+            length: 0
+        }
+    }
+
+    fn flush( &mut self ) {
+        let mut offset = 0;
+        while offset < self.length {
+            let count = unsafe {
+                libc::write( libc::STDERR_FILENO, self.buffer[ offset.. ].as_ptr() as *const libc::c_void, self.length - offset )
+            };
+
+            if count <= 0 {
+                break;
+            }
+
+            offset += count as usize;
+        }
+
+        self.length = 0;
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str( &mut self, mut string: &str ) -> fmt::Result {
+        while !string.is_empty() {
+            if self.length == self.buffer.len() {
+                self.flush();
+            }
+
+            let count = std::cmp::min( self.buffer.len() - self.length, string.len() );
+            self.buffer[ self.length..self.length + count ].copy_from_slice( &string.as_bytes()[ ..count ] );
+            self.length += count;
+            string = unsafe { string.get_unchecked( count.. ) };
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rustc-demangle")]
+fn write_symbol( output: &mut Output, symbol: &str ) {
+    match rustc_demangle::try_demangle( symbol ) {
+        Ok( symbol ) => { let _ = write!( output, "{:#}", symbol ); },
+        Err( _ ) => { let _ = output.write_str( symbol ); }
+    }
+}
+
+#[cfg(not(feature = "rustc-demangle"))]
+fn write_symbol( output: &mut Output, symbol: &str ) {
+    let _ = output.write_str( symbol );
+}
+
+impl CrashHandler {
+    unsafe fn print_backtrace( &mut self, signal: libc::c_int, ucontext: &libc::ucontext_t ) {
+        let mut output = Output::new();
+        let _ = writeln!( output, "Received {} (signal {}); backtrace:", signal_name( signal ), signal );
+        output.flush();
+
+        let memory = CrashMemory {
+            regions: &self.address_space.regions,
+            readable_regions: &self.readable_regions,
+            dynamic_fde_registry: &self.address_space.dynamic_fde_registry
+        };
+
+        let mut count = 0;
+        let mut ctx = self.ctx.start( &memory, |regs: &mut NativeRegs| {
+            regs.clear();
+            load_ucontext( ucontext, |register, value| regs.append( register, value as _ ) );
+        });
+
+        while count < self.frames.len() {
+            let address: u64 = ctx.current_address().into();
+            self.frames[ count ] = address as usize;
+            count += 1;
+
+            if !ctx.unwind( &memory ) {
+                break;
+            }
+        }
+
+        for (nth_frame, &address) in self.frames[ ..count ].iter().enumerate() {
+            // Every frame except the first one points to the instruction after the call.
+            let lookup_address = if nth_frame == 0 { address } else { address.wrapping_sub( 1 ) } as u64;
+            let _ = write!( output, "  #{:<3} 0x{:016X}", nth_frame, address );
+
+            if let Some( region ) = memory.regions.get_value( lookup_address ) {
+                let binary = region.binary();
+                if let Some( (symbol, offset) ) = binary.lookup_raw_symbol( lookup_address ) {
+                    let _ = output.write_str( " in " );
+                    write_symbol( &mut output, symbol );
+                    let _ = write!( output, "+0x{:X}", offset + (address as u64).wrapping_sub( lookup_address ) );
+                }
+
+                let _ = write!( output, " ({})", binary.name() );
+            }
+
+            let _ = output.write_str( "\n" );
+            output.flush();
+        }
+    }
+}
+
+#[inline(never)]
+#[cfg(test)]
+fn crash_here() {
+    unsafe {
+        ptr::read_volatile( ptr::null::< u8 >() );
+    }
+}
+
+#[test]
+fn test_crash_handler() {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    install_crash_handler_with_opts( CrashHandlerOptions::new().signals( &[libc::SIGSEGV] ) ).unwrap();
+
+    let mut fds = [0; 2];
+    assert_eq!( unsafe { libc::pipe( fds.as_mut_ptr() ) }, 0 );
+
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        unsafe {
+            libc::dup2( fds[ 1 ], libc::STDERR_FILENO );
+        }
+
+        crash_here();
+        unsafe {
+            libc::_exit( 0 );
+        }
+    }
+
+    unsafe {
+        libc::close( fds[ 1 ] );
+    }
+
+    let mut output = String::new();
+    let mut fp = unsafe { File::from_raw_fd( fds[ 0 ] ) };
+    fp.read_to_string( &mut output ).unwrap();
+
+    let mut status = 0;
+    unsafe {
+        libc::waitpid( pid, &mut status, 0 );
+    }
+
+    assert!( libc::WIFSIGNALED( status ) );
+    assert_eq!( libc::WTERMSIG( status ), libc::SIGSEGV );
+    assert!( output.starts_with( "Received SIGSEGV" ), "unexpected output: {}", output );
+
+    let lines: Vec< _ > = output.lines().collect();
+    let index = lines.iter().position( |line| line.contains( "crash_here" ) ).expect( "crashing function not found" );
+    // The test function itself might get inlined, so we can only check that we've unwound past the crash.
+    assert!( lines.len() > index + 2, "unexpected output: {}", output );
+}
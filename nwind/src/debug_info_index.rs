@@ -10,6 +10,9 @@ use crate::utils::HexString;
 pub struct DebugInfoIndex {
     by_filename: HashMap< Vec< u8 >, Vec< Arc< BinaryData > > >,
     by_build_id: HashMap< Vec< u8 >, Vec< Arc< BinaryData > > >,
+    by_path: HashMap< PathBuf, Option< Arc< BinaryData > > >,
+    sysroot: Option< PathBuf >,
+    path_mappings: Vec< (PathBuf, PathBuf) >,
     auto_load: bool
 }
 
@@ -23,6 +26,9 @@ impl DebugInfoIndex {
         DebugInfoIndex {
             by_filename: HashMap::new(),
             by_build_id: HashMap::new(),
+            by_path: HashMap::new(),
+            sysroot: None,
+            path_mappings: Vec::new(),
             auto_load: false
         }
     }
@@ -31,6 +37,18 @@ impl DebugInfoIndex {
         self.auto_load = true;
     }
 
+    /// Sets a directory which contains a copy of the target's root filesystem
+    /// under which the binaries will be looked up by their original paths.
+    pub fn set_sysroot< P: AsRef< Path > >( &mut self, path: P ) {
+        self.sysroot = Some( path.as_ref().to_owned() );
+    }
+
+    /// Adds a rule which rewrites the original paths of the binaries
+    /// starting with the `from` prefix to start with `to` instead.
+    pub fn add_path_mapping< P: AsRef< Path >, Q: AsRef< Path > >( &mut self, from: P, to: Q ) {
+        self.path_mappings.push( (from.as_ref().to_owned(), to.as_ref().to_owned()) );
+    }
+
     pub fn add< P: AsRef< Path > >( &mut self, path: P ) {
         let mut done = HashSet::new();
         self.add_impl( &mut done, path.as_ref(), true );
@@ -79,6 +97,10 @@ impl DebugInfoIndex {
             }
         }
 
+        if candidates.is_empty() {
+            candidates = self.try_load_mapped( path, debuglink, build_id );
+        }
+
         if candidates.is_empty() && debuglink.is_none() {
             if let Some( build_id ) = build_id {
                 if let Some( binary ) = self.try_auto_load( path, build_id ) {
@@ -130,6 +152,88 @@ impl DebugInfoIndex {
         Some( binary )
     }
 
+    /// Returns the paths under which a binary originally located at `path` might be found locally.
+    fn mapped_paths( &self, path: &Path ) -> Vec< PathBuf > {
+        let mut output = Vec::new();
+        for (from, to) in &self.path_mappings {
+            if let Ok( relative_path ) = path.strip_prefix( from ) {
+                output.push( to.join( relative_path ) );
+            }
+        }
+
+        if let Some( ref sysroot ) = self.sysroot {
+            if let Ok( relative_path ) = path.strip_prefix( "/" ) {
+                output.push( sysroot.join( relative_path ) );
+            }
+        }
+
+        output
+    }
+
+    fn load_cached( &mut self, path: &Path ) -> Option< Arc< BinaryData > > {
+        if let Some( binary ) = self.by_path.get( path ) {
+            return binary.clone();
+        }
+
+        let binary = if path.is_file() {
+            match BinaryData::load_from_fs( path ) {
+                Ok( binary ) => Some( Arc::new( binary ) ),
+                Err( error ) => {
+                    warn!( "Cannot read {:?}: {}", path, error );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        self.by_path.insert( path.to_owned(), binary.clone() );
+        binary
+    }
+
+    fn try_load_mapped( &mut self, path: &str, debuglink: Option< &[u8] >, build_id: Option< &[u8] > ) -> Vec< Arc< BinaryData > > {
+        let original_path = Path::new( path );
+        let mut paths = self.mapped_paths( original_path );
+        if paths.is_empty() {
+            return Vec::new();
+        }
+
+        if let Some( debuglink ) = debuglink {
+            let debuglink = String::from_utf8_lossy( debuglink ).into_owned();
+            for directory in paths.clone().iter().filter_map( |path| path.parent() ) {
+                paths.push( directory.join( &debuglink ) );
+                paths.push( directory.join( ".debug" ).join( &debuglink ) );
+            }
+
+            if let (Some( sysroot ), Some( directory )) = (self.sysroot.as_ref(), original_path.parent()) {
+                if let Ok( directory ) = directory.strip_prefix( "/" ) {
+                    paths.push( sysroot.join( "usr/lib/debug" ).join( directory ).join( &debuglink ) );
+                }
+            }
+        }
+
+        if let (Some( sysroot ), Some( build_id )) = (self.sysroot.as_ref(), build_id) {
+            if build_id.len() > 1 {
+                let build_id: String = build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect();
+                paths.push( sysroot.join( "usr/lib/debug/.build-id" ).join( &build_id[ ..2 ] ).join( format!( "{}.debug", &build_id[ 2.. ] ) ) );
+            }
+        }
+
+        let mut candidates = Vec::new();
+        for path in paths {
+            if let Some( binary ) = self.load_cached( &path ) {
+                if check_build_id( &binary, build_id ) {
+                    debug!( "Found '{}' at {:?}", original_path.display(), path );
+                    candidates.push( binary );
+                } else {
+                    debug!( "Ignoring {:?} for '{}' due to a build ID mismatch", path, original_path.display() );
+                }
+            }
+        }
+
+        candidates
+    }
+
     fn add_impl( &mut self, done: &mut HashSet< PathBuf >, path: &Path, is_toplevel: bool ) {
         if !path.exists() {
             warn!( "Failed to load {:?}: file not found", path );
@@ -2,6 +2,8 @@ use std::fmt;
 
 use crate::arch::{Registers, RegsIter};
 use crate::utils::HexValue;
+#[cfg(target_os = "linux")]
+use crate::kernel_regs;
 
 #[derive(Clone)]
 pub struct DwarfRegs {
@@ -42,6 +44,22 @@ impl Default for DwarfRegs {
 
 impl DwarfRegs {
     pub fn new() -> Self { Default::default() }
+
+    /// Takes the registers from the context which a signal handler gets as its third argument.
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+    pub fn from_ucontext( ucontext: &libc::ucontext_t ) -> Self {
+        let mut regs = Self::new();
+        kernel_regs::load_ucontext( ucontext, |register, value| regs.append( register, value ) );
+        regs
+    }
+
+    /// Takes the registers of a thread stopped with `ptrace`, e.g. fetched with `PTRACE_GETREGSET`.
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn from_user_regs( user_regs: &libc::user_regs_struct ) -> Self {
+        let mut regs = Self::new();
+        kernel_regs::load_user_regs( user_regs, |register, value| regs.append( register, value ) );
+        regs
+    }
 }
 
 impl Registers for DwarfRegs {
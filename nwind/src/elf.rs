@@ -17,7 +17,7 @@ pub use scroll::Endian;
 pub use goblin::elf::header::Header;
 pub use goblin::elf::section_header::SectionHeader;
 pub use goblin::elf::program_header::ProgramHeader;
-pub use goblin::elf::sym::Sym;
+pub use goblin::elf::sym::{Sym, STT_OBJECT};
 pub use goblin::elf::note::Note;
 pub use goblin::strtab::Strtab;
 
@@ -67,7 +67,8 @@ macro_rules! define_elf {
             #[inline]
             fn section_headers( &self ) -> Self::SectionHeaderIter {
                 let header = &self.header;
-                let bytes = &self.bytes[ header.e_shoff as usize..header.e_shoff as usize + (header.e_shnum as usize * header.e_shentsize as usize) ];
+                let start = header.e_shoff as usize;
+                let bytes = self.bytes.get( start..start.saturating_add( header.e_shnum as usize * header.e_shentsize as usize ) ).unwrap_or( &[] );
 
                 Self::SectionHeaderIter::new( bytes, self.endianness )
             }
@@ -75,7 +76,8 @@ macro_rules! define_elf {
             #[inline]
             fn program_headers( &self ) -> Self::ProgramHeaderIter {
                 let header = &self.header;
-                let bytes = &self.bytes[ header.e_phoff as usize..header.e_phoff as usize + (header.e_phnum as usize * header.e_phentsize as usize) ];
+                let start = header.e_phoff as usize;
+                let bytes = self.bytes.get( start..start.saturating_add( header.e_phnum as usize * header.e_phentsize as usize ) ).unwrap_or( &[] );
 
                 Self::ProgramHeaderIter::new( bytes, self.endianness )
             }
@@ -83,19 +85,20 @@ macro_rules! define_elf {
             #[inline]
             fn get_section_header( &self, index: usize ) -> Option< SectionHeader > {
                 let header = &self.header;
-                let start = header.e_shoff as usize + (index * header.e_shentsize as usize);
-                let end = header.e_shoff as usize + (header.e_shnum as usize * header.e_shentsize as usize);
+                let start = (header.e_shoff as usize).saturating_add( index.saturating_mul( header.e_shentsize as usize ) );
+                let end = (header.e_shoff as usize).saturating_add( header.e_shnum as usize * header.e_shentsize as usize );
                 if start >= end {
                     return None;
                 }
 
-                let bytes = &self.bytes[ start..end ];
+                let bytes = self.bytes.get( start..end )?;
                 Self::SectionHeaderIter::new( bytes, self.endianness ).next()
             }
 
             #[inline]
             fn get_section_body( &self, section_header: &SectionHeader ) -> &'a [u8] {
-                &self.bytes[ section_header.sh_offset as usize..section_header.sh_offset as usize + section_header.sh_size as usize ]
+                let start = section_header.sh_offset as usize;
+                self.bytes.get( start..start.saturating_add( section_header.sh_size as usize ) ).unwrap_or( &[] )
             }
 
             #[inline]
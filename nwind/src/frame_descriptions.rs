@@ -1,3 +1,5 @@
+use std::io;
+use std::convert::TryFrom;
 use std::ops::Range;
 use std::sync::Arc;
 use std::mem::{self, ManuallyDrop};
@@ -5,6 +7,7 @@ use std::time::Instant;
 use std::marker::PhantomData;
 
 use lru::LruCache;
+use speedy::{Readable, Writable};
 
 use gimli::{
     self,
@@ -29,9 +32,11 @@ use gimli::{
 use crate::utils::get_ms;
 use crate::binary::{BinaryData};
 use crate::arch::Endianity;
+use crate::malformed::report_malformed;
 use crate::range_map::RangeMap;
+use crate::types::Bitness;
 
-type DataReader< E > = EndianSlice< 'static, E >;
+pub(crate) type DataReader< E > = EndianSlice< 'static, E >;
 
 pub struct ContextCache< E: Endianity > {
     cached_context: UninitializedUnwindContext< DataReader< E > >
@@ -46,7 +51,7 @@ impl< E: Endianity > ContextCache< E > {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Default, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Default, Debug, Hash, Readable, Writable)]
 pub struct AddressMapping {
     pub declared_address: u64,
     pub actual_address: u64,
@@ -54,12 +59,55 @@ pub struct AddressMapping {
     pub size: u64
 }
 
-type FDE< E > = FrameDescriptionEntry< DataReader< E > >;
+pub(crate) type FDE< E > = FrameDescriptionEntry< DataReader< E > >;
+
+/// The offsets of the FDEs within their section, sorted by the addresses they describe,
+/// so that they can be binary searched; this is the same kind of a lookup table which
+/// `.eh_frame_hdr` provides, for when the binary doesn't have a usable one.
+type FdeIndex = RangeMap< usize >;
+
+type DebugFrameSection< E > = (BaseAddresses, DebugFrame< DataReader< E > >);
+type EhFrameSection< E > = (BaseAddresses, EhFrame< DataReader< E > >);
+
+/// The FDEs indexed by `FrameDescriptions`, as pairs of address ranges and section offsets.
+pub(crate) struct SavedFdeIndex {
+    pub use_eh_frame_hdr: bool,
+    pub eh_frame: Vec< (Range< u64 >, usize) >,
+    pub debug_frame: Vec< (Range< u64 >, usize) >
+}
+
+/// Parses an FDE from a given offset within its section. Every FDE which ends up in an `FdeIndex`
+/// was already successfully parsed once when the index was built, so this isn't expected to fail.
+fn fde_at< E, U >( bases: &BaseAddresses, section: &U, offset: usize ) -> gimli::Result< FDE< E > >
+    where E: Endianity,
+          U: UnwindSection< DataReader< E > >
+{
+    section.fde_from_offset( bases, offset.into(), |section, bases, offset| section.cie_from_offset( bases, offset ) )
+}
+
+/// Where an FDE was found.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum FdeSource {
+    DebugFrame,
+    EhFrame,
+    /// In `.eh_frame`, through the lookup table from `.eh_frame_hdr`.
+    EhFrameHdr
+}
+
+impl FdeSource {
+    pub(crate) fn name( self ) -> &'static str {
+        match self {
+            FdeSource::DebugFrame => ".debug_frame",
+            FdeSource::EhFrame => ".eh_frame",
+            FdeSource::EhFrameHdr => ".eh_frame (through .eh_frame_hdr)"
+        }
+    }
+}
 
 pub struct FrameDescriptions< E: Endianity > {
     binary: ManuallyDrop< Arc< BinaryData > >,
-    eh_descriptions: ManuallyDrop< RangeMap< FDE< E > > >,
-    debug_descriptions: ManuallyDrop< RangeMap< FDE< E > > >,
+    eh_descriptions: FdeIndex,
+    debug_descriptions: FdeIndex,
 
     debug_frame: ManuallyDrop< Option< (BaseAddresses, DebugFrame< DataReader< E > >) > >,
     eh_frame: ManuallyDrop< Option< (BaseAddresses, EhFrame< DataReader< E > >) > >,
@@ -70,8 +118,6 @@ impl< E: Endianity > Drop for FrameDescriptions< E > {
     #[inline]
     fn drop( &mut self ) {
         unsafe {
-            ManuallyDrop::drop( &mut self.eh_descriptions );
-            ManuallyDrop::drop( &mut self.debug_descriptions );
             ManuallyDrop::drop( &mut self.eh_frame );
             ManuallyDrop::drop( &mut self.eh_frame_hdr );
             ManuallyDrop::drop( &mut self.binary );
@@ -80,16 +126,23 @@ impl< E: Endianity > Drop for FrameDescriptions< E > {
 }
 
 pub struct UnwindInfoCache {
-    cache: Option< LruCache< u64, CachedUnwindInfo > >
+    cache: Option< LruCache< u64, CachedUnwindInfo > >,
+    is_disabled: bool
 }
 
 impl UnwindInfoCache {
     pub fn new() -> Self {
         UnwindInfoCache {
-            cache: None
+            cache: None,
+            is_disabled: false
         }
     }
 
+    /// Stops putting new entries into the cache, so that unwinding won't allocate.
+    pub fn disable( &mut self ) {
+        self.is_disabled = true;
+    }
+
     pub fn clear( &mut self ) {
         if let Some( cache ) = self.cache.as_mut() {
             cache.clear();
@@ -145,7 +198,7 @@ impl< T: ::gimli::Reader > Into< RegisterRule< T > > for SimpleRegisterRule {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Readable, Writable)]
 pub enum LoadHint {
     WhenNecessary,
     Always,
@@ -165,6 +218,12 @@ impl< E: Endianity > FrameDescriptionsBuilder< E > {
         FrameDescriptions::< E >::load_with_options( self )
     }
 
+    /// Loads the descriptions with the FDEs which were already indexed once, instead of indexing
+    /// the sections again; the options set on the builder are ignored.
+    pub(crate) fn load_from_saved_index( self, index: SavedFdeIndex ) -> Option< FrameDescriptions< E > > {
+        FrameDescriptions::< E >::load_from_saved_index( &self.binary, index )
+    }
+
     pub fn should_use_eh_frame_hdr( mut self, value: bool ) -> Self {
         self.use_eh_frame_hdr = value;
         self
@@ -197,14 +256,21 @@ impl< E: Endianity > FrameDescriptions< E > {
         }
     }
 
-    fn load_with_options( builder: FrameDescriptionsBuilder< E > ) -> Option< Self > {
-        let binary = &builder.binary;
+    /// Sets up the `.debug_frame` and `.eh_frame` sections of a binary without parsing any of their entries.
+    fn load_sections( binary: &Arc< BinaryData > ) -> (Option< DebugFrameSection< E > >, Option< EhFrameSection< E > >) {
+        let address_size = match binary.bitness() {
+            Bitness::B32 => 4,
+            Bitness::B64 => 8
+        };
+
         let debug_frame;
         if let Some( range ) = binary.debug_frame_range() {
             let bases = BaseAddresses::default();
             let debug_frame_data: &[u8] = &binary.as_bytes()[ range ];
             let debug_frame_data: &'static [u8] = unsafe { mem::transmute( debug_frame_data ) };
-            debug_frame = Some( (bases, DebugFrame::new( debug_frame_data, E::get() )) );
+            let mut section = DebugFrame::new( debug_frame_data, E::get() );
+            section.set_address_size( address_size );
+            debug_frame = Some( (bases, section) );
         } else {
             debug_frame = None;
         }
@@ -223,11 +289,20 @@ impl< E: Endianity > FrameDescriptions< E > {
 
             let eh_frame_data: &[u8] = &binary.as_bytes()[ range ];
             let eh_frame_data: &'static [u8] = unsafe { mem::transmute( eh_frame_data ) };
-            eh_frame = Some( (bases, EhFrame::new( eh_frame_data, E::get() )) );
+            let mut section = EhFrame::new( eh_frame_data, E::get() );
+            section.set_address_size( address_size );
+            eh_frame = Some( (bases, section) );
         } else {
             eh_frame = None;
         }
 
+        (debug_frame, eh_frame)
+    }
+
+    fn load_with_options( builder: FrameDescriptionsBuilder< E > ) -> Option< Self > {
+        let binary = &builder.binary;
+        let (debug_frame, eh_frame) = Self::load_sections( binary );
+
         let eh_frame_hdr;
         if builder.use_eh_frame_hdr {
             eh_frame_hdr = Self::load_eh_frame_hdr( binary );
@@ -235,31 +310,49 @@ impl< E: Endianity > FrameDescriptions< E > {
             eh_frame_hdr = None;
         }
 
-        let debug_descriptions: RangeMap< FDE< E > >;
+        let debug_descriptions: FdeIndex;
         if debug_frame.is_some() && builder.load_debug_frame {
             debug!( "Loading FDEs from .debug_frame for {}...", binary.name() );
             let (ref bases, ref debug_frame) = debug_frame.as_ref().unwrap();
+            let range = binary.debug_frame_range().unwrap();
 
             let start_timestamp = Instant::now();
-            debug_descriptions = Self::load_section( bases, binary.name(), debug_frame );
+            debug_descriptions = match Self::load_section( bases, binary.name(), ".debug_frame", &binary.as_bytes()[ range.clone() ], range.start, debug_frame ) {
+                Ok( descriptions ) => descriptions,
+                Err( error ) => {
+                    error!( "Failed to load the FDEs from .debug_frame for '{}': {}", binary.name(), error );
+                    return None;
+                }
+            };
             let elapsed = start_timestamp.elapsed();
 
-            debug!( "Loaded {} FDEs from .debug_frame for '{}' in {}ms", debug_descriptions.len(), binary.name(), get_ms( elapsed ) );
+            debug!( "Indexed {} FDEs from .debug_frame for '{}' in {}ms", debug_descriptions.len(), binary.name(), get_ms( elapsed ) );
         } else {
             debug_descriptions = RangeMap::new();
         }
 
         let want_to_load_eh_frame = builder.load_eh_frame == LoadHint::Always || (builder.load_eh_frame == LoadHint::WhenNecessary && eh_frame_hdr.is_none());
-        let eh_descriptions: RangeMap< FDE< E > >;
+        let eh_descriptions: FdeIndex;
         if eh_frame.is_some() && want_to_load_eh_frame {
+            if builder.load_eh_frame == LoadHint::WhenNecessary && builder.use_eh_frame_hdr {
+                debug!( "No usable .eh_frame_hdr found for '{}'; the FDEs from .eh_frame will be indexed instead", binary.name() );
+            }
+
             debug!( "Loading FDEs from .eh_frame for {}...", binary.name() );
             let (ref bases, ref eh_frame) = eh_frame.as_ref().unwrap();
+            let range = binary.eh_frame_range().unwrap();
 
             let start_timestamp = Instant::now();
-            eh_descriptions = Self::load_section( bases, binary.name(), eh_frame );
+            eh_descriptions = match Self::load_section( bases, binary.name(), ".eh_frame", &binary.as_bytes()[ range.clone() ], range.start, eh_frame ) {
+                Ok( descriptions ) => descriptions,
+                Err( error ) => {
+                    error!( "Failed to load the FDEs from .eh_frame for '{}': {}", binary.name(), error );
+                    return None;
+                }
+            };
             let elapsed = start_timestamp.elapsed();
 
-            debug!( "Loaded {} FDEs from .eh_frame for '{}' in {}ms", eh_descriptions.len(), binary.name(), get_ms( elapsed ) );
+            debug!( "Indexed {} FDEs from .eh_frame for '{}' in {}ms", eh_descriptions.len(), binary.name(), get_ms( elapsed ) );
         } else {
             if want_to_load_eh_frame {
                 warn!( "No .eh_frame section found for '{}'", binary.name() );
@@ -268,29 +361,47 @@ impl< E: Endianity > FrameDescriptions< E > {
             eh_descriptions = RangeMap::new();
         }
 
-        let debug_descriptions: RangeMap< FDE< E > > = unsafe { mem::transmute( debug_descriptions ) };
-        let eh_descriptions: RangeMap< FDE< E > > = unsafe { mem::transmute( eh_descriptions ) };
-
         Some( FrameDescriptions {
             binary: ManuallyDrop::new( binary.clone() ),
-            debug_descriptions: ManuallyDrop::new( debug_descriptions ),
-            eh_descriptions: ManuallyDrop::new( eh_descriptions ),
+            debug_descriptions,
+            eh_descriptions,
             debug_frame: ManuallyDrop::new( debug_frame ),
             eh_frame: ManuallyDrop::new( eh_frame ),
             eh_frame_hdr: ManuallyDrop::new( eh_frame_hdr )
         })
     }
 
-    fn get_base( binary: &Arc< BinaryData >, range: Option< Range< usize > > ) -> Option< u64 > {
-        let range = range?;
-        let start = range.start as u64;
-        for header in binary.load_headers() {
-            if start >= header.file_offset && start < (header.file_offset + header.file_size) {
-                return Some( header.address + (start - header.file_offset) );
-            }
+    /// Returns the FDEs which were indexed when these descriptions were loaded, so that
+    /// they can be loaded again later with `FrameDescriptionsBuilder::load_from_saved_index`.
+    pub(crate) fn save_index( &self ) -> SavedFdeIndex {
+        let save = |index: &FdeIndex| index.iter().map( |(range, &offset)| (range, offset) ).collect();
+        SavedFdeIndex {
+            use_eh_frame_hdr: self.eh_frame_hdr.is_some(),
+            eh_frame: save( &self.eh_descriptions ),
+            debug_frame: save( &self.debug_descriptions )
         }
+    }
 
-        None
+    fn load_from_saved_index( binary: &Arc< BinaryData >, index: SavedFdeIndex ) -> Option< Self > {
+        let (debug_frame, eh_frame) = Self::load_sections( binary );
+        let eh_frame_hdr = if index.use_eh_frame_hdr {
+            Self::load_eh_frame_hdr( binary )
+        } else {
+            None
+        };
+
+        Some( FrameDescriptions {
+            binary: ManuallyDrop::new( binary.clone() ),
+            debug_descriptions: RangeMap::from_vec( index.debug_frame ),
+            eh_descriptions: RangeMap::from_vec( index.eh_frame ),
+            debug_frame: ManuallyDrop::new( debug_frame ),
+            eh_frame: ManuallyDrop::new( eh_frame ),
+            eh_frame_hdr: ManuallyDrop::new( eh_frame_hdr )
+        })
+    }
+
+    fn get_base( binary: &Arc< BinaryData >, range: Option< Range< usize > > ) -> Option< u64 > {
+        binary.file_offset_to_address( range?.start as u64 )
     }
 
     fn load_eh_frame_hdr< 'a >( binary: &'a Arc< BinaryData > ) -> Option< (BaseAddresses, ParsedEhFrameHdr< DataReader< E > >) > {
@@ -311,7 +422,8 @@ impl< E: Endianity > FrameDescriptions< E > {
         let eh_frame_hdr = match eh_frame_hdr.parse( &bases, mem::size_of::< usize >() as u8 ) {
             Ok( eh_frame_hdr ) => eh_frame_hdr,
             Err( error ) => {
-                warn!( "Failed to load .eh_frame_hdr for {}: {}", binary.name(), error );
+                let offset = binary.eh_frame_hdr_range()?.start as u64;
+                let _ = report_malformed( binary.name(), ".eh_frame_hdr", offset, error );
                 return None;
             }
         };
@@ -331,35 +443,216 @@ impl< E: Endianity > FrameDescriptions< E > {
         Some( (bases, eh_frame_hdr) )
     }
 
-    fn load_section< R, U >( bases: &BaseAddresses, name: &str, section: &U ) -> RangeMap< FrameDescriptionEntry< R > >
+    /// Returns the size of the CFI entry at a given offset, including its length field.
+    ///
+    /// Returns `None` if the entry is truncated, or if its length doesn't fit in an `usize`.
+    fn entry_size( data: &[u8], offset: usize ) -> Option< usize > {
+        let endianness = E::get();
+        let length = endianness.read_u32( data.get( offset..offset.checked_add( 4 )? )? );
+        if length == 0xffff_ffff {
+            let length = endianness.read_u64( data.get( offset + 4..offset.checked_add( 12 )? )? );
+            usize::try_from( length ).ok()?.checked_add( 12 )
+        } else {
+            (length as usize).checked_add( 4 )
+        }
+    }
+
+    /// Parses every FDE from a section whose raw `data` starts at `file_offset` within the binary, and indexes them.
+    fn load_section< R, U >( bases: &BaseAddresses, binary_name: &str, section_name: &str, data: &[u8], file_offset: usize, section: &U ) -> io::Result< FdeIndex >
         where R: gimli::Reader< Offset = usize >,
               U: UnwindSection< R >,
               <U as UnwindSection< R >>::Offset: UnwindOffset
     {
         let mut entries = section.entries( bases );
         let mut descriptions = Vec::new();
+        let mut offset = 0;
         loop {
-            match entries.next() {
-                Ok( Some( CieOrFde::Cie( _ ) ) ) => continue,
+            let entry_offset = (file_offset + offset) as u64;
+            let result = entries.next();
+
+            // If we can't tell where the next entry starts then this one is the last one we can trust.
+            let next_offset = Self::entry_size( data, offset ).and_then( |size| offset.checked_add( size ) );
+            let is_last = next_offset.is_none();
+            offset = next_offset.unwrap_or( offset );
+
+            match result {
+                Ok( Some( CieOrFde::Cie( _ ) ) ) => {},
                 Ok( Some( CieOrFde::Fde( partial ) ) ) => {
                     match partial.parse( |_, _, offset| section.cie_from_offset( bases, offset ) ) {
                         Ok( fde ) => {
-                            descriptions.push( (fde.initial_address()..fde.initial_address() + fde.len(), fde) );
+                            match fde.initial_address().checked_add( fde.len() ) {
+                                Some( end ) => descriptions.push( (fde.initial_address()..end, fde.offset()) ),
+                                None => report_malformed( binary_name, section_name, entry_offset, "the FDE's address range overflows" )?
+                            }
                         },
                         Err( error ) => {
-                            warn!( "Failed to parse FDE for '{}': {}", name, error );
+                            report_malformed( binary_name, section_name, entry_offset, format_args!( "failed to parse an FDE: {}", error ) )?;
                         }
                     }
                 },
                 Ok( None ) => break,
                 Err( error ) => {
-                    warn!( "Failed to iterate FDEs for '{}': {}", name, error );
+                    // Nothing after a broken entry can be trusted, so the rest of the section is skipped.
+                    report_malformed( binary_name, section_name, entry_offset, format_args!( "failed to parse a CFI entry: {}", error ) )?;
                     break;
                 }
             }
+
+            if is_last {
+                break;
+            }
+        }
+
+        Ok( RangeMap::from_vec( descriptions ) )
+    }
+
+    /// Calls `callback` for every row of every unwind table, preferring `.debug_frame` when
+    /// both it and `.eh_frame` describe the same function. The rows of a single FDE are
+    /// passed in order, and the first one always starts at the FDE's initial address.
+    pub fn each_unwind_row< F >( &self, mut callback: F )
+        where F: FnMut( Range< u64 >, Register, &UnwindTableRow< DataReader< E > > )
+    {
+        let mut ctx = UninitializedUnwindContext::new();
+        if let Some( (ref bases, ref debug_frame) ) = *self.debug_frame {
+            for (range, &offset) in self.debug_descriptions.iter() {
+                let result = fde_at( bases, debug_frame, offset ).and_then( |fde| Self::each_row_of( &mut ctx, bases, debug_frame, &fde, range.clone(), &mut callback ) );
+                if let Err( error ) = result {
+                    warn!( "Failed to evaluate the unwind table for 0x{:016X}: {}", range.start, error );
+                }
+            }
+        }
+
+        if let Some( (ref bases, ref eh_frame) ) = *self.eh_frame {
+            for (range, &offset) in self.eh_descriptions.iter() {
+                if self.debug_descriptions.get_index( range.start ).is_some() {
+                    continue;
+                }
+
+                let result = fde_at( bases, eh_frame, offset ).and_then( |fde| Self::each_row_of( &mut ctx, bases, eh_frame, &fde, range.clone(), &mut callback ) );
+                if let Err( error ) = result {
+                    warn!( "Failed to evaluate the unwind table for 0x{:016X}: {}", range.start, error );
+                }
+            }
+        }
+    }
+
+    fn each_row_of< U, F >(
+        ctx: &mut UninitializedUnwindContext< DataReader< E > >,
+        bases: &BaseAddresses,
+        section: &U,
+        fde: &FDE< E >,
+        range: Range< u64 >,
+        callback: &mut F
+    ) -> gimli::Result< () >
+        where U: UnwindSection< DataReader< E > >,
+              F: FnMut( Range< u64 >, Register, &UnwindTableRow< DataReader< E > > )
+    {
+        let return_address_register = fde.cie().return_address_register();
+        let mut table = UnwindTable::new( section, bases, ctx, fde )?;
+        while let Some( row ) = table.next_row()? {
+            callback( range.clone(), return_address_register, row );
+        }
+
+        Ok(())
+    }
+
+    /// Goes through every row of every FDE, first from `.debug_frame` and then from `.eh_frame`;
+    /// unlike `each_unwind_row` FDEs which are present in both sections are visited twice.
+    pub(crate) fn each_fde_row< F >( &self, callback: F )
+        where F: FnMut( FdeSource, &FDE< E >, &UnwindTableRow< DataReader< E > > )
+    {
+        self.try_each_fde_row( callback, |_, fde, error| {
+            warn!( "Failed to evaluate the unwind table for 0x{:016X}: {}", fde.initial_address(), error );
+        });
+    }
+
+    /// Same as `each_fde_row`, except every FDE whose rows can't be evaluated is passed to `on_error`.
+    pub(crate) fn try_each_fde_row< F, G >( &self, mut callback: F, mut on_error: G )
+        where F: FnMut( FdeSource, &FDE< E >, &UnwindTableRow< DataReader< E > > ),
+              G: FnMut( FdeSource, &FDE< E >, gimli::Error )
+    {
+        let mut ctx = UninitializedUnwindContext::new();
+        if let Some( (ref bases, ref debug_frame) ) = *self.debug_frame {
+            for (range, &offset) in self.debug_descriptions.iter() {
+                let fde = match fde_at( bases, debug_frame, offset ) {
+                    Ok( fde ) => fde,
+                    Err( _ ) => continue
+                };
+
+                if let Err( error ) = Self::each_row_of( &mut ctx, bases, debug_frame, &fde, range, &mut |_, _, row| callback( FdeSource::DebugFrame, &fde, row ) ) {
+                    on_error( FdeSource::DebugFrame, &fde, error );
+                }
+            }
         }
 
-        RangeMap::from_vec( descriptions )
+        if let Some( (ref bases, ref eh_frame) ) = *self.eh_frame {
+            for (range, &offset) in self.eh_descriptions.iter() {
+                let fde = match fde_at( bases, eh_frame, offset ) {
+                    Ok( fde ) => fde,
+                    Err( _ ) => continue
+                };
+
+                if let Err( error ) = Self::each_row_of( &mut ctx, bases, eh_frame, &fde, range, &mut |_, _, row| callback( FdeSource::EhFrame, &fde, row ) ) {
+                    on_error( FdeSource::EhFrame, &fde, error );
+                }
+            }
+        }
+    }
+
+    /// Finds the unwind table row for a given (non-relocated) address the same way `find_unwind_info` does,
+    /// and passes it to `callback` along with the FDE it came from and which section that FDE was found in.
+    pub(crate) fn lookup_row< F, T >( &self, address: u64, mut callback: F ) -> Option< T >
+        where F: FnMut( FdeSource, &FDE< E >, &UnwindTableRow< DataReader< E > > ) -> T
+    {
+        let mut ctx = UninitializedUnwindContext::new();
+        if let Some( (ref bases, ref debug_frame) ) = *self.debug_frame {
+            if let Some( fde ) = self.debug_descriptions.get_value( address ).and_then( |&offset| fde_at( bases, debug_frame, offset ).ok() ) {
+                if let Some( result ) = Self::find_row_of( &mut ctx, bases, debug_frame, &fde, address, FdeSource::DebugFrame, &mut callback ) {
+                    return Some( result );
+                }
+            }
+        }
+
+        if let Some( (ref bases, ref eh_frame) ) = *self.eh_frame {
+            if let Some( fde ) = self.eh_descriptions.get_value( address ).and_then( |&offset| fde_at( bases, eh_frame, offset ).ok() ) {
+                if let Some( result ) = Self::find_row_of( &mut ctx, bases, eh_frame, &fde, address, FdeSource::EhFrame, &mut callback ) {
+                    return Some( result );
+                }
+            }
+        }
+
+        if let Some( &(ref bases, ref eh_frame_hdr) ) = self.eh_frame_hdr.as_ref() {
+            let eh_frame = &self.eh_frame.as_ref()?.1;
+            let fde = eh_frame_hdr.table()?.fde_for_address( eh_frame, bases, address, |_, _, offset| {
+                eh_frame.cie_from_offset( bases, offset )
+            }).ok()?;
+
+            return Self::find_row_of( &mut ctx, bases, eh_frame, &fde, address, FdeSource::EhFrameHdr, &mut callback );
+        }
+
+        None
+    }
+
+    fn find_row_of< U, F, T >(
+        ctx: &mut UninitializedUnwindContext< DataReader< E > >,
+        bases: &BaseAddresses,
+        section: &U,
+        fde: &FDE< E >,
+        address: u64,
+        source: FdeSource,
+        callback: &mut F
+    ) -> Option< T >
+        where U: UnwindSection< DataReader< E > >,
+              F: FnMut( FdeSource, &FDE< E >, &UnwindTableRow< DataReader< E > > ) -> T
+    {
+        let mut table = UnwindTable::new( section, bases, ctx, fde ).ok()?;
+        while let Some( row ) = table.next_row().ok()? {
+            if row.contains( address ) {
+                return Some( callback( source, fde, row ) );
+            }
+        }
+
+        None
     }
 
     pub fn find_unwind_info< 'a >(
@@ -377,10 +670,10 @@ impl< E: Endianity > FrameDescriptions< E > {
         let ctx = &mut ctx_cache.cached_context;
 
         if !self.debug_descriptions.is_empty() {
-            if let Some( fde ) = self.debug_descriptions.get_value( address ) {
+            let (bases, debug_frame) = &self.debug_frame.as_ref().unwrap();
+            if let Some( fde ) = self.debug_descriptions.get_value( address ).and_then( |&offset| fde_at( bases, debug_frame, offset ).ok() ) {
                 let initial_address = fde.initial_address();
 
-                let (bases, debug_frame) = &self.debug_frame.as_ref().unwrap();
                 let ctx = unsafe { launder_lifetime( ctx ) };
                 if let Ok( mut table ) = UnwindTable::new( debug_frame, bases, ctx, &fde ) {
                     loop {
@@ -410,10 +703,10 @@ impl< E: Endianity > FrameDescriptions< E > {
         }
 
         if !self.eh_descriptions.is_empty() {
-            if let Some( fde ) = self.eh_descriptions.get_value( address ) {
+            let (bases, eh_frame) = &self.eh_frame.as_ref().unwrap();
+            if let Some( fde ) = self.eh_descriptions.get_value( address ).and_then( |&offset| fde_at( bases, eh_frame, offset ).ok() ) {
                 let initial_address = fde.initial_address();
 
-                let (bases, eh_frame) = &self.eh_frame.as_ref().unwrap();
                 let ctx = unsafe { launder_lifetime( ctx ) };
                 if let Ok( mut table ) = UnwindTable::new( eh_frame, bases, ctx, &fde ) {
                     loop {
@@ -579,6 +872,10 @@ impl< 'a, E: Endianity > UnwindInfo< 'a, E > {
             _ => return
         };
 
+        if unwind_cache.is_disabled {
+            return;
+        }
+
         let cache = unwind_cache.cache.get_or_insert_with( || LruCache::new( 4096 ) );
 
         let mut rules = Vec::new();
@@ -635,7 +932,7 @@ unsafe fn calculate_fde_length( fde: *const u8 ) -> usize {
 struct DynamicTable< E > where E: Endianity {
     bases: BaseAddresses,
     section: EhFrame< EndianSlice< 'static, E > >,
-    fde_map: RangeMap< FrameDescriptionEntry< EndianSlice< 'static, E > > >
+    fde_map: FdeIndex
 }
 
 #[derive(Default)]
@@ -651,7 +948,7 @@ impl< E > DynamicFdeRegistry< E > where E: Endianity {
     ) -> Option< UnwindInfo< 'a, E > > {
         let ctx = &mut ctx_cache.cached_context;
         for (_, table) in &self.tables {
-            if let Some( fde ) = table.fde_map.get_value( address ) {
+            if let Some( fde ) = table.fde_map.get_value( address ).and_then( |&offset| fde_at( &table.bases, &table.section, offset ).ok() ) {
                 let initial_address = fde.initial_address();
 
                 let ctx = unsafe { launder_lifetime( ctx ) };
@@ -694,7 +991,8 @@ impl DynamicFdeRegistry< gimli::NativeEndian > {
         bases = bases.set_eh_frame( fde as u64 );
 
         let section = gimli::read::EhFrame::new( slice, gimli::NativeEndian );
-        let fde_map = FrameDescriptions::< gimli::NativeEndian >::load_section( &bases, "<dynamic>", &section );
+        let fde_map = FrameDescriptions::< gimli::NativeEndian >::load_section( &bases, "<dynamic>", ".eh_frame", slice, 0, &section )
+            .unwrap_or_else( |_| RangeMap::new() );
         let table = DynamicTable {
             bases,
             section,
@@ -715,4 +1013,67 @@ impl DynamicFdeRegistry< gimli::NativeEndian > {
             self.tables.remove( index );
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_load_debug_frame_of_a_32bit_binary() {
+    use std::path::PathBuf;
+
+    let path = PathBuf::from( env!( "CARGO_MANIFEST_DIR" ) )
+        .join( ".." )
+        .join( "test-data" )
+        .join( "bin" )
+        .join( "arm-inline_functions" );
+
+    // The addresses in `.debug_frame` are as wide as the target's pointers, so they'd be misparsed
+    // if they were read as 64-bit ones.
+    let binary = Arc::new( BinaryData::load_from_fs( path ).unwrap() );
+    let descriptions = FrameDescriptions::< gimli::LittleEndian >::new( &binary ).load().unwrap();
+    assert!( !descriptions.debug_descriptions.is_empty() );
+
+    let code: Vec< _ > = binary.load_headers().iter().filter( |header| header.is_executable ).collect();
+    for (range, _) in descriptions.debug_descriptions.iter() {
+        assert!( code.iter().any( |header| range.start >= header.address && range.end <= header.address + header.memory_size ), "FDE out of the code: {:?}", range );
+    }
+}
+
+#[test]
+fn test_fde_index_matches_eh_frame_hdr() {
+    let binary = Arc::new( BinaryData::load_from_fs( std::env::current_exe().unwrap() ).unwrap() );
+    let with_hdr = FrameDescriptions::< gimli::NativeEndian >::new( &binary )
+        .should_load_debug_frame( false )
+        .load()
+        .unwrap();
+
+    let without_hdr = FrameDescriptions::< gimli::NativeEndian >::new( &binary )
+        .should_use_eh_frame_hdr( false )
+        .should_load_debug_frame( false )
+        .load()
+        .unwrap();
+
+    assert!( with_hdr.eh_descriptions.is_empty() );
+    assert!( !without_hdr.eh_descriptions.is_empty() );
+
+    for (range, _) in without_hdr.eh_descriptions.iter().filter( |(range, _)| range.start < range.end ).step_by( 31 ) {
+        for &address in &[range.start, range.end - 1] {
+            let expected = with_hdr.lookup_row( address, |source, fde, row| (source, fde.offset(), row.start_address()) );
+            let actual = without_hdr.lookup_row( address, |source, fde, row| (source, fde.offset(), row.start_address()) );
+            assert_eq!( expected.map( |(source, ..)| source ), Some( FdeSource::EhFrameHdr ) );
+            assert_eq!( actual.map( |(source, ..)| source ), Some( FdeSource::EhFrame ) );
+            assert_eq!( expected.map( |(_, offset, start)| (offset, start) ), actual.map( |(_, offset, start)| (offset, start) ) );
+        }
+    }
+}
+
+#[test]
+fn test_entry_size_of_malformed_entries() {
+    type Descriptions = FrameDescriptions< gimli::LittleEndian >;
+
+    assert_eq!( Descriptions::entry_size( &[0x10, 0, 0, 0], 0 ), Some( 0x14 ) );
+    assert_eq!( Descriptions::entry_size( &[0xff, 0xff, 0xff, 0xff, 0x10, 0, 0, 0, 0, 0, 0, 0], 0 ), Some( 0x1c ) );
+    assert_eq!( Descriptions::entry_size( &[0x10, 0, 0], 0 ), None );
+    assert_eq!( Descriptions::entry_size( &[0x10, 0, 0, 0], usize::MAX - 1 ), None );
+
+    // A 64-bit length which doesn't fit when the size of the header is added to it.
+    assert_eq!( Descriptions::entry_size( &[0xff; 12], 0 ), None );
+}
@@ -0,0 +1,523 @@
+//! Parsing of the `.gopclntab` section which the Go toolchain puts into every binary it builds.
+//!
+//! The Go runtime uses it to generate its own backtraces, so unlike the ELF symbol table
+//! it's still there when the binary is stripped; it maps the addresses to the function
+//! names, and to the file names and line numbers. (The `.gosymtab` section which
+//! accompanies it has been empty since Go 1.3.)
+//!
+//! Source: `src/debug/gosym/pclntab.go` and `src/runtime/symtab.go` from the Go sources.
+
+use std::ops::Range;
+use std::str;
+use std::sync::Arc;
+
+use crate::binary::BinaryData;
+use crate::types::Endianness;
+
+/// The functions at which the Go runtime's own traceback stops, either because they're at the very
+/// bottom of a goroutine's or a thread's stack, or because they switch between the stacks; the rest
+/// of the backtrace lives on another stack which we don't have.
+const OUTERMOST_FUNCTIONS: &[&str] = &[
+    "runtime.goexit",
+    "runtime.mstart",
+    "runtime.rt0_go",
+    "runtime.mcall",
+    "runtime.morestack",
+    "runtime.systemstack_switch"
+];
+
+/// The information needed to unwind a frame of a Go function.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GoFrame {
+    /// The address at which the function starts.
+    pub entry: u64,
+    /// How far the stack pointer was moved since the function was entered, which
+    /// is where the return address (or the saved link register) can be found.
+    pub sp_offset: u64,
+    /// Whether the unwinding should stop at this function.
+    pub is_outermost: bool
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Version {
+    Go12,
+    Go116,
+    Go118,
+    Go120
+}
+
+#[derive(Clone, Debug)]
+struct Header {
+    version: Version,
+    is_little_endian: bool,
+    quantum: u32,
+    pointer_size: usize,
+    function_count: usize,
+    text_start: u64,
+    functab: usize,
+    funcdata: usize,
+    funcnametab: usize,
+    cutab: usize,
+    filetab: usize,
+    pctab: usize
+}
+
+/// A borrowed view of the line table with all of the actual parsing logic.
+struct Table< 'a > {
+    bytes: &'a [u8],
+    header: &'a Header
+}
+
+impl< 'a > Table< 'a > {
+    fn u32_at( &self, offset: usize ) -> Option< u32 > {
+        let bytes = self.bytes.get( offset..offset.checked_add( 4 )? )?;
+        let bytes = [bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ]];
+        Some( if self.header.is_little_endian { u32::from_le_bytes( bytes ) } else { u32::from_be_bytes( bytes ) } )
+    }
+
+    fn u64_at( &self, offset: usize ) -> Option< u64 > {
+        let bytes = self.bytes.get( offset..offset.checked_add( 8 )? )?;
+        let mut array = [0; 8];
+        array.copy_from_slice( bytes );
+        Some( if self.header.is_little_endian { u64::from_le_bytes( array ) } else { u64::from_be_bytes( array ) } )
+    }
+
+    fn uintptr_at( &self, offset: usize ) -> Option< u64 > {
+        if self.header.pointer_size == 8 {
+            self.u64_at( offset )
+        } else {
+            self.u32_at( offset ).map( |value| value as u64 )
+        }
+    }
+
+    fn string_at( &self, offset: usize ) -> Option< &'a str > {
+        let bytes = self.bytes.get( offset.. )?;
+        let length = bytes.iter().position( |&byte| byte == 0 )?;
+        str::from_utf8( &bytes[ ..length ] ).ok()
+    }
+
+    fn functab_field_size( &self ) -> usize {
+        if self.header.version >= Version::Go118 { 4 } else { self.header.pointer_size }
+    }
+
+    fn functab_field( &self, index: usize ) -> Option< u64 > {
+        let offset = index.checked_mul( self.functab_field_size() )?.checked_add( self.header.functab )?;
+        if self.header.version >= Version::Go118 {
+            self.u32_at( offset ).map( |value| value as u64 )
+        } else {
+            self.uintptr_at( offset )
+        }
+    }
+
+    /// Returns the address at which the nth function starts; the one past the last function is where it ends.
+    fn function_entry( &self, index: usize ) -> Option< u64 > {
+        let entry = self.functab_field( index.checked_mul( 2 )? )?;
+        if self.header.version >= Version::Go118 {
+            entry.checked_add( self.header.text_start )
+        } else {
+            Some( entry )
+        }
+    }
+
+    /// Returns the offset of the `_func` structure of the nth function.
+    fn function_data( &self, index: usize ) -> Option< usize > {
+        let offset = self.functab_field( index.checked_mul( 2 )?.checked_add( 1 )? )?;
+        self.header.funcdata.checked_add( offset as usize )
+    }
+
+    /// Returns the nth 32-bit field of the `_func` structure, not counting the entry.
+    fn function_field( &self, function_data: usize, nth: usize ) -> Option< u32 > {
+        let entry_size = if self.header.version >= Version::Go118 { 4 } else { self.header.pointer_size };
+        self.u32_at( function_data.checked_add( entry_size + (nth - 1) * 4 )? )
+    }
+
+    fn function_name( &self, function_data: usize ) -> Option< &'a str > {
+        let offset = self.function_field( function_data, 1 )?;
+        self.string_at( self.header.funcnametab.checked_add( offset as usize )? )
+    }
+
+    fn find_function( &self, address: u64 ) -> Option< usize > {
+        let count = self.header.function_count;
+        if count == 0 || address < self.function_entry( 0 )? || address >= self.function_entry( count )? {
+            return None;
+        }
+
+        let (mut low, mut high) = (0, count);
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            if self.function_entry( middle )? <= address {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        Some( low )
+    }
+
+    fn read_varint( &self, offset: &mut usize ) -> Option< u32 > {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self.bytes.get( *offset )?;
+            *offset += 1;
+            value |= ((byte & 0x7F) as u32).checked_shl( shift )?;
+            if byte & 0x80 == 0 {
+                return Some( value );
+            }
+            shift += 7;
+        }
+    }
+
+    /// Decodes the value of a pc-value table for a given address.
+    fn pc_value( &self, table_offset: u32, entry: u64, address: u64 ) -> Option< i32 > {
+        let mut offset = self.header.pctab.checked_add( table_offset as usize )?;
+        let mut value: i32 = -1;
+        let mut pc = entry;
+        let mut is_first = true;
+        loop {
+            let delta = self.read_varint( &mut offset )?;
+            if delta == 0 && !is_first {
+                return None;
+            }
+            is_first = false;
+
+            let delta = if delta & 1 != 0 { !(delta >> 1) } else { delta >> 1 };
+            value = value.wrapping_add( delta as i32 );
+            pc = pc.checked_add( self.read_varint( &mut offset )? as u64 * self.header.quantum as u64 )?;
+            if address < pc {
+                return Some( value );
+            }
+        }
+    }
+
+    fn file_name( &self, function_data: usize, file_index: i32 ) -> Option< &'a str > {
+        if self.header.version == Version::Go12 {
+            if file_index <= 0 {
+                return None;
+            }
+
+            let offset = self.u32_at( (file_index as usize).checked_mul( 4 )?.checked_add( self.header.filetab )? )?;
+            return self.string_at( offset as usize );
+        }
+
+        if file_index < 0 {
+            return None;
+        }
+
+        let cu_offset = self.function_field( function_data, 8 )?;
+        let index = (cu_offset as usize).checked_add( file_index as usize )?;
+        let offset = self.u32_at( index.checked_mul( 4 )?.checked_add( self.header.cutab )? )?;
+        if offset == !0 {
+            return None;
+        }
+
+        self.string_at( self.header.filetab.checked_add( offset as usize )? )
+    }
+
+    fn lookup_location( &self, address: u64 ) -> Option< (&'a str, u64) > {
+        let index = self.find_function( address )?;
+        let entry = self.function_entry( index )?;
+        let function_data = self.function_data( index )?;
+
+        let file_index = self.pc_value( self.function_field( function_data, 5 )?, entry, address )?;
+        let line = self.pc_value( self.function_field( function_data, 6 )?, entry, address )?;
+        if line < 0 {
+            return None;
+        }
+
+        let file = self.file_name( function_data, file_index )?;
+        Some( (file, line as u64) )
+    }
+
+    fn lookup_frame( &self, address: u64 ) -> Option< GoFrame > {
+        let index = self.find_function( address )?;
+        let entry = self.function_entry( index )?;
+        let function_data = self.function_data( index )?;
+
+        let sp_offset = self.pc_value( self.function_field( function_data, 4 )?, entry, address )?;
+        if sp_offset < 0 {
+            return None;
+        }
+
+        let name = self.function_name( function_data )?;
+        Some( GoFrame {
+            entry,
+            sp_offset: sp_offset as u64,
+            is_outermost: OUTERMOST_FUNCTIONS.contains( &name )
+        })
+    }
+}
+
+/// The size of the function table, with an entry and a `_func` offset for every function plus the end address.
+fn functab_size( header: &Header ) -> Option< usize > {
+    let field_count = header.function_count.checked_mul( 2 )?.checked_add( 1 )?;
+    field_count.checked_mul( Table { bytes: &[], header }.functab_field_size() )
+}
+
+fn parse_header( bytes: &[u8], endianness: Endianness ) -> Option< Header > {
+    if bytes.len() < 16 || bytes[ 4 ] != 0 || bytes[ 5 ] != 0 {
+        return None;
+    }
+
+    let is_little_endian = endianness == Endianness::LittleEndian;
+    let magic = [bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ]];
+    let magic = if is_little_endian { u32::from_le_bytes( magic ) } else { u32::from_be_bytes( magic ) };
+    let version = match magic {
+        0xfffffffb => Version::Go12,
+        0xfffffffa => Version::Go116,
+        0xfffffff0 => Version::Go118,
+        0xfffffff1 => Version::Go120,
+        _ => return None
+    };
+
+    let quantum = bytes[ 6 ] as u32;
+    let pointer_size = bytes[ 7 ] as usize;
+    if !(quantum == 1 || quantum == 2 || quantum == 4) || !(pointer_size == 4 || pointer_size == 8) {
+        return None;
+    }
+
+    let mut header = Header {
+        version,
+        is_little_endian,
+        quantum,
+        pointer_size,
+        function_count: 0,
+        text_start: 0,
+        functab: 0,
+        funcdata: 0,
+        funcnametab: 0,
+        cutab: 0,
+        filetab: 0,
+        pctab: 0
+    };
+
+    let words = Header { ..header.clone() };
+    let word = |nth: usize| Table { bytes, header: &words }.uintptr_at( 8 + nth * pointer_size );
+
+    match version {
+        Version::Go118 | Version::Go120 => {
+            header.function_count = word( 0 )? as usize;
+            header.text_start = word( 2 )?;
+            header.funcnametab = word( 3 )? as usize;
+            header.cutab = word( 4 )? as usize;
+            header.filetab = word( 5 )? as usize;
+            header.pctab = word( 6 )? as usize;
+            header.funcdata = word( 7 )? as usize;
+            header.functab = header.funcdata;
+        },
+        Version::Go116 => {
+            header.function_count = word( 0 )? as usize;
+            header.funcnametab = word( 2 )? as usize;
+            header.cutab = word( 3 )? as usize;
+            header.filetab = word( 4 )? as usize;
+            header.pctab = word( 5 )? as usize;
+            header.funcdata = word( 6 )? as usize;
+            header.functab = header.funcdata;
+        },
+        Version::Go12 => {
+            header.function_count = word( 0 )? as usize;
+            header.functab = 8 + pointer_size;
+
+            let functab_size = functab_size( &header )?;
+            header.filetab = Table { bytes, header: &header }.u32_at( header.functab.checked_add( functab_size )? )? as usize;
+        }
+    }
+
+    // Make sure the whole function table is there, so that we won't have to check it on every lookup.
+    let functab_end = header.functab.checked_add( functab_size( &header )? )?;
+    if functab_end > bytes.len() {
+        return None;
+    }
+
+    Some( header )
+}
+
+pub struct GoLineTable {
+    data: Arc< BinaryData >,
+    range: Range< usize >,
+    header: Header
+}
+
+impl GoLineTable {
+    pub fn load( data: &Arc< BinaryData > ) -> Option< Self > {
+        let range = data.gopclntab_range()?;
+        let header = match parse_header( &data.as_bytes()[ range.clone() ], data.endianness() ) {
+            Some( header ) => header,
+            None => {
+                warn!( "Failed to parse .gopclntab of '{}'", data.name() );
+                return None;
+            }
+        };
+
+        debug!( "Loaded .gopclntab for '{}' with {} functions ({:?})", data.name(), header.function_count, header.version );
+        Some( GoLineTable {
+            data: data.clone(),
+            range,
+            header
+        })
+    }
+
+    fn table( &self ) -> Table {
+        Table {
+            bytes: &self.data.as_bytes()[ self.range.clone() ],
+            header: &self.header
+        }
+    }
+
+    pub fn data( &self ) -> &Arc< BinaryData > {
+        &self.data
+    }
+
+    /// Goes through every function in the table.
+    pub fn each_function< 'a, F: FnMut( Range< u64 >, &'a str ) >( &'a self, mut callback: F ) {
+        let table = self.table();
+        for index in 0..self.header.function_count {
+            let range = match (table.function_entry( index ), table.function_entry( index + 1 )) {
+                (Some( start ), Some( end )) if start < end => start..end,
+                _ => continue
+            };
+
+            if let Some( name ) = table.function_data( index ).and_then( |function_data| table.function_name( function_data ) ) {
+                callback( range, name );
+            }
+        }
+    }
+
+    /// Returns the file name and the line number of a given address.
+    pub fn lookup_location( &self, address: u64 ) -> Option< (&str, u64) > {
+        self.table().lookup_location( address )
+    }
+
+    /// Returns what's needed to unwind the frame of a function executing at a given address.
+    pub fn lookup_frame( &self, address: u64 ) -> Option< GoFrame > {
+        self.table().lookup_frame( address )
+    }
+}
+
+#[cfg(test)]
+fn build_go120_table() -> Vec< u8 > {
+    // A table with two functions: `main.main` at 0x1000..0x1010 and `main.foo` at 0x1010..0x1040,
+    // with one source file, and with `main.foo` spanning lines 10 and 11.
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice( &0xfffffff1_u32.to_le_bytes() );
+    bytes.extend_from_slice( &[0, 0, 1, 8] );
+
+    let header_size = 8 + 8 * 8;
+    let funcnametab = header_size;
+    let names = b"main.main\0main.foo\0";
+    let cutab = funcnametab + names.len();
+    let filetab = cutab + 4;
+    let files = b"/src/main.go\0";
+    let pctab = filetab + files.len();
+    let pcdata: &[u8] = &[
+        // file for both functions: file #0 for the whole function
+        0x02, 0x40, 0x00,
+        // lines for `main.main`: line 5 for 0x10 bytes
+        0x0c, 0x10, 0x00,
+        // lines for `main.foo`: line 10 for 0x20 bytes, and then line 11 for 0x10 bytes
+        0x16, 0x20, 0x02, 0x10, 0x00,
+        // stack pointer offsets for `main.foo`: 0 for 4 bytes, and then 0x18 for the rest
+        0x02, 0x04, 0x30, 0x2c, 0x00
+    ];
+    let funcdata = pctab + pcdata.len();
+
+    let words = [2, 1, 0x1000, funcnametab, cutab, filetab, pctab, funcdata];
+    for &word in &words {
+        bytes.extend_from_slice( &(word as u64).to_le_bytes() );
+    }
+
+    bytes.extend_from_slice( names );
+    bytes.extend_from_slice( &0_u32.to_le_bytes() );
+    bytes.extend_from_slice( files );
+    bytes.extend_from_slice( pcdata );
+
+    let functab_size = (2 * 2 + 1) * 4;
+    let func_size = 4 + 8 * 4;
+    let functab = [0, functab_size, 0x10, functab_size + func_size, 0x40];
+    for &value in &functab {
+        bytes.extend_from_slice( &(value as u32).to_le_bytes() );
+    }
+
+    let funcs = [(0, 0, 0, 3), (0x10, 10, 11, 6)];
+    for &(entry, name, sp_offsets, lines) in &funcs {
+        let fields = [entry, name, 0, 0, sp_offsets, 0, lines, 0, 0];
+        for &field in &fields {
+            bytes.extend_from_slice( &(field as u32).to_le_bytes() );
+        }
+    }
+
+    bytes
+}
+
+#[test]
+fn test_go120_table() {
+    let bytes = build_go120_table();
+    let header = parse_header( &bytes, Endianness::LittleEndian ).unwrap();
+    assert_eq!( header.version, Version::Go120 );
+    assert_eq!( header.function_count, 2 );
+
+    let table = Table { bytes: &bytes, header: &header };
+    assert_eq!( table.find_function( 0x0fff ), None );
+    assert_eq!( table.find_function( 0x1000 ), Some( 0 ) );
+    assert_eq!( table.find_function( 0x100f ), Some( 0 ) );
+    assert_eq!( table.find_function( 0x1010 ), Some( 1 ) );
+    assert_eq!( table.find_function( 0x1040 ), None );
+
+    assert_eq!( table.function_name( table.function_data( 0 ).unwrap() ), Some( "main.main" ) );
+    assert_eq!( table.function_name( table.function_data( 1 ).unwrap() ), Some( "main.foo" ) );
+
+    assert_eq!( table.lookup_location( 0x1004 ), Some( ("/src/main.go", 5) ) );
+    assert_eq!( table.lookup_location( 0x1010 ), Some( ("/src/main.go", 10) ) );
+    assert_eq!( table.lookup_location( 0x102f ), Some( ("/src/main.go", 10) ) );
+    assert_eq!( table.lookup_location( 0x1030 ), Some( ("/src/main.go", 11) ) );
+
+    assert_eq!( table.lookup_frame( 0x1010 ), Some( GoFrame { entry: 0x1010, sp_offset: 0, is_outermost: false } ) );
+    assert_eq!( table.lookup_frame( 0x1013 ).unwrap().sp_offset, 0 );
+    assert_eq!( table.lookup_frame( 0x1014 ).unwrap().sp_offset, 0x18 );
+    assert_eq!( table.lookup_frame( 0x103f ).unwrap().sp_offset, 0x18 );
+}
+
+#[test]
+fn test_invalid_table() {
+    let mut bytes = build_go120_table();
+    bytes[ 0 ] = 0;
+    assert!( parse_header( &bytes, Endianness::LittleEndian ).is_none() );
+
+    let bytes = build_go120_table();
+    assert!( parse_header( &bytes[ ..100 ], Endianness::LittleEndian ).is_none() );
+}
+
+#[test]
+fn test_overflowing_table() {
+    // The function count is so big that the size of the function table overflows.
+    let mut bytes = build_go120_table();
+    bytes[ 8..16 ].copy_from_slice( &(u64::MAX / 2).to_le_bytes() );
+    assert!( parse_header( &bytes, Endianness::LittleEndian ).is_none() );
+
+    // The offsets in the header are so big that adding anything to them overflows.
+    let overflowing = |range: std::ops::Range< usize >| {
+        let mut bytes = build_go120_table();
+        bytes[ range ].copy_from_slice( &u64::MAX.to_le_bytes() );
+        bytes
+    };
+
+    let bytes = overflowing( 24..32 );
+    let header = parse_header( &bytes, Endianness::LittleEndian ).unwrap();
+    let table = Table { bytes: &bytes, header: &header };
+    assert_eq!( table.function_entry( 1 ), None );
+    assert_eq!( table.lookup_location( 0x1004 ), None );
+
+    let bytes = overflowing( 32..40 );
+    let header = parse_header( &bytes, Endianness::LittleEndian ).unwrap();
+    let table = Table { bytes: &bytes, header: &header };
+    assert_eq!( table.function_name( table.function_data( 1 ).unwrap() ), None );
+    assert!( table.lookup_frame( 0x1020 ).is_none() );
+
+    for range in [40..48, 56..64].iter().cloned() {
+        let bytes = overflowing( range );
+        let header = parse_header( &bytes, Endianness::LittleEndian ).unwrap();
+        let table = Table { bytes: &bytes, header: &header };
+        assert_eq!( table.lookup_location( 0x1020 ), None );
+    }
+}
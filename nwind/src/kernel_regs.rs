@@ -0,0 +1,129 @@
+//! Mapping of the register sets which the kernel hands out (to signal handlers
+//! as an `ucontext_t`, or through `ptrace` as an `user_regs_struct`) to their DWARF numbers.
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn load_ucontext( ucontext: &libc::ucontext_t, mut append: impl FnMut( u16, u64 ) ) {
+    use crate::arch::native::dwarf::*;
+
+    let gregs = &ucontext.uc_mcontext.gregs;
+    let mapping = [
+        (RAX, libc::REG_RAX),
+        (RDX, libc::REG_RDX),
+        (RCX, libc::REG_RCX),
+        (RBX, libc::REG_RBX),
+        (RSI, libc::REG_RSI),
+        (RDI, libc::REG_RDI),
+        (RBP, libc::REG_RBP),
+        (RSP, libc::REG_RSP),
+        (R8, libc::REG_R8),
+        (R9, libc::REG_R9),
+        (R10, libc::REG_R10),
+        (R11, libc::REG_R11),
+        (R12, libc::REG_R12),
+        (R13, libc::REG_R13),
+        (R14, libc::REG_R14),
+        (R15, libc::REG_R15),
+        (RETURN_ADDRESS, libc::REG_RIP)
+    ];
+
+    for &(register, index) in &mapping {
+        append( register, gregs[ index as usize ] as u64 );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn load_ucontext( ucontext: &libc::ucontext_t, mut append: impl FnMut( u16, u64 ) ) {
+    use crate::arch::native::dwarf::*;
+
+    let mcontext = &ucontext.uc_mcontext;
+    for (register, &value) in mcontext.regs.iter().enumerate() {
+        append( X0 + register as u16, value );
+    }
+
+    append( X31, mcontext.sp );
+    append( PC, mcontext.pc );
+}
+
+#[cfg(target_arch = "arm")]
+pub(crate) fn load_ucontext( ucontext: &libc::ucontext_t, mut append: impl FnMut( u16, u64 ) ) {
+    use crate::arch::native::dwarf::*;
+
+    let mcontext = &ucontext.uc_mcontext;
+    let values = [
+        mcontext.arm_r0, mcontext.arm_r1, mcontext.arm_r2, mcontext.arm_r3,
+        mcontext.arm_r4, mcontext.arm_r5, mcontext.arm_r6, mcontext.arm_r7,
+        mcontext.arm_r8, mcontext.arm_r9, mcontext.arm_r10, mcontext.arm_fp,
+        mcontext.arm_ip, mcontext.arm_sp, mcontext.arm_lr, mcontext.arm_pc
+    ];
+
+    for (register, &value) in values.iter().enumerate() {
+        append( R0 + register as u16, value as u64 );
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn load_user_regs( regs: &libc::user_regs_struct, mut append: impl FnMut( u16, u64 ) ) {
+    use crate::arch::native::dwarf::*;
+
+    let mapping = [
+        (RAX, regs.rax),
+        (RDX, regs.rdx),
+        (RCX, regs.rcx),
+        (RBX, regs.rbx),
+        (RSI, regs.rsi),
+        (RDI, regs.rdi),
+        (RBP, regs.rbp),
+        (RSP, regs.rsp),
+        (R8, regs.r8),
+        (R9, regs.r9),
+        (R10, regs.r10),
+        (R11, regs.r11),
+        (R12, regs.r12),
+        (R13, regs.r13),
+        (R14, regs.r14),
+        (R15, regs.r15),
+        (RETURN_ADDRESS, regs.rip)
+    ];
+
+    for &(register, value) in &mapping {
+        append( register, value );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn load_user_regs( regs: &libc::user_regs_struct, mut append: impl FnMut( u16, u64 ) ) {
+    use crate::arch::native::dwarf::*;
+
+    for (register, &value) in regs.regs.iter().enumerate() {
+        append( X0 + register as u16, value );
+    }
+
+    append( X31, regs.sp );
+    append( PC, regs.pc );
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_kernel_regs() {
+    use crate::arch::{amd64, Architecture, Registers};
+    use crate::dwarf_regs::DwarfRegs;
+
+    let mut user_regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+    user_regs.rip = 0x1000;
+    user_regs.rsp = 0x2000;
+    user_regs.rbp = 0x3000;
+    user_regs.orig_rax = 0x4000;
+
+    let regs = DwarfRegs::from_user_regs( &user_regs );
+    assert_eq!( regs.get( amd64::Arch::INSTRUCTION_POINTER_REG ), Some( 0x1000 ) );
+    assert_eq!( regs.get( amd64::Arch::STACK_POINTER_REG ), Some( 0x2000 ) );
+    assert_eq!( regs.get( amd64::dwarf::RBP ), Some( 0x3000 ) );
+    assert_eq!( regs.iter().count(), 17 );
+
+    let mut ucontext: libc::ucontext_t = unsafe { std::mem::zeroed() };
+    ucontext.uc_mcontext.gregs[ libc::REG_RIP as usize ] = 0x1000;
+    ucontext.uc_mcontext.gregs[ libc::REG_RSP as usize ] = 0x2000;
+    let regs = DwarfRegs::from_ucontext( &ucontext );
+    assert_eq!( regs.get( amd64::Arch::INSTRUCTION_POINTER_REG ), Some( 0x1000 ) );
+    assert_eq!( regs.get( amd64::Arch::STACK_POINTER_REG ), Some( 0x2000 ) );
+}
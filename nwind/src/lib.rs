@@ -54,10 +54,18 @@ mod address_space;
 pub mod arch;
 mod arm_extab;
 mod binary;
+mod breakpad;
+mod cfi_dump;
+mod cfi_verify;
 mod dwarf;
 mod dwarf_regs;
+#[cfg(target_os = "linux")]
+mod kernel_regs;
 mod frame_descriptions;
+mod gopclntab;
+mod malformed;
 mod range_map;
+mod snapshot;
 mod symbols;
 mod types;
 pub mod utils;
@@ -65,27 +73,43 @@ mod unwind_context;
 mod debug_info_index;
 #[cfg(feature = "local-unwinding")]
 mod local_unwinding;
+#[cfg(all(feature = "local-unwinding", not(target_arch = "mips64")))]
+mod crash_handler;
+#[cfg(feature = "local-unwinding")]
+mod backtrace;
 
 pub use crate::address_space::{
     BufferReader,
     Primitive,
     IAddressSpace,
     AddressSpace,
+    UnwindIter,
     Frame
 };
 pub use crate::dwarf_regs::DwarfRegs;
 pub use crate::range_map::RangeMap;
 pub use crate::binary::{BinaryData, BinaryDataReader, SymbolTable, LoadHeader};
 pub use crate::symbols::Symbols;
+pub use crate::snapshot::{AddressSpaceSnapshot, write_snapshots, read_snapshots};
 pub use crate::types::{
     Inode,
     Bitness,
     UserFrame,
+    UnwindFailure,
     BinaryId
 };
 
 pub use crate::debug_info_index::DebugInfoIndex;
+pub use crate::breakpad::write_breakpad_symbols;
+pub use crate::cfi_dump::{write_cfi_for_address, write_unwind_tables};
+pub use crate::cfi_verify::{
+    UnwindTableIssue,
+    UnwindTableIssueKind,
+    UnwindTableSummary,
+    verify_unwind_tables
+};
 pub use crate::frame_descriptions::LoadHint;
+pub use crate::malformed::{set_strict_parsing, is_strict_parsing, malformed_structure_count};
 
 #[cfg(feature = "local-unwinding")]
 pub use crate::local_unwinding::{
@@ -94,3 +118,18 @@ pub use crate::local_unwinding::{
     LocalUnwindContext,
     UnwindControl
 };
+
+#[cfg(all(feature = "local-unwinding", not(target_arch = "mips64")))]
+pub use crate::crash_handler::{
+    CrashHandlerOptions,
+    install_crash_handler,
+    install_crash_handler_with_opts
+};
+
+#[cfg(feature = "local-unwinding")]
+pub use crate::backtrace::{
+    Backtrace,
+    BacktraceFrame,
+    BacktraceStatus,
+    BacktraceSymbol
+};
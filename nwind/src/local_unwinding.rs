@@ -561,12 +561,12 @@ impl LocalUnwindContext {
 }
 
 pub struct LocalAddressSpace {
-    regions: RangeMap< BinaryRegion< arch::native::Arch > >,
+    pub(crate) regions: RangeMap< BinaryRegion< arch::native::Arch > >,
     binary_map: HashMap< BinaryId, BinaryHandle< arch::native::Arch > >,
     use_shadow_stack: bool,
     should_load_symbols: bool,
     reload_count: usize,
-    dynamic_fde_registry: DynamicFdeRegistry< gimli::NativeEndian >
+    pub(crate) dynamic_fde_registry: DynamicFdeRegistry< gimli::NativeEndian >
 }
 
 struct LocalRegsInitializer< A: Architecture >( PhantomData< A > );
@@ -948,6 +948,17 @@ impl LocalAddressSpace {
         }
     }
 
+    /// Decodes the symbols for a given address, calling the callback once for every inlined function
+    /// and for the function itself; returning `false` from the callback stops the decoding.
+    pub fn decode_symbol_while< 'a >( &'a self, address: usize, callback: &mut dyn FnMut( &mut Frame< 'a > ) -> bool ) {
+        let address = address as u64;
+        if let Some( region ) = self.regions.get_value( address ) {
+            region.binary().decode_symbol_while( address, callback );
+        } else {
+            callback( &mut Frame::from_address( address, address ) );
+        }
+    }
+
     pub fn decode_symbol_once( &self, address: usize ) -> Frame {
         let address = address as u64;
         if let Some( region ) = self.regions.get_value( address ) {
@@ -0,0 +1,51 @@
+//! Bookkeeping for the malformed ELF and CFI structures found while loading the binaries.
+//!
+//! By default (the lenient mode) such structures are skipped and only counted, since a single
+//! broken FDE or symbol table shouldn't make the rest of the binary unusable; in the strict mode
+//! every one of them is reported with its exact location and fails whatever was being loaded.
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static IS_STRICT: AtomicBool = AtomicBool::new( false );
+static MALFORMED_COUNT: AtomicU64 = AtomicU64::new( 0 );
+
+pub fn set_strict_parsing( value: bool ) {
+    IS_STRICT.store( value, Ordering::Relaxed );
+}
+
+pub fn is_strict_parsing() -> bool {
+    IS_STRICT.load( Ordering::Relaxed )
+}
+
+/// Returns how many malformed structures were found so far, in either mode.
+pub fn malformed_structure_count() -> u64 {
+    MALFORMED_COUNT.load( Ordering::Relaxed )
+}
+
+fn describe( binary: &str, section: &str, offset: u64, message: &dyn fmt::Display ) -> String {
+    format!( "malformed {} in '{}' at offset 0x{:X}: {}", section, binary, offset, message )
+}
+
+/// Records a malformed structure at a given file offset; returns an error only in the strict mode.
+pub(crate) fn report_malformed( binary: &str, section: &str, offset: u64, message: impl fmt::Display ) -> io::Result< () > {
+    MALFORMED_COUNT.fetch_add( 1, Ordering::Relaxed );
+
+    let description = describe( binary, section, offset, &message );
+    if is_strict_parsing() {
+        error!( "{}", description );
+        Err( io::Error::new( io::ErrorKind::InvalidData, description ) )
+    } else {
+        debug!( "{}", description );
+        Ok(())
+    }
+}
+
+#[test]
+fn test_describe() {
+    assert_eq!(
+        describe( "libfoo.so", ".eh_frame", 0x1a2c, &"unexpected end of input" ),
+        "malformed .eh_frame in 'libfoo.so' at offset 0x1A2C: unexpected end of input"
+    );
+}
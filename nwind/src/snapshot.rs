@@ -0,0 +1,270 @@
+//! Snapshots of fully loaded address spaces, which can be written to disk and restored later
+//! (possibly on another machine) without having to find and parse the binaries again.
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+use speedy::{Readable, Writable};
+use proc_maps::Region;
+
+use crate::binary::{BinaryData, LoadHeader, RawBinaryData};
+use crate::frame_descriptions::{AddressMapping, SavedFdeIndex};
+use crate::types::BinaryId;
+
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// The state of an `AddressSpace` as returned by `IAddressSpace::snapshot`.
+pub struct AddressSpaceSnapshot {
+    pub(crate) architecture: String,
+    pub(crate) binaries: Vec< BinarySnapshot >
+}
+
+pub(crate) struct BinarySnapshot {
+    pub id: BinaryId,
+    pub name: String,
+    pub arm_exidx: Option< u64 >,
+    pub arm_extab: Option< u64 >,
+    pub load_headers: Vec< LoadHeader >,
+    pub mappings: Vec< AddressMapping >,
+    pub data: Option< Arc< BinaryData > >,
+    pub debug_data: Option< Arc< BinaryData > >,
+    pub symbols: Vec< Vec< (Range< u64 >, String) > >,
+    /// The already indexed FDEs, so that the unwinding tables don't have to be indexed again.
+    pub frame_descriptions: Option< SavedFdeIndex >,
+    pub has_context: bool,
+    pub has_symbol_decode_cache: bool,
+    pub regions: Vec< Region >
+}
+
+impl AddressSpaceSnapshot {
+    #[inline]
+    pub fn architecture( &self ) -> &str {
+        &self.architecture
+    }
+
+    /// The names of the binaries which are mapped into the address space.
+    pub fn binary_names( &self ) -> impl Iterator< Item = &str > {
+        self.binaries.iter().map( |binary| binary.name.as_str() )
+    }
+}
+
+#[derive(Readable, Writable)]
+struct RawRegion {
+    start: u64,
+    end: u64,
+    is_read: bool,
+    is_write: bool,
+    is_executable: bool,
+    is_shared: bool,
+    file_offset: u64,
+    major: u32,
+    minor: u32,
+    inode: u64,
+    name: String
+}
+
+#[derive(Readable, Writable)]
+struct RawSymbol {
+    range: Range< u64 >,
+    name: String
+}
+
+#[derive(Readable, Writable)]
+struct RawFde {
+    range: Range< u64 >,
+    offset: u64
+}
+
+#[derive(Readable, Writable)]
+struct RawFrameDescriptions {
+    use_eh_frame_hdr: bool,
+    eh_frame: Vec< RawFde >,
+    debug_frame: Vec< RawFde >
+}
+
+#[derive(Readable, Writable)]
+struct RawBinary {
+    id: BinaryId,
+    name: String,
+    arm_exidx: Option< u64 >,
+    arm_extab: Option< u64 >,
+    load_headers: Vec< LoadHeader >,
+    mappings: Vec< AddressMapping >,
+    data: Option< u32 >,
+    debug_data: Option< u32 >,
+    symbols: Vec< Vec< RawSymbol > >,
+    frame_descriptions: Option< RawFrameDescriptions >,
+    has_context: bool,
+    has_symbol_decode_cache: bool,
+    regions: Vec< RawRegion >
+}
+
+#[derive(Readable, Writable)]
+struct RawAddressSpace {
+    key: u32,
+    architecture: String,
+    binaries: Vec< RawBinary >
+}
+
+#[derive(Readable, Writable)]
+struct RawSnapshots< 'a > {
+    version: u32,
+    binary_data: Vec< RawBinaryData< 'a > >,
+    address_spaces: Vec< RawAddressSpace >
+}
+
+fn to_raw_fdes( fdes: &[(Range< u64 >, usize)] ) -> Vec< RawFde > {
+    fdes.iter().map( |(range, offset)| RawFde { range: range.clone(), offset: *offset as u64 } ).collect()
+}
+
+fn from_raw_fdes( fdes: Vec< RawFde > ) -> io::Result< Vec< (Range< u64 >, usize) > > {
+    fdes.into_iter().map( |fde| {
+        if fde.range.start > fde.range.end || fde.offset > usize::MAX as u64 {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "snapshot contains an invalid FDE" ) );
+        }
+
+        Ok( (fde.range, fde.offset as usize) )
+    }).collect()
+}
+
+/// Writes out the snapshots of multiple address spaces, keyed by e.g. the PID of their process;
+/// the binaries which are shared between them are written only once.
+pub fn write_snapshots( snapshots: &[(u32, AddressSpaceSnapshot)], output: &mut dyn io::Write ) -> io::Result< () > {
+    let mut binary_data = Vec::new();
+    let mut index_by_pointer = HashMap::new();
+    for (_, snapshot) in snapshots {
+        for binary in &snapshot.binaries {
+            for data in binary.data.iter().chain( binary.debug_data.iter() ) {
+                index_by_pointer.entry( Arc::as_ptr( data ) ).or_insert_with( || {
+                    binary_data.push( data.to_raw() );
+                    binary_data.len() as u32 - 1
+                });
+            }
+        }
+    }
+
+    let get_index = |data: &Option< Arc< BinaryData > >| data.as_ref().map( |data| index_by_pointer[ &Arc::as_ptr( data ) ] );
+
+    let mut address_spaces = Vec::with_capacity( snapshots.len() );
+    for &(key, ref snapshot) in snapshots {
+        let binaries = snapshot.binaries.iter().map( |binary| RawBinary {
+            id: binary.id.clone(),
+            name: binary.name.clone(),
+            arm_exidx: binary.arm_exidx,
+            arm_extab: binary.arm_extab,
+            load_headers: binary.load_headers.clone(),
+            mappings: binary.mappings.clone(),
+            data: get_index( &binary.data ),
+            debug_data: get_index( &binary.debug_data ),
+            symbols: binary.symbols.iter().map( |symbols| {
+                symbols.iter().map( |(range, name)| RawSymbol { range: range.clone(), name: name.clone() } ).collect()
+            }).collect(),
+            frame_descriptions: binary.frame_descriptions.as_ref().map( |index| RawFrameDescriptions {
+                use_eh_frame_hdr: index.use_eh_frame_hdr,
+                eh_frame: to_raw_fdes( &index.eh_frame ),
+                debug_frame: to_raw_fdes( &index.debug_frame )
+            }),
+            has_context: binary.has_context,
+            has_symbol_decode_cache: binary.has_symbol_decode_cache,
+            regions: binary.regions.iter().map( |region| RawRegion {
+                start: region.start,
+                end: region.end,
+                is_read: region.is_read,
+                is_write: region.is_write,
+                is_executable: region.is_executable,
+                is_shared: region.is_shared,
+                file_offset: region.file_offset,
+                major: region.major,
+                minor: region.minor,
+                inode: region.inode,
+                name: region.name.clone()
+            }).collect()
+        }).collect();
+
+        address_spaces.push( RawAddressSpace {
+            key,
+            architecture: snapshot.architecture.clone(),
+            binaries
+        });
+    }
+
+    let raw = RawSnapshots {
+        version: SNAPSHOT_VERSION,
+        binary_data,
+        address_spaces
+    };
+
+    raw.write_to_stream( output ).map_err( |error| io::Error::new( io::ErrorKind::Other, error ) )
+}
+
+/// Reads the snapshots written by `write_snapshots`.
+pub fn read_snapshots( bytes: &[u8] ) -> io::Result< Vec< (u32, AddressSpaceSnapshot) > > {
+    let raw = RawSnapshots::read_from_buffer_owned( bytes ).map_err( |error| io::Error::new( io::ErrorKind::InvalidData, error ) )?;
+    if raw.version != SNAPSHOT_VERSION {
+        return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "unsupported snapshot version: {}", raw.version ) ) );
+    }
+
+    let binary_data = raw.binary_data.into_iter()
+        .map( |data| BinaryData::from_raw( data ).map( Arc::new ) )
+        .collect::< io::Result< Vec< _ > > >()?;
+
+    let get_data = |index: Option< u32 >| -> io::Result< Option< Arc< BinaryData > > > {
+        match index {
+            Some( index ) => binary_data.get( index as usize ).cloned().map( Some )
+                .ok_or_else( || io::Error::new( io::ErrorKind::InvalidData, "snapshot refers to a nonexistent binary" ) ),
+            None => Ok( None )
+        }
+    };
+
+    let mut output = Vec::with_capacity( raw.address_spaces.len() );
+    for address_space in raw.address_spaces {
+        let mut binaries = Vec::with_capacity( address_space.binaries.len() );
+        for binary in address_space.binaries {
+            binaries.push( BinarySnapshot {
+                id: binary.id,
+                name: binary.name,
+                arm_exidx: binary.arm_exidx,
+                arm_extab: binary.arm_extab,
+                load_headers: binary.load_headers,
+                mappings: binary.mappings,
+                data: get_data( binary.data )?,
+                debug_data: get_data( binary.debug_data )?,
+                symbols: binary.symbols.into_iter().map( |symbols| {
+                    symbols.into_iter().map( |symbol| (symbol.range, symbol.name) ).collect()
+                }).collect(),
+                frame_descriptions: match binary.frame_descriptions {
+                    Some( raw ) => Some( SavedFdeIndex {
+                        use_eh_frame_hdr: raw.use_eh_frame_hdr,
+                        eh_frame: from_raw_fdes( raw.eh_frame )?,
+                        debug_frame: from_raw_fdes( raw.debug_frame )?
+                    }),
+                    None => None
+                },
+                has_context: binary.has_context,
+                has_symbol_decode_cache: binary.has_symbol_decode_cache,
+                regions: binary.regions.into_iter().map( |region| Region {
+                    start: region.start,
+                    end: region.end,
+                    is_read: region.is_read,
+                    is_write: region.is_write,
+                    is_executable: region.is_executable,
+                    is_shared: region.is_shared,
+                    file_offset: region.file_offset,
+                    major: region.major,
+                    minor: region.minor,
+                    inode: region.inode,
+                    name: region.name
+                }).collect()
+            });
+        }
+
+        output.push( (address_space.key, AddressSpaceSnapshot {
+            architecture: address_space.architecture,
+            binaries
+        }));
+    }
+
+    Ok( output )
+}
@@ -9,6 +9,7 @@ use crate::elf::{self, Strtab, Endian};
 use crate::utils::{StableIndex, get_ms};
 use crate::types::{Bitness, Endianness};
 use crate::binary::{BinaryData, SymbolTable};
+use crate::gopclntab::GoLineTable;
 
 trait ByteContainer: StableIndex + Index< Range< u64 >, Output = [u8] > + 'static {}
 impl< T > ByteContainer for T where T: StableIndex + Index< Range< u64 >, Output = [u8] > + 'static {}
@@ -28,7 +29,29 @@ impl Drop for Symbols {
     }
 }
 
-fn load_symbols< 'a, F: FnMut( Range< u64 >, &'a str ) >( architecture: &str, bitness: Bitness, endianness: Endianness, sym_bytes: &[u8], strtab_bytes: &'a [u8], mut callback: F ) {
+/// The names of symbols which didn't come from a symbol table, packed one after another.
+struct NameBuffer( Vec< u8 > );
+
+unsafe impl StableIndex for NameBuffer {}
+
+impl Index< Range< u64 > > for NameBuffer {
+    type Output = [u8];
+
+    #[inline]
+    fn index( &self, index: Range< u64 > ) -> &Self::Output {
+        &self.0[ index.start as usize..index.end as usize ]
+    }
+}
+
+fn is_function( sym: &elf::Sym ) -> bool {
+    sym.is_function()
+}
+
+fn is_object( sym: &elf::Sym ) -> bool {
+    sym.st_type() == elf::STT_OBJECT
+}
+
+fn load_symbols< 'a, F: FnMut( Range< u64 >, &'a str ) >( architecture: &str, bitness: Bitness, endianness: Endianness, sym_bytes: &[u8], strtab_bytes: &'a [u8], is_wanted: fn( &elf::Sym ) -> bool, mut callback: F ) {
     macro_rules! select_branch {
         (if ($condition: expr) { $true_case:expr } else { $false_case:expr } => |$name:ident| $callback:expr) => {
             if $condition {
@@ -55,7 +78,7 @@ fn load_symbols< 'a, F: FnMut( Range< u64 >, &'a str ) >( architecture: &str, bi
             elf::Elf32SymIter::new( sym_bytes, endian )
         } => |syms| {
             for sym in syms {
-                if !sym.is_function() || sym.st_size == 0 || sym.st_value == 0 {
+                if !is_wanted( &sym ) || sym.st_size == 0 || sym.st_value == 0 {
                     continue;
                 }
                 if let Some( Ok( name ) ) = strtab.get( sym.st_name ) {
@@ -90,18 +113,91 @@ impl Symbols {
         )
     }
 
+    /// Loads the function names from the `.gopclntab` of a Go binary.
+    pub fn load_from_gopclntab( table: &GoLineTable ) -> Self {
+        let start_timestamp = Instant::now();
+        let mut symbols: Vec< (Range< u64 >, &str) > = Vec::new();
+        table.each_function( |range, name| symbols.push( (range, name) ) );
+
+        let symbols: Vec< (Range< u64 >, &'static str) > = unsafe { mem::transmute( symbols ) };
+        let elapsed = start_timestamp.elapsed();
+        debug!( "Loaded {} symbols for '{}' from .gopclntab in {}ms", symbols.len(), table.data().name(), get_ms( elapsed ) );
+        Symbols {
+            strtab_owner: ManuallyDrop::new( table.data().clone() ),
+            symbols: ManuallyDrop::new( RangeMap::from_vec( symbols ) )
+        }
+    }
+
+    /// Creates the symbols from a list of address ranges and names, e.g. one which was previously made with `iter`.
+    pub fn from_list( symbols: Vec< (Range< u64 >, String) > ) -> Self {
+        let mut buffer = Vec::with_capacity( symbols.iter().map( |(_, name)| name.len() ).sum() );
+        let mut ranges = Vec::with_capacity( symbols.len() );
+        for (range, name) in symbols {
+            let start = buffer.len();
+            buffer.extend_from_slice( name.as_bytes() );
+            ranges.push( (range, start..buffer.len()) );
+        }
+
+        let buffer = Arc::new( NameBuffer( buffer ) );
+        let symbols: Vec< (Range< u64 >, &str) > = ranges.into_iter().map( |(range, name_range)| {
+            (range, unsafe { str::from_utf8_unchecked( &buffer.0[ name_range ] ) })
+        }).collect();
+
+        let symbols: Vec< (Range< u64 >, &'static str) > = unsafe { mem::transmute( symbols ) };
+        Symbols {
+            strtab_owner: ManuallyDrop::new( buffer ),
+            symbols: ManuallyDrop::new( RangeMap::from_vec( symbols ) )
+        }
+    }
+
+    /// Moves every symbol by a given (wrapping) offset.
+    pub fn with_offset( &self, offset: u64 ) -> Self {
+        Symbols::from_list( self.iter().map( |(range, name)| {
+            (range.start.wrapping_add( offset )..range.end.wrapping_add( offset ), name.to_owned())
+        }).collect() )
+    }
+
     pub fn each_from_binary_data< F: FnMut( Range< u64 >, &str ) >(
         data: &BinaryData,
         mut callback: F
     ) {
+        for symbol_table in data.symbol_tables() {
+            Symbols::each_from_symbol_table( data, symbol_table, &mut callback );
+        }
+    }
+
+    /// Goes through the symbols of a single symbol table of a binary, the same ones which `load` would pick up.
+    pub fn each_from_symbol_table< F: FnMut( Range< u64 >, &str ) >(
+        data: &BinaryData,
+        symbol_table: &SymbolTable,
+        mut callback: F
+    ) {
+        let sym_bytes = &data[ symbol_table.range.clone() ];
+        let strtab_bytes = &data[ symbol_table.strtab_range.clone() ];
+
+        load_symbols( data.architecture(), data.bitness(), data.endianness(), sym_bytes, strtab_bytes, is_function, |range, name| {
+            callback( range, name );
+        });
+    }
+
+    /// Looks up a data object (e.g. a global variable) by its name; returns its address range.
+    pub fn find_object( data: &BinaryData, name: &str ) -> Option< Range< u64 > > {
+        let mut output = None;
         for symbol_table in data.symbol_tables() {
             let sym_bytes = &data[ symbol_table.range.clone() ];
             let strtab_bytes = &data[ symbol_table.strtab_range.clone() ];
-
-            load_symbols( data.architecture(), data.bitness(), data.endianness(), sym_bytes, strtab_bytes, |range, name| {
-                callback( range, name );
+            load_symbols( data.architecture(), data.bitness(), data.endianness(), sym_bytes, strtab_bytes, is_object, |range, symbol_name| {
+                if output.is_none() && symbol_name == name {
+                    output = Some( range );
+                }
             });
+
+            if output.is_some() {
+                break;
+            }
         }
+
+        output
     }
 
     pub fn load< T, S >(
@@ -127,7 +223,7 @@ impl Symbols {
             let strtab_bytes = &strtab_owner[ symbol_table.strtab_range.clone() ];
 
             let count_before = symbols.len();
-            load_symbols( architecture, bitness, endianness, sym_bytes, strtab_bytes, |range, name| {
+            load_symbols( architecture, bitness, endianness, sym_bytes, strtab_bytes, is_function, |range, name| {
                 symbols.push( (range, name) );
             });
 
@@ -156,6 +252,11 @@ impl Symbols {
         &self.symbols
     }
 
+    #[inline]
+    pub fn iter( &self ) -> impl ExactSizeIterator< Item = (Range< u64 >, &str) > {
+        self.as_range_map().iter().map( |(range, name)| (range, *name) )
+    }
+
     #[inline]
     pub fn get_symbol( &self, address: u64 ) -> Option< (Range< u64 >, &str) > {
         self.as_range_map().get( address ).map( |(range, name)| (range, *name) )
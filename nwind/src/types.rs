@@ -1,9 +1,12 @@
+use std::fmt;
+
 use speedy::{Readable, Writable, Context, Reader, Writer};
 
 pub use speedy::Endianness;
 use proc_maps::Region;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Readable, Writable)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub struct Inode {
     pub inode: u64,
     pub dev_major: u32,
@@ -23,12 +26,41 @@ impl Inode {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Readable, Writable)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub enum Bitness {
     B32,
     B64
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+/// The reason why unwinding stopped before reaching the outermost frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Readable, Writable)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnwindFailure {
+    /// The registers needed to start unwinding were missing.
+    MissingRegisters,
+    /// The unwinding needed to read past the end of the captured stack.
+    StackTruncated,
+    /// A frame's address wasn't inside of any mapped binary.
+    UnknownAddress,
+    /// A frame's binary had no usable unwinding information.
+    MissingUnwindInfo
+}
+
+impl fmt::Display for UnwindFailure {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        let reason = match *self {
+            UnwindFailure::MissingRegisters => "missing registers",
+            UnwindFailure::StackTruncated => "stack truncated",
+            UnwindFailure::UnknownAddress => "unknown address",
+            UnwindFailure::MissingUnwindInfo => "missing unwind info"
+        };
+
+        fmt.write_str( reason )
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Hash, Readable, Writable)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryId {
     ByInode( Inode ),
     ByName( String )
@@ -64,6 +96,7 @@ impl Bitness {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub struct UserFrame {
     pub address: u64,
     pub initial_address: Option< u64 >
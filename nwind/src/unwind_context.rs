@@ -10,6 +10,7 @@ pub struct UnwindContext< A: Architecture > {
     regs: A::Regs,
     state: A::State,
     is_done: bool,
+    is_partial: bool,
     panic_on_partial_backtrace: bool,
 
     phantom: PhantomData< A >
@@ -44,6 +45,7 @@ impl< A: Architecture > UnwindContext< A > {
             state: A::initial_state(),
             panic_on_partial_backtrace: false,
             is_done: true,
+            is_partial: false,
             phantom: PhantomData
         }
     }
@@ -62,8 +64,14 @@ impl< A: Architecture > UnwindContext< A > {
         A::clear_cache( &mut self.state );
     }
 
+    #[cfg(feature = "local-unwinding")]
+    pub(crate) fn disable_cache( &mut self ) {
+        A::disable_cache( &mut self.state );
+    }
+
     fn start_impl< 'a, M: MemoryReader< A > >( &'a mut self, memory: &M ) -> UnwindHandle< 'a, A > {
         self.is_done = false;
+        self.is_partial = false;
         self.nth_frame = 0;
 
         self.address = self.regs.get( A::INSTRUCTION_POINTER_REG ).unwrap();
@@ -77,6 +85,7 @@ impl< A: Architecture > UnwindContext< A > {
                 }
 
                 self.is_done = true;
+                self.is_partial = true;
             },
             Some( UnwindStatus::Finished ) => self.is_done = true,
             Some( UnwindStatus::InProgress ) => {}
@@ -109,6 +118,7 @@ impl< 'a, A: Architecture > UnwindHandle< 'a, A > {
                 }
 
                 self.ctx.is_done = true;
+                self.ctx.is_partial = true;
             },
             Some( UnwindStatus::Finished ) => self.ctx.is_done = true,
             Some( UnwindStatus::InProgress ) => {
@@ -129,6 +139,12 @@ impl< 'a, A: Architecture > UnwindHandle< 'a, A > {
         self.ctx.address
     }
 
+    /// Returns whether the unwinding stopped before reaching the outermost frame.
+    #[inline]
+    pub fn is_partial( &self ) -> bool {
+        self.ctx.is_partial
+    }
+
     #[cfg(feature = "local-unwinding")]
     #[inline]
     pub fn next_address_location( &mut self ) -> Option< A::RegTy > {
@@ -0,0 +1,123 @@
+use std::io;
+use std::mem;
+use std::ops;
+use std::os::unix::io::RawFd;
+
+use libc::{self, pid_t, c_void};
+
+use crate::sys::*;
+
+/// The value of a counter, along with for how long the event was enabled and for how long it was
+/// actually running; those differ when there are more events than hardware counters and they have to be multiplexed.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct CounterValue {
+    pub value: u64,
+    pub time_enabled: u64,
+    pub time_running: u64
+}
+
+impl CounterValue {
+    /// The value extrapolated to the whole time the event was enabled.
+    pub fn scaled( &self ) -> u64 {
+        if self.time_running == 0 {
+            return 0;
+        }
+
+        if self.time_running >= self.time_enabled {
+            return self.value;
+        }
+
+        (self.value as u128 * self.time_enabled as u128 / self.time_running as u128) as u64
+    }
+
+    /// The fraction of the time the event was actually being counted.
+    pub fn running_ratio( &self ) -> f64 {
+        if self.time_enabled == 0 {
+            return 1.0;
+        }
+
+        self.time_running as f64 / self.time_enabled as f64
+    }
+}
+
+impl ops::Add for CounterValue {
+    type Output = Self;
+    fn add( self, rhs: Self ) -> Self {
+        CounterValue {
+            value: self.value + rhs.value,
+            time_enabled: self.time_enabled + rhs.time_enabled,
+            time_running: self.time_running + rhs.time_running
+        }
+    }
+}
+
+impl ops::Sub for CounterValue {
+    type Output = Self;
+    fn sub( self, rhs: Self ) -> Self {
+        CounterValue {
+            value: self.value.saturating_sub( rhs.value ),
+            time_enabled: self.time_enabled.saturating_sub( rhs.time_enabled ),
+            time_running: self.time_running.saturating_sub( rhs.time_running )
+        }
+    }
+}
+
+/// Reads a counter opened with the `PERF_FORMAT_TOTAL_TIME_ENABLED` and `PERF_FORMAT_TOTAL_TIME_RUNNING` read format.
+pub(crate) fn read_counter( fd: RawFd ) -> io::Result< CounterValue > {
+    let mut buffer = [0_u64; 3];
+    let result = unsafe {
+        libc::read( fd, buffer.as_mut_ptr() as *mut c_void, mem::size_of_val( &buffer ) )
+    };
+
+    if result < 0 {
+        return Err( io::Error::last_os_error() );
+    }
+
+    Ok( CounterValue {
+        value: buffer[ 0 ],
+        time_enabled: buffer[ 1 ],
+        time_running: buffer[ 2 ]
+    })
+}
+
+/// A perf event which only counts, without gathering any samples.
+#[derive(Debug)]
+pub struct Counter {
+    fd: RawFd
+}
+
+impl Drop for Counter {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.fd );
+        }
+    }
+}
+
+impl Counter {
+    /// Starts counting a given event (e.g. `PERF_TYPE_HARDWARE` and `PERF_COUNT_HW_INSTRUCTIONS`)
+    /// for a given thread, and every thread which it will spawn.
+    pub fn open( pid: u32, kind: u32, config: u64, exclude_kernel: bool ) -> io::Result< Self > {
+        let mut attr: PerfEventAttr = unsafe { mem::zeroed() };
+        attr.size = mem::size_of::< PerfEventAttr >() as u32;
+        attr.kind = kind;
+        attr.config = config;
+        attr.read_format = PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING;
+        attr.flags = PERF_ATTR_FLAG_INHERIT;
+
+        if exclude_kernel {
+            attr.flags |= PERF_ATTR_FLAG_EXCLUDE_KERNEL | PERF_ATTR_FLAG_EXCLUDE_HV;
+        }
+
+        let fd = sys_perf_event_open( &attr, pid as pid_t, -1, -1, PERF_FLAG_FD_CLOEXEC );
+        if fd < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok( Counter { fd } )
+    }
+
+    pub fn read( &self ) -> io::Result< CounterValue > {
+        read_counter( self.fd )
+    }
+}
@@ -1,7 +1,11 @@
 #[macro_use]
 extern crate log;
 
+// Only the parsing of the already gathered data is available on the other platforms.
+#[cfg(unix)]
 mod perf;
+#[cfg(unix)]
+mod counter;
 mod raw_data;
 mod utils;
 
@@ -11,6 +15,10 @@ pub use raw_data::{
     RawRegs
 };
 
+#[cfg(unix)]
+pub use counter::{Counter, CounterValue};
+
+#[cfg(unix)]
 pub use perf::{
     CommEvent,
     Mmap2Event,
@@ -19,5 +27,6 @@ pub use perf::{
     Event,
     EventRef,
     EventSource,
-    Perf
+    Perf,
+    PerfBuilder
 };
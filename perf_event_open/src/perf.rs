@@ -3,7 +3,7 @@ use std::io;
 use std::os::unix::io::RawFd;
 use std::ptr;
 use std::sync::atomic::fence;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::fmt;
 use std::slice;
@@ -15,7 +15,8 @@ use byteorder::{ReadBytesExt, NativeEndian};
 use parking_lot::Mutex;
 
 use crate::sys::*;
-use crate::utils::{HexValue, HexSlice};
+use crate::counter::{CounterValue, read_counter};
+use crate::utils::{HexValue, HexSlice, HexBytes};
 use crate::raw_data::{RawData, RawRegs};
 
 #[derive(Debug)]
@@ -90,7 +91,9 @@ pub struct ProcessEvent {
 pub struct CommEvent {
     pub pid: u32,
     pub tid: u32,
-    pub name: Vec< u8 >
+    pub name: Vec< u8 >,
+    /// Whether the name changed because the process called `exec`.
+    pub is_exec: bool
 }
 
 pub struct Mmap2Event {
@@ -105,7 +108,11 @@ pub struct Mmap2Event {
     pub inode_generation: u64,
     pub protection: u32,
     pub flags: u32,
-    pub filename: Vec< u8 >
+    pub filename: Vec< u8 >,
+    /// The build ID of the mapped file, if the kernel supports it (Linux 5.12+) and it was asked for;
+    /// when the kernel reports a build ID (even an empty one) the `major`, `minor`, `inode`
+    /// and `inode_generation` are all zero.
+    pub build_id: Option< Vec< u8 > >
 }
 
 #[derive(Debug)]
@@ -163,7 +170,8 @@ impl fmt::Debug for CommEvent {
         let mut map = fmt.debug_map();
         map
             .entry( &"pid", &self.pid )
-            .entry( &"tid", &self.tid );
+            .entry( &"tid", &self.tid )
+            .entry( &"is_exec", &self.is_exec );
 
         if let Ok( string ) = str::from_utf8( &self.name ) {
             map.entry( &"name", &string );
@@ -190,6 +198,7 @@ impl fmt::Debug for Mmap2Event {
             .entry( &"protection", &HexValue( self.protection as _ ) )
             .entry( &"flags", &HexValue( self.flags as _ ) )
             .entry( &"filename", &&*String::from_utf8_lossy( &self.filename ) )
+            .entry( &"build_id", &self.build_id.as_ref().map( |build_id| HexBytes( build_id ) ) )
             .finish()
     }
 }
@@ -326,7 +335,8 @@ impl< 'a > RawEvent< 'a > {
                 Event::Comm( CommEvent {
                     pid,
                     tid,
-                    name: name.to_owned()
+                    name: name.to_owned(),
+                    is_exec: self.misc & PERF_RECORD_MISC_COMM_EXEC != 0
                 })
             },
 
@@ -339,10 +349,21 @@ impl< 'a > RawEvent< 'a > {
                 let address = cur.read_u64::< NativeEndian >().unwrap();
                 let length = cur.read_u64::< NativeEndian >().unwrap();
                 let page_offset = cur.read_u64::< NativeEndian >().unwrap();
-                let major = cur.read_u32::< NativeEndian >().unwrap();
-                let minor = cur.read_u32::< NativeEndian >().unwrap();
-                let inode = cur.read_u64::< NativeEndian >().unwrap();
-                let inode_generation = cur.read_u64::< NativeEndian >().unwrap();
+                let (major, minor, inode, inode_generation, build_id) = if self.misc & PERF_RECORD_MISC_MMAP_BUILD_ID != 0 {
+                    // The build ID takes the place of the major, minor, inode and inode generation.
+                    let build_id_size = (cur.read_u8().unwrap() as usize).min( 20 );
+                    let position = cur.position() as usize + 3;
+                    let build_id = raw_data[ position..position + build_id_size ].to_owned();
+                    cur.set_position( (position + 20) as u64 );
+                    let build_id = if build_id.is_empty() { None } else { Some( build_id ) };
+                    (0, 0, 0, 0, build_id)
+                } else {
+                    let major = cur.read_u32::< NativeEndian >().unwrap();
+                    let minor = cur.read_u32::< NativeEndian >().unwrap();
+                    let inode = cur.read_u64::< NativeEndian >().unwrap();
+                    let inode_generation = cur.read_u64::< NativeEndian >().unwrap();
+                    (major, minor, inode, inode_generation, None)
+                };
                 let protection = cur.read_u32::< NativeEndian >().unwrap();
                 let flags = cur.read_u32::< NativeEndian >().unwrap();
                 let name = &raw_data[ cur.position() as usize.. ];
@@ -360,7 +381,8 @@ impl< 'a > RawEvent< 'a > {
                     inode_generation,
                     protection,
                     flags,
-                    filename: name.to_owned()
+                    filename: name.to_owned(),
+                    build_id
                 })
             },
 
@@ -497,18 +519,38 @@ pub enum EventSource {
     SwDummy
 }
 
+impl EventSource {
+    /// Whether this event needs a hardware PMU, which e.g. isn't available in many virtual machines.
+    pub fn is_hardware( self ) -> bool {
+        match self {
+            EventSource::HwCpuCycles | EventSource::HwRefCpuCycles => true,
+            _ => false
+        }
+    }
+}
+
+// Set once the kernel rejects the build ID flag, so that it's not retried for every event.
+static BUILD_IDS_UNSUPPORTED: AtomicBool = AtomicBool::new( false );
+
+fn supports_build_ids() -> bool {
+    !BUILD_IDS_UNSUPPORTED.load( Ordering::Relaxed )
+}
+
 #[derive(Clone, Debug)]
 pub struct PerfBuilder {
     pid: u32,
     cpu: Option< u32 >,
     frequency: u64,
+    period: Option< u64 >,
     stack_size: u32,
+    buffer_size: Option< u32 >,
     reg_mask: u64,
     event_source: EventSource,
     inherit: bool,
     start_disabled: bool,
     exclude_kernel: bool,
-    gather_context_switches: bool
+    gather_context_switches: bool,
+    gather_build_ids: bool
 }
 
 impl PerfBuilder {
@@ -532,11 +574,24 @@ impl PerfBuilder {
         self
     }
 
+    /// Samples every `period` events instead of with a given frequency.
+    pub fn period( mut self, period: u64 ) -> Self {
+        self.period = Some( period );
+        self
+    }
+
     pub fn sample_user_stack( mut self, stack_size: u32 ) -> Self {
         self.stack_size = stack_size;
         self
     }
 
+    /// Sets the size of the ring buffer (in bytes); it gets rounded up to a power of two pages.
+    /// By default it's picked based on the size of the sampled stacks.
+    pub fn buffer_size( mut self, buffer_size: u32 ) -> Self {
+        self.buffer_size = Some( buffer_size );
+        self
+    }
+
     pub fn sample_user_regs( mut self, reg_mask: u64 ) -> Self {
         self.reg_mask = reg_mask;
         self
@@ -568,23 +623,35 @@ impl PerfBuilder {
         self
     }
 
+    /// Asks the kernel to include the build IDs of the mapped files in the `Mmap2` events.
+    ///
+    /// This is only supported on Linux 5.12 and newer; on older kernels it's silently ignored.
+    pub fn gather_build_ids( mut self ) -> Self {
+        self.gather_build_ids = true;
+        self
+    }
+
     pub fn open( self ) -> io::Result< Perf > {
         let pid = self.pid;
         let cpu = self.cpu.map( |cpu| cpu as i32 ).unwrap_or( -1 );
         let frequency = self.frequency;
+        let period = self.period;
         let stack_size = self.stack_size;
+        let buffer_size = self.buffer_size;
         let reg_mask = self.reg_mask;
         let event_source = self.event_source;
         let inherit = self.inherit;
         let start_disabled = self.start_disabled;
         let exclude_kernel = self.exclude_kernel;
         let gather_context_switches = self.gather_context_switches;
+        let gather_build_ids = self.gather_build_ids;
 
         debug!(
-            "Opening perf events; pid={}, cpu={}, frequency={}, stack_size={}, reg_mask=0x{:016X}, event_source={:?}, inherit={}, start_disabled={}...",
+            "Opening perf events; pid={}, cpu={}, frequency={}, period={:?}, stack_size={}, reg_mask=0x{:016X}, event_source={:?}, inherit={}, start_disabled={}...",
             pid,
             cpu,
             frequency,
+            period,
             stack_size,
             reg_mask,
             event_source,
@@ -595,7 +662,7 @@ impl PerfBuilder {
         let max_sample_rate = Perf::max_sample_rate();
         if let Some( max_sample_rate ) = max_sample_rate {
             debug!( "Maximum sample rate: {}", max_sample_rate );
-            if frequency > max_sample_rate {
+            if period.is_none() && frequency > max_sample_rate {
                 let message = format!( "frequency can be at most {} as configured in /proc/sys/kernel/perf_event_max_sample_rate", max_sample_rate );
                 return Err( io::Error::new( io::ErrorKind::InvalidInput, message ) );
             }
@@ -660,9 +727,10 @@ impl PerfBuilder {
             attr.sample_type |= PERF_SAMPLE_STACK_USER;
         }
 
+        attr.read_format = PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING;
         attr.sample_regs_user = reg_mask;
         attr.sample_stack_user = stack_size;
-        attr.sample_period_or_freq = frequency;
+        attr.sample_period_or_freq = period.unwrap_or( frequency );
 
         attr.flags =
             PERF_ATTR_FLAG_DISABLED |
@@ -670,10 +738,14 @@ impl PerfBuilder {
             PERF_ATTR_FLAG_MMAP2 |
             PERF_ATTR_FLAG_MMAP_DATA |
             PERF_ATTR_FLAG_COMM |
-            PERF_ATTR_FLAG_FREQ |
+            PERF_ATTR_FLAG_COMM_EXEC |
             PERF_ATTR_FLAG_EXCLUDE_CALLCHAIN_USER |
             PERF_ATTR_FLAG_TASK;
 
+        if period.is_none() {
+            attr.flags |= PERF_ATTR_FLAG_FREQ;
+        }
+
         if exclude_kernel {
             attr.flags |= PERF_ATTR_FLAG_EXCLUDE_KERNEL;
         }
@@ -686,10 +758,21 @@ impl PerfBuilder {
             attr.flags |= PERF_ATTR_FLAG_CONTEX_SWITCH;
         }
 
-        let fd = sys_perf_event_open( &attr, pid as pid_t, cpu as _, -1, PERF_FLAG_FD_CLOEXEC );
+        if gather_build_ids && supports_build_ids() {
+            attr.flags |= PERF_ATTR_FLAG_BUILD_ID;
+        }
+
+        let mut fd = sys_perf_event_open( &attr, pid as pid_t, cpu as _, -1, PERF_FLAG_FD_CLOEXEC );
+        if fd == -libc::EINVAL && attr.flags & PERF_ATTR_FLAG_BUILD_ID != 0 {
+            debug!( "The kernel doesn't support build IDs in the mmap events; retrying without them..." );
+            BUILD_IDS_UNSUPPORTED.store( true, Ordering::Relaxed );
+            attr.flags &= !PERF_ATTR_FLAG_BUILD_ID;
+            fd = sys_perf_event_open( &attr, pid as pid_t, cpu as _, -1, PERF_FLAG_FD_CLOEXEC );
+        }
+
         if fd < 0 {
             let err = io::Error::from_raw_os_error( -fd );
-            error!( "The perf_event_open syscall failed for PID {}: {}", pid, err );
+            debug!( "The perf_event_open syscall failed for PID {}: {}", pid, err );
             if let Some( errcode ) = err.raw_os_error() {
                 if errcode == libc::EINVAL {
                     info!( "Your profiling frequency might be too high; try lowering it" );
@@ -702,7 +785,24 @@ impl PerfBuilder {
         let required_space = max( stack_size, 4096 ) * 8;
         let page_size = 4096;
         let n = (1..26).into_iter().find( |n| (1_u32 << n) * 4096_u32 >= required_space ).expect( "cannot find appropriate page count for given stack size" );
-        let page_count: u32 = max( 1 << n, 16 );
+        let mut page_count: u32 = max( 1 << n, 16 );
+        if let Some( buffer_size ) = buffer_size {
+            // The ring buffer must have a power of two pages, and has to be able to fit at least a single sample.
+            let requested_page_count = ((buffer_size as u64 + page_size as u64 - 1) / page_size as u64).next_power_of_two();
+            if requested_page_count < (1 << n) {
+                warn!( "The requested buffer size of {} bytes is too small for the stack size; using {} bytes instead", buffer_size, (1_u32 << n) * page_size );
+                page_count = 1 << n;
+            } else if requested_page_count > (1 << 25) {
+                unsafe {
+                    libc::close( fd );
+                }
+
+                return Err( io::Error::new( io::ErrorKind::InvalidInput, "the buffer size is too big" ) );
+            } else {
+                page_count = requested_page_count as u32;
+            }
+        }
+
         debug!( "Allocating {} + 1 pages for the ring buffer for PID {} on CPU {}", page_count, pid, cpu );
 
         let full_size = (page_size * (page_count + 1)) as usize;
@@ -711,8 +811,13 @@ impl PerfBuilder {
         unsafe {
             buffer = libc::mmap( ptr::null_mut(), full_size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0 );
             if buffer == libc::MAP_FAILED {
+                let err = io::Error::last_os_error();
                 libc::close( fd );
-                return Err( io::Error::new( io::ErrorKind::Other, "mmap failed" ) );
+                if err.raw_os_error() == Some( libc::EPERM ) {
+                    info!( "The ring buffer might be bigger than what's allowed by /proc/sys/kernel/perf_event_mlock_kb; try a smaller buffer size" );
+                }
+
+                return Err( io::Error::new( err.kind(), format!( "mmap failed: {}", err ) ) );
             }
         }
 
@@ -750,13 +855,16 @@ impl Perf {
             pid: 0,
             cpu: None,
             frequency: 0,
+            period: None,
             stack_size: 0,
+            buffer_size: None,
             reg_mask: 0,
             event_source: EventSource::SwCpuClock,
             inherit: false,
             start_disabled: false,
             exclude_kernel: true,
-            gather_context_switches: false
+            gather_context_switches: false,
+            gather_build_ids: false
         }
     }
 
@@ -808,6 +916,11 @@ impl Perf {
         self.poll( 0 ) & libc::POLLHUP != 0
     }
 
+    /// Reads the total count of the event, and for how long it was enabled and running.
+    pub fn read_counter( &self ) -> io::Result< CounterValue > {
+        read_counter( self.fd )
+    }
+
     #[inline]
     pub fn fd( &self ) -> RawFd {
         self.fd
@@ -1,6 +1,7 @@
 #![allow(unused)]
 
 use std::fmt;
+#[cfg(unix)]
 use libc::{
     syscall,
     c_int,
@@ -18,6 +19,7 @@ macro_rules! flag {
     ($nth:expr) => {1 << $nth}
 }
 
+#[cfg(unix)]
 pub const PERF_FLAG_FD_CLOEXEC: c_ulong = 1 << 3;
 
 pub const PERF_TYPE_HARDWARE: u32 = 0;
@@ -49,15 +51,31 @@ pub const PERF_ATTR_FLAG_MMAP2: u64                     = flag!( 23 );
 pub const PERF_ATTR_FLAG_COMM_EXEC: u64                 = flag!( 24 );
 pub const PERF_ATTR_FLAG_USE_CLOCKID: u64               = flag!( 25 );
 pub const PERF_ATTR_FLAG_CONTEX_SWITCH: u64             = flag!( 26 );
+pub const PERF_ATTR_FLAG_BUILD_ID: u64                  = flag!( 34 );
 
 pub const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+pub const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+pub const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+pub const PERF_COUNT_HW_BRANCH_INSTRUCTIONS: u64 = 4;
+pub const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+pub const PERF_COUNT_HW_BUS_CYCLES: u64 = 6;
+pub const PERF_COUNT_HW_STALLED_CYCLES_FRONTEND: u64 = 7;
+pub const PERF_COUNT_HW_STALLED_CYCLES_BACKEND: u64 = 8;
 pub const PERF_COUNT_HW_REF_CPU_CYCLES: u64 = 9;
 
 pub const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
 pub const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
 pub const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+pub const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+pub const PERF_COUNT_SW_CPU_MIGRATIONS: u64 = 4;
+pub const PERF_COUNT_SW_PAGE_FAULTS_MIN: u64 = 5;
+pub const PERF_COUNT_SW_PAGE_FAULTS_MAJ: u64 = 6;
 pub const PERF_COUNT_SW_DUMMY: u64 = 9;
 
+pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
 pub const PERF_RECORD_LOST: u32 = 2;
 pub const PERF_RECORD_COMM: u32 = 3;
 pub const PERF_RECORD_EXIT: u32 = 4;
@@ -68,8 +86,10 @@ pub const PERF_RECORD_SAMPLE: u32 = 9;
 pub const PERF_RECORD_MMAP2: u32 = 10;
 pub const PERF_RECORD_SWITCH: u32 = 14;
 
+pub const PERF_RECORD_MISC_COMM_EXEC: u16 = 1 << 13;
 pub const PERF_RECORD_MISC_SWITCH_OUT: u16 = 1 << 13;
 pub const PERF_RECORD_MISC_SWITCH_OUT_PREEMPT: u16 = 1 << 14;
+pub const PERF_RECORD_MISC_MMAP_BUILD_ID: u16 = 1 << 14;
 
 pub const PERF_SAMPLE_IP: u64              = 1 << 0;
 pub const PERF_SAMPLE_TID: u64             = 1 << 1;
@@ -207,6 +227,7 @@ pub const PERF_REG_ARM64_MAX: u64 = 33;
 pub const PERF_SAMPLE_REGS_ABI_32: u64 = 1;
 pub const PERF_SAMPLE_REGS_ABI_64: u64 = 2;
 
+#[cfg(unix)]
 mod ioctl {
     use libc::c_ulong;
 
@@ -253,7 +274,9 @@ macro_rules! io {
     }
 }
 
+#[cfg(unix)]
 pub const PERF_EVENT_IOC_ENABLE: c_ulong = io!( b'$', 0 );
+#[cfg(unix)]
 pub const PERF_EVENT_IOC_DISABLE: c_ulong = io!( b'$', 1 );
 
 #[repr(C)]
@@ -371,6 +394,7 @@ const PERF_EVENT_OPEN: usize = 5292;
 #[cfg(target_arch = "aarch64")]
 const PERF_EVENT_OPEN: usize = 241;
 
+#[cfg(unix)]
 pub fn sys_perf_event_open( attr: &PerfEventAttr, pid: pid_t, cpu: c_int, group_fd: c_int, flags: c_ulong ) -> c_int {
     unsafe {
         syscall( PERF_EVENT_OPEN as _, attr as *const _, pid, cpu, group_fd, flags ) as c_int
@@ -13,3 +13,14 @@ impl< 'a > fmt::Debug for HexSlice< 'a > {
         fmt.debug_list().entries( self.0.iter().map( |&value| HexValue( value ) ) ).finish()
     }
 }
+
+pub struct HexBytes< 'a >( pub &'a [u8] );
+impl< 'a > fmt::Debug for HexBytes< 'a > {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> Result< (), fmt::Error > {
+        for byte in self.0 {
+            write!( fmt, "{:02x}", byte )?;
+        }
+
+        Ok(())
+    }
+}
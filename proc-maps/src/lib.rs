@@ -1,4 +1,5 @@
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serialization", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     pub start: u64,
     pub end: u64,
@@ -0,0 +1,221 @@
+//! Python bindings for reading the recordings made by `nperf record`.
+//!
+//! Every function takes the path to a recording and, optionally, a list of extra
+//! arguments which are interpreted exactly like the command line arguments
+//! of the corresponding subcommand, e.g. `["--debug-symbols", "/usr/lib/debug"]`.
+
+use std::error::Error;
+use std::iter;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::vec;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use structopt::StructOpt;
+
+use nperf_core::{args, cmd_collate, cmd_metadata, reader};
+
+fn to_py_error( error: Box< dyn Error > ) -> PyErr {
+    PyRuntimeError::new_err( error.to_string() )
+}
+
+fn parse_args< T: StructOpt >( path: &str, args: Option< Vec< String > > ) -> PyResult< T > {
+    let argv = iter::once( "nperf".to_owned() ).chain( iter::once( path.to_owned() ) ).chain( args.unwrap_or_default() );
+    T::from_iter_safe( argv ).map_err( |error| PyValueError::new_err( error.message ) )
+}
+
+/// A single symbolized frame of a sample.
+#[pyclass(name = "Frame", get_all)]
+#[derive(Clone)]
+struct Frame {
+    name: String,
+    binary: Option< String >,
+    address: Option< u64 >,
+    file: Option< String >,
+    line: Option< u64 >,
+    is_inline: bool,
+    is_kernel: bool
+}
+
+#[pymethods]
+impl Frame {
+    fn __repr__( &self ) -> String {
+        match self.binary {
+            Some( ref binary ) => format!( "<Frame {} [{}]>", self.name, binary ),
+            None => format!( "<Frame {}>", self.name )
+        }
+    }
+}
+
+/// A single symbolized sample; the frames are ordered from the innermost one.
+#[pyclass(name = "Sample", get_all)]
+#[derive(Clone)]
+struct Sample {
+    timestamp: u64,
+    pid: u32,
+    tid: u32,
+    cpu: u32,
+    executable: String,
+    thread_name: Option< String >,
+    frames: Vec< Frame >
+}
+
+#[pymethods]
+impl Sample {
+    fn __repr__( &self ) -> String {
+        format!( "<Sample timestamp={} pid={} tid={} frames={}>", self.timestamp, self.pid, self.tid, self.frames.len() )
+    }
+}
+
+impl From< reader::Sample > for Sample {
+    fn from( sample: reader::Sample ) -> Self {
+        let frames = sample.frames.into_iter().map( |frame| Frame {
+            name: frame.name,
+            binary: frame.binary,
+            address: frame.address,
+            file: frame.file,
+            line: frame.line,
+            is_inline: frame.is_inline,
+            is_kernel: frame.is_kernel
+        }).collect();
+
+        Sample {
+            timestamp: sample.timestamp,
+            pid: sample.pid,
+            tid: sample.tid,
+            cpu: sample.cpu,
+            executable: sample.executable,
+            thread_name: sample.thread_name,
+            frames
+        }
+    }
+}
+
+/// How many samples are sent at once from the thread which reads the recording.
+const CHUNK_SIZE: usize = 256;
+
+/// Lazily reads the samples on a background thread, which is kept at most a few chunks ahead.
+#[pyclass]
+struct SampleIterator {
+    chunk: vec::IntoIter< reader::Sample >,
+    receiver: mpsc::Receiver< Result< Vec< reader::Sample >, String > >
+}
+
+#[pymethods]
+impl SampleIterator {
+    fn __iter__( slf: PyRef< Self > ) -> PyRef< Self > {
+        slf
+    }
+
+    fn __next__( mut slf: PyRefMut< Self >, py: Python ) -> PyResult< Option< Sample > > {
+        loop {
+            if let Some( sample ) = slf.chunk.next() {
+                return Ok( Some( Sample::from( sample ) ) );
+            }
+
+            let receiver = &mut slf.receiver;
+            match py.allow_threads( move || receiver.recv() ) {
+                Ok( Ok( chunk ) ) => slf.chunk = chunk.into_iter(),
+                Ok( Err( error ) ) => return Err( PyRuntimeError::new_err( error ) ),
+                Err( mpsc::RecvError ) => return Ok( None )
+            }
+        }
+    }
+}
+
+/// Returns an iterator over the symbolized samples of a recording; accepts the same
+/// extra arguments as `nperf collate` (e.g. `--debug-symbols` or `--only-matching`).
+///
+/// The recording is read lazily while iterating, so any errors are raised from `__next__`.
+#[pyfunction]
+#[pyo3(text_signature = "(path, args=None)")]
+fn samples( path: &str, args: Option< Vec< String > > ) -> PyResult< SampleIterator > {
+    let args: args::SharedCollationArgs = parse_args( path, args )?;
+    let (sender, receiver) = mpsc::sync_channel( 2 );
+    thread::spawn( move || {
+        let mut chunk = Vec::with_capacity( CHUNK_SIZE );
+        let mut is_closed = false;
+        let result = reader::for_each_sample( &args, |sample| {
+            // The iterator was dropped, so there's no point in keeping the samples.
+            if is_closed {
+                return;
+            }
+
+            chunk.push( sample );
+            if chunk.len() == CHUNK_SIZE {
+                let chunk = mem::replace( &mut chunk, Vec::with_capacity( CHUNK_SIZE ) );
+                is_closed = sender.send( Ok( chunk ) ).is_err();
+            }
+        });
+
+        let _ = match result {
+            Ok(()) => sender.send( Ok( chunk ) ),
+            Err( error ) => sender.send( Err( error.to_string() ) )
+        };
+    });
+
+    Ok( SampleIterator {
+        chunk: Vec::new().into_iter(),
+        receiver
+    })
+}
+
+/// Returns the same metadata as `nperf metadata`, as a dict.
+#[pyfunction]
+#[pyo3(text_signature = "(path)")]
+fn metadata( py: Python, path: &str ) -> PyResult< PyObject > {
+    let args: args::MetadataArgs = parse_args( path, None )?;
+    let metadata = cmd_metadata::generate_metadata( args ).map_err( to_py_error )?;
+    let metadata = serde_json::to_string( &metadata ).unwrap();
+    let metadata = py.import( "json" )?.call_method1( "loads", (metadata,) )?;
+    Ok( metadata.into() )
+}
+
+/// Returns the collapsed stacks of a recording as a dict mapping the stacks to the number
+/// of their samples; accepts the same extra arguments as `nperf collate` (e.g. `--granularity function`).
+#[pyfunction]
+#[pyo3(text_signature = "(path, args=None)")]
+fn collapse( py: Python, path: &str, args: Option< Vec< String > > ) -> PyResult< PyObject > {
+    let args: args::CollateArgs = parse_args( path, args )?;
+    let lines = py.allow_threads( || {
+        cmd_collate::collapse_into_sorted_vec(
+            &args.collation_args,
+            &args.arg_granularity,
+            &args.arg_merge_threads,
+            &args.arg_mark_truncated,
+            &args.arg_group_by,
+            Some( &args.arg_quality_thresholds )
+        ).map_err( |error| error.to_string() )
+    }).map_err( PyRuntimeError::new_err )?;
+
+    let output = PyDict::new( py );
+    for line in lines {
+        let index = match line.rfind( ' ' ) {
+            Some( index ) => index,
+            None => continue
+        };
+
+        let count: u64 = match line[ index + 1.. ].parse() {
+            Ok( count ) => count,
+            Err( _ ) => continue
+        };
+
+        output.set_item( &line[ ..index ], count )?;
+    }
+
+    Ok( output.into() )
+}
+
+#[pymodule]
+fn nperf( _py: Python, module: &PyModule ) -> PyResult< () > {
+    module.add_class::< Frame >()?;
+    module.add_class::< Sample >()?;
+    module.add_class::< SampleIterator >()?;
+    module.add_function( wrap_pyfunction!( samples, module )? )?;
+    module.add_function( wrap_pyfunction!( metadata, module )? )?;
+    module.add_function( wrap_pyfunction!( collapse, module )? )?;
+    Ok(())
+}
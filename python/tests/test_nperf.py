@@ -0,0 +1,39 @@
+import os
+
+import pytest
+
+import nperf
+
+RECORDING = os.path.join(os.path.dirname(__file__), "..", "..", "test-data", "artifacts", "amd64-pthread_cond_wait.nperf")
+
+
+def test_samples():
+    samples = list(nperf.samples(RECORDING))
+    assert samples
+
+    for sample in samples:
+        assert isinstance(sample, nperf.Sample)
+        assert sample.pid > 0
+        assert sample.frames
+
+    stacks = nperf.collapse(RECORDING)
+    assert len(samples) == sum(stacks.values())
+
+
+def test_samples_can_be_abandoned():
+    iterator = nperf.samples(RECORDING)
+    first = next(iterator)
+    del iterator
+
+    assert first.timestamp == next(nperf.samples(RECORDING)).timestamp
+
+
+def test_samples_errors_are_raised_when_iterating():
+    iterator = nperf.samples("/nonexistent.nperf")
+    with pytest.raises(RuntimeError):
+        next(iterator)
+
+
+def test_samples_invalid_arguments():
+    with pytest.raises(ValueError):
+        nperf.samples(RECORDING, ["--no-such-argument"])
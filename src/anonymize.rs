@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use nwind::RangeMap;
+
+use crate::kallsyms::KernelSymbol;
+use crate::utils::fnv1a;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Anonymization {
+    /// Every name is replaced with its hash, so the same name gets the same replacement in every profile.
+    ///
+    /// The hash is unsalted, so anyone who can guess a name (e.g. of a well known library or function)
+    /// can check whether it's in the profile by hashing it.
+    Hash,
+    /// Every name is replaced with a sequential number, so nothing about the original names is left.
+    Strip
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum NameKind {
+    Symbol,
+    Binary,
+    SourceFile,
+    Process,
+    Thread,
+    Cgroup,
+    KernelModule
+}
+
+impl NameKind {
+    fn prefix( self ) -> &'static str {
+        match self {
+            NameKind::Symbol => "fn",
+            NameKind::Binary => "bin",
+            NameKind::SourceFile => "src",
+            NameKind::Process => "proc",
+            NameKind::Thread => "thread",
+            NameKind::Cgroup => "cgroup",
+            NameKind::KernelModule => "module"
+        }
+    }
+}
+
+/// Replaces the names of the symbols, binaries, source files, processes, threads and cgroups
+/// so that the profiles can be shared without revealing the names used in the profiled software,
+/// while keeping the shape of the callstacks intact. Only the names are replaced, so e.g. the number
+/// of the threads, the sizes of the stacks or the timings are still there.
+pub(crate) struct Anonymizer {
+    mode: Anonymization,
+    names: RefCell< HashMap< (NameKind, String), String > >,
+    counters: RefCell< HashMap< NameKind, u64 > >
+}
+
+impl Anonymizer {
+    pub fn new( mode: Anonymization ) -> Self {
+        Anonymizer {
+            mode,
+            names: RefCell::new( HashMap::new() ),
+            counters: RefCell::new( HashMap::new() )
+        }
+    }
+
+    pub fn anonymize( &self, kind: NameKind, name: &str ) -> String {
+        if name.is_empty() {
+            return String::new();
+        }
+
+        let mut names = self.names.borrow_mut();
+        if let Some( anonymized ) = names.get( &(kind, name.to_owned()) ) {
+            return anonymized.clone();
+        }
+
+        let anonymized = match self.mode {
+            Anonymization::Hash => format!( "{}_{:012x}", kind.prefix(), fnv1a( name.as_bytes() ) >> 16 ),
+            Anonymization::Strip => {
+                let mut counters = self.counters.borrow_mut();
+                let counter = counters.entry( kind ).or_insert( 0 );
+                *counter += 1;
+                format!( "{}_{}", kind.prefix(), counter )
+            }
+        };
+
+        names.insert( (kind, name.to_owned()), anonymized.clone() );
+        anonymized
+    }
+
+    /// The symbols from the kernel itself are public so they're left alone; only the ones from the modules,
+    /// which might be proprietary, are anonymized.
+    pub fn anonymize_kallsyms( &self, kallsyms: RangeMap< KernelSymbol > ) -> RangeMap< KernelSymbol > {
+        let symbols = kallsyms.iter().map( |(range, symbol)| {
+            let symbol = match symbol.module {
+                Some( ref module ) => KernelSymbol {
+                    address: symbol.address,
+                    name: self.anonymize( NameKind::Symbol, &symbol.name ),
+                    module: Some( self.anonymize( NameKind::KernelModule, module ) )
+                },
+                None => symbol.clone()
+            };
+
+            (range, symbol)
+        }).collect();
+
+        RangeMap::from_vec( symbols )
+    }
+}
+
+/// Anonymizes a name if an anonymizer is given, and returns it unchanged otherwise.
+pub(crate) fn anonymize_if< 'a >( anonymizer: Option< &Anonymizer >, kind: NameKind, name: &'a str ) -> Cow< 'a, str > {
+    match anonymizer {
+        Some( anonymizer ) => anonymizer.anonymize( kind, name ).into(),
+        None => name.into()
+    }
+}
+
+#[test]
+fn test_anonymizer() {
+    let anonymizer = Anonymizer::new( Anonymization::Hash );
+    let foo = anonymizer.anonymize( NameKind::Symbol, "foo" );
+    assert!( foo.starts_with( "fn_" ) );
+    assert_eq!( foo.len(), "fn_".len() + 12 );
+    assert_eq!( anonymizer.anonymize( NameKind::Symbol, "foo" ), foo );
+    assert_eq!( Anonymizer::new( Anonymization::Hash ).anonymize( NameKind::Symbol, "foo" ), foo );
+    assert_ne!( anonymizer.anonymize( NameKind::Symbol, "bar" ), foo );
+    assert_eq!( anonymizer.anonymize( NameKind::Symbol, "" ), "" );
+
+    let anonymizer = Anonymizer::new( Anonymization::Strip );
+    assert_eq!( anonymizer.anonymize( NameKind::Symbol, "foo" ), "fn_1" );
+    assert_eq!( anonymizer.anonymize( NameKind::Symbol, "bar" ), "fn_2" );
+    assert_eq!( anonymizer.anonymize( NameKind::Symbol, "foo" ), "fn_1" );
+    assert_eq!( anonymizer.anonymize( NameKind::Binary, "/usr/lib/libfoo.so" ), "bin_1" );
+    assert_eq!( anonymize_if( None, NameKind::Thread, "worker" ), "worker" );
+}
@@ -9,6 +9,7 @@ pub use nwind::{
     Inode,
     Bitness,
     UserFrame,
+    UnwindFailure,
     LoadHeader
 };
 
@@ -45,6 +46,17 @@ impl< 'a, C: Context > Writable< C > for DwarfReg {
     }
 }
 
+/// A frame of a Python stack sampled from a CPython interpreter.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Readable, Writable)]
+pub struct PythonFrame {
+    /// The `id` of the `PythonFunction` which describes the function.
+    pub function: u64,
+    /// The line which was being executed; zero if unknown.
+    pub line: u32,
+    /// Whether the frame is the outermost one evaluated by a given invocation of the interpreter loop.
+    pub is_entry: bool
+}
+
 pub const ARCHIVE_MAGIC: u32 = 0x4652504E;
 pub const ARCHIVE_VERSION: u32 = 1;
 
@@ -55,6 +67,14 @@ pub enum ContextSwitchKind {
     OutWhileRunning
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Readable, Writable)]
+pub enum StallKind {
+    /// The thread's stack didn't change for the whole duration.
+    SameStack,
+    /// The thread was runnable, but didn't get any CPU time for the whole duration.
+    NoProgress
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Readable, Writable)]
 pub enum Packet< 'a > {
@@ -71,7 +91,9 @@ pub enum Packet< 'a > {
     ProcessInfo {
         pid: u32,
         executable: Cow< 'a, [u8] >,
-        binary_id: Inode
+        binary_id: Inode,
+        #[speedy(default_on_eof)]
+        cgroup: Cow< 'a, [u8] >
     },
     Sample {
         timestamp: u64,
@@ -79,7 +101,12 @@ pub enum Packet< 'a > {
         tid: u32,
         cpu: u32,
         kernel_backtrace: Cow< 'a, [u64] >,
-        user_backtrace: Cow< 'a, [UserFrame] >
+        user_backtrace: Cow< 'a, [UserFrame] >,
+        #[speedy(default_on_eof)]
+        unwind_failure: Option< UnwindFailure >,
+        /// The Python stack of the thread, innermost first; only gathered with `--python`.
+        #[speedy(default_on_eof)]
+        python_backtrace: Cow< 'a, [PythonFrame] >
     },
     BinaryInfo {
         inode: Inode,
@@ -88,7 +115,13 @@ pub enum Packet< 'a > {
         path: Cow< 'a, [u8] >,
         debuglink: Cow< 'a, [u8] >,
         #[speedy(default_on_eof)]
-        load_headers: Cow< 'a, [LoadHeader] >
+        load_headers: Cow< 'a, [LoadHeader] >,
+        /// The size of the file; zero if unknown.
+        #[speedy(default_on_eof)]
+        file_size: u64,
+        /// The modification time of the file, in seconds since the epoch; zero if unknown.
+        #[speedy(default_on_eof)]
+        mtime: u64
     },
     StringTable {
         inode: Inode,
@@ -182,6 +215,88 @@ pub enum Packet< 'a > {
         pid: u32,
         cpu: u32,
         kind: ContextSwitchKind
+    },
+    Stall {
+        timestamp: u64,
+        pid: u32,
+        tid: u32,
+        duration: u64,
+        kind: StallKind,
+        backtrace: Vec< String >
+    },
+    /// For how long (in total, over all of the threads) the sampled event was enabled,
+    /// and for how long it was actually running, since it might have been multiplexed with other events.
+    Multiplexing {
+        time_enabled: u64,
+        time_running: u64
+    },
+    /// Only `active_cpus` out of `total_cpus` were being sampled at any given time.
+    RoundRobinCpus {
+        active_cpus: u32,
+        total_cpus: u32
+    },
+    /// The registers and the stack of the preceding `Sample`, kept so that it can be unwound again later.
+    RawSampleData {
+        stack: CowRawData< 'a >,
+        regs: Cow< 'a, [DwarfReg] >
+    },
+    /// A summary of how well the recording went; written out when the recording is finished.
+    RecordingStats {
+        sample_count: u64,
+        lost_event_count: u64,
+        /// The number of samples which were unwound while recording; zero in the offline mode.
+        unwound_sample_count: u64,
+        frame_count: u64,
+        truncated_stack_count: u64,
+        unwind_failure_count: u64,
+        binary_count: u64,
+        bytes_written: u64
+    },
+    /// How much the profiler itself has spent on the recording; written out at the end with `--self-profile`.
+    ///
+    /// The times are in nanoseconds; the `sampling_time` is the CPU time spent on draining the ring buffers
+    /// (or on stopping the threads with the non-perf samplers), and the `io_time` is the CPU time of the thread
+    /// which serializes and writes out the data.
+    SelfProfile {
+        wall_time: u64,
+        cpu_time: u64,
+        sampling_time: u64,
+        unwinding_time: u64,
+        symbol_loading_time: u64,
+        io_time: u64,
+        max_rss: u64
+    },
+    /// A Python function referenced by the `python_backtrace` of the samples from a given process.
+    PythonFunction {
+        pid: u32,
+        id: u64,
+        name: Cow< 'a, str >,
+        file: Cow< 'a, str >,
+        first_line: u32
+    },
+    /// New lines of the `/tmp/perf-<pid>.map` file in which a JIT compiler running
+    /// in a given process lists the names of the code it has generated, and when they were read;
+    /// the symbols from the ReadyToRun maps of .NET images and from the jitdump files are recorded in the same format.
+    PerfMap {
+        pid: u32,
+        timestamp: u64,
+        data: Cow< 'a, [u8] >
+    },
+    /// The memory use of a given process, from its `/proc/<pid>/smaps_rollup`; written out periodically with `--memory-stats`.
+    ///
+    /// The sizes are in bytes.
+    MemoryStats {
+        pid: u32,
+        timestamp: u64,
+        rss: u64,
+        pss: u64,
+        swap: u64
+    },
+    /// The cgroup (v2) a given process was in when it was first sampled, or after it called `exec`;
+    /// supersedes the one from its `ProcessInfo`.
+    ProcessCgroup {
+        pid: u32,
+        cgroup: Cow< 'a, [u8] >
     }
 }
 
@@ -1,10 +1,27 @@
 use std::ffi::OsString;
 use structopt::StructOpt;
 
+#[cfg(feature = "native")]
 use perf_event_open::EventSource;
 
-use crate::cmd_collate::CollateFormat;
+use crate::cmd_collate::{CollateFormat, SplitBy, GroupBy};
+#[cfg(feature = "native")]
+use crate::cmd_record::Sampler;
+use crate::cmd_export::ExportFormat;
+use crate::cmd_report::{ReportFormat, ReportSort};
+use crate::cmd_diff::DiffMetric;
+use crate::cmd_timeline::{TimelineKey, TimelineFormat};
+#[cfg(feature = "native")]
+use crate::push::{PushProtocol, PushFormat};
+use crate::anonymize::Anonymization;
+use crate::quality::{Threshold, parse_threshold};
+#[cfg(feature = "native")]
+use crate::utils::parse_size;
 
+#[cfg(feature = "inferno")]
+use crate::cmd_flamegraph::FlamegraphFormat;
+
+#[cfg(feature = "native")]
 fn parse_event_source( source: &str ) -> EventSource {
     match source {
         "hw_cpu_cycles" => EventSource::HwCpuCycles,
@@ -16,10 +33,119 @@ fn parse_event_source( source: &str ) -> EventSource {
     }
 }
 
+#[cfg(feature = "native")]
+fn parse_sampler( sampler: &str ) -> Sampler {
+    match sampler {
+        "perf" => Sampler::Perf,
+        "timer" => Sampler::Timer,
+        "snapshot" => Sampler::Snapshot,
+        _ => unreachable!()
+    }
+}
+
+#[cfg(feature = "native")]
+fn parse_buffer_size( size: &str ) -> Result< u32, String > {
+    match parse_size( size ) {
+        Some( size ) if size > 0 && size <= u32::MAX as u64 => Ok( size as u32 ),
+        _ => Err( format!( "invalid buffer size: '{}'", size ) )
+    }
+}
+
 fn parse_collate_format( format: &str ) -> CollateFormat {
     match format {
         "collapsed" => CollateFormat::Collapsed,
         "perf-like" => CollateFormat::PerfLike,
+        "speedscope" => CollateFormat::Speedscope,
+        "json-samples" => CollateFormat::JsonSamples,
+        _ => unreachable!()
+    }
+}
+
+fn parse_anonymization( anonymization: &str ) -> Anonymization {
+    match anonymization {
+        "hash" => Anonymization::Hash,
+        "strip" => Anonymization::Strip,
+        _ => unreachable!()
+    }
+}
+
+fn parse_split_by( split_by: &str ) -> SplitBy {
+    match split_by {
+        "pid" => SplitBy::Pid,
+        "tid" => SplitBy::Tid,
+        "cpu" => SplitBy::Cpu,
+        _ => unreachable!()
+    }
+}
+
+fn parse_group_by( group_by: &str ) -> GroupBy {
+    match group_by {
+        "cgroup" => GroupBy::Cgroup,
+        _ => unreachable!()
+    }
+}
+
+#[cfg(feature = "native")]
+fn parse_push_protocol( protocol: &str ) -> PushProtocol {
+    match protocol {
+        "pyroscope" => PushProtocol::Pyroscope,
+        "parca" => PushProtocol::Parca,
+        _ => unreachable!()
+    }
+}
+
+#[cfg(feature = "native")]
+fn parse_push_format( format: &str ) -> PushFormat {
+    match format {
+        "folded" => PushFormat::Folded,
+        "pprof" => PushFormat::Pprof,
+        _ => unreachable!()
+    }
+}
+
+fn parse_export_format( format: &str ) -> ExportFormat {
+    match format {
+        "pprof" => ExportFormat::Pprof,
+        "gecko" => ExportFormat::Gecko,
+        "chrome-trace" => ExportFormat::ChromeTrace,
+        "callgrind" => ExportFormat::Callgrind,
+        "speedscope-evented" => ExportFormat::SpeedscopeEvented,
+        "sqlite" => ExportFormat::Sqlite,
+        _ => unreachable!()
+    }
+}
+
+#[cfg(feature = "inferno")]
+fn parse_flamegraph_format( format: &str ) -> FlamegraphFormat {
+    match format {
+        "svg" => FlamegraphFormat::Svg,
+        "html" => FlamegraphFormat::Html,
+        _ => unreachable!()
+    }
+}
+
+fn parse_report_sort( sort: &str ) -> ReportSort {
+    match sort {
+        "self" => ReportSort::SelfSamples,
+        "total" => ReportSort::TotalSamples,
+        "symbol" => ReportSort::Symbol,
+        "module" => ReportSort::Module,
+        _ => unreachable!()
+    }
+}
+
+fn parse_report_format( format: &str ) -> ReportFormat {
+    match format {
+        "table" => ReportFormat::Table,
+        "csv" => ReportFormat::Csv,
+        _ => unreachable!()
+    }
+}
+
+fn parse_diff_metric( metric: &str ) -> DiffMetric {
+    match metric {
+        "self" => DiffMetric::SelfSamples,
+        "total" => DiffMetric::TotalSamples,
         _ => unreachable!()
     }
 }
@@ -40,6 +166,32 @@ fn try_parse_period( period: &str ) -> Result< u64, <u64 as std::str::FromStr>::
     Ok( period )
 }
 
+fn parse_bin( bin: &str ) -> u64 {
+    match try_parse_period( bin ) {
+        Ok( bin ) if bin > 0 => bin,
+        _ => {
+            eprintln!( "error: invalid '--bin' specified" );
+            std::process::exit( 1 );
+        }
+    }
+}
+
+fn parse_timeline_key( key: &str ) -> TimelineKey {
+    match key {
+        "process" => TimelineKey::Process,
+        "function" => TimelineKey::Function,
+        _ => unreachable!()
+    }
+}
+
+fn parse_timeline_format( format: &str ) -> TimelineFormat {
+    match format {
+        "csv" => TimelineFormat::Csv,
+        "json" => TimelineFormat::Json,
+        _ => unreachable!()
+    }
+}
+
 fn parse_period( period: &str ) -> u64 {
     match try_parse_period( period ) {
         Ok( period ) => period,
@@ -95,6 +247,18 @@ pub struct ProcessFilter {
     wait_timeout: u32,
 }
 
+impl ProcessFilter {
+    /// A filter which selects a process with a given PID.
+    pub fn by_pid( pid: u32 ) -> Self {
+        ProcessFilter {
+            pid: Some( pid ),
+            process: None,
+            wait: false,
+            wait_timeout: 60
+        }
+    }
+}
+
 impl From< ProcessFilter > for TargetProcess {
     fn from( args: ProcessFilter ) -> Self {
         if let Some( process ) = args.process {
@@ -115,7 +279,8 @@ impl From< ProcessFilter > for TargetProcess {
 pub enum Granularity {
     Address,
     Function,
-    Line
+    Line,
+    Module
 }
 
 impl Default for Granularity {
@@ -129,14 +294,16 @@ fn parse_granularity( value: &str ) -> Granularity {
         "address" => Granularity::Address,
         "function" => Granularity::Function,
         "line" => Granularity::Line,
+        "module" => Granularity::Module,
         _ => unreachable!()
     }
 }
 
+#[cfg(feature = "native")]
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct GenericProfilerArgs {
-    /// The file to which the profiling data will be written
+    /// The file to which the profiling data will be written; `-` writes it to stdout
     #[structopt(long, short = "o", parse(from_os_str))]
     pub output: Option< OsString >,
 
@@ -156,13 +323,65 @@ pub struct GenericProfilerArgs {
     #[structopt(long)]
     pub offline: bool,
 
+    /// Keep the raw registers and stacks even when backtracing online,
+    /// so that the samples can be unwound again later with the `reprocess` subcommand
+    #[structopt(long, raw(conflicts_with = r#""offline""#))]
+    pub keep_raw: bool,
+
+    /// Also samples the Python stacks of a CPython interpreter running in the profiled process
+    /// and shows them in place of the interpreter's own frames; only CPython 3.11
+    /// on 64-bit little-endian targets (e.g. amd64 and aarch64) is supported
+    #[structopt(long, raw(conflicts_with = r#""offline""#))]
+    pub python: bool,
+
+    /// Periodically attaches `perf-map-agent` from a given directory (the one with its `attach-main.jar`)
+    /// to the profiled JVM so that the Java frames are named; the `/tmp/perf-<pid>.map` files
+    /// are recorded even without this, whenever the profiled process has one
+    #[structopt(long, parse(from_os_str))]
+    pub perf_map_agent: Option< OsString >,
+
+    /// How often, in seconds, `perf-map-agent` is reattached to pick up the code which was compiled in the meantime
+    #[structopt(long, default_value = "10")]
+    pub perf_map_agent_interval: f64,
+
+    /// A directory with the `.r2rmap` files generated by `crossgen2 --perfmap` for the ReadyToRun images
+    /// loaded by a profiled .NET process; they're also looked for next to the images themselves;
+    /// can be specified multiple times
+    #[structopt(long, parse(from_os_str), raw(number_of_values = "1"))]
+    pub r2r_map_dir: Vec< OsString >,
+
     #[structopt(long, raw(hidden = "true"))]
     pub panic_on_partial_backtrace: bool,
 
+    /// Periodically writes out the status of the recording (the number of samples, samples per second,
+    /// lost events and bytes written) as JSON, one object per line, to a given file, pipe or `/dev/fd/N`;
+    /// use `-` to write it to stderr
+    #[structopt(long, parse(from_os_str))]
+    pub progress: Option< OsString >,
+
+    /// How often, in seconds, the status is written out when `--progress` is used
+    #[structopt(long, default_value = "1")]
+    pub progress_interval: f64,
+
+    /// Periodically records the memory use (RSS, PSS and swap) of the profiled process from its
+    /// `/proc/<pid>/smaps_rollup`; see the `info` and `timeline` subcommands
+    #[structopt(long)]
+    pub memory_stats: bool,
+
+    /// How often, in seconds, the memory use is recorded when `--memory-stats` is used
+    #[structopt(long, default_value = "1")]
+    pub memory_stats_interval: f64,
+
+    /// Measures how much CPU time the profiler itself spends on sampling, unwinding, loading
+    /// the symbols and writing out the data, and stores it in the output; see the `info` subcommand
+    #[structopt(long)]
+    pub self_profile: bool,
+
     #[structopt(flatten)]
     pub process_filter: ProcessFilter
 }
 
+#[cfg(feature = "native")]
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct RecordArgs {
@@ -170,6 +389,11 @@ pub struct RecordArgs {
     #[structopt(long, short = "F", default_value = "900")]
     pub frequency: u32,
 
+    /// Gather a sample every given number of events instead of with a fixed frequency,
+    /// e.g. `-c 1` together with `-s sw_page_faults` samples every page fault
+    #[structopt(long, short = "c", raw(conflicts_with = r#""frequency""#))]
+    pub period: Option< u64 >,
+
     /// The source of perf events
     #[structopt(
         long,
@@ -186,31 +410,274 @@ pub struct RecordArgs {
     )]
     pub event_source: EventSource,
 
+    /// How the samples are gathered; `timer` briefly stops the running threads with ptrace instead of using
+    /// perf events, which is slower and can't sample the kernel, but works where perf events are forbidden
+    /// (it's also used automatically when the perf events can't be opened); `snapshot` periodically stops
+    /// the whole process and samples all of its threads, even the sleeping ones, and is meant for low frequencies
+    #[structopt(
+        long,
+        default_value = "perf",
+        parse(from_str = "parse_sampler"),
+        raw(possible_values = r#"&[
+            "perf",
+            "timer",
+            "snapshot"
+        ]"#)
+    )]
+    pub sampler: Sampler,
+
     /// Size of the gathered stack payloads (in bytes)
     #[structopt(long, default_value = "24576")]
     pub stack_size: u32,
 
+    /// Size of the per-CPU ring buffer into which the kernel writes the samples, e.g. `16M`;
+    /// increase it if events are being lost while sampling at a high frequency
+    #[structopt(long, parse(try_from_str = "parse_buffer_size"))]
+    pub buffer_size: Option< u32 >,
+
     /// Gather data but do not do anything with it; useful only for testing
     #[structopt(long)]
     pub discard_all: bool,
 
+    /// Only sample a given number of CPUs at a time, cycling through all of them;
+    /// limits the profiler's overhead on machines with a lot of CPUs
+    #[structopt(long)]
+    pub round_robin_cpus: Option< u32 >,
+
+    /// How often (in milliseconds) the set of sampled CPUs is changed when `--round-robin-cpus` is used
+    #[structopt(long, default_value = "100")]
+    pub round_robin_interval: u64,
+
+    /// Only start writing out the samples once a given condition is met, e.g. `cpu>80%`, `rss>1G`,
+    /// or `marker` (a SIGUSR1 sent to the profiler); can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub start_when: Vec< String >,
+
+    /// Stop writing out the samples once a given condition is met, e.g. `cpu<10%`; if any start
+    /// conditions were given the profiler will wait for them again, otherwise it will exit
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub stop_when: Vec< String >,
+
+    #[structopt(flatten)]
+    pub push_args: PushArgs,
+
+    #[structopt(flatten)]
+    pub arg_quality_thresholds: ArgQualityThresholds,
+
     #[structopt(flatten)]
     pub profiler_args: GenericProfilerArgs
 }
 
+#[cfg(feature = "native")]
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct PushArgs {
+    /// Periodically uploads the aggregated callstacks to a given Pyroscope or Parca
+    /// server (e.g. `http://localhost:4040`) while recording
+    #[structopt(long)]
+    pub push_url: Option< String >,
+
+    /// Selects the ingestion API of the server the data will be pushed to
+    #[structopt(
+        long,
+        default_value = "pyroscope",
+        parse(from_str = "parse_push_protocol"),
+        raw(possible_values = r#"&[
+            "pyroscope",
+            "parca"
+        ]"#)
+    )]
+    pub push_protocol: PushProtocol,
+
+    /// Selects the format of the pushed data; Parca only supports `pprof`
+    #[structopt(
+        long,
+        parse(from_str = "parse_push_format"),
+        raw(possible_values = r#"&[
+            "folded",
+            "pprof"
+        ]"#)
+    )]
+    pub push_format: Option< PushFormat >,
+
+    /// The interval (in seconds) with which the data will be pushed
+    #[structopt(long, default_value = "10")]
+    pub push_interval: u64,
+
+    /// The name of the service under which the data will be pushed; the name of the profiled process by default
+    #[structopt(long)]
+    pub push_service: Option< String >,
+
+    /// An extra `<key>=<value>` label attached to the pushed data, on top of `host`, `service` and `container`;
+    /// can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub push_label: Vec< String >
+}
+
+#[cfg(feature = "native")]
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
+pub struct TopArgs {
+    /// The frequency with which the measurements will be gathered
+    #[structopt(long, short = "F", default_value = "900")]
+    pub frequency: u32,
+
+    /// The source of perf events
+    #[structopt(
+        long,
+        short = "s",
+        default_value = "hw_cpu_cycles",
+        parse(from_str = "parse_event_source"),
+        raw(possible_values = r#"&[
+            "hw_cpu_cycles",
+            "hw_ref_cpu_cycles",
+            "sw_cpu_clock",
+            "sw_page_faults",
+            "sw_dummy"
+        ]"#)
+    )]
+    pub event_source: EventSource,
+
+    /// Size of the gathered stack payloads (in bytes)
+    #[structopt(long, default_value = "24576")]
+    pub stack_size: u32,
+
+    /// The interval (in seconds) with which the output will be refreshed
+    #[structopt(long, default_value = "1")]
+    pub interval: u64,
+
+    /// The number of functions to show; by default as many as will fit in the terminal
+    #[structopt(long, short = "n")]
+    pub lines: Option< usize >,
+
+    /// Shows all of the samples gathered so far instead of only the ones from the last interval
+    #[structopt(long)]
+    pub cumulative: bool,
+
+    /// Shows a small flamegraph below the list of functions
+    #[structopt(long)]
+    pub flamegraph: bool,
+
+    /// Determines for how many seconds the measurements will be gathered
+    #[structopt(long, short = "l")]
+    pub time_limit: Option< u64 >,
+
+    #[structopt(flatten)]
+    pub process_filter: ProcessFilter
+}
+
+#[derive(StructOpt, Clone, Debug)]
+#[structopt(rename_all = "kebab-case")]
 pub struct SharedCollationArgs {
     /// A file or directory with extra debugging symbols; can be specified multiple times
     #[structopt(long, short = "d", parse(from_os_str))]
     pub debug_symbols: Vec< OsString >,
 
+    /// A directory with a copy of the target's root filesystem; binaries and their
+    /// debug symbols will be looked up under it by their original paths and build IDs
+    #[structopt(long, parse(from_os_str))]
+    pub sysroot: Option< OsString >,
+
+    /// Looks up binaries whose original path starts with `<from>` under `<to>` instead
+    /// (e.g. `--binary-path-map /opt/app=/home/me/app/build`); can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub binary_path_map: Vec< String >,
+
+    /// Looks up source files whose original path starts with `<from>` under `<to>` instead
+    /// (e.g. `--path-map /build/src=/home/me/src`); can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub path_map: Vec< String >,
+
+    /// An extra source of symbols for the user space frames, e.g. `breakpad:<directory>` for a Breakpad symbol store;
+    /// they're consulted in the order given, and `elf` stands for the built-in symbolization of the ELF binaries,
+    /// which otherwise comes first; can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub symbol_resolver: Vec< String >,
+
+    /// The URL of an `nperf symbolize-server` (e.g. `http://symbols:8090`) which will be asked about the frames
+    /// from the binaries with a build ID before they're symbolized locally; whatever it can't resolve,
+    /// or everything if it can't be reached, is still symbolized locally
+    #[structopt(long)]
+    pub symbolize_via: Option< String >,
+
+    /// A copy of the target's `/proc/kallsyms` to use for symbolizing the kernel frames
+    /// instead of the one from the profiling data (e.g. if it was hidden by `kernel.kptr_restrict`)
+    #[structopt(long, parse(from_os_str))]
+    pub kallsyms: Option< OsString >,
+
+    /// A file into which the loaded binaries, along with their symbols and unwinding tables, are saved
+    /// on the first run, and from which they're then restored on the following runs over the same profiling data;
+    /// it's rewritten whenever the profiling data or the options used to find the binaries change
+    #[structopt(long, parse(from_os_str))]
+    pub address_space_cache: Option< OsString >,
+
+    /// A directory in which the symbolized addresses of every binary with a build ID are kept between runs,
+    /// so that they don't have to be resolved again; it should be cleared whenever different debug symbols
+    /// for the same binaries become available
+    #[structopt(long, parse(from_os_str))]
+    pub symbol_cache: Option< OsString >,
+
+    /// Treats any malformed ELF structure or unwinding info in the binaries as an error, reporting
+    /// exactly where it is; by default such structures are skipped and only their number is reported
+    #[structopt(long)]
+    pub strict: bool,
+
+    /// Unwinds every sample a second time with libunwind and writes out every sample for which
+    /// the backtraces differ into a given file; only for amd64, and needs the `libunwind` feature
+    #[structopt(long, parse(from_os_str))]
+    pub cross_check_unwinding: Option< OsString >,
+
     #[structopt(long, raw(hidden = "true"))]
     pub force_stack_size: Option< u32 >,
 
     #[structopt(long, raw(hidden = "true"))]
     pub omit: Vec< String >,
 
+    /// Only process the samples with at least one frame whose symbol matches this regex;
+    /// can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub only_matching: Vec< String >,
+
+    /// Ignores the samples with any frame whose symbol matches this regex;
+    /// can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub exclude_matching: Vec< String >,
+
+    /// Hides all of the frames called by the first frame (counting from the root)
+    /// whose symbol matches this regex; can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub hide_above: Vec< String >,
+
+    /// Hides all of the frames below (callers of) the first frame whose symbol
+    /// matches this regex, e.g. `--hide-below '^main$'`; can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub hide_below: Vec< String >,
+
+    /// A file with frame pruning rules; every line should contain either
+    /// `hide-above <regex>` or `hide-below <regex>`
+    #[structopt(long, parse(from_os_str))]
+    pub prune_rules: Option< OsString >,
+
+    /// A file with rules which hide, keep or rename individual frames; every line should
+    /// contain one of `hide <regex>`, `keep <regex>`, `rename <regex> <replacement>`
+    /// or their `-module` variants which match the name of the binary instead
+    #[structopt(long, parse(from_os_str))]
+    pub symbol_rules: Option< OsString >,
+
+    /// Collapses consecutive identical frames (direct recursion) into a single frame
+    #[structopt(long)]
+    pub collapse_recursion: bool,
+
+    /// Collapses whole recursive cycles (e.g. `a;b;a;b`) so that every function appears
+    /// only once in a callstack; implies `--collapse-recursion`
+    #[structopt(long)]
+    pub collapse_recursive_cycles: bool,
+
+    /// A Rhai script with a `process( frames )` function which can tag, relabel, reweight
+    /// or drop each of the collapsed stacks; needs the `scripting` feature
+    #[structopt(long, parse(from_os_str))]
+    pub script: Option< OsString >,
+
     #[structopt(long, raw(hidden = "true"))]
     pub only_sample: Option< u64 >,
 
@@ -218,17 +685,43 @@ pub struct SharedCollationArgs {
     #[structopt(long)]
     pub without_kernel_callstacks: bool,
 
-    /// Only process the samples generated *after* this many seconds after launch.
+    /// Replaces the names of the symbols, binaries, source files, processes, threads and cgroups
+    /// (keeping the callstacks and their weights intact) so that the profile can be shared;
+    /// `hash` replaces every name with its hash, so that the same names match across profiles
+    /// (but since it's unsalted any name which can be guessed can be checked for), and `strip`
+    /// replaces them with sequential numbers. The symbols from the kernel itself
+    /// (but not from its modules) are kept since they're public
+    #[structopt(
+        long,
+        parse(from_str = "parse_anonymization"),
+        raw(possible_values = r#"&[
+            "hash",
+            "strip"
+        ]"#)
+    )]
+    pub anonymize: Option< Anonymization >,
+
+    /// Only process the samples generated *after* this many seconds after launch;
+    /// also accepts units (e.g. `500ms`, `10s`, `2m`), a percentage (e.g. `25%`)
+    /// or an absolute timestamp (e.g. `@1234.567890`)
     #[structopt(long)]
     pub from: Option< String >,
 
-    /// Only process the samples generated *before* this many seconds after launch.
+    /// Only process the samples generated *before* this many seconds after launch;
+    /// accepts the same formats as `--from`
     #[structopt(long)]
     pub to: Option< String >,
 
-    /// The input file to use; record it with the `record` subcommand
-    #[structopt(parse(from_os_str))]
-    pub input: OsString
+    /// Only process the samples from within the given time range (e.g. `10s..30s`);
+    /// a shorthand for `--from` and `--to`
+    #[structopt(long)]
+    pub range: Option< String >,
+
+    /// The input file to use; record it with the `record` subcommand, and use `-` to read it from stdin;
+    /// if multiple files are given they will be merged together; collapsed stack
+    /// files (e.g. from `stackcollapse-perf.pl`) are also accepted when collapsing the stacks
+    #[structopt(parse(from_os_str), raw(required = "true"))]
+    pub input: Vec< OsString >
 }
 
 #[derive(StructOpt, Debug)]
@@ -239,10 +732,69 @@ pub struct ArgMergeThreads {
     pub merge_threads: bool
 }
 
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ArgMarkTruncated {
+    /// Adds a `[truncated]` or `[unwind failed: <reason>]` frame at the root of every
+    /// callstack whose unwinding stopped prematurely and reports how many there were
+    #[structopt(long)]
+    pub mark_truncated: bool
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ArgQualityThresholds {
+    /// Exits with an exit code of 2 if more than a given percentage (e.g. `>1%`)
+    /// or number (e.g. `>100`) of the events were lost
+    #[structopt(long, parse(try_from_str = "parse_threshold"))]
+    pub fail_if_lost: Option< Threshold >,
+
+    /// Exits with an exit code of 2 if the unwinding stopped prematurely for more than
+    /// a given percentage (e.g. `>5%`) or number (e.g. `>100`) of the samples, including the truncated stacks
+    #[structopt(long, parse(try_from_str = "parse_threshold"))]
+    pub fail_if_unwind_failures: Option< Threshold >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ArgGroupBy {
+    /// Groups the callstacks under an extra root frame, e.g. to break down
+    /// the profile per service or container with `cgroup`
+    #[structopt(
+        long,
+        parse(from_str = "parse_group_by"),
+        raw(possible_values = r#"&[
+            "cgroup"
+        ]"#)
+    )]
+    pub group_by: Option< GroupBy >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ArgSplitBy {
+    /// Generates a separate output for every process, thread or CPU
+    #[structopt(
+        long,
+        parse(from_str = "parse_split_by"),
+        raw(possible_values = r#"&[
+            "pid",
+            "tid",
+            "cpu"
+        ]"#)
+    )]
+    pub split_by: Option< SplitBy >,
+
+    /// The directory to which the outputs will be written when using `--split-by`
+    #[structopt(long, parse(from_os_str))]
+    pub output_dir: Option< OsString >
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct ArgGranularity {
-    /// Specifies at what granularity the call frames will be merged
+    /// Specifies at what granularity the call frames will be merged; `module` turns
+    /// every frame into the name of its binary, e.g. `libfoo.so`
     #[structopt(
         long,
         default_value = "function",
@@ -250,7 +802,8 @@ pub struct ArgGranularity {
         raw(possible_values = r#"&[
             "address",
             "function",
-            "line"
+            "line",
+            "module"
         ]"#)
     )]
     pub granularity: Granularity
@@ -266,9 +819,40 @@ pub struct FlamegraphArgs {
     #[structopt(flatten)]
     pub arg_merge_threads: ArgMergeThreads,
 
+    #[structopt(flatten)]
+    pub arg_mark_truncated: ArgMarkTruncated,
+
+    #[structopt(flatten)]
+    pub arg_group_by: ArgGroupBy,
+
+    #[structopt(flatten)]
+    pub arg_split_by: ArgSplitBy,
+
     #[structopt(flatten)]
     pub arg_granularity: ArgGranularity,
 
+    /// Generates a differential flamegraph against the given baseline profile;
+    /// frames are colored red if they've regressed and blue if they've improved
+    #[structopt(long, parse(from_os_str))]
+    pub diff: Option< OsString >,
+
+    /// Selects the output format; `html` generates a self-contained interactive page
+    #[structopt(
+        long,
+        default_value = "svg",
+        parse(from_str = "parse_flamegraph_format"),
+        raw(possible_values = r#"&[
+            "svg",
+            "html"
+        ]"#)
+    )]
+    pub format: FlamegraphFormat,
+
+    /// Generates an inverted (icicle) flamegraph, where the stacks are rooted
+    /// at the leaf functions and merged by their callees
+    #[structopt(long)]
+    pub inverted: bool,
+
     /// The file to which the flamegraph will be written to (instead of the stdout)
     #[structopt(long, short = "o", parse(from_os_str))]
     pub output: Option< OsString >
@@ -291,37 +875,103 @@ pub struct CsvArgs {
 
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
-pub struct TraceEventsArgs {
+pub struct BenchArgs {
     #[structopt(flatten)]
     pub collation_args: SharedCollationArgs,
 
-    #[structopt(flatten)]
-    pub arg_granularity: ArgGranularity,
-
-    #[structopt(long)]
-    pub absolute_time: bool,
-
-    /// The sampling period; samples within one sampling period will be merged together
-    #[structopt(long, short = "p", parse(from_str = "parse_period"))]
-    pub period: Option< u64 >,
-
-    /// The file to which the trace events will be written to
-    #[structopt(long, short = "o", parse(from_os_str))]
-    pub output: OsString
+    /// How many times the samples will be unwound and symbolized
+    #[structopt(long, short = "n", default_value = "5")]
+    pub iterations: u32
 }
 
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
-pub struct CollateArgs {
+pub struct TimelineArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// The width of a single time bin, e.g. `100ms` or `1s`
+    #[structopt(long, default_value = "100ms", parse(from_str = "parse_bin"))]
+    pub bin: u64,
+
+    /// Selects what the samples will be counted for; `function` counts the leaf functions
+    #[structopt(
+        long,
+        default_value = "function",
+        parse(from_str = "parse_timeline_key"),
+        raw(possible_values = r#"&[
+            "process",
+            "function"
+        ]"#)
+    )]
+    pub by: TimelineKey,
+
+    /// The number of series with the most samples to emit; the rest will be lumped together as `[other]`
+    #[structopt(long, default_value = "20")]
+    pub top: usize,
+
+    /// Selects the output format
+    #[structopt(
+        long,
+        default_value = "csv",
+        parse(from_str = "parse_timeline_format"),
+        raw(possible_values = r#"&[
+            "csv",
+            "json"
+        ]"#)
+    )]
+    pub format: TimelineFormat,
+
+    /// The file to which the timeline will be written to (instead of the stdout)
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: Option< OsString >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct TraceEventsArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    #[structopt(flatten)]
+    pub arg_granularity: ArgGranularity,
+
+    #[structopt(long)]
+    pub absolute_time: bool,
+
+    /// The sampling period; samples within one sampling period will be merged together
+    #[structopt(long, short = "p", parse(from_str = "parse_period"))]
+    pub period: Option< u64 >,
+
+    /// The file to which the trace events will be written to
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct CollateArgs {
     #[structopt(flatten)]
     pub collation_args: SharedCollationArgs,
 
     #[structopt(flatten)]
     pub arg_merge_threads: ArgMergeThreads,
 
+    #[structopt(flatten)]
+    pub arg_mark_truncated: ArgMarkTruncated,
+
+    #[structopt(flatten)]
+    pub arg_group_by: ArgGroupBy,
+
+    #[structopt(flatten)]
+    pub arg_split_by: ArgSplitBy,
+
     #[structopt(flatten)]
     pub arg_granularity: ArgGranularity,
 
+    #[structopt(flatten)]
+    pub arg_quality_thresholds: ArgQualityThresholds,
+
     /// Selects the output format
     #[structopt(
         long,
@@ -329,12 +979,432 @@ pub struct CollateArgs {
         parse(from_str = "parse_collate_format"),
         raw(possible_values = r#"&[
             "collapsed",
-            "perf-like"
+            "perf-like",
+            "speedscope",
+            "json-samples"
         ]"#)
     )]
     pub format: CollateFormat
 }
 
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ExportArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// Selects the output format
+    #[structopt(
+        long,
+        parse(from_str = "parse_export_format"),
+        raw(possible_values = r#"&[
+            "pprof",
+            "gecko",
+            "chrome-trace",
+            "callgrind",
+            "speedscope-evented",
+            "sqlite"
+        ]"#),
+        raw(required_unless = r#""exporter""#, conflicts_with = r#""exporter""#)
+    )]
+    pub format: Option< ExportFormat >,
+
+    /// Uses an exporter which was registered by whoever embeds nperf instead of one of the built-in formats,
+    /// as `<name>[:<config>]`
+    #[structopt(long)]
+    pub exporter: Option< String >,
+
+    /// The file to which the profile will be written to (instead of the stdout);
+    /// required for the `sqlite` format, which needs nperf to be compiled with the `sqlite` feature
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: Option< OsString >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ReportArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    #[structopt(flatten)]
+    pub arg_granularity: ArgGranularity,
+
+    /// Selects by which column the report will be sorted
+    #[structopt(
+        long,
+        default_value = "self",
+        parse(from_str = "parse_report_sort"),
+        raw(possible_values = r#"&[
+            "self",
+            "total",
+            "symbol",
+            "module"
+        ]"#)
+    )]
+    pub sort: ReportSort,
+
+    /// Selects the output format; `csv` emits the table as CSV for use in spreadsheets and dashboards
+    #[structopt(
+        long,
+        default_value = "table",
+        parse(from_str = "parse_report_format"),
+        raw(possible_values = r#"&[
+            "table",
+            "csv"
+        ]"#)
+    )]
+    pub format: ReportFormat,
+
+    /// Instead of the table shows the callers and the callees of functions matching the given regexp
+    #[structopt(long)]
+    pub focus: Option< String >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct AnnotateArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// A regexp matching the names of the functions to annotate
+    #[structopt(long)]
+    pub function: String,
+
+    /// The number of source lines to show around every line with samples
+    #[structopt(long, default_value = "3")]
+    pub context: u64,
+
+    /// Show per-instruction sample counts interleaved with the source lines;
+    /// requires nperf to be compiled with the `disassembler` feature
+    #[structopt(long)]
+    pub asm: bool
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DiffArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// Selects which overhead of the functions will be compared
+    #[structopt(
+        long,
+        default_value = "self",
+        parse(from_str = "parse_diff_metric"),
+        raw(possible_values = r#"&[
+            "self",
+            "total"
+        ]"#)
+    )]
+    pub metric: DiffMetric,
+
+    /// Only show the functions whose overhead changed by at least this many percentage points
+    #[structopt(long, default_value = "0.01")]
+    pub min_delta: f64,
+
+    /// Exit with a non-zero exit code if the overhead of the functions matching
+    /// the given regexp increased by more than X percentage points (e.g. `'^malloc$ +2%'`);
+    /// can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub fail_if: Vec< String >,
+
+    /// The minimum confidence (in percent) which a change must have to trigger `--fail-if`
+    #[structopt(long, default_value = "95")]
+    pub confidence: f64
+}
+
+#[cfg(feature = "tui")]
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct TuiArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// Selects by which column the function list will be initially sorted
+    #[structopt(
+        long,
+        default_value = "self",
+        parse(from_str = "parse_report_sort"),
+        raw(possible_values = r#"&[
+            "self",
+            "total",
+            "symbol",
+            "module"
+        ]"#)
+    )]
+    pub sort: ReportSort,
+
+    /// The number of source lines to show around every line with samples when annotating
+    #[structopt(long, default_value = "3")]
+    pub context: u64
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct MergeArgs {
+    /// The input files to merge; if they're all collapsed stack files the output will be a collapsed stack file too
+    #[structopt(parse(from_os_str), raw(required = "true"))]
+    pub input: Vec< OsString >,
+
+    /// The file to which the merged recording will be written to
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ReprocessArgs {
+    /// The file to which the reprocessed recording will be written to
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: OsString,
+
+    /// The input file to use; record it with the `record` subcommand and `--keep-raw`
+    #[structopt(parse(from_os_str))]
+    pub input: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct TrimArgs {
+    /// Only keep the samples from the process with this PID; can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub pid: Vec< u32 >,
+
+    /// Only keep the samples generated *after* this many seconds after launch;
+    /// accepts the same formats as in the `collate` subcommand
+    #[structopt(long)]
+    pub from: Option< String >,
+
+    /// Only keep the samples generated *before* this many seconds after launch
+    #[structopt(long)]
+    pub to: Option< String >,
+
+    /// Only keep the samples from within the given time range (e.g. `10s..30s`)
+    #[structopt(long)]
+    pub range: Option< String >,
+
+    /// The file to which the trimmed recording will be written to
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: OsString,
+
+    /// The input file to use; record it with the `record` subcommand
+    #[structopt(parse(from_os_str))]
+    pub input: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct BuildidListArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ArchiveArgs {
+    #[structopt(flatten)]
+    pub collation_args: SharedCollationArgs,
+
+    /// The directory or the tarball (if it ends with `.tar` or `.tar.gz`) to which
+    /// the binaries and their debug info will be written to
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct AnalyzeCoreArgs {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// A directory with a copy of the crashed machine's root filesystem under which
+    /// the binaries will be looked up by their original paths
+    #[structopt(long, parse(from_os_str))]
+    pub sysroot: Option< OsString >,
+
+    /// The core dump to analyze
+    #[structopt(parse(from_os_str))]
+    pub core: OsString,
+
+    /// The binaries which were loaded into the crashed process (e.g. its executable), matched
+    /// by their filenames; by default they're looked up under their original paths
+    #[structopt(parse(from_os_str))]
+    pub binaries: Vec< OsString >
+}
+
+#[cfg(feature = "native")]
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct BacktraceArgs {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str), raw(number_of_values = "1"))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// The maximum size of the stack which will be copied from every thread (in bytes)
+    #[structopt(long, default_value = "262144")]
+    pub stack_size: u32,
+
+    /// The PID of the process whose threads will be inspected
+    pub pid: u32
+}
+
+#[cfg(feature = "native")]
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct StatArgs {
+    /// The events to count, separated by commas, e.g. `cycles,instructions,cache-misses`;
+    /// by default the most common hardware and software events are counted
+    #[structopt(long, short = "e", raw(number_of_values = "1"))]
+    pub events: Vec< String >,
+
+    /// Prints the counts every given number of milliseconds
+    #[structopt(long, short = "I")]
+    pub interval: Option< u64 >,
+
+    /// Determines for how many seconds the events will be counted
+    #[structopt(long, short = "l")]
+    pub time_limit: Option< u64 >,
+
+    /// Only count the events which happen in the user space
+    #[structopt(long)]
+    pub user_only: bool,
+
+    #[structopt(flatten)]
+    pub process_filter: ProcessFilter
+}
+
+#[cfg(feature = "native")]
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct WatchdogArgs {
+    /// The frequency with which the threads of the process will be sampled
+    #[structopt(long, short = "F", default_value = "1")]
+    pub frequency: u32,
+
+    /// The time (in seconds) after which a thread whose stack didn't change,
+    /// or which was runnable but didn't get any CPU time, is considered stalled
+    #[structopt(long, default_value = "5")]
+    pub stall_threshold: f64,
+
+    /// The maximum size of the stack which will be copied from every thread (in bytes)
+    #[structopt(long, default_value = "262144")]
+    pub stack_size: u32,
+
+    #[structopt(flatten)]
+    pub profiler_args: GenericProfilerArgs
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DumpSymsArgs {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str), raw(number_of_values = "1"))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// The file to which the symbols will be written; by default they're written to stdout
+    #[structopt(long, short = "o", parse(from_os_str))]
+    pub output: Option< OsString >,
+
+    /// The binary whose symbols will be dumped
+    #[structopt(parse(from_os_str))]
+    pub binary: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ShowCfiArgs {
+    /// The binary whose unwinding information will be shown
+    #[structopt(parse(from_os_str))]
+    pub binary: OsString,
+
+    /// The address (in hex) inside of the binary, as seen in its ELF headers and not where it was loaded
+    pub address: String
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct SymbolsArgs {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str), raw(number_of_values = "1"))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// The binary whose symbols will be listed
+    #[structopt(parse(from_os_str))]
+    pub binary: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct ResolveArgs {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str), raw(number_of_values = "1"))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// The binary in which the addresses will be resolved; the addresses are then
+    /// the ones from its ELF headers, and not where it was loaded
+    #[structopt(long, short = "b", parse(from_os_str), raw(required_unless = r#""input""#, conflicts_with = r#""input""#))]
+    pub binary: Option< OsString >,
+
+    /// A recording from which the address space of the process given with `--pid` will be taken;
+    /// the addresses are then the absolute ones from that process
+    #[structopt(long, short = "i", parse(from_os_str), raw(requires = r#""pid""#))]
+    pub input: Option< OsString >,
+
+    /// The PID of the process from the recording whose address space will be used
+    #[structopt(long, short = "p")]
+    pub pid: Option< u32 >,
+
+    /// The addresses (in hex) to resolve
+    #[structopt(raw(required = "true"))]
+    pub addresses: Vec< String >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct SymbolizeServerArgs {
+    /// The address and port on which the server will listen
+    #[structopt(long, default_value = "127.0.0.1:8090")]
+    pub listen: String,
+
+    /// A file or directory with the binaries and their debugging symbols, which will be
+    /// looked up by their build IDs; can be specified multiple times
+    #[structopt(long, short = "d", parse(from_os_str), raw(number_of_values = "1"))]
+    pub debug_symbols: Vec< OsString >,
+
+    /// A directory with a copy of the targets' root filesystem; binaries will be looked up
+    /// under it by the original paths given in the requests
+    #[structopt(long, parse(from_os_str))]
+    pub sysroot: Option< OsString >,
+
+    /// An extra source of symbols, e.g. `breakpad:<directory>` for a Breakpad symbol store;
+    /// they're consulted in the order given, and `elf` stands for the built-in symbolization of the ELF binaries,
+    /// which otherwise comes first; can be specified multiple times
+    #[structopt(long, raw(number_of_values = "1"))]
+    pub symbol_resolver: Vec< String >
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DumpUnwindTablesArgs {
+    /// The binary whose unwind tables will be dumped
+    #[structopt(parse(from_os_str))]
+    pub binary: OsString
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct VerifyUnwindTablesArgs {
+    /// The binary whose unwind tables will be verified
+    #[structopt(parse(from_os_str))]
+    pub binary: OsString,
+
+    /// Also fails on the FDEs and CIEs which can't be parsed at all; by default they're skipped
+    /// the same way as when unwinding, and only their number is reported
+    #[structopt(long)]
+    pub strict: bool
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct MetadataArgs {
@@ -343,17 +1413,35 @@ pub struct MetadataArgs {
     pub input: OsString
 }
 
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct InfoArgs {
+    /// The input file to use; record it with the `record` subcommand
+    #[structopt(parse(from_os_str))]
+    pub input: OsString
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     raw(author = "\"Jan Bujak <j@exia.io>\""),
-    raw(setting = "structopt::clap::AppSettings::ArgRequiredElseHelp")
+    raw(setting = "structopt::clap::AppSettings::ArgRequiredElseHelp"),
+    raw(global_setting = "structopt::clap::AppSettings::AllArgsOverrideSelf"),
+    after_help = "The defaults for any subcommand can be put in ~/.config/nperf.toml, or in a file given with --config, \
+                  with one section per subcommand, e.g. `[record]` followed by `frequency = 500`.\n\n\
+                  The logs can be emitted as JSON, one object per line, with `--log-format json`."
 )]
 pub enum Opt {
     /// Records profiling information with perf_event_open
+    #[cfg(feature = "native")]
     #[structopt(name = "record")]
     Record( RecordArgs ),
 
-    /// Emits an SVG flamegraph
+    /// Continuously shows the functions with the highest overhead, similar to `perf top`
+    #[cfg(feature = "native")]
+    #[structopt(name = "top")]
+    Top( TopArgs ),
+
+    /// Emits a flamegraph, either as an SVG or as an interactive HTML page
     #[cfg(feature = "inferno")]
     #[structopt(name = "flamegraph")]
     Flamegraph( FlamegraphArgs ),
@@ -362,6 +1450,10 @@ pub enum Opt {
     #[structopt(name = "csv")]
     Csv( CsvArgs ),
 
+    /// Emits the number of samples per process or function binned over time, for plotting
+    #[structopt(name = "timeline")]
+    Timeline( TimelineArgs ),
+
     /// Emits trace events for use with Chromium's Trace Viewer
     #[structopt(name = "trace-events")]
     TraceEvents( TraceEventsArgs ),
@@ -370,7 +1462,103 @@ pub enum Opt {
     #[structopt(name = "collate")]
     Collate( CollateArgs ),
 
+    /// Emits a table of functions with their self and total overhead, similar to `perf report`
+    #[structopt(name = "report")]
+    Report( ReportArgs ),
+
+    /// Shows the per-source-line overhead of the given function, similar to `perf annotate`
+    #[structopt(name = "annotate")]
+    Annotate( AnnotateArgs ),
+
+    /// Compares the per-function overhead of two recordings
+    #[structopt(name = "diff")]
+    Diff( DiffArgs ),
+
+    /// Browses the profiling data in an interactive terminal UI, similar to `perf report`
+    #[cfg(feature = "tui")]
+    #[structopt(name = "tui")]
+    Tui( TuiArgs ),
+
+    /// Exports the profiling data into a format understood by other tools (e.g. `pprof` or the Firefox Profiler)
+    #[structopt(name = "export")]
+    Export( ExportArgs ),
+
+    /// Merges multiple recordings into a single one
+    #[structopt(name = "merge")]
+    Merge( MergeArgs ),
+
+    /// Extracts a subset of the samples from a recording into a new, smaller file
+    #[structopt(name = "trim")]
+    Trim( TrimArgs ),
+
+    /// Rewrites a recording made with `--keep-raw` so that its samples get unwound again when read
+    #[structopt(name = "reprocess")]
+    Reprocess( ReprocessArgs ),
+
+    /// Lists the binaries referenced by a recording with their build IDs and whether their debug info is available
+    #[structopt(name = "buildid-list")]
+    BuildidList( BuildidListArgs ),
+
+    /// Bundles the binaries and the debug info referenced by a recording, similar to `perf archive`
+    #[structopt(name = "archive")]
+    Archive( ArchiveArgs ),
+
     /// Outputs rudimentary JSON-formatted metadata
     #[structopt(name = "metadata")]
-    Metadata( MetadataArgs )
+    Metadata( MetadataArgs ),
+
+    /// Shows a human readable summary of a recording, including the profiler's own overhead if it was measured
+    #[structopt(name = "info")]
+    Info( InfoArgs ),
+
+    /// Prints the fully unwound backtraces of every thread from a core dump
+    #[structopt(name = "analyze-core")]
+    AnalyzeCore( AnalyzeCoreArgs ),
+
+    /// Dumps the symbols, the line tables, the inline frames and the CFI of a binary in the Breakpad `.sym` format
+    #[structopt(name = "dump-syms")]
+    DumpSyms( DumpSymsArgs ),
+
+    /// Shows the unwinding information (CFI) which applies to a given address of a binary
+    #[structopt(name = "show-cfi")]
+    ShowCfi( ShowCfiArgs ),
+
+    /// Dumps every unwind table (`.eh_frame`, `.debug_frame` and `.ARM.exidx`) of a binary in a human readable form
+    #[structopt(name = "dump-unwind-tables")]
+    DumpUnwindTables( DumpUnwindTablesArgs ),
+
+    /// Evaluates every row of every FDE of a binary and reports the rules which nperf couldn't unwind through
+    #[structopt(name = "verify-unwind-tables")]
+    VerifyUnwindTables( VerifyUnwindTablesArgs ),
+
+    /// Lists the symbols which would be loaded for a given binary, along with where they came from
+    #[structopt(name = "symbols")]
+    Symbols( SymbolsArgs ),
+
+    /// Resolves addresses into their symbols, inline frames and source locations, like `addr2line`
+    #[structopt(name = "resolve")]
+    Resolve( ResolveArgs ),
+
+    /// Serves the symbolization of (build ID, address) pairs over HTTP for the machines which have no debug symbols
+    #[structopt(name = "symbolize-server")]
+    SymbolizeServer( SymbolizeServerArgs ),
+
+    /// Briefly stops a running process and prints the backtraces of all of its threads
+    #[cfg(feature = "native")]
+    #[structopt(name = "backtrace")]
+    Backtrace( BacktraceArgs ),
+
+    /// Periodically samples a running process and reports the threads which seem to be stalled
+    #[cfg(feature = "native")]
+    #[structopt(name = "watchdog")]
+    Watchdog( WatchdogArgs ),
+
+    /// Counts the hardware and software events of a running process, like `perf stat`
+    #[cfg(feature = "native")]
+    #[structopt(name = "stat")]
+    Stat( StatArgs ),
+
+    /// Repeatedly unwinds and symbolizes the samples from a data file, and reports how fast it was
+    #[structopt(name = "bench")]
+    Bench( BenchArgs )
 }
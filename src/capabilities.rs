@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::utils::read_string_lossy;
+
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_SYSLOG: u32 = 34;
+const CAP_PERFMON: u32 = 38;
+
+/// The privileges which determine what can be profiled, as seen by the current process.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Capabilities {
+    pub perf_event_paranoid: Option< i32 >,
+    pub kptr_restrict: Option< u32 >,
+    pub has_cap_perfmon: bool,
+    pub has_cap_sys_admin: bool,
+    pub has_cap_syslog: bool
+}
+
+fn parse_effective_capabilities( status: &str ) -> Option< u64 > {
+    let line = status.lines().find( |line| line.starts_with( "CapEff:" ) )?;
+    u64::from_str_radix( line[ "CapEff:".len().. ].trim(), 16 ).ok()
+}
+
+fn read_sysctl< T: std::str::FromStr >( name: &str ) -> Option< T > {
+    read_string_lossy( format!( "/proc/sys/kernel/{}", name ) ).ok()?.trim().parse().ok()
+}
+
+impl Capabilities {
+    pub fn detect() -> Self {
+        let capabilities = read_string_lossy( "/proc/self/status" ).ok()
+            .and_then( |status| parse_effective_capabilities( &status ) )
+            .unwrap_or( 0 );
+
+        Capabilities {
+            perf_event_paranoid: read_sysctl( "perf_event_paranoid" ),
+            kptr_restrict: read_sysctl( "kptr_restrict" ),
+            has_cap_perfmon: capabilities & (1 << CAP_PERFMON) != 0,
+            has_cap_sys_admin: capabilities & (1 << CAP_SYS_ADMIN) != 0,
+            has_cap_syslog: capabilities & (1 << CAP_SYSLOG) != 0
+        }
+    }
+
+    /// Whether the kernel lets us bypass the `perf_event_paranoid` restrictions.
+    fn is_perfmon_capable( &self ) -> bool {
+        self.has_cap_perfmon || self.has_cap_sys_admin
+    }
+
+    fn paranoid_reason( &self ) -> String {
+        format!( "kernel.perf_event_paranoid is {} and neither CAP_PERFMON nor CAP_SYS_ADMIN is available", self.perf_event_paranoid.unwrap_or( 0 ) )
+    }
+
+    /// Returns why no perf events can be opened at all, if that's the case.
+    ///
+    /// The `3` level isn't in the mainline kernel; it's a patch carried by some distributions.
+    pub fn check_perf_events( &self ) -> Result< (), String > {
+        if self.perf_event_paranoid.map( |level| level >= 3 ).unwrap_or( false ) && !self.is_perfmon_capable() {
+            return Err( self.paranoid_reason() );
+        }
+
+        Ok(())
+    }
+
+    /// Returns why the kernel can't be sampled, if that's the case.
+    pub fn check_kernel_sampling( &self ) -> Result< (), String > {
+        if self.perf_event_paranoid.map( |level| level >= 2 ).unwrap_or( false ) && !self.is_perfmon_capable() {
+            return Err( self.paranoid_reason() );
+        }
+
+        Ok(())
+    }
+
+    /// Returns why CPU-wide events can't be opened, if that's the case.
+    pub fn check_cpu_wide_events( &self ) -> Result< (), String > {
+        if self.perf_event_paranoid.map( |level| level >= 1 ).unwrap_or( false ) && !self.is_perfmon_capable() {
+            return Err( self.paranoid_reason() );
+        }
+
+        Ok(())
+    }
+
+    /// Returns why the addresses in `/proc/kallsyms` are hidden, if that's the case;
+    /// this mirrors `kallsyms_show_value` from the kernel.
+    pub fn check_kernel_addresses( &self ) -> Result< (), String > {
+        let kptr_restrict = self.kptr_restrict.unwrap_or( 0 );
+        match kptr_restrict {
+            0 if self.perf_event_paranoid.map( |level| level <= 1 ).unwrap_or( true ) || self.has_cap_syslog => Ok(()),
+            0 => Err( format!( "kernel.perf_event_paranoid is {} and CAP_SYSLOG is not available", self.perf_event_paranoid.unwrap_or( 0 ) ) ),
+            1 if self.has_cap_syslog => Ok(()),
+            1 => Err( "kernel.kptr_restrict is 1 and CAP_SYSLOG is not available".to_owned() ),
+            _ => Err( format!( "kernel.kptr_restrict is {}", kptr_restrict ) )
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        let level = |value: Option< i64 >| value.map( |value| value.to_string() ).unwrap_or_else( || "unknown".to_owned() );
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        write!(
+            fmt,
+            "perf_event_paranoid = {}, kptr_restrict = {}, CAP_PERFMON: {}, CAP_SYS_ADMIN: {}, CAP_SYSLOG: {}",
+            level( self.perf_event_paranoid.map( |value| value as i64 ) ),
+            level( self.kptr_restrict.map( |value| value as i64 ) ),
+            yes_no( self.has_cap_perfmon ),
+            yes_no( self.has_cap_sys_admin ),
+            yes_no( self.has_cap_syslog )
+        )
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let status = "Name:\tnperf\nCapInh:\t0000000000000000\nCapPrm:\t000001ffffffffff\nCapEff:\t0000004000000000\n";
+    assert_eq!( parse_effective_capabilities( status ), Some( 1 << CAP_PERFMON ) );
+    assert_eq!( parse_effective_capabilities( "Name:\tnperf\n" ), None );
+
+    let mut capabilities = Capabilities {
+        perf_event_paranoid: Some( 2 ),
+        kptr_restrict: Some( 0 ),
+        has_cap_perfmon: false,
+        has_cap_sys_admin: false,
+        has_cap_syslog: false
+    };
+
+    assert!( capabilities.check_perf_events().is_ok() );
+    assert!( capabilities.check_kernel_sampling().is_err() );
+    assert!( capabilities.check_cpu_wide_events().is_err() );
+    assert!( capabilities.check_kernel_addresses().is_err() );
+
+    capabilities.perf_event_paranoid = Some( 1 );
+    assert!( capabilities.check_kernel_sampling().is_ok() );
+    assert!( capabilities.check_cpu_wide_events().is_err() );
+    assert!( capabilities.check_kernel_addresses().is_ok() );
+
+    capabilities.perf_event_paranoid = Some( 3 );
+    assert!( capabilities.check_perf_events().is_err() );
+
+    capabilities.has_cap_perfmon = true;
+    assert!( capabilities.check_perf_events().is_ok() );
+    assert!( capabilities.check_kernel_sampling().is_ok() );
+    assert!( capabilities.check_cpu_wide_events().is_ok() );
+
+    capabilities.kptr_restrict = Some( 1 );
+    assert!( capabilities.check_kernel_addresses().is_err() );
+    capabilities.has_cap_syslog = true;
+    assert!( capabilities.check_kernel_addresses().is_ok() );
+
+    capabilities.kptr_restrict = Some( 2 );
+    assert_eq!( capabilities.check_kernel_addresses(), Err( "kernel.kptr_restrict is 2".to_owned() ) );
+}
@@ -0,0 +1,495 @@
+use std::fs;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::error::Error;
+use std::io::{self, Write};
+use std::collections::HashSet;
+
+use proc_maps::Region;
+use nwind::arch::{self, Architecture, Registers};
+use nwind::{AddressSpace, BinaryData, DebugInfoIndex, DwarfRegs, IAddressSpace, UnwindFailure, UserFrame};
+
+use crate::args;
+
+const ET_CORE: u16 = 4;
+pub(crate) const EM_X86_64: u16 = 62;
+pub(crate) const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+pub(crate) const NT_PRSTATUS: u32 = 1;
+const NT_FILE: u32 = 0x46494c45;
+
+// The offsets within the 64-bit `struct elf_prstatus`.
+const PRSTATUS_CURSIG_OFFSET: usize = 12;
+const PRSTATUS_PID_OFFSET: usize = 32;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+
+const SKIP: u16 = !0;
+
+// The order of the registers in `struct user_regs_struct` mapped to their DWARF numbers.
+static AMD64_REGS: &[u16] = &[
+    arch::amd64::dwarf::R15,
+    arch::amd64::dwarf::R14,
+    arch::amd64::dwarf::R13,
+    arch::amd64::dwarf::R12,
+    arch::amd64::dwarf::RBP,
+    arch::amd64::dwarf::RBX,
+    arch::amd64::dwarf::R11,
+    arch::amd64::dwarf::R10,
+    arch::amd64::dwarf::R9,
+    arch::amd64::dwarf::R8,
+    arch::amd64::dwarf::RAX,
+    arch::amd64::dwarf::RCX,
+    arch::amd64::dwarf::RDX,
+    arch::amd64::dwarf::RSI,
+    arch::amd64::dwarf::RDI,
+    SKIP, // orig_rax
+    arch::amd64::dwarf::RETURN_ADDRESS,
+    SKIP, // cs
+    SKIP, // eflags
+    arch::amd64::dwarf::RSP
+];
+
+// The AArch64 registers are laid out in the same order as their DWARF numbers: `x0`-`x30`, `sp` and `pc`.
+const AARCH64_REG_COUNT: usize = 33;
+
+fn read_u16( data: &[u8], offset: usize ) -> Option< u16 > {
+    let bytes = data.get( offset..offset + 2 )?;
+    Some( u16::from_le_bytes( [bytes[ 0 ], bytes[ 1 ]] ) )
+}
+
+fn read_u32( data: &[u8], offset: usize ) -> Option< u32 > {
+    let bytes = data.get( offset..offset + 4 )?;
+    let mut array = [0; 4];
+    array.copy_from_slice( bytes );
+    Some( u32::from_le_bytes( array ) )
+}
+
+fn read_u64( data: &[u8], offset: usize ) -> Option< u64 > {
+    let bytes = data.get( offset..offset + 8 )?;
+    let mut array = [0; 8];
+    array.copy_from_slice( bytes );
+    Some( u64::from_le_bytes( array ) )
+}
+
+#[derive(PartialEq, Debug)]
+struct Segment {
+    address: u64,
+    file_offset: u64,
+    file_size: u64,
+    flags: u32
+}
+
+#[derive(PartialEq, Debug)]
+struct Thread {
+    tid: u32,
+    signal: u16,
+    registers: Vec< u64 >
+}
+
+#[derive(PartialEq, Debug)]
+struct MappedFile {
+    start: u64,
+    end: u64,
+    file_offset: u64,
+    path: String
+}
+
+#[derive(Debug)]
+struct Core {
+    machine: u16,
+    segments: Vec< Segment >,
+    threads: Vec< Thread >,
+    files: Vec< MappedFile >
+}
+
+impl Core {
+    /// Returns the contents of the memory starting at `address` until the end of its segment.
+    fn memory_at< 'a >( &self, data: &'a [u8], address: u64 ) -> &'a [u8] {
+        for segment in &self.segments {
+            let segment_end = match segment.address.checked_add( segment.file_size ) {
+                Some( end ) => end,
+                None => continue
+            };
+
+            if address >= segment.address && address < segment_end {
+                let start = segment.file_offset.checked_add( address - segment.address );
+                let end = segment.file_offset.checked_add( segment.file_size );
+                return match (start, end) {
+                    (Some( start ), Some( end )) => data.get( start as usize..end as usize ).unwrap_or( &[] ),
+                    _ => &[]
+                };
+            }
+        }
+
+        &[]
+    }
+}
+
+fn parse_nt_file( desc: &[u8] ) -> Option< Vec< MappedFile > > {
+    let count = usize::try_from( read_u64( desc, 0 )? ).ok()?;
+    let page_size = read_u64( desc, 8 )?;
+
+    // Every file has a 24 byte entry, so the count can be checked before anything's allocated.
+    let names_offset = count.checked_mul( 24 )?.checked_add( 16 )?;
+    if names_offset > desc.len() {
+        return None;
+    }
+
+    let mut files = Vec::with_capacity( count );
+    let mut names = desc[ names_offset.. ].split( |&byte| byte == 0 );
+    for index in 0..count {
+        let offset = 16 + index * 24;
+        let name = names.next()?;
+        files.push( MappedFile {
+            start: read_u64( desc, offset )?,
+            end: read_u64( desc, offset + 8 )?,
+            file_offset: read_u64( desc, offset + 16 )?.checked_mul( page_size )?,
+            path: String::from_utf8_lossy( name ).into_owned()
+        });
+    }
+
+    Some( files )
+}
+
+fn parse_core( data: &[u8] ) -> Result< Core, String > {
+    if data.get( 0..4 ) != Some( b"\x7FELF" ) {
+        return Err( "not an ELF file".into() );
+    }
+
+    if data.get( 4 ) != Some( &2 ) || data.get( 5 ) != Some( &1 ) {
+        return Err( "only 64-bit little endian core dumps are supported".into() );
+    }
+
+    let truncated = || "truncated ELF header".to_owned();
+    if read_u16( data, 16 ).ok_or_else( truncated )? != ET_CORE {
+        return Err( "not a core dump".into() );
+    }
+
+    let machine = read_u16( data, 18 ).ok_or_else( truncated )?;
+    let phoff = read_u64( data, 32 ).ok_or_else( truncated )? as usize;
+    let phentsize = read_u16( data, 54 ).ok_or_else( truncated )? as usize;
+    let phnum = read_u16( data, 56 ).ok_or_else( truncated )? as usize;
+
+    let mut core = Core {
+        machine,
+        segments: Vec::new(),
+        threads: Vec::new(),
+        files: Vec::new()
+    };
+
+    let truncated = || "truncated program header".to_owned();
+    let mut notes = Vec::new();
+    for index in 0..phnum {
+        let offset = index.checked_mul( phentsize ).and_then( |offset| offset.checked_add( phoff ) ).ok_or_else( truncated )?;
+        let kind = read_u32( data, offset ).ok_or_else( truncated )?;
+        let flags = read_u32( data, offset + 4 ).ok_or_else( truncated )?;
+        let file_offset = read_u64( data, offset + 8 ).ok_or_else( truncated )?;
+        let address = read_u64( data, offset + 16 ).ok_or_else( truncated )?;
+        let file_size = read_u64( data, offset + 32 ).ok_or_else( truncated )?;
+        match kind {
+            PT_LOAD => core.segments.push( Segment { address, file_offset, file_size, flags } ),
+            PT_NOTE => {
+                let truncated = || "truncated PT_NOTE segment".to_owned();
+                let end = file_offset.checked_add( file_size ).ok_or_else( truncated )?;
+                let notes_data = data.get( file_offset as usize..end as usize ).ok_or_else( truncated )?;
+                notes.push( notes_data );
+            },
+            _ => {}
+        }
+    }
+
+    let align = |value: usize| (value + 3) & !3;
+    for mut notes_data in notes {
+        while notes_data.len() >= 12 {
+            let name_size = read_u32( notes_data, 0 ).unwrap() as usize;
+            let desc_size = read_u32( notes_data, 4 ).unwrap() as usize;
+            let kind = read_u32( notes_data, 8 ).unwrap();
+            let desc_offset = 12 + align( name_size );
+            let desc = notes_data.get( desc_offset..desc_offset + desc_size ).ok_or_else( || "truncated note".to_owned() )?;
+            match kind {
+                NT_PRSTATUS => {
+                    let truncated = || "truncated NT_PRSTATUS note".to_owned();
+                    let registers = desc.get( PRSTATUS_REGS_OFFSET.. ).ok_or_else( truncated )?;
+                    core.threads.push( Thread {
+                        tid: read_u32( desc, PRSTATUS_PID_OFFSET ).ok_or_else( truncated )?,
+                        signal: read_u16( desc, PRSTATUS_CURSIG_OFFSET ).ok_or_else( truncated )?,
+                        registers: registers.chunks_exact( 8 ).map( |chunk| read_u64( chunk, 0 ).unwrap() ).collect()
+                    });
+                },
+                NT_FILE => {
+                    core.files = parse_nt_file( desc ).ok_or_else( || "malformed NT_FILE note".to_owned() )?;
+                },
+                _ => {}
+            }
+
+            notes_data = notes_data.get( desc_offset + align( desc_size ).. ).unwrap_or( &[] );
+        }
+    }
+
+    Ok( core )
+}
+
+pub(crate) fn into_dwarf_regs( machine: u16, registers: &[u64], dwarf_regs: &mut DwarfRegs ) {
+    dwarf_regs.clear();
+    match machine {
+        EM_X86_64 => {
+            for (&register, &value) in AMD64_REGS.iter().zip( registers ) {
+                if register != SKIP {
+                    dwarf_regs.append( register, value );
+                }
+            }
+        },
+        EM_AARCH64 => {
+            for (register, &value) in registers.iter().take( AARCH64_REG_COUNT ).enumerate() {
+                dwarf_regs.append( register as u16, value );
+            }
+        },
+        _ => unreachable!()
+    }
+}
+
+/// Builds the regions of the address space from the files which were mapped into the process.
+fn into_regions( core: &Core ) -> Vec< Region > {
+    core.files.iter().enumerate().map( |(index, file)| {
+        let flags = core.segments.iter()
+            .find( |segment| segment.address == file.start )
+            .map( |segment| segment.flags )
+            .unwrap_or( PF_R | PF_X );
+
+        Region {
+            start: file.start,
+            end: file.end,
+            is_read: flags & PF_R != 0,
+            is_write: flags & PF_W != 0,
+            is_executable: flags & PF_X != 0,
+            is_shared: false,
+            file_offset: file.file_offset,
+            major: 0,
+            minor: 0,
+            // The inode doesn't matter as long as it's non-zero since the binaries are identified by their paths.
+            inode: index as u64 + 1,
+            name: file.path.clone()
+        }
+    }).collect()
+}
+
+/// Prints a symbolized backtrace, one line per frame, including the inlined ones.
+pub(crate) fn write_backtrace( fp: &mut dyn Write, address_space: &dyn IAddressSpace, backtrace: &[UserFrame], result: Result< (), UnwindFailure > ) -> io::Result< () > {
+    let mut nth_frame = 0;
+    for (index, frame) in backtrace.iter().enumerate() {
+        let address = if index == 0 { frame.address } else { frame.address.saturating_sub( 1 ) };
+        let mut found = false;
+        address_space.decode_symbol_while( address, &mut |symbol| {
+            let name = symbol.demangled_name.as_ref().or( symbol.name.as_ref() ).map( |name| name.as_ref() ).unwrap_or( "??" );
+            let library = symbol.library.as_ref().map( |library| library.as_ref() ).unwrap_or( "" );
+            let library = &library[ library.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+            let inline = if symbol.is_inline { " [inlined]" } else { "" };
+            let _ = write!( fp, "  #{:<3} 0x{:016X} in {}{} ({})", nth_frame, frame.address, name, inline, library );
+            if let (Some( file ), Some( line )) = (symbol.file.as_ref(), symbol.line) {
+                let _ = write!( fp, " at {}:{}", file, line );
+            }
+            let _ = writeln!( fp );
+
+            nth_frame += 1;
+            found = true;
+            true
+        });
+
+        if !found {
+            writeln!( fp, "  #{:<3} 0x{:016X} in ??", nth_frame, frame.address )?;
+            nth_frame += 1;
+        }
+    }
+
+    if let Err( failure ) = result {
+        writeln!( fp, "  (unwinding stopped: {})", failure )?;
+    }
+
+    Ok(())
+}
+
+pub fn main( args: args::AnalyzeCoreArgs ) -> Result< (), Box< dyn Error > > {
+    let data = fs::read( &args.core ).map_err( |err| format!( "cannot read {:?}: {}", args.core, err ) )?;
+    let core = parse_core( &data ).map_err( |err| format!( "cannot parse {:?}: {}", args.core, err ) )?;
+
+    let mut address_space: Box< dyn IAddressSpace > = match core.machine {
+        EM_X86_64 => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
+        EM_AARCH64 => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
+        machine => return Err( format!( "unsupported architecture of the core dump: e_machine = {}", machine ).into() )
+    };
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    for path in args.binaries.iter().chain( args.debug_symbols.iter() ) {
+        debug_info_index.add( path );
+    }
+
+    if let Some( ref sysroot ) = args.sysroot {
+        debug_info_index.set_sysroot( sysroot );
+    }
+
+    let mut missing = HashSet::new();
+    address_space.reload( into_regions( &core ), &mut |region, handle| {
+        let (binary, debug_binary) = debug_info_index.get_pair( &region.name, None, None );
+        let binary = binary.or_else( || {
+            let path = match args.sysroot {
+                Some( ref sysroot ) => Path::new( sysroot ).join( region.name.trim_start_matches( '/' ) ),
+                None => Path::new( &region.name ).to_owned()
+            };
+
+            BinaryData::load_from_fs( &path ).ok().map( |binary| binary.into() )
+        });
+
+        match binary {
+            Some( binary ) => handle.set_binary( binary ),
+            None => {
+                if missing.insert( region.name.clone() ) {
+                    warn!( "Cannot find '{}'; pass it on the command line if it's available elsewhere", region.name );
+                }
+                return;
+            }
+        }
+
+        if let Some( debug_binary ) = debug_binary {
+            handle.set_debug_binary( debug_binary );
+        }
+    });
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut dwarf_regs = DwarfRegs::new();
+    let mut user_backtrace = Vec::new();
+    for (nth_thread, thread) in core.threads.iter().enumerate() {
+        write!( stdout, "Thread #{} (TID {})", nth_thread + 1, thread.tid )?;
+        if thread.signal != 0 {
+            write!( stdout, ", received signal {}", thread.signal )?;
+        }
+        writeln!( stdout, ":" )?;
+
+        into_dwarf_regs( core.machine, &thread.registers, &mut dwarf_regs );
+        let stack_pointer = match core.machine {
+            EM_X86_64 => dwarf_regs.get( arch::amd64::Arch::STACK_POINTER_REG ),
+            _ => dwarf_regs.get( arch::aarch64::Arch::STACK_POINTER_REG )
+        };
+
+        let stack = core.memory_at( &data, stack_pointer.unwrap_or( 0 ) ).to_vec();
+        let result = address_space.unwind( &mut dwarf_regs, &stack, &mut user_backtrace );
+
+        write_backtrace( &mut stdout, &*address_space, &user_backtrace, result )?;
+
+        writeln!( stdout )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn build_core( notes: &[(u32, Vec< u8 >)], stack_address: u64, stack: &[u8] ) -> Vec< u8 > {
+    let mut notes_data = Vec::new();
+    for (kind, desc) in notes {
+        notes_data.extend_from_slice( &5_u32.to_le_bytes() );
+        notes_data.extend_from_slice( &(desc.len() as u32).to_le_bytes() );
+        notes_data.extend_from_slice( &kind.to_le_bytes() );
+        notes_data.extend_from_slice( b"CORE\0\0\0\0" );
+        notes_data.extend_from_slice( desc );
+        while notes_data.len() % 4 != 0 {
+            notes_data.push( 0 );
+        }
+    }
+
+    let phoff = 64;
+    let notes_offset = phoff + 2 * 56;
+    let stack_offset = notes_offset + notes_data.len();
+
+    let mut data = vec![ 0; 64 ];
+    data[ 0..4 ].copy_from_slice( b"\x7FELF" );
+    data[ 4 ] = 2;
+    data[ 5 ] = 1;
+    data[ 16..18 ].copy_from_slice( &ET_CORE.to_le_bytes() );
+    data[ 18..20 ].copy_from_slice( &EM_X86_64.to_le_bytes() );
+    data[ 32..40 ].copy_from_slice( &(phoff as u64).to_le_bytes() );
+    data[ 54..56 ].copy_from_slice( &56_u16.to_le_bytes() );
+    data[ 56..58 ].copy_from_slice( &2_u16.to_le_bytes() );
+
+    for &(kind, flags, offset, address, size) in &[
+        (PT_NOTE, 0, notes_offset, 0, notes_data.len()),
+        (PT_LOAD, PF_R | PF_W, stack_offset, stack_address, stack.len())
+    ] {
+        let mut header = vec![ 0; 56 ];
+        header[ 0..4 ].copy_from_slice( &kind.to_le_bytes() );
+        header[ 4..8 ].copy_from_slice( &flags.to_le_bytes() );
+        header[ 8..16 ].copy_from_slice( &(offset as u64).to_le_bytes() );
+        header[ 16..24 ].copy_from_slice( &address.to_le_bytes() );
+        header[ 32..40 ].copy_from_slice( &(size as u64).to_le_bytes() );
+        data.extend_from_slice( &header );
+    }
+
+    data.extend_from_slice( &notes_data );
+    data.extend_from_slice( stack );
+    data
+}
+
+#[test]
+fn test_parse_core() {
+    let mut prstatus = vec![ 0; PRSTATUS_REGS_OFFSET ];
+    prstatus[ PRSTATUS_CURSIG_OFFSET..PRSTATUS_CURSIG_OFFSET + 2 ].copy_from_slice( &11_u16.to_le_bytes() );
+    prstatus[ PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4 ].copy_from_slice( &1234_u32.to_le_bytes() );
+    for value in 0..27_u64 {
+        prstatus.extend_from_slice( &value.to_le_bytes() );
+    }
+
+    let mut nt_file = Vec::new();
+    for value in &[ 1, 0x1000, 0x400000, 0x401000, 2 ] {
+        nt_file.extend_from_slice( &(*value as u64).to_le_bytes() );
+    }
+    nt_file.extend_from_slice( b"/usr/bin/app\0" );
+
+    let data = build_core( &[ (NT_PRSTATUS, prstatus), (NT_FILE, nt_file) ], 0x7000, &[ 1, 2, 3, 4 ] );
+    let core = parse_core( &data ).unwrap();
+    assert_eq!( core.machine, EM_X86_64 );
+    assert_eq!( core.threads, vec![ Thread { tid: 1234, signal: 11, registers: (0..27).collect() } ] );
+    assert_eq!( core.files, vec![ MappedFile { start: 0x400000, end: 0x401000, file_offset: 0x2000, path: "/usr/bin/app".to_owned() } ] );
+    assert_eq!( core.memory_at( &data, 0x7002 ), &[ 3, 4 ] );
+    assert_eq!( core.memory_at( &data, 0x7004 ), &[] as &[u8] );
+
+    let mut dwarf_regs = DwarfRegs::new();
+    into_dwarf_regs( core.machine, &core.threads[ 0 ].registers, &mut dwarf_regs );
+    assert_eq!( dwarf_regs.get( arch::amd64::dwarf::R15 ), Some( 0 ) );
+    assert_eq!( dwarf_regs.get( arch::amd64::dwarf::RETURN_ADDRESS ), Some( 16 ) );
+    assert_eq!( dwarf_regs.get( arch::amd64::dwarf::RSP ), Some( 19 ) );
+
+    assert!( parse_core( b"\x7FELF" ).is_err() );
+    assert!( parse_core( b"not an ELF" ).is_err() );
+}
+
+#[test]
+fn test_parse_malformed_nt_file() {
+    let mut nt_file = Vec::new();
+    for value in &[ 2, 0x1000, 0x400000, 0x401000, 2 ] {
+        nt_file.extend_from_slice( &(*value as u64).to_le_bytes() );
+    }
+    nt_file.extend_from_slice( b"/usr/bin/app\0" );
+
+    // Two files are declared, but there's only an entry for one of them.
+    assert_eq!( parse_nt_file( &nt_file ), None );
+    assert!( parse_core( &build_core( &[ (NT_FILE, nt_file.clone()) ], 0x7000, &[] ) ).is_err() );
+
+    // A count so big that the size of the entries overflows.
+    nt_file[ 0..8 ].copy_from_slice( &u64::MAX.to_le_bytes() );
+    assert_eq!( parse_nt_file( &nt_file ), None );
+    nt_file[ 0..8 ].copy_from_slice( &(u64::MAX / 24).to_le_bytes() );
+    assert_eq!( parse_nt_file( &nt_file ), None );
+
+    // A file offset which overflows when multiplied by the page size.
+    nt_file[ 0..8 ].copy_from_slice( &1_u64.to_le_bytes() );
+    nt_file[ 32..40 ].copy_from_slice( &u64::MAX.to_le_bytes() );
+    assert_eq!( parse_nt_file( &nt_file ), None );
+
+    assert_eq!( parse_nt_file( &nt_file[ ..12 ] ), None );
+}
@@ -0,0 +1,402 @@
+use std::fs;
+use std::error::Error;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+use nwind::{BinaryId, LoadHeader, Symbols};
+use proc_maps::Region;
+
+use crate::args::{self, Granularity};
+use crate::interner::{StringId, StringInterner};
+use crate::data_reader::{Binary, State, DecodeOpts, EventKind, FrameKind, read_data, repack_cli_args};
+use crate::disassembler::{disassemble, find_instruction};
+use crate::utils::percent;
+
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub(crate) struct Counts {
+    pub self_samples: u64,
+    pub total_samples: u64
+}
+
+impl Counts {
+    pub(crate) fn add( &mut self, count: u64, is_self: bool ) {
+        self.total_samples += count;
+        if is_self {
+            self.self_samples += count;
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Function {
+    pub samples: Counts,
+    pub lines: HashMap< (StringId, u64), Counts >,
+    // Keyed by the virtual address as seen in the binary's load headers.
+    addresses: HashMap< u64, Counts >,
+    // The PID of one of the processes in which the function was sampled,
+    // and the offset from the binary's virtual addresses to absolute addresses in that process.
+    mapping: Option< (u32, u64) >
+}
+
+/// Translates an absolute `address` from a given memory `region`
+/// into a virtual address as seen in the binary's load headers.
+fn to_virtual_address( region: &Region, load_headers: &[LoadHeader], address: u64 ) -> Option< u64 > {
+    let offset = address.checked_sub( region.start )? + region.file_offset;
+    let header = load_headers.iter().find( |header| offset >= header.file_offset && offset < header.file_offset + header.file_size )?;
+    Some( offset - header.file_offset + header.address )
+}
+
+/// Returns the inclusive ranges of lines which should be shown
+/// to display every one of the `lines` with the given amount of `context`.
+fn line_windows( lines: &[u64], context: u64 ) -> Vec< (u64, u64) > {
+    let mut lines = lines.to_vec();
+    lines.sort();
+
+    let mut windows: Vec< (u64, u64) > = Vec::new();
+    for line in lines {
+        let start = line.saturating_sub( context ).max( 1 );
+        let end = line + context;
+        if let Some( last ) = windows.last_mut() {
+            if start <= last.1 + 1 {
+                last.1 = end;
+                continue;
+            }
+        }
+
+        windows.push( (start, end) );
+    }
+
+    windows
+}
+
+fn write_counts( output: &mut dyn Write, counts: Option< &Counts >, total: u64 ) -> io::Result< () > {
+    match counts {
+        Some( counts ) if counts.total_samples > 0 => {
+            let self_percent = percent( counts.self_samples, total );
+            if counts.self_samples > 0 {
+                write!( output, "{:>7.2}% ", self_percent )?;
+            } else {
+                write!( output, "{:>9}", "" )?;
+            }
+
+            write!( output, "{:>7.2}% ", percent( counts.total_samples, total ) )
+        },
+        _ => write!( output, "{:>18}", "" )
+    }
+}
+
+pub(crate) fn write_function(
+    interner: &StringInterner,
+    function: &Function,
+    context: u64,
+    output: &mut dyn Write
+) -> io::Result< () > {
+    let total = function.samples.total_samples;
+
+    let mut lines_for_file: HashMap< StringId, HashMap< u64, Counts > > = HashMap::new();
+    for (&(file, line), counts) in &function.lines {
+        lines_for_file.entry( file ).or_insert_with( HashMap::new ).insert( line, *counts );
+    }
+
+    let mut files: Vec< _ > = lines_for_file.into_iter().collect();
+    let file_samples = |lines: &HashMap< u64, Counts >| lines.values().map( |counts| counts.total_samples ).sum::< u64 >();
+    files.sort_by( |a, b| file_samples( &b.1 ).cmp( &file_samples( &a.1 ) ) );
+
+    if files.is_empty() {
+        writeln!( output, "  No line information available." )?;
+    }
+
+    for (file, lines) in files {
+        let path = interner.resolve( file ).unwrap();
+        writeln!( output, "  {}", path )?;
+        writeln!( output, "{:>9}{:>9} {:>6}  Source", "Self", "Total", "Line" )?;
+
+        let source = fs::read_to_string( path ).ok();
+        let source: Option< Vec< &str > > = source.as_ref().map( |source| source.lines().collect() );
+        let hit_lines: Vec< _ > = lines.keys().cloned().collect();
+
+        match source {
+            Some( source ) => {
+                for (nth, &(start, end)) in line_windows( &hit_lines, context ).iter().enumerate() {
+                    if nth != 0 {
+                        writeln!( output, "{:>18} {:>6}", "", "..." )?;
+                    }
+
+                    for line in start..=end.min( source.len() as u64 ) {
+                        write_counts( output, lines.get( &line ), total )?;
+                        writeln!( output, "{:>6}  {}", line, source[ line as usize - 1 ] )?;
+                    }
+                }
+            },
+            None => {
+                let mut hit_lines = hit_lines;
+                hit_lines.sort();
+                for line in hit_lines {
+                    write_counts( output, lines.get( &line ), total )?;
+                    writeln!( output, "{:>6}", line )?;
+                }
+            }
+        }
+
+        writeln!( output )?;
+    }
+
+    Ok(())
+}
+
+fn write_disassembly(
+    state: &State,
+    binary: &Binary,
+    symbol: &str,
+    function: &Function,
+    output: &mut dyn Write
+) -> Result< (), Box< dyn Error > > {
+    let total = function.samples.total_samples;
+    let (first_address, last_address) = match (function.addresses.keys().min(), function.addresses.keys().max()) {
+        (Some( &first ), Some( &last )) => (first, last),
+        _ => {
+            writeln!( output, "  No address information available." )?;
+            writeln!( output )?;
+            return Ok(());
+        }
+    };
+
+    let data = binary.load_contents().ok_or_else( || {
+        format!( "cannot disassemble '{}': the contents of '{}' are not available", symbol, binary.path() )
+    })?;
+
+    let symbols = Symbols::load_from_binary_data( &data );
+    let range = match symbols.get_symbol( first_address ) {
+        Some( (range, _) ) if range.end > last_address => range,
+        _ => {
+            warn!( "No symbol table entry found for '{}'; only the sampled instructions will be disassembled", symbol );
+            first_address..last_address + 16
+        }
+    };
+
+    let start = if data.architecture() == "arm" { range.start & !1 } else { range.start };
+    let header = data.load_headers().iter().find( |header| start >= header.address && start < header.address + header.file_size ).ok_or_else( || {
+        format!( "cannot disassemble '{}': address 0x{:x} is not mapped in '{}'", symbol, start, binary.path() )
+    })?;
+
+    let offset = (start - header.address + header.file_offset) as usize;
+    let length = range.end.min( header.address + header.file_size ) - start;
+    // The file might not actually match the recorded binary, e.g. if it's a stale debug file.
+    let code = match data.as_bytes().get( offset..offset + length as usize ) {
+        Some( code ) => code,
+        None => {
+            warn!( "Cannot disassemble '{}': the code at 0x{:x} lies outside of '{}'", symbol, start, binary.path() );
+            writeln!( output, "  No code available." )?;
+            writeln!( output )?;
+            return Ok(());
+        }
+    };
+    let instructions = disassemble( data.architecture(), data.endianness(), code, range.start )?;
+
+    let mut counts = vec![ Counts::default(); instructions.len() ];
+    for (&address, address_counts) in &function.addresses {
+        if let Some( index ) = find_instruction( &instructions, address ) {
+            counts[ index ].self_samples += address_counts.self_samples;
+            counts[ index ].total_samples += address_counts.total_samples;
+        }
+    }
+
+    let process = function.mapping.and_then( |(pid, bias)| state.get_process( pid ).map( |process| (process, bias) ) );
+    let mut sources: HashMap< String, Option< Vec< String > > > = HashMap::new();
+    let mut last_location = None;
+
+    writeln!( output, "{:>8}{:>9} {:<18}  Instruction", "Self", "Total", "Address" )?;
+    for (instruction, counts) in instructions.iter().zip( counts.iter() ) {
+        if let Some( (process, bias) ) = process {
+            let location = process.source_location( instruction.address.wrapping_add( bias ) )
+                .map( |(file, line)| (state.map_source_path( &file ).into_owned(), line) );
+            if location.is_some() && location != last_location {
+                let (file, line) = location.as_ref().unwrap();
+                let source = sources.entry( file.clone() ).or_insert_with( || {
+                    fs::read_to_string( file ).ok().map( |source| source.lines().map( |line| line.to_owned() ).collect() )
+                });
+
+                write!( output, "{:>38}{}:{}", "", file, line )?;
+                match source.as_ref().and_then( |source| line.checked_sub( 1 ).and_then( |index| source.get( index as usize ) ) ) {
+                    Some( text ) => writeln!( output, "  {}", text.trim() )?,
+                    None => writeln!( output )?
+                }
+                last_location = location;
+            }
+        }
+
+        write_counts( output, Some( counts ), total )?;
+        writeln!( output, "0x{:016x}  {}", instruction.address, instruction.text )?;
+    }
+
+    writeln!( output )?;
+    Ok(())
+}
+
+pub fn main( args: args::AnnotateArgs ) -> Result< (), Box< dyn Error > > {
+    if args.asm && !cfg!( feature = "disassembler" ) {
+        return Err( "`--asm` requires nperf to be compiled with the `disassembler` feature".into() );
+    }
+
+    let function_regex = Regex::new( &args.function ).map_err( |err| format!( "invalid regexp passed in `--function`: {}", err ) )?;
+
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: false,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let mut interner = StringInterner::new();
+    let mut functions: HashMap< (BinaryId, StringId), Function > = HashMap::new();
+    let mut is_matching_symbol: HashMap< StringId, bool > = HashMap::new();
+    let mut sample_count = 0;
+    let mut frames = Vec::new();
+    let mut seen_functions = HashSet::new();
+    let mut seen_lines = HashSet::new();
+    let mut seen_addresses = HashSet::new();
+    let state = read_data( read_data_args, |event| {
+        let sample = match event.kind {
+            EventKind::Sample( sample ) => sample,
+            _ => return
+        };
+
+        frames.clear();
+        if !sample.try_decode( &event.state, &opts, &mut interner, Some( &mut frames ) ) {
+            return;
+        }
+
+        sample_count += 1;
+        seen_functions.clear();
+        seen_lines.clear();
+        seen_addresses.clear();
+
+        // The frames are ordered from the leaf; the leaf frame and every inline
+        // frame up to the first non-inline one share the same instruction.
+        let mut is_self = true;
+        for frame in &frames {
+            let (binary_id, is_inline, symbol, address, file, line) = match *frame {
+                FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, file, line } => (binary_id, is_inline, symbol, address, file, line),
+                _ => {
+                    is_self = false;
+                    continue;
+                }
+            };
+
+            let is_matching = *is_matching_symbol.entry( symbol ).or_insert_with( || {
+                function_regex.is_match( interner.resolve( symbol ).unwrap() )
+            });
+
+            if is_matching {
+                let key = (binary_id.clone(), symbol);
+                let function = functions.entry( key.clone() ).or_insert_with( Function::default );
+                if seen_functions.insert( key.clone() ) {
+                    function.samples.add( 1, is_self );
+                }
+
+                if args.asm {
+                    let region = sample.process.memory_region( address );
+                    let load_headers = event.state.get_binary( binary_id ).load_headers();
+                    if let Some( virtual_address ) = region.and_then( |region| to_virtual_address( region, load_headers, address ) ) {
+                        if function.mapping.is_none() {
+                            function.mapping = Some( (sample.process.pid(), address.wrapping_sub( virtual_address )) );
+                        }
+
+                        if seen_addresses.insert( (key.clone(), virtual_address) ) {
+                            function.addresses.entry( virtual_address ).or_insert_with( Counts::default ).add( 1, is_self );
+                        }
+                    }
+                }
+
+                if let (Some( file ), Some( line )) = (file, line) {
+                    if seen_lines.insert( (key, file, line) ) {
+                        function.lines.entry( (file, line) ).or_insert_with( Counts::default ).add( 1, is_self );
+                    }
+                }
+            }
+
+            if !is_inline {
+                is_self = false;
+            }
+        }
+    })?;
+
+    let mut functions: Vec< _ > = functions.into_iter().collect();
+    if functions.is_empty() {
+        return Err( format!( "no samples found for a function matching '{}'", args.function ).into() );
+    }
+
+    functions.sort_by( |a, b| b.1.samples.total_samples.cmp( &a.1.samples.total_samples ) );
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    for ((binary_id, symbol), function) in functions {
+        writeln!(
+            stdout,
+            "{} [{}]: {} samples ({:.2}% of all), {} self ({:.2}% of all)",
+            interner.resolve( symbol ).unwrap(),
+            state.get_binary( &binary_id ).basename(),
+            function.samples.total_samples,
+            percent( function.samples.total_samples, sample_count ),
+            function.samples.self_samples,
+            percent( function.samples.self_samples, sample_count )
+        )?;
+        writeln!( stdout )?;
+
+        if args.asm {
+            write_disassembly( &state, state.get_binary( &binary_id ), interner.resolve( symbol ).unwrap(), &function, &mut stdout )?;
+        } else {
+            write_function( &interner, &function, args.context, &mut stdout )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_to_virtual_address() {
+    let region = Region {
+        start: 0x7f00_0000_1000,
+        end: 0x7f00_0000_3000,
+        is_read: true,
+        is_write: false,
+        is_executable: true,
+        is_shared: false,
+        file_offset: 0x1000,
+        major: 0,
+        minor: 0,
+        inode: 0,
+        name: String::new()
+    };
+
+    let header = LoadHeader {
+        address: 0x401000,
+        file_offset: 0x1000,
+        file_size: 0x2000,
+        memory_size: 0x2000,
+        alignment: 0x1000,
+        is_readable: true,
+        is_writable: false,
+        is_executable: true
+    };
+
+    assert_eq!( to_virtual_address( &region, &[ header.clone() ], 0x7f00_0000_1000 ), Some( 0x401000 ) );
+    assert_eq!( to_virtual_address( &region, &[ header.clone() ], 0x7f00_0000_1234 ), Some( 0x401234 ) );
+    assert_eq!( to_virtual_address( &region, &[ header.clone() ], 0x7f00_0000_0fff ), None );
+    assert_eq!( to_virtual_address( &region, &[], 0x7f00_0000_1234 ), None );
+}
+
+#[test]
+fn test_line_windows() {
+    assert_eq!( line_windows( &[], 3 ), vec![] );
+    assert_eq!( line_windows( &[ 10 ], 3 ), vec![ (7, 13) ] );
+    assert_eq!( line_windows( &[ 2 ], 3 ), vec![ (1, 5) ] );
+    assert_eq!( line_windows( &[ 20, 10, 14 ], 3 ), vec![ (7, 23) ] );
+    assert_eq!( line_windows( &[ 10, 17 ], 3 ), vec![ (7, 20) ] );
+    assert_eq!( line_windows( &[ 10, 30 ], 3 ), vec![ (7, 13), (27, 33) ] );
+}
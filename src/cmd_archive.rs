@@ -0,0 +1,239 @@
+use std::fs::{self, File};
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::args;
+use crate::data_reader::{read_data, repack_cli_args};
+
+fn hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+}
+
+/// Returns the path under which a file should be stored in the archive.
+///
+/// Files are keyed by their build IDs if they have one, in the same layout
+/// as used by `/usr/lib/debug/.build-id`; otherwise the original path is used.
+fn archive_path( original_path: &str, build_id: Option< &[u8] >, is_debug: bool ) -> PathBuf {
+    match build_id {
+        Some( build_id ) if build_id.len() > 1 => {
+            let build_id = hex( build_id );
+            let filename = if is_debug { format!( "{}.debug", &build_id[ 2.. ] ) } else { build_id[ 2.. ].to_owned() };
+            Path::new( ".build-id" ).join( &build_id[ ..2 ] ).join( filename )
+        },
+        _ => Path::new( "by-path" ).join( original_path.trim_start_matches( '/' ) )
+    }
+}
+
+fn write_octal( field: &mut [u8], value: u64 ) {
+    let digits = format!( "{:0width$o}", value, width = field.len() - 1 );
+    field[ ..digits.len() ].copy_from_slice( digits.as_bytes() );
+    field[ digits.len() ] = 0;
+}
+
+/// Writes a single file in the POSIX ustar format.
+fn write_tar_entry( output: &mut dyn Write, path: &str, data: &[u8] ) -> io::Result< () > {
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        // The prefix can take up to 155 bytes, so the separator has to be within the first 156.
+        let mut end = path.len().min( 156 );
+        while !path.is_char_boundary( end ) {
+            end -= 1;
+        }
+
+        let index = path[ ..end ].rfind( '/' ).filter( |&index| path.len() - index - 1 <= 100 );
+        match index {
+            Some( index ) => (&path[ ..index ], &path[ index + 1.. ]),
+            None => return Err( io::Error::new( io::ErrorKind::InvalidInput, format!( "path is too long: {}", path ) ) )
+        }
+    };
+
+    let mut header = [0; 512];
+    header[ 0..name.len() ].copy_from_slice( name.as_bytes() );
+    write_octal( &mut header[ 100..108 ], 0o644 );
+    write_octal( &mut header[ 108..116 ], 0 );
+    write_octal( &mut header[ 116..124 ], 0 );
+    write_octal( &mut header[ 124..136 ], data.len() as u64 );
+    write_octal( &mut header[ 136..148 ], 0 );
+    header[ 156 ] = b'0';
+    header[ 257..263 ].copy_from_slice( b"ustar\0" );
+    header[ 263..265 ].copy_from_slice( b"00" );
+    header[ 345..345 + prefix.len() ].copy_from_slice( prefix.as_bytes() );
+
+    // The checksum is calculated as if the checksum field was filled with spaces.
+    header[ 148..156 ].copy_from_slice( b"        " );
+    let checksum: u64 = header.iter().map( |&byte| byte as u64 ).sum();
+    write_octal( &mut header[ 148..155 ], checksum );
+    header[ 155 ] = b' ';
+
+    output.write_all( &header )?;
+    output.write_all( data )?;
+
+    let padding = (512 - data.len() % 512) % 512;
+    output.write_all( &[0; 512][ ..padding ] )?;
+    Ok(())
+}
+
+enum Output {
+    Directory( PathBuf ),
+    Tar( io::BufWriter< File > ),
+    TarGz( GzEncoder< io::BufWriter< File > > )
+}
+
+impl Output {
+    fn add( &mut self, path: &Path, data: &[u8] ) -> io::Result< () > {
+        match *self {
+            Output::Directory( ref root ) => {
+                let path = root.join( path );
+                fs::create_dir_all( path.parent().unwrap() )?;
+                fs::write( path, data )
+            },
+            Output::Tar( ref mut output ) => {
+                write_tar_entry( output, &path.to_string_lossy(), data )
+            },
+            Output::TarGz( ref mut output ) => {
+                write_tar_entry( output, &path.to_string_lossy(), data )
+            }
+        }
+    }
+
+    fn finish( self ) -> io::Result< () > {
+        match self {
+            Output::Directory( _ ) => Ok(()),
+            Output::Tar( mut output ) => {
+                output.write_all( &[0; 1024] )?;
+                output.flush()
+            },
+            Output::TarGz( mut output ) => {
+                output.write_all( &[0; 1024] )?;
+                output.finish()?.flush()
+            }
+        }
+    }
+}
+
+pub fn main( args: args::ArchiveArgs ) -> Result< (), Box< dyn Error > > {
+    let output_path = Path::new( &args.output );
+    let output_name = output_path.to_string_lossy();
+    let mut output = if output_name.ends_with( ".tar.gz" ) || output_name.ends_with( ".tgz" ) {
+        let fp = File::create( output_path ).map_err( |err| format!( "cannot create {:?}: {}", output_path, err ) )?;
+        Output::TarGz( GzEncoder::new( io::BufWriter::new( fp ), Compression::default() ) )
+    } else if output_name.ends_with( ".tar" ) {
+        let fp = File::create( output_path ).map_err( |err| format!( "cannot create {:?}: {}", output_path, err ) )?;
+        Output::Tar( io::BufWriter::new( fp ) )
+    } else {
+        fs::create_dir_all( output_path ).map_err( |err| format!( "cannot create {:?}: {}", output_path, err ) )?;
+        Output::Directory( output_path.to_owned() )
+    };
+
+    let (_, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let mut state = read_data( read_data_args, |_| {} )?;
+
+    let mut binaries: Vec< _ > = state.binaries().map( |(binary_id, binary)| {
+        (binary_id.clone(), binary.path().to_owned(), binary.build_id().map( |build_id| build_id.to_owned() ), binary.is_embedded())
+    }).collect();
+    binaries.sort_by( |a, b| a.1.cmp( &b.1 ) );
+
+    let mut added = HashSet::new();
+    let mut missing = Vec::new();
+    for (binary_id, path, build_id, is_embedded) in binaries {
+        let (binary, debug_binary) = state.find_local_binary( &binary_id );
+        // Binaries which were embedded in the recording don't have to be archived.
+        let binary = if is_embedded { None } else { binary.or_else( || state.get_binary( &binary_id ).load_contents() ) };
+        if binary.is_none() && debug_binary.is_none() {
+            if !is_embedded {
+                missing.push( path );
+            }
+            continue;
+        }
+
+        for (data, is_debug) in binary.into_iter().map( |data| (data, false) ).chain( debug_binary.into_iter().map( |data| (data, true) ) ) {
+            let original_path = if is_debug {
+                let directory = Path::new( &path ).parent().unwrap_or( Path::new( "/" ) );
+                let filename = Path::new( data.name() ).file_name().map( |filename| filename.to_owned() ).unwrap_or_default();
+                directory.join( filename ).to_string_lossy().into_owned()
+            } else {
+                path.clone()
+            };
+
+            // The files are keyed by the build IDs from the recording since that's what they'll be looked up by.
+            let target_path = archive_path( &original_path, build_id.as_ref().map( |build_id| build_id.as_slice() ), is_debug );
+            if !added.insert( target_path.clone() ) {
+                continue;
+            }
+
+            info!( "Adding {} as {:?}", data.name(), target_path );
+            output.add( &target_path, data.as_bytes() )?;
+        }
+    }
+
+    output.finish()?;
+
+    for path in &missing {
+        warn!( "No local copy found for '{}'", path );
+    }
+
+    info!( "Archived {} file(s) into {:?}; use it with `--debug-symbols` after extracting", added.len(), output_path );
+    Ok(())
+}
+
+#[test]
+fn test_archive_path() {
+    assert_eq!( archive_path( "/usr/lib/libfoo.so", Some( &[0xab, 0xcd, 0xef] ), false ), Path::new( ".build-id/ab/cdef" ) );
+    assert_eq!( archive_path( "/usr/lib/libfoo.so", Some( &[0xab, 0xcd, 0xef] ), true ), Path::new( ".build-id/ab/cdef.debug" ) );
+    assert_eq!( archive_path( "/usr/lib/libfoo.so", None, false ), Path::new( "by-path/usr/lib/libfoo.so" ) );
+}
+
+#[test]
+fn test_write_tar_entry() {
+    let mut output = Vec::new();
+    write_tar_entry( &mut output, "a/b.txt", b"hello" ).unwrap();
+    assert_eq!( output.len(), 1024 );
+    assert_eq!( &output[ 0..7 ], b"a/b.txt" );
+    assert_eq!( &output[ 124..136 ], b"00000000005\0" );
+    assert_eq!( &output[ 257..263 ], b"ustar\0" );
+    assert_eq!( &output[ 512..517 ], b"hello" );
+
+    let checksum: u64 = output[ ..512 ].iter().enumerate().map( |(index, &byte)| if index >= 148 && index < 156 { b' ' as u64 } else { byte as u64 } ).sum();
+    assert_eq!( &output[ 148..156 ], format!( "{:06o}\0 ", checksum ).as_bytes() );
+
+    let long_path = format!( "{}/{}", "d".repeat( 120 ), "f".repeat( 50 ) );
+    let mut output = Vec::new();
+    write_tar_entry( &mut output, &long_path, b"" ).unwrap();
+    assert_eq!( &output[ 0..50 ], "f".repeat( 50 ).as_bytes() );
+    assert_eq!( &output[ 345..465 ], "d".repeat( 120 ).as_bytes() );
+
+    assert!( write_tar_entry( &mut Vec::new(), &"x".repeat( 101 ), b"" ).is_err() );
+
+    // The 156th byte falls in the middle of a multi-byte character.
+    let long_path = format!( "{}ą/{}", "d".repeat( 155 ), "f".repeat( 50 ) );
+    assert!( write_tar_entry( &mut Vec::new(), &long_path, b"" ).is_err() );
+
+    let long_path = format!( "{}ą/{}", "d".repeat( 152 ), "f".repeat( 50 ) );
+    let mut output = Vec::new();
+    write_tar_entry( &mut output, &long_path, b"" ).unwrap();
+    assert_eq!( &output[ 345..499 ], format!( "{}ą", "d".repeat( 152 ) ).as_bytes() );
+}
+
+#[test]
+fn test_finish_tar_gz() {
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    let path = std::env::temp_dir().join( format!( "nperf-archive-{}.tar.gz", std::process::id() ) );
+    let mut output = Output::TarGz( GzEncoder::new( io::BufWriter::new( File::create( &path ).unwrap() ), Compression::default() ) );
+    output.add( Path::new( "a/b.txt" ), b"hello" ).unwrap();
+    output.finish().unwrap();
+
+    let mut tar = Vec::new();
+    GzDecoder::new( File::open( &path ).unwrap() ).read_to_end( &mut tar ).unwrap();
+    fs::remove_file( &path ).unwrap();
+
+    assert_eq!( tar.len(), 1024 + 1024 );
+    assert_eq!( &tar[ 512..517 ], b"hello" );
+}
@@ -0,0 +1,302 @@
+use std::fs::{self, File};
+use std::mem;
+use std::ptr;
+use std::error::Error;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+
+use proc_maps::Region;
+use nwind::arch::{self, Architecture, Registers};
+use nwind::{AddressSpace, DebugInfoIndex, DwarfRegs, IAddressSpace};
+
+use crate::args;
+use crate::cmd_analyze_core::{NT_PRSTATUS, into_dwarf_regs, write_backtrace};
+use crate::mount_info::PathResolver;
+use crate::profiler::load_binary_for_region;
+use crate::utils::read_string_lossy;
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) const MACHINE: Option< u16 > = Some( crate::cmd_analyze_core::EM_X86_64 );
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) const MACHINE: Option< u16 > = Some( crate::cmd_analyze_core::EM_AARCH64 );
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) const MACHINE: Option< u16 > = None;
+
+pub(crate) struct Thread {
+    pub tid: u32,
+    pub name: String,
+    pub registers: Vec< u64 >,
+    pub stack: Vec< u8 >
+}
+
+/// Keeps a thread stopped for as long as it's alive.
+pub(crate) struct Attached {
+    tid: u32,
+    pending_signals: Vec< libc::c_int >
+}
+
+impl Attached {
+    pub(crate) fn new( tid: u32 ) -> io::Result< Self > {
+        unsafe {
+            if libc::ptrace( libc::PTRACE_SEIZE, tid as libc::pid_t, ptr::null_mut::< libc::c_void >(), ptr::null_mut::< libc::c_void >() ) < 0 {
+                return Err( io::Error::last_os_error() );
+            }
+
+            let mut attached = Attached { tid, pending_signals: Vec::new() };
+            if libc::ptrace( libc::PTRACE_INTERRUPT, tid as libc::pid_t, ptr::null_mut::< libc::c_void >(), ptr::null_mut::< libc::c_void >() ) < 0 {
+                return Err( io::Error::last_os_error() );
+            }
+
+            loop {
+                let mut status = 0;
+                if libc::waitpid( tid as libc::pid_t, &mut status, libc::__WALL ) < 0 {
+                    return Err( io::Error::last_os_error() );
+                }
+
+                if !libc::WIFSTOPPED( status ) {
+                    return Err( io::Error::from_raw_os_error( libc::ESRCH ) );
+                }
+
+                if status >> 16 == libc::PTRACE_EVENT_STOP {
+                    break;
+                }
+
+                // A signal arrived before our interrupt did; it'll be sent again once we detach.
+                if status >> 16 == 0 {
+                    attached.pending_signals.push( libc::WSTOPSIG( status ) );
+                }
+
+                if libc::ptrace( libc::PTRACE_CONT, tid as libc::pid_t, ptr::null_mut::< libc::c_void >(), ptr::null_mut::< libc::c_void >() ) < 0 {
+                    return Err( io::Error::last_os_error() );
+                }
+            }
+
+            Ok( attached )
+        }
+    }
+
+    fn registers( &self ) -> io::Result< Vec< u64 > > {
+        let mut registers = vec![ 0_u64; 64 ];
+        let mut iovec = libc::iovec {
+            iov_base: registers.as_mut_ptr() as *mut libc::c_void,
+            iov_len: registers.len() * mem::size_of::< u64 >()
+        };
+
+        let result = unsafe {
+            libc::ptrace( libc::PTRACE_GETREGSET, self.tid as libc::pid_t, NT_PRSTATUS as usize as *mut libc::c_void, &mut iovec as *mut libc::iovec )
+        };
+
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        registers.truncate( iovec.iov_len / mem::size_of::< u64 >() );
+        Ok( registers )
+    }
+}
+
+impl Drop for Attached {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::ptrace( libc::PTRACE_DETACH, self.tid as libc::pid_t, ptr::null_mut::< libc::c_void >(), ptr::null_mut::< libc::c_void >() );
+        }
+
+        // The signal passed to `PTRACE_DETACH` is ignored unless the thread is in a signal-delivery-stop,
+        // which it isn't since we've waited for the interrupt, so the signals have to be sent again.
+        for &signal in &self.pending_signals {
+            unsafe {
+                libc::syscall( libc::SYS_tkill, self.tid as libc::pid_t, signal );
+            }
+        }
+    }
+}
+
+fn get_threads( pid: u32 ) -> io::Result< Vec< (u32, String) > > {
+    let mut threads = Vec::new();
+    for entry in fs::read_dir( format!( "/proc/{}/task", pid ) )? {
+        let tid: u32 = match entry?.file_name().to_string_lossy().parse() {
+            Ok( tid ) => tid,
+            Err( _ ) => continue
+        };
+
+        let name = read_string_lossy( format!( "/proc/{}/task/{}/comm", pid, tid ) ).unwrap_or_default();
+        threads.push( (tid, name.trim_end().to_owned()) );
+    }
+
+    threads.sort_by_key( |&(tid, _)| (tid != pid, tid) );
+    Ok( threads )
+}
+
+/// Grabs the registers and the top of the stack of an already stopped thread.
+pub(crate) fn read_thread( handle: &Attached, regions: &[Region], memory: &File, stack_size: u32 ) -> Option< (Vec< u64 >, Vec< u8 >) > {
+    let registers = match handle.registers() {
+        Ok( registers ) => registers,
+        Err( error ) => {
+            warn!( "Failed to read the registers of TID {}: {}", handle.tid, error );
+            return None;
+        }
+    };
+
+    let mut dwarf_regs = DwarfRegs::new();
+    into_dwarf_regs( MACHINE.unwrap(), &registers, &mut dwarf_regs );
+    let stack_pointer = match MACHINE {
+        Some( crate::cmd_analyze_core::EM_X86_64 ) => dwarf_regs.get( arch::amd64::Arch::STACK_POINTER_REG ),
+        _ => dwarf_regs.get( arch::aarch64::Arch::STACK_POINTER_REG )
+    }.unwrap_or( 0 );
+
+    let mut stack = Vec::new();
+    if let Some( region ) = regions.iter().find( |region| stack_pointer >= region.start && stack_pointer < region.end ) {
+        stack.resize( std::cmp::min( stack_size as u64, region.end - stack_pointer ) as usize, 0 );
+        if let Err( error ) = memory.read_exact_at( &mut stack, stack_pointer ) {
+            warn!( "Failed to read the stack of TID {}: {}", handle.tid, error );
+            stack.clear();
+        }
+    }
+
+    Some( (registers, stack) )
+}
+
+/// Stops every thread of the process, grabs their registers and the tops of their stacks,
+/// and lets them go; everything else is done only after the process is running again.
+pub(crate) fn snapshot( pid: u32, stack_size: u32 ) -> Result< (Vec< Region >, Vec< Thread >), Box< dyn Error > > {
+    let threads = get_threads( pid ).map_err( |err| format!( "cannot read the threads of PID {}: {}", pid, err ) )?;
+    let mut attached = Vec::new();
+    for (tid, name) in threads {
+        match Attached::new( tid ) {
+            Ok( handle ) => attached.push( (handle, name) ),
+            Err( ref error ) if error.raw_os_error() == Some( libc::ESRCH ) => continue,
+            Err( error ) => return Err( format!( "cannot attach to TID {}: {}", tid, error ).into() )
+        }
+    }
+
+    let maps = read_string_lossy( format!( "/proc/{}/maps", pid ) ).map_err( |err| format!( "cannot read /proc/{}/maps: {}", pid, err ) )?;
+    let regions = proc_maps::parse( &maps );
+    let memory = File::open( format!( "/proc/{}/mem", pid ) ).map_err( |err| format!( "cannot open /proc/{}/mem: {}", pid, err ) )?;
+
+    let mut threads = Vec::new();
+    for (handle, name) in attached {
+        if let Some( (registers, stack) ) = read_thread( &handle, &regions, &memory, stack_size ) {
+            threads.push( Thread { tid: handle.tid, name, registers, stack } );
+        }
+    }
+
+    Ok( (regions, threads) )
+}
+
+pub fn main( args: args::BacktraceArgs ) -> Result< (), Box< dyn Error > > {
+    let machine = match MACHINE {
+        Some( machine ) => machine,
+        None => return Err( "the backtrace subcommand is not supported on this architecture".into() )
+    };
+
+    let pid = args.pid;
+    let path_resolver = match PathResolver::new_for_pid( pid ) {
+        Ok( value ) => Some( value ),
+        Err( error ) => {
+            warn!( "Failed to process the mounts: {}", error );
+            None
+        }
+    };
+
+    let (regions, threads) = snapshot( pid, args.stack_size )?;
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    for path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
+
+    let mut address_space = AddressSpace::< arch::native::Arch >::new();
+    address_space.reload( regions, &mut |region, handle| {
+        let binary = match load_binary_for_region( &path_resolver, region, None ) {
+            Some( binary ) => binary,
+            None => return
+        };
+
+        if let Some( debug_binary ) = debug_info_index.get( &region.name, binary.debuglink(), binary.build_id() ) {
+            handle.set_debug_binary( debug_binary );
+        }
+
+        handle.set_binary( binary.into() );
+    });
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut dwarf_regs = DwarfRegs::new();
+    let mut user_backtrace = Vec::new();
+    for (nth_thread, thread) in threads.iter().enumerate() {
+        writeln!( stdout, "Thread #{} (TID {}) \"{}\":", nth_thread + 1, thread.tid, thread.name )?;
+
+        into_dwarf_regs( machine, &thread.registers, &mut dwarf_regs );
+        let result = address_space.unwind( &mut dwarf_regs, &thread.stack, &mut user_backtrace );
+        write_backtrace( &mut stdout, &address_space, &user_backtrace, result )?;
+        writeln!( stdout )?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_signals_are_not_lost_when_attaching() {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static WRITE_FD: AtomicI32 = AtomicI32::new( -1 );
+    extern "C" fn on_signal( _: libc::c_int ) {
+        unsafe {
+            libc::write( WRITE_FD.load( Ordering::SeqCst ), b"x".as_ptr() as *const libc::c_void, 1 );
+        }
+    }
+
+    let mut fds = [0; 2];
+    assert_eq!( unsafe { libc::pipe( fds.as_mut_ptr() ) }, 0 );
+    WRITE_FD.store( fds[ 1 ], Ordering::SeqCst );
+
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        unsafe {
+            let on_signal: extern "C" fn( libc::c_int ) = on_signal;
+            libc::signal( libc::SIGUSR1, on_signal as libc::sighandler_t );
+            on_signal( 0 );
+            loop {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    // Wait until the handler is installed.
+    let mut byte = 0_u8;
+    assert_eq!( unsafe { libc::read( fds[ 0 ], &mut byte as *mut u8 as *mut libc::c_void, 1 ) }, 1 );
+
+    for _ in 0..1000 {
+        unsafe {
+            libc::kill( pid, libc::SIGUSR1 );
+        }
+
+        drop( Attached::new( pid as u32 ).unwrap() );
+
+        let mut pollfd = libc::pollfd { fd: fds[ 0 ], events: libc::POLLIN, revents: 0 };
+        let received = unsafe { libc::poll( &mut pollfd, 1, 5000 ) } == 1;
+        if received {
+            unsafe {
+                libc::read( fds[ 0 ], &mut byte as *mut u8 as *mut libc::c_void, 1 );
+            }
+        }
+
+        if !received {
+            unsafe {
+                libc::kill( pid, libc::SIGKILL );
+                libc::waitpid( pid, ptr::null_mut(), 0 );
+            }
+
+            panic!( "the signal was lost" );
+        }
+    }
+
+    unsafe {
+        libc::kill( pid, libc::SIGKILL );
+        libc::waitpid( pid, ptr::null_mut(), 0 );
+        libc::close( fds[ 0 ] );
+        libc::close( fds[ 1 ] );
+    }
+}
@@ -0,0 +1,166 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::args::{self, Granularity};
+use crate::interner::StringInterner;
+use crate::data_reader::{DecodeOpts, EventKind, read_data, repack_cli_args};
+
+static IS_INSTALLED: AtomicBool = AtomicBool::new( false );
+static ALLOCATION_COUNT: AtomicU64 = AtomicU64::new( 0 );
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new( 0 );
+
+/// A global allocator which keeps track of how many allocations were made;
+/// the binary has to install it with `#[global_allocator]` for `bench` to report the allocation stats.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc( &self, layout: Layout ) -> *mut u8 {
+        IS_INSTALLED.store( true, Ordering::Relaxed );
+        ALLOCATION_COUNT.fetch_add( 1, Ordering::Relaxed );
+        ALLOCATED_BYTES.fetch_add( layout.size() as u64, Ordering::Relaxed );
+        System.alloc( layout )
+    }
+
+    unsafe fn dealloc( &self, pointer: *mut u8, layout: Layout ) {
+        System.dealloc( pointer, layout )
+    }
+
+    unsafe fn realloc( &self, pointer: *mut u8, layout: Layout, new_size: usize ) -> *mut u8 {
+        ALLOCATION_COUNT.fetch_add( 1, Ordering::Relaxed );
+        ALLOCATED_BYTES.fetch_add( new_size as u64, Ordering::Relaxed );
+        System.realloc( pointer, layout, new_size )
+    }
+}
+
+fn allocation_stats() -> Option< (u64, u64) > {
+    if !IS_INSTALLED.load( Ordering::Relaxed ) {
+        return None;
+    }
+
+    Some( (ALLOCATION_COUNT.load( Ordering::Relaxed ), ALLOCATED_BYTES.load( Ordering::Relaxed )) )
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+struct Iteration {
+    elapsed: Duration,
+    sample_count: u64,
+    frame_count: u64,
+    allocations: Option< (u64, u64) >
+}
+
+impl Iteration {
+    fn frames_per_second( &self ) -> f64 {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+
+        self.frame_count as f64 / elapsed
+    }
+
+    fn describe( &self ) -> String {
+        let mut output = format!(
+            "{} samples, {} frames in {:.3}s ({:.0} frames/s)",
+            self.sample_count,
+            self.frame_count,
+            self.elapsed.as_secs_f64(),
+            self.frames_per_second()
+        );
+
+        if let Some( (count, bytes) ) = self.allocations {
+            output.push_str( &format!( ", {} allocations ({:.1} MB)", count, bytes as f64 / (1024.0 * 1024.0) ) );
+            if self.frame_count > 0 {
+                output.push_str( &format!( ", {:.2} allocations/frame", count as f64 / self.frame_count as f64 ) );
+            }
+        }
+
+        output
+    }
+}
+
+fn run_iteration( args: &args::BenchArgs, interner: &mut StringInterner ) -> Result< Iteration, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let allocations_before = allocation_stats();
+    let timestamp = Instant::now();
+    let mut iteration = Iteration::default();
+    let mut frames = Vec::new();
+    read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                frames.clear();
+                if !sample.try_decode( event.state, &opts, interner, Some( &mut frames ) ) {
+                    return;
+                }
+
+                iteration.sample_count += 1;
+                iteration.frame_count += frames.len() as u64;
+            },
+            _ => {}
+        }
+    })?;
+
+    iteration.elapsed = timestamp.elapsed();
+    iteration.allocations = match (allocations_before, allocation_stats()) {
+        (Some( (count_before, bytes_before) ), Some( (count_after, bytes_after) )) => Some( (count_after - count_before, bytes_after - bytes_before) ),
+        _ => None
+    };
+
+    Ok( iteration )
+}
+
+pub fn main( args: args::BenchArgs ) -> Result< (), Box< dyn Error > > {
+    if args.iterations == 0 {
+        return Err( "the number of iterations must be greater than zero".into() );
+    }
+
+    // The same interner is reused so that only the first iteration pays for interning the symbol names.
+    let mut interner = StringInterner::new();
+    let mut iterations = Vec::with_capacity( args.iterations as usize );
+    for nth_iteration in 0..args.iterations {
+        let iteration = run_iteration( &args, &mut interner )?;
+        println!( "Iteration #{}: {}", nth_iteration + 1, iteration.describe() );
+        iterations.push( iteration );
+    }
+
+    if allocation_stats().is_none() {
+        info!( "The allocation stats are not available since the counting allocator is not installed; rebuild with the `counting-allocator` feature" );
+    }
+
+    let best = iterations.iter().max_by( |lhs, rhs| lhs.frames_per_second().partial_cmp( &rhs.frames_per_second() ).unwrap() ).unwrap();
+    let mean = iterations.iter().map( |iteration| iteration.frames_per_second() ).sum::< f64 >() / iterations.len() as f64;
+    println!();
+    println!( "Best: {:.0} frames/s", best.frames_per_second() );
+    println!( "Mean: {:.0} frames/s", mean );
+
+    Ok(())
+}
+
+#[test]
+fn test_describe_iteration() {
+    let mut iteration = Iteration {
+        elapsed: Duration::from_millis( 500 ),
+        sample_count: 10,
+        frame_count: 200,
+        allocations: None
+    };
+
+    assert_eq!( iteration.frames_per_second(), 400.0 );
+    assert_eq!( iteration.describe(), "10 samples, 200 frames in 0.500s (400 frames/s)" );
+
+    iteration.allocations = Some( (1000, 2 * 1024 * 1024) );
+    assert_eq!( iteration.describe(), "10 samples, 200 frames in 0.500s (400 frames/s), 1000 allocations (2.0 MB), 5.00 allocations/frame" );
+
+    assert_eq!( Iteration::default().frames_per_second(), 0.0 );
+}
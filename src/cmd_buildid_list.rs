@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::args;
+use crate::data_reader::{read_data, repack_cli_args};
+
+fn format_build_id( build_id: Option< &[u8] > ) -> String {
+    match build_id {
+        Some( build_id ) => build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect(),
+        None => "-".to_owned()
+    }
+}
+
+pub fn main( args: args::BuildidListArgs ) -> Result< (), Box< dyn Error > > {
+    let (_, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let mut state = read_data( read_data_args, |_| {} )?;
+
+    let mut binaries: Vec< _ > = state.binaries().map( |(binary_id, binary)| {
+        (binary_id.clone(), binary.path().to_owned(), binary.build_id().map( |build_id| build_id.to_owned() ), binary.is_embedded())
+    }).collect();
+    binaries.sort_by( |a, b| a.1.cmp( &b.1 ) );
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    writeln!( stdout, "# {:<40}  {:<10}  {}", "Build ID", "Debug info", "Path [local copy]" )?;
+
+    let mut missing_count = 0;
+    for (binary_id, path, build_id, is_embedded) in binaries {
+        let (binary, debug_binary) = state.find_local_binary( &binary_id );
+        let binary = binary.or_else( || state.get_binary( &binary_id ).load_contents() );
+        let has_debug_info = binary.iter().chain( debug_binary.iter() ).any( |data| data.has_debug_info() );
+        if !has_debug_info {
+            missing_count += 1;
+        }
+
+        write!(
+            stdout,
+            "  {:<40}  {:<10}  {}",
+            format_build_id( build_id.as_ref().map( |build_id| build_id.as_slice() ) ),
+            if has_debug_info { "yes" } else { "no" },
+            path
+        )?;
+
+        match debug_binary.as_ref().or( binary.as_ref() ) {
+            Some( data ) if data.name() != path => writeln!( stdout, " [{}]", data.name() )?,
+            Some( _ ) if is_embedded => writeln!( stdout, " [embedded in the recording]" )?,
+            Some( _ ) => writeln!( stdout )?,
+            None => writeln!( stdout, " [not found]" )?
+        }
+    }
+
+    if missing_count > 0 {
+        writeln!( stdout, "#" )?;
+        writeln!( stdout, "# {} binaries have no debug info available locally; use `--debug-symbols`, `--sysroot` or `--binary-path-map` to point nperf to them", missing_count )?;
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
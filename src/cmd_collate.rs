@@ -1,18 +1,87 @@
 use std::io::{self, Write};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::Path;
 use std::fmt::Write as FmtWrite;
 use std::error::Error;
 use std::borrow::Cow;
+use std::sync::Arc;
 
+use crate::anonymize::{Anonymization, Anonymizer};
 use crate::args::{self, Granularity};
 use crate::interner::StringInterner;
+use crate::speedscope;
+use crate::script::Script;
 
-use crate::data_reader::{State, DecodeOpts, EventKind, EventSample, FrameKind, read_data, repack_cli_args, write_frame};
+use crate::folded;
+use crate::exporter::to_sample;
+use crate::quality::QualityStats;
+use crate::data_reader::{State, ReadDataArgs, StackFilter, DecodeOpts, Event, EventKind, EventSample, FrameKind, read_data, repack_cli_args, write_frame, unwind_failure_frame_name};
 
 #[derive(Debug)]
 pub enum CollateFormat {
     Collapsed,
-    PerfLike
+    PerfLike,
+    Speedscope,
+    JsonSamples
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GroupBy {
+    Cgroup
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SplitBy {
+    Pid,
+    Tid,
+    Cpu
+}
+
+/// The collapsed stacks belonging to a single process or thread.
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct CollapsedGroup {
+    /// A short identifier suitable to be used as a part of a filename.
+    pub key: String,
+    pub name: String,
+    pub lines: Vec< String >
+}
+
+/// Counts the samples whose unwinding stopped prematurely, and the lost events.
+#[derive(Default)]
+struct UnwindFailureCounter {
+    sample_count: u64,
+    failure_count: u64,
+    lost_event_count: u64
+}
+
+impl UnwindFailureCounter {
+    fn add( &mut self, sample: &EventSample ) {
+        self.sample_count += 1;
+        if sample.unwind_failure.is_some() {
+            self.failure_count += 1;
+        }
+    }
+
+    fn add_lost( &mut self, count: u64 ) {
+        self.lost_event_count += count;
+    }
+
+    fn log( &self ) {
+        let percent = if self.sample_count == 0 { 0.0 } else { self.failure_count as f64 * 100.0 / self.sample_count as f64 };
+        info!( "{} out of {} samples ({:.2}%) have truncated callstacks", self.failure_count, self.sample_count, percent );
+    }
+
+    fn check( &self, thresholds: &args::ArgQualityThresholds ) -> Result< (), Box< dyn Error > > {
+        let stats = QualityStats {
+            sample_count: self.sample_count,
+            lost_event_count: self.lost_event_count,
+            unwound_sample_count: self.sample_count,
+            unwind_failure_count: self.failure_count
+        };
+
+        stats.check( thresholds.fail_if_lost, thresholds.fail_if_unwind_failures )
+    }
 }
 
 fn escape< 'a >( string: &'a str ) -> Cow< 'a, str > {
@@ -23,6 +92,19 @@ fn escape< 'a >( string: &'a str ) -> Cow< 'a, str > {
     output
 }
 
+fn write_json_sample_output< T: io::Write >(
+    state: &State,
+    sample: EventSample,
+    frames: &[FrameKind],
+    interner: &StringInterner,
+    output: &mut T
+) -> Result< (), Box< dyn Error > > {
+    serde_json::to_writer( &mut *output, &to_sample( state, &sample, frames, interner ) )?;
+    writeln!( output )?;
+
+    Ok(())
+}
+
 fn write_perf_like_output< T: io::Write >(
     state: &State,
     sample: EventSample,
@@ -57,7 +139,7 @@ fn write_perf_like_output< T: io::Write >(
                 let binary = state.get_binary( binary_id );
                 writeln!( output, "\t{:16X} 0x{:016X} ({})", address, address, binary.basename() )?;
             },
-            FrameKind::UserByAddress { ref binary_id, address, is_inline, symbol } => {
+            FrameKind::UserByAddress { ref binary_id, address, is_inline, symbol, .. } => {
                 let binary = state.get_binary( binary_id );
                 let symbol = interner.resolve( *symbol ).unwrap();
                 if *is_inline {
@@ -66,6 +148,15 @@ fn write_perf_like_output< T: io::Write >(
                     writeln!( output, "\t{:16X} {} ({})", address, symbol, binary.basename() )?;
                 }
             },
+            FrameKind::UnwindFailure( failure ) => {
+                writeln!( output, "\t{:16X} {} ([unknown])", 0, unwind_failure_frame_name( *failure ) )?;
+            },
+            FrameKind::Python { symbol, .. } => {
+                writeln!( output, "\t{:16X} {} ([python])", 0, interner.resolve( *symbol ).unwrap() )?;
+            },
+            FrameKind::Jit( symbol ) => {
+                writeln!( output, "\t{:16X} {} ([jit])", 0, interner.resolve( *symbol ).unwrap() )?;
+            },
             _ => unreachable!()
         }
     }
@@ -78,77 +169,423 @@ fn write_perf_like_output< T: io::Write >(
 pub fn collapse_into_sorted_vec(
     args: &args::SharedCollationArgs,
     arg_granularity: &args::ArgGranularity,
-    arg_merge_threads: &args::ArgMergeThreads
+    arg_merge_threads: &args::ArgMergeThreads,
+    arg_mark_truncated: &args::ArgMarkTruncated,
+    arg_group_by: &args::ArgGroupBy,
+    arg_quality_thresholds: Option< &args::ArgQualityThresholds >
+) -> Result< Vec< String >, Box< dyn Error > > {
+    let emit_cgroup_frames = arg_group_by.group_by == Some( GroupBy::Cgroup );
+    let mut groups = collapse_into_groups_impl( args, arg_granularity.granularity, !arg_merge_threads.merge_threads, true, arg_mark_truncated.mark_truncated, emit_cgroup_frames, None, arg_quality_thresholds )?;
+    Ok( groups.pop().map( |group| group.lines ).unwrap_or_default() )
+}
+
+/// Collapses the stacks without any PID or TID-specific frames so that
+/// the output from two different profiling runs can be compared.
+pub fn collapse_into_sorted_vec_for_comparison(
+    args: &args::SharedCollationArgs,
+    arg_granularity: &args::ArgGranularity
 ) -> Result< Vec< String >, Box< dyn Error > > {
-    let (omit_regex, read_data_args) = repack_cli_args( args );
+    let mut groups = collapse_into_groups_impl( args, arg_granularity.granularity, false, false, false, false, None, None )?;
+    Ok( groups.pop().map( |group| group.lines ).unwrap_or_default() )
+}
+
+/// Collapses the stacks separately for every process, thread or CPU.
+pub fn collapse_into_groups(
+    args: &args::SharedCollationArgs,
+    arg_granularity: &args::ArgGranularity,
+    arg_merge_threads: &args::ArgMergeThreads,
+    arg_mark_truncated: &args::ArgMarkTruncated,
+    arg_group_by: &args::ArgGroupBy,
+    arg_quality_thresholds: Option< &args::ArgQualityThresholds >,
+    split_by: SplitBy
+) -> Result< Vec< CollapsedGroup >, Box< dyn Error > > {
+    let emit_thread_frames = split_by == SplitBy::Pid && !arg_merge_threads.merge_threads;
+    let emit_cgroup_frames = arg_group_by.group_by == Some( GroupBy::Cgroup );
+    collapse_into_groups_impl( args, arg_granularity.granularity, emit_thread_frames, false, arg_mark_truncated.mark_truncated, emit_cgroup_frames, Some( split_by ), arg_quality_thresholds )
+}
+
+fn collapse_into_groups_impl(
+    args: &args::SharedCollationArgs,
+    granularity: Granularity,
+    emit_thread_frames: bool,
+    emit_process_frames: bool,
+    emit_unwind_failure_frames: bool,
+    emit_cgroup_frames: bool,
+    split_by: Option< SplitBy >,
+    arg_quality_thresholds: Option< &args::ArgQualityThresholds >
+) -> Result< Vec< CollapsedGroup >, Box< dyn Error > > {
+    let (recordings, folded_inputs) = folded::partition_inputs( &args.input )?;
+    if !folded_inputs.is_empty() && split_by.is_some() {
+        return Err( "`--split-by` cannot be used with collapsed stack files as inputs".into() );
+    }
+
+    if !folded_inputs.is_empty() {
+        folded::check_supported_options( args, granularity )?;
+    }
+
+    let script = match args.script {
+        Some( ref path ) => Some( Script::load( path )? ),
+        None => None
+    };
+
+    let folded_stacks = folded::read_folded( &folded_inputs )?;
+    let mut args = args.clone();
+    args.input = recordings;
+
+    let (filter, read_data_args) = repack_cli_args( &args )?;
     let opts = DecodeOpts {
-        omit_regex,
+        filter,
         emit_kernel_frames: true,
-        emit_thread_frames: !arg_merge_threads.merge_threads,
-        emit_process_frames: true,
-        granularity: arg_granularity.granularity
+        emit_thread_frames,
+        emit_process_frames,
+        emit_unwind_failure_frames,
+        emit_cgroup_frames,
+        granularity
     };
 
-    let mut stacks: HashMap< Vec< FrameKind >, u64 > = HashMap::new();
+    // With only collapsed stack files as inputs there's no recording to read.
+    let read_data_args = if args.input.is_empty() { None } else { Some( read_data_args ) };
+    collapse( read_data_args, &opts, folded_stacks, args.anonymize, split_by, arg_quality_thresholds, script.as_ref() )
+}
+
+fn collapse(
+    read_data_args: Option< ReadDataArgs >,
+    opts: &DecodeOpts,
+    mut folded_stacks: HashMap< String, u64 >,
+    anonymization: Option< Anonymization >,
+    split_by: Option< SplitBy >,
+    arg_quality_thresholds: Option< &args::ArgQualityThresholds >,
+    script: Option< &Script >
+) -> Result< Vec< CollapsedGroup >, Box< dyn Error > > {
+    let mut stacks: BTreeMap< u32, HashMap< Vec< FrameKind >, f64 > > = BTreeMap::new();
     let mut interner = StringInterner::new();
-    let state = read_data( read_data_args, |event| {
+    let mut counter = UnwindFailureCounter::default();
+    let mut on_event = |event: Event| {
         match event.kind {
             EventKind::Sample( sample ) => {
-                let frames = sample.decode( &event.state, &opts, &mut interner );
+                let frames = sample.decode( &event.state, opts, &mut interner );
                 if let Some( frames ) = frames {
-                    *stacks.entry( frames ).or_insert( 0 ) += 1;
+                    counter.add( &sample );
+                    let key = match split_by {
+                        Some( SplitBy::Pid ) => sample.process.pid(),
+                        Some( SplitBy::Tid ) => sample.tid,
+                        Some( SplitBy::Cpu ) => sample.cpu,
+                        None => 0
+                    };
+
+                    *stacks.entry( key ).or_default().entry( frames ).or_insert( 0.0 ) += sample.weight;
                 }
             },
+            EventKind::Lost { count } => counter.add_lost( count ),
             _ => {}
         }
-    })?;
+    };
 
-    let mut output = Vec::with_capacity( stacks.len() );
-    for (ref frames, count) in &stacks {
-        let mut line = String::new();
-        let mut is_first = true;
-        for frame in frames.into_iter().rev() {
-            if is_first {
-                is_first = false;
-            } else {
-                line.push( ';' );
+    let state = match read_data_args {
+        Some( read_data_args ) => Some( read_data( read_data_args, &mut on_event )? ),
+        None => None
+    };
+
+    if opts.emit_unwind_failure_frames {
+        counter.log();
+    }
+
+    if let Some( thresholds ) = arg_quality_thresholds {
+        counter.check( thresholds )?;
+    }
+
+    if let Some( weight ) = state.as_ref().map( |state| state.round_robin_weight() ).filter( |&weight| weight != 1.0 ) {
+        info!( "Only a part of the CPUs were sampled at a time; scaling the sample counts by {:.2}", weight );
+    }
+
+    if split_by.is_none() && stacks.is_empty() {
+        stacks.insert( 0, HashMap::new() );
+    }
+
+    // The same anonymizer has to be used for both kinds of inputs so that the same names match.
+    let fallback_anonymizer;
+    let anonymizer = match state.as_ref().and_then( |state| state.anonymizer() ) {
+        Some( anonymizer ) => Some( anonymizer ),
+        None => {
+            fallback_anonymizer = anonymization.map( Anonymizer::new );
+            fallback_anonymizer.as_ref()
+        }
+    };
+
+    let mut groups = Vec::with_capacity( stacks.len() );
+    for (key, stacks) in stacks {
+        let mut counts: HashMap< String, u64 > = HashMap::with_capacity( stacks.len() );
+        for (ref frames, count) in &stacks {
+            let state = state.as_ref().unwrap();
+            let mut line = String::new();
+            let mut is_first = true;
+            for frame in frames.into_iter().rev() {
+                if is_first {
+                    is_first = false;
+                } else {
+                    line.push( ';' );
+                }
+
+                write_frame( state, &interner, &mut line, frame );
             }
 
-            write_frame( &state, &interner, &mut line, frame );
+            *counts.entry( line ).or_insert( 0 ) += count.round() as u64;
+        }
+
+        for (line, count) in folded_stacks.drain() {
+            let line = match anonymizer {
+                Some( anonymizer ) => folded::anonymize_stack( anonymizer, &line ),
+                None => line
+            };
+
+            *counts.entry( line ).or_insert( 0 ) += count;
         }
 
-        write!( &mut line, " {}", count ).unwrap();
-        output.push( line );
+        if let Some( script ) = script {
+            counts = script.apply( counts )?;
+        }
+
+        let mut output: Vec< _ > = counts.into_iter().map( |(line, count)| format!( "{} {}", line, count ) ).collect();
+        output.sort_unstable();
+
+        let mut name = String::new();
+        let key = match split_by {
+            Some( SplitBy::Pid ) => {
+                write_frame( state.as_ref().unwrap(), &interner, &mut name, &FrameKind::Process( key ) );
+                format!( "pid-{}", key )
+            },
+            Some( SplitBy::Tid ) => {
+                write_frame( state.as_ref().unwrap(), &interner, &mut name, &FrameKind::Thread( key ) );
+                format!( "tid-{}", key )
+            },
+            Some( SplitBy::Cpu ) => {
+                write!( &mut name, "[CPU={}]", key ).unwrap();
+                format!( "cpu-{}", key )
+            },
+            None => String::new()
+        };
+
+        groups.push( CollapsedGroup {
+            key,
+            name,
+            lines: output
+        });
     }
 
-    output.sort_unstable();
-    Ok( output )
+    Ok( groups )
+}
+
+/// Collapses the stacks of a recording which is already in memory, e.g. in a web browser
+/// where there's no filesystem to read it from.
+pub fn collapse_from_memory( data: Arc< [u8] >, granularity: Granularity, merge_threads: bool ) -> Result< Vec< String >, Box< dyn Error > > {
+    let opts = DecodeOpts {
+        filter: StackFilter::default(),
+        emit_kernel_frames: true,
+        emit_thread_frames: !merge_threads,
+        emit_process_frames: true,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity
+    };
+
+    let mut groups = collapse( Some( ReadDataArgs::from_memory( data ) ), &opts, HashMap::new(), None, None, None, None )?;
+    Ok( groups.pop().map( |group| group.lines ).unwrap_or_default() )
+}
+
+/// Calls `callback` with a newly created file for every group
+/// in the `output_dir`, which will be created if necessary.
+pub fn write_groups(
+    groups: &[CollapsedGroup],
+    output_dir: &Path,
+    extension: &str,
+    mut callback: impl FnMut( &CollapsedGroup, io::BufWriter< File > ) -> Result< (), Box< dyn Error > >
+) -> Result< (), Box< dyn Error > > {
+    fs::create_dir_all( output_dir )?;
+    for group in groups {
+        let path = output_dir.join( format!( "{}.{}", group.key, extension ) );
+        let fp = File::create( &path ).map_err( |err| format!( "failed to create {:?}: {}", path, err ) )?;
+        callback( group, io::BufWriter::new( fp ) )?;
+        info!( "Written {:?} ({})", path, group.name );
+    }
+
+    Ok(())
+}
+
+fn write_speedscope_output< T: io::Write >( args: &args::CollateArgs, output: T ) -> Result< (), Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: args.arg_mark_truncated.mark_truncated,
+        emit_cgroup_frames: false,
+        granularity: args.arg_granularity.granularity
+    };
+
+    let mut interner = StringInterner::new();
+    let mut frame_to_index: HashMap< FrameKind, usize > = HashMap::new();
+    let mut frames = Vec::new();
+    let mut samples_for_thread: HashMap< u32, Vec< (u64, Vec< usize >) > > = HashMap::new();
+    let mut executable = None;
+    let mut counter = UnwindFailureCounter::default();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                let stack = match sample.decode( &event.state, &opts, &mut interner ) {
+                    Some( stack ) => stack,
+                    None => return
+                };
+
+                counter.add( &sample );
+
+                if executable.is_none() {
+                    executable = Some( sample.process.executable().to_owned() );
+                }
+
+                let stack = stack.into_iter().rev().map( |frame| {
+                    let index = frames.len();
+                    *frame_to_index.entry( frame.clone() ).or_insert_with( || {
+                        frames.push( frame );
+                        index
+                    })
+                }).collect();
+
+                samples_for_thread.entry( sample.tid ).or_insert_with( Vec::new ).push( (sample.timestamp, stack) );
+            },
+            EventKind::Lost { count } => counter.add_lost( count ),
+            _ => {}
+        }
+    })?;
+
+    if args.arg_mark_truncated.mark_truncated {
+        counter.log();
+    }
+
+    counter.check( &args.arg_quality_thresholds )?;
+
+    let shared = speedscope::Shared {
+        frames: frames.iter().map( |frame| {
+            let mut name = String::new();
+            write_frame( &state, &interner, &mut name, frame );
+
+            let (file, line) = match *frame {
+                FrameKind::UserByLine { file, line, .. } => (Some( interner.resolve( file ).unwrap().to_owned() ), Some( line )),
+                _ => (None, None)
+            };
+
+            speedscope::Frame { name, file, line }
+        }).collect()
+    };
+
+    let period = state.frequency().map( |frequency| 1_000_000_000 / frequency as u64 );
+    let first_timestamp = samples_for_thread.values().flat_map( |samples| samples.iter() ).map( |&(timestamp, _)| timestamp ).min().unwrap_or( 0 );
+
+    let mut tids: Vec< _ > = samples_for_thread.keys().cloned().collect();
+    tids.sort();
+
+    let mut profiles = Vec::with_capacity( tids.len() );
+    for tid in tids {
+        let mut samples = samples_for_thread.remove( &tid ).unwrap();
+        samples.sort_by_key( |&(timestamp, _)| timestamp );
+
+        let name = match state.get_thread_name( tid ) {
+            Some( name ) => format!( "{} [THREAD={}]", name, tid ),
+            None => format!( "[THREAD={}]", tid )
+        };
+
+        let profile = if let Some( period ) = period {
+            let start_value = samples.first().map( |&(timestamp, _)| timestamp - first_timestamp ).unwrap_or( 0 );
+            let end_value = samples.last().map( |&(timestamp, _)| timestamp - first_timestamp + period ).unwrap_or( 0 );
+            speedscope::SampledProfile {
+                kind: "sampled",
+                name,
+                unit: "nanoseconds",
+                start_value,
+                end_value,
+                weights: vec![ period; samples.len() ],
+                samples: samples.into_iter().map( |(_, stack)| stack ).collect()
+            }
+        } else {
+            speedscope::SampledProfile {
+                kind: "sampled",
+                name,
+                unit: "none",
+                start_value: 0,
+                end_value: samples.len() as u64,
+                weights: vec![ 1; samples.len() ],
+                samples: samples.into_iter().map( |(_, stack)| stack ).collect()
+            }
+        };
+
+        profiles.push( speedscope::Profile::Sampled( profile ) );
+    }
+
+    let file = speedscope::File {
+        schema: speedscope::SCHEMA,
+        shared,
+        profiles,
+        name: executable,
+        active_profile_index: 0,
+        exporter: format!( "nperf {}", env!( "CARGO_PKG_VERSION" ) )
+    };
+
+    serde_json::to_writer( output, &file )?;
+    Ok(())
 }
 
 pub fn main( args: args::CollateArgs ) -> Result< (), Box< dyn Error > > {
+    if args.arg_group_by.group_by.is_some() {
+        match args.format {
+            CollateFormat::Collapsed => {},
+            _ => return Err( "`--group-by` is only supported for the `collapsed` format".into() )
+        }
+    }
+
+    if let Some( split_by ) = args.arg_split_by.split_by {
+        let output_dir = match args.arg_split_by.output_dir {
+            Some( ref output_dir ) => Path::new( output_dir ),
+            None => return Err( "`--split-by` requires `--output-dir` to be specified".into() )
+        };
+
+        match args.format {
+            CollateFormat::Collapsed => {},
+            _ => return Err( "`--split-by` is only supported for the `collapsed` format".into() )
+        }
+
+        let groups = collapse_into_groups( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads, &args.arg_mark_truncated, &args.arg_group_by, Some( &args.arg_quality_thresholds ), split_by )?;
+        return write_groups( &groups, output_dir, "collapsed", |group, mut fp| {
+            fp.write_all( group.lines.join( "\n" ).as_bytes() )?;
+            Ok(())
+        });
+    }
+
     match args.format {
         CollateFormat::Collapsed => {
-            let output = collapse_into_sorted_vec( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads )?;
+            let output = collapse_into_sorted_vec( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads, &args.arg_mark_truncated, &args.arg_group_by, Some( &args.arg_quality_thresholds ) )?;
             let output = output.join( "\n" );
             let stdout = io::stdout();
             let mut stdout = stdout.lock();
             stdout.write_all( output.as_bytes() ).unwrap();
         },
         CollateFormat::PerfLike => {
-            let (omit_regex, read_data_args) = repack_cli_args( &args.collation_args );
+            let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
             let stdout = io::stdout();
             let mut stdout = stdout.lock();
 
             let mut interner = StringInterner::new();
             let mut frames = Vec::new();
             let opts = DecodeOpts {
-                omit_regex,
+                filter,
                 emit_kernel_frames: false,
                 emit_thread_frames: false,
                 emit_process_frames: false,
+                emit_unwind_failure_frames: args.arg_mark_truncated.mark_truncated,
+                emit_cgroup_frames: false,
                 granularity: Granularity::Address
             };
 
+            let mut counter = UnwindFailureCounter::default();
             read_data( read_data_args, |event| {
                 match event.kind {
                     EventKind::Sample( sample ) => {
@@ -156,6 +593,7 @@ pub fn main( args: args::CollateArgs ) -> Result< (), Box< dyn Error > > {
                             return; // Was filtered out.
                         }
 
+                        counter.add( &sample );
                         write_perf_like_output(
                             &event.state,
                             sample,
@@ -166,11 +604,217 @@ pub fn main( args: args::CollateArgs ) -> Result< (), Box< dyn Error > > {
 
                         frames.clear();
                     },
+                    EventKind::Lost { count } => counter.add_lost( count ),
+                    _ => {}
+                }
+            })?;
+
+            if args.arg_mark_truncated.mark_truncated {
+                counter.log();
+            }
+
+            counter.check( &args.arg_quality_thresholds )?;
+        },
+        CollateFormat::Speedscope => {
+            let stdout = io::stdout();
+            let stdout = stdout.lock();
+            write_speedscope_output( &args, io::BufWriter::new( stdout ) )?;
+        },
+        CollateFormat::JsonSamples => {
+            let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+            let stdout = io::stdout();
+            let mut stdout = io::BufWriter::new( stdout.lock() );
+
+            let mut interner = StringInterner::new();
+            let mut frames = Vec::new();
+            let opts = DecodeOpts {
+                filter,
+                emit_kernel_frames: false,
+                emit_thread_frames: false,
+                emit_process_frames: false,
+                emit_unwind_failure_frames: args.arg_mark_truncated.mark_truncated,
+                emit_cgroup_frames: false,
+                granularity: Granularity::Address
+            };
+
+            let mut result = Ok(());
+            let mut counter = UnwindFailureCounter::default();
+            read_data( read_data_args, |event| {
+                if result.is_err() {
+                    return;
+                }
+
+                match event.kind {
+                    EventKind::Sample( sample ) => {
+                        if !sample.try_decode( &event.state, &opts, &mut interner, Some( &mut frames ) ) {
+                            return; // Was filtered out.
+                        }
+
+                        counter.add( &sample );
+                        result = write_json_sample_output( &event.state, sample, &frames, &interner, &mut stdout );
+                        frames.clear();
+                    },
+                    EventKind::Lost { count } => counter.add_lost( count ),
                     _ => {}
                 }
             })?;
+
+            result?;
+            stdout.flush()?;
+
+            if args.arg_mark_truncated.mark_truncated {
+                counter.log();
+            }
+
+            counter.check( &args.arg_quality_thresholds )?;
         }
     }
 
     Ok(())
 }
+
+#[test]
+fn test_collapse_from_memory() {
+    let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-inline_functions.nperf" );
+    let from_memory = collapse_from_memory( fs::read( &path ).unwrap().into(), Granularity::Function, false ).unwrap();
+    assert!( !from_memory.is_empty() );
+
+    let opts = DecodeOpts {
+        filter: StackFilter::default(),
+        emit_kernel_frames: true,
+        emit_thread_frames: true,
+        emit_process_frames: true,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let mut groups = collapse( Some( ReadDataArgs::new( path.as_os_str(), Vec::new() ) ), &opts, HashMap::new(), None, None, None, None ).unwrap();
+    assert_eq!( from_memory, groups.pop().unwrap().lines );
+}
+
+#[test]
+fn test_rename_rules_by_address() {
+    use std::ffi::OsStr;
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-pthread_cond_wait.nperf" );
+    let rules = std::env::temp_dir().join( format!( "nperf-collate-symbol-rules-{}", std::process::id() ) );
+    fs::write( &rules, "rename-module ^libpthread.* renamed_pthread\n" ).unwrap();
+    let args = args::SharedCollationArgs::from_iter_safe( &[OsStr::new( "nperf" ), OsStr::new( "--symbol-rules" ), rules.as_os_str(), input.as_os_str()] ).unwrap();
+
+    type Writer = dyn Fn( &State, EventSample, &[FrameKind], &mut StringInterner, &mut Vec< u8 > );
+
+    // The frames from `libpthread` which couldn't be symbolized have to be renamed too.
+    let write_with = |write: &Writer| -> String {
+        let (filter, read_data_args) = repack_cli_args( &args ).unwrap();
+        let opts = DecodeOpts {
+            filter,
+            emit_kernel_frames: false,
+            emit_thread_frames: false,
+            emit_process_frames: false,
+            emit_unwind_failure_frames: false,
+            emit_cgroup_frames: false,
+            granularity: Granularity::Address
+        };
+
+        let mut interner = StringInterner::new();
+        let mut frames = Vec::new();
+        let mut output = Vec::new();
+        read_data( read_data_args, |event| {
+            if let EventKind::Sample( sample ) = event.kind {
+                if sample.try_decode( event.state, &opts, &mut interner, Some( &mut frames ) ) {
+                    write( event.state, sample, &frames, &mut interner, &mut output );
+                }
+                frames.clear();
+            }
+        }).unwrap();
+
+        String::from_utf8( output ).unwrap()
+    };
+
+    let perf_like = write_with( &|state, sample, frames, interner, output| write_perf_like_output( state, sample, frames, interner, output ).unwrap() );
+    assert!( perf_like.contains( " renamed_pthread (libpthread" ) );
+
+    let json_samples = write_with( &|state, sample, frames, interner, output| write_json_sample_output( state, sample, frames, interner, output ).unwrap() );
+    assert!( json_samples.contains( r#""symbol":"renamed_pthread""# ) );
+
+    let mut names = Vec::new();
+    crate::reader::for_each_sample( &args, |sample| names.extend( sample.frames.into_iter().map( |frame| frame.name ) ) ).unwrap();
+    assert!( names.iter().any( |name| name == "renamed_pthread" ) );
+
+    fs::remove_file( &rules ).unwrap();
+}
+
+#[test]
+fn test_anonymize_folded_inputs() {
+    use std::ffi::OsStr;
+    use regex::Regex;
+    use structopt::StructOpt;
+
+    let recording = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-inline_functions.nperf" );
+    let folded = std::env::temp_dir().join( format!( "nperf-collate-anonymize-{}.folded", std::process::id() ) );
+    fs::write( &folded, "main [app];secret_function [libsecret.so] 5\n" ).unwrap();
+
+    let collapse = |inputs: &[&OsStr]| {
+        let mut argv = vec![ OsStr::new( "nperf" ), OsStr::new( "--anonymize" ), OsStr::new( "strip" ) ];
+        argv.extend( inputs );
+        let args = args::SharedCollationArgs::from_iter_safe( &argv ).unwrap();
+        collapse_into_sorted_vec_for_comparison( &args, &args::ArgGranularity { granularity: Granularity::Function } ).unwrap()
+    };
+
+    let folded_only = collapse( &[folded.as_os_str()] );
+    let mixed = collapse( &[recording.as_os_str(), folded.as_os_str()] );
+    fs::remove_file( &folded ).unwrap();
+
+    assert_eq!( folded_only, vec![ "fn_1 [bin_1];fn_2 [bin_2] 5".to_owned() ] );
+
+    let anonymized_line = Regex::new( r"^fn_\d+ \[bin_\d+\];fn_\d+ \[bin_\d+\] 5$" ).unwrap();
+    assert!( mixed.len() > 1 );
+    assert!( mixed.iter().any( |line| anonymized_line.is_match( line ) ) );
+    assert!( mixed.iter().all( |line| !line.contains( "secret" ) && !line.contains( "main" ) ) );
+}
+
+#[test]
+fn test_reject_options_unsupported_for_folded_inputs() {
+    use std::ffi::OsStr;
+    use structopt::StructOpt;
+
+    let folded = std::env::temp_dir().join( format!( "nperf-collate-unsupported-{}.folded", std::process::id() ) );
+    fs::write( &folded, "main [app];work [app] 5\n" ).unwrap();
+
+    let collapse = |extra: &[&str], granularity| {
+        let mut argv = vec![ OsStr::new( "nperf" ) ];
+        argv.extend( extra.iter().map( OsStr::new ) );
+        argv.push( folded.as_os_str() );
+        let args = args::SharedCollationArgs::from_iter_safe( &argv ).unwrap();
+        collapse_into_sorted_vec_for_comparison( &args, &args::ArgGranularity { granularity } ).map_err( |error| error.to_string() )
+    };
+
+    let plain = collapse( &[], Granularity::Function );
+    let omit = collapse( &["--omit=work"], Granularity::Function );
+    let only_matching = collapse( &["--only-matching=work"], Granularity::Function );
+    let from = collapse( &["--from", "1s"], Granularity::Function );
+    let to = collapse( &["--to", "1s"], Granularity::Function );
+    let granularity = collapse( &[], Granularity::Module );
+    fs::remove_file( &folded ).unwrap();
+
+    assert_eq!( plain.unwrap(), vec![ "main [app];work [app] 5".to_owned() ] );
+    assert!( omit.unwrap_err().contains( "`--omit`" ) );
+    assert!( only_matching.unwrap_err().contains( "`--only-matching`" ) );
+    assert!( from.unwrap_err().contains( "`--from`" ) );
+    assert!( to.unwrap_err().contains( "`--to`" ) );
+    assert!( granularity.unwrap_err().contains( "`--granularity`" ) );
+}
+
+#[test]
+fn test_speedscope_output() {
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
+    let args = args::CollateArgs::from_iter_safe( &[std::ffi::OsStr::new( "nperf" ), std::ffi::OsStr::new( "--format=speedscope" ), input.as_os_str()] ).unwrap();
+
+    let mut output = Vec::new();
+    write_speedscope_output( &args, &mut output ).unwrap();
+    crate::utils::assert_golden( "amd64-usleep_in_a_loop_no_fp_online.speedscope.json", &output );
+}
@@ -19,12 +19,14 @@ impl GraphSample {
 }
 
 pub fn into_graph( args: &args::SharedCollationArgs, sampling_interval: Option< f64 > ) -> Result< Vec< GraphSample >, Box< dyn Error > > {
-    let (omit_regex, read_data_args) = repack_cli_args( args );
+    let (filter, read_data_args) = repack_cli_args( args )?;
     let opts = DecodeOpts {
-        omit_regex,
+        filter,
         emit_kernel_frames: true,
         emit_thread_frames: false,
         emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
         granularity: Granularity::Address
     };
 
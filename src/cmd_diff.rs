@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::args::{self, Granularity};
+use crate::interner::StringInterner;
+use crate::data_reader::{DecodeOpts, EventKind, read_data, repack_cli_args, round_weights};
+use crate::cmd_report::describe_frame;
+use crate::folded;
+use crate::utils::percent;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DiffMetric {
+    SelfSamples,
+    TotalSamples
+}
+
+#[derive(Copy, Clone, Default)]
+struct Counts {
+    self_samples: u64,
+    total_samples: u64
+}
+
+impl Counts {
+    fn get( &self, metric: DiffMetric ) -> u64 {
+        match metric {
+            DiffMetric::SelfSamples => self.self_samples,
+            DiffMetric::TotalSamples => self.total_samples
+        }
+    }
+}
+
+#[derive(Default)]
+struct Profile {
+    sample_count: u64,
+    // Functions are identified by their module and symbol names
+    // since binary IDs are not comparable between different recordings.
+    functions: HashMap< (String, String), Counts >
+}
+
+#[derive(Debug)]
+struct Delta {
+    module: String,
+    symbol: String,
+    old: f64,
+    new: f64,
+    confidence: f64
+}
+
+impl Delta {
+    fn delta( &self ) -> f64 {
+        self.new - self.old
+    }
+}
+
+#[derive(Debug)]
+struct FailIf {
+    symbol: Regex,
+    threshold: f64
+}
+
+/// Parses a `<symbol-regexp> +<X>%` condition.
+fn parse_fail_if( condition: &str ) -> Result< FailIf, String > {
+    let condition = condition.trim();
+    let index = condition.rfind( char::is_whitespace ).ok_or_else( || format!( "invalid `--fail-if` condition '{}': expected '<symbol> +<X>%'", condition ) )?;
+    let symbol = condition[ ..index ].trim();
+    let threshold = condition[ index + 1.. ].trim();
+    if !threshold.starts_with( '+' ) || !threshold.ends_with( '%' ) {
+        return Err( format!( "invalid threshold in `--fail-if` condition '{}': expected '+<X>%'", condition ) );
+    }
+
+    let threshold: f64 = threshold[ 1..threshold.len() - 1 ].parse().map_err( |_| format!( "invalid threshold in `--fail-if` condition '{}'", condition ) )?;
+    let symbol = Regex::new( symbol ).map_err( |err| format!( "invalid regexp in `--fail-if` condition '{}': {}", condition, err ) )?;
+
+    Ok( FailIf { symbol, threshold } )
+}
+
+// An approximation of the error function with a maximum error of 1.5e-7; see
+// formula 7.1.26 in Abramowitz and Stegun's "Handbook of Mathematical Functions".
+fn erf( x: f64 ) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let polynomial = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    sign * (1.0 - polynomial * (-x * x).exp())
+}
+
+/// Returns the confidence (from 0 to 1) that the proportions `x1 / n1` and `x2 / n2`
+/// are different, using a two-sided two-proportion z-test.
+fn confidence( x1: u64, n1: u64, x2: u64, n2: u64 ) -> f64 {
+    if n1 == 0 || n2 == 0 {
+        return 0.0;
+    }
+
+    let (x1, n1, x2, n2) = (x1 as f64, n1 as f64, x2 as f64, n2 as f64);
+    let pooled = (x1 + x2) / (n1 + n2);
+    let standard_error = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+    if standard_error == 0.0 {
+        return 0.0;
+    }
+
+    let z = (x2 / n2 - x1 / n1).abs() / standard_error;
+    erf( z / 2.0_f64.sqrt() )
+}
+
+fn compare( old: &Profile, new: &Profile, metric: DiffMetric ) -> Vec< Delta > {
+    let keys: HashSet< _ > = old.functions.keys().chain( new.functions.keys() ).collect();
+    let mut deltas: Vec< _ > = keys.into_iter().map( |key| {
+        let old_count = old.functions.get( key ).map( |counts| counts.get( metric ) ).unwrap_or( 0 );
+        let new_count = new.functions.get( key ).map( |counts| counts.get( metric ) ).unwrap_or( 0 );
+        Delta {
+            module: key.0.clone(),
+            symbol: key.1.clone(),
+            old: percent( old_count, old.sample_count ),
+            new: percent( new_count, new.sample_count ),
+            confidence: confidence( old_count, old.sample_count, new_count, new.sample_count )
+        }
+    }).collect();
+
+    deltas.sort_by( |a, b| {
+        b.delta().abs().partial_cmp( &a.delta().abs() ).unwrap()
+            .then_with( || a.symbol.cmp( &b.symbol ) )
+            .then_with( || a.module.cmp( &b.module ) )
+    });
+
+    deltas
+}
+
+impl Profile {
+    /// Adds a stack of `(module, symbol)` pairs ordered from the leaf.
+    fn add_stack( &mut self, stack: impl IntoIterator< Item = (String, String) >, count: u64 ) {
+        self.sample_count += count;
+
+        let mut seen = HashSet::new();
+        for (index, key) in stack.into_iter().enumerate() {
+            let counts = self.functions.entry( key.clone() ).or_insert_with( Counts::default );
+            if index == 0 {
+                counts.self_samples += count;
+            }
+
+            if seen.insert( key ) {
+                counts.total_samples += count;
+            }
+        }
+    }
+}
+
+fn load_folded_profile( input: &OsString ) -> Result< Profile, Box< dyn Error > > {
+    let mut profile = Profile::default();
+    for (stack, count) in folded::read_folded( &[ input.clone() ] )? {
+        profile.add_stack( stack.rsplit( ';' ).map( folded::parse_frame ), count );
+    }
+
+    Ok( profile )
+}
+
+fn load_profile( args: &args::SharedCollationArgs, input: &OsString ) -> Result< Profile, Box< dyn Error > > {
+    if folded::is_folded_file( input ).map_err( |err| format!( "cannot read {:?}: {}", input, err ) )? {
+        folded::check_supported_options( args, Granularity::Function )?;
+        return load_folded_profile( input );
+    }
+
+    let mut args = args.clone();
+    args.input = vec![ input.clone() ];
+
+    let (filter, read_data_args) = repack_cli_args( &args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let mut interner = StringInterner::new();
+    let mut stacks = HashMap::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                if let Some( frames ) = sample.decode( &event.state, &opts, &mut interner ) {
+                    *stacks.entry( frames ).or_insert( 0.0 ) += sample.weight;
+                }
+            },
+            _ => {}
+        }
+    })?;
+
+    let stacks = round_weights( stacks );
+
+    let mut profile = Profile::default();
+    for (frames, count) in stacks {
+        let stack = frames.iter().map( |frame| {
+            let (module, symbol, _) = describe_frame( &state, &interner, frame );
+            (module, symbol)
+        });
+
+        profile.add_stack( stack, count );
+    }
+
+    Ok( profile )
+}
+
+pub fn main( args: args::DiffArgs ) -> Result< (), Box< dyn Error > > {
+    if args.collation_args.input.len() != 2 {
+        return Err( "expected exactly two input files: the baseline and the new profile".into() );
+    }
+
+    let fail_if: Vec< _ > = args.fail_if.iter().map( |condition| parse_fail_if( condition ) ).collect::< Result< _, _ > >()?;
+
+    let old = load_profile( &args.collation_args, &args.collation_args.input[ 0 ] )?;
+    let new = load_profile( &args.collation_args, &args.collation_args.input[ 1 ] )?;
+    let deltas = compare( &old, &new, args.metric );
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    writeln!( stdout, "# Samples: {} (old), {} (new)", old.sample_count, new.sample_count )?;
+    writeln!( stdout, "#" )?;
+    writeln!( stdout, "# {:>8}  {:>8}  {:>9}  {:>10}  {:<24}  {}", "Old", "New", "Delta", "Confidence", "Module", "Symbol" )?;
+    writeln!( stdout, "#" )?;
+
+    for delta in deltas.iter().filter( |delta| delta.delta().abs() >= args.min_delta ) {
+        writeln!(
+            stdout,
+            "  {:>7.2}%  {:>7.2}%  {:>+8.2}%  {:>9.1}%  {:<24}  {}",
+            delta.old,
+            delta.new,
+            delta.delta(),
+            delta.confidence * 100.0,
+            delta.module,
+            delta.symbol
+        )?;
+    }
+
+    stdout.flush()?;
+
+    let mut failures = Vec::new();
+    for condition in &fail_if {
+        for delta in deltas.iter().filter( |delta| condition.symbol.is_match( &delta.symbol ) ) {
+            if delta.delta() > condition.threshold && delta.confidence * 100.0 >= args.confidence {
+                failures.push( format!(
+                    "'{}' [{}] increased by {:+.2}% (from {:.2}% to {:.2}%; confidence {:.1}%), above the allowed +{}%",
+                    delta.symbol,
+                    delta.module,
+                    delta.delta(),
+                    delta.old,
+                    delta.new,
+                    delta.confidence * 100.0,
+                    condition.threshold
+                ));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        for failure in &failures {
+            error!( "Regression: {}", failure );
+        }
+
+        return Err( format!( "{} regression(s) found", failures.len() ).into() );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_fail_if() {
+    let condition = parse_fail_if( "^malloc$ +5%" ).unwrap();
+    assert_eq!( condition.symbol.as_str(), "^malloc$" );
+    assert_eq!( condition.threshold, 5.0 );
+
+    let condition = parse_fail_if( "std::vec::Vec<T> as Drop +0.5%" ).unwrap();
+    assert_eq!( condition.symbol.as_str(), "std::vec::Vec<T> as Drop" );
+    assert_eq!( condition.threshold, 0.5 );
+
+    assert!( parse_fail_if( "malloc" ).is_err() );
+    assert!( parse_fail_if( "malloc 5%" ).is_err() );
+    assert!( parse_fail_if( "malloc +5" ).is_err() );
+    assert!( parse_fail_if( "malloc +five%" ).is_err() );
+}
+
+#[test]
+fn test_confidence() {
+    assert_eq!( confidence( 0, 0, 10, 100 ), 0.0 );
+    assert_eq!( confidence( 0, 100, 0, 100 ), 0.0 );
+    assert!( confidence( 10, 100, 10, 100 ) < 0.01 );
+    assert!( confidence( 10, 100, 11, 100 ) < 0.5 );
+    assert!( confidence( 100, 1000, 200, 1000 ) > 0.99 );
+
+    // For z = 1.96 the two-sided confidence should be 95%.
+    assert!( (erf( 1.96 / 2.0_f64.sqrt() ) - 0.95).abs() < 0.001 );
+}
+
+#[test]
+fn test_compare() {
+    let profile = |sample_count, functions: &[(&str, u64, u64)]| Profile {
+        sample_count,
+        functions: functions.iter().map( |&(symbol, self_samples, total_samples)| {
+            (("module".to_owned(), symbol.to_owned()), Counts { self_samples, total_samples })
+        }).collect()
+    };
+
+    let old = profile( 100, &[ ("a", 10, 100), ("b", 50, 60), ("c", 40, 40) ] );
+    let new = profile( 200, &[ ("a", 20, 200), ("b", 140, 160), ("d", 40, 40) ] );
+
+    let deltas = compare( &old, &new, DiffMetric::SelfSamples );
+    let symbols: Vec< _ > = deltas.iter().map( |delta| (delta.symbol.as_str(), delta.delta().round() as i64) ).collect();
+    assert_eq!( symbols, vec![ ("c", -40), ("b", 20), ("d", 20), ("a", 0) ] );
+
+    let deltas = compare( &old, &new, DiffMetric::TotalSamples );
+    let symbols: Vec< _ > = deltas.iter().map( |delta| (delta.symbol.as_str(), delta.delta().round() as i64) ).collect();
+    assert_eq!( symbols, vec![ ("c", -40), ("b", 20), ("d", 20), ("a", 0) ] );
+}
@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, DebugInfoIndex, write_breakpad_symbols};
+
+use crate::args;
+
+pub fn main( args: args::DumpSymsArgs ) -> Result< (), Box< dyn Error > > {
+    let binary = BinaryData::load_from_fs( &args.binary ).map_err( |err| format!( "cannot load {:?}: {}", args.binary, err ) )?;
+    let binary = Arc::new( binary );
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    debug_info_index.enable_auto_load();
+    debug_info_index.add( &args.binary );
+    for path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
+
+    let (other_binary, debug_binary) = debug_info_index.get_pair( binary.name(), binary.debuglink(), binary.build_id() );
+    let debug_binary = debug_binary.into_iter().chain( other_binary ).find( |debug_binary| debug_binary.has_debug_info() );
+    match debug_binary {
+        Some( ref debug_binary ) => info!( "Using debug info from '{}'", debug_binary.name() ),
+        None => warn!( "No debug info found for {:?}; only the symbols and the CFI will be dumped", args.binary )
+    }
+
+    let mut fp: Box< dyn Write > = match args.output {
+        Some( ref output ) => Box::new( io::BufWriter::new( File::create( output ).map_err( |err| format!( "cannot create {:?}: {}", output, err ) )? ) ),
+        None => Box::new( io::BufWriter::new( io::stdout() ) )
+    };
+
+    write_breakpad_symbols( &binary, debug_binary.as_ref(), &mut fp )?;
+    fp.flush()?;
+
+    Ok(())
+}
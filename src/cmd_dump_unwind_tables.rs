@@ -0,0 +1,22 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, write_unwind_tables};
+
+use crate::args;
+
+pub fn main( args: args::DumpUnwindTablesArgs ) -> Result< (), Box< dyn Error > > {
+    let binary = BinaryData::load_from_fs( &args.binary ).map_err( |err| format!( "cannot load {:?}: {}", args.binary, err ) )?;
+    let binary = Arc::new( binary );
+    if binary.eh_frame_range().is_none() && binary.debug_frame_range().is_none() && binary.arm_exidx_range().is_none() {
+        return Err( format!( "{:?} doesn't have any unwind tables", args.binary ).into() );
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    write_unwind_tables( &binary, &mut stdout )?;
+    stdout.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,1300 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::fs::File;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cmp::{max, min};
+use std::fmt::Write as FmtWrite;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use nwind::BinaryId;
+use serde_json::{self, json};
+
+use crate::archive::StallKind;
+use crate::args::{self, Granularity};
+use crate::interner::{StringId, StringInterner};
+use crate::data_reader::{State, DecodeOpts, EventKind, EventSample, FrameKind, read_data, repack_cli_args, round_weights, write_frame};
+use crate::pprof;
+use crate::exporter::{create_exporter, run_exporter};
+use crate::speedscope;
+use crate::cmd_trace_events::{emit_events, sampling_periods};
+
+#[derive(Debug)]
+pub enum ExportFormat {
+    Pprof,
+    Gecko,
+    ChromeTrace,
+    Callgrind,
+    SpeedscopeEvented,
+    Sqlite
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum LocationKey {
+    Kernel( u64 ),
+    User( Option< BinaryId >, u64 ),
+    Python( StringId, StringId, Option< u64 > ),
+    Jit( StringId )
+}
+
+struct PprofBuilder {
+    profile: pprof::Profile,
+    location_by_key: HashMap< LocationKey, u64 >,
+    function_by_key: HashMap< (i64, i64), u64 >,
+    mapping_by_start: HashMap< u64, usize >,
+    kernel_mapping: Option< usize >,
+    stacks: HashMap< (Vec< u64 >, u32, u32), f64 >
+}
+
+impl PprofBuilder {
+    fn new() -> Self {
+        PprofBuilder {
+            profile: pprof::Profile::new(),
+            location_by_key: HashMap::new(),
+            function_by_key: HashMap::new(),
+            mapping_by_start: HashMap::new(),
+            kernel_mapping: None,
+            stacks: HashMap::new()
+        }
+    }
+
+    fn get_or_create_function( &mut self, name: &str, filename: Option< &str > ) -> u64 {
+        let name = self.profile.intern( name );
+        let filename = filename.map( |filename| self.profile.intern( filename ) ).unwrap_or( 0 );
+        let profile = &mut self.profile;
+        *self.function_by_key.entry( (name, filename) ).or_insert_with( || {
+            let id = profile.functions.len() as u64 + 1;
+            profile.functions.push( pprof::Function {
+                id,
+                name,
+                system_name: name,
+                filename
+            });
+            id
+        })
+    }
+
+    fn get_or_create_kernel_mapping( &mut self, address: u64 ) -> usize {
+        let index = match self.kernel_mapping {
+            Some( index ) => index,
+            None => {
+                let index = self.profile.mappings.len();
+                let filename = self.profile.intern( "[kernel.kallsyms]" );
+                self.profile.mappings.push( pprof::Mapping {
+                    id: index as u64 + 1,
+                    memory_start: address,
+                    memory_limit: address + 1,
+                    filename,
+                    has_functions: true,
+                    .. pprof::Mapping::default()
+                });
+                self.kernel_mapping = Some( index );
+                index
+            }
+        };
+
+        let mapping = &mut self.profile.mappings[ index ];
+        mapping.memory_start = min( mapping.memory_start, address );
+        mapping.memory_limit = max( mapping.memory_limit, address + 1 );
+        index
+    }
+
+    fn get_or_create_user_mapping( &mut self, state: &State, sample: &EventSample, binary_id: &BinaryId, address: u64 ) -> Option< usize > {
+        let region = sample.process.memory_region( address )?;
+        if let Some( &index ) = self.mapping_by_start.get( &region.start ) {
+            return Some( index );
+        }
+
+        let binary = state.get_binary( binary_id );
+        let filename = self.profile.intern( binary.path() );
+        let build_id = match binary.build_id() {
+            Some( build_id ) => {
+                let build_id: Vec< _ > = build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect();
+                self.profile.intern( &build_id.join( "" ) )
+            },
+            None => 0
+        };
+
+        let index = self.profile.mappings.len();
+        self.profile.mappings.push( pprof::Mapping {
+            id: index as u64 + 1,
+            memory_start: region.start,
+            memory_limit: region.end,
+            file_offset: region.file_offset,
+            filename,
+            build_id,
+            .. pprof::Mapping::default()
+        });
+
+        self.mapping_by_start.insert( region.start, index );
+        Some( index )
+    }
+
+    fn add_location( &mut self, key: LocationKey, mapping: Option< usize >, address: u64, lines: Vec< pprof::Line > ) -> u64 {
+        if let Some( &id ) = self.location_by_key.get( &key ) {
+            return id;
+        }
+
+        let id = self.profile.locations.len() as u64 + 1;
+        self.profile.locations.push( pprof::Location {
+            id,
+            mapping_id: mapping.map( |index| index as u64 + 1 ).unwrap_or( 0 ),
+            address,
+            lines
+        });
+
+        self.location_by_key.insert( key, id );
+        id
+    }
+
+    fn add_kernel_frame( &mut self, state: &State, address: u64 ) -> u64 {
+        if let Some( &id ) = self.location_by_key.get( &LocationKey::Kernel( address ) ) {
+            return id;
+        }
+
+        let mapping = self.get_or_create_kernel_mapping( address );
+        let mut lines = Vec::new();
+        if let Some( symbol ) = state.get_kernel_symbol_by_address( address ) {
+            let function_id = match symbol.module {
+                Some( ref module ) => self.get_or_create_function( &format!( "{} [{}]", symbol.name, module ), None ),
+                None => self.get_or_create_function( &symbol.name, None )
+            };
+
+            lines.push( pprof::Line { function_id, line: 0 } );
+        }
+
+        self.add_location( LocationKey::Kernel( address ), Some( mapping ), address, lines )
+    }
+
+    fn add_sample( &mut self, state: &State, sample: &EventSample, frames: &[FrameKind], interner: &StringInterner ) {
+        let mut location_ids = Vec::with_capacity( sample.kernel_backtrace.len() + frames.len() );
+        for &address in sample.kernel_backtrace {
+            location_ids.push( self.add_kernel_frame( state, address ) );
+        }
+
+        let mut lines = Vec::new();
+        for frame in frames {
+            match *frame {
+                FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, file, line } => {
+                    let symbol = interner.resolve( symbol ).unwrap();
+                    let file = file.map( |file| interner.resolve( file ).unwrap() );
+                    let function_id = self.get_or_create_function( symbol, file );
+                    lines.push( pprof::Line {
+                        function_id,
+                        line: line.unwrap_or( 0 ) as i64
+                    });
+
+                    if is_inline {
+                        continue;
+                    }
+
+                    let mapping = self.get_or_create_user_mapping( state, sample, binary_id, address );
+                    if let Some( index ) = mapping {
+                        let mapping = &mut self.profile.mappings[ index ];
+                        mapping.has_functions = true;
+                        mapping.has_filenames |= file.is_some();
+                        mapping.has_line_numbers |= line.is_some();
+                        mapping.has_inline_frames |= lines.len() > 1;
+                    }
+
+                    let key = LocationKey::User( Some( binary_id.clone() ), address );
+                    let lines = std::mem::replace( &mut lines, Vec::new() );
+                    location_ids.push( self.add_location( key, mapping, address, lines ) );
+                },
+                FrameKind::UserBinary( ref binary_id, address ) => {
+                    let mapping = self.get_or_create_user_mapping( state, sample, binary_id, address );
+                    let key = LocationKey::User( Some( binary_id.clone() ), address );
+                    location_ids.push( self.add_location( key, mapping, address, Vec::new() ) );
+                },
+                FrameKind::User( address ) => {
+                    location_ids.push( self.add_location( LocationKey::User( None, address ), None, address, Vec::new() ) );
+                },
+                FrameKind::Python { symbol, file, line } => {
+                    let function_id = self.get_or_create_function( interner.resolve( symbol ).unwrap(), interner.resolve( file ) );
+                    let lines = vec![ pprof::Line {
+                        function_id,
+                        line: line.unwrap_or( 0 ) as i64
+                    }];
+
+                    location_ids.push( self.add_location( LocationKey::Python( symbol, file, line ), None, 0, lines ) );
+                },
+                FrameKind::Jit( symbol ) => {
+                    let function_id = self.get_or_create_function( interner.resolve( symbol ).unwrap(), None );
+                    let lines = vec![ pprof::Line {
+                        function_id,
+                        line: 0
+                    }];
+
+                    location_ids.push( self.add_location( LocationKey::Jit( symbol ), None, 0, lines ) );
+                },
+                _ => unreachable!()
+            }
+        }
+
+        *self.stacks.entry( (location_ids, sample.process.pid(), sample.tid) ).or_insert( 0.0 ) += sample.weight;
+    }
+
+    fn finish( mut self, state: &State, duration: u64 ) -> pprof::Profile {
+        let period = state.frequency().map( |frequency| 1_000_000_000 / frequency as i64 );
+
+        let samples = self.profile.intern( "samples" );
+        let count = self.profile.intern( "count" );
+        self.profile.sample_types.push( pprof::ValueType { kind: samples, unit: count } );
+        if let Some( period ) = period {
+            let cpu = self.profile.intern( "cpu" );
+            let nanoseconds = self.profile.intern( "nanoseconds" );
+            self.profile.sample_types.push( pprof::ValueType { kind: cpu, unit: nanoseconds } );
+            self.profile.period_type = Some( pprof::ValueType { kind: cpu, unit: nanoseconds } );
+            self.profile.period = period;
+        }
+
+        let pid_key = self.profile.intern( "pid" );
+        let tid_key = self.profile.intern( "tid" );
+        let thread_key = self.profile.intern( "thread" );
+
+        let mut stacks: Vec< _ > = round_weights( self.stacks.drain().collect() ).into_iter().collect();
+        stacks.sort();
+
+        for ((location_ids, pid, tid), count) in stacks {
+            let mut values = vec![ count as i64 ];
+            if let Some( period ) = period {
+                values.push( count as i64 * period );
+            }
+
+            let mut labels = vec![
+                pprof::Label { key: pid_key, string: 0, number: pid as i64 },
+                pprof::Label { key: tid_key, string: 0, number: tid as i64 }
+            ];
+
+            if let Some( name ) = state.get_thread_name( tid ) {
+                let name = self.profile.intern( name );
+                labels.push( pprof::Label { key: thread_key, string: name, number: 0 } );
+            }
+
+            self.profile.samples.push( pprof::Sample {
+                location_ids,
+                values,
+                labels
+            });
+        }
+
+        self.profile.duration_nanos = duration as i64;
+        self.profile
+    }
+}
+
+fn export_pprof( args: &args::SharedCollationArgs ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: false,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let mut builder = PprofBuilder::new();
+    let mut interner = StringInterner::new();
+    let mut frames = Vec::new();
+    let mut first_timestamp = None;
+    let mut last_timestamp = None;
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                frames.clear();
+                if !sample.try_decode( &event.state, &opts, &mut interner, Some( &mut frames ) ) {
+                    return;
+                }
+
+                first_timestamp = Some( first_timestamp.map_or( sample.timestamp, |timestamp| min( timestamp, sample.timestamp ) ) );
+                last_timestamp = Some( last_timestamp.map_or( sample.timestamp, |timestamp| max( timestamp, sample.timestamp ) ) );
+                builder.add_sample( &event.state, &sample, &frames, &interner );
+            },
+            _ => {}
+        }
+    })?;
+
+    let duration = match (first_timestamp, last_timestamp) {
+        (Some( first ), Some( last )) => last - first,
+        _ => 0
+    };
+
+    let profile = builder.finish( &state, duration );
+    let mut encoder = GzEncoder::new( Vec::new(), Compression::default() );
+    encoder.write_all( &profile.serialize() )?;
+    Ok( encoder.finish()? )
+}
+
+const GECKO_CATEGORY_USER: u32 = 0;
+const GECKO_CATEGORY_KERNEL: u32 = 1;
+
+fn gecko_location( state: &State, interner: &StringInterner, frame: &FrameKind ) -> (String, Option< u64 >, u32) {
+    match *frame {
+        FrameKind::UserByLine { ref binary_id, symbol, line, .. } => {
+            let binary = state.get_binary( binary_id );
+            let symbol = interner.resolve( symbol ).unwrap();
+            (format!( "{} (in {})", symbol, binary.basename() ), Some( line ), GECKO_CATEGORY_USER)
+        },
+        FrameKind::UserByFunction { ref binary_id, symbol, .. } |
+        FrameKind::UserByAddress { ref binary_id, symbol, .. } => {
+            let binary = state.get_binary( binary_id );
+            let symbol = interner.resolve( symbol ).unwrap();
+            (format!( "{} (in {})", symbol, binary.basename() ), None, GECKO_CATEGORY_USER)
+        },
+        FrameKind::UserBinary( ref binary_id, address ) => {
+            let binary = state.get_binary( binary_id );
+            (format!( "0x{:x} (in {})", address, binary.basename() ), None, GECKO_CATEGORY_USER)
+        },
+        FrameKind::User( address ) => {
+            (format!( "0x{:x}", address ), None, GECKO_CATEGORY_USER)
+        },
+        FrameKind::KernelSymbol( index ) => {
+            let symbol = state.get_kernel_symbol( index );
+            let module = symbol.module.as_ref().map( |module| module.as_str() ).unwrap_or( "[kernel.kallsyms]" );
+            (format!( "{} (in {})", symbol.name, module ), None, GECKO_CATEGORY_KERNEL)
+        },
+        FrameKind::Kernel( address ) => {
+            (format!( "0x{:x} (in [kernel.kallsyms])", address ), None, GECKO_CATEGORY_KERNEL)
+        },
+        FrameKind::Python { symbol, file, line } => {
+            let symbol = interner.resolve( symbol ).unwrap();
+            let file = interner.resolve( file ).unwrap();
+            (format!( "{} (in {})", symbol, file ), line, GECKO_CATEGORY_USER)
+        },
+        FrameKind::Jit( symbol ) => {
+            (format!( "{} (in [jit])", interner.resolve( symbol ).unwrap() ), None, GECKO_CATEGORY_USER)
+        },
+        FrameKind::Process( .. ) | FrameKind::Thread( .. ) | FrameKind::MainThread | FrameKind::UnwindFailure( .. ) | FrameKind::Cgroup( .. ) | FrameKind::Module { .. } => unreachable!()
+    }
+}
+
+struct GeckoThread {
+    pid: u32,
+    samples: Vec< (u64, Vec< FrameKind >) >
+}
+
+fn to_ms( timestamp: u64 ) -> f64 {
+    timestamp as f64 / 1_000_000.0
+}
+
+fn export_gecko( args: &args::SharedCollationArgs ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Line
+    };
+
+    let mut interner = StringInterner::new();
+    let mut threads: HashMap< u32, GeckoThread > = HashMap::new();
+    let mut lost = Vec::new();
+    let mut stalls: HashMap< u32, Vec< (u64, u64, StallKind, String) > > = HashMap::new();
+    let mut last_timestamp = None;
+    let mut executable = String::new();
+    let mut main_pid = 0;
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                last_timestamp = Some( sample.timestamp );
+                let frames = match sample.decode( &event.state, &opts, &mut interner ) {
+                    Some( frames ) => frames,
+                    None => return
+                };
+
+                if executable.is_empty() {
+                    executable = sample.process.executable().to_owned();
+                    main_pid = sample.process.pid();
+                }
+
+                let pid = sample.process.pid();
+                threads.entry( sample.tid ).or_insert_with( || GeckoThread { pid, samples: Vec::new() } ).samples.push( (sample.timestamp, frames) );
+            },
+            EventKind::Lost { count } => {
+                lost.push( (last_timestamp, count) );
+            },
+            EventKind::Stall { timestamp, tid, duration, kind, backtrace } => {
+                stalls.entry( tid ).or_insert_with( Vec::new ).push( (timestamp, duration, kind, backtrace.join( "\n" )) );
+            },
+            _ => {}
+        }
+    })?;
+
+    let first_timestamp = threads.values().flat_map( |thread| thread.samples.iter() ).map( |&(timestamp, _)| timestamp ).min().unwrap_or( 0 );
+    let interval = state.frequency().map( |frequency| 1000.0 / frequency as f64 ).unwrap_or( 1.0 );
+
+    let mut tids: Vec< _ > = threads.keys().cloned().collect();
+    tids.sort();
+
+    // The lost events aren't tied to any particular thread, so they're shown on the main thread,
+    // or on the first one if the main thread has no samples.
+    let lost_events_tid = tids.iter().cloned().find( |&tid| tid == main_pid ).or_else( || tids.first().cloned() );
+
+    let mut output_threads = Vec::with_capacity( tids.len() );
+    for tid in tids {
+        let mut thread = threads.remove( &tid ).unwrap();
+        thread.samples.sort_by_key( |&(timestamp, _)| timestamp );
+
+        let mut strings: Vec< String > = Vec::new();
+        let mut string_to_index: HashMap< String, usize > = HashMap::new();
+        let mut frame_table = Vec::new();
+        let mut frame_to_index: HashMap< FrameKind, usize > = HashMap::new();
+        let mut stack_table = Vec::new();
+        let mut stack_to_index: HashMap< (Option< usize >, usize), usize > = HashMap::new();
+        let mut samples = Vec::with_capacity( thread.samples.len() );
+
+        let mut intern = |string: &str| -> usize {
+            if let Some( &index ) = string_to_index.get( string ) {
+                return index;
+            }
+
+            let index = strings.len();
+            strings.push( string.to_owned() );
+            string_to_index.insert( string.to_owned(), index );
+            index
+        };
+
+        for (timestamp, frames) in thread.samples {
+            let mut prefix = None;
+            for frame in frames.into_iter().rev() {
+                let frame_index = match frame_to_index.get( &frame ) {
+                    Some( &index ) => index,
+                    None => {
+                        let (location, line, category) = gecko_location( &state, &interner, &frame );
+                        let index = frame_table.len();
+                        frame_table.push( json!([ intern( &location ), false, 0, null, null, line, null, category, 0 ]) );
+                        frame_to_index.insert( frame, index );
+                        index
+                    }
+                };
+
+                let stack_index = stack_table.len();
+                let stack_index = *stack_to_index.entry( (prefix, frame_index) ).or_insert_with( || {
+                    stack_table.push( json!([ prefix, frame_index ]) );
+                    stack_index
+                });
+
+                prefix = Some( stack_index );
+            }
+
+            samples.push( json!([ prefix, to_ms( timestamp - first_timestamp ), 0 ]) );
+        }
+
+        let mut markers = Vec::new();
+        if Some( tid ) == lost_events_tid {
+            let name = intern( "LostEvents" );
+            for &(timestamp, count) in &lost {
+                let time = to_ms( timestamp.unwrap_or( first_timestamp ).saturating_sub( first_timestamp ) );
+                markers.push( json!([ name, time, null, 0, GECKO_CATEGORY_USER, { "type": "LostEvents", "count": count } ]) );
+            }
+        }
+
+        if let Some( stalls ) = stalls.get( &tid ) {
+            let name = intern( "Stall" );
+            for &(timestamp, duration, kind, ref backtrace) in stalls {
+                let start = to_ms( timestamp.saturating_sub( duration ).saturating_sub( first_timestamp ) );
+                let end = to_ms( timestamp.saturating_sub( first_timestamp ) );
+                let kind = match kind {
+                    StallKind::SameStack => "same stack",
+                    StallKind::NoProgress => "no progress"
+                };
+
+                markers.push( json!([ name, start, end, 1, GECKO_CATEGORY_USER, { "type": "Stall", "kind": kind, "backtrace": backtrace } ]) );
+            }
+        }
+
+        let name = match state.get_thread_name( tid ) {
+            Some( name ) => name.to_owned(),
+            None => format!( "[THREAD={}]", tid )
+        };
+
+        output_threads.push( json!({
+            "name": name,
+            "processType": "default",
+            "processName": executable,
+            "pid": thread.pid,
+            "tid": tid,
+            "registerTime": 0,
+            "unregisterTime": null,
+            "markers": {
+                "schema": { "name": 0, "startTime": 1, "endTime": 2, "phase": 3, "category": 4, "data": 5 },
+                "data": markers
+            },
+            "samples": {
+                "schema": { "stack": 0, "time": 1, "responsiveness": 2 },
+                "data": samples
+            },
+            "frameTable": {
+                "schema": {
+                    "location": 0,
+                    "relevantForJS": 1,
+                    "innerWindowID": 2,
+                    "implementation": 3,
+                    "optimizations": 4,
+                    "line": 5,
+                    "column": 6,
+                    "category": 7,
+                    "subcategory": 8
+                },
+                "data": frame_table
+            },
+            "stackTable": {
+                "schema": { "prefix": 0, "frame": 1 },
+                "data": stack_table
+            },
+            "stringTable": strings
+        }));
+    }
+
+    let profile = json!({
+        "meta": {
+            "interval": interval,
+            "processType": 0,
+            "product": executable,
+            "stackwalk": 1,
+            "debug": false,
+            "startTime": 0,
+            "shutdownTime": null,
+            "version": 24,
+            "presymbolicated": true,
+            "categories": [
+                { "name": "User", "color": "yellow", "subcategories": [ "Other" ] },
+                { "name": "Kernel", "color": "orange", "subcategories": [ "Other" ] }
+            ],
+            "markerSchema": [
+                {
+                    "name": "LostEvents",
+                    "display": [ "marker-chart", "marker-table", "timeline-overview" ],
+                    "chartLabel": "{marker.data.count} events lost",
+                    "tableLabel": "{marker.data.count} events lost",
+                    "data": [
+                        { "key": "count", "label": "Lost events", "format": "integer" }
+                    ]
+                },
+                {
+                    "name": "Stall",
+                    "display": [ "marker-chart", "marker-table", "timeline-overview" ],
+                    "chartLabel": "Stall ({marker.data.kind})",
+                    "tableLabel": "Stall ({marker.data.kind})",
+                    "data": [
+                        { "key": "kind", "label": "Kind", "format": "string" },
+                        { "key": "backtrace", "label": "Backtrace", "format": "string" }
+                    ]
+                }
+            ]
+        },
+        "libs": [],
+        "threads": output_threads,
+        "processes": [],
+        "pausedRanges": []
+    });
+
+    Ok( serde_json::to_vec( &profile )? )
+}
+
+fn chrome_trace_category< 'a >( state: &'a State, frame: &FrameKind ) -> &'a str {
+    match *frame {
+        FrameKind::UserByLine { ref binary_id, .. } |
+        FrameKind::UserByFunction { ref binary_id, .. } |
+        FrameKind::UserByAddress { ref binary_id, .. } |
+        FrameKind::UserBinary( ref binary_id, _ ) => state.get_binary( binary_id ).basename(),
+        FrameKind::KernelSymbol( .. ) | FrameKind::Kernel( .. ) => "kernel",
+        FrameKind::Python { .. } => "python",
+        FrameKind::Jit( _ ) => "jit",
+        _ => "user"
+    }
+}
+
+fn export_chrome_trace( args: &args::SharedCollationArgs ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let mut interner = StringInterner::new();
+    let mut stack_frames: Vec< (Option< usize >, FrameKind) > = Vec::new();
+    let mut stack_frame_to_index: HashMap< (Option< usize >, FrameKind), usize > = HashMap::new();
+    let mut samples = Vec::new();
+    let mut threads = HashMap::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                let frames = match sample.decode( &event.state, &opts, &mut interner ) {
+                    Some( frames ) => frames,
+                    None => return
+                };
+
+                let mut parent = None;
+                for frame in frames.into_iter().rev() {
+                    let key = (parent, frame);
+                    let index = match stack_frame_to_index.get( &key ) {
+                        Some( &index ) => index,
+                        None => {
+                            let index = stack_frames.len();
+                            stack_frames.push( key.clone() );
+                            stack_frame_to_index.insert( key, index );
+                            index
+                        }
+                    };
+
+                    parent = Some( index );
+                }
+
+                let pid = sample.process.pid();
+                threads.insert( sample.tid, (pid, sample.process.executable().to_owned()) );
+                samples.push( (sample.timestamp, pid, sample.tid, sample.cpu, parent) );
+            },
+            _ => {}
+        }
+    })?;
+
+    samples.sort_by_key( |&(timestamp, ..)| timestamp );
+    let first_timestamp = samples.first().map( |&(timestamp, ..)| timestamp ).unwrap_or( 0 );
+
+    let mut events = Vec::with_capacity( samples.len() + threads.len() * 2 );
+    let mut tids: Vec< _ > = threads.keys().cloned().collect();
+    tids.sort();
+    for tid in tids {
+        let (pid, ref executable) = threads[ &tid ];
+        if pid == tid {
+            events.push( json!({ "ph": "M", "pid": pid, "tid": tid, "name": "process_name", "args": { "name": executable } }) );
+        }
+
+        if let Some( name ) = state.get_thread_name( tid ) {
+            events.push( json!({ "ph": "M", "pid": pid, "tid": tid, "name": "thread_name", "args": { "name": name } }) );
+        }
+    }
+
+    for (timestamp, pid, tid, cpu, stack_frame) in samples {
+        let mut event = json!({
+            "ph": "P",
+            "name": "cpu-clock",
+            "cat": "sample",
+            "ts": (timestamp - first_timestamp) as f64 / 1000.0,
+            "pid": pid,
+            "tid": tid,
+            "args": { "cpu": cpu }
+        });
+
+        if let Some( stack_frame ) = stack_frame {
+            event[ "sf" ] = json!( stack_frame.to_string() );
+        }
+
+        events.push( event );
+    }
+
+    let mut output_stack_frames = serde_json::Map::new();
+    for (index, (parent, frame)) in stack_frames.iter().enumerate() {
+        let mut name = String::new();
+        write_frame( &state, &interner, &mut name, frame );
+
+        let mut entry = json!({
+            "name": name,
+            "category": chrome_trace_category( &state, frame )
+        });
+
+        if let Some( parent ) = parent {
+            entry[ "parent" ] = json!( parent.to_string() );
+        }
+
+        output_stack_frames.insert( index.to_string(), entry );
+    }
+
+    let trace = json!({
+        "traceEvents": events,
+        "stackFrames": output_stack_frames,
+        "displayTimeUnit": "ns",
+        "otherData": {
+            "version": format!( "nperf {}", env!( "CARGO_PKG_VERSION" ) )
+        }
+    });
+
+    Ok( serde_json::to_vec( &trace )? )
+}
+
+fn export_speedscope_evented( args: &args::SharedCollationArgs ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let mut interner = StringInterner::new();
+    let mut frame_to_index: HashMap< FrameKind, usize > = HashMap::new();
+    let mut frames = Vec::new();
+    let mut samples_for_thread: HashMap< (u32, u32), Vec< (u64, Vec< usize >) > > = HashMap::new();
+    let mut executable = None;
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                let stack = match sample.decode( &event.state, &opts, &mut interner ) {
+                    Some( stack ) => stack,
+                    None => return
+                };
+
+                if executable.is_none() {
+                    executable = Some( sample.process.executable().to_owned() );
+                }
+
+                let stack = stack.into_iter().map( |frame| {
+                    let index = frames.len();
+                    *frame_to_index.entry( frame.clone() ).or_insert_with( || {
+                        frames.push( frame );
+                        index
+                    })
+                }).collect();
+
+                let key = (sample.process.pid(), sample.tid);
+                samples_for_thread.entry( key ).or_insert_with( Vec::new ).push( (sample.timestamp, stack) );
+            },
+            _ => {}
+        }
+    })?;
+
+    let shared = speedscope::Shared {
+        frames: frames.iter().map( |frame| {
+            let mut name = String::new();
+            write_frame( &state, &interner, &mut name, frame );
+            speedscope::Frame { name, file: None, line: None }
+        }).collect()
+    };
+
+    // All of the threads share the same time axis so that they can be compared with each other.
+    let (sampling_period, merge_period) = sampling_periods( state.frequency(), None );
+    let first_timestamp = samples_for_thread.values().flat_map( |samples| samples.iter() ).map( |&(timestamp, _)| timestamp ).min().unwrap_or( 0 );
+
+    let mut keys: Vec< _ > = samples_for_thread.keys().cloned().collect();
+    keys.sort();
+
+    let mut profiles = Vec::with_capacity( keys.len() );
+    for (pid, tid) in keys {
+        let mut samples = samples_for_thread.remove( &(pid, tid) ).unwrap();
+        samples.sort_by_key( |&(timestamp, _)| timestamp );
+
+        let events: Vec< _ > = emit_events( samples, sampling_period, merge_period ).into_iter().map( |event| {
+            speedscope::Event {
+                kind: if event.is_end { "C" } else { "O" },
+                frame: event.frame,
+                at: event.timestamp - first_timestamp
+            }
+        }).collect();
+
+        let name = match state.get_thread_name( tid ) {
+            Some( name ) => format!( "{} [PID={}, THREAD={}]", name, pid, tid ),
+            None => format!( "[PID={}, THREAD={}]", pid, tid )
+        };
+
+        profiles.push( speedscope::Profile::Evented( speedscope::EventedProfile {
+            kind: "evented",
+            name,
+            unit: "nanoseconds",
+            start_value: events.first().map( |event| event.at ).unwrap_or( 0 ),
+            end_value: events.last().map( |event| event.at ).unwrap_or( 0 ),
+            events
+        }));
+    }
+
+    let file = speedscope::File {
+        schema: speedscope::SCHEMA,
+        shared,
+        profiles,
+        name: executable,
+        active_profile_index: 0,
+        exporter: format!( "nperf {}", env!( "CARGO_PKG_VERSION" ) )
+    };
+
+    Ok( serde_json::to_vec( &file )? )
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct CallgrindFunction {
+    object: String,
+    file: String,
+    name: String
+}
+
+fn callgrind_function( state: &State, interner: &StringInterner, frame: &FrameKind ) -> (CallgrindFunction, u64) {
+    let unknown = || "???".to_owned();
+    match *frame {
+        FrameKind::UserByLine { ref binary_id, symbol, file, line, .. } => {
+            let function = CallgrindFunction {
+                object: state.get_binary( binary_id ).path().to_owned(),
+                file: interner.resolve( file ).unwrap().to_owned(),
+                name: interner.resolve( symbol ).unwrap().to_owned()
+            };
+            (function, line)
+        },
+        FrameKind::UserByFunction { ref binary_id, symbol, .. } |
+        FrameKind::UserByAddress { ref binary_id, symbol, .. } => {
+            let function = CallgrindFunction {
+                object: state.get_binary( binary_id ).path().to_owned(),
+                file: unknown(),
+                name: interner.resolve( symbol ).unwrap().to_owned()
+            };
+            (function, 0)
+        },
+        FrameKind::UserBinary( ref binary_id, address ) => {
+            let function = CallgrindFunction {
+                object: state.get_binary( binary_id ).path().to_owned(),
+                file: unknown(),
+                name: format!( "0x{:016X}", address )
+            };
+            (function, 0)
+        },
+        FrameKind::User( address ) => {
+            let function = CallgrindFunction {
+                object: unknown(),
+                file: unknown(),
+                name: format!( "0x{:016X}", address )
+            };
+            (function, 0)
+        },
+        FrameKind::KernelSymbol( index ) => {
+            let symbol = state.get_kernel_symbol( index );
+            let function = CallgrindFunction {
+                object: symbol.module.clone().unwrap_or_else( || "[kernel.kallsyms]".to_owned() ),
+                file: unknown(),
+                name: symbol.name.clone()
+            };
+            (function, 0)
+        },
+        FrameKind::Kernel( address ) => {
+            let function = CallgrindFunction {
+                object: "[kernel.kallsyms]".to_owned(),
+                file: unknown(),
+                name: format!( "0x{:016X}", address )
+            };
+            (function, 0)
+        },
+        FrameKind::Python { symbol, file, line } => {
+            let function = CallgrindFunction {
+                object: "[python]".to_owned(),
+                file: interner.resolve( file ).unwrap().to_owned(),
+                name: interner.resolve( symbol ).unwrap().to_owned()
+            };
+            (function, line.unwrap_or( 0 ))
+        },
+        FrameKind::Jit( symbol ) => {
+            let function = CallgrindFunction {
+                object: "[jit]".to_owned(),
+                file: unknown(),
+                name: interner.resolve( symbol ).unwrap().to_owned()
+            };
+            (function, 0)
+        },
+        FrameKind::Process( .. ) | FrameKind::Thread( .. ) | FrameKind::MainThread | FrameKind::UnwindFailure( .. ) | FrameKind::Cgroup( .. ) | FrameKind::Module { .. } => unreachable!()
+    }
+}
+
+#[derive(Default)]
+struct CallgrindCosts {
+    exclusive: BTreeMap< u64, u64 >,
+    calls: BTreeMap< (u64, CallgrindFunction), (u64, u64) >
+}
+
+struct CallgrindNames {
+    ids: HashMap< (&'static str, String), usize >
+}
+
+impl CallgrindNames {
+    // Callgrind supports name compression, where every name is written in full only once
+    // and is referred to by its numeric ID afterwards.
+    fn get( &mut self, kind: &'static str, name: &str ) -> String {
+        let next_id = self.ids.len() + 1;
+        let key = (kind, name.to_owned());
+        if let Some( &id ) = self.ids.get( &key ) {
+            return format!( "({})", id );
+        }
+
+        self.ids.insert( key, next_id );
+        format!( "({}) {}", next_id, name )
+    }
+}
+
+fn export_callgrind( args: &args::SharedCollationArgs ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Line
+    };
+
+    let mut interner = StringInterner::new();
+    let mut stacks: HashMap< Vec< FrameKind >, f64 > = HashMap::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                if let Some( frames ) = sample.decode( &event.state, &opts, &mut interner ) {
+                    *stacks.entry( frames ).or_insert( 0.0 ) += sample.weight;
+                }
+            },
+            _ => {}
+        }
+    })?;
+
+    let stacks = round_weights( stacks );
+
+    let mut costs: BTreeMap< CallgrindFunction, CallgrindCosts > = BTreeMap::new();
+    let mut total = 0;
+    for (frames, count) in stacks {
+        total += count;
+
+        let frames: Vec< _ > = frames.iter().rev().map( |frame| callgrind_function( &state, &interner, frame ) ).collect();
+        if let Some( &(ref function, line) ) = frames.last() {
+            *costs.entry( function.clone() ).or_default().exclusive.entry( line ).or_insert( 0 ) += count;
+        }
+
+        // Recursive calls would otherwise have their inclusive cost counted multiple times.
+        let mut seen = HashSet::new();
+        for pair in frames.windows( 2 ) {
+            let (ref caller, caller_line) = pair[ 0 ];
+            let (ref callee, _) = pair[ 1 ];
+            if !seen.insert( (caller, caller_line, callee) ) {
+                continue;
+            }
+
+            let entry = costs.entry( caller.clone() ).or_default().calls.entry( (caller_line, callee.clone()) ).or_insert( (0, 0) );
+            entry.0 += count;
+            entry.1 += count;
+        }
+    }
+
+    let mut names = CallgrindNames { ids: HashMap::new() };
+    let mut output = String::new();
+    writeln!( output, "# callgrind format" ).unwrap();
+    writeln!( output, "version: 1" ).unwrap();
+    writeln!( output, "creator: nperf {}", env!( "CARGO_PKG_VERSION" ) ).unwrap();
+    writeln!( output, "positions: line" ).unwrap();
+    writeln!( output, "events: Samples" ).unwrap();
+    writeln!( output, "summary: {}", total ).unwrap();
+
+    for (function, costs) in &costs {
+        writeln!( output ).unwrap();
+        writeln!( output, "ob={}", names.get( "ob", &function.object ) ).unwrap();
+        writeln!( output, "fl={}", names.get( "fl", &function.file ) ).unwrap();
+        writeln!( output, "fn={}", names.get( "fn", &function.name ) ).unwrap();
+
+        for (line, cost) in &costs.exclusive {
+            writeln!( output, "{} {}", line, cost ).unwrap();
+        }
+
+        for (&(line, ref callee), &(calls, inclusive)) in &costs.calls {
+            if callee.object != function.object {
+                writeln!( output, "cob={}", names.get( "ob", &callee.object ) ).unwrap();
+            }
+            if callee.file != function.file {
+                writeln!( output, "cfi={}", names.get( "fl", &callee.file ) ).unwrap();
+            }
+            writeln!( output, "cfn={}", names.get( "fn", &callee.name ) ).unwrap();
+            writeln!( output, "calls={} 0", calls ).unwrap();
+            writeln!( output, "{} {}", line, inclusive ).unwrap();
+        }
+    }
+
+    Ok( output.into_bytes() )
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_frame( state: &State, interner: &StringInterner, sample: &EventSample, frame: &FrameKind ) -> (Option< String >, Option< String >, u64) {
+    let file_offset = |address: u64| {
+        sample.process.memory_region( address ).map( |region| address - region.start + region.file_offset ).unwrap_or( address )
+    };
+
+    match *frame {
+        FrameKind::UserByAddress { ref binary_id, symbol, address, .. } => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            let symbol = interner.resolve( symbol ).unwrap().to_owned();
+            (Some( symbol ), Some( module ), file_offset( address ))
+        },
+        FrameKind::UserBinary( ref binary_id, address ) => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            (None, Some( module ), file_offset( address ))
+        },
+        FrameKind::User( address ) => (None, None, address),
+        FrameKind::Python { symbol, .. } => (Some( interner.resolve( symbol ).unwrap().to_owned() ), Some( "[python]".to_owned() ), 0),
+        FrameKind::Jit( symbol ) => (Some( interner.resolve( symbol ).unwrap().to_owned() ), Some( "[jit]".to_owned() ), 0),
+        _ => unreachable!()
+    }
+}
+
+/// Writes one row per every frame of every sample into an SQLite database.
+#[cfg(feature = "sqlite")]
+fn export_sqlite( args: &args::SharedCollationArgs, output: &std::ffi::OsStr ) -> Result< (), Box< dyn Error > > {
+    use rusqlite::{Connection, params};
+
+    if std::path::Path::new( output ).exists() {
+        std::fs::remove_file( output )?;
+    }
+
+    let mut connection = Connection::open( output )?;
+    connection.execute_batch( "
+        CREATE TABLE frames (
+            sample INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            pid INTEGER NOT NULL,
+            tid INTEGER NOT NULL,
+            cpu INTEGER NOT NULL,
+            depth INTEGER NOT NULL,
+            symbol TEXT,
+            module TEXT,
+            offset INTEGER NOT NULL
+        );
+    " )?;
+
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: false,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let transaction = connection.transaction()?;
+    {
+        let mut statement = transaction.prepare( "INSERT INTO frames VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)" )?;
+        let mut interner = StringInterner::new();
+        let mut sample_index: i64 = 0;
+        let mut result = Ok(());
+        read_data( read_data_args, |event| {
+            if result.is_err() {
+                return;
+            }
+
+            let state = event.state;
+            match event.kind {
+                EventKind::Sample( sample ) => {
+                    let frames = match sample.decode( state, &opts, &mut interner ) {
+                        Some( frames ) => frames,
+                        None => return
+                    };
+
+                    // The context markers (e.g. PERF_CONTEXT_KERNEL) are skipped.
+                    let kernel_frames = sample.kernel_backtrace.iter().filter( |&&address| address < !0 - 4095 ).map( |&address| {
+                        match state.get_kernel_symbol_by_address( address ) {
+                            Some( symbol ) => {
+                                let module = match symbol.module {
+                                    Some( ref module ) => format!( "[linux:{}]", module ),
+                                    None => "[linux]".to_owned()
+                                };
+                                (Some( symbol.name.clone() ), Some( module ), address)
+                            },
+                            None => (None, Some( "[linux]".to_owned() ), address)
+                        }
+                    });
+
+                    let user_frames = frames.iter().map( |frame| sqlite_frame( state, &interner, &sample, frame ) );
+
+                    // The frames are ordered from the leaf, which has a depth of zero.
+                    for (depth, (symbol, module, offset)) in kernel_frames.chain( user_frames ).enumerate() {
+                        result = statement.execute( params![
+                            sample_index,
+                            sample.timestamp as i64,
+                            sample.process.pid(),
+                            sample.tid,
+                            sample.cpu,
+                            depth as i64,
+                            symbol,
+                            module,
+                            offset as i64
+                        ]).map( |_| () );
+
+                        if result.is_err() {
+                            return;
+                        }
+                    }
+
+                    sample_index += 1;
+                },
+                _ => {}
+            }
+        })?;
+
+        result?;
+    }
+
+    transaction.commit()?;
+    connection.execute_batch( "CREATE INDEX frames_by_sample ON frames (sample, depth);" )?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn export_sqlite( _: &args::SharedCollationArgs, _: &std::ffi::OsStr ) -> Result< (), Box< dyn Error > > {
+    Err( "nperf was compiled without the `sqlite` feature".into() )
+}
+
+fn export_with_exporter( args: &args::ExportArgs, spec: &str ) -> Result< (), Box< dyn Error > > {
+    let mut exporter = create_exporter( spec )?;
+    if let Some( ref output ) = args.output {
+        let mut fp = io::BufWriter::new( File::create( output )? );
+        run_exporter( &args.collation_args, &mut *exporter, &mut fp )?;
+        fp.flush()?;
+    } else {
+        let stdout = io::stdout();
+        let mut stdout = io::BufWriter::new( stdout.lock() );
+        run_exporter( &args.collation_args, &mut *exporter, &mut stdout )?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+pub fn main( args: args::ExportArgs ) -> Result< (), Box< dyn Error > > {
+    let format = match args.format {
+        Some( ref format ) => format,
+        None => return export_with_exporter( &args, args.exporter.as_deref().unwrap() )
+    };
+
+    let data = match format {
+        ExportFormat::Pprof => export_pprof( &args.collation_args )?,
+        ExportFormat::Gecko => export_gecko( &args.collation_args )?,
+        ExportFormat::ChromeTrace => export_chrome_trace( &args.collation_args )?,
+        ExportFormat::Callgrind => export_callgrind( &args.collation_args )?,
+        ExportFormat::SpeedscopeEvented => export_speedscope_evented( &args.collation_args )?,
+        ExportFormat::Sqlite => {
+            let output = args.output.ok_or( "the `sqlite` format requires an output file to be specified with `--output`" )?;
+            return export_sqlite( &args.collation_args, &output );
+        }
+    };
+
+    if let Some( output ) = args.output {
+        let mut fp = io::BufWriter::new( File::create( output )? );
+        fp.write_all( &data )?;
+    } else {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all( &data )?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rename_rules_by_address() {
+    use std::ffi::OsStr;
+    use std::io::Read;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-pthread_cond_wait.nperf" );
+    let rules = std::env::temp_dir().join( format!( "nperf-export-symbol-rules-{}", std::process::id() ) );
+    std::fs::write( &rules, "rename-module ^libpthread.* renamed_pthread\n" ).unwrap();
+    let args = args::SharedCollationArgs::from_iter_safe( &[OsStr::new( "nperf" ), OsStr::new( "--symbol-rules" ), rules.as_os_str(), input.as_os_str()] ).unwrap();
+
+    let mut profile = Vec::new();
+    flate2::read::GzDecoder::new( &export_pprof( &args ).unwrap()[ .. ] ).read_to_end( &mut profile ).unwrap();
+    assert!( profile.windows( 15 ).any( |window| window == b"renamed_pthread" ) );
+
+    #[cfg(feature = "sqlite")]
+    {
+        let output = std::env::temp_dir().join( format!( "nperf-export-symbol-rules-{}.sqlite", std::process::id() ) );
+        export_sqlite( &args, output.as_os_str() ).unwrap();
+
+        let connection = rusqlite::Connection::open( &output ).unwrap();
+        let count: i64 = connection.query_row( "SELECT COUNT(*) FROM frames WHERE symbol = 'renamed_pthread'", rusqlite::NO_PARAMS, |row| row.get( 0 ) ).unwrap();
+        assert_ne!( count, 0 );
+        std::fs::remove_file( &output ).unwrap();
+    }
+
+    std::fs::remove_file( &rules ).unwrap();
+}
+
+#[test]
+fn test_anonymize_stalls() {
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use speedy::Writable;
+    use structopt::StructOpt;
+    use crate::archive::{ArchiveReader, FramedPacket, Packet};
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-pthread_cond_wait.nperf" );
+    let (pid, tid, timestamp) = ArchiveReader::new( File::open( &input ).unwrap() ).validate_header().unwrap().skip_unknown()
+        .find_map( |packet| match packet.unwrap() {
+            Packet::Sample { pid, tid, timestamp, .. } | Packet::RawSample { pid, tid, timestamp, .. } => Some( (pid, tid, timestamp) ),
+            _ => None
+        }).unwrap();
+
+    let output = std::env::temp_dir().join( format!( "nperf-export-stall-{}.nperf", std::process::id() ) );
+    let mut data = std::fs::read( &input ).unwrap();
+    FramedPacket::Known( Packet::Stall {
+        timestamp,
+        pid,
+        tid,
+        duration: 1_000_000_000,
+        kind: StallKind::SameStack,
+        backtrace: vec![ "secret_function [libsecret.so]".to_owned() ]
+    }).write_to_stream( &mut data ).unwrap();
+    std::fs::write( &output, &data ).unwrap();
+
+    let export = |extra: &[&str]| {
+        let mut argv = vec![ OsStr::new( "nperf" ) ];
+        argv.extend( extra.iter().map( OsStr::new ) );
+        argv.push( output.as_os_str() );
+        let args = args::SharedCollationArgs::from_iter_safe( &argv ).unwrap();
+        String::from_utf8( export_gecko( &args ).unwrap() ).unwrap()
+    };
+
+    let plain = export( &[] );
+    let anonymized = export( &["--anonymize", "strip"] );
+    std::fs::remove_file( &output ).unwrap();
+
+    assert!( plain.contains( "secret_function" ) );
+    assert!( anonymized.contains( "\"Stall\"" ) );
+    assert!( !anonymized.contains( "secret_function" ) && !anonymized.contains( "libsecret" ) );
+}
+
+#[test]
+fn test_gecko_output() {
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
+    let args = args::SharedCollationArgs::from_iter_safe( &[OsStr::new( "nperf" ), input.as_os_str()] ).unwrap();
+    crate::utils::assert_golden( "amd64-usleep_in_a_loop_no_fp_online.gecko.json", &export_gecko( &args ).unwrap() );
+}
+
+#[test]
+fn test_chrome_trace_output() {
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
+    let args = args::SharedCollationArgs::from_iter_safe( &[OsStr::new( "nperf" ), input.as_os_str()] ).unwrap();
+    crate::utils::assert_golden( "amd64-usleep_in_a_loop_no_fp_online.chrome-trace.json", &export_chrome_trace( &args ).unwrap() );
+}
+
+#[test]
+fn test_callgrind_output() {
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    let input = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
+    let args = args::SharedCollationArgs::from_iter_safe( &[OsStr::new( "nperf" ), input.as_os_str()] ).unwrap();
+    crate::utils::assert_golden( "amd64-usleep_in_a_loop_no_fp_online.callgrind", &export_callgrind( &args ).unwrap() );
+}
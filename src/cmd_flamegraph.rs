@@ -1,25 +1,225 @@
 use std::error::Error;
-use std::io;
+use std::io::{self, Write};
 use std::fs::File;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
 
-use inferno::flamegraph;
+use inferno::{differential, flamegraph};
 
-use crate::args;
-use crate::cmd_collate::collapse_into_sorted_vec;
+use crate::args::{self, Granularity};
+use crate::cmd_collate::{collapse_into_groups, collapse_into_sorted_vec, collapse_into_sorted_vec_for_comparison, collapse_from_memory, write_groups};
+
+#[derive(Debug)]
+pub enum FlamegraphFormat {
+    Svg,
+    Html
+}
+
+#[derive(Serialize)]
+struct HtmlNode {
+    #[serde(rename = "n")]
+    name: String,
+    #[serde(rename = "v")]
+    value: u64,
+    #[serde(rename = "c")]
+    children: Vec< HtmlNode >
+}
+
+#[derive(Default)]
+struct TreeNode {
+    value: u64,
+    children: BTreeMap< String, TreeNode >
+}
+
+impl TreeNode {
+    fn into_html_node( self, name: String ) -> HtmlNode {
+        HtmlNode {
+            name,
+            value: self.value,
+            children: self.children.into_iter().map( |(name, child)| child.into_html_node( name ) ).collect()
+        }
+    }
+}
+
+fn build_tree< 'a >( lines: impl IntoIterator< Item = &'a str >, inverted: bool ) -> HtmlNode {
+    let mut root = TreeNode::default();
+    for line in lines {
+        let index = match line.rfind( ' ' ) {
+            Some( index ) => index,
+            None => continue
+        };
+
+        let count: u64 = match line[ index + 1.. ].parse() {
+            Ok( count ) => count,
+            Err( _ ) => continue
+        };
+
+        root.value += count;
+        let mut frames: Vec< _ > = line[ ..index ].split( ';' ).collect();
+        if inverted {
+            frames.reverse();
+        }
+
+        let mut node = &mut root;
+        for frame in frames {
+            node = node.children.entry( frame.to_owned() ).or_default();
+            node.value += count;
+        }
+    }
+
+    root.into_html_node( "all".to_owned() )
+}
+
+fn escape_html( string: &str ) -> String {
+    string.replace( '&', "&amp;" ).replace( '<', "&lt;" ).replace( '>', "&gt;" )
+}
+
+fn write_html< 'a, T: Write >( lines: impl IntoIterator< Item = &'a str >, title: &str, inverted: bool, mut output: T ) -> Result< (), Box< dyn Error > > {
+    let tree = build_tree( lines, inverted );
+
+    // Make sure that nothing in the data can prematurely terminate the `<script>` tag.
+    let data = serde_json::to_string( &tree )?.replace( "</", "<\\/" );
+
+    let html = include_str!( "flamegraph.html" )
+        .replace( "{{TITLE}}", &escape_html( title ) )
+        .replace( "{{INVERTED}}", if inverted { "true" } else { "false" } )
+        .replace( "{{DATA}}", &data );
+
+    output.write_all( html.as_bytes() )?;
+    Ok(())
+}
+
+fn collapse_differential( args: &args::FlamegraphArgs ) -> Result< Vec< String >, Box< dyn Error > > {
+    let baseline = args.diff.as_ref().unwrap();
+    let mut baseline_args = args.collation_args.clone();
+    baseline_args.input = vec![ baseline.clone() ];
+
+    let before = collapse_into_sorted_vec_for_comparison( &baseline_args, &args.arg_granularity )?.join( "\n" );
+    let after = collapse_into_sorted_vec_for_comparison( &args.collation_args, &args.arg_granularity )?.join( "\n" );
+
+    let mut options = differential::Options::default();
+    options.normalize = true;
+
+    let mut output = Vec::new();
+    differential::from_readers( options, before.as_bytes(), after.as_bytes(), &mut output )?;
+
+    // The differential output comes out in an arbitrary order.
+    let output = String::from_utf8( output )?;
+    let mut lines: Vec< String > = output.lines().map( |line| line.to_owned() ).collect();
+    lines.sort();
+    Ok( lines )
+}
+
+fn write_flamegraph< T: Write >( format: &FlamegraphFormat, inverted: bool, lines: &[String], subtitle: Option< &str >, output: T ) -> Result< (), Box< dyn Error > > {
+    let mut title = if inverted { "Icicle Graph" } else { "Flame Graph" }.to_owned();
+    if let Some( subtitle ) = subtitle {
+        title = format!( "{}: {}", title, subtitle );
+    }
 
-pub fn main( args: args::FlamegraphArgs ) -> Result< (), Box< dyn Error > > {
-    let lines = collapse_into_sorted_vec( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads )?;
     let iter = lines.iter().map( |line| line.as_str() );
+    if let FlamegraphFormat::Html = format {
+        return write_html( iter, &title, inverted, output );
+    }
+
     let mut options = flamegraph::Options::default();
+    options.title = title;
+    if inverted {
+        options.reverse_stack_order = true;
+        options.direction = flamegraph::Direction::Inverted;
+    }
+
+    flamegraph::from_lines( &mut options, iter, output )?;
+    Ok(())
+}
+
+pub fn main( args: args::FlamegraphArgs ) -> Result< (), Box< dyn Error > > {
+    if let FlamegraphFormat::Html = args.format {
+        if args.diff.is_some() {
+            return Err( "differential flamegraphs are not supported in the HTML format".into() );
+        }
+    }
+
+    if let Some( split_by ) = args.arg_split_by.split_by {
+        if args.diff.is_some() {
+            return Err( "`--split-by` cannot be used together with `--diff`".into() );
+        }
+
+        let output_dir = match args.arg_split_by.output_dir {
+            Some( ref output_dir ) => Path::new( output_dir ),
+            None => return Err( "`--split-by` requires `--output-dir` to be specified".into() )
+        };
+
+        let extension = match args.format {
+            FlamegraphFormat::Svg => "svg",
+            FlamegraphFormat::Html => "html"
+        };
+
+        let groups = collapse_into_groups( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads, &args.arg_mark_truncated, &args.arg_group_by, None, split_by )?;
+        return write_groups( &groups, output_dir, extension, |group, fp| {
+            write_flamegraph( &args.format, args.inverted, &group.lines, Some( &group.name ), fp )
+        });
+    }
+
+    let lines = if args.diff.is_some() {
+        collapse_differential( &args )?
+    } else {
+        collapse_into_sorted_vec( &args.collation_args, &args.arg_granularity, &args.arg_merge_threads, &args.arg_mark_truncated, &args.arg_group_by, None )?
+    };
 
-    if let Some( output ) = args.output {
-        let fp = io::BufWriter::new( File::create( output )? );
-        flamegraph::from_lines( &mut options, iter, fp ).unwrap();
+    if let Some( ref output ) = args.output {
+        write_flamegraph( &args.format, args.inverted, &lines, None, io::BufWriter::new( File::create( output )? ) )?;
     } else {
         let stdout = io::stdout();
-        let stdout = stdout.lock();
-        flamegraph::from_lines( &mut options, iter, stdout ).unwrap();
+        write_flamegraph( &args.format, args.inverted, &lines, None, stdout.lock() )?;
     }
 
     Ok(())
 }
+
+/// Generates a flamegraph from a recording which is already in memory, e.g. in a web browser
+/// where there's no filesystem to read it from.
+pub fn flamegraph_from_memory( data: Arc< [u8] >, granularity: Granularity, format: FlamegraphFormat, inverted: bool ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let lines = collapse_from_memory( data, granularity, false )?;
+    let mut output = Vec::new();
+    write_flamegraph( &format, inverted, &lines, None, &mut output )?;
+    Ok( output )
+}
+
+#[test]
+fn test_build_tree() {
+    let tree = build_tree( vec![ "a;b;c 1", "a;b 2", "a;d 3" ], false );
+    assert_eq!( tree.value, 6 );
+    assert_eq!( tree.children.len(), 1 );
+
+    let a = &tree.children[ 0 ];
+    assert_eq!( a.name, "a" );
+    assert_eq!( a.value, 6 );
+    assert_eq!( a.children.iter().map( |child| (child.name.as_str(), child.value) ).collect::< Vec< _ > >(), vec![ ("b", 3), ("d", 3) ] );
+    assert_eq!( a.children[ 0 ].children[ 0 ].name, "c" );
+}
+
+#[test]
+fn test_build_tree_inverted() {
+    let tree = build_tree( vec![ "a;b;c 1", "d;c 2", "a;b 3" ], true );
+    assert_eq!( tree.value, 6 );
+    assert_eq!( tree.children.iter().map( |child| (child.name.as_str(), child.value) ).collect::< Vec< _ > >(), vec![ ("b", 3), ("c", 3) ] );
+
+    let c = &tree.children[ 1 ];
+    assert_eq!( c.children.iter().map( |child| (child.name.as_str(), child.value) ).collect::< Vec< _ > >(), vec![ ("b", 1), ("d", 2) ] );
+}
+
+#[test]
+fn test_collapse_differential() {
+    use std::ffi::OsStr;
+    use structopt::StructOpt;
+
+    let artifacts = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" );
+    let baseline = artifacts.join( "amd64-usleep_in_a_loop_no_fp.nperf" );
+    let input = artifacts.join( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
+    let args = args::FlamegraphArgs::from_iter_safe( &[OsStr::new( "nperf" ), OsStr::new( "--diff" ), baseline.as_os_str(), input.as_os_str()] ).unwrap();
+
+    let mut output = collapse_differential( &args ).unwrap().join( "\n" );
+    output.push( '\n' );
+    crate::utils::assert_golden( "amd64-usleep_in_a_loop_no_fp.diff.folded", output.as_bytes() );
+}
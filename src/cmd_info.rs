@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::args;
+use crate::cmd_metadata::generate_metadata;
+use crate::data_reader::to_s;
+use crate::metadata;
+use crate::self_profile::describe_self_profile;
+
+/// At most this many points of the memory use are shown.
+const MAX_MEMORY_TIMELINE_LENGTH: usize = 20;
+
+fn mb( bytes: u64 ) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Summarizes the memory use over the course of the recording, with evenly spaced points of its timeline.
+fn describe_memory_stats( stats: &[metadata::MemoryStats] ) -> Vec< String > {
+    let first_timestamp = stats.iter().map( |stats| stats.timestamp ).min().unwrap_or( 0 );
+    let peak = |value: fn( &metadata::MemoryStats ) -> u64| stats.iter().map( value ).max().unwrap_or( 0 );
+
+    let mut output = vec![
+        format!( "Measurements: {}", stats.len() ),
+        format!( "Peak RSS: {:.1} MB", mb( peak( |stats| stats.rss ) ) ),
+        format!( "Peak PSS: {:.1} MB", mb( peak( |stats| stats.pss ) ) ),
+        format!( "Peak swap: {:.1} MB", mb( peak( |stats| stats.swap ) ) ),
+        format!( "{:>10} {:>8} {:>12} {:>12} {:>12}", "Time", "PID", "RSS", "PSS", "Swap" )
+    ];
+
+    let step = stats.len().saturating_sub( 1 ) / MAX_MEMORY_TIMELINE_LENGTH + 1;
+    let last_index = stats.len().saturating_sub( 1 );
+    for (index, stats) in stats.iter().enumerate() {
+        if index % step != 0 && index != last_index {
+            continue;
+        }
+
+        output.push( format!(
+            "{:>9.1}s {:>8} {:>9.1} MB {:>9.1} MB {:>9.1} MB",
+            to_s( stats.timestamp - first_timestamp ),
+            stats.pid,
+            mb( stats.rss ),
+            mb( stats.pss ),
+            mb( stats.swap )
+        ));
+    }
+
+    output
+}
+
+pub fn main( args: args::InfoArgs ) -> Result< (), Box< dyn Error > > {
+    let metadata = generate_metadata( args::MetadataArgs { input: args.input } )?;
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    if let Some( ref machine_info ) = metadata.machine_info {
+        writeln!( stdout, "Architecture: {}", machine_info.architecture )?;
+    }
+
+    for process in &metadata.processes {
+        writeln!( stdout, "Process: {} (PID {})", process.executable, process.pid )?;
+    }
+
+    writeln!( stdout, "Binaries: {}", metadata.binaries.len() )?;
+
+    if let Some( ref stats ) = metadata.recording_stats {
+        writeln!( stdout )?;
+        writeln!( stdout, "Recording:" )?;
+        writeln!( stdout, "  Samples: {}", stats.sample_count )?;
+        writeln!( stdout, "  Lost events: {}", stats.lost_event_count )?;
+        writeln!( stdout, "  Unwound samples: {}", stats.unwound_sample_count )?;
+        writeln!( stdout, "  Frames: {}", stats.frame_count )?;
+        writeln!( stdout, "  Truncated stacks: {}", stats.truncated_stack_count )?;
+        writeln!( stdout, "  Unwind failures: {}", stats.unwind_failure_count )?;
+        writeln!( stdout, "  Bytes written: {}", stats.bytes_written )?;
+    }
+
+    if !metadata.memory_stats.is_empty() {
+        writeln!( stdout )?;
+        writeln!( stdout, "Memory use:" )?;
+        for line in describe_memory_stats( &metadata.memory_stats ) {
+            writeln!( stdout, "  {}", line )?;
+        }
+    }
+
+    writeln!( stdout )?;
+    writeln!( stdout, "Profiler overhead:" )?;
+    match metadata.self_profile {
+        Some( ref self_profile ) => {
+            for line in describe_self_profile( self_profile ) {
+                writeln!( stdout, "  {}", line )?;
+            }
+        },
+        None => writeln!( stdout, "  Not measured; record with `--self-profile` to measure it" )?
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_describe_memory_stats() {
+    let stats: Vec< _ > = (0..50).map( |index| metadata::MemoryStats {
+        pid: 1234,
+        timestamp: 1_000_000_000 + index * 500_000_000,
+        rss: (10 + index) * 1024 * 1024,
+        pss: 5 * 1024 * 1024,
+        swap: 0
+    }).collect();
+
+    let lines = describe_memory_stats( &stats );
+    assert_eq!( &lines[ ..4 ], &[
+        "Measurements: 50".to_owned(),
+        "Peak RSS: 59.0 MB".to_owned(),
+        "Peak PSS: 5.0 MB".to_owned(),
+        "Peak swap: 0.0 MB".to_owned()
+    ]);
+
+    // The header, every third point, and the last one.
+    assert_eq!( lines.len(), 5 + 17 + 1 );
+    assert_eq!( lines[ 5 ], "      0.0s     1234      10.0 MB       5.0 MB       0.0 MB" );
+    assert_eq!( lines.last().unwrap(), "     24.5s     1234      59.0 MB       5.0 MB       0.0 MB" );
+}
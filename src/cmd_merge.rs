@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::error::Error;
+use std::io::{self, Write};
+
+use speedy::Writable;
+
+use crate::args;
+use crate::archive::{ARCHIVE_MAGIC, ARCHIVE_VERSION, FramedPacket, Packet};
+use crate::merge::MergedReader;
+use crate::folded;
+
+fn merge_folded( args: &args::MergeArgs ) -> Result< (), Box< dyn Error > > {
+    let stacks = folded::read_folded( &args.input )?;
+    let mut lines: Vec< _ > = stacks.into_iter().map( |(stack, count)| format!( "{} {}", stack, count ) ).collect();
+    lines.sort_unstable();
+
+    let fp = File::create( &args.output ).map_err( |err| format!( "cannot create {:?}: {}", args.output, err ) )?;
+    let mut fp = io::BufWriter::new( fp );
+    for line in &lines {
+        writeln!( fp, "{}", line )?;
+    }
+
+    fp.flush()?;
+    info!( "Written {} stacks from {} inputs into {:?}", lines.len(), args.input.len(), args.output );
+
+    Ok(())
+}
+
+pub fn main( args: args::MergeArgs ) -> Result< (), Box< dyn Error > > {
+    let (recordings, folded_inputs) = folded::partition_inputs( &args.input )?;
+    if !folded_inputs.is_empty() {
+        if !recordings.is_empty() {
+            return Err( "cannot merge nperf recordings with collapsed stack files; pass them all to `collate` or `flamegraph` instead, or convert the recordings with `collate` first".into() );
+        }
+
+        return merge_folded( &args );
+    }
+
+    let inputs: Vec< _ > = args.input.iter().map( |path| path.as_os_str() ).collect();
+    let reader = MergedReader::open( &inputs )?;
+
+    let fp = File::create( &args.output ).map_err( |err| format!( "cannot create {:?}: {}", args.output, err ) )?;
+    let mut fp = io::BufWriter::new( fp );
+
+    FramedPacket::Known( Packet::Header {
+        magic: ARCHIVE_MAGIC,
+        version: ARCHIVE_VERSION
+    }).write_to_stream( &mut fp )?;
+
+    let mut count = 0;
+    for packet in reader {
+        FramedPacket::Known( packet? ).write_to_stream( &mut fp )?;
+        count += 1;
+    }
+
+    fp.flush()?;
+    info!( "Written {} packets from {} inputs into {:?}", count, inputs.len(), args.output );
+
+    Ok(())
+}
@@ -67,6 +67,32 @@ pub fn generate_metadata( args: args::MetadataArgs ) -> Result< Metadata, Box< d
                     metadata.binaries[ index ].build_id = Some( build_id );
                 }
             },
+            Packet::RecordingStats { sample_count, lost_event_count, unwound_sample_count, frame_count, truncated_stack_count, unwind_failure_count, binary_count, bytes_written } => {
+                metadata.recording_stats = Some( metadata::RecordingStats {
+                    sample_count,
+                    lost_event_count,
+                    unwound_sample_count,
+                    frame_count,
+                    truncated_stack_count,
+                    unwind_failure_count,
+                    binary_count,
+                    bytes_written
+                });
+            },
+            Packet::SelfProfile { wall_time, cpu_time, sampling_time, unwinding_time, symbol_loading_time, io_time, max_rss } => {
+                metadata.self_profile = Some( metadata::SelfProfile {
+                    wall_time,
+                    cpu_time,
+                    sampling_time,
+                    unwinding_time,
+                    symbol_loading_time,
+                    io_time,
+                    max_rss
+                });
+            },
+            Packet::MemoryStats { pid, timestamp, rss, pss, swap } => {
+                metadata.memory_stats.push( metadata::MemoryStats { pid, timestamp, rss, pss, swap } );
+            },
             _ => {}
         }
     }
@@ -1,20 +1,44 @@
 use std::borrow::Cow;
 use std::error::Error;
+use std::io;
+use std::time::Duration;
 
 use libc;
 
 use proc_maps::Region;
 use nwind::arch::Registers;
-use nwind::DwarfRegs;
+use nwind::{DwarfRegs, RangeMap};
 
 use crate::args;
-use perf_event_open::{Event, CommEvent, Mmap2Event};
-use crate::perf_group::PerfGroup;
+use crate::capabilities::Capabilities;
+use crate::kallsyms;
+use crate::push::Pusher;
+use perf_event_open::{Event, EventSource, CommEvent, Mmap2Event};
+use crate::perf_group::{PerfGroup, Sampling};
 use crate::perf_arch;
 use crate::archive::{ContextSwitchKind, Packet};
 use crate::profiler::{ProfilingController, Sample};
+use crate::triggers::{Trigger, Triggers};
+use crate::timer_sampler;
+use crate::data_reader::HEAVY_MULTIPLEXING_THRESHOLD;
+
+/// How the samples are gathered.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sampler {
+    /// With perf events; this is the most accurate and has the lowest overhead.
+    Perf,
+    /// By periodically stopping the running threads with ptrace.
+    Timer,
+    /// By periodically stopping the whole process with ptrace and sampling every thread.
+    Snapshot
+}
+
+fn handle_comm_event( event: CommEvent, controller: &mut ProfilingController ) {
+    // The process could have been moved into another cgroup when it was exec'd, e.g. by a container runtime.
+    if event.is_exec {
+        controller.refresh_cgroup( event.pid );
+    }
 
-fn handle_comm_event( event: CommEvent, controller: &ProfilingController ) {
     let packet = Packet::ThreadName {
         pid: event.pid,
         tid: event.tid,
@@ -24,20 +48,29 @@ fn handle_comm_event( event: CommEvent, controller: &ProfilingController ) {
     controller.write_borrowed_packet( packet );
 }
 
-fn handle_mmap2_event( event: Mmap2Event, new_maps: &mut Vec< Region > ) {
+fn handle_mmap2_event( event: Mmap2Event, controller: &mut ProfilingController, new_maps: &mut Vec< Region > ) {
     let name = if event.filename == b"//anon" {
         "".to_owned()
     } else {
         String::from_utf8( event.filename ).expect( "mmaped page's name contains invalid UTF-8" )
     };
 
+    // When the kernel gives us the build ID it doesn't give us the major, minor and inode.
+    let is_identified = event.major != 0 || event.minor != 0 || event.inode != 0;
+    let (major, minor, inode) = if !is_identified && !name.is_empty() {
+        let inode = controller.identify_mapped_file( &name, event.build_id );
+        (inode.dev_major, inode.dev_minor, inode.inode)
+    } else {
+        (event.major, event.minor, event.inode)
+    };
+
     let region = Region {
         start: event.address,
         end: event.address + event.length,
         file_offset: event.page_offset,
-        major: event.major,
-        minor: event.minor,
-        inode: event.inode,
+        major,
+        minor,
+        inode,
         name,
         is_shared: (event.flags & libc::MAP_SHARED as u32) != 0,
         is_read: (event.protection & libc::PROT_READ as u32) != 0,
@@ -50,43 +83,104 @@ fn handle_mmap2_event( event: Mmap2Event, new_maps: &mut Vec< Region > ) {
     }
 }
 
-pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
-    let discard_all = args.discard_all;
+fn is_not_supported( error: &io::Error ) -> bool {
+    match error.raw_os_error() {
+        Some( libc::ENOENT ) | Some( libc::ENODEV ) | Some( libc::EOPNOTSUPP ) => true,
+        _ => false
+    }
+}
 
-    let mut controller = ProfilingController::new( &args.profiler_args )?;
-    controller.write_packet( Packet::ProfilingFrequency {
-        frequency: args.frequency
-    });
+fn is_permission_denied( error: &io::Error ) -> bool {
+    match error.raw_os_error() {
+        Some( libc::EACCES ) | Some( libc::EPERM ) => true,
+        _ => false
+    }
+}
 
-    info!( "Opening perf events for process with PID {}...", controller.pid() );
-    let mut perf = match PerfGroup::open( controller.pid(), args.frequency, args.stack_size, args.event_source ) {
-        Ok( perf ) => perf,
-        Err( error ) => {
-            error!( "Failed to start profiling: {}", error );
-            if error.kind() == std::io::ErrorKind::PermissionDenied {
-                if let Ok( perf_event_paranoid ) = crate::utils::read_string_lossy( "/proc/sys/kernel/perf_event_paranoid" ) {
-                    let perf_event_paranoid = perf_event_paranoid.trim();
-                    match perf_event_paranoid {
-                        "2" => {
-                            warn!( "The '/proc/sys/kernel/perf_event_paranoid' is set to '{}', which is probably why you can't start the profiling", perf_event_paranoid );
-                            warn!( "You can try lowering it before trying to start the profiling again:" );
-                            warn!( "    echo '1' | sudo tee /proc/sys/kernel/perf_event_paranoid" );
-                        },
-                        _ => {}
-                    }
-                }
-            }
+/// Whether the failure to open the perf events means that they can't be used at all.
+fn is_perf_unavailable( error: &io::Error ) -> bool {
+    error.kind() == io::ErrorKind::PermissionDenied || is_not_supported( error ) || error.raw_os_error() == Some( libc::ENOSYS )
+}
+
+fn perf_group_failure( controller: &ProfilingController, error: io::Error ) -> Box< dyn Error > {
+    error!( "Failed to start profiling: {}", error );
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        let capabilities = Capabilities::detect();
+        warn!( "Current privileges: {}", capabilities );
+        if capabilities.check_perf_events().is_err() {
+            warn!( "You can try lowering the '/proc/sys/kernel/perf_event_paranoid' before trying to start the profiling again:" );
+            warn!( "    echo '1' | sudo tee /proc/sys/kernel/perf_event_paranoid" );
+        } else {
+            warn!( "Profiling a process which belongs to another user requires running as that user, or as root" );
+        }
+    }
 
-            let _ = std::fs::remove_file( controller.output_path() );
-            return Err( format!( "failed to start profiling: {}", error ).into() );
+    controller.remove_output();
+    format!( "failed to start profiling: {}", error ).into()
+}
+
+/// Opens the perf events for the profiled process and enables them.
+///
+/// If we're not privileged enough to sample the kernel, or the requested event is not
+/// supported by the machine, the profiling falls back to what's available and says so;
+/// any other permission errors are returned as-is.
+pub(crate) fn open_perf_group(
+    controller: &mut ProfilingController,
+    sampling: Sampling,
+    stack_size: u32,
+    buffer_size: Option< u32 >,
+    event_source: EventSource
+) -> Result< PerfGroup, Box< dyn Error > > {
+    try_open_perf_group( controller, sampling, stack_size, buffer_size, event_source ).map_err( |error| perf_group_failure( controller, error ) )
+}
+
+fn try_open_perf_group(
+    controller: &mut ProfilingController,
+    sampling: Sampling,
+    stack_size: u32,
+    buffer_size: Option< u32 >,
+    mut event_source: EventSource
+) -> io::Result< PerfGroup > {
+    let capabilities = Capabilities::detect();
+    debug!( "Privileges: {}", capabilities );
+
+    if let Err( reason ) = capabilities.check_perf_events() {
+        return Err( io::Error::new( io::ErrorKind::PermissionDenied, format!( "not allowed to use perf events since {}", reason ) ) );
+    }
+
+    let mut sample_kernel = true;
+    if let Err( reason ) = capabilities.check_kernel_sampling() {
+        warn!( "Kernel samples are disabled since {}; only the user space will be sampled", reason );
+        sample_kernel = false;
+    }
+
+    // We only ever open the events for the threads of the profiled process, which doesn't need this.
+    if let Err( reason ) = capabilities.check_cpu_wide_events() {
+        debug!( "CPU-wide events are not allowed since {}", reason );
+    }
+
+    info!( "Opening perf events for process with PID {}...", controller.pid() );
+    let mut perf = loop {
+        match PerfGroup::open( controller.pid(), sampling, stack_size, buffer_size, event_source, sample_kernel ) {
+            Ok( perf ) => break perf,
+            // A lack of permissions won't be fixed by switching the event, so that's handled below.
+            Err( ref error ) if event_source.is_hardware() && is_not_supported( error ) => {
+                warn!( "Hardware events can't be used on this machine ({}); falling back to the software CPU clock", error );
+                event_source = EventSource::SwCpuClock;
+            },
+            Err( ref error ) if sample_kernel && is_permission_denied( error ) => {
+                warn!( "Not allowed to sample the kernel ({}); only the user space will be sampled", error );
+                sample_kernel = false;
+            },
+            Err( error ) => return Err( error )
         }
     };
 
     let mut new_maps = Vec::new();
     for event in perf.take_initial_events() {
         match event {
-            Event::Mmap2( event ) => handle_mmap2_event( event, &mut new_maps ),
-            Event::Comm( event ) => handle_comm_event( event, &controller ),
+            Event::Mmap2( event ) => handle_mmap2_event( event, controller, &mut new_maps ),
+            Event::Comm( event ) => handle_comm_event( event, controller ),
             _ => unreachable!()
         }
     }
@@ -96,13 +190,28 @@ pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
     info!( "Enabling perf events..." );
     perf.enable();
 
+    Ok( perf )
+}
+
+/// Processes the events from the perf events until the profiling is stopped,
+/// calling `on_sample` for every gathered sample.
+///
+/// Returns the total number of lost events.
+pub(crate) fn run< F >( controller: &mut ProfilingController, perf: &mut PerfGroup, discard_all: bool, mut on_sample: F ) -> u64
+    where F: FnMut( &mut ProfilingController, &mut DwarfRegs, Sample )
+{
     info!( "Running..." );
 
+    let mut new_maps = Vec::new();
     let mut wait = false;
     let mut pending_lost_events = 0;
     let mut total_lost_events = 0;
     let mut dwarf_regs = DwarfRegs::new();
     loop {
+        controller.poll_triggers();
+        controller.report_progress();
+        controller.poll_perf_map();
+        controller.poll_memory_stats();
         if perf.is_empty() || controller.should_stop() {
             break;
         }
@@ -141,16 +250,17 @@ pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
                         continue;
                     }
 
-                    handle_mmap2_event( event, &mut new_maps );
+                    handle_mmap2_event( event, controller, &mut new_maps );
                     continue;
                 },
                 Event::Comm( event ) => {
-                    handle_comm_event( event, &controller );
+                    handle_comm_event( event, controller );
                     continue;
                 },
                 Event::Lost( event ) => {
                     pending_lost_events += event.count;
                     total_lost_events += event.count;
+                    controller.add_lost_events( event.count );
                     continue;
                 },
                 _ => {}
@@ -167,13 +277,17 @@ pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
 
             match event {
                 Event::Sample( event ) => {
+                    if controller.is_paused() {
+                        continue;
+                    }
+
                     if let Some( regs ) = event.regs {
                         perf_arch::native::into_dwarf_regs( &regs, &mut dwarf_regs );
                     } else {
                         dwarf_regs.clear();
                     }
 
-                    controller.generate_sample( &mut dwarf_regs, Sample {
+                    on_sample( controller, &mut dwarf_regs, Sample {
                         timestamp: event.timestamp,
                         pid: event.pid,
                         tid: event.tid,
@@ -200,9 +314,131 @@ pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
         }
     }
 
+    total_lost_events
+}
+
+fn parse_triggers( triggers: &[String], option: &str ) -> Result< Vec< Trigger >, Box< dyn Error > > {
+    triggers.iter().map( |trigger| {
+        Trigger::parse( trigger ).map_err( |err| format!( "invalid '{}' trigger '{}': {}", option, trigger, err ).into() )
+    }).collect()
+}
+
+pub fn main( args: args::RecordArgs ) -> Result< (), Box< dyn Error > > {
+    let start_triggers = parse_triggers( &args.start_when, "--start-when" )?;
+    let stop_triggers = parse_triggers( &args.stop_when, "--stop-when" )?;
+
+    let mut controller = ProfilingController::new( &args.profiler_args )?;
+    if !start_triggers.is_empty() || !stop_triggers.is_empty() {
+        let triggers = Triggers::new( controller.pid(), start_triggers, stop_triggers );
+        controller.set_triggers( triggers );
+    }
+
+    let sampling = match args.period {
+        Some( 0 ) => return Err( "the sampling period must be greater than zero".into() ),
+        Some( period ) => Sampling::Period( period ),
+        None => {
+            controller.write_packet( Packet::ProfilingFrequency {
+                frequency: args.frequency
+            });
+
+            Sampling::Frequency( args.frequency )
+        }
+    };
+
+    let pusher = match args.push_args.push_url {
+        Some( ref url ) => Some( Pusher::new( &args.push_args, url, controller.pid(), args.frequency )? ),
+        None => None
+    };
+
+    let kallsyms = if pusher.is_some() {
+        kallsyms::read().map( |kallsyms| kallsyms::parse( &kallsyms ) ).unwrap_or_else( RangeMap::new )
+    } else {
+        RangeMap::new()
+    };
+
+    let mut perf = None;
+    if args.sampler == Sampler::Perf {
+        match try_open_perf_group( &mut controller, sampling, args.stack_size, args.buffer_size, args.event_source ) {
+            Ok( group ) => perf = Some( group ),
+            Err( ref error ) if is_perf_unavailable( error ) => {
+                warn!( "Perf events can't be used ({}); falling back to the timer-based sampler", error );
+            },
+            Err( error ) => return Err( perf_group_failure( &controller, error ) )
+        }
+    }
+
+    let mut frames = Vec::new();
+    let mut on_sample = |controller: &mut ProfilingController, dwarf_regs: &mut DwarfRegs, sample: Sample| {
+        if let Some( ref pusher ) = pusher {
+            frames.clear();
+            kallsyms::symbolize_backtrace( &kallsyms, &sample.kernel_backtrace, &mut frames );
+            controller.generate_and_symbolize_sample( dwarf_regs, sample, &mut frames );
+            pusher.add( &frames );
+        } else {
+            controller.generate_sample( dwarf_regs, sample );
+        }
+    };
+
+    let total_lost_events = match perf {
+        Some( ref mut perf ) => {
+            if let Some( active_cpus ) = args.round_robin_cpus {
+                let total_cpus = perf.cpu_count();
+                if active_cpus == 0 {
+                    return Err( "the number of round-robin CPUs must be greater than zero".into() );
+                } else if active_cpus < total_cpus {
+                    info!( "Sampling {} out of {} CPUs at a time", active_cpus, total_cpus );
+                    perf.set_round_robin( active_cpus, Duration::from_millis( args.round_robin_interval ) );
+                    controller.write_packet( Packet::RoundRobinCpus {
+                        active_cpus,
+                        total_cpus
+                    });
+                }
+            }
+
+            run( &mut controller, perf, args.discard_all, on_sample )
+        },
+        None => {
+            let frequency = match sampling {
+                Sampling::Frequency( frequency ) => frequency,
+                Sampling::Period( _ ) => return Err( "only the perf sampler can sample every given number of events; use `--frequency` instead".into() )
+            };
+
+            if args.round_robin_cpus.is_some() {
+                warn!( "The `--round-robin-cpus` is only supported by the perf sampler; ignoring it" );
+            }
+
+            if args.sampler == Sampler::Snapshot {
+                timer_sampler::run_snapshots( &mut controller, frequency, args.stack_size, args.discard_all, &mut on_sample )?
+            } else {
+                timer_sampler::run( &mut controller, frequency, args.stack_size, args.discard_all, &mut on_sample )?
+            }
+        }
+    };
+
+    if let Some( pusher ) = pusher {
+        pusher.finish();
+    }
+
+    if let Some( ref perf ) = perf {
+        let counters = perf.read_counters();
+        if counters.time_enabled > 0 {
+            if counters.running_ratio() < HEAVY_MULTIPLEXING_THRESHOLD {
+                warn!( "The event was only running {:.1}% of the time due to multiplexing with other events!", counters.running_ratio() * 100.0 );
+            }
+
+            controller.write_packet( Packet::Multiplexing {
+                time_enabled: counters.time_enabled,
+                time_running: counters.time_running
+            });
+        }
+    }
+
     if total_lost_events > 0 {
         warn!( "Lost {} events!", total_lost_events );
     }
 
-    Ok(())
+    controller.finish( total_lost_events );
+
+    let thresholds = &args.arg_quality_thresholds;
+    controller.quality_stats( total_lost_events ).check( thresholds.fail_if_lost, thresholds.fail_if_unwind_failures )
 }
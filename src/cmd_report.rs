@@ -0,0 +1,402 @@
+use std::error::Error;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::args;
+use crate::interner::StringInterner;
+use crate::data_reader::{State, DecodeOpts, EventKind, FrameKind, read_data, repack_cli_args, round_weights};
+use crate::utils::percent;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ReportSort {
+    SelfSamples,
+    TotalSamples,
+    Symbol,
+    Module
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ReportFormat {
+    Table,
+    Csv
+}
+
+pub(crate) struct Entry {
+    pub module: String,
+    pub symbol: String,
+    pub is_kernel: bool,
+    pub self_samples: u64,
+    pub total_samples: u64
+}
+
+pub(crate) fn describe_frame( state: &State, interner: &StringInterner, frame: &FrameKind ) -> (String, String, bool) {
+    match *frame {
+        FrameKind::UserByLine { ref binary_id, is_inline, symbol, file, line } => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            let symbol = interner.resolve( symbol ).unwrap();
+            let file = interner.resolve( file ).unwrap();
+            let file = &file[ file.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+            let inline = if is_inline { "inline " } else { "" };
+            (module, format!( "{}{} [{}:{}]", inline, symbol, file, line ), false)
+        },
+        FrameKind::UserByFunction { ref binary_id, is_inline, symbol } => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            let symbol = interner.resolve( symbol ).unwrap();
+            let inline = if is_inline { "inline " } else { "" };
+            (module, format!( "{}{}", inline, symbol ), false)
+        },
+        FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, .. } => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            let symbol = interner.resolve( symbol ).unwrap();
+            let inline = if is_inline { "inline " } else { "" };
+            (module, format!( "0x{:016X} {}{}", address, inline, symbol ), false)
+        },
+        FrameKind::UserBinary( ref binary_id, address ) => {
+            let module = state.get_binary( binary_id ).basename().to_owned();
+            (module, format!( "0x{:016X}", address ), false)
+        },
+        FrameKind::User( address ) => {
+            ("[unknown]".to_owned(), format!( "0x{:016X}", address ), false)
+        },
+        FrameKind::KernelSymbol( index ) => {
+            let symbol = state.get_kernel_symbol( index );
+            let module = match symbol.module {
+                Some( ref module ) => format!( "[linux:{}]", module ),
+                None => "[linux]".to_owned()
+            };
+            (module, symbol.name.clone(), true)
+        },
+        FrameKind::Kernel( address ) => {
+            ("[linux]".to_owned(), format!( "0x{:016X}", address ), true)
+        },
+        FrameKind::Module { name, is_kernel } => {
+            let name = interner.resolve( name ).unwrap().to_owned();
+            (name.clone(), name, is_kernel)
+        },
+        FrameKind::Python { symbol, file, line } => {
+            let symbol = interner.resolve( symbol ).unwrap();
+            let file = interner.resolve( file ).unwrap();
+            let file = &file[ file.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+            match line {
+                Some( line ) => ("[python]".to_owned(), format!( "{} [{}:{}]", symbol, file, line ), false),
+                None => ("[python]".to_owned(), format!( "{} [{}]", symbol, file ), false)
+            }
+        },
+        FrameKind::Jit( symbol ) => {
+            ("[jit]".to_owned(), interner.resolve( symbol ).unwrap().to_owned(), false)
+        },
+        FrameKind::Process( .. ) | FrameKind::Thread( .. ) | FrameKind::MainThread | FrameKind::UnwindFailure( .. ) | FrameKind::Cgroup( .. ) => unreachable!()
+    }
+}
+
+pub(crate) fn compare_entries( a: &Entry, b: &Entry, sort: ReportSort ) -> Ordering {
+    match sort {
+        ReportSort::SelfSamples => b.self_samples.cmp( &a.self_samples ).then( b.total_samples.cmp( &a.total_samples ) ),
+        ReportSort::TotalSamples => b.total_samples.cmp( &a.total_samples ).then( b.self_samples.cmp( &a.self_samples ) ),
+        ReportSort::Symbol => a.symbol.cmp( &b.symbol ).then( a.module.cmp( &b.module ) ),
+        ReportSort::Module => a.module.cmp( &b.module ).then( b.self_samples.cmp( &a.self_samples ) )
+    }
+}
+
+fn sort_entries( entries: &mut Vec< Entry >, sort: ReportSort ) {
+    entries.sort_by( |a, b| compare_entries( a, b, sort ) );
+}
+
+fn collect_entries(
+    state: &State,
+    interner: &StringInterner,
+    stacks: &HashMap< Vec< FrameKind >, u64 >,
+    sort: ReportSort
+) -> Vec< Entry > {
+    let mut self_samples: HashMap< &FrameKind, u64 > = HashMap::new();
+    let mut total_samples: HashMap< &FrameKind, u64 > = HashMap::new();
+    for (frames, &count) in stacks {
+        if let Some( frame ) = frames.first() {
+            *self_samples.entry( frame ).or_insert( 0 ) += count;
+        }
+
+        // A function which appears multiple times in a single stack (e.g. due to recursion)
+        // should only be counted once.
+        let unique: HashSet< _ > = frames.iter().collect();
+        for frame in unique {
+            *total_samples.entry( frame ).or_insert( 0 ) += count;
+        }
+    }
+
+    let mut entries: Vec< _ > = total_samples.into_iter().map( |(frame, total_samples)| {
+        let (module, symbol, is_kernel) = describe_frame( state, interner, frame );
+        Entry {
+            module,
+            symbol,
+            is_kernel,
+            self_samples: self_samples.get( frame ).cloned().unwrap_or( 0 ),
+            total_samples
+        }
+    }).collect();
+
+    sort_entries( &mut entries, sort );
+    entries
+}
+
+fn write_table< T: Write >( entries: &[Entry], sample_count: u64, output: &mut T ) -> Result< (), io::Error > {
+    writeln!( output, "# Samples: {}", sample_count )?;
+    writeln!( output, "#" )?;
+    writeln!( output, "# {:>8}  {:>8}  {:>10}  {:>12}  {:<24}  {}", "Children", "Self", "Samples", "Self samples", "Module", "Symbol" )?;
+    writeln!( output, "#" )?;
+
+    for entry in entries {
+        writeln!(
+            output,
+            "  {:>7.2}%  {:>7.2}%  {:>10}  {:>12}  {:<24}  [{}] {}",
+            percent( entry.total_samples, sample_count ),
+            percent( entry.self_samples, sample_count ),
+            entry.total_samples,
+            entry.self_samples,
+            entry.module,
+            if entry.is_kernel { "k" } else { "." },
+            entry.symbol
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Quotes a CSV field if necessary, as described in RFC 4180.
+pub(crate) fn csv_field( field: &str ) -> Cow< str > {
+    if field.contains( |ch| ch == ',' || ch == '"' || ch == '\n' || ch == '\r' ) {
+        format!( "\"{}\"", field.replace( "\"", "\"\"" ) ).into()
+    } else {
+        field.into()
+    }
+}
+
+fn write_csv< T: Write >( entries: &[Entry], sample_count: u64, output: &mut T ) -> Result< (), io::Error > {
+    writeln!( output, "symbol,module,self_percent,total_percent,self_samples,total_samples" )?;
+    for entry in entries {
+        writeln!(
+            output,
+            "{},{},{:.4},{:.4},{},{}",
+            csv_field( &entry.symbol ),
+            csv_field( &entry.module ),
+            percent( entry.self_samples, sample_count ),
+            percent( entry.total_samples, sample_count ),
+            entry.self_samples,
+            entry.total_samples
+        )?;
+    }
+
+    Ok(())
+}
+
+struct Butterfly< T > {
+    total_samples: u64,
+    self_samples: u64,
+    callers: HashMap< T, u64 >,
+    callees: HashMap< T, u64 >
+}
+
+fn butterflies< T, F >( stacks: &[(Vec< T >, u64)], is_focused: F ) -> HashMap< T, Butterfly< T > >
+    where T: Clone + Eq + std::hash::Hash, F: Fn( &T ) -> bool
+{
+    let mut output: HashMap< T, Butterfly< T > > = HashMap::new();
+    for &(ref frames, count) in stacks {
+        let mut seen_functions = HashSet::new();
+        let mut seen_edges = HashSet::new();
+        for (index, frame) in frames.iter().enumerate() {
+            if !is_focused( frame ) {
+                continue;
+            }
+
+            let butterfly = output.entry( frame.clone() ).or_insert_with( || Butterfly {
+                total_samples: 0,
+                self_samples: 0,
+                callers: HashMap::new(),
+                callees: HashMap::new()
+            });
+
+            if seen_functions.insert( frame ) {
+                butterfly.total_samples += count;
+            }
+
+            if index == 0 {
+                butterfly.self_samples += count;
+            } else if seen_edges.insert( (frame, false, &frames[ index - 1 ]) ) {
+                *butterfly.callees.entry( frames[ index - 1 ].clone() ).or_insert( 0 ) += count;
+            }
+
+            if let Some( caller ) = frames.get( index + 1 ) {
+                if seen_edges.insert( (frame, true, caller) ) {
+                    *butterfly.callers.entry( caller.clone() ).or_insert( 0 ) += count;
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn write_butterflies< T: Write >(
+    state: &State,
+    interner: &StringInterner,
+    stacks: HashMap< Vec< FrameKind >, u64 >,
+    sample_count: u64,
+    focus: &Regex,
+    output: &mut T
+) -> Result< (), Box< dyn Error > > {
+    let stacks: Vec< _ > = stacks.into_iter().collect();
+    let butterflies = butterflies( &stacks, |frame| {
+        let (_, symbol, _) = describe_frame( state, interner, frame );
+        focus.is_match( &symbol )
+    });
+
+    if butterflies.is_empty() {
+        return Err( format!( "no function matching '{}' was found", focus ).into() );
+    }
+
+    let mut butterflies: Vec< _ > = butterflies.into_iter().collect();
+    butterflies.sort_by( |a, b| b.1.total_samples.cmp( &a.1.total_samples ) );
+
+    let write_entries = |output: &mut T, entries: HashMap< FrameKind, u64 >, total: u64| -> Result< (), io::Error > {
+        let mut entries: Vec< _ > = entries.into_iter().collect();
+        entries.sort_by( |a, b| b.1.cmp( &a.1 ) );
+        for (frame, count) in entries {
+            let (module, symbol, is_kernel) = describe_frame( state, interner, &frame );
+            writeln!(
+                output,
+                "  {:>7.2}%  {:>10}  {:<24}  [{}] {}",
+                percent( count, total ),
+                count,
+                module,
+                if is_kernel { "k" } else { "." },
+                symbol
+            )?;
+        }
+
+        Ok(())
+    };
+
+    for (frame, butterfly) in butterflies {
+        let (module, symbol, _) = describe_frame( state, interner, &frame );
+        writeln!( output, "# Function: {} [{}]", symbol, module )?;
+        writeln!( output, "#   Total: {} samples ({:.2}%)", butterfly.total_samples, percent( butterfly.total_samples, sample_count ) )?;
+        writeln!( output, "#   Self:  {} samples ({:.2}%)", butterfly.self_samples, percent( butterfly.self_samples, sample_count ) )?;
+        writeln!( output, "#" )?;
+        writeln!( output, "# Callers:" )?;
+        write_entries( output, butterfly.callers, butterfly.total_samples )?;
+        writeln!( output, "#" )?;
+        writeln!( output, "# Callees:" )?;
+        write_entries( output, butterfly.callees, butterfly.total_samples )?;
+        writeln!( output )?;
+    }
+
+    Ok(())
+}
+
+pub fn main( args: args::ReportArgs ) -> Result< (), Box< dyn Error > > {
+    let focus = match args.focus {
+        Some( ref focus ) => Some( Regex::new( focus ).map_err( |err| format!( "invalid regexp passed in `--focus`: {}", err ) )? ),
+        None => None
+    };
+
+    if focus.is_some() && args.format != ReportFormat::Table {
+        return Err( "`--focus` is only supported for the `table` format".into() );
+    }
+
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: args.arg_granularity.granularity
+    };
+
+    let mut interner = StringInterner::new();
+    let mut stacks: HashMap< Vec< FrameKind >, f64 > = HashMap::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                if let Some( frames ) = sample.decode( &event.state, &opts, &mut interner ) {
+                    *stacks.entry( frames ).or_insert( 0.0 ) += sample.weight;
+                }
+            },
+            _ => {}
+        }
+    })?;
+
+    let stacks = round_weights( stacks );
+    let sample_count = stacks.values().sum();
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    if let Some( focus ) = focus {
+        write_butterflies( &state, &interner, stacks, sample_count, &focus, &mut stdout )?;
+    } else {
+        let entries = collect_entries( &state, &interner, &stacks, args.sort );
+        match args.format {
+            ReportFormat::Table => write_table( &entries, sample_count, &mut stdout )?,
+            ReportFormat::Csv => write_csv( &entries, sample_count, &mut stdout )?
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sort_entries() {
+    let entry = |symbol: &str, self_samples, total_samples| Entry {
+        module: "module".to_owned(),
+        symbol: symbol.to_owned(),
+        is_kernel: false,
+        self_samples,
+        total_samples
+    };
+
+    let mut entries = vec![ entry( "b", 1, 10 ), entry( "a", 5, 5 ), entry( "c", 0, 20 ) ];
+    sort_entries( &mut entries, ReportSort::SelfSamples );
+    assert_eq!( entries.iter().map( |entry| entry.symbol.as_str() ).collect::< Vec< _ > >(), vec![ "a", "b", "c" ] );
+
+    sort_entries( &mut entries, ReportSort::TotalSamples );
+    assert_eq!( entries.iter().map( |entry| entry.symbol.as_str() ).collect::< Vec< _ > >(), vec![ "c", "b", "a" ] );
+
+    sort_entries( &mut entries, ReportSort::Symbol );
+    assert_eq!( entries.iter().map( |entry| entry.symbol.as_str() ).collect::< Vec< _ > >(), vec![ "a", "b", "c" ] );
+}
+
+#[test]
+fn test_butterflies() {
+    // Frames are ordered from the leaf to the root.
+    let stacks = vec![
+        (vec![ 'C', 'B', 'A' ], 1),
+        (vec![ 'B', 'A' ], 2),
+        (vec![ 'D', 'B', 'E' ], 3),
+        (vec![ 'B', 'B', 'A' ], 4)
+    ];
+
+    let butterflies = butterflies( &stacks, |&frame| frame == 'B' );
+    assert_eq!( butterflies.len(), 1 );
+
+    let butterfly = &butterflies[ &'B' ];
+    assert_eq!( butterfly.total_samples, 10 );
+    assert_eq!( butterfly.self_samples, 6 );
+    assert_eq!( butterfly.callers[ &'A' ], 7 );
+    assert_eq!( butterfly.callers[ &'E' ], 3 );
+    assert_eq!( butterfly.callers[ &'B' ], 4 );
+    assert_eq!( butterfly.callees[ &'C' ], 1 );
+    assert_eq!( butterfly.callees[ &'D' ], 3 );
+    assert_eq!( butterfly.callees[ &'B' ], 4 );
+}
+
+#[test]
+fn test_csv_field() {
+    assert_eq!( csv_field( "main" ), "main" );
+    assert_eq!( csv_field( "std::vec::Vec<T>::push" ), "std::vec::Vec<T>::push" );
+    assert_eq!( csv_field( "HashMap<K, V>::insert" ), "\"HashMap<K, V>::insert\"" );
+    assert_eq!( csv_field( "operator\"\"_s" ), "\"operator\"\"\"\"_s\"" );
+}
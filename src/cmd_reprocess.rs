@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::error::Error;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use nwind::{BinaryData, BinaryId};
+use speedy::Writable;
+
+use crate::args;
+use crate::archive::{ARCHIVE_MAGIC, ARCHIVE_VERSION, ArchiveReader, FramedPacket, Packet};
+use crate::merge::to_binary_id;
+
+/// Loads a binary from the filesystem, as long as it's the same one which was profiled.
+fn load_binary( path: &str, build_id: Option< &[u8] > ) -> Option< BinaryData > {
+    let data = match BinaryData::load_from_fs( path ) {
+        Ok( data ) => data,
+        Err( error ) => {
+            warn!( "Cannot load '{}': {}; its frames won't be unwound", path, error );
+            return None;
+        }
+    };
+
+    if let (Some( expected ), Some( actual )) = (build_id, data.build_id()) {
+        if expected != actual {
+            warn!( "Build ID of '{}' doesn't match the one from the profiling data; its frames won't be unwound", path );
+            return None;
+        }
+    }
+
+    Some( data )
+}
+
+/// Turns the samples which were unwound while recording back into raw samples, using the registers
+/// and stacks kept with `--keep-raw`, so that they're unwound again by whatever reads the output.
+/// Since raw samples need the binaries themselves those are embedded into the output too.
+pub fn main( args: args::ReprocessArgs ) -> Result< (), Box< dyn Error > > {
+    let open = || -> Result< _, Box< dyn Error > > {
+        let fp = File::open( &args.input ).map_err( |err| format!( "cannot open {:?}: {}", args.input, err ) )?;
+        Ok( ArchiveReader::new( fp ).validate_header()?.skip_unknown() )
+    };
+
+    let mut raw_sample_count = 0;
+    let mut build_ids: HashMap< BinaryId, Vec< u8 > > = HashMap::new();
+    let mut embedded_binaries = HashSet::new();
+    for packet in open()? {
+        match packet? {
+            Packet::RawSampleData { .. } => raw_sample_count += 1,
+            Packet::BuildId { inode, build_id, path } => {
+                build_ids.insert( to_binary_id( inode, &path ), build_id );
+            },
+            Packet::BinaryBlob { inode, path, .. } => {
+                embedded_binaries.insert( to_binary_id( inode, &path ) );
+            },
+            _ => {}
+        }
+    }
+
+    if raw_sample_count == 0 {
+        return Err( format!( "input {:?} doesn't contain any raw samples; record it with '--keep-raw'", args.input ).into() );
+    }
+
+    let fp = File::create( &args.output ).map_err( |err| format!( "cannot create {:?}: {}", args.output, err ) )?;
+    let mut fp = io::BufWriter::new( fp );
+
+    FramedPacket::Known( Packet::Header {
+        magic: ARCHIVE_MAGIC,
+        version: ARCHIVE_VERSION
+    }).write_to_stream( &mut fp )?;
+
+    let mut converted_count = 0;
+    let mut unconverted_count = 0;
+    let mut pending_sample = None;
+    for packet in open()? {
+        let packet = packet?;
+        if let Packet::RawSampleData { stack, regs } = packet {
+            match pending_sample.take() {
+                Some( Packet::Sample { timestamp, pid, tid, cpu, kernel_backtrace, .. } ) => {
+                    FramedPacket::Known( Packet::RawSample { timestamp, pid, tid, cpu, kernel_backtrace, stack, regs } ).write_to_stream( &mut fp )?;
+                    converted_count += 1;
+                },
+                _ => warn!( "Found raw sample data without a matching sample; ignoring" )
+            }
+
+            continue;
+        }
+
+        if let Some( sample ) = pending_sample.take() {
+            FramedPacket::Known( sample ).write_to_stream( &mut fp )?;
+            unconverted_count += 1;
+        }
+
+        match packet {
+            Packet::Sample { .. } => {
+                pending_sample = Some( packet );
+            },
+            Packet::BinaryInfo { inode, ref path, .. } => {
+                let binary_id = to_binary_id( inode, path );
+                let path = String::from_utf8_lossy( path ).into_owned();
+                // Pseudo-binaries like the `[vdso]` can't be loaded from the filesystem.
+                let blob = if embedded_binaries.contains( &binary_id ) || path.starts_with( '[' ) {
+                    None
+                } else {
+                    load_binary( &path, build_ids.get( &binary_id ).map( |build_id| build_id.as_slice() ) )
+                };
+
+                FramedPacket::Known( packet ).write_to_stream( &mut fp )?;
+                if let Some( blob ) = blob {
+                    debug!( "Embedding '{}'...", path );
+                    FramedPacket::Known( Packet::BinaryBlob {
+                        inode,
+                        path: path.as_bytes().into(),
+                        data: blob.as_bytes().into()
+                    }).write_to_stream( &mut fp )?;
+                }
+            },
+            packet => {
+                FramedPacket::Known( packet ).write_to_stream( &mut fp )?;
+            }
+        }
+    }
+
+    if let Some( sample ) = pending_sample.take() {
+        FramedPacket::Known( sample ).write_to_stream( &mut fp )?;
+        unconverted_count += 1;
+    }
+
+    fp.flush()?;
+    info!( "Written {} raw samples into {:?}", converted_count, args.output );
+    if unconverted_count > 0 {
+        warn!( "{} samples had no raw data and were left as they were", unconverted_count );
+    }
+
+    Ok(())
+}
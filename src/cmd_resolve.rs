@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, DebugInfoIndex, IAddressSpace};
+use proc_maps::Region;
+
+use crate::args;
+use crate::data_reader::{ReadDataArgs, new_address_space, read_data};
+use crate::utils::parse_hex_address;
+
+/// Writes out the symbol, the inline chain and the source location of a single address, innermost frame first.
+fn write_address( fp: &mut dyn Write, address_space: &dyn IAddressSpace, address: u64 ) -> io::Result< () > {
+    writeln!( fp, "0x{:016X}", address )?;
+
+    let mut found = false;
+    let mut result = Ok(());
+    address_space.decode_symbol_while( address, &mut |frame| {
+        found = true;
+        let name = frame.demangled_name.as_ref().or( frame.name.as_ref() ).map( |name| name.as_ref() ).unwrap_or( "??" );
+        let library = frame.library.as_ref().map( |library| library.as_ref() ).unwrap_or( "" );
+        let library = &library[ library.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+        let library = if library.is_empty() { String::new() } else { format!( " ({})", library ) };
+        let inline = if frame.is_inline { " [inlined]" } else { "" };
+        let location = match (frame.file.as_ref(), frame.line) {
+            (Some( file ), Some( line )) => format!( " at {}:{}", file, line ),
+            _ => String::new()
+        };
+
+        result = writeln!( fp, "    {}{}{}{}", name, inline, library, location );
+        result.is_ok()
+    });
+
+    result?;
+    if !found {
+        writeln!( fp, "    ??" )?;
+    }
+
+    Ok(())
+}
+
+/// Builds an address space with the binary mapped at the addresses from its ELF headers.
+fn address_space_for_binary( args: &args::ResolveArgs, path: &OsStr ) -> Result< Box< dyn IAddressSpace >, Box< dyn Error > > {
+    let binary = BinaryData::load_from_fs( path ).map_err( |err| format!( "cannot load {:?}: {}", path, err ) )?;
+    let binary = Arc::new( binary );
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    debug_info_index.enable_auto_load();
+    debug_info_index.add( path );
+    for path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
+
+    let (_, debug_binary) = debug_info_index.get_pair( binary.name(), binary.debuglink(), binary.build_id() );
+    if let Some( ref debug_binary ) = debug_binary {
+        info!( "Using debug info from '{}'", debug_binary.name() );
+    }
+
+    address_space_for( binary, debug_binary )
+}
+
+/// Builds an address space with the binary mapped at the addresses from its ELF headers,
+/// taking the symbols and the debug info also from the `debug_binary`, if given.
+pub(crate) fn address_space_for( binary: Arc< BinaryData >, debug_binary: Option< Arc< BinaryData > > ) -> Result< Box< dyn IAddressSpace >, Box< dyn Error > > {
+    const PAGE_MASK: u64 = !(4096 - 1);
+    let regions: Vec< _ > = binary.load_headers().iter().filter( |header| header.is_executable ).map( |header| {
+        Region {
+            start: header.address & PAGE_MASK,
+            end: header.address + header.memory_size,
+            is_read: true,
+            is_write: false,
+            is_executable: true,
+            is_shared: false,
+            file_offset: header.file_offset & PAGE_MASK,
+            major: 0,
+            minor: 0,
+            inode: 1,
+            name: binary.name().to_owned()
+        }
+    }).collect();
+
+    let mut address_space = new_address_space( binary.architecture() )
+        .ok_or_else( || format!( "unsupported architecture of '{}': {}", binary.name(), binary.architecture() ) )?;
+
+    address_space.reload( regions, &mut |_, handle| {
+        handle.set_binary( binary.clone() );
+        if let Some( ref debug_binary ) = debug_binary {
+            handle.set_debug_binary( debug_binary.clone() );
+        }
+        handle.should_load_frame_descriptions( false );
+    });
+
+    Ok( address_space )
+}
+
+pub fn main( args: args::ResolveArgs ) -> Result< (), Box< dyn Error > > {
+    let addresses: Vec< u64 > = args.addresses.iter().map( |address| {
+        parse_hex_address( address ).ok_or_else( || format!( "invalid address: '{}'", address ) )
+    }).collect::< Result< _, _ > >()?;
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    if let Some( ref input ) = args.input {
+        let pid = args.pid.ok_or( "the PID of the process must be given with '--pid' when resolving addresses from a recording" )?;
+        let debug_symbols = args.debug_symbols.iter().map( |path| path.as_os_str() ).collect();
+        let mut state = read_data( ReadDataArgs::new( input, debug_symbols ), |_| {} )?;
+        let process = state.get_reloaded_process( pid ).ok_or_else( || format!( "process with PID {} not found in {:?}", pid, input ) )?;
+        for &address in &addresses {
+            write_address( &mut stdout, process.address_space(), address )?;
+        }
+    } else if let Some( ref binary ) = args.binary {
+        let address_space = address_space_for_binary( &args, binary )?;
+        for &address in &addresses {
+            write_address( &mut stdout, &*address_space, address )?;
+        }
+    } else {
+        return Err( "either a binary or a recording has to be given".into() );
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, write_cfi_for_address};
+
+use crate::args;
+use crate::utils::parse_hex_address;
+
+pub fn main( args: args::ShowCfiArgs ) -> Result< (), Box< dyn Error > > {
+    let address = parse_hex_address( &args.address ).ok_or_else( || format!( "invalid address: '{}'", args.address ) )?;
+
+    let binary = BinaryData::load_from_fs( &args.binary ).map_err( |err| format!( "cannot load {:?}: {}", args.binary, err ) )?;
+    let binary = Arc::new( binary );
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if !write_cfi_for_address( &binary, address, &mut stdout )? {
+        return Err( format!( "no unwinding information found for 0x{:016X} in {:?}", address, args.binary ).into() );
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
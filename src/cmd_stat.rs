@@ -0,0 +1,303 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use perf_event_open::{Counter, CounterValue};
+use perf_event_open::sys::*;
+
+use crate::args;
+use crate::profiler::find_target_process;
+use crate::utils::SigintHandler;
+
+const EVENTS: &[(&str, u32, u64)] = &[
+    ("cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+    ("instructions", PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+    ("cache-references", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_REFERENCES),
+    ("cache-misses", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES),
+    ("branches", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_INSTRUCTIONS),
+    ("branch-misses", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_MISSES),
+    ("bus-cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BUS_CYCLES),
+    ("stalled-cycles-frontend", PERF_TYPE_HARDWARE, PERF_COUNT_HW_STALLED_CYCLES_FRONTEND),
+    ("stalled-cycles-backend", PERF_TYPE_HARDWARE, PERF_COUNT_HW_STALLED_CYCLES_BACKEND),
+    ("ref-cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_REF_CPU_CYCLES),
+    ("cpu-clock", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_CLOCK),
+    ("task-clock", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK),
+    ("page-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS),
+    ("context-switches", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES),
+    ("cpu-migrations", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_MIGRATIONS),
+    ("minor-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS_MIN),
+    ("major-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS_MAJ)
+];
+
+const DEFAULT_EVENTS: &[&str] = &[
+    "task-clock",
+    "context-switches",
+    "cpu-migrations",
+    "page-faults",
+    "cycles",
+    "instructions",
+    "branches",
+    "branch-misses"
+];
+
+struct Event {
+    name: &'static str,
+    /// One counter for every thread which was running when the counting started;
+    /// the threads spawned later on are accounted to their parents. Empty if the event is not supported.
+    counters: Vec< Counter >,
+    last_value: CounterValue
+}
+
+impl Event {
+    fn read( &self ) -> Option< CounterValue > {
+        if self.counters.is_empty() {
+            return None;
+        }
+
+        Some( self.counters.iter().filter_map( |counter| counter.read().ok() ).fold( CounterValue::default(), |sum, value| sum + value ) )
+    }
+}
+
+fn is_clock( name: &str ) -> bool {
+    name == "task-clock" || name == "cpu-clock"
+}
+
+fn format_count( count: u64 ) -> String {
+    let digits = count.to_string();
+    let mut output = String::with_capacity( digits.len() + digits.len() / 3 );
+    for (index, digit) in digits.chars().enumerate() {
+        if index != 0 && (digits.len() - index) % 3 == 0 {
+            output.push( ',' );
+        }
+        output.push( digit );
+    }
+
+    output
+}
+
+fn format_value( name: &str, count: Option< u64 > ) -> String {
+    match count {
+        None => "<not supported>".to_owned(),
+        Some( count ) if is_clock( name ) => format!( "{:.2} msec", count as f64 / 1_000_000.0 ),
+        Some( count ) => format_count( count )
+    }
+}
+
+/// Calculates the metric which is derived from a given event and its peers, e.g. the instructions per cycle.
+fn derived_metric( name: &str, counts: &[(&str, Option< u64 >)], elapsed: Duration ) -> Option< String > {
+    let get = |name: &str| counts.iter().find( |&&(event, _)| event == name ).and_then( |&(_, count)| count );
+    let ratio = |numerator: &str, denominator: &str| {
+        let denominator = get( denominator )?;
+        if denominator == 0 {
+            return None;
+        }
+
+        Some( get( numerator )? as f64 / denominator as f64 )
+    };
+
+    match name {
+        "task-clock" => {
+            let elapsed = elapsed.as_nanos() as f64;
+            if elapsed == 0.0 {
+                return None;
+            }
+
+            Some( format!( "{:.3} CPUs utilized", get( "task-clock" )? as f64 / elapsed ) )
+        },
+        "cycles" => ratio( "cycles", "task-clock" ).map( |ghz| format!( "{:.3} GHz", ghz ) ),
+        "instructions" => ratio( "instructions", "cycles" ).map( |ipc| format!( "{:.2} insn per cycle", ipc ) ),
+        "branch-misses" => ratio( "branch-misses", "branches" ).map( |ratio| format!( "{:.2}% of all branches", ratio * 100.0 ) ),
+        "cache-misses" => ratio( "cache-misses", "cache-references" ).map( |ratio| format!( "{:.2}% of all cache refs", ratio * 100.0 ) ),
+        _ => None
+    }
+}
+
+/// The counts are scaled to account for the multiplexing; if an event wasn't counted
+/// the whole time then the percentage of the time it was is shown next to it.
+fn write_counts( fp: &mut dyn Write, prefix: &str, values: &[(&str, Option< CounterValue >)], elapsed: Duration ) -> io::Result< () > {
+    let counts: Vec< _ > = values.iter().map( |&(name, value)| (name, value.map( |value| value.scaled() )) ).collect();
+    for (&(name, count), &(_, value)) in counts.iter().zip( values ) {
+        let mut line = format!( "{}{:>20}      {:<24}", prefix, format_value( name, count ), name );
+        if let Some( metric ) = derived_metric( name, &counts, elapsed ) {
+            line.push_str( &format!( " #  {:<24}", metric ) );
+        }
+
+        if let Some( value ) = value {
+            if value.time_running < value.time_enabled {
+                line.push_str( &format!( " ({:.2}%)", value.running_ratio() * 100.0 ) );
+            }
+        }
+
+        writeln!( fp, "{}", line.trim_end() )?;
+    }
+
+    Ok(())
+}
+
+fn open_event( name: &'static str, kind: u32, config: u64, tids: &[u32], exclude_kernel: &mut bool ) -> Result< Event, Box< dyn Error > > {
+    let mut counters = Vec::new();
+    for &tid in tids {
+        let counter = match Counter::open( tid, kind, config, *exclude_kernel ) {
+            Err( ref error ) if !*exclude_kernel && (error.raw_os_error() == Some( libc::EACCES ) || error.raw_os_error() == Some( libc::EPERM )) => {
+                warn!( "Not allowed to count the kernel events; only the user space will be counted" );
+                *exclude_kernel = true;
+                Counter::open( tid, kind, config, true )
+            },
+            result => result
+        };
+
+        match counter {
+            Ok( counter ) => counters.push( counter ),
+            Err( ref error ) if error.raw_os_error() == Some( libc::ESRCH ) => continue,
+            Err( ref error ) if error.raw_os_error() == Some( libc::ENOENT ) || error.raw_os_error() == Some( libc::EOPNOTSUPP ) => {
+                warn!( "The '{}' event is not supported", name );
+                counters.clear();
+                break;
+            },
+            Err( error ) => return Err( format!( "failed to open the '{}' counter for TID {}: {}", name, tid, error ).into() )
+        }
+    }
+
+    Ok( Event { name, counters, last_value: CounterValue::default() } )
+}
+
+pub fn main( args: args::StatArgs ) -> Result< (), Box< dyn Error > > {
+    let mut names = Vec::new();
+    for name in args.events.iter().flat_map( |events| events.split( ',' ) ).map( |name| name.trim() ).filter( |name| !name.is_empty() ) {
+        match EVENTS.iter().find( |&&(event, _, _)| event == name ) {
+            Some( event ) => names.push( *event ),
+            None => {
+                let known: Vec< _ > = EVENTS.iter().map( |&(event, _, _)| event ).collect();
+                return Err( format!( "unknown event '{}'; the supported events are: {}", name, known.join( ", " ) ).into() );
+            }
+        }
+    }
+
+    if names.is_empty() {
+        names = DEFAULT_EVENTS.iter().map( |&name| *EVENTS.iter().find( |&&(event, _, _)| event == name ).unwrap() ).collect();
+    }
+
+    let sigint = SigintHandler::new();
+    let pid = find_target_process( &sigint, &args.process_filter )?;
+
+    let mut tids = Vec::new();
+    for entry in fs::read_dir( format!( "/proc/{}/task", pid ) ).map_err( |err| format!( "cannot read the threads of PID {}: {}", pid, err ) )? {
+        if let Ok( tid ) = entry?.file_name().to_string_lossy().parse() {
+            tids.push( tid );
+        }
+    }
+
+    let mut exclude_kernel = args.user_only;
+    let mut events = Vec::with_capacity( names.len() );
+    for &(name, kind, config) in &names {
+        let was_excluded = exclude_kernel;
+        events.push( open_event( name, kind, config, &tids, &mut exclude_kernel )? );
+
+        // The counters which were already opened still count the kernel too, so they have to be reopened
+        // for all of the events to be comparable.
+        if exclude_kernel != was_excluded {
+            events.clear();
+            for &(name, kind, config) in &names {
+                events.push( open_event( name, kind, config, &tids, &mut exclude_kernel )? );
+            }
+
+            break;
+        }
+    }
+
+    info!( "Counting the events of PID {}...", pid );
+
+    let stdout = io::stdout();
+    let start = Instant::now();
+    let interval = args.interval.map( Duration::from_millis );
+    let mut last_print = start;
+    loop {
+        thread::sleep( Duration::from_millis( 50 ) );
+
+        let is_finished =
+            sigint.was_triggered() ||
+            !Path::new( &format!( "/proc/{}", pid ) ).exists() ||
+            args.time_limit.map( |time_limit| start.elapsed().as_secs() >= time_limit ).unwrap_or( false );
+
+        if let Some( interval ) = interval {
+            let now = Instant::now();
+            if now - last_print >= interval || is_finished {
+                let counts: Vec< _ > = events.iter_mut().map( |event| {
+                    let value = event.read().map( |value| {
+                        let delta = value - event.last_value;
+                        event.last_value = value;
+                        delta
+                    });
+                    (event.name, value)
+                }).collect();
+
+                let prefix = format!( "{:>14.6}", (now - start).as_secs_f64() );
+                write_counts( &mut stdout.lock(), &prefix, &counts, now - last_print )?;
+                last_print = now;
+            }
+        }
+
+        if is_finished {
+            break;
+        }
+    }
+
+    let elapsed = start.elapsed();
+    let counts: Vec< _ > = events.iter().map( |event| (event.name, event.read()) ).collect();
+
+    let mut stdout = stdout.lock();
+    writeln!( stdout )?;
+    writeln!( stdout, " Performance counter stats for PID {}:", pid )?;
+    writeln!( stdout )?;
+    write_counts( &mut stdout, "", &counts, elapsed )?;
+    writeln!( stdout )?;
+    writeln!( stdout, "{:>20.9} seconds time elapsed", elapsed.as_secs_f64() )?;
+
+    Ok(())
+}
+
+#[test]
+fn test_format_count() {
+    assert_eq!( format_count( 0 ), "0" );
+    assert_eq!( format_count( 999 ), "999" );
+    assert_eq!( format_count( 1000 ), "1,000" );
+    assert_eq!( format_count( 1234567 ), "1,234,567" );
+}
+
+#[test]
+fn test_derived_metric() {
+    let counts = [
+        ("task-clock", Some( 2_000_000_000 )),
+        ("cycles", Some( 6_000_000_000 )),
+        ("instructions", Some( 9_000_000_000 )),
+        ("branches", Some( 1000 )),
+        ("branch-misses", Some( 25 )),
+        ("cache-misses", Some( 10 ))
+    ];
+
+    let elapsed = Duration::from_secs( 1 );
+    assert_eq!( derived_metric( "task-clock", &counts, elapsed ).unwrap(), "2.000 CPUs utilized" );
+    assert_eq!( derived_metric( "cycles", &counts, elapsed ).unwrap(), "3.000 GHz" );
+    assert_eq!( derived_metric( "instructions", &counts, elapsed ).unwrap(), "1.50 insn per cycle" );
+    assert_eq!( derived_metric( "branch-misses", &counts, elapsed ).unwrap(), "2.50% of all branches" );
+    assert_eq!( derived_metric( "cache-misses", &counts, elapsed ), None );
+    assert_eq!( derived_metric( "branches", &counts, elapsed ), None );
+}
+
+#[test]
+fn test_write_counts_scales_multiplexed_events() {
+    let values = [
+        ("cycles", Some( CounterValue { value: 1000, time_enabled: 400, time_running: 100 } )),
+        ("instructions", Some( CounterValue { value: 2000, time_enabled: 400, time_running: 400 } ))
+    ];
+
+    let mut output = Vec::new();
+    write_counts( &mut output, "", &values, Duration::from_secs( 1 ) ).unwrap();
+    let output = String::from_utf8( output ).unwrap();
+    let lines: Vec< _ > = output.lines().collect();
+    assert_eq!( lines[ 0 ], "               4,000      cycles                   (25.00%)" );
+    assert_eq!( lines[ 1 ], "               2,000      instructions             #  0.50 insn per cycle" );
+}
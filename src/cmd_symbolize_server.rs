@@ -0,0 +1,224 @@
+//! A server which symbolizes the addresses of binaries identified by their build IDs, so that the machines
+//! which are being profiled don't need to have any debug symbols.
+//!
+//! It accepts `POST /symbolize` requests with a JSON body like:
+//!
+//! ```json
+//! { "build_id": "5e0f4b3a...", "path": "/usr/bin/app", "load_address": 0, "addresses": [4198400, 4198656] }
+//! ```
+//!
+//! where the addresses are the ones from the binary's ELF headers, and `path` (the binary's original path)
+//! and `load_address` are optional; it responds with the frames of every address, innermost first,
+//! or `null` if the address couldn't be symbolized:
+//!
+//! ```json
+//! { "frames": [[{ "name": "inlined", "file": "/src/a.c", "line": 10 }, { "name": "main", "file": "/src/a.c", "line": 20 }], null] }
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nwind::{DebugInfoIndex, IAddressSpace};
+
+use crate::args;
+use crate::cmd_resolve::address_space_for;
+use crate::http;
+use crate::symbol_resolver::{SymbolResolver, SymbolResolvers, SymbolQuery, ResolvedFrame, resolve_elf};
+use crate::utils::{parse_hex_bytes, to_hex};
+
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct SymbolizeRequest {
+    pub build_id: String,
+    #[serde(default)]
+    pub path: Option< String >,
+    #[serde(default)]
+    pub load_address: Option< u64 >,
+    pub addresses: Vec< u64 >
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct SymbolizeResponse {
+    pub frames: Vec< Option< Vec< ResolvedFrame > > >
+}
+
+struct Binary {
+    address_space: Box< dyn IAddressSpace >,
+    load_address: u64
+}
+
+struct Symbolizer {
+    debug_info_index: DebugInfoIndex,
+    symbol_resolvers: SymbolResolvers,
+    binaries: HashMap< Vec< u8 >, Option< Binary > >
+}
+
+fn resolve_with( resolvers: &[Box< dyn SymbolResolver >], query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > > {
+    resolvers.iter().find_map( |resolver| resolver.resolve( query ) ).filter( |frames| !frames.is_empty() )
+}
+
+impl Symbolizer {
+    /// Finds the binary with a given build ID; the result is cached, whether it was found or not.
+    fn binary( &mut self, build_id: &[u8], path: &str ) -> Option< &Binary > {
+        let debug_info_index = &mut self.debug_info_index;
+        self.binaries.entry( build_id.to_owned() ).or_insert_with( || {
+            let (binary, debug_binary) = match debug_info_index.get_pair( path, None, Some( build_id ) ) {
+                (Some( binary ), debug_binary) => (binary, debug_binary),
+                (None, Some( debug_binary )) => (debug_binary, None),
+                (None, None) => {
+                    info!( "No binary found for build ID {} ('{}')", to_hex( build_id ), path );
+                    return None;
+                }
+            };
+
+            info!( "Loaded '{}' for build ID {}", debug_binary.as_ref().unwrap_or( &binary ).name(), to_hex( build_id ) );
+            let load_address = binary.load_headers().iter()
+                .filter( |header| header.file_offset == 0 )
+                .map( |header| header.address )
+                .min()
+                .unwrap_or( 0 );
+
+            match address_space_for( Arc::clone( &binary ), debug_binary ) {
+                Ok( address_space ) => Some( Binary { address_space, load_address } ),
+                Err( error ) => {
+                    warn!( "Cannot symbolize '{}': {}", binary.name(), error );
+                    None
+                }
+            }
+        }).as_ref()
+    }
+
+    fn symbolize( &mut self, request: &SymbolizeRequest ) -> Result< SymbolizeResponse, String > {
+        let build_id = parse_hex_bytes( &request.build_id ).filter( |build_id| !build_id.is_empty() )
+            .ok_or_else( || format!( "invalid build ID: '{}'", request.build_id ) )?;
+
+        let path = request.path.as_deref().unwrap_or( "" );
+        let binary_load_address = self.binary( &build_id, path ).map( |binary| binary.load_address );
+        let binary = self.binaries.get( &build_id ).and_then( |binary| binary.as_ref() );
+        let resolvers = &self.symbol_resolvers;
+
+        let frames = request.addresses.iter().map( |&address| {
+            let query = SymbolQuery {
+                path,
+                build_id: Some( &build_id ),
+                address,
+                load_address: request.load_address.or( binary_load_address ).unwrap_or( 0 )
+            };
+
+            resolve_with( &resolvers.before, &query )
+                .or_else( || binary.and_then( |binary| resolve_elf( &*binary.address_space, address ) ) )
+                .or_else( || resolve_with( &resolvers.after, &query ) )
+        }).collect();
+
+        Ok( SymbolizeResponse { frames } )
+    }
+}
+
+/// Returns the status, the content type and the body of the response.
+fn handle_request( symbolizer: &mut Symbolizer, request: &http::Request ) -> (&'static str, &'static str, Vec< u8 >) {
+    if request.path != "/symbolize" {
+        return ("404 Not Found", "text/plain", b"not found\n".to_vec());
+    }
+
+    if request.method != "POST" {
+        return ("405 Method Not Allowed", "text/plain", b"only POST is supported\n".to_vec());
+    }
+
+    let response = serde_json::from_slice( &request.body )
+        .map_err( |error| format!( "invalid request: {}", error ) )
+        .and_then( |request: SymbolizeRequest| symbolizer.symbolize( &request ) );
+
+    match response {
+        Ok( response ) => ("200 OK", "application/json", serde_json::to_vec( &response ).unwrap()),
+        Err( error ) => ("400 Bad Request", "text/plain", format!( "{}\n", error ).into_bytes())
+    }
+}
+
+fn serve( symbolizer: &mut Symbolizer, stream: &mut TcpStream ) -> Result< (), Box< dyn Error > > {
+    let timeout = Some( Duration::from_secs( 30 ) );
+    stream.set_read_timeout( timeout )?;
+    stream.set_write_timeout( timeout )?;
+
+    let request = http::read_request( stream, MAX_REQUEST_SIZE )?;
+    let (status, content_type, body) = handle_request( symbolizer, &request );
+    debug!( "{} {}: {}", request.method, request.path, status );
+    http::write_response( stream, status, content_type, &body )?;
+    Ok(())
+}
+
+pub fn main( args: args::SymbolizeServerArgs ) -> Result< (), Box< dyn Error > > {
+    let mut debug_info_index = DebugInfoIndex::new();
+    for path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
+
+    if let Some( ref sysroot ) = args.sysroot {
+        debug_info_index.set_sysroot( sysroot );
+    }
+
+    let mut symbolizer = Symbolizer {
+        debug_info_index,
+        symbol_resolvers: SymbolResolvers::new( &args.symbol_resolver )?,
+        binaries: HashMap::new()
+    };
+
+    let listener = TcpListener::bind( &args.listen ).map_err( |error| format!( "cannot listen on '{}': {}", args.listen, error ) )?;
+    info!( "Listening on {}", listener.local_addr()? );
+
+    // The requests are served one by one since the loaded binaries are shared between them.
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok( stream ) => stream,
+            Err( error ) => {
+                warn!( "Failed to accept a connection: {}", error );
+                continue;
+            }
+        };
+
+        if let Err( error ) = serve( &mut symbolizer, &mut stream ) {
+            warn!( "Failed to serve a request: {}", error );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_handle_request() {
+    struct TestResolver;
+    impl SymbolResolver for TestResolver {
+        fn resolve( &self, query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > > {
+            if query.build_id != Some( &[0x12, 0xab][..] ) || query.address != 0x1000 {
+                return None;
+            }
+
+            Some( vec![ ResolvedFrame { name: format!( "func_{}", query.path ), file: Some( "a.c".to_owned() ), line: Some( 10 ) } ] )
+        }
+    }
+
+    let mut symbolizer = Symbolizer {
+        debug_info_index: DebugInfoIndex::new(),
+        symbol_resolvers: SymbolResolvers { before: vec![ Box::new( TestResolver ) ], .. SymbolResolvers::default() },
+        binaries: HashMap::new()
+    };
+
+    let mut request = |method: &str, path: &str, body: &str| {
+        let request = http::Request { method: method.to_owned(), path: path.to_owned(), body: body.as_bytes().to_vec() };
+        let (status, _, body) = handle_request( &mut symbolizer, &request );
+        (status, String::from_utf8( body ).unwrap())
+    };
+
+    assert_eq!(
+        request( "POST", "/symbolize", r#"{ "build_id": "12AB", "path": "app", "addresses": [4096, 8192] }"# ),
+        ("200 OK", r#"{"frames":[[{"name":"func_app","file":"a.c","line":10}],null]}"#.to_owned())
+    );
+
+    assert_eq!( request( "POST", "/symbolize", r#"{ "build_id": "12A", "addresses": [] }"# ).0, "400 Bad Request" );
+    assert_eq!( request( "POST", "/symbolize", "{}" ).0, "400 Bad Request" );
+    assert_eq!( request( "GET", "/symbolize", "" ).0, "405 Method Not Allowed" );
+    assert_eq!( request( "POST", "/", "" ).0, "404 Not Found" );
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, DebugInfoIndex, Symbols};
+
+use crate::args;
+
+fn symbol_source( is_from_debug_binary: bool, is_dynamic: bool ) -> &'static str {
+    if is_from_debug_binary {
+        "debug"
+    } else if is_dynamic {
+        "dynsym"
+    } else {
+        "symtab"
+    }
+}
+
+pub fn main( args: args::SymbolsArgs ) -> Result< (), Box< dyn Error > > {
+    let binary = BinaryData::load_from_fs( &args.binary ).map_err( |err| format!( "cannot load {:?}: {}", args.binary, err ) )?;
+    let binary = Arc::new( binary );
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    debug_info_index.enable_auto_load();
+    debug_info_index.add( &args.binary );
+    for path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
+
+    // This mirrors what the unwinder does: when a separate debug binary is found
+    // the symbols are only loaded from it, and the binary's own tables are ignored.
+    let (_, debug_binary) = debug_info_index.get_pair( binary.name(), binary.debuglink(), binary.build_id() );
+    let is_from_debug_binary = debug_binary.is_some();
+    let source_binary = debug_binary.unwrap_or( binary );
+    if is_from_debug_binary {
+        info!( "Using the symbols from '{}'", source_binary.name() );
+    }
+
+    let mut symbols = Vec::new();
+    for symbol_table in source_binary.symbol_tables() {
+        let source = symbol_source( is_from_debug_binary, symbol_table.is_dynamic );
+        Symbols::each_from_symbol_table( &source_binary, symbol_table, |range, name| {
+            symbols.push( (range, source, name.to_owned()) );
+        });
+    }
+
+    if symbols.is_empty() {
+        warn!( "No symbols were found for {:?}", args.binary );
+    }
+
+    symbols.sort_by( |lhs, rhs| (lhs.0.start, lhs.0.end, &lhs.2).cmp( &(rhs.0.start, rhs.0.end, &rhs.2) ) );
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    for (range, source, name) in symbols {
+        writeln!( stdout, "0x{:016X} {:>8} {:<6} {}", range.start, range.end - range.start, source, name )?;
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_symbol_source() {
+    assert_eq!( symbol_source( false, true ), "dynsym" );
+    assert_eq!( symbol_source( false, false ), "symtab" );
+    assert_eq!( symbol_source( true, false ), "debug" );
+    assert_eq!( symbol_source( true, true ), "debug" );
+}
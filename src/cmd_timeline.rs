@@ -0,0 +1,303 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::fs::File;
+use std::collections::HashMap;
+
+use crate::args::{self, Granularity};
+use crate::interner::StringInterner;
+use crate::data_reader::{DecodeOpts, EventKind, FrameKind, read_data, repack_cli_args, to_s};
+use crate::cmd_report::{csv_field, describe_frame};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TimelineKey {
+    Process,
+    Function
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TimelineFormat {
+    Csv,
+    Json
+}
+
+const OTHER: &str = "[other]";
+
+struct Series {
+    name: String,
+    total: u64,
+    // The number of samples in every bin.
+    samples: Vec< u64 >
+}
+
+#[derive(Serialize)]
+struct JsonSeries< 'a > {
+    name: &'a str,
+    samples: &'a [u64]
+}
+
+/// The memory use in every bin, in bytes, summed over all of the processes.
+#[derive(Serialize, PartialEq, Debug)]
+struct MemorySeries {
+    rss: Vec< u64 >,
+    pss: Vec< u64 >,
+    swap: Vec< u64 >
+}
+
+#[derive(Serialize)]
+struct JsonTimeline< 'a > {
+    /// The width of a single bin, in seconds.
+    bin: f64,
+    /// The start of every bin, in seconds since the start of the recording.
+    timestamps: Vec< f64 >,
+    series: Vec< JsonSeries< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option< &'a MemorySeries >
+}
+
+#[derive(Copy, Clone)]
+struct MemoryStats {
+    timestamp: u64,
+    pid: u32,
+    rss: u64,
+    pss: u64,
+    swap: u64
+}
+
+/// Bins the `(timestamp, key, weight)` triples into per-key series, keeping only the `top` keys
+/// with the most samples and lumping the rest together into an `[other]` series.
+fn into_series( samples: &[(u64, usize, f64)], names: Vec< String >, bin: u64, top: usize ) -> (u64, Vec< Series >) {
+    let first_bin = samples.iter().map( |&(timestamp, _, _)| timestamp / bin ).min().unwrap_or( 0 );
+    let last_bin = samples.iter().map( |&(timestamp, _, _)| timestamp / bin ).max().unwrap_or( 0 );
+    let bin_count = if samples.is_empty() { 0 } else { (last_bin - first_bin + 1) as usize };
+
+    let mut weights = vec![ vec![ 0.0; bin_count ]; names.len() ];
+    for &(timestamp, key, weight) in samples {
+        weights[ key ][ (timestamp / bin - first_bin) as usize ] += weight;
+    }
+
+    let mut series: Vec< _ > = names.into_iter().zip( weights ).map( |(name, weights)| {
+        let samples: Vec< u64 > = weights.into_iter().map( |weight| weight.round() as u64 ).collect();
+        Series { name, total: samples.iter().sum(), samples }
+    }).collect();
+
+    series.sort_by( |a, b| b.total.cmp( &a.total ).then_with( || a.name.cmp( &b.name ) ) );
+    if series.len() > top {
+        let mut other = Series { name: OTHER.to_owned(), total: 0, samples: vec![ 0; bin_count ] };
+        for series in series.drain( top.. ) {
+            other.total += series.total;
+            for (output, count) in other.samples.iter_mut().zip( series.samples ) {
+                *output += count;
+            }
+        }
+
+        series.push( other );
+    }
+
+    (first_bin * bin, series)
+}
+
+/// Bins the memory statistics into the same bins as the samples; every bin gets the last known
+/// memory use of every process as of the end of that bin.
+fn into_memory_series( stats: &[MemoryStats], start: u64, bin: u64, bin_count: usize ) -> MemorySeries {
+    let mut stats = stats.to_vec();
+    stats.sort_by_key( |stats| stats.timestamp );
+
+    let mut latest = HashMap::new();
+    let mut stats = stats.into_iter().peekable();
+    let mut output = MemorySeries { rss: Vec::with_capacity( bin_count ), pss: Vec::with_capacity( bin_count ), swap: Vec::with_capacity( bin_count ) };
+    for index in 0..bin_count {
+        let end = start + (index as u64 + 1) * bin;
+        while let Some( &entry ) = stats.peek() {
+            if entry.timestamp >= end {
+                break;
+            }
+
+            latest.insert( entry.pid, entry );
+            stats.next();
+        }
+
+        output.rss.push( latest.values().map( |stats| stats.rss ).sum() );
+        output.pss.push( latest.values().map( |stats| stats.pss ).sum() );
+        output.swap.push( latest.values().map( |stats| stats.swap ).sum() );
+    }
+
+    output
+}
+
+fn write_csv( start: u64, bin: u64, series: &[Series], memory: Option< &MemorySeries >, mut fp: impl Write ) -> Result< (), io::Error > {
+    write!( fp, "Timestamp" )?;
+    for series in series {
+        write!( fp, ",{}", csv_field( &series.name ) )?;
+    }
+    if memory.is_some() {
+        write!( fp, ",RSS,PSS,Swap" )?;
+    }
+    writeln!( fp )?;
+
+    let bin_count = series.first().map( |series| series.samples.len() ).unwrap_or( 0 );
+    for index in 0..bin_count {
+        write!( fp, "{}", to_s( start + index as u64 * bin ) )?;
+        for series in series {
+            write!( fp, ",{}", series.samples[ index ] )?;
+        }
+        if let Some( memory ) = memory {
+            write!( fp, ",{},{},{}", memory.rss[ index ], memory.pss[ index ], memory.swap[ index ] )?;
+        }
+        writeln!( fp )?;
+    }
+
+    Ok(())
+}
+
+fn write_json( start: u64, bin: u64, series: &[Series], memory: Option< &MemorySeries >, fp: impl Write ) -> Result< (), Box< dyn Error > > {
+    let bin_count = series.first().map( |series| series.samples.len() ).unwrap_or( 0 );
+    let timeline = JsonTimeline {
+        bin: to_s( bin ),
+        timestamps: (0..bin_count).map( |index| to_s( start + index as u64 * bin ) ).collect(),
+        series: series.iter().map( |series| JsonSeries { name: &series.name, samples: &series.samples } ).collect(),
+        memory
+    };
+
+    serde_json::to_writer( fp, &timeline )?;
+    Ok(())
+}
+
+pub fn main( args: args::TimelineArgs ) -> Result< (), Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Function
+    };
+
+    let mut interner = StringInterner::new();
+    let mut names = Vec::new();
+    let mut index_for_name = HashMap::new();
+    let mut samples = Vec::new();
+    let mut memory_stats = Vec::new();
+    let mut frames = Vec::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                frames.clear();
+                if !sample.try_decode( &event.state, &opts, &mut interner, Some( &mut frames ) ) {
+                    return;
+                }
+
+                let name = match args.by {
+                    TimelineKey::Process => format!( "{} [PID={}]", sample.process.executable(), sample.process.pid() ),
+                    TimelineKey::Function => {
+                        // Skip the kernel's context markers.
+                        let leaf = frames.iter().find( |frame| match **frame {
+                            FrameKind::Kernel( address ) => address < !0 - 4095,
+                            _ => true
+                        });
+
+                        match leaf {
+                            Some( frame ) => {
+                                let (module, symbol, _) = describe_frame( &event.state, &interner, frame );
+                                if module.starts_with( '[' ) {
+                                    format!( "{} {}", symbol, module )
+                                } else {
+                                    format!( "{} [{}]", symbol, module )
+                                }
+                            },
+                            None => "[unknown]".to_owned()
+                        }
+                    }
+                };
+
+                let index = *index_for_name.entry( name.clone() ).or_insert_with( || {
+                    names.push( name );
+                    names.len() - 1
+                });
+
+                samples.push( (sample.timestamp, index, sample.weight) );
+            },
+            EventKind::MemoryStats { timestamp, pid, rss, pss, swap } => {
+                memory_stats.push( MemoryStats { timestamp, pid, rss, pss, swap } );
+            },
+            _ => {}
+        }
+    })?;
+
+    let unfiltered_first_timestamp = state.unfiltered_first_timestamp().unwrap_or( 0 );
+    for sample in &mut samples {
+        sample.0 -= unfiltered_first_timestamp;
+    }
+
+    for stats in &mut memory_stats {
+        stats.timestamp = stats.timestamp.saturating_sub( unfiltered_first_timestamp );
+    }
+
+    let (start, series) = into_series( &samples, names, args.bin, args.top );
+    let bin_count = series.first().map( |series| series.samples.len() ).unwrap_or( 0 );
+    let memory = if memory_stats.is_empty() {
+        None
+    } else {
+        Some( into_memory_series( &memory_stats, start, args.bin, bin_count ) )
+    };
+
+    let fp: Box< dyn Write > = match args.output {
+        Some( ref output ) => Box::new( io::BufWriter::new( File::create( output )? ) ),
+        None => Box::new( io::BufWriter::new( io::stdout() ) )
+    };
+
+    match args.format {
+        TimelineFormat::Csv => write_csv( start, args.bin, &series, memory.as_ref(), fp )?,
+        TimelineFormat::Json => write_json( start, args.bin, &series, memory.as_ref(), fp )?
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_into_series() {
+    let names = vec![ "a".to_owned(), "b".to_owned(), "c".to_owned() ];
+    let samples = [ (150, 0, 1.0), (160, 1, 1.0), (250, 0, 1.0), (420, 0, 1.0), (430, 2, 1.0), (440, 2, 1.0) ];
+
+    let (start, series) = into_series( &samples, names.clone(), 100, 10 );
+    assert_eq!( start, 100 );
+    let series: Vec< _ > = series.iter().map( |series| (series.name.as_str(), series.samples.clone()) ).collect();
+    assert_eq!( series, vec![
+        ("a", vec![ 1, 1, 0, 1 ]),
+        ("c", vec![ 0, 0, 0, 2 ]),
+        ("b", vec![ 1, 0, 0, 0 ])
+    ]);
+
+    let (_, series) = into_series( &samples, names, 100, 1 );
+    let series: Vec< _ > = series.iter().map( |series| (series.name.as_str(), series.samples.clone()) ).collect();
+    assert_eq!( series, vec![
+        ("a", vec![ 1, 1, 0, 1 ]),
+        (OTHER, vec![ 1, 0, 0, 2 ])
+    ]);
+}
+
+#[test]
+fn test_into_series_weighted() {
+    let names = vec![ "a".to_owned(), "b".to_owned() ];
+    let samples = [ (150, 0, 2.0), (160, 0, 2.0), (170, 1, 1.5), (250, 1, 1.5), (260, 1, 1.5) ];
+
+    let (_, series) = into_series( &samples, names, 100, 10 );
+    let series: Vec< _ > = series.iter().map( |series| (series.name.as_str(), series.total, series.samples.clone()) ).collect();
+    assert_eq!( series, vec![
+        ("b", 5, vec![ 2, 3 ]),
+        ("a", 4, vec![ 4, 0 ])
+    ]);
+}
+
+#[test]
+fn test_into_memory_series() {
+    let stats = |timestamp, pid, rss| MemoryStats { timestamp, pid, rss, pss: rss / 2, swap: 0 };
+    let memory_stats = [ stats( 350, 2, 30 ), stats( 50, 1, 10 ), stats( 150, 1, 20 ), stats( 160, 1, 40 ) ];
+
+    assert_eq!( into_memory_series( &memory_stats, 100, 100, 4 ), MemorySeries {
+        rss: vec![ 40, 40, 70, 70 ],
+        pss: vec![ 20, 20, 35, 35 ],
+        swap: vec![ 0, 0, 0, 0 ]
+    });
+}
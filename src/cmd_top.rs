@@ -0,0 +1,254 @@
+use std::mem;
+use std::thread;
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, HashSet};
+
+use libc;
+use nwind::RangeMap;
+
+use crate::args;
+use crate::kallsyms;
+use crate::profiler::ProfilingController;
+use crate::cmd_record::{open_perf_group, run};
+use crate::perf_group::Sampling;
+use crate::utils::percent;
+
+#[derive(Default)]
+struct Samples {
+    count: u64,
+    // The frames are ordered from the leaf.
+    stacks: HashMap< Vec< String >, u64 >
+}
+
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl( libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size ) };
+    if result != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return (80, 25);
+    }
+
+    (size.ws_col as usize, size.ws_row as usize)
+}
+
+/// Returns the functions sorted by their self samples along with their self and total sample counts.
+fn top_functions( stacks: &HashMap< Vec< String >, u64 > ) -> Vec< (&str, u64, u64) > {
+    let mut self_samples: HashMap< &str, u64 > = HashMap::new();
+    let mut total_samples: HashMap< &str, u64 > = HashMap::new();
+    for (frames, &count) in stacks {
+        if let Some( frame ) = frames.first() {
+            *self_samples.entry( frame ).or_insert( 0 ) += count;
+        }
+
+        let unique: HashSet< &str > = frames.iter().map( |frame| frame.as_str() ).collect();
+        for frame in unique {
+            *total_samples.entry( frame ).or_insert( 0 ) += count;
+        }
+    }
+
+    let mut output: Vec< _ > = total_samples.into_iter().map( |(name, total)| {
+        (name, self_samples.get( name ).cloned().unwrap_or( 0 ), total)
+    }).collect();
+
+    output.sort_by( |a, b| b.1.cmp( &a.1 ).then( b.2.cmp( &a.2 ) ).then( a.0.cmp( &b.0 ) ) );
+    output
+}
+
+fn place_frames( stacks: &[&(Vec< &str >, u64)], level: usize, start: usize, width: usize, rows: &mut Vec< Vec< char > > ) {
+    if level >= rows.len() || width == 0 {
+        return;
+    }
+
+    let total: u64 = stacks.iter().map( |&&(_, count)| count ).sum();
+    let mut groups: Vec< (&str, u64, Vec< &(Vec< &str >, u64) >) > = Vec::new();
+    for &stack in stacks {
+        let name = match stack.0.get( level ) {
+            Some( &name ) => name,
+            None => continue
+        };
+
+        match groups.iter_mut().find( |group| group.0 == name ) {
+            Some( group ) => {
+                group.1 += stack.1;
+                group.2.push( stack );
+            },
+            None => groups.push( (name, stack.1, vec![ stack ]) )
+        }
+    }
+
+    groups.sort_by( |a, b| b.1.cmp( &a.1 ).then( a.0.cmp( &b.0 ) ) );
+
+    let mut consumed = 0;
+    for (name, count, children) in groups {
+        let group_start = start + (width as u64 * consumed / total) as usize;
+        consumed += count;
+        let group_end = start + (width as u64 * consumed / total) as usize;
+        let cells = group_end - group_start;
+        if cells == 0 {
+            continue;
+        }
+
+        let row = &mut rows[ level ];
+        if cells == 1 {
+            row[ group_start ] = '|';
+        } else {
+            row[ group_start ] = '[';
+            row[ group_end - 1 ] = ']';
+            for (cell, ch) in row[ group_start + 1..group_end - 1 ].iter_mut().zip( name.chars() ) {
+                *cell = ch;
+            }
+        }
+
+        place_frames( &children, level + 1, group_start, cells, rows );
+    }
+}
+
+/// Renders a small text flamegraph with the root at the bottom.
+fn flamegraph_rows( stacks: &HashMap< Vec< String >, u64 >, width: usize, height: usize ) -> Vec< String > {
+    let stacks: Vec< (Vec< &str >, u64) > = stacks.iter().map( |(frames, &count)| {
+        (frames.iter().rev().map( |frame| frame.as_str() ).collect(), count)
+    }).collect();
+
+    let stacks: Vec< _ > = stacks.iter().collect();
+    let mut rows = vec![ vec![ ' '; width ]; height ];
+    place_frames( &stacks, 0, 0, width, &mut rows );
+
+    let mut rows: Vec< String > = rows.into_iter().map( |row| row.into_iter().collect::< String >().trim_end().to_owned() ).collect();
+    while rows.last().map( |row| row.is_empty() ).unwrap_or( false ) {
+        rows.pop();
+    }
+
+    rows.reverse();
+    rows
+}
+
+fn render( args: &args::TopArgs, pid: u32, samples: &Samples, output: &mut dyn Write ) -> io::Result< () > {
+    let (width, height) = terminal_size();
+    let flamegraph_height = if args.flamegraph { height / 3 } else { 0 };
+    let lines = args.lines.unwrap_or_else( || height.saturating_sub( 5 + flamegraph_height ) );
+
+    let period = if args.cumulative { "in total".to_owned() } else { format!( "in the last {}s", args.interval ) };
+    write!( output, "\x1B[H\x1B[2J" )?;
+    writeln!( output, "nperf top - PID {} - {} samples {}", pid, samples.count, period )?;
+    writeln!( output )?;
+    writeln!( output, "{:>8}  {:>8}  Function", "Self", "Total" )?;
+    for (name, self_samples, total_samples) in top_functions( &samples.stacks ).into_iter().take( lines ) {
+        let line = format!( "{:>7.2}%  {:>7.2}%  {}", percent( self_samples, samples.count ), percent( total_samples, samples.count ), name );
+        writeln!( output, "{}", line.chars().take( width ).collect::< String >() )?;
+    }
+
+    if args.flamegraph {
+        writeln!( output )?;
+        for row in flamegraph_rows( &samples.stacks, width, flamegraph_height ) {
+            writeln!( output, "{}", row )?;
+        }
+    }
+
+    output.flush()
+}
+
+pub fn main( args: args::TopArgs ) -> Result< (), Box< dyn Error > > {
+    let profiler_args = args::GenericProfilerArgs {
+        output: None,
+        sample_count: None,
+        time_limit: args.time_limit,
+        lock_memory: false,
+        offline: false,
+        keep_raw: false,
+        python: false,
+        perf_map_agent: None,
+        perf_map_agent_interval: 10.0,
+        r2r_map_dir: Vec::new(),
+        panic_on_partial_backtrace: false,
+        progress: None,
+        progress_interval: 1.0,
+        memory_stats: false,
+        memory_stats_interval: 1.0,
+        self_profile: false,
+        process_filter: args.process_filter.clone()
+    };
+
+    let kallsyms = kallsyms::read().map( |kallsyms| kallsyms::parse( &kallsyms ) ).unwrap_or_else( RangeMap::new );
+    let mut controller = ProfilingController::new_without_output( &profiler_args )?;
+    let mut perf = open_perf_group( &mut controller, Sampling::Frequency( args.frequency ), args.stack_size, None, args.event_source )?;
+
+    let pid = controller.pid();
+    let samples = Arc::new( Mutex::new( Samples::default() ) );
+    let is_running = Arc::new( AtomicBool::new( true ) );
+    let args = Arc::new( args );
+    let display = {
+        let samples = samples.clone();
+        let is_running = is_running.clone();
+        let args = args.clone();
+        thread::spawn( move || -> io::Result< () > {
+            let interval = Duration::from_secs( args.interval.max( 1 ) );
+            let stdout = io::stdout();
+            while is_running.load( Ordering::Relaxed ) {
+                let timestamp = Instant::now();
+                while timestamp.elapsed() < interval && is_running.load( Ordering::Relaxed ) {
+                    thread::sleep( Duration::from_millis( 50 ) );
+                }
+
+                let mut stdout = stdout.lock();
+                if args.cumulative {
+                    render( &args, pid, &samples.lock().unwrap(), &mut stdout )?;
+                } else {
+                    let samples = mem::replace( &mut *samples.lock().unwrap(), Samples::default() );
+                    render( &args, pid, &samples, &mut stdout )?;
+                }
+            }
+
+            Ok(())
+        })
+    };
+
+    let mut frames = Vec::new();
+    let total_lost_events = run( &mut controller, &mut perf, false, |controller, dwarf_regs, sample| {
+        frames.clear();
+        kallsyms::symbolize_backtrace( &kallsyms, &sample.kernel_backtrace, &mut frames );
+        controller.symbolize_sample( dwarf_regs, &sample, &mut frames );
+
+        let mut samples = samples.lock().unwrap();
+        samples.count += 1;
+        *samples.stacks.entry( frames.clone() ).or_insert( 0 ) += 1;
+    });
+
+    is_running.store( false, Ordering::Relaxed );
+    display.join().unwrap()?;
+
+    if total_lost_events > 0 {
+        warn!( "Lost {} events!", total_lost_events );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_top_functions() {
+    let mut stacks = HashMap::new();
+    stacks.insert( vec![ "c".to_owned(), "b".to_owned(), "a".to_owned() ], 1 );
+    stacks.insert( vec![ "b".to_owned(), "a".to_owned() ], 2 );
+    stacks.insert( vec![ "a".to_owned(), "a".to_owned() ], 3 );
+
+    assert_eq!( top_functions( &stacks ), vec![ ("a", 3, 6), ("b", 2, 3), ("c", 1, 1) ] );
+}
+
+#[test]
+fn test_flamegraph_rows() {
+    let mut stacks = HashMap::new();
+    stacks.insert( vec![ "b".to_owned(), "a".to_owned() ], 3 );
+    stacks.insert( vec![ "c".to_owned(), "a".to_owned() ], 1 );
+    stacks.insert( vec![ "x".to_owned() ], 4 );
+
+    assert_eq!( flamegraph_rows( &stacks, 16, 3 ), vec![
+        "[b   ][]",
+        "[a     ][x     ]"
+    ]);
+
+    assert_eq!( flamegraph_rows( &stacks, 16, 1 ), vec![
+        "[a     ][x     ]"
+    ]);
+}
@@ -9,13 +9,14 @@ use crate::interner::StringInterner;
 use crate::data_reader::{FrameKind, DecodeOpts, EventKind, read_data, repack_cli_args, write_frame};
 
 #[derive(PartialEq, Debug)]
-struct TraceEvent< T > {
-    frame: T,
-    timestamp: u64,
-    is_end: bool
+pub(crate) struct TraceEvent< T > {
+    pub frame: T,
+    pub timestamp: u64,
+    pub is_end: bool
 }
 
-fn emit_events< T >( raw_events: Vec< (u64, Vec< T >) >, sampling_period: u64, merge_period: Option< u64 > ) -> Vec< TraceEvent< T > > where T: PartialEq + Clone {
+/// Converts the samples of a single thread sorted by their timestamps into begin/end events.
+pub(crate) fn emit_events< T >( raw_events: Vec< (u64, Vec< T >) >, sampling_period: u64, merge_period: Option< u64 > ) -> Vec< TraceEvent< T > > where T: PartialEq + Clone {
     let mut events = Vec::with_capacity( raw_events.len() * 2 );
     let mut current_frames: Vec< T > = Vec::new();
     let mut last_timestamp = raw_events.first().map( |&(timestamp, _)| timestamp ).unwrap_or( 0 );
@@ -269,17 +270,50 @@ fn test_emit_events_8() {
     );
 }
 
+/// Returns the sampling period, and the period within which consecutive samples
+/// will be merged together unless explicitly specified.
+pub(crate) fn sampling_periods( frequency: Option< u32 >, mut merge_period: Option< u64 > ) -> (u64, Option< u64 >) {
+    let sampling_period = if let Some( frequency ) = frequency {
+        info!( "Profiling data frequency: {}", frequency );
+
+        let profiling_period = (1.0 / frequency as f64) * 1000_000_000.0;
+        if merge_period.is_none() {
+            let overhead =
+                max(
+                    min(
+                        ((frequency as f64).log10() * 10_000.0) as u64,
+                        40_000
+                    ),
+                    min(
+                        (profiling_period * 0.01) as u64,
+                        100_000
+                    )
+                );
+            merge_period = Some( profiling_period as u64 + overhead );
+            info!( "Trace period for frame collapsing: {}us", merge_period.unwrap() / 1000 );
+        }
+
+        profiling_period as u64
+    } else {
+        merge_period.unwrap_or( 1_000_000 )
+    };
+
+    (sampling_period, merge_period)
+}
+
 pub fn main( args: args::TraceEventsArgs ) -> Result< (), Box< dyn Error > > {
-    let (omit_regex, read_data_args) = repack_cli_args( &args.collation_args );
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
     let opts = DecodeOpts {
-        omit_regex,
+        filter,
         emit_kernel_frames: true,
         emit_thread_frames: false,
         emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
         granularity: args.arg_granularity.granularity
     };
 
-    let mut merge_period = args.period;
+    let merge_period = args.period;
     let mut raw_events_for_thread = HashMap::new();
     let mut interner = StringInterner::new();
     let state = read_data( read_data_args, |event| {
@@ -299,30 +333,7 @@ pub fn main( args: args::TraceEventsArgs ) -> Result< (), Box< dyn Error > > {
 
     })?;
 
-    let sampling_period = if let Some( frequency ) = state.frequency() {
-        info!( "Profiling data frequency: {}", frequency );
-
-        let profiling_period = (1.0 / frequency as f64) * 1000_000_000.0;
-        if merge_period.is_none() {
-            let overhead =
-                max(
-                    min(
-                        ((frequency as f64).log10() * 10_000.0) as u64,
-                        40_000
-                    ),
-                    min(
-                        (profiling_period * 0.01) as u64,
-                        100_000
-                    )
-                );
-            merge_period = Some( profiling_period as u64 + overhead );
-            info!( "Trace period for frame collapsing: {}us", merge_period.unwrap() / 1000 );
-        }
-
-        profiling_period as u64
-    } else {
-        merge_period.unwrap_or( 1_000_000 )
-    };
+    let (sampling_period, merge_period) = sampling_periods( state.frequency(), merge_period );
 
     let mut wrote_pid = HashSet::new();
     let mut wrote_tid = HashSet::new();
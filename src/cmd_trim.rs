@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::error::Error;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use nwind::BinaryId;
+use speedy::Writable;
+
+use crate::args;
+use crate::archive::{ARCHIVE_MAGIC, ARCHIVE_VERSION, ArchiveReader, FramedPacket, Inode, Packet};
+use crate::data_reader::{is_in_bounds, parse_timestamp_bounds};
+use crate::merge::to_binary_id;
+
+fn region_binary_id( inode: u64, major: u32, minor: u32, name: &[u8] ) -> BinaryId {
+    to_binary_id( Inode { inode, dev_major: major, dev_minor: minor }, name )
+}
+
+pub fn main( args: args::TrimArgs ) -> Result< (), Box< dyn Error > > {
+    let (from, to) = parse_timestamp_bounds( args.from.as_ref(), args.to.as_ref(), args.range.as_ref() )?;
+    let open = || -> Result< _, Box< dyn Error > > {
+        let fp = File::open( &args.input ).map_err( |err| format!( "cannot open {:?}: {}", args.input, err ) )?;
+        Ok( ArchiveReader::new( fp ).validate_header()?.skip_unknown() )
+    };
+
+    let mut first_timestamp = None;
+    let mut last_timestamp = None;
+    for packet in open()? {
+        match packet? {
+            Packet::Sample { timestamp, .. } | Packet::RawSample { timestamp, .. } => {
+                first_timestamp = Some( first_timestamp.map( |first: u64| first.min( timestamp ) ).unwrap_or( timestamp ) );
+                last_timestamp = Some( last_timestamp.map( |last: u64| last.max( timestamp ) ).unwrap_or( timestamp ) );
+            },
+            _ => {}
+        }
+    }
+
+    let first_timestamp = match first_timestamp {
+        Some( timestamp ) => timestamp,
+        None => return Err( format!( "input {:?} doesn't contain any samples", args.input ).into() )
+    };
+
+    let is_sample_kept = |pid: u32, timestamp: u64| {
+        (args.pid.is_empty() || args.pid.contains( &pid )) &&
+        is_in_bounds( from, to, first_timestamp, last_timestamp, timestamp )
+    };
+
+    // Figure out which processes have any samples left, and which binaries they need.
+    let mut kept_pids = HashSet::new();
+    let mut binaries_for_pid: HashMap< u32, HashSet< BinaryId > > = HashMap::new();
+    for packet in open()? {
+        match packet? {
+            Packet::Sample { timestamp, pid, .. } | Packet::RawSample { timestamp, pid, .. } => {
+                if is_sample_kept( pid, timestamp ) {
+                    kept_pids.insert( pid );
+                }
+            },
+            Packet::ProcessInfo { pid, binary_id, executable, .. } => {
+                binaries_for_pid.entry( pid ).or_default().insert( to_binary_id( binary_id, &executable ) );
+            },
+            Packet::MemoryRegionMap { pid, inode, major, minor, name, .. } => {
+                binaries_for_pid.entry( pid ).or_default().insert( region_binary_id( inode, major, minor, &name ) );
+            },
+            Packet::BinaryLoaded { pid, inode, name } => {
+                binaries_for_pid.entry( pid ).or_default().insert( to_binary_id( inode.unwrap_or( Inode::empty() ), &name ) );
+            },
+            Packet::Deprecated_BinaryMap { pid, inode, .. } => {
+                binaries_for_pid.entry( pid ).or_default().insert( BinaryId::ByInode( inode ) );
+            },
+            _ => {}
+        }
+    }
+
+    let mut needed_binaries = HashSet::new();
+    for pid in &kept_pids {
+        if let Some( binaries ) = binaries_for_pid.remove( pid ) {
+            needed_binaries.extend( binaries );
+        }
+    }
+
+    let fp = File::create( &args.output ).map_err( |err| format!( "cannot create {:?}: {}", args.output, err ) )?;
+    let mut fp = io::BufWriter::new( fp );
+
+    FramedPacket::Known( Packet::Header {
+        magic: ARCHIVE_MAGIC,
+        version: ARCHIVE_VERSION
+    }).write_to_stream( &mut fp )?;
+
+    let mut sample_count = 0;
+    let mut is_last_sample_kept = false;
+    for packet in open()? {
+        let packet = packet?;
+        let is_kept = match packet {
+            Packet::Sample { timestamp, pid, .. } | Packet::RawSample { timestamp, pid, .. } => {
+                let is_kept = is_sample_kept( pid, timestamp );
+                if is_kept {
+                    sample_count += 1;
+                }
+                is_last_sample_kept = is_kept;
+                is_kept
+            },
+            Packet::RawSampleData { .. } => is_last_sample_kept,
+            Packet::ProcessInfo { pid, .. } |
+            Packet::ThreadName { pid, .. } |
+            Packet::MemoryRegionMap { pid, .. } |
+            Packet::MemoryRegionUnmap { pid, .. } |
+            Packet::Deprecated_BinaryMap { pid, .. } |
+            Packet::Deprecated_BinaryUnmap { pid, .. } |
+            Packet::BinaryLoaded { pid, .. } |
+            Packet::BinaryUnloaded { pid, .. } |
+            Packet::ContextSwitch { pid, .. } |
+            Packet::PythonFunction { pid, .. } |
+            Packet::PerfMap { pid, .. } => kept_pids.contains( &pid ),
+            Packet::Stall { timestamp, pid, .. } => is_sample_kept( pid, timestamp ),
+            Packet::BinaryInfo { inode, ref path, .. } |
+            Packet::StringTable { inode, ref path, .. } |
+            Packet::SymbolTable { inode, ref path, .. } |
+            Packet::BinaryBlob { inode, ref path, .. } |
+            Packet::BuildId { inode, ref path, .. } => needed_binaries.contains( &to_binary_id( inode, path ) ),
+            _ => true
+        };
+
+        if is_kept {
+            FramedPacket::Known( packet ).write_to_stream( &mut fp )?;
+        }
+    }
+
+    fp.flush()?;
+    info!( "Written {} samples from {} processes into {:?}", sample_count, kept_pids.len(), args.output );
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn read_packets( path: &std::path::Path ) -> Vec< Packet< 'static > > {
+    let fp = File::open( path ).unwrap();
+    ArchiveReader::new( fp ).validate_header().unwrap().skip_unknown().map( |packet| packet.unwrap() ).collect()
+}
+
+#[test]
+fn test_trim_by_time_range() {
+    let input = std::path::Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-pthread_cond_wait.nperf" );
+    let output = std::env::temp_dir().join( format!( "nperf-trim-test-{}.nperf", std::process::id() ) );
+
+    let packets = read_packets( &input );
+    let mut timestamps: Vec< u64 > = packets.iter().filter_map( |packet| match *packet {
+        Packet::Sample { timestamp, .. } | Packet::RawSample { timestamp, .. } => Some( timestamp ),
+        _ => None
+    }).collect();
+    timestamps.sort();
+    timestamps.dedup();
+    assert!( timestamps.len() >= 4 );
+
+    // Keep the samples from the second one up to the one in the middle, with the bounds
+    // placed halfway between the samples so that they're unambiguous.
+    let first = timestamps[ 0 ];
+    let middle = timestamps.len() / 2;
+    let from = (timestamps[ 0 ] + timestamps[ 1 ]) / 2 - first;
+    let to = (timestamps[ middle ] + timestamps[ middle + 1 ]) / 2 - first;
+
+    main( args::TrimArgs {
+        pid: Vec::new(),
+        from: None,
+        to: None,
+        range: Some( format!( "{}ns..{}ns", from, to ) ),
+        output: output.clone().into(),
+        input: input.clone().into()
+    }).unwrap();
+
+    let trimmed = read_packets( &output );
+    std::fs::remove_file( &output ).unwrap();
+
+    let kept: Vec< u64 > = trimmed.iter().filter_map( |packet| match *packet {
+        Packet::Sample { timestamp, .. } | Packet::RawSample { timestamp, .. } => Some( timestamp ),
+        _ => None
+    }).collect();
+    assert_eq!( kept.len(), middle );
+    assert!( kept.iter().all( |&timestamp| timestamp > timestamps[ 0 ] && timestamp <= timestamps[ middle ] ) );
+
+    // The process still has samples, so everything needed to decode them has to be kept.
+    let region_maps = |packets: &[Packet]| packets.iter().filter( |packet| matches!( packet, Packet::MemoryRegionMap { .. } ) ).count();
+    let process_infos = |packets: &[Packet]| packets.iter().filter( |packet| matches!( packet, Packet::ProcessInfo { .. } ) ).count();
+    let thread_names = |packets: &[Packet]| packets.iter().filter( |packet| matches!( packet, Packet::ThreadName { .. } ) ).count();
+
+    assert_ne!( region_maps( &packets ), 0 );
+    assert_eq!( region_maps( &trimmed ), region_maps( &packets ) );
+    assert_eq!( process_infos( &trimmed ), 1 );
+    assert_ne!( thread_names( &packets ), 0 );
+    assert_eq!( thread_names( &trimmed ), thread_names( &packets ) );
+}
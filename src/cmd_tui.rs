@@ -0,0 +1,777 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::hash::Hash;
+use std::collections::{HashMap, HashSet};
+use std::panic;
+use std::sync::Arc;
+
+use regex::Regex;
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+
+use crate::args::{self, Granularity};
+use crate::interner::StringInterner;
+use crate::data_reader::{State, DecodeOpts, EventKind, FrameKind, read_data, repack_cli_args};
+use crate::cmd_report::{Entry, ReportSort, compare_entries, describe_frame};
+use crate::utils::percent;
+use crate::cmd_annotate::{self, Counts};
+
+struct Node< T > {
+    frame: Option< T >,
+    parent: usize,
+    self_samples: u64,
+    total_samples: u64,
+    children: Vec< usize >
+}
+
+/// A call tree where every node is a single call path from the root.
+struct CallTree< T > {
+    nodes: Vec< Node< T > >
+}
+
+impl< T > CallTree< T > where T: Clone + Eq + Hash {
+    /// Builds the tree from stacks whose frames are ordered from the leaf to the root.
+    fn new( stacks: &[(Vec< T >, u64)] ) -> Self {
+        let mut nodes = vec![ Node {
+            frame: None,
+            parent: 0,
+            self_samples: 0,
+            total_samples: 0,
+            children: Vec::new()
+        }];
+
+        let mut child_index: HashMap< (usize, T), usize > = HashMap::new();
+        for &(ref frames, count) in stacks {
+            let mut current = 0;
+            nodes[ 0 ].total_samples += count;
+            for frame in frames.iter().rev() {
+                let key = (current, frame.clone());
+                let index = match child_index.get( &key ) {
+                    Some( &index ) => index,
+                    None => {
+                        let index = nodes.len();
+                        nodes.push( Node {
+                            frame: Some( frame.clone() ),
+                            parent: current,
+                            self_samples: 0,
+                            total_samples: 0,
+                            children: Vec::new()
+                        });
+                        nodes[ current ].children.push( index );
+                        child_index.insert( key, index );
+                        index
+                    }
+                };
+
+                nodes[ index ].total_samples += count;
+                current = index;
+            }
+
+            nodes[ current ].self_samples += count;
+        }
+
+        for index in 0..nodes.len() {
+            let mut children = std::mem::replace( &mut nodes[ index ].children, Vec::new() );
+            children.sort_by( |&a, &b| nodes[ b ].total_samples.cmp( &nodes[ a ].total_samples ) );
+            nodes[ index ].children = children;
+        }
+
+        CallTree { nodes }
+    }
+
+    /// Returns the nodes which are visible when only the `expanded` nodes
+    /// show their children, along with their depth.
+    fn visible_rows( &self, expanded: &HashSet< usize > ) -> Vec< (usize, usize) > {
+        let mut output = Vec::new();
+        let mut queue: Vec< _ > = self.nodes[ 0 ].children.iter().rev().map( |&index| (index, 0) ).collect();
+        while let Some( (index, depth) ) = queue.pop() {
+            output.push( (index, depth) );
+            if expanded.contains( &index ) {
+                queue.extend( self.nodes[ index ].children.iter().rev().map( |&child| (child, depth + 1) ) );
+            }
+        }
+
+        output
+    }
+
+    /// Returns the first node in pre-order after `after` which matches the `predicate`, wrapping around.
+    fn find_next< F >( &self, after: usize, predicate: F ) -> Option< usize > where F: Fn( &T ) -> bool {
+        let mut order = Vec::with_capacity( self.nodes.len() );
+        let mut queue = vec![ 0 ];
+        while let Some( index ) = queue.pop() {
+            order.push( index );
+            queue.extend( self.nodes[ index ].children.iter().rev() );
+        }
+
+        let position = order.iter().position( |&index| index == after ).unwrap_or( 0 );
+        order[ position + 1.. ].iter().chain( order[ ..=position ].iter() ).cloned().find( |&index| {
+            self.nodes[ index ].frame.as_ref().map( |frame| predicate( frame ) ).unwrap_or( false )
+        })
+    }
+
+    fn ancestors( &self, mut index: usize ) -> Vec< usize > {
+        let mut output = Vec::new();
+        while index != 0 {
+            index = self.nodes[ index ].parent;
+            if index != 0 {
+                output.push( index );
+            }
+        }
+
+        output
+    }
+}
+
+#[derive(Default)]
+struct ListState {
+    cursor: usize,
+    offset: usize
+}
+
+impl ListState {
+    fn move_by( &mut self, delta: isize, length: usize ) {
+        if length == 0 {
+            self.cursor = 0;
+            return;
+        }
+
+        let cursor = self.cursor as isize + delta;
+        self.cursor = cursor.max( 0 ).min( length as isize - 1 ) as usize;
+    }
+
+    fn scroll_into_view( &mut self, height: usize ) {
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if height > 0 && self.cursor >= self.offset + height {
+            self.offset = self.cursor + 1 - height;
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum View {
+    Functions,
+    CallTree,
+    Annotation
+}
+
+struct App< 'a > {
+    state: &'a State,
+    interner: &'a StringInterner,
+    sample_count: u64,
+    context: u64,
+    stacks: Vec< (Vec< FrameKind >, u64) >,
+    functions: Vec< (FrameKind, Entry) >,
+    sort: ReportSort,
+    tree: CallTree< FrameKind >,
+    expanded: HashSet< usize >,
+    view: View,
+    previous_view: View,
+    search: Option< Regex >,
+    search_input: Option< String >,
+    function_list: ListState,
+    tree_list: ListState,
+    annotation: Vec< String >,
+    annotation_list: ListState,
+    message: String
+}
+
+/// Converts a frame decoded with an address granularity into one identifying the whole function.
+fn function_frame( frame: &FrameKind ) -> FrameKind {
+    match *frame {
+        FrameKind::UserByAddress { ref binary_id, is_inline, symbol, .. } => FrameKind::UserByFunction {
+            binary_id: binary_id.clone(),
+            is_inline,
+            symbol
+        },
+        FrameKind::Python { symbol, file, .. } => FrameKind::Python { symbol, file, line: None },
+        ref frame => frame.clone()
+    }
+}
+
+fn truncate( string: &str, width: usize ) -> String {
+    string.chars().take( width ).collect()
+}
+
+fn next_sort( sort: ReportSort ) -> ReportSort {
+    match sort {
+        ReportSort::SelfSamples => ReportSort::TotalSamples,
+        ReportSort::TotalSamples => ReportSort::Symbol,
+        ReportSort::Symbol => ReportSort::Module,
+        ReportSort::Module => ReportSort::SelfSamples
+    }
+}
+
+fn sort_name( sort: ReportSort ) -> &'static str {
+    match sort {
+        ReportSort::SelfSamples => "self",
+        ReportSort::TotalSamples => "total",
+        ReportSort::Symbol => "symbol",
+        ReportSort::Module => "module"
+    }
+}
+
+const HELP: &str = "q: quit/back  tab: switch view  enter: expand  a: annotate  s: sort  /: search  n: next match";
+
+impl< 'a > App< 'a > {
+    fn new( state: &'a State, interner: &'a StringInterner, stacks: Vec< (Vec< FrameKind >, u64) >, sort: ReportSort, context: u64 ) -> Self {
+        let function_stacks: Vec< (Vec< FrameKind >, u64) > = stacks.iter().map( |&(ref frames, count)| {
+            (frames.iter().map( function_frame ).collect(), count)
+        }).collect();
+
+        let sample_count = stacks.iter().map( |&(_, count)| count ).sum();
+        let mut self_samples: HashMap< &FrameKind, u64 > = HashMap::new();
+        let mut total_samples: HashMap< &FrameKind, u64 > = HashMap::new();
+        for &(ref frames, count) in &function_stacks {
+            if let Some( frame ) = frames.first() {
+                *self_samples.entry( frame ).or_insert( 0 ) += count;
+            }
+
+            let unique: HashSet< _ > = frames.iter().collect();
+            for frame in unique {
+                *total_samples.entry( frame ).or_insert( 0 ) += count;
+            }
+        }
+
+        let functions = total_samples.into_iter().map( |(frame, total_samples)| {
+            let (module, symbol, is_kernel) = describe_frame( state, interner, frame );
+            let entry = Entry {
+                module,
+                symbol,
+                is_kernel,
+                self_samples: self_samples.get( frame ).cloned().unwrap_or( 0 ),
+                total_samples
+            };
+
+            (frame.clone(), entry)
+        }).collect();
+
+        let tree = CallTree::new( &function_stacks );
+        let mut app = App {
+            state,
+            interner,
+            sample_count,
+            context,
+            stacks,
+            functions,
+            sort,
+            tree,
+            expanded: HashSet::new(),
+            view: View::Functions,
+            previous_view: View::Functions,
+            search: None,
+            search_input: None,
+            function_list: ListState::default(),
+            tree_list: ListState::default(),
+            annotation: Vec::new(),
+            annotation_list: ListState::default(),
+            message: HELP.to_owned()
+        };
+
+        app.sort_functions();
+        app
+    }
+
+    fn sort_functions( &mut self ) {
+        let sort = self.sort;
+        self.functions.sort_by( |a, b| compare_entries( &a.1, &b.1, sort ) );
+    }
+
+    fn is_match( &self, entry: &Entry ) -> bool {
+        match self.search {
+            Some( ref search ) => search.is_match( &entry.symbol ),
+            None => true
+        }
+    }
+
+    fn visible_functions( &self ) -> Vec< usize > {
+        (0..self.functions.len()).filter( |&index| self.is_match( &self.functions[ index ].1 ) ).collect()
+    }
+
+    fn selected_frame( &self ) -> Option< FrameKind > {
+        match self.view {
+            View::Functions => {
+                let visible = self.visible_functions();
+                visible.get( self.function_list.cursor ).map( |&index| self.functions[ index ].0.clone() )
+            },
+            View::CallTree => {
+                let rows = self.tree.visible_rows( &self.expanded );
+                rows.get( self.tree_list.cursor ).and_then( |&(index, _)| self.tree.nodes[ index ].frame.clone() )
+            },
+            View::Annotation => None
+        }
+    }
+
+    fn annotate( &mut self, key: &FrameKind ) {
+        let (module, symbol, _) = describe_frame( self.state, self.interner, key );
+        match *key {
+            FrameKind::UserByFunction { .. } => {},
+            _ => {
+                self.message = format!( "Annotation is not available for '{}'", symbol );
+                return;
+            }
+        }
+
+        let mut function = cmd_annotate::Function::default();
+        for &(ref frames, count) in &self.stacks {
+            // The frames are ordered from the leaf; the leaf frame and every inline
+            // frame up to the first non-inline one share the same instruction.
+            let mut is_self = true;
+            let mut seen_function = false;
+            let mut seen_lines = HashSet::new();
+            for frame in frames {
+                let (is_inline, file, line) = match *frame {
+                    FrameKind::UserByAddress { is_inline, file, line, .. } => (is_inline, file, line),
+                    _ => {
+                        is_self = false;
+                        continue;
+                    }
+                };
+
+                if function_frame( frame ) == *key {
+                    if !seen_function {
+                        seen_function = true;
+                        function.samples.add( count, is_self );
+                    }
+
+                    if let (Some( file ), Some( line )) = (file, line) {
+                        if seen_lines.insert( (file, line) ) {
+                            function.lines.entry( (file, line) ).or_insert_with( Counts::default ).add( count, is_self );
+                        }
+                    }
+                }
+
+                if !is_inline {
+                    is_self = false;
+                }
+            }
+        }
+
+        let mut output = Vec::new();
+        let _ = writeln!(
+            output,
+            "{} [{}]: {} samples ({:.2}% of all), {} self ({:.2}% of all)",
+            symbol,
+            module,
+            function.samples.total_samples,
+            percent( function.samples.total_samples, self.sample_count ),
+            function.samples.self_samples,
+            percent( function.samples.self_samples, self.sample_count )
+        );
+        let _ = writeln!( output );
+        let _ = cmd_annotate::write_function( self.interner, &function, self.context, &mut output );
+
+        self.annotation = String::from_utf8_lossy( &output ).lines().map( |line| line.to_owned() ).collect();
+        self.annotation_list = ListState::default();
+        if self.view != View::Annotation {
+            self.previous_view = self.view;
+        }
+        self.view = View::Annotation;
+    }
+
+    fn find_next( &mut self ) {
+        let search = match self.search {
+            Some( ref search ) => search.clone(),
+            None => return
+        };
+
+        match self.view {
+            View::Functions => {
+                let length = self.visible_functions().len();
+                if length == 0 {
+                    self.message = format!( "No function matches '{}'", search );
+                }
+                self.function_list.move_by( 0, length );
+            },
+            View::CallTree => {
+                let rows = self.tree.visible_rows( &self.expanded );
+                let current = rows.get( self.tree_list.cursor ).map( |&(index, _)| index ).unwrap_or( 0 );
+                let state = self.state;
+                let interner = self.interner;
+                let found = self.tree.find_next( current, |frame| {
+                    let (_, symbol, _) = describe_frame( state, interner, frame );
+                    search.is_match( &symbol )
+                });
+
+                match found {
+                    Some( index ) => {
+                        self.expanded.extend( self.tree.ancestors( index ) );
+                        let rows = self.tree.visible_rows( &self.expanded );
+                        self.tree_list.cursor = rows.iter().position( |&(row, _)| row == index ).unwrap_or( 0 );
+                    },
+                    None => {
+                        self.message = format!( "No function matches '{}'", search );
+                    }
+                }
+            },
+            View::Annotation => {}
+        }
+    }
+
+    /// Handles a single key press; returns `false` if the application should quit.
+    fn handle_key( &mut self, key: KeyEvent, page: usize ) -> bool {
+        if let Some( mut input ) = self.search_input.take() {
+            match key.code {
+                KeyCode::Enter => {
+                    if input.is_empty() {
+                        self.search = None;
+                        self.message = HELP.to_owned();
+                    } else {
+                        match Regex::new( &input ) {
+                            Ok( regex ) => {
+                                self.search = Some( regex );
+                                self.message = format!( "Search: {}", input );
+                                self.function_list = ListState::default();
+                                self.find_next();
+                            },
+                            Err( error ) => {
+                                self.message = format!( "Invalid regexp: {}", error );
+                            }
+                        }
+                    }
+                },
+                KeyCode::Esc => {},
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.search_input = Some( input );
+                },
+                KeyCode::Char( ch ) => {
+                    input.push( ch );
+                    self.search_input = Some( input );
+                },
+                _ => {
+                    self.search_input = Some( input );
+                }
+            }
+
+            return true;
+        }
+
+        if key.code == KeyCode::Char( 'c' ) && key.modifiers.contains( KeyModifiers::CONTROL ) {
+            return false;
+        }
+
+        let page = page.max( 1 ) as isize;
+        let length = match self.view {
+            View::Functions => self.visible_functions().len(),
+            View::CallTree => self.tree.visible_rows( &self.expanded ).len(),
+            View::Annotation => self.annotation.len()
+        };
+
+        let delta = match key.code {
+            KeyCode::Up | KeyCode::Char( 'k' ) => Some( -1 ),
+            KeyCode::Down | KeyCode::Char( 'j' ) => Some( 1 ),
+            KeyCode::PageUp => Some( -page ),
+            KeyCode::PageDown | KeyCode::Char( ' ' ) => Some( page ),
+            KeyCode::Home | KeyCode::Char( 'g' ) => Some( isize::min_value() / 2 ),
+            KeyCode::End | KeyCode::Char( 'G' ) => Some( isize::max_value() / 2 ),
+            _ => None
+        };
+
+        if let Some( delta ) = delta {
+            match self.view {
+                View::Functions => self.function_list.move_by( delta, length ),
+                View::CallTree => self.tree_list.move_by( delta, length ),
+                View::Annotation => self.annotation_list.move_by( delta, length )
+            }
+
+            return true;
+        }
+
+        match (self.view, key.code) {
+            (View::Annotation, KeyCode::Char( 'q' ))
+            | (View::Annotation, KeyCode::Esc)
+            | (View::Annotation, KeyCode::Left) => {
+                self.view = self.previous_view;
+            },
+            (_, KeyCode::Char( 'q' )) => return false,
+            (_, KeyCode::Esc) => {
+                self.search = None;
+                self.message = HELP.to_owned();
+            },
+            (View::Functions, KeyCode::Tab) => self.view = View::CallTree,
+            (View::CallTree, KeyCode::Tab) => self.view = View::Functions,
+            (_, KeyCode::Char( '/' )) => {
+                self.search_input = Some( String::new() );
+            },
+            (_, KeyCode::Char( 'n' )) => self.find_next(),
+            (View::Functions, KeyCode::Char( 's' )) => {
+                self.sort = next_sort( self.sort );
+                self.sort_functions();
+                self.message = format!( "Sorted by: {}", sort_name( self.sort ) );
+            },
+            (View::Functions, KeyCode::Enter) | (View::Functions, KeyCode::Right) | (_, KeyCode::Char( 'a' )) => {
+                if let Some( frame ) = self.selected_frame() {
+                    self.annotate( &frame );
+                }
+            },
+            (View::CallTree, KeyCode::Enter) | (View::CallTree, KeyCode::Right) | (View::CallTree, KeyCode::Left) => {
+                let rows = self.tree.visible_rows( &self.expanded );
+                if let Some( &(index, _) ) = rows.get( self.tree_list.cursor ) {
+                    let is_expanded = self.expanded.contains( &index );
+                    if key.code == KeyCode::Left && !is_expanded {
+                        let parent = self.tree.nodes[ index ].parent;
+                        if let Some( position ) = rows.iter().position( |&(row, _)| row == parent ) {
+                            self.tree_list.cursor = position;
+                        }
+                    } else if is_expanded && key.code != KeyCode::Right {
+                        self.expanded.remove( &index );
+                    } else if !is_expanded && key.code != KeyCode::Left {
+                        self.expanded.insert( index );
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        true
+    }
+
+    fn draw< T: Write >( &mut self, output: &mut T, width: usize, height: usize ) -> crossterm::Result< () > {
+        let body_height = height.saturating_sub( 3 );
+        let mut lines: Vec< (String, bool) > = Vec::with_capacity( height );
+
+        let view_name = match self.view {
+            View::Functions => "Functions",
+            View::CallTree => "Call tree",
+            View::Annotation => "Annotation"
+        };
+
+        lines.push( (format!( " nperf | {} | Samples: {} | Sort: {}", view_name, self.sample_count, sort_name( self.sort ) ), true) );
+        match self.view {
+            View::Functions => {
+                lines.push( (format!( "  {:>8}  {:>8}  {:<24}  {}", "Children", "Self", "Module", "Symbol" ), false) );
+                let visible = self.visible_functions();
+                self.function_list.move_by( 0, visible.len() );
+                self.function_list.scroll_into_view( body_height );
+                for (row, &index) in visible.iter().enumerate().skip( self.function_list.offset ).take( body_height ) {
+                    let entry = &self.functions[ index ].1;
+                    let line = format!(
+                        "  {:>7.2}%  {:>7.2}%  {:<24}  [{}] {}",
+                        percent( entry.total_samples, self.sample_count ),
+                        percent( entry.self_samples, self.sample_count ),
+                        entry.module,
+                        if entry.is_kernel { "k" } else { "." },
+                        entry.symbol
+                    );
+                    lines.push( (line, row == self.function_list.cursor) );
+                }
+            },
+            View::CallTree => {
+                lines.push( (format!( "  {:>8}  {:>8}  {}", "Total", "Self", "Call path" ), false) );
+                let rows = self.tree.visible_rows( &self.expanded );
+                self.tree_list.move_by( 0, rows.len() );
+                self.tree_list.scroll_into_view( body_height );
+                for (row, &(index, depth)) in rows.iter().enumerate().skip( self.tree_list.offset ).take( body_height ) {
+                    let node = &self.tree.nodes[ index ];
+                    let (module, symbol, _) = describe_frame( self.state, self.interner, node.frame.as_ref().unwrap() );
+                    let marker = if node.children.is_empty() {
+                        " "
+                    } else if self.expanded.contains( &index ) {
+                        "-"
+                    } else {
+                        "+"
+                    };
+
+                    let line = format!(
+                        "  {:>7.2}%  {:>7.2}%  {:indent$}{} {} [{}]",
+                        percent( node.total_samples, self.sample_count ),
+                        percent( node.self_samples, self.sample_count ),
+                        "",
+                        marker,
+                        symbol,
+                        module,
+                        indent = depth * 2
+                    );
+                    lines.push( (line, row == self.tree_list.cursor) );
+                }
+            },
+            View::Annotation => {
+                lines.push( (String::new(), false) );
+                self.annotation_list.move_by( 0, self.annotation.len() );
+                self.annotation_list.scroll_into_view( body_height );
+                for (row, line) in self.annotation.iter().enumerate().skip( self.annotation_list.offset ).take( body_height ) {
+                    lines.push( (line.clone(), row == self.annotation_list.cursor) );
+                }
+            }
+        }
+
+        while lines.len() < height.saturating_sub( 1 ) {
+            lines.push( (String::new(), false) );
+        }
+
+        let status = match self.search_input {
+            Some( ref input ) => format!( "/{}", input ),
+            None => self.message.clone()
+        };
+        lines.push( (status, false) );
+
+        for (nth, (line, is_highlighted)) in lines.into_iter().enumerate().take( height ) {
+            queue!( output, cursor::MoveTo( 0, nth as u16 ), terminal::Clear( terminal::ClearType::CurrentLine ) )?;
+            if is_highlighted {
+                queue!( output, SetAttribute( Attribute::Reverse ) )?;
+            }
+            queue!( output, Print( truncate( &line, width ) ) )?;
+            if is_highlighted {
+                queue!( output, SetAttribute( Attribute::Reset ) )?;
+            }
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    fn run< T: Write >( &mut self, output: &mut T ) -> Result< (), Box< dyn Error > > {
+        loop {
+            let (width, height) = terminal::size()?;
+            self.draw( output, width as usize, height as usize )?;
+            match event::read()? {
+                Event::Key( key ) => {
+                    if !self.handle_key( key, (height as usize).saturating_sub( 3 ) ) {
+                        return Ok(());
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn main( args: args::TuiArgs ) -> Result< (), Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( &args.collation_args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let mut interner = StringInterner::new();
+    let mut stacks: HashMap< Vec< FrameKind >, u64 > = HashMap::new();
+    let state = read_data( read_data_args, |event| {
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                if let Some( frames ) = sample.decode( &event.state, &opts, &mut interner ) {
+                    *stacks.entry( frames ).or_insert( 0 ) += 1;
+                }
+            },
+            _ => {}
+        }
+    })?;
+
+    if stacks.is_empty() {
+        return Err( "no samples found".into() );
+    }
+
+    let mut app = App::new( &state, &interner, stacks.into_iter().collect(), args.sort, args.context );
+
+    // The terminal has to be restored before the panic message is printed, otherwise it's lost with the alternate screen.
+    let previous_hook = Arc::new( panic::take_hook() );
+    {
+        let previous_hook = previous_hook.clone();
+        panic::set_hook( Box::new( move |info| {
+            restore_terminal();
+            previous_hook( info );
+        }));
+    }
+
+    let result = {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        TerminalGuard::enter( &mut stdout ).and_then( |_guard| app.run( &mut stdout ) )
+    };
+
+    drop( panic::take_hook() );
+    if let Ok( previous_hook ) = Arc::try_unwrap( previous_hook ) {
+        panic::set_hook( previous_hook );
+    }
+
+    result
+}
+
+/// Switches the terminal into raw mode and the alternate screen, and switches it back when dropped,
+/// so that it's restored even when the UI fails or panics.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter( stdout: &mut impl Write ) -> Result< Self, Box< dyn Error > > {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!( stdout, terminal::EnterAlternateScreen, cursor::Hide )?;
+        Ok( guard )
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop( &mut self ) {
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = execute!( io::stdout(), cursor::Show, terminal::LeaveAlternateScreen );
+    let _ = terminal::disable_raw_mode();
+}
+
+#[test]
+fn test_call_tree() {
+    // Frames are ordered from the leaf to the root.
+    let stacks = vec![
+        (vec![ 'C', 'B', 'A' ], 1),
+        (vec![ 'B', 'A' ], 2),
+        (vec![ 'D', 'E' ], 3),
+        (vec![ 'D', 'B', 'A' ], 4)
+    ];
+
+    let tree = CallTree::new( &stacks );
+    let frames = |rows: Vec< (usize, usize) >| -> Vec< (char, usize, u64) > {
+        rows.into_iter().map( |(index, depth)| (tree.nodes[ index ].frame.unwrap(), depth, tree.nodes[ index ].total_samples) ).collect()
+    };
+
+    assert_eq!( tree.nodes[ 0 ].total_samples, 10 );
+    assert_eq!( frames( tree.visible_rows( &HashSet::new() ) ), vec![ ('A', 0, 7), ('E', 0, 3) ] );
+
+    let a = tree.nodes[ 0 ].children[ 0 ];
+    let b = tree.nodes[ a ].children[ 0 ];
+    let expanded: HashSet< _ > = vec![ a, b ].into_iter().collect();
+    assert_eq!(
+        frames( tree.visible_rows( &expanded ) ),
+        vec![ ('A', 0, 7), ('B', 1, 7), ('D', 2, 4), ('C', 2, 1), ('E', 0, 3) ]
+    );
+    assert_eq!( tree.nodes[ b ].self_samples, 2 );
+
+    let d = tree.find_next( 0, |&frame| frame == 'D' ).unwrap();
+    assert_eq!( tree.nodes[ d ].parent, b );
+    assert_eq!( tree.ancestors( d ), vec![ b, a ] );
+
+    let next_d = tree.find_next( d, |&frame| frame == 'D' ).unwrap();
+    assert_ne!( next_d, d );
+    assert_eq!( tree.find_next( next_d, |&frame| frame == 'D' ), Some( d ) );
+    assert_eq!( tree.find_next( 0, |&frame| frame == 'X' ), None );
+}
+
+#[test]
+fn test_list_state() {
+    let mut list = ListState::default();
+    list.move_by( 5, 10 );
+    list.scroll_into_view( 3 );
+    assert_eq!( (list.cursor, list.offset), (5, 3) );
+
+    list.move_by( 100, 10 );
+    list.scroll_into_view( 3 );
+    assert_eq!( (list.cursor, list.offset), (9, 7) );
+
+    list.move_by( -8, 10 );
+    list.scroll_into_view( 3 );
+    assert_eq!( (list.cursor, list.offset), (1, 1) );
+
+    list.move_by( -100, 10 );
+    assert_eq!( list.cursor, 0 );
+
+    list.move_by( 1, 0 );
+    assert_eq!( list.cursor, 0 );
+}
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use nwind::{BinaryData, UnwindTableIssueKind, verify_unwind_tables};
+
+use crate::args;
+
+pub fn main( args: args::VerifyUnwindTablesArgs ) -> Result< (), Box< dyn Error > > {
+    nwind::set_strict_parsing( args.strict );
+    let initial_malformed_count = nwind::malformed_structure_count();
+
+    let binary = BinaryData::load_from_fs( &args.binary ).map_err( |err| format!( "cannot load {:?}: {}", args.binary, err ) )?;
+    let binary = Arc::new( binary );
+    if binary.eh_frame_range().is_none() && binary.debug_frame_range().is_none() {
+        return Err( format!( "{:?} doesn't have any DWARF unwind tables", args.binary ).into() );
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new( stdout.lock() );
+    let mut unsupported_count = 0;
+    let mut inconsistent_count = 0;
+    let mut malformed_count = 0;
+    let mut result = Ok(());
+    let summary = verify_unwind_tables( &binary, &mut |issue| {
+        match issue.kind {
+            UnwindTableIssueKind::Unsupported => unsupported_count += 1,
+            UnwindTableIssueKind::Inconsistent => inconsistent_count += 1,
+            UnwindTableIssueKind::Malformed => malformed_count += 1
+        }
+
+        if result.is_ok() {
+            result = writeln!( stdout, "{}", issue );
+        }
+    });
+
+    result?;
+    stdout.flush()?;
+
+    let summary = match summary {
+        Some( summary ) => summary,
+        None if args.strict => return Err( format!( "cannot load the unwind tables of {:?}", args.binary ).into() ),
+        None => return Err( format!( "verifying the unwind tables of {} binaries is not supported", binary.architecture() ).into() )
+    };
+
+    let skipped_count = nwind::malformed_structure_count() - initial_malformed_count;
+    if skipped_count > 0 {
+        warn!( "Skipped {} malformed entries which the unwinder will ignore; use `--strict` to see where they are", skipped_count );
+    }
+
+    info!(
+        "Verified {} FDEs with {} rows: {} unsupported, {} inconsistent and {} malformed",
+        summary.fde_count,
+        summary.row_count,
+        unsupported_count,
+        inconsistent_count,
+        malformed_count
+    );
+
+    let issue_count = unsupported_count + inconsistent_count + malformed_count;
+    if issue_count > 0 {
+        return Err( format!( "found {} issue(s) in the unwind tables of {:?}", issue_count, args.binary ).into() );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nwind::DwarfRegs;
+use proc_maps::Region;
+
+use crate::archive::{Packet, StallKind};
+use crate::args;
+use crate::cmd_analyze_core::into_dwarf_regs;
+use crate::cmd_backtrace::{MACHINE, snapshot};
+use crate::profiler::{ProfilingController, Sample};
+use crate::raw_data::CowRawData;
+use crate::utils::{ProcStat, parse_stat, read_string_lossy};
+
+pub(crate) fn read_stats( pid: u32 ) -> HashMap< u32, ProcStat > {
+    let mut stats = HashMap::new();
+    let entries = match std::fs::read_dir( format!( "/proc/{}/task", pid ) ) {
+        Ok( entries ) => entries,
+        Err( _ ) => return stats
+    };
+
+    for entry in entries.flatten() {
+        let tid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok( tid ) => tid,
+            Err( _ ) => continue
+        };
+
+        if let Some( stat ) = read_string_lossy( format!( "/proc/{}/task/{}/stat", pid, tid ) ).ok().as_ref().and_then( |stat| parse_stat( stat ) ) {
+            stats.insert( tid, stat );
+        }
+    }
+
+    stats
+}
+
+pub(crate) fn monotonic_timestamp() -> u64 {
+    let mut timespec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime( libc::CLOCK_MONOTONIC, &mut timespec );
+    }
+
+    timespec.tv_sec as u64 * 1_000_000_000 + timespec.tv_nsec as u64
+}
+
+/// Keeps track of how long a single thread was stuck.
+struct Watch {
+    name: String,
+    backtrace: Vec< String >,
+    same_stack_since: Instant,
+    same_stack_reported: bool,
+    cpu_time: u64,
+    no_progress_since: Instant,
+    no_progress_reported: bool
+}
+
+impl Watch {
+    fn new( now: Instant ) -> Self {
+        Watch {
+            name: String::new(),
+            backtrace: Vec::new(),
+            same_stack_since: now,
+            same_stack_reported: false,
+            cpu_time: 0,
+            no_progress_since: now,
+            no_progress_reported: false
+        }
+    }
+
+    /// Takes a new observation into account and returns the stalls which have just crossed the threshold;
+    /// every stall is reported only once, until the thread gets going again.
+    fn update( &mut self, now: Instant, threshold: Duration, backtrace: &[String], stat: Option< &ProcStat >, stalls: &mut Vec< (StallKind, Duration) > ) {
+        if backtrace.is_empty() || backtrace != self.backtrace.as_slice() {
+            self.backtrace.clear();
+            self.backtrace.extend( backtrace.iter().cloned() );
+            self.same_stack_since = now;
+            self.same_stack_reported = false;
+        } else if !self.same_stack_reported && now - self.same_stack_since >= threshold {
+            self.same_stack_reported = true;
+            stalls.push( (StallKind::SameStack, now - self.same_stack_since) );
+        }
+
+        match stat {
+            Some( stat ) if stat.state == 'R' && stat.cpu_time == self.cpu_time => {
+                if !self.no_progress_reported && now - self.no_progress_since >= threshold {
+                    self.no_progress_reported = true;
+                    stalls.push( (StallKind::NoProgress, now - self.no_progress_since) );
+                }
+            },
+            _ => {
+                self.cpu_time = stat.map( |stat| stat.cpu_time ).unwrap_or( 0 );
+                self.no_progress_since = now;
+                self.no_progress_reported = false;
+            }
+        }
+    }
+}
+
+pub fn main( args: args::WatchdogArgs ) -> Result< (), Box< dyn Error > > {
+    let machine = match MACHINE {
+        Some( machine ) => machine,
+        None => return Err( "the watchdog subcommand is not supported on this architecture".into() )
+    };
+
+    if args.frequency == 0 {
+        return Err( "the frequency must be greater than zero".into() );
+    }
+
+    if !(args.stall_threshold > 0.0) {
+        return Err( "the stall threshold must be greater than zero".into() );
+    }
+
+    let interval = Duration::from_secs( 1 ) / args.frequency;
+    let threshold = Duration::from_secs_f64( args.stall_threshold );
+
+    let mut controller = ProfilingController::new( &args.profiler_args )?;
+    controller.write_packet( Packet::ProfilingFrequency {
+        frequency: args.frequency
+    });
+
+    let pid = controller.pid();
+    let mut watches: HashMap< u32, Watch > = HashMap::new();
+    let mut last_regions: Vec< Region > = Vec::new();
+    let mut dwarf_regs = DwarfRegs::new();
+    let mut backtrace = Vec::new();
+    let mut stalls = Vec::new();
+    let mut stall_count = 0;
+    let mut next_tick = Instant::now();
+
+    info!( "Watching PID {} for stalls longer than {:.1}s...", pid, threshold.as_secs_f64() );
+    while !controller.should_stop() {
+        controller.report_progress();
+        controller.poll_perf_map();
+        controller.poll_memory_stats();
+
+        // This has to be read before the threads are stopped, or else they won't be seen as runnable.
+        let stats = read_stats( pid );
+        let (regions, threads) = match snapshot( pid, args.stack_size ) {
+            Ok( snapshot ) => snapshot,
+            Err( error ) => {
+                if !Path::new( &format!( "/proc/{}", pid ) ).exists() {
+                    info!( "The process has exited; stopping!" );
+                    break;
+                }
+
+                return Err( error );
+            }
+        };
+
+        let timestamp = monotonic_timestamp();
+        let now = Instant::now();
+
+        if regions != last_regions {
+            let mut new_maps = regions.iter().filter( |region| !region.name.is_empty() && !region.is_shared ).cloned().collect();
+            controller.update_maps( &mut new_maps );
+            last_regions = regions;
+        }
+
+        watches.retain( |tid, _| threads.iter().any( |thread| thread.tid == *tid ) );
+        for thread in threads {
+            let stat = stats.get( &thread.tid );
+            let watch = watches.entry( thread.tid ).or_insert_with( || Watch::new( now ) );
+            if watch.name != thread.name {
+                controller.write_packet( Packet::ThreadName {
+                    pid,
+                    tid: thread.tid,
+                    name: thread.name.clone().into_bytes().into()
+                });
+                watch.name = thread.name;
+            }
+
+            into_dwarf_regs( machine, &thread.registers, &mut dwarf_regs );
+            let sample = Sample {
+                timestamp,
+                pid,
+                tid: thread.tid,
+                cpu: stat.map( |stat| stat.cpu ).unwrap_or( 0 ),
+                kernel_backtrace: Cow::Borrowed( &[] ),
+                stack: CowRawData::Owned( thread.stack )
+            };
+
+            backtrace.clear();
+            controller.generate_and_symbolize_sample( &mut dwarf_regs, sample, &mut backtrace );
+
+            watch.update( now, threshold, &backtrace, stat, &mut stalls );
+            for (kind, duration) in stalls.drain( .. ) {
+                stall_count += 1;
+                match kind {
+                    StallKind::SameStack => warn!( "Thread {} (TID {}) seems to be stalled; its stack didn't change for {:.1}s:", watch.name, thread.tid, duration.as_secs_f64() ),
+                    StallKind::NoProgress => warn!( "Thread {} (TID {}) seems to be stalled; it was runnable but didn't get any CPU time for {:.1}s:", watch.name, thread.tid, duration.as_secs_f64() )
+                }
+
+                for (nth_frame, frame) in backtrace.iter().enumerate() {
+                    warn!( "  #{:02} {}", nth_frame, frame );
+                }
+
+                controller.write_packet( Packet::Stall {
+                    timestamp,
+                    pid,
+                    tid: thread.tid,
+                    duration: duration.as_nanos() as u64,
+                    kind,
+                    backtrace: backtrace.clone()
+                });
+            }
+        }
+
+        next_tick += interval;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep( next_tick - now );
+        } else {
+            next_tick = now;
+        }
+    }
+
+    info!( "Detected {} stalls in total", stall_count );
+    Ok(())
+}
+
+#[test]
+fn test_watch() {
+    let threshold = Duration::from_secs( 5 );
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs( secs );
+    let backtrace_a = vec![ "a [app]".to_owned(), "main [app]".to_owned() ];
+    let backtrace_b = vec![ "b [app]".to_owned(), "main [app]".to_owned() ];
+    let sleeping = ProcStat { state: 'S', cpu_time: 10, cpu: 0, rss: 0 };
+    let starved = ProcStat { state: 'R', cpu_time: 10, cpu: 0, rss: 0 };
+
+    let mut watch = Watch::new( start );
+    let mut stalls = Vec::new();
+    watch.update( at( 0 ), threshold, &backtrace_a, Some( &sleeping ), &mut stalls );
+    watch.update( at( 4 ), threshold, &backtrace_a, Some( &sleeping ), &mut stalls );
+    assert_eq!( stalls, vec![] );
+
+    watch.update( at( 5 ), threshold, &backtrace_a, Some( &sleeping ), &mut stalls );
+    assert_eq!( stalls, vec![ (StallKind::SameStack, Duration::from_secs( 5 )) ] );
+
+    stalls.clear();
+    watch.update( at( 6 ), threshold, &backtrace_a, Some( &starved ), &mut stalls );
+    watch.update( at( 9 ), threshold, &backtrace_b, Some( &starved ), &mut stalls );
+    assert_eq!( stalls, vec![] );
+
+    watch.update( at( 10 ), threshold, &backtrace_a, Some( &starved ), &mut stalls );
+    assert_eq!( stalls, vec![ (StallKind::NoProgress, Duration::from_secs( 5 )) ] );
+
+    stalls.clear();
+    watch.update( at( 20 ), threshold, &[], Some( &starved ), &mut stalls );
+    assert_eq!( stalls, vec![] );
+}
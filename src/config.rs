@@ -0,0 +1,177 @@
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// Figures out where the default config file lives; this follows the XDG spec,
+/// so it's usually `~/.config/nperf.toml`.
+fn default_config_path() -> Option< PathBuf > {
+    if let Some( config_home ) = env::var_os( "XDG_CONFIG_HOME" ).filter( |path| !path.is_empty() ) {
+        return Some( PathBuf::from( config_home ).join( "nperf.toml" ) );
+    }
+
+    env::var_os( "HOME" ).filter( |path| !path.is_empty() ).map( |home| PathBuf::from( home ).join( ".config" ).join( "nperf.toml" ) )
+}
+
+/// Removes an option which has to be handled before the arguments are parsed
+/// (e.g. `--config`) and returns its value, if it was there.
+pub fn take_option( args: &mut Vec< OsString >, name: &str ) -> Result< Option< OsString >, Box< dyn Error > > {
+    let prefix = format!( "{}=", name );
+    let mut value = None;
+    let mut index = 1;
+    while index < args.len() {
+        let arg = args[ index ].to_string_lossy().into_owned();
+        if arg == "--" {
+            break;
+        } else if arg == name {
+            if index + 1 >= args.len() {
+                return Err( format!( "the '{}' option requires a value", name ).into() );
+            }
+
+            value = Some( args.remove( index + 1 ) );
+            args.remove( index );
+        } else if arg.starts_with( &prefix ) {
+            value = Some( arg[ prefix.len().. ].into() );
+            args.remove( index );
+        } else {
+            index += 1;
+        }
+    }
+
+    Ok( value )
+}
+
+fn scalar_to_string( key: &str, value: &toml::Value ) -> Result< String, String > {
+    match *value {
+        toml::Value::String( ref value ) => Ok( value.clone() ),
+        toml::Value::Integer( value ) => Ok( value.to_string() ),
+        toml::Value::Float( value ) => Ok( value.to_string() ),
+        toml::Value::Datetime( ref value ) => Ok( value.to_string() ),
+        _ => Err( format!( "unsupported value for '{}'", key ) )
+    }
+}
+
+/// Converts a section of the config file into command line arguments:
+///
+///   * `key = true` becomes `--key`, and `key = false` is skipped,
+///   * `key = [a, b]` becomes `--key=a --key=b`,
+///   * anything else becomes `--key=value`.
+fn section_to_args( section: &toml::value::Table ) -> Result< Vec< OsString >, String > {
+    let mut args = Vec::new();
+    for (key, value) in section {
+        match *value {
+            toml::Value::Boolean( true ) => args.push( format!( "--{}", key ).into() ),
+            toml::Value::Boolean( false ) => {},
+            toml::Value::Array( ref values ) => {
+                for value in values {
+                    args.push( format!( "--{}={}", key, scalar_to_string( key, value )? ).into() );
+                }
+            },
+            _ => args.push( format!( "--{}={}", key, scalar_to_string( key, value )? ).into() )
+        }
+    }
+
+    Ok( args )
+}
+
+/// Turns the section for the given subcommand into command line arguments.
+fn config_to_args( config: &str, subcommand: &str ) -> Result< Vec< OsString >, String > {
+    let config: toml::Value = toml::from_str( config ).map_err( |err| err.to_string() )?;
+    let config = match config.as_table() {
+        Some( config ) => config,
+        None => return Ok( Vec::new() )
+    };
+
+    for (key, value) in config {
+        if !value.is_table() {
+            return Err( format!( "'{}' is not inside of any section; every option must be under a section named after the subcommand it's for, e.g. [record]", key ) );
+        }
+    }
+
+    match config.get( subcommand ).and_then( |section| section.as_table() ) {
+        Some( section ) => section_to_args( section ),
+        None => Ok( Vec::new() )
+    }
+}
+
+/// Loads the defaults from the config file and inserts them right after the subcommand's name,
+/// so that anything which was explicitly given on the command line takes precedence.
+///
+/// The config is read from the file given with `--config`, or from `~/.config/nperf.toml` if it exists.
+pub fn apply_config_file( mut args: Vec< OsString > ) -> Result< Vec< OsString >, Box< dyn Error > > {
+    let explicit_path = take_option( &mut args, "--config" )?.map( PathBuf::from );
+
+    let subcommand = match args.get( 1 ).and_then( |arg| arg.to_str() ) {
+        Some( subcommand ) if !subcommand.starts_with( '-' ) => subcommand.to_owned(),
+        _ => return Ok( args )
+    };
+
+    let (path, config) = match explicit_path {
+        Some( path ) => {
+            let config = fs::read_to_string( &path ).map_err( |err| format!( "cannot read the config file {:?}: {}", path, err ) )?;
+            (path, config)
+        },
+        None => {
+            let path = match default_config_path() {
+                Some( path ) if path.exists() => path,
+                _ => return Ok( args )
+            };
+
+            let config = fs::read_to_string( &path ).map_err( |err| format!( "cannot read the config file {:?}: {}", path, err ) )?;
+            (path, config)
+        }
+    };
+
+    let defaults = config_to_args( &config, &subcommand ).map_err( |err| format!( "failed to load the config file {:?}: {}", path, err ) )?;
+    if !defaults.is_empty() {
+        debug!( "Using defaults from {:?}: {:?}", path, defaults );
+    }
+
+    args.splice( 2..2, defaults );
+    Ok( args )
+}
+
+#[test]
+fn test_config_to_args() {
+    let config = r#"
+        [record]
+        frequency = 500
+        sampler = "timer"
+        offline = true
+        discard-all = false
+
+        [collate]
+        debug-symbols = ["/usr/lib/debug", "/opt/debug"]
+    "#;
+
+    assert_eq!( config_to_args( config, "record" ).unwrap(), vec![
+        OsString::from( "--frequency=500" ),
+        OsString::from( "--offline" ),
+        OsString::from( "--sampler=timer" )
+    ]);
+
+    assert_eq!( config_to_args( config, "collate" ).unwrap(), vec![
+        OsString::from( "--debug-symbols=/usr/lib/debug" ),
+        OsString::from( "--debug-symbols=/opt/debug" )
+    ]);
+
+    assert!( config_to_args( config, "csv" ).unwrap().is_empty() );
+    assert!( config_to_args( "frequency = 500", "record" ).is_err() );
+    assert!( config_to_args( "[record]\nfoo = { bar = 1 }", "record" ).is_err() );
+}
+
+#[test]
+fn test_take_option() {
+    let mut args: Vec< OsString > = vec![ "nperf".into(), "record".into(), "--config".into(), "a.toml".into(), "-p".into(), "1".into() ];
+    assert_eq!( take_option( &mut args, "--config" ).unwrap(), Some( "a.toml".into() ) );
+    assert_eq!( args, vec![ OsString::from( "nperf" ), "record".into(), "-p".into(), "1".into() ] );
+
+    let mut args: Vec< OsString > = vec![ "nperf".into(), "record".into(), "--config=b.toml".into() ];
+    assert_eq!( take_option( &mut args, "--config" ).unwrap(), Some( "b.toml".into() ) );
+    assert_eq!( args, vec![ OsString::from( "nperf" ), "record".into() ] );
+
+    let mut args: Vec< OsString > = vec![ "nperf".into(), "record".into(), "--".into(), "--config".into() ];
+    assert_eq!( take_option( &mut args, "--config" ).unwrap(), None );
+    assert_eq!( args.len(), 4 );
+}
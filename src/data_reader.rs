@@ -1,11 +1,17 @@
 use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::sync::Arc;
 use std::ops::{Range, Index};
 use std::cmp::{max, min};
 use std::fmt;
 use std::error::Error;
+use std::time::UNIX_EPOCH;
 
 use speedy::Endianness;
 use regex::Regex;
@@ -23,16 +29,26 @@ use nwind::{
     LoadHeader,
     BinaryId,
     DebugInfoIndex,
-    LoadHint
+    LoadHint,
+    AddressSpaceSnapshot,
+    read_snapshots,
+    write_snapshots
 };
 
 use crate::args::{self, Granularity};
-use crate::archive::{Packet, Inode, Bitness, UserFrame, ArchiveReader};
+use crate::archive::{Packet, Inode, Bitness, UserFrame, UnwindFailure, StallKind, PythonFrame};
+use crate::merge::{MergedReader, is_stdin};
 use crate::utils::StableIndex;
 use crate::kallsyms::{self, KernelSymbol};
+use crate::anonymize::{Anonymization, Anonymizer, NameKind, anonymize_if};
 use crate::interner::{StringId, StringInterner};
 
 use crate::stack_reader::StackReader;
+use crate::python::EVAL_FUNCTION;
+use crate::perf_map::JitSymbols;
+use crate::symbol_resolver::{SymbolResolver, SymbolResolvers, SymbolQuery, ResolvedFrame, RemoteResolver, resolve_elf};
+use crate::symbol_cache::SymbolCache;
+use crate::unwind_check::{CheckedSample, UnwindChecker};
 
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub(crate) enum FrameKind {
@@ -45,7 +61,9 @@ pub(crate) enum FrameKind {
         binary_id: BinaryId,
         is_inline: bool,
         symbol: StringId,
-        address: u64
+        address: u64,
+        file: Option< StringId >,
+        line: Option< u64 >
     },
     UserByFunction {
         binary_id: BinaryId,
@@ -59,17 +77,31 @@ pub(crate) enum FrameKind {
         file: StringId,
         line: u64
     },
+    UnwindFailure( UnwindFailure ),
+    Cgroup( Option< StringId > ),
+    Module {
+        name: StringId,
+        is_kernel: bool
+    },
     Kernel( u64 ),
-    KernelSymbol( usize )
+    KernelSymbol( usize ),
+    Python {
+        symbol: StringId,
+        file: StringId,
+        line: Option< u64 >
+    },
+    Jit( StringId )
 }
 
 pub(crate) struct Process {
     pid: u32,
     executable: String,
+    cgroup: Option< String >,
     memory_regions: RangeMap< Region >,
     base_address_for_binary: HashMap< BinaryId, u64 >,
     address_space: Box< dyn IAddressSpace >,
-    address_space_needs_reload: bool
+    address_space_needs_reload: bool,
+    jit_symbols: JitSymbols
 }
 
 pub(crate) struct FdeHints {
@@ -87,6 +119,34 @@ impl Process {
         &self.executable
     }
 
+    /// The process' cgroup (v2) path, if it was recorded.
+    pub(crate) fn cgroup( &self ) -> Option< &str > {
+        self.cgroup.as_ref().map( |cgroup| cgroup.as_str() )
+    }
+
+    pub(crate) fn address_space( &self ) -> &dyn IAddressSpace {
+        &*self.address_space
+    }
+
+    pub(crate) fn memory_region( &self, address: u64 ) -> Option< &Region > {
+        self.memory_regions.get_value( address )
+    }
+
+    /// Returns the innermost source location of the instruction at a given absolute `address`.
+    pub(crate) fn source_location( &self, address: u64 ) -> Option< (String, u64) > {
+        let mut location = None;
+        self.address_space.decode_symbol_while( address, &mut |frame| {
+            if let (Some( file ), Some( line )) = (frame.file.take(), frame.line) {
+                location = Some( (file, line) );
+                return false;
+            }
+
+            true
+        });
+
+        location
+    }
+
     fn reload_if_necessary( &mut self, debug_info_index: &mut DebugInfoIndex, binary_by_id: &mut HashMap< BinaryId, Binary >, fde_hints: &FdeHints ) {
         if !self.address_space_needs_reload {
             return;
@@ -142,8 +202,49 @@ impl Process {
     }
 }
 
+/// Creates an empty address space for a given architecture, as named by `Architecture::NAME`.
+pub(crate) fn new_address_space( architecture: &str ) -> Option< Box< dyn IAddressSpace > > {
+    let address_space: Box< dyn IAddressSpace > = match architecture {
+        arch::arm::Arch::NAME => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
+        arch::amd64::Arch::NAME => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
+        arch::mips64::Arch::NAME => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
+        arch::aarch64::Arch::NAME => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
+        _ => return None
+    };
+
+    Some( address_space )
+}
+
+/// How a local file was matched with a binary from the profiling data.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum MatchStrategy {
+    BuildId,
+    Inode,
+    SizeAndMtime,
+    Debuglink,
+    PathOnly
+}
+
+impl MatchStrategy {
+    pub(crate) fn name( self ) -> &'static str {
+        match self {
+            MatchStrategy::BuildId => "build ID",
+            MatchStrategy::Inode => "inode",
+            MatchStrategy::SizeAndMtime => "path, size and modification time",
+            MatchStrategy::Debuglink => "debuglink",
+            MatchStrategy::PathOnly => "path only (unverified)"
+        }
+    }
+}
+
 pub(crate) struct Binary {
     path: String,
+    anonymized_path: Option< String >,
+    inode: Inode,
+    file_size: u64,
+    mtime: u64,
+    match_strategy: Option< MatchStrategy >,
+    is_local_file_rejected: bool,
     basename: String,
     string_tables: Arc< BinaryChunks >,
     symbol_table_count: u16,
@@ -166,17 +267,100 @@ impl Binary {
         self.debuglink.as_ref().map( |debuglink| debuglink.as_slice() )
     }
 
-    fn build_id( &self ) -> Option< &[u8] > {
+    /// The binary's original path, or its anonymized replacement.
+    pub(crate) fn path( &self ) -> &str {
+        self.anonymized_path.as_ref().unwrap_or( &self.path )
+    }
+
+    pub(crate) fn build_id( &self ) -> Option< &[u8] > {
         self.build_id.as_ref().map( |build_id| build_id.as_slice() )
     }
 
+    pub(crate) fn load_headers( &self ) -> &[LoadHeader] {
+        &self.load_headers
+    }
+
+    /// Whether the binary itself was embedded in the profiling data.
+    pub(crate) fn is_embedded( &self ) -> bool {
+        self.data.is_some()
+    }
+
+    /// Checks whether a local file is the same binary as the one which was profiled,
+    /// trying the strategies from the most to the least reliable one; the inodes alone can't be
+    /// relied upon since they're not stable on e.g. overlayfs, or after the binary was upgraded in place.
+    pub(crate) fn match_local_file( &self, data: &BinaryData ) -> Result< MatchStrategy, &'static str > {
+        if let (Some( expected ), Some( actual )) = (self.build_id(), data.build_id()) {
+            if expected != actual {
+                return Err( "its build ID doesn't match" );
+            }
+
+            return Ok( MatchStrategy::BuildId );
+        }
+
+        if !self.inode.is_invalid() && data.inode() == Some( self.inode ) {
+            return Ok( MatchStrategy::Inode );
+        }
+
+        if self.file_size != 0 && self.mtime != 0 {
+            let mtime = fs::metadata( data.name() ).and_then( |metadata| metadata.modified() ).ok()
+                .and_then( |mtime| mtime.duration_since( UNIX_EPOCH ).ok() )
+                .map( |mtime| mtime.as_secs() )
+                .unwrap_or( 0 );
+            if data.as_bytes().len() as u64 != self.file_size || mtime != self.mtime {
+                return Err( "its size or modification time doesn't match" );
+            }
+
+            return Ok( MatchStrategy::SizeAndMtime );
+        }
+
+        Ok( MatchStrategy::PathOnly )
+    }
+
+    /// Returns the contents of the binary, either as embedded in the profiling data
+    /// or as found on the filesystem if it matches the one which was profiled.
+    pub(crate) fn load_contents( &self ) -> Option< Arc< BinaryData > > {
+        if let Some( ref data ) = self.data {
+            return Some( data.clone() );
+        }
+
+        let data = match BinaryData::load_from_fs( &self.path ) {
+            Ok( data ) => data,
+            Err( error ) => {
+                debug!( "Cannot load '{}': {}", self.path, error );
+                return None;
+            }
+        };
+
+        if let Err( reason ) = self.match_local_file( &data ) {
+            warn!( "'{}' is not the same binary as the one which was profiled: {}", self.path, reason );
+            return None;
+        }
+
+        Some( Arc::new( data ) )
+    }
+
     fn load_debug_info( &mut self, debug_info_index: &mut DebugInfoIndex ) {
-        if self.debug_data.is_some() {
+        if self.debug_data.is_some() || self.is_local_file_rejected {
             return;
         }
 
         if let Some( debug_data ) = debug_info_index.get( &self.path, self.debuglink(), self.build_id() ) {
-            debug!( "Found debug symbols for '{}': '{}'", self.path, debug_data.name() );
+            let is_separate_debug_file = self.build_id().is_none() && self.debuglink().map( |debuglink| get_basename( debug_data.name() ).as_bytes() == debuglink ).unwrap_or( false );
+            let strategy = if is_separate_debug_file {
+                MatchStrategy::Debuglink
+            } else {
+                match self.match_local_file( &debug_data ) {
+                    Ok( strategy ) => strategy,
+                    Err( reason ) => {
+                        warn!( "Ignoring '{}' since it's not the same binary as '{}' which was profiled: {}", debug_data.name(), self.path, reason );
+                        self.is_local_file_rejected = true;
+                        return;
+                    }
+                }
+            };
+
+            debug!( "Found debug symbols for '{}': '{}' (matched by {})", self.path, debug_data.name(), strategy.name() );
+            self.match_strategy = Some( strategy );
             self.debug_data = Some( debug_data.clone() );
         }
     }
@@ -229,38 +413,306 @@ impl Index< Range< u64 > > for BinaryChunks {
 
 unsafe impl StableIndex for BinaryChunks {}
 
-fn get_basename( path: &str ) -> &str {
+pub(crate) fn get_basename( path: &str ) -> &str {
     &path[ path.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ]
 }
 
 pub(crate) struct ReadDataArgs< 'a > {
-    input_path: &'a OsStr,
+    input_paths: Vec< &'a OsStr >,
+    /// A recording which is already in memory; used instead of the `input_paths`.
+    input_data: Option< Arc< [u8] > >,
     debug_symbols: Vec< &'a OsStr >,
+    sysroot: Option< &'a OsStr >,
+    kallsyms: Option< &'a OsStr >,
+    address_space_cache: Option< &'a OsStr >,
+    symbol_cache: Option< &'a OsStr >,
+    binary_path_mappings: Vec< (&'a str, &'a str) >,
+    source_path_mappings: Vec< (&'a str, &'a str) >,
     force_stack_size: Option< u32 >,
     only_sample: Option< u64 >,
     without_kernel_callstacks: bool,
+    anonymization: Option< Anonymization >,
     fde_hints: FdeHints,
+    strict: bool,
+    cross_check_unwinding: Option< &'a OsStr >,
     from: Option< TimestampBound >,
-    to: Option< TimestampBound >
+    to: Option< TimestampBound >,
+    symbol_resolvers: SymbolResolvers
 }
 
-#[derive(Copy, Clone)]
-enum TimestampBound {
+impl< 'a > ReadDataArgs< 'a > {
+    /// Arguments for reading a single input file, without any of the filtering and remapping which the collation commands support.
+    pub(crate) fn new( input_path: &'a OsStr, debug_symbols: Vec< &'a OsStr > ) -> Self {
+        ReadDataArgs {
+            input_paths: vec![ input_path ],
+            input_data: None,
+            debug_symbols,
+            sysroot: None,
+            kallsyms: None,
+            address_space_cache: None,
+            symbol_cache: None,
+            binary_path_mappings: Vec::new(),
+            source_path_mappings: Vec::new(),
+            force_stack_size: None,
+            only_sample: None,
+            without_kernel_callstacks: false,
+            anonymization: None,
+            fde_hints: FdeHints {
+                use_eh_frame_hdr: false,
+                load_eh_frame: LoadHint::Always,
+                load_debug_frame: true
+            },
+            strict: false,
+            cross_check_unwinding: None,
+            from: None,
+            to: None,
+            symbol_resolvers: SymbolResolvers::default()
+        }
+    }
+
+    /// Arguments for reading a recording which is already in memory, e.g. when there's no filesystem to read it from.
+    pub(crate) fn from_memory( data: Arc< [u8] > ) -> Self {
+        ReadDataArgs {
+            input_paths: Vec::new(),
+            input_data: Some( data ),
+            .. ReadDataArgs::new( OsStr::new( "" ), Vec::new() )
+        }
+    }
+}
+
+/// Describes the profiling data and the options used to find the binaries, so that an address space cache
+/// which was saved for something else isn't used; returns `None` if the input can't be described (e.g. it's stdin).
+fn address_space_cache_fingerprint( args: &ReadDataArgs ) -> Option< String > {
+    fn describe( fingerprint: &mut String, kind: &str, path: &OsStr ) {
+        let path = fs::canonicalize( path ).unwrap_or_else( |_| PathBuf::from( path ) );
+        let metadata = fs::metadata( &path ).ok();
+        let size = metadata.as_ref().map( |metadata| metadata.len() );
+        let modified = metadata.as_ref()
+            .and_then( |metadata| metadata.modified().ok() )
+            .and_then( |modified| modified.duration_since( UNIX_EPOCH ).ok() )
+            .map( |modified| modified.as_nanos() );
+
+        fingerprint.push_str( &format!( "{} {:?} {:?} {:?}\n", kind, path, size, modified ) );
+    }
+
+    if args.input_data.is_some() || args.input_paths.iter().any( |path| is_stdin( path ) ) {
+        return None;
+    }
+
+    let mut fingerprint = String::new();
+    for path in &args.input_paths {
+        describe( &mut fingerprint, "input", path );
+    }
+
+    for path in &args.debug_symbols {
+        describe( &mut fingerprint, "debug-symbols", path );
+    }
+
+    if let Some( path ) = args.sysroot {
+        describe( &mut fingerprint, "sysroot", path );
+    }
+
+    if let Some( path ) = args.kallsyms {
+        describe( &mut fingerprint, "kallsyms", path );
+    }
+
+    for (from, to) in &args.binary_path_mappings {
+        fingerprint.push_str( &format!( "binary-path-map {:?} {:?}\n", from, to ) );
+    }
+
+    Some( fingerprint )
+}
+
+/// Reads the address spaces saved by a previous run over the same profiling data, keyed by PID;
+/// returns `None` if there's nothing saved yet, or if what's saved was made for something else.
+fn load_address_space_cache( path: &OsStr, fingerprint: &str ) -> Result< Option< HashMap< u32, AddressSpaceSnapshot > >, Box< dyn Error > > {
+    let bytes = match fs::read( path ) {
+        Ok( bytes ) => bytes,
+        Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Ok( None ),
+        Err( error ) => return Err( format!( "cannot read {:?}: {}", path, error ).into() )
+    };
+
+    // The cache starts with the length of the fingerprint of what it was made for, followed by the fingerprint itself.
+    let saved_fingerprint = bytes.get( ..4 )
+        .map( |length| u32::from_le_bytes( [length[ 0 ], length[ 1 ], length[ 2 ], length[ 3 ]] ) as usize )
+        .and_then( |length| bytes.get( 4..4 + length ) );
+
+    if saved_fingerprint != Some( fingerprint.as_bytes() ) {
+        warn!( "The address space cache in {:?} was made for different profiling data or binaries; it will be rewritten", path );
+        return Ok( None );
+    }
+
+    let snapshots = match read_snapshots( &bytes[ 4 + fingerprint.len().. ] ) {
+        Ok( snapshots ) => snapshots,
+        Err( error ) => {
+            warn!( "Cannot load the address space cache from {:?}: {}; it will be rewritten", path, error );
+            return Ok( None );
+        }
+    };
+
+    info!( "Loaded {} address space(s) from {:?}", snapshots.len(), path );
+    Ok( Some( snapshots.into_iter().collect() ) )
+}
+
+fn save_address_space_cache( path: &OsStr, fingerprint: &str, processes: &[Process] ) -> Result< (), Box< dyn Error > > {
+    let snapshots: Vec< _ > = processes.iter().map( |process| (process.pid, process.address_space.snapshot()) ).collect();
+    let mut fp = BufWriter::new( fs::File::create( path ).map_err( |error| format!( "cannot create {:?}: {}", path, error ) )? );
+    let write_fingerprint = |fp: &mut BufWriter< fs::File >| -> io::Result< () > {
+        fp.write_all( &(fingerprint.len() as u32).to_le_bytes() )?;
+        fp.write_all( fingerprint.as_bytes() )
+    };
+
+    write_fingerprint( &mut fp )
+        .and_then( |_| write_snapshots( &snapshots, &mut fp ) )
+        .map_err( |error| format!( "cannot write the address space cache to {:?}: {}", path, error ) )?;
+
+    info!( "Saved {} address space(s) to {:?}", snapshots.len(), path );
+    Ok(())
+}
+
+fn open_input( input_paths: &[&OsStr], input_data: Option< &Arc< [u8] > > ) -> Result< MergedReader, Box< dyn Error > > {
+    match input_data {
+        Some( data ) => MergedReader::from_memory( data.clone() ),
+        None => MergedReader::open( input_paths )
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum TimestampBound {
     Relative( f64 ),
-    RelativePercent( u8 )
+    RelativePercent( u8 ),
+    Absolute( u64 )
 }
 
-fn parse_timestamp_bound( timestamp: impl AsRef< str > ) -> TimestampBound {
-    let timestamp = timestamp.as_ref();
-    if timestamp.ends_with( "%" ) {
-        let timestamp: u8 = timestamp[ ..timestamp.len() - 1 ].parse().unwrap();
-        TimestampBound::RelativePercent( timestamp )
+fn parse_absolute_timestamp( timestamp: &str ) -> Option< u64 > {
+    let (secs, nsecs) = match timestamp.find( '.' ) {
+        Some( index ) => (&timestamp[ ..index ], &timestamp[ index + 1.. ]),
+        None => (timestamp, "")
+    };
+
+    if nsecs.len() > 9 {
+        return None;
+    }
+
+    let secs: u64 = secs.parse().ok()?;
+    let nsecs: u64 = if nsecs.is_empty() {
+        0
     } else {
-        let timestamp: f64 = timestamp.parse().unwrap();
-        TimestampBound::Relative( timestamp )
+        nsecs.parse::< u64 >().ok()? * 10_u64.pow( 9 - nsecs.len() as u32 )
+    };
+
+    Some( secs * 1_000_000_000 + nsecs )
+}
+
+fn parse_timestamp_bound( timestamp: impl AsRef< str > ) -> Result< TimestampBound, String > {
+    let timestamp = timestamp.as_ref().trim();
+    let error = || format!( "invalid timestamp: '{}'", timestamp );
+
+    if timestamp.ends_with( "%" ) {
+        let timestamp: u8 = timestamp[ ..timestamp.len() - 1 ].parse().map_err( |_| error() )?;
+        if timestamp > 100 {
+            return Err( error() );
+        }
+
+        return Ok( TimestampBound::RelativePercent( timestamp ) );
+    }
+
+    if timestamp.starts_with( "@" ) {
+        // An absolute timestamp in the same format as used by `collate --format perf-like`.
+        return parse_absolute_timestamp( &timestamp[ 1.. ] ).map( TimestampBound::Absolute ).ok_or_else( error );
+    }
+
+    let (value, multiplier) =
+        if timestamp.ends_with( "ms" ) {
+            (&timestamp[ ..timestamp.len() - 2 ], 0.001)
+        } else if timestamp.ends_with( "us" ) {
+            (&timestamp[ ..timestamp.len() - 2 ], 0.000_001)
+        } else if timestamp.ends_with( "ns" ) {
+            (&timestamp[ ..timestamp.len() - 2 ], 0.000_000_001)
+        } else if timestamp.ends_with( "s" ) {
+            (&timestamp[ ..timestamp.len() - 1 ], 1.0)
+        } else if timestamp.ends_with( "m" ) {
+            (&timestamp[ ..timestamp.len() - 1 ], 60.0)
+        } else {
+            (timestamp, 1.0)
+        };
+
+    let value: f64 = value.parse().map_err( |_| error() )?;
+    if value < 0.0 {
+        return Err( error() );
+    }
+
+    Ok( TimestampBound::Relative( value * multiplier ) )
+}
+
+/// Checks whether the `timestamp` lies within the given bounds, where the relative
+/// bounds are measured from the `first_timestamp`.
+pub(crate) fn is_in_bounds(
+    from: Option< TimestampBound >,
+    to: Option< TimestampBound >,
+    first_timestamp: u64,
+    last_timestamp: Option< u64 >,
+    timestamp: u64
+) -> bool {
+    let relative = timestamp - first_timestamp;
+    let relative_s = to_s( relative );
+    let relative_p = last_timestamp.map( |last_timestamp|
+        (relative_s / to_s( last_timestamp - first_timestamp ) * 100.0) as u8
+    );
+
+    if let Some( from ) = from {
+        match from {
+            TimestampBound::Relative( bound ) => if relative_s < bound { return false },
+            TimestampBound::RelativePercent( bound ) => if relative_p.unwrap() < bound { return false },
+            TimestampBound::Absolute( bound ) => if timestamp < bound { return false }
+        }
+    }
+
+    if let Some( to ) = to {
+        match to {
+            TimestampBound::Relative( bound ) => if relative_s > bound { return false },
+            TimestampBound::RelativePercent( bound ) => if relative_p.unwrap() > bound { return false },
+            TimestampBound::Absolute( bound ) => if timestamp > bound { return false }
+        }
+    }
+
+    true
+}
+
+/// Parses the `--from`, `--to` and `--range` arguments.
+pub(crate) fn parse_timestamp_bounds(
+    from: Option< &String >,
+    to: Option< &String >,
+    range: Option< &String >
+) -> Result< (Option< TimestampBound >, Option< TimestampBound >), String > {
+    let (mut from_bound, mut to_bound) = match range {
+        Some( range ) => parse_timestamp_range( range )?,
+        None => (None, None)
+    };
+
+    if let Some( timestamp ) = from {
+        from_bound = Some( parse_timestamp_bound( timestamp )? );
+    }
+
+    if let Some( timestamp ) = to {
+        to_bound = Some( parse_timestamp_bound( timestamp )? );
     }
+
+    Ok( (from_bound, to_bound) )
+}
+
+fn parse_timestamp_range( range: &str ) -> Result< (Option< TimestampBound >, Option< TimestampBound >), String > {
+    let index = range.find( ".." ).ok_or_else( || format!( "invalid time range: '{}'", range ) )?;
+    let from = &range[ ..index ];
+    let to = &range[ index + 2.. ];
+    let from = if from.is_empty() { None } else { Some( parse_timestamp_bound( from )? ) };
+    let to = if to.is_empty() { None } else { Some( parse_timestamp_bound( to )? ) };
+    Ok( (from, to) )
 }
 
+/// Below this fraction of the time the sampled event was running the results are flagged as unreliable.
+pub(crate) const HEAVY_MULTIPLEXING_THRESHOLD: f64 = 0.5;
+
 pub(crate) struct State {
     kallsyms: RangeMap< KernelSymbol >,
     process_index_by_pid: HashMap< u32, usize >,
@@ -269,11 +721,27 @@ pub(crate) struct State {
     binary_by_id: HashMap< BinaryId, Binary >,
     unfiltered_first_timestamp: Option< u64 >,
     cpu_count: u32,
-    frequency: Option< u32 >
+    frequency: Option< u32 >,
+    multiplexing: Option< (u64, u64) >,
+    // By how much the samples are scaled up to compensate for the multiplexing; this is only known
+    // at the end of the recording, so it's taken from the first pass over the data, if there was one.
+    multiplexing_weight: f64,
+    round_robin_cpus: Option< (u32, u32) >,
+    debug_info_index: DebugInfoIndex,
+    source_path_mappings: Vec< (PathBuf, PathBuf) >,
+    anonymizer: Option< Anonymizer >,
+    python_functions: HashMap< (u32, u64), PythonFunction >,
+    symbol_resolvers: SymbolResolvers,
+    symbol_cache: Option< SymbolCache >
+}
+
+struct PythonFunction {
+    name: String,
+    file: String
 }
 
 impl State {
-    fn get_kernel_symbol( &self, symbol_index: usize ) -> &KernelSymbol {
+    pub(crate) fn get_kernel_symbol( &self, symbol_index: usize ) -> &KernelSymbol {
         self.kallsyms.get_value_by_index( symbol_index ).unwrap()
     }
 
@@ -285,6 +753,32 @@ impl State {
         self.binary_by_id.get( binary_id ).unwrap()
     }
 
+    pub(crate) fn binaries( &self ) -> impl Iterator< Item = (&BinaryId, &Binary) > {
+        self.binary_by_id.iter()
+    }
+
+    /// Looks up the local copies of a given binary and of its external debug info, if any.
+    pub(crate) fn find_local_binary( &mut self, binary_id: &BinaryId ) -> (Option< Arc< BinaryData > >, Option< Arc< BinaryData > >) {
+        let binary = match self.binary_by_id.get( binary_id ) {
+            Some( binary ) => binary,
+            None => return (None, None)
+        };
+
+        self.debug_info_index.get_pair( &binary.path, binary.debuglink(), binary.build_id() )
+    }
+
+    /// Rewrites the original path of a source file according to the `--path-map` rules, or anonymizes it.
+    pub(crate) fn map_source_path< 'a >( &self, path: &'a str ) -> Cow< 'a, str > {
+        match self.anonymizer {
+            Some( ref anonymizer ) => anonymizer.anonymize( NameKind::SourceFile, path ).into(),
+            None => map_path( &self.source_path_mappings, path )
+        }
+    }
+
+    pub(crate) fn anonymizer( &self ) -> Option< &Anonymizer > {
+        self.anonymizer.as_ref()
+    }
+
     pub(crate) fn get_thread_name( &self, tid: u32 ) -> Option< &str > {
         self.thread_names.get( &tid ).map( |str| str.as_str() )
     }
@@ -293,6 +787,21 @@ impl State {
         self.process_index_by_pid.get( &pid ).map( |&index| &self.processes[ index ] )
     }
 
+    /// Returns a given process with its address space brought up-to-date with every memory mapping which was read so far;
+    /// the address spaces are otherwise only reloaded when a sample from a given process is encountered.
+    pub(crate) fn get_reloaded_process( &mut self, pid: u32 ) -> Option< &Process > {
+        let index = *self.process_index_by_pid.get( &pid )?;
+        // This is only used for symbolication, so there's no need to load any of the unwind tables.
+        let fde_hints = FdeHints {
+            use_eh_frame_hdr: false,
+            load_eh_frame: LoadHint::Never,
+            load_debug_frame: false
+        };
+
+        self.processes[ index ].reload_if_necessary( &mut self.debug_info_index, &mut self.binary_by_id, &fde_hints );
+        Some( &self.processes[ index ] )
+    }
+
     pub(crate) fn cpu_count( &self ) -> u32 {
         self.cpu_count
     }
@@ -301,6 +810,30 @@ impl State {
         self.frequency.clone()
     }
 
+    /// By how much every sample should be weighted to compensate for only a part of the CPUs being sampled at a time.
+    pub(crate) fn round_robin_weight( &self ) -> f64 {
+        match self.round_robin_cpus {
+            Some( (active_cpus, total_cpus) ) if active_cpus > 0 => total_cpus as f64 / active_cpus as f64,
+            _ => 1.0
+        }
+    }
+
+    /// By how much every sample should be weighted to compensate for both the round-robin sampling
+    /// of the CPUs and the multiplexing of the sampled event with other events.
+    pub(crate) fn sample_weight( &self ) -> f64 {
+        self.round_robin_weight() * self.multiplexing_weight
+    }
+
+    /// The fraction of the time the sampled event was actually running, if it was recorded.
+    pub(crate) fn multiplexing_ratio( &self ) -> Option< f64 > {
+        let (time_enabled, time_running) = self.multiplexing?;
+        if time_enabled == 0 {
+            return None;
+        }
+
+        Some( time_running as f64 / time_enabled as f64 )
+    }
+
     pub(crate) fn unfiltered_first_timestamp( &self ) -> Option< u64 > {
         self.unfiltered_first_timestamp.clone()
     }
@@ -314,6 +847,134 @@ fn to_binary_id( inode: Inode, name: &str ) -> BinaryId {
     }
 }
 
+fn push_symbol_frame(
+    state: &State,
+    opts: &DecodeOpts,
+    interner: &mut StringInterner,
+    output: &mut Vec< FrameKind >,
+    binary_id: &BinaryId,
+    is_inline: bool,
+    name: &str,
+    file: Option< &str >,
+    line: Option< u64 >,
+    address: u64
+) {
+    let symbol = interner.get_or_intern( anonymize_if( state.anonymizer.as_ref(), NameKind::Symbol, name ) );
+    if opts.granularity == Granularity::Line {
+        if let (Some( file ), Some( line )) = (file, line) {
+            output.push( FrameKind::UserByLine {
+                binary_id: binary_id.clone(),
+                is_inline,
+                symbol,
+                file: interner.get_or_intern( state.map_source_path( file ) ),
+                line
+            });
+            return;
+        }
+    }
+
+    if opts.granularity == Granularity::Line || opts.granularity == Granularity::Function {
+        output.push( FrameKind::UserByFunction {
+            binary_id: binary_id.clone(),
+            is_inline,
+            symbol
+        });
+    } else {
+        output.push( FrameKind::UserByAddress {
+            binary_id: binary_id.clone(),
+            is_inline,
+            symbol,
+            address,
+            file: file.map( |file| interner.get_or_intern( state.map_source_path( file ) ) ),
+            line
+        });
+    }
+}
+
+/// Builds the query about an address within a given region for the symbol resolvers.
+fn symbol_query< 'a >( state: &'a State, region: &Region, address: u64 ) -> Option< SymbolQuery< 'a > > {
+    let binary = state.binary_by_id.get( &region.into() )?;
+    let file_offset = address - region.start + region.file_offset;
+    let relative_address = binary.load_headers().iter()
+        .find( |header| file_offset >= header.file_offset && file_offset < header.file_offset + header.file_size )
+        .map( |header| header.address + (file_offset - header.file_offset) )
+        .unwrap_or( file_offset );
+
+    let load_address = binary.load_headers().iter()
+        .filter( |header| header.file_offset == 0 )
+        .map( |header| header.address )
+        .min()
+        .unwrap_or( 0 );
+
+    Some( SymbolQuery {
+        path: &binary.path,
+        build_id: binary.build_id(),
+        address: relative_address,
+        load_address
+    })
+}
+
+fn resolve_query( resolvers: &[Box< dyn SymbolResolver >], query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > > {
+    resolvers.iter().find_map( |resolver| resolver.resolve( query ) ).filter( |frames| !frames.is_empty() )
+}
+
+/// Asks the given resolvers about an address within a given region; the first one which knows it wins.
+fn resolve_with( resolvers: &[Box< dyn SymbolResolver >], state: &State, region: &Region, address: u64 ) -> Option< Vec< ResolvedFrame > > {
+    if resolvers.is_empty() {
+        return None;
+    }
+
+    resolve_query( resolvers, &symbol_query( state, region, address )? )
+}
+
+/// Looks up an address of a binary with a build ID in the persistent symbol cache; if it's not there
+/// it's resolved by every resolver in turn, and the result is added to the cache.
+fn resolve_cached( cache: &SymbolCache, state: &State, address_space: &dyn IAddressSpace, region: &Region, address: u64 ) -> Option< Vec< ResolvedFrame > > {
+    let query = symbol_query( state, region, address )?;
+    let build_id = query.build_id?;
+    if let Some( frames ) = cache.get( build_id, query.address ) {
+        return Some( frames );
+    }
+
+    let frames = resolve_query( &state.symbol_resolvers.before, &query )
+        .or_else( || resolve_elf( address_space, address ) )
+        .or_else( || resolve_query( &state.symbol_resolvers.after, &query ) )?;
+
+    cache.insert( build_id, query.address, frames.clone() );
+    Some( frames )
+}
+
+/// Returns `false` if the sample should be omitted.
+fn push_resolved_frames(
+    state: &State,
+    opts: &DecodeOpts,
+    interner: &mut StringInterner,
+    output: &mut Option< &mut Vec< FrameKind > >,
+    matched: &mut bool,
+    binary_id: &BinaryId,
+    frames: &[ResolvedFrame],
+    address: u64
+) -> bool {
+    for (index, frame) in frames.iter().enumerate() {
+        if opts.filter.is_omitted( &frame.name ) {
+            return false;
+        }
+
+        *matched = *matched || opts.filter.is_matching( &frame.name );
+        if let Some( ref mut output ) = output {
+            let is_inline = index + 1 != frames.len();
+            push_symbol_frame( state, opts, interner, output, binary_id, is_inline, &frame.name, frame.file.as_deref(), frame.line, address );
+        }
+    }
+
+    true
+}
+
+/// Turns the summed up weights of the samples into the sample counts.
+pub(crate) fn round_weights< K: Eq + Hash >( weights: HashMap< K, f64 > ) -> HashMap< K, u64 > {
+    weights.into_iter().map( |(key, weight)| (key, weight.round() as u64) ).collect()
+}
+
 pub(crate) fn to_s( timestamp: u64 ) -> f64 {
     timestamp as f64 / 1_000_000_000.0
 }
@@ -324,7 +985,13 @@ pub(crate) struct EventSample< 'a > {
     pub tid: u32,
     pub cpu: u32,
     pub user_backtrace: &'a [UserFrame],
-    pub kernel_backtrace: &'a [u64]
+    pub kernel_backtrace: &'a [u64],
+    pub unwind_failure: Option< UnwindFailure >,
+    pub python_backtrace: &'a [PythonFrame],
+    /// How many samples this one should count as, e.g. to compensate for only a part
+    /// of the CPUs being sampled at a time or for the multiplexing of the sampled event;
+    /// the counts should be rounded only once summed up.
+    pub weight: f64
 }
 
 impl< 'a > EventSample< 'a > {
@@ -350,6 +1017,19 @@ impl< 'a > EventSample< 'a > {
         interner: &mut StringInterner,
         mut output: Option< &mut Vec< FrameKind > >
     ) -> bool {
+        let initial_length = output.as_ref().map( |output| output.len() ).unwrap_or( 0 );
+        // Whether the sample should be kept when `--only-matching` is used.
+        let mut matched = opts.filter.only_matching.is_none();
+        for &addr in self.kernel_backtrace.iter() {
+            if let Some( symbol ) = state.kallsyms.get_value( addr ) {
+                if opts.filter.is_omitted( &symbol.name ) {
+                    return false;
+                }
+
+                matched = matched || opts.filter.is_matching( &symbol.name );
+            }
+        }
+
         if let Some( ref mut output ) = output {
             let mut length = 0;
             length += self.user_backtrace.len();
@@ -362,6 +1042,12 @@ impl< 'a > EventSample< 'a > {
             if opts.emit_process_frames {
                 length += 1;
             }
+            if opts.emit_unwind_failure_frames {
+                length += 1;
+            }
+            if opts.emit_cgroup_frames {
+                length += 1;
+            }
             output.reserve( length );
 
             if opts.emit_kernel_frames {
@@ -375,61 +1061,124 @@ impl< 'a > EventSample< 'a > {
             }
         }
 
+        let mut python_backtrace = self.python_backtrace;
         for (nth_frame, user_frame) in self.user_backtrace.iter().enumerate() {
             let default = FrameKind::User( user_frame.initial_address.unwrap_or( user_frame.address ) );
+            let address = if nth_frame == 0 { user_frame.address } else { user_frame.address - 1 };
             let region = match self.process.memory_regions.get_value( user_frame.address ) {
                 Some( region ) => region,
                 None => {
+                    // The code generated by JIT compilers isn't backed by any binary, and neither are
+                    // the .NET images as far as we're concerned since they're not ELF files.
+                    if let Some( name ) = self.process.jit_symbols.get( address, self.timestamp ) {
+                        if opts.filter.is_omitted( name ) {
+                            return false;
+                        }
+
+                        matched = matched || opts.filter.is_matching( name );
+                        if let Some( ref mut output ) = output {
+                            output.push( FrameKind::Jit( interner.get_or_intern( anonymize_if( state.anonymizer.as_ref(), NameKind::Symbol, name ) ) ) );
+                        }
+
+                        continue;
+                    }
+
                     if let Some( ref mut output ) = output {
                         output.push( default );
+                        if opts.granularity == Granularity::Module {
+                            let mut frames = output.split_off( initial_length );
+                            into_module_frames( state, interner, &mut frames );
+                            output.extend( frames );
+                        }
+
+                        if opts.emit_unwind_failure_frames {
+                            if let Some( failure ) = self.unwind_failure {
+                                output.push( FrameKind::UnwindFailure( failure ) );
+                            }
+                        }
                     }
-                    return true;
+                    return matched;
                 }
             };
 
             let binary_id: BinaryId = region.into();
+            // The Python frames are interleaved with the interpreter's own, so those stacks always go the long way.
+            let cached_frames = match state.symbol_cache {
+                Some( ref cache ) if python_backtrace.is_empty() => resolve_cached( cache, state, &*self.process.address_space, region, address ),
+                _ => None
+            };
+
+            if let Some( frames ) = cached_frames {
+                if !push_resolved_frames( state, opts, interner, &mut output, &mut matched, &binary_id, &frames, address ) {
+                    return false;
+                }
+
+                continue;
+            }
+
+            if let Some( frames ) = resolve_with( &state.symbol_resolvers.before, state, region, address ) {
+                if !push_resolved_frames( state, opts, interner, &mut output, &mut matched, &binary_id, &frames, address ) {
+                    return false;
+                }
+
+                continue;
+            }
+
             let mut omit = false;
-            self.process.address_space.decode_symbol_while( if nth_frame == 0 { user_frame.address } else { user_frame.address - 1 }, &mut |frame| {
-                if let Some( name ) = frame.demangled_name.take().or_else( || frame.name.take() ) {
-                    if let Some( ref regex ) = opts.omit_regex {
-                        if regex.is_match( &name ) {
+            self.process.address_space.decode_symbol_while( address, &mut |frame| {
+                if !python_backtrace.is_empty() && frame.name.as_ref().map( |name| name == EVAL_FUNCTION ).unwrap_or( false ) {
+                    // Every invocation of the interpreter loop is replaced with the Python frames which it was evaluating.
+                    let count = python_backtrace.iter().position( |frame| frame.is_entry ).map( |index| index + 1 ).unwrap_or( python_backtrace.len() );
+                    let (frames, remaining) = python_backtrace.split_at( count );
+                    python_backtrace = remaining;
+
+                    for python_frame in frames {
+                        let function = match state.python_functions.get( &(self.process.pid, python_frame.function) ) {
+                            Some( function ) => function,
+                            None => continue
+                        };
+
+                        if opts.filter.is_omitted( &function.name ) {
                             omit = true;
                             return false;
                         }
-                    }
 
-                    if let Some( ref mut output ) = output {
-                        let string_id = interner.get_or_intern( name );
-                        if opts.granularity == Granularity::Line {
-                            if let Some( ref file ) = frame.file {
-                                if let Some( line ) = frame.line {
-                                    output.push( FrameKind::UserByLine {
-                                        binary_id: binary_id.clone(),
-                                        is_inline: frame.is_inline,
-                                        symbol: string_id,
-                                        file: interner.get_or_intern( file ),
-                                        line
-                                    });
-                                    return true;
-                                }
-                            }
-                        }
+                        matched = matched || opts.filter.is_matching( &function.name );
 
-                        if opts.granularity == Granularity::Line || opts.granularity == Granularity::Function {
-                            output.push( FrameKind::UserByFunction {
-                                binary_id: binary_id.clone(),
-                                is_inline: frame.is_inline,
-                                symbol: string_id
-                            });
-                        } else {
-                            output.push( FrameKind::UserByAddress {
-                                binary_id: binary_id.clone(),
-                                is_inline: frame.is_inline,
-                                symbol: string_id,
-                                address: frame.absolute_address
+                        if let Some( ref mut output ) = output {
+                            let line = if python_frame.line == 0 || opts.granularity == Granularity::Function {
+                                None
+                            } else {
+                                Some( python_frame.line as u64 )
+                            };
+
+                            output.push( FrameKind::Python {
+                                symbol: interner.get_or_intern( anonymize_if( state.anonymizer.as_ref(), NameKind::Symbol, &function.name ) ),
+                                file: interner.get_or_intern( state.map_source_path( &function.file ) ),
+                                line
                             });
                         }
                     }
+
+                    return true;
+                }
+
+                if let Some( name ) = frame.demangled_name.take().or_else( || frame.name.take() ) {
+                    if opts.filter.is_omitted( &name ) {
+                        omit = true;
+                        return false;
+                    }
+
+                    matched = matched || opts.filter.is_matching( &name );
+
+                    if let Some( ref mut output ) = output {
+                        push_symbol_frame( state, opts, interner, output, &binary_id, frame.is_inline, &name, frame.file.as_deref(), frame.line, frame.absolute_address );
+                    }
+                } else if let Some( frames ) = resolve_with( &state.symbol_resolvers.after, state, region, address ) {
+                    if !push_resolved_frames( state, opts, interner, &mut output, &mut matched, &binary_id, &frames, frame.absolute_address ) {
+                        omit = true;
+                        return false;
+                    }
                 } else {
                     if let Some( ref mut output ) = output {
                         output.push( FrameKind::UserBinary( binary_id.clone(), frame.absolute_address ) );
@@ -444,7 +1193,24 @@ impl< 'a > EventSample< 'a > {
             }
         }
 
+        if !matched {
+            return false;
+        }
+
         if let Some( ref mut output ) = output {
+            let mut frames = output.split_off( initial_length );
+            if opts.granularity == Granularity::Module {
+                into_module_frames( state, interner, &mut frames );
+            }
+            opts.filter.prune( opts.granularity, state, interner, &mut frames );
+            output.extend( frames );
+
+            if opts.emit_unwind_failure_frames {
+                if let Some( failure ) = self.unwind_failure {
+                    output.push( FrameKind::UnwindFailure( failure ) );
+                }
+            }
+
             if opts.emit_thread_frames {
                 if self.process.pid == self.tid {
                     output.push( FrameKind::MainThread );
@@ -456,6 +1222,11 @@ impl< 'a > EventSample< 'a > {
             if opts.emit_process_frames {
                 output.push( FrameKind::Process( self.process.pid ) );
             }
+
+            if opts.emit_cgroup_frames {
+                let cgroup = self.process.cgroup().map( |cgroup| interner.get_or_intern( cgroup ) );
+                output.push( FrameKind::Cgroup( cgroup ) );
+            }
         }
 
         true
@@ -464,6 +1235,10 @@ impl< 'a > EventSample< 'a > {
 
 pub(crate) enum EventKind< 'a > {
     Sample( EventSample< 'a > ),
+    Lost { count: u64 },
+    Stall { timestamp: u64, tid: u32, duration: u64, kind: StallKind, backtrace: &'a [String] },
+    /// The memory use of a process, in bytes.
+    MemoryStats { timestamp: u64, pid: u32, rss: u64, pss: u64, swap: u64 },
 
     #[doc(hidden)]
     __NonExhaustive
@@ -477,21 +1252,83 @@ pub(crate) struct Event< 'a > {
 pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< State, Box< dyn Error > >
     where F: FnMut( Event )
 {
-    let input_path = args.input_path;
-    let fp = fs::File::open( args.input_path ).map_err( |err| format!( "cannot open {:?}: {}", input_path.clone(), err ) )?;
-    let mut reader = ArchiveReader::new( fp ).validate_header().unwrap().skip_unknown();
+    if (args.from.is_some() || args.to.is_some()) && args.input_paths.iter().any( |&path| is_stdin( path ) ) {
+        return Err( "a time range can't be used when reading from stdin since the input would have to be read twice".into() );
+    }
+
+    let mut reader = open_input( &args.input_paths, args.input_data.as_ref() )?;
+
+    nwind::set_strict_parsing( args.strict );
+    let initial_malformed_count = nwind::malformed_structure_count();
+
+    let mut debug_info_index = DebugInfoIndex::new();
+    for &path in &args.debug_symbols {
+        debug_info_index.add( path );
+    }
 
+    if let Some( sysroot ) = args.sysroot {
+        debug_info_index.set_sysroot( sysroot );
+    }
+
+    for &(from, to) in &args.binary_path_mappings {
+        debug_info_index.add_path_mapping( from, to );
+    }
+
+    let kallsyms_override = match args.kallsyms {
+        Some( path ) => Some( fs::read( path ).map_err( |err| format!( "cannot read {:?}: {}", path, err ) )? ),
+        None => None
+    };
+
+    let address_space_cache_path = args.address_space_cache.and_then( |path| {
+        match address_space_cache_fingerprint( &args ) {
+            Some( fingerprint ) => Some( (path, fingerprint) ),
+            None => {
+                warn!( "The address space cache can only be used when the profiling data is read from files" );
+                None
+            }
+        }
+    });
+
+    let mut address_space_cache = match address_space_cache_path {
+        Some( (path, ref fingerprint) ) => load_address_space_cache( path, fingerprint )?,
+        None => None
+    };
+
+    let mut unwind_checker = match args.cross_check_unwinding {
+        Some( path ) => Some( UnwindChecker::create( path )? ),
+        None => None
+    };
+
+    let symbol_cache = match args.symbol_cache {
+        Some( path ) => Some( SymbolCache::open( Path::new( path ), &args.symbol_resolvers.description ).map_err( |err| format!( "cannot open the symbol cache {:?}: {}", path, err ) )? ),
+        None => None
+    };
+
+    let anonymizer = args.anonymization.map( Anonymizer::new );
     let mut state = State {
-        kallsyms: RangeMap::new(),
+        kallsyms: kallsyms_override.as_ref().map( |kallsyms| kallsyms::parse( kallsyms ) ).unwrap_or_else( RangeMap::new ),
         process_index_by_pid: HashMap::new(),
         processes: Vec::new(),
         thread_names: HashMap::new(),
         binary_by_id: HashMap::new(),
         unfiltered_first_timestamp: None,
         cpu_count: 1,
-        frequency: None
+        frequency: None,
+        multiplexing: None,
+        multiplexing_weight: 1.0,
+        round_robin_cpus: None,
+        debug_info_index,
+        source_path_mappings: args.source_path_mappings.iter().map( |&(from, to)| (from.into(), to.into()) ).collect(),
+        anonymizer,
+        python_functions: HashMap::new(),
+        symbol_resolvers: args.symbol_resolvers,
+        symbol_cache
     };
 
+    if let Some( ref anonymizer ) = state.anonymizer {
+        state.kallsyms = anonymizer.anonymize_kallsyms( mem::replace( &mut state.kallsyms, RangeMap::new() ) );
+    }
+
     let mut machine_architecture = String::new();
     let mut machine_endianness = Endianness::LittleEndian;
     let mut machine_bitness = Bitness::B64;
@@ -499,16 +1336,25 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
     let mut first_timestamp = None;
     let mut last_timestamp = None;
 
-    let mut debug_info_index = DebugInfoIndex::new();
-    for path in args.debug_symbols {
-        debug_info_index.add( path );
-    }
-
-    if args.from.is_some() || args.to.is_some() {
+    // The perf maps are only read periodically while recording, so the samples from right after some code
+    // was compiled come before its name in the archive; hence if we can we load all of them up front.
+    let mut jit_symbols_by_pid: HashMap< u32, JitSymbols > = HashMap::new();
+    let preload_jit_symbols = !args.input_paths.iter().any( |&path| is_stdin( path ) );
+    let has_bounds = args.from.is_some() || args.to.is_some();
+    let is_prescanned = preload_jit_symbols || has_bounds;
+    if is_prescanned {
+        let mut multiplexing = (0, 0);
         while let Some( packet ) = reader.next() {
             let packet = packet.unwrap();
             match packet {
-                Packet::Sample { timestamp, .. } | Packet::RawSample { timestamp, .. } => {
+                Packet::PerfMap { pid, timestamp, data } => {
+                    jit_symbols_by_pid.entry( pid ).or_default().add( &data, timestamp );
+                },
+                Packet::Multiplexing { time_enabled, time_running } => {
+                    multiplexing.0 += time_enabled;
+                    multiplexing.1 += time_running;
+                },
+                Packet::Sample { timestamp, .. } | Packet::RawSample { timestamp, .. } if has_bounds => {
                     if let Some( prev ) = first_timestamp {
                         first_timestamp = Some( min( prev, timestamp ) );
                     } else {
@@ -530,8 +1376,12 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
             info!( "Elapsed: {:.02}s", to_s( elapsed ) );
         }
 
-        let fp = fs::File::open( args.input_path ).map_err( |err| format!( "cannot open {:?}: {}", input_path, err ) )?;
-        reader = ArchiveReader::new( fp ).validate_header().unwrap().skip_unknown();
+        let (time_enabled, time_running) = multiplexing;
+        if time_running > 0 && time_running < time_enabled {
+            state.multiplexing_weight = time_enabled as f64 / time_running as f64;
+        }
+
+        reader = open_input( &args.input_paths, args.input_data.as_ref() )?;
     }
 
     let from = args.from;
@@ -541,27 +1391,7 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
             return true;
         }
 
-        let relative = timestamp - first_timestamp.unwrap();
-        let relative_s = to_s( relative );
-        let relative_p = last_timestamp.map( |last_timestamp|
-            (relative_s / to_s( last_timestamp - first_timestamp.unwrap() ) * 100.0) as u8
-        );
-
-        if let Some( from ) = from {
-            match from {
-                TimestampBound::Relative( bound ) => if relative_s < bound { return false },
-                TimestampBound::RelativePercent( bound ) => if relative_p.unwrap() < bound { return false }
-            }
-        }
-
-        if let Some( to ) = to {
-            match to {
-                TimestampBound::Relative( bound ) => if relative_s > bound { return false },
-                TimestampBound::RelativePercent( bound ) => if relative_p.unwrap() > bound { return false }
-            }
-        }
-
-        true
+        is_in_bounds( from, to, first_timestamp.unwrap(), last_timestamp, timestamp )
     };
 
     while let Some( packet ) = reader.next() {
@@ -573,40 +1403,46 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                 machine_endianness = endianness;
                 state.cpu_count = cpu_count;
 
+                if unwind_checker.is_some() && machine_architecture != arch::amd64::Arch::NAME {
+                    return Err( format!( "cross-checking the unwinding against libunwind isn't supported for {}", machine_architecture ).into() );
+                }
+
                 if machine_architecture == arch::native::Arch::NAME &&
                    machine_endianness == Endianness::NATIVE &&
                    machine_bitness == Bitness::NATIVE
                 {
-                    debug_info_index.enable_auto_load();
+                    state.debug_info_index.enable_auto_load();
                 }
             },
-            Packet::ProcessInfo { pid, executable, .. } => {
+            Packet::ProcessInfo { pid, executable, cgroup, .. } => {
                 let executable = String::from_utf8_lossy( &executable ).into_owned();
-                let executable = get_basename( &executable ).to_owned();
+                let executable = anonymize_if( state.anonymizer.as_ref(), NameKind::Process, get_basename( &executable ) ).into_owned();
+                let cgroup = if cgroup.is_empty() { None } else { Some( anonymize_if( state.anonymizer.as_ref(), NameKind::Cgroup, &String::from_utf8_lossy( &cgroup ) ).into_owned() ) };
                 debug!( "New process with PID {}: \"{}\"", pid, executable );
 
-                let address_space: Box< dyn IAddressSpace > = match &*machine_architecture {
-                    arch::arm::Arch::NAME => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
-                    arch::amd64::Arch::NAME => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
-                    arch::mips64::Arch::NAME => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
-                    arch::aarch64::Arch::NAME => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
-                    _ => panic!( "Unknown architecture: {}", machine_architecture )
-                };
+                let mut address_space = new_address_space( &machine_architecture )
+                    .unwrap_or_else( || panic!( "Unknown architecture: {}", machine_architecture ) );
+
+                if let Some( snapshot ) = address_space_cache.as_mut().and_then( |cache| cache.remove( &pid ) ) {
+                    address_space.restore( snapshot ).map_err( |error| format!( "cannot restore the address space of PID {} from the cache: {}", pid, error ) )?;
+                }
 
                 let process = Process {
                     pid,
                     executable,
+                    cgroup,
                     memory_regions: RangeMap::new(),
                     base_address_for_binary: HashMap::new(),
                     address_space,
-                    address_space_needs_reload: true
+                    address_space_needs_reload: true,
+                    jit_symbols: jit_symbols_by_pid.remove( &pid ).unwrap_or_default()
                 };
 
                 let process_index = state.processes.len();
                 state.processes.push( process );
                 state.process_index_by_pid.insert( pid, process_index );
             },
-            Packet::BinaryInfo { inode, symbol_table_count, path, debuglink, load_headers, .. } => {
+            Packet::BinaryInfo { inode, symbol_table_count, path, debuglink, load_headers, file_size, mtime, .. } => {
                 let debuglink_length = debuglink.iter().position( |&byte| byte == 0 ).unwrap_or( debuglink.len() );
                 let debuglink = debuglink[ 0..debuglink_length ].to_owned();
                 let debuglink = if debuglink.is_empty() {
@@ -622,9 +1458,16 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                     BinaryId::ByInode( inode )
                 };
 
+                let anonymized_path = state.anonymizer.as_ref().map( |anonymizer| anonymizer.anonymize( NameKind::Binary, &path ) );
                 let binary = Binary {
-                    basename: get_basename( &path ).to_owned(),
+                    basename: get_basename( anonymized_path.as_ref().unwrap_or( &path ) ).to_owned(),
+                    anonymized_path,
                     path,
+                    inode,
+                    file_size,
+                    mtime,
+                    match_strategy: None,
+                    is_local_file_rejected: false,
                     string_tables: Arc::new( BinaryChunks::new() ),
                     symbol_table_count,
                     symbol_tables_chunks: BinaryChunks::new(),
@@ -639,7 +1482,7 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
 
                 debug!( "New binary: {:?}", binary.path );
                 if let Some( ref debuglink ) = binary.debuglink {
-                    if debug_info_index.get( &binary.path, binary.debuglink(), binary.build_id() ).is_none() {
+                    if state.debug_info_index.get( &binary.path, binary.debuglink(), binary.build_id() ).is_none() {
                         warn!( "Missing external debug symbols for '{}': '{}'", binary.path, String::from_utf8_lossy( debuglink ) );
                     }
                 }
@@ -774,7 +1617,7 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                     binary.symbol_tables_chunks.clear();
                 }
             },
-            Packet::Sample { user_backtrace, mut kernel_backtrace, pid, tid, cpu, timestamp, .. } => {
+            Packet::Sample { user_backtrace, mut kernel_backtrace, pid, tid, cpu, timestamp, unwind_failure, python_backtrace } => {
                 if first_timestamp.is_none() {
                     first_timestamp = Some( timestamp );
                 } else {
@@ -794,12 +1637,15 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
 
                 debug!( "Sample #{}", sample_counter );
 
-                if state.processes[ 0 ].pid != pid {
-                    debug!( "Sample #{} is from different process with PID {}, skipping!", sample_counter, pid );
-                    continue;
-                }
+                let process_index = match state.process_index_by_pid.get( &pid ).cloned() {
+                    Some( index ) => index,
+                    None => {
+                        debug!( "Sample #{} is from an unknown process with PID {}, skipping!", sample_counter, pid );
+                        continue;
+                    }
+                };
 
-                state.processes[ 0 ].reload_if_necessary( &mut debug_info_index, &mut state.binary_by_id, &args.fde_hints );
+                state.processes[ process_index ].reload_if_necessary( &mut state.debug_info_index, &mut state.binary_by_id, &args.fde_hints );
 
                 if args.without_kernel_callstacks {
                     kernel_backtrace = Vec::new().into();
@@ -809,11 +1655,14 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                     state: &state,
                     kind: EventKind::Sample( EventSample {
                         timestamp,
-                        process: &state.processes[ 0 ],
+                        process: &state.processes[ process_index ],
                         tid,
                         cpu,
                         user_backtrace: &user_backtrace,
-                        kernel_backtrace: &kernel_backtrace
+                        kernel_backtrace: &kernel_backtrace,
+                        unwind_failure,
+                        python_backtrace: &python_backtrace,
+                        weight: state.sample_weight()
                     }
                 )});
 
@@ -839,18 +1688,21 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
 
                 debug!( "Sample #{}", sample_counter );
 
-                if state.processes[ 0 ].pid != pid {
-                    warn!( "Sample #{} is from different process with PID {}, skipping!", sample_counter, pid );
-                    continue;
-                }
+                let process_index = match state.process_index_by_pid.get( &pid ).cloned() {
+                    Some( index ) => index,
+                    None => {
+                        warn!( "Sample #{} is from an unknown process with PID {}, skipping!", sample_counter, pid );
+                        continue;
+                    }
+                };
 
                 if args.without_kernel_callstacks {
                     kernel_backtrace = Vec::new().into();
                 }
 
-                let user_backtrace = {
-                    let process = &mut state.processes[ 0 ];
-                    process.reload_if_necessary( &mut debug_info_index, &mut state.binary_by_id, &args.fde_hints );
+                let (user_backtrace, unwind_failure) = {
+                    let process = &mut state.processes[ process_index ];
+                    process.reload_if_necessary( &mut state.debug_info_index, &mut state.binary_by_id, &args.fde_hints );
 
                     let mut dwarf_regs = DwarfRegs::new();
                     for reg in regs.iter() {
@@ -864,19 +1716,45 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
 
                     let reader = StackReader { stack: stack.into() };
                     let mut user_backtrace = Vec::new();
-                    process.address_space.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
-                    user_backtrace
+                    let unwind_failure = process.address_space.unwind( &mut dwarf_regs, &reader, &mut user_backtrace ).err();
+
+                    if let Some( ref mut unwind_checker ) = unwind_checker {
+                        let sample = CheckedSample {
+                            number: sample_counter,
+                            pid,
+                            tid,
+                            timestamp,
+                            regs: &regs[..],
+                            stack,
+                            memory_regions: &process.memory_regions,
+                            backtrace: &user_backtrace,
+                            unwind_failure
+                        };
+
+                        let binary_by_id = &state.binary_by_id;
+                        let binary_for = |region: &Region| {
+                            let binary = binary_by_id.get( &region.into() )?;
+                            binary.data.clone().or_else( || binary.debug_data.clone() )
+                        };
+
+                        unwind_checker.check( &sample, &binary_for ).map_err( |error| format!( "cannot write the unwinding divergences: {}", error ) )?;
+                    }
+
+                    (user_backtrace, unwind_failure)
                 };
 
                 on_event( Event {
                     state: &state,
                     kind: EventKind::Sample( EventSample {
                         timestamp,
-                        process: &state.processes[ 0 ],
+                        process: &state.processes[ process_index ],
                         tid,
                         cpu,
                         user_backtrace: &user_backtrace,
-                        kernel_backtrace: &kernel_backtrace
+                        kernel_backtrace: &kernel_backtrace,
+                        unwind_failure,
+                        python_backtrace: &[],
+                        weight: state.sample_weight()
                     }
                 )});
 
@@ -894,7 +1772,19 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                 state.binary_by_id.get_mut( &binary_id ).unwrap().data = Some( Arc::new( data ) );
             },
             Packet::FileBlob { ref path, ref data } if path.as_ref() == b"/proc/kallsyms" => {
+                if kallsyms_override.is_some() {
+                    continue;
+                }
+
+                if !kallsyms::has_addresses( data.as_ref() ) {
+                    warn!( "The kernel symbols in the profiling data have no addresses (most likely due to `kernel.kptr_restrict`); use `--kallsyms` to provide them" );
+                    continue;
+                }
+
                 state.kallsyms = kallsyms::parse( data.as_ref() );
+                if let Some( ref anonymizer ) = state.anonymizer {
+                    state.kallsyms = anonymizer.anonymize_kallsyms( mem::replace( &mut state.kallsyms, RangeMap::new() ) );
+                }
             },
             Packet::ThreadName { tid, name, .. } => {
                 if name.is_empty() {
@@ -902,28 +1792,394 @@ pub(crate) fn read_data< F >( args: ReadDataArgs, mut on_event: F ) -> Result< S
                     continue;
                 }
 
-                let name = String::from_utf8_lossy( &name ).into_owned();
-                state.thread_names.insert( tid, name );
-            },
-            Packet::ProfilingFrequency { frequency } => {
-                state.frequency = Some( frequency );
-            },
-            _ => {}
+                let name = anonymize_if( state.anonymizer.as_ref(), NameKind::Thread, &String::from_utf8_lossy( &name ) ).into_owned();
+                state.thread_names.insert( tid, name );
+            },
+            Packet::PythonFunction { pid, id, name, file, .. } => {
+                let function = PythonFunction {
+                    name: name.into_owned(),
+                    file: file.into_owned()
+                };
+
+                state.python_functions.insert( (pid, id), function );
+            },
+            Packet::PerfMap { pid, timestamp, data } => {
+                if preload_jit_symbols {
+                    continue;
+                }
+
+                if let Some( &process_index ) = state.process_index_by_pid.get( &pid ) {
+                    state.processes[ process_index ].jit_symbols.add( &data, timestamp );
+                }
+            },
+            Packet::ProfilingFrequency { frequency } => {
+                state.frequency = Some( frequency );
+            },
+            Packet::Lost { count } => {
+                on_event( Event {
+                    state: &state,
+                    kind: EventKind::Lost { count }
+                });
+            },
+            Packet::RoundRobinCpus { active_cpus, total_cpus } => {
+                state.round_robin_cpus = Some( (active_cpus, total_cpus) );
+            },
+            Packet::Multiplexing { time_enabled, time_running } => {
+                let (total_enabled, total_running) = state.multiplexing.unwrap_or( (0, 0) );
+                state.multiplexing = Some( (total_enabled + time_enabled, total_running + time_running) );
+            },
+            Packet::Stall { timestamp, tid, duration, kind, ref backtrace, .. } => {
+                let anonymized: Vec< String >;
+                let backtrace = match state.anonymizer {
+                    Some( ref anonymizer ) => {
+                        anonymized = backtrace.iter().map( |frame| anonymizer.anonymize( NameKind::Symbol, frame ) ).collect();
+                        &anonymized
+                    },
+                    None => backtrace
+                };
+
+                on_event( Event {
+                    state: &state,
+                    kind: EventKind::Stall { timestamp, tid, duration, kind, backtrace }
+                });
+            },
+            Packet::ProcessCgroup { pid, cgroup } => {
+                if let Some( &index ) = state.process_index_by_pid.get( &pid ) {
+                    let cgroup = if cgroup.is_empty() { None } else { Some( anonymize_if( state.anonymizer.as_ref(), NameKind::Cgroup, &String::from_utf8_lossy( &cgroup ) ).into_owned() ) };
+                    state.processes[ index ].cgroup = cgroup;
+                }
+            },
+            Packet::MemoryStats { pid, timestamp, rss, pss, swap } => {
+                on_event( Event {
+                    state: &state,
+                    kind: EventKind::MemoryStats { timestamp, pid, rss, pss, swap }
+                });
+            },
+            _ => {}
+        }
+    }
+
+    if let Some( ratio ) = state.multiplexing_ratio() {
+        if ratio < HEAVY_MULTIPLEXING_THRESHOLD {
+            warn!( "The sampled event was only running {:.1}% of the time due to multiplexing; the results might not be representative", ratio * 100.0 );
+        }
+
+        if ratio < 1.0 && !is_prescanned {
+            warn!( "The sample counts weren't scaled to compensate for the multiplexing since the data was read from stdin" );
+        }
+    }
+
+    let mut matched: Vec< _ > = state.binary_by_id.values().filter_map( |binary| binary.match_strategy.map( |strategy| (&binary.path, strategy) ) ).collect();
+    if !matched.is_empty() {
+        matched.sort_by_key( |&(path, _)| path );
+        info!( "Matched {} binaries with local files:", matched.len() );
+        for (path, strategy) in matched {
+            info!( "  '{}': by {}", path, strategy.name() );
+        }
+    }
+
+    if let Some( (path, ref fingerprint) ) = address_space_cache_path {
+        if address_space_cache.is_none() {
+            save_address_space_cache( path, fingerprint, &state.processes )?;
+        }
+    }
+
+    let malformed_count = nwind::malformed_structure_count() - initial_malformed_count;
+    if malformed_count > 0 {
+        if args.strict {
+            return Err( format!( "found {} malformed ELF or unwinding info structure(s) in the binaries", malformed_count ).into() );
+        }
+
+        warn!( "Skipped {} malformed ELF or unwinding info structure(s) in the binaries; use `--strict` to see where they are", malformed_count );
+    }
+
+    if let Some( unwind_checker ) = unwind_checker {
+        unwind_checker.finish().map_err( |error| format!( "cannot write the unwinding divergences: {}", error ) )?;
+    }
+
+    state.unfiltered_first_timestamp = first_timestamp;
+    Ok( state )
+}
+
+/// Decides which samples and frames are kept based on the names of the frames.
+#[derive(Default)]
+pub(crate) struct StackFilter {
+    /// Samples which have any frame matching this are dropped.
+    pub omit: Option< Regex >,
+    /// Only samples which have at least one frame matching this are kept.
+    pub only_matching: Option< Regex >,
+    /// All of the callees of the first frame (counting from the root) matching this are dropped.
+    pub hide_above: Option< Regex >,
+    /// All of the callers of the first frame (counting from the root) matching this are dropped.
+    pub hide_below: Option< Regex >,
+    /// Whether consecutive identical frames are merged together.
+    pub collapse_recursion: bool,
+    /// Whether whole recursive cycles are merged together.
+    pub collapse_recursive_cycles: bool,
+    /// The rules which hide, keep or rename individual frames.
+    pub symbol_rules: SymbolRules
+}
+
+/// Decides which individual frames are hidden or renamed based on the names of
+/// their symbols or of the modules they belong to.
+#[derive(Default)]
+pub(crate) struct SymbolRules {
+    /// Frames whose symbol matches this are dropped from the callstack.
+    pub hide: Option< Regex >,
+    /// Frames whose symbol matches this are never hidden.
+    pub keep: Option< Regex >,
+    /// Frames whose module matches this are dropped from the callstack.
+    pub hide_module: Option< Regex >,
+    /// Frames whose module matches this are never hidden.
+    pub keep_module: Option< Regex >,
+    /// The symbols matching any of these are renamed; the first matching rule wins.
+    pub rename: Vec< (Regex, String) >,
+    /// The symbols of the frames whose module matches any of these are renamed.
+    pub rename_module: Vec< (Regex, String) >
+}
+
+impl SymbolRules {
+    fn is_empty( &self ) -> bool {
+        self.hide.is_none() && self.hide_module.is_none() && self.rename.is_empty() && self.rename_module.is_empty()
+    }
+}
+
+/// Returns the name of the binary (or of a pseudo-module, e.g. `[jit]`) which a frame belongs to,
+/// and whether it's the kernel.
+fn module_of< 'a >( state: &'a State, frame: &FrameKind ) -> Option< (Cow< 'a, str >, bool) > {
+    let module = match *frame {
+        | FrameKind::UserByFunction { ref binary_id, .. }
+        | FrameKind::UserByLine { ref binary_id, .. }
+        | FrameKind::UserByAddress { ref binary_id, .. }
+        | FrameKind::UserBinary( ref binary_id, _ )
+        => (state.get_binary( binary_id ).basename().into(), false),
+        FrameKind::User( _ ) => ("[unknown]".into(), false),
+        FrameKind::Python { .. } => ("[python]".into(), false),
+        FrameKind::Jit( _ ) => ("[jit]".into(), false),
+        FrameKind::KernelSymbol( index ) => match state.get_kernel_symbol( index ).module {
+            Some( ref module ) => (format!( "[linux:{}]", module ).into(), true),
+            None => ("[linux]".into(), true)
+        },
+        FrameKind::Kernel( _ ) => ("[linux]".into(), true),
+        _ => return None
+    };
+
+    Some( module )
+}
+
+fn symbol_of< 'a >( state: &'a State, interner: &'a StringInterner, frame: &FrameKind ) -> Option< &'a str > {
+    match *frame {
+        | FrameKind::UserByFunction { symbol, .. }
+        | FrameKind::UserByLine { symbol, .. }
+        | FrameKind::UserByAddress { symbol, .. }
+        | FrameKind::Python { symbol, .. }
+        | FrameKind::Jit( symbol )
+        => interner.resolve( symbol ),
+        FrameKind::KernelSymbol( index ) => Some( &state.get_kernel_symbol( index ).name ),
+        _ => None
+    }
+}
+
+/// Replaces every frame with the name of the binary it belongs to,
+/// merging consecutive frames from the same binary together.
+fn into_module_frames( state: &State, interner: &mut StringInterner, frames: &mut Vec< FrameKind > ) {
+    for frame in frames.iter_mut() {
+        if let Some( (name, is_kernel) ) = module_of( state, frame ) {
+            *frame = FrameKind::Module { name: interner.get_or_intern( name ), is_kernel };
+        }
+    }
+
+    frames.dedup();
+}
+
+/// Replaces the symbol of a frame; returns `false` if the frame can't be renamed.
+///
+/// A frame without a symbol gets one, becoming the same kind of a frame as a symbolized one
+/// would be for a given granularity.
+fn rename_frame( frame: &mut FrameKind, name: StringId, granularity: Granularity ) -> bool {
+    match *frame {
+        | FrameKind::UserByFunction { ref mut symbol, .. }
+        | FrameKind::UserByLine { ref mut symbol, .. }
+        | FrameKind::UserByAddress { ref mut symbol, .. }
+        | FrameKind::Python { ref mut symbol, .. }
+        | FrameKind::Jit( ref mut symbol )
+        | FrameKind::Module { name: ref mut symbol, .. }
+        => *symbol = name,
+        FrameKind::UserBinary( ref binary_id, address ) => {
+            *frame = if granularity == Granularity::Address {
+                FrameKind::UserByAddress {
+                    binary_id: binary_id.clone(),
+                    is_inline: false,
+                    symbol: name,
+                    address,
+                    file: None,
+                    line: None
+                }
+            } else {
+                FrameKind::UserByFunction {
+                    binary_id: binary_id.clone(),
+                    is_inline: false,
+                    symbol: name
+                }
+            };
+        },
+        _ => return false
+    }
+
+    true
+}
+
+/// Hides and renames the frames according to the symbol rules; consecutive frames
+/// which were renamed to the same name are merged together.
+fn apply_symbol_rules( rules: &SymbolRules, granularity: Granularity, state: &State, interner: &mut StringInterner, frames: &mut Vec< FrameKind > ) {
+    if rules.is_empty() {
+        return;
+    }
+
+    let is_match = |regex: &Option< Regex >, name: Option< &str >| {
+        match (regex, name) {
+            (Some( regex ), Some( name )) => regex.is_match( name ),
+            _ => false
+        }
+    };
+
+    let rename = |rules: &[(Regex, String)], name: Option< &str >| {
+        let name = name?;
+        rules.iter()
+            .find( |(regex, _)| regex.is_match( name ) )
+            .map( |(regex, replacement)| regex.replace( name, replacement.as_str() ).into_owned() )
+    };
+
+    let mut output = Vec::with_capacity( frames.len() );
+    let mut last_renamed = None;
+    for mut frame in frames.drain( .. ) {
+        let (is_hidden, new_name) = {
+            let symbol = symbol_of( state, interner, &frame );
+            let module = match frame {
+                FrameKind::Module { name, .. } => interner.resolve( name ).map( Cow::from ),
+                _ => module_of( state, &frame ).map( |(name, _)| name )
+            };
+            let module = module.as_ref().map( |name| name.as_ref() );
+
+            let is_kept = is_match( &rules.keep, symbol ) || is_match( &rules.keep_module, module );
+            let is_hidden = !is_kept && (is_match( &rules.hide, symbol ) || is_match( &rules.hide_module, module ));
+            let new_name = if is_hidden {
+                None
+            } else {
+                rename( &rules.rename, symbol ).or_else( || rename( &rules.rename_module, module ) )
+            };
+
+            (is_hidden, new_name)
+        };
+
+        if is_hidden {
+            continue;
+        }
+
+        let new_name = new_name.map( |name| interner.get_or_intern( name ) );
+        match new_name {
+            Some( name ) if rename_frame( &mut frame, name, granularity ) => {
+                if last_renamed == Some( name ) {
+                    continue;
+                }
+
+                last_renamed = Some( name );
+            },
+            _ => last_renamed = None
+        }
+
+        output.push( frame );
+    }
+
+    *frames = output;
+}
+
+/// Merges consecutive identical frames together.
+fn collapse_recursion( frames: &mut Vec< FrameKind > ) {
+    frames.dedup();
+}
+
+/// Drops every part of the callstack which starts and ends with the same frame,
+/// so that every frame appears only once.
+fn collapse_recursive_cycles( frames: &mut Vec< FrameKind > ) {
+    // The frames are ordered from the leaf to the root.
+    let mut output: Vec< FrameKind > = Vec::with_capacity( frames.len() );
+    for frame in frames.drain( .. ).rev() {
+        if let Some( index ) = output.iter().position( |previous| *previous == frame ) {
+            output.truncate( index + 1 );
+        } else {
+            output.push( frame );
+        }
+    }
+
+    output.reverse();
+    *frames = output;
+}
+
+impl StackFilter {
+    fn prune( &self, granularity: Granularity, state: &State, interner: &mut StringInterner, frames: &mut Vec< FrameKind > ) {
+        apply_symbol_rules( &self.symbol_rules, granularity, state, interner, frames );
+
+        if self.collapse_recursive_cycles {
+            collapse_recursive_cycles( frames );
+        } else if self.collapse_recursion {
+            collapse_recursion( frames );
+        }
+
+        if self.hide_above.is_none() && self.hide_below.is_none() {
+            return;
+        }
+
+        let find = |frames: &[FrameKind], regex: &Regex| {
+            frames.iter().rposition( |frame| symbol_of( state, interner, frame ).map( |name| regex.is_match( name ) ).unwrap_or( false ) )
+        };
+
+        // The frames are ordered from the leaf to the root.
+        if let Some( ref regex ) = self.hide_below {
+            if let Some( index ) = find( frames, regex ) {
+                frames.truncate( index + 1 );
+            }
+        }
+
+        if let Some( ref regex ) = self.hide_above {
+            if let Some( index ) = find( frames, regex ) {
+                frames.drain( ..index );
+            }
         }
     }
 
-    state.unfiltered_first_timestamp = first_timestamp;
-    Ok( state )
+    fn is_omitted( &self, name: &str ) -> bool {
+        self.omit.as_ref().map( |regex| regex.is_match( name ) ).unwrap_or( false )
+    }
+
+    fn is_matching( &self, name: &str ) -> bool {
+        self.only_matching.as_ref().map( |regex| regex.is_match( name ) ).unwrap_or( false )
+    }
 }
 
 pub(crate) struct DecodeOpts {
-    pub omit_regex: Option< Regex >,
+    pub filter: StackFilter,
     pub emit_kernel_frames: bool,
     pub emit_thread_frames: bool,
     pub emit_process_frames: bool,
+    /// Whether to emit a synthetic `[truncated]` or `[unwind failed: ...]` frame
+    /// at the root of the user backtrace when the unwinding stopped prematurely.
+    pub emit_unwind_failure_frames: bool,
+    /// Whether to emit the process' cgroup as the outermost frame.
+    pub emit_cgroup_frames: bool,
     pub granularity: Granularity
 }
 
+/// Returns the name of the synthetic frame which marks a backtrace as incomplete.
+pub(crate) fn unwind_failure_frame_name( failure: UnwindFailure ) -> &'static str {
+    match failure {
+        UnwindFailure::StackTruncated => "[truncated]",
+        UnwindFailure::MissingRegisters => "[unwind failed: missing registers]",
+        UnwindFailure::UnknownAddress => "[unwind failed: unknown address]",
+        UnwindFailure::MissingUnwindInfo => "[unwind failed: missing unwind info]"
+    }
+}
+
 pub(crate) fn write_frame< T: fmt::Write >(
     state: &State,
     interner: &StringInterner,
@@ -967,7 +2223,7 @@ pub(crate) fn write_frame< T: fmt::Write >(
             let symbol = interner.resolve( symbol ).unwrap();
             write!( output, "{} [{}]", symbol, binary.basename ).unwrap()
         },
-        FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address } => {
+        FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, .. } => {
             write!( output, "0x{:016X} ", address ).unwrap();
             if is_inline {
                 write!( output, "inline " ).unwrap();
@@ -983,6 +2239,21 @@ pub(crate) fn write_frame< T: fmt::Write >(
         FrameKind::User( addr ) => {
             write!( output, "0x{:016X}", addr ).unwrap()
         },
+        FrameKind::UnwindFailure( failure ) => {
+            write!( output, "{}", unwind_failure_frame_name( failure ) ).unwrap()
+        },
+        FrameKind::Cgroup( Some( cgroup ) ) => {
+            write!( output, "[CGROUP={}]", interner.resolve( cgroup ).unwrap() ).unwrap()
+        },
+        FrameKind::Cgroup( None ) => {
+            write!( output, "[CGROUP=?]" ).unwrap()
+        },
+        FrameKind::Module { name, is_kernel } => {
+            write!( output, "{}", interner.resolve( name ).unwrap() ).unwrap();
+            if is_kernel {
+                write!( output, "_[k]" ).unwrap();
+            }
+        },
         FrameKind::KernelSymbol( symbol_index ) => {
             let symbol = state.get_kernel_symbol( symbol_index );
             if let Some( module ) = symbol.module.as_ref() {
@@ -993,45 +2264,272 @@ pub(crate) fn write_frame< T: fmt::Write >(
         },
         FrameKind::Kernel( addr ) => {
             write!( output, "0x{:016X}_[k]", addr ).unwrap()
+        },
+        FrameKind::Python { symbol, file, line } => {
+            let symbol = interner.resolve( symbol ).unwrap();
+            let basename = get_basename( interner.resolve( file ).unwrap() );
+            match line {
+                Some( line ) => write!( output, "{} [{}:{}]", symbol, basename, line ).unwrap(),
+                None => write!( output, "{} [{}]", symbol, basename ).unwrap()
+            }
+        },
+        FrameKind::Jit( symbol ) => {
+            write!( output, "{} [jit]", interner.resolve( symbol ).unwrap() ).unwrap()
+        }
+    }
+}
+
+
+/// Parses a file with frame pruning rules, one per line, in the following format:
+///
+/// ```text
+/// # Everything called by the event loop dispatcher is irrelevant.
+/// hide-above ^dispatch_event$
+/// hide-below ^main$
+/// ```
+fn parse_prune_rules( rules: &str ) -> Result< (Vec< String >, Vec< String >), String > {
+    let mut hide_above = Vec::new();
+    let mut hide_below = Vec::new();
+    for (nth, line) in rules.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with( "#" ) {
+            continue;
+        }
+
+        let (kind, pattern) = match line.find( char::is_whitespace ) {
+            Some( index ) => (&line[ ..index ], line[ index.. ].trim()),
+            None => (line, "")
+        };
+
+        if pattern.is_empty() {
+            return Err( format!( "line {}: missing a pattern", nth + 1 ) );
+        }
+
+        match kind {
+            "hide-above" => hide_above.push( pattern.to_owned() ),
+            "hide-below" => hide_below.push( pattern.to_owned() ),
+            _ => return Err( format!( "line {}: unknown rule: '{}'", nth + 1, kind ) )
+        }
+    }
+
+    Ok( (hide_above, hide_below) )
+}
+
+/// Parses a file with symbol rules, one per line, in the following format:
+///
+/// ```text
+/// # Hide the frames of the allocator, except for `malloc` itself.
+/// hide ^(_int_malloc|malloc_consolidate)$
+/// keep ^malloc$
+/// hide-module ^libtcmalloc
+/// # Merge all of glibc's internals into a single frame.
+/// rename ^__libc_.* libc
+/// rename-module ^libpython.* libpython
+/// ```
+///
+/// The replacement of a `rename` rule is the last word on its line, and can refer
+/// to the groups captured by the pattern, e.g. `rename ^(\w+)::.* $1`.
+fn parse_symbol_rules( rules: &str ) -> Result< SymbolRules, String > {
+    let mut hide = Vec::new();
+    let mut keep = Vec::new();
+    let mut hide_module = Vec::new();
+    let mut keep_module = Vec::new();
+    let mut output = SymbolRules::default();
+    for (nth, line) in rules.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with( "#" ) {
+            continue;
+        }
+
+        let (kind, pattern) = match line.find( char::is_whitespace ) {
+            Some( index ) => (&line[ ..index ], line[ index.. ].trim()),
+            None => (line, "")
+        };
+
+        if pattern.is_empty() {
+            return Err( format!( "line {}: missing a pattern", nth + 1 ) );
+        }
+
+        let (pattern, replacement) = match kind {
+            "rename" | "rename-module" => match pattern.rfind( char::is_whitespace ) {
+                Some( index ) => (pattern[ ..index ].trim(), Some( &pattern[ index + 1.. ] )),
+                None => return Err( format!( "line {}: missing a replacement", nth + 1 ) )
+            },
+            _ => (pattern, None)
+        };
+
+        let regex = Regex::new( pattern ).map_err( |err| format!( "line {}: invalid regex: {}", nth + 1, err ) )?;
+        match (kind, replacement) {
+            ("hide", _) => hide.push( pattern ),
+            ("keep", _) => keep.push( pattern ),
+            ("hide-module", _) => hide_module.push( pattern ),
+            ("keep-module", _) => keep_module.push( pattern ),
+            ("rename", Some( replacement )) => output.rename.push( (regex, replacement.to_owned()) ),
+            ("rename-module", Some( replacement )) => output.rename_module.push( (regex, replacement.to_owned()) ),
+            _ => return Err( format!( "line {}: unknown rule: '{}'", nth + 1, kind ) )
+        }
+    }
+
+    let compile = |patterns: Vec< &str >| {
+        if patterns.is_empty() {
+            return None;
+        }
+
+        // Every pattern was already checked on its own.
+        Some( Regex::new( &patterns.join( "|" ) ).unwrap() )
+    };
+
+    output.hide = compile( hide );
+    output.keep = compile( keep );
+    output.hide_module = compile( hide_module );
+    output.keep_module = compile( keep_module );
+    Ok( output )
+}
+
+/// Parses a `<from>=<to>` path prefix mapping.
+/// Rewrites the `path` according to the first mapping whose `from` prefix it starts with.
+fn map_path< 'a >( mappings: &[(PathBuf, PathBuf)], path: &'a str ) -> Cow< 'a, str > {
+    for (from, to) in mappings {
+        if let Ok( relative_path ) = Path::new( path ).strip_prefix( from ) {
+            return to.join( relative_path ).to_string_lossy().into_owned().into();
         }
     }
+
+    path.into()
+}
+
+fn parse_path_mapping( mapping: &str ) -> Option< (&str, &str) > {
+    let index = mapping.find( '=' )?;
+    let (from, to) = (&mapping[ ..index ], &mapping[ index + 1.. ]);
+    if from.is_empty() || to.is_empty() {
+        return None;
+    }
+
+    Some( (from, to) )
 }
 
+pub(crate) fn repack_cli_args( args: &args::SharedCollationArgs ) -> Result< (StackFilter, ReadDataArgs< '_ >), Box< dyn Error > > {
+    // Both options end up in the same regex, so check them separately to name the right one on error.
+    for &(patterns, option) in &[(&args.omit, "--omit"), (&args.exclude_matching, "--exclude-matching")] {
+        for pattern in patterns {
+            Regex::new( pattern ).map_err( |err| format!( "invalid regex passed in `{}`: {}", option, err ) )?;
+        }
+    }
+
+    let omit: Vec< _ > = args.omit.iter().chain( args.exclude_matching.iter() ).cloned().collect();
+    let omit = if omit.is_empty() {
+        None
+    } else {
+        let regex = omit.join( "|" );
+        let regex = Regex::new( &regex ).map_err( |err| format!( "invalid regex passed in `--exclude-matching`: {}", err ) )?;
+        Some( regex )
+    };
 
-pub(crate) fn repack_cli_args( args: &args::SharedCollationArgs ) -> (Option< Regex >, ReadDataArgs) {
-    let omit_regex = if args.omit.is_empty() {
+    let only_matching = if args.only_matching.is_empty() {
         None
     } else {
-        let regex = args.omit.join( "|" );
-        let regex = Regex::new( &regex ).expect( "invalid regexp passed in `--omit`" );
+        let regex = args.only_matching.join( "|" );
+        let regex = Regex::new( &regex ).map_err( |err| format!( "invalid regex passed in `--only-matching`: {}", err ) )?;
         Some( regex )
     };
 
+    let mut hide_above = args.hide_above.clone();
+    let mut hide_below = args.hide_below.clone();
+    if let Some( ref path ) = args.prune_rules {
+        let rules = fs::read_to_string( path ).map_err( |err| format!( "cannot read {:?}: {}", path, err ) )?;
+        let (above, below) = parse_prune_rules( &rules ).map_err( |error| format!( "{:?}: {}", path, error ) )?;
+        hide_above.extend( above );
+        hide_below.extend( below );
+    }
+
+    let compile = |patterns: Vec< String >, option: &str| -> Result< Option< Regex >, String > {
+        if patterns.is_empty() {
+            return Ok( None );
+        }
+
+        let regex = patterns.join( "|" );
+        let regex = Regex::new( &regex ).map_err( |err| format!( "invalid regex passed in `{}`: {}", option, err ) )?;
+        Ok( Some( regex ) )
+    };
+
+    let symbol_rules = match args.symbol_rules {
+        Some( ref path ) => {
+            let rules = fs::read_to_string( path ).map_err( |err| format!( "cannot read {:?}: {}", path, err ) )?;
+            parse_symbol_rules( &rules ).map_err( |error| format!( "{:?}: {}", path, error ) )?
+        },
+        None => SymbolRules::default()
+    };
+
+    let filter = StackFilter {
+        omit,
+        only_matching,
+        hide_above: compile( hide_above, "--hide-above" )?,
+        hide_below: compile( hide_below, "--hide-below" )?,
+        collapse_recursion: args.collapse_recursion,
+        collapse_recursive_cycles: args.collapse_recursive_cycles,
+        symbol_rules
+    };
+
+    let (from, to) = parse_timestamp_bounds( args.from.as_ref(), args.to.as_ref(), args.range.as_ref() )?;
+
+    let binary_path_mappings = args.binary_path_map.iter().map( |mapping| {
+        parse_path_mapping( mapping ).ok_or_else( || format!( "invalid mapping passed in `--binary-path-map`: '{}'; expected '<from>=<to>'", mapping ) )
+    }).collect::< Result< _, _ > >()?;
+
+    let source_path_mappings = args.path_map.iter().map( |mapping| {
+        parse_path_mapping( mapping ).ok_or_else( || format!( "invalid mapping passed in `--path-map`: '{}'; expected '<from>=<to>'", mapping ) )
+    }).collect::< Result< _, _ > >()?;
+
+    let mut symbol_resolvers = SymbolResolvers::new( &args.symbol_resolver )?;
+    if let Some( ref url ) = args.symbolize_via {
+        let resolver = RemoteResolver::new( url )?;
+        symbol_resolvers.before.insert( 0, Box::new( resolver ) );
+        symbol_resolvers.add_to_description( &format!( "remote:{}", url ) );
+    }
+
     let debug_symbols: Vec< _ > = args.debug_symbols.iter().map( |path| path.as_os_str() ).collect();
     let read_data_args = ReadDataArgs {
-        input_path: &args.input,
+        input_paths: args.input.iter().map( |path| path.as_os_str() ).collect(),
+        input_data: None,
         debug_symbols,
+        sysroot: args.sysroot.as_ref().map( |path| path.as_os_str() ),
+        kallsyms: args.kallsyms.as_ref().map( |path| path.as_os_str() ),
+        address_space_cache: args.address_space_cache.as_ref().map( |path| path.as_os_str() ),
+        symbol_cache: args.symbol_cache.as_ref().map( |path| path.as_os_str() ),
+        binary_path_mappings,
+        source_path_mappings,
         force_stack_size: args.force_stack_size,
         only_sample: args.only_sample,
         without_kernel_callstacks: args.without_kernel_callstacks,
+        anonymization: args.anonymize,
         fde_hints: FdeHints {
             use_eh_frame_hdr: false,
             load_eh_frame: LoadHint::Always,
             load_debug_frame: true
         },
-        from: args.from.as_ref().map( parse_timestamp_bound ),
-        to: args.to.as_ref().map( parse_timestamp_bound )
+        strict: args.strict,
+        cross_check_unwinding: args.cross_check_unwinding.as_ref().map( |path| path.as_os_str() ),
+        from,
+        to,
+        symbol_resolvers
     };
 
-    (omit_regex, read_data_args)
+    Ok( (filter, read_data_args) )
 }
 
 #[cfg(test)]
 mod test {
-    use super::{StringInterner, ReadDataArgs, DecodeOpts, EventKind, FrameKind, State, FdeHints, read_data};
-    use nwind::LoadHint;
-    use std::path::Path;
+    use super::{StringInterner, ReadDataArgs, DecodeOpts, EventKind, FrameKind, State, FdeHints, StackFilter, TimestampBound, Binary, BinaryChunks, MatchStrategy, read_data, parse_timestamp_bound, parse_timestamp_range, parse_prune_rules, parse_symbol_rules, parse_path_mapping, map_path, unwind_failure_frame_name, collapse_recursion, collapse_recursive_cycles};
+    use nwind::{BinaryData, LoadHint};
+    use regex::Regex;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::{Path, PathBuf};
     use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::archive::Inode;
+    use crate::symbol_resolver::SymbolResolvers;
 
     use crate::args::Granularity;
 
@@ -1050,25 +2548,46 @@ mod test {
     }
 
     fn load_with_fde_hints( filename: &str, fde_hints: FdeHints ) -> Data {
+        load_with_filter( filename, fde_hints, StackFilter::default() )
+    }
+
+    fn load_with_filter( filename: &str, fde_hints: FdeHints, filter: StackFilter ) -> Data {
+        load_with_caches( filename, fde_hints, filter, None, None )
+    }
+
+    fn load_with_caches( filename: &str, fde_hints: FdeHints, filter: StackFilter, address_space_cache: Option< &Path >, symbol_cache: Option< &Path > ) -> Data {
         let _ = env_logger::try_init();
         let mut interner = StringInterner::new();
         let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( filename );
         let args = ReadDataArgs {
-            input_path: path.as_os_str(),
+            input_paths: vec![ path.as_os_str() ],
+            input_data: None,
             debug_symbols: Vec::new(),
+            sysroot: None,
+            kallsyms: None,
+            address_space_cache: address_space_cache.map( |path| path.as_os_str() ),
+            symbol_cache: symbol_cache.map( |path| path.as_os_str() ),
+            binary_path_mappings: Vec::new(),
+            source_path_mappings: Vec::new(),
             force_stack_size: None,
             only_sample: None,
             without_kernel_callstacks: false,
+            anonymization: None,
             fde_hints,
+            strict: false,
+            cross_check_unwinding: None,
             from: None,
-            to: None
+            to: None,
+            symbol_resolvers: SymbolResolvers::default()
         };
 
         let opts = DecodeOpts {
-            omit_regex: None,
+            filter,
             emit_kernel_frames: true,
             emit_thread_frames: true,
             emit_process_frames: true,
+            emit_unwind_failure_frames: false,
+            emit_cgroup_frames: false,
             granularity: Granularity::Function
         };
 
@@ -1145,6 +2664,21 @@ mod test {
             },
             FrameKind::Kernel( _ ) => {
                 format!( "?" )
+            },
+            FrameKind::UnwindFailure( failure ) => {
+                unwind_failure_frame_name( failure ).to_owned()
+            },
+            FrameKind::Cgroup( _ ) => {
+                format!( "[cgroup]" )
+            },
+            FrameKind::Module { name, .. } => {
+                data.interner.resolve( name ).unwrap().to_owned()
+            },
+            FrameKind::Python { symbol, .. } => {
+                format!( "{}:python", data.interner.resolve( symbol ).unwrap() )
+            },
+            FrameKind::Jit( symbol ) => {
+                format!( "{}:jit", data.interner.resolve( symbol ).unwrap() )
             }
         }
     }
@@ -1556,6 +3090,65 @@ mod test {
         }
     }
 
+    #[test]
+    fn collate_amd64_with_address_space_cache() {
+        fn load_stacks( address_space_cache: Option< &Path > ) -> Vec< (String, u64) > {
+            let fde_hints = FdeHints {
+                use_eh_frame_hdr: false,
+                load_eh_frame: LoadHint::Always,
+                load_debug_frame: true
+            };
+
+            let data = load_with_caches( "amd64-usleep_in_a_loop_no_fp.nperf", fde_hints, StackFilter::default(), address_space_cache, None );
+            let mut stacks: Vec< _ > = data.stacks.iter().map( |(frames, &count)| (frames_to_str( &data, frames, None ), count) ).collect();
+            stacks.sort();
+            stacks
+        }
+
+        let cache = std::env::temp_dir().join( format!( "nperf-address-space-cache-{}", std::process::id() ) );
+        let _ = fs::remove_file( &cache );
+
+        let expected = load_stacks( None );
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+        assert!( cache.exists() );
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+
+        // A cache which was made for a different recording is ignored and rewritten.
+        let mut bytes = fs::read( &cache ).unwrap();
+        bytes[ 4 ] ^= 0xff;
+        fs::write( &cache, &bytes ).unwrap();
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+        assert_ne!( fs::read( &cache ).unwrap(), bytes );
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+
+        fs::remove_file( &cache ).unwrap();
+    }
+
+    #[test]
+    fn collate_amd64_inline_functions_with_symbol_cache() {
+        fn load_stacks( symbol_cache: Option< &Path > ) -> Vec< (String, u64) > {
+            let fde_hints = FdeHints {
+                use_eh_frame_hdr: false,
+                load_eh_frame: LoadHint::Always,
+                load_debug_frame: true
+            };
+
+            let data = load_with_caches( "amd64-inline_functions.nperf", fde_hints, StackFilter::default(), None, symbol_cache );
+            let mut stacks: Vec< _ > = data.stacks.iter().map( |(frames, &count)| (frames_to_str( &data, frames, None ), count) ).collect();
+            stacks.sort();
+            stacks
+        }
+
+        let cache = std::env::temp_dir().join( format!( "nperf-symbol-cache-{}", std::process::id() ) );
+        let _ = fs::remove_dir_all( &cache );
+
+        let expected = load_stacks( None );
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+        assert!( fs::read_dir( &cache ).unwrap().count() > 0 );
+        assert_eq!( load_stacks( Some( &cache ) ), expected );
+        fs::remove_dir_all( &cache ).unwrap();
+    }
+
     #[test]
     fn collate_amd64_pthread_cond_wait() {
         let data = load( "amd64-pthread_cond_wait.nperf" );
@@ -1591,6 +3184,143 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn collate_amd64_pthread_cond_wait_filtered() {
+        let fde_hints = || FdeHints {
+            use_eh_frame_hdr: false,
+            load_eh_frame: LoadHint::Always,
+            load_debug_frame: true
+        };
+
+        let contains = |data: &Data, frames: &[FrameKind], name: &str| frames.iter().any( |frame| frame_to_str( data, frame ) == name );
+
+        let data = load_with_filter( "amd64-pthread_cond_wait.nperf", fde_hints(), StackFilter {
+            only_matching: Some( Regex::new( "^thread_main$" ).unwrap() ),
+            .. StackFilter::default()
+        });
+
+        assert!( !data.stacks.is_empty() );
+        for frames in data.stacks.keys() {
+            assert!( contains( &data, frames, "thread_main:amd64-pthread_cond_wait" ) );
+        }
+
+        let data = load_with_filter( "amd64-pthread_cond_wait.nperf", fde_hints(), StackFilter {
+            omit: Some( Regex::new( "^(thread_main|sys_futex)$" ).unwrap() ),
+            .. StackFilter::default()
+        });
+
+        assert!( !data.stacks.is_empty() );
+        for frames in data.stacks.keys() {
+            assert!( !contains( &data, frames, "thread_main:amd64-pthread_cond_wait" ) );
+            assert!( !contains( &data, frames, "sys_futex:linux" ) );
+        }
+    }
+
+    #[test]
+    fn collate_amd64_pthread_cond_wait_pruned() {
+        let data = load_with_filter( "amd64-pthread_cond_wait.nperf", FdeHints {
+            use_eh_frame_hdr: false,
+            load_eh_frame: LoadHint::Always,
+            load_debug_frame: true
+        }, StackFilter {
+            hide_above: Some( Regex::new( "^pthread_cond_wait$" ).unwrap() ),
+            hide_below: Some( Regex::new( "^main$" ).unwrap() ),
+            .. StackFilter::default()
+        });
+
+        let main_stacks: Vec< _ > = data.stacks.iter().filter( |&(ref frames, _)| frame_to_str( &data, &frames[ frames.len() - 2 ] ) == "[main_thread]" ).collect();
+        let &(ref main_frames, _) = main_stacks.iter().max_by( |a, b| a.1.cmp( &b.1 ) ).unwrap();
+
+        assert_backtrace( &data, &main_frames, &[
+            "[process:amd64-pthread_cond_wait]",
+            "[main_thread]",
+            "main:amd64-pthread_cond_wait",
+            "pthread_cond_wait:libpthread-2.26.so"
+        ]);
+    }
+
+    #[test]
+    fn parse_prune_rules_file() {
+        let rules = "# A comment.\n\nhide-above ^dispatch$\n  hide-below   ^main|start$\n";
+        assert_eq!( parse_prune_rules( rules ), Ok( (vec![ "^dispatch$".to_owned() ], vec![ "^main|start$".to_owned() ]) ) );
+        assert!( parse_prune_rules( "hide-above" ).is_err() );
+        assert!( parse_prune_rules( "hide ^main$" ).is_err() );
+    }
+
+    #[test]
+    fn parse_symbol_rules_file() {
+        let rules = "# A comment.\n\nhide ^foo$\nhide ^bar$\nkeep-module ^libc\nrename ^std::vector<.*> vector\n  rename-module   ^libpthread   pthread\n";
+        let rules = parse_symbol_rules( rules ).unwrap();
+        assert_eq!( rules.hide.map( |regex| regex.as_str().to_owned() ), Some( "^foo$|^bar$".to_owned() ) );
+        assert!( rules.keep.is_none() );
+        assert!( rules.hide_module.is_none() );
+        assert_eq!( rules.keep_module.map( |regex| regex.as_str().to_owned() ), Some( "^libc".to_owned() ) );
+        assert_eq!( rules.rename.iter().map( |(regex, name)| (regex.as_str(), name.as_str()) ).collect::< Vec< _ > >(), vec![ ("^std::vector<.*>", "vector") ] );
+        assert_eq!( rules.rename_module.iter().map( |(regex, name)| (regex.as_str(), name.as_str()) ).collect::< Vec< _ > >(), vec![ ("^libpthread", "pthread") ] );
+
+        assert!( parse_symbol_rules( "hide" ).is_err() );
+        assert!( parse_symbol_rules( "rename ^foo$" ).is_err() );
+        assert!( parse_symbol_rules( "hide (" ).is_err() );
+        assert!( parse_symbol_rules( "hide-above ^main$" ).is_err() );
+    }
+
+    #[test]
+    fn collate_amd64_pthread_cond_wait_with_symbol_rules() {
+        let rules = "hide-module ^\\[linux\nkeep ^sys_futex$\nrename ^__libc_.* libc\nrename-module ^libpthread.* pthread\n";
+        let data = load_with_filter( "amd64-pthread_cond_wait.nperf", FdeHints {
+            use_eh_frame_hdr: false,
+            load_eh_frame: LoadHint::Always,
+            load_debug_frame: true
+        }, StackFilter {
+            symbol_rules: parse_symbol_rules( rules ).unwrap(),
+            .. StackFilter::default()
+        });
+
+        let main_stacks: Vec< _ > = data.stacks.iter().filter( |&(ref frames, _)| frame_to_str( &data, &frames[ frames.len() - 2 ] ) == "[main_thread]" ).collect();
+        let thread_stacks: Vec< _ > = data.stacks.iter().filter( |&(ref frames, _)| frame_to_str( &data, &frames[ frames.len() - 2 ] ) == "[thread:another thread]" ).collect();
+
+        let &(ref main_frames, _) = main_stacks.iter().max_by( |a, b| a.1.cmp( &b.1 ) ).unwrap();
+        let &(ref thread_frames, _) = thread_stacks.iter().max_by( |a, b| a.1.cmp( &b.1 ) ).unwrap();
+
+        assert_backtrace( &data, &main_frames, &[
+            "[process:amd64-pthread_cond_wait]",
+            "[main_thread]",
+            "_start:amd64-pthread_cond_wait",
+            "libc:libc-2.26.so",
+            "main:amd64-pthread_cond_wait",
+            "pthread:libpthread-2.26.so",
+            "sys_futex:linux"
+        ]);
+
+        assert_backtrace( &data, &thread_frames, &[
+            "[process:amd64-pthread_cond_wait]",
+            "[thread:another thread]",
+            "clone:libc-2.26.so",
+            "pthread:libpthread-2.26.so",
+            "thread_main:amd64-pthread_cond_wait",
+            "pthread:libpthread-2.26.so",
+            "sys_futex:linux"
+        ]);
+    }
+
+    #[test]
+    fn collapse_recursive_frames() {
+        let frames = |addresses: &[u64]| -> Vec< FrameKind > { addresses.iter().map( |&address| FrameKind::User( address ) ).collect() };
+
+        // The frames are ordered from the leaf to the root.
+        let mut stack = frames( &[ 4, 3, 3, 3, 2, 3, 2, 1, 1 ] );
+        collapse_recursion( &mut stack );
+        assert_eq!( stack, frames( &[ 4, 3, 2, 3, 2, 1 ] ) );
+
+        let mut stack = frames( &[ 4, 3, 3, 3, 2, 3, 2, 1, 1 ] );
+        collapse_recursive_cycles( &mut stack );
+        assert_eq!( stack, frames( &[ 4, 3, 2, 1 ] ) );
+
+        let mut stack = frames( &[ 1, 2, 3 ] );
+        collapse_recursive_cycles( &mut stack );
+        assert_eq!( stack, frames( &[ 1, 2, 3 ] ) );
+    }
+
     #[test]
     fn collate_amd64_perfect_unwinding_pthread_cond_wait() {
         let data = load( "amd64-pthread_cond_wait.nperf" );
@@ -1869,4 +3599,220 @@ mod test {
             ]);
         }
     }
+
+    #[test]
+    fn parse_timestamp_bounds() {
+        assert_eq!( parse_timestamp_bound( "10" ), Ok( TimestampBound::Relative( 10.0 ) ) );
+        assert_eq!( parse_timestamp_bound( "1.5s" ), Ok( TimestampBound::Relative( 1.5 ) ) );
+        assert_eq!( parse_timestamp_bound( "250ms" ), Ok( TimestampBound::Relative( 0.25 ) ) );
+        assert_eq!( parse_timestamp_bound( "2m" ), Ok( TimestampBound::Relative( 120.0 ) ) );
+        assert_eq!( parse_timestamp_bound( "50%" ), Ok( TimestampBound::RelativePercent( 50 ) ) );
+        assert_eq!( parse_timestamp_bound( "@123.000000456" ), Ok( TimestampBound::Absolute( 123_000_000_456 ) ) );
+        assert_eq!( parse_timestamp_bound( "@123.5" ), Ok( TimestampBound::Absolute( 123_500_000_000 ) ) );
+        assert!( parse_timestamp_bound( "101%" ).is_err() );
+        assert!( parse_timestamp_bound( "abc" ).is_err() );
+        assert!( parse_timestamp_bound( "@1.0000000001" ).is_err() );
+    }
+
+    #[test]
+    fn parse_timestamp_ranges() {
+        assert_eq!( parse_timestamp_range( "10s..30s" ), Ok( (Some( TimestampBound::Relative( 10.0 ) ), Some( TimestampBound::Relative( 30.0 ) )) ) );
+        assert_eq!( parse_timestamp_range( "..50%" ), Ok( (None, Some( TimestampBound::RelativePercent( 50 ) )) ) );
+        assert_eq!( parse_timestamp_range( "5.." ), Ok( (Some( TimestampBound::Relative( 5.0 ) ), None) ) );
+        assert!( parse_timestamp_range( "5" ).is_err() );
+    }
+
+    #[test]
+    fn parse_path_mappings() {
+        assert_eq!( parse_path_mapping( "/opt/app=/home/me/app" ), Some( ("/opt/app", "/home/me/app") ) );
+        assert_eq!( parse_path_mapping( "/a=b=/c" ), Some( ("/a", "b=/c") ) );
+        assert_eq!( parse_path_mapping( "/opt/app" ), None );
+        assert_eq!( parse_path_mapping( "=/home/me/app" ), None );
+        assert_eq!( parse_path_mapping( "/opt/app=" ), None );
+    }
+
+    #[test]
+    fn map_source_paths() {
+        let mappings: Vec< (PathBuf, PathBuf) > = vec![
+            ("/build/src".into(), "/home/me/src".into()),
+            ("/build".into(), "/tmp/build".into())
+        ];
+
+        assert_eq!( map_path( &mappings, "/build/src/main.c" ), "/home/me/src/main.c" );
+        assert_eq!( map_path( &mappings, "/build/lib/util.c" ), "/tmp/build/lib/util.c" );
+        assert_eq!( map_path( &mappings, "/build/srcs/main.c" ), "/tmp/build/srcs/main.c" );
+        assert_eq!( map_path( &mappings, "/usr/include/stdio.h" ), "/usr/include/stdio.h" );
+        assert_eq!( map_path( &[], "/build/src/main.c" ), "/build/src/main.c" );
+    }
+
+    #[test]
+    fn match_local_files() {
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "bin" ).join( "amd64-inline_functions" );
+        let data = BinaryData::load_from_fs( &path ).unwrap();
+        let metadata = fs::metadata( &path ).unwrap();
+
+        let recorded = || Binary {
+            path: path.to_str().unwrap().to_owned(),
+            anonymized_path: None,
+            inode: Inode::empty(),
+            file_size: 0,
+            mtime: 0,
+            match_strategy: None,
+            is_local_file_rejected: false,
+            basename: "amd64-inline_functions".to_owned(),
+            string_tables: Arc::new( BinaryChunks::new() ),
+            symbol_table_count: 0,
+            symbol_tables_chunks: BinaryChunks::new(),
+            symbol_tables: Vec::new(),
+            symbols: None,
+            data: None,
+            debug_data: None,
+            load_headers: Vec::new(),
+            build_id: None,
+            debuglink: None
+        };
+
+        let mut binary = recorded();
+        binary.build_id = data.build_id().map( |build_id| build_id.to_owned() );
+        assert_eq!( binary.match_local_file( &data ), Ok( MatchStrategy::BuildId ) );
+
+        binary.build_id = Some( vec![ 0; 20 ] );
+        assert!( binary.match_local_file( &data ).is_err() );
+
+        let mut binary = recorded();
+        binary.inode = data.inode().unwrap();
+        assert_eq!( binary.match_local_file( &data ), Ok( MatchStrategy::Inode ) );
+
+        let mut binary = recorded();
+        binary.file_size = metadata.len();
+        binary.mtime = metadata.mtime() as u64;
+        assert_eq!( binary.match_local_file( &data ), Ok( MatchStrategy::SizeAndMtime ) );
+
+        binary.mtime += 1;
+        assert!( binary.match_local_file( &data ).is_err() );
+
+        assert_eq!( recorded().match_local_file( &data ), Ok( MatchStrategy::PathOnly ) );
+    }
+
+    #[test]
+    fn repack_cli_args_returns_errors() {
+        use crate::args;
+        use super::repack_cli_args;
+        use std::ffi::OsStr;
+        use structopt::StructOpt;
+
+        let repack = |extra: &[&str]| {
+            let mut argv = vec![ OsStr::new( "nperf" ) ];
+            argv.extend( extra.iter().map( OsStr::new ) );
+            argv.push( OsStr::new( "input.nperf" ) );
+            let args = args::SharedCollationArgs::from_iter_safe( &argv ).unwrap();
+            repack_cli_args( &args ).map( |_| () ).map_err( |error| error.to_string() )
+        };
+
+        assert!( repack( &[] ).is_ok() );
+        assert!( repack( &["--only-matching", "("] ).unwrap_err().contains( "--only-matching" ) );
+        assert!( repack( &["--omit=("] ).unwrap_err().contains( "`--omit`" ) );
+        assert!( repack( &["--exclude-matching", "("] ).unwrap_err().contains( "`--exclude-matching`" ) );
+        assert!( repack( &["--omit=(", "--exclude-matching", ")"] ).unwrap_err().contains( "`--omit`" ) );
+        assert!( repack( &["--symbol-rules", "/nonexistent/symbol-rules"] ).unwrap_err().starts_with( "cannot read" ) );
+        assert!( repack( &["--path-map", "nothing"] ).unwrap_err().contains( "--path-map" ) );
+    }
+
+    #[test]
+    fn round_robin_weights_the_samples() {
+        use crate::archive::{FramedPacket, Packet};
+        use speedy::Writable;
+        use std::convert::TryInto;
+
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp.nperf" );
+        let original = fs::read( &path ).unwrap();
+
+        // Splice the packet right after the header so that it applies to every sample.
+        let header_length = 4 + u32::from_le_bytes( original[ ..4 ].try_into().unwrap() ) as usize;
+        let mut data = original[ ..header_length ].to_vec();
+        FramedPacket::Known( Packet::RoundRobinCpus { active_cpus: 1, total_cpus: 4 } ).write_to_stream( &mut data ).unwrap();
+        data.extend_from_slice( &original[ header_length.. ] );
+
+        let weights = |data: Vec< u8 >| {
+            let mut weights = Vec::new();
+            read_data( ReadDataArgs::from_memory( data.into() ), |event| {
+                if let EventKind::Sample( sample ) = event.kind {
+                    weights.push( sample.weight );
+                }
+            }).unwrap();
+            weights
+        };
+
+        let plain = weights( original );
+        let weighted = weights( data );
+        assert!( !plain.is_empty() );
+        assert_eq!( plain.len(), weighted.len() );
+        assert!( plain.iter().all( |&weight| weight == 1.0 ) );
+        assert!( weighted.iter().all( |&weight| weight == 4.0 ) );
+    }
+
+    #[test]
+    fn multiplexing_weights_the_samples() {
+        use crate::archive::{FramedPacket, Packet};
+        use speedy::Writable;
+
+        // Just as when recording the packet comes after all of the samples.
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp.nperf" );
+        let mut data = fs::read( &path ).unwrap();
+        FramedPacket::Known( Packet::Multiplexing { time_enabled: 300, time_running: 100 } ).write_to_stream( &mut data ).unwrap();
+
+        let mut weights = Vec::new();
+        let state = read_data( ReadDataArgs::from_memory( data.into() ), |event| {
+            if let EventKind::Sample( sample ) = event.kind {
+                weights.push( sample.weight );
+            }
+        }).unwrap();
+
+        assert!( !weights.is_empty() );
+        assert!( weights.iter().all( |&weight| weight == 3.0 ) );
+        assert_eq!( state.multiplexing_ratio(), Some( 1.0 / 3.0 ) );
+    }
+
+    #[test]
+    fn process_cgroup_supersedes_the_initial_one() {
+        use crate::archive::{ArchiveReader, FramedPacket, Packet};
+        use speedy::Writable;
+
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( "amd64-usleep_in_a_loop_no_fp.nperf" );
+        let mut data = Vec::new();
+        for packet in ArchiveReader::new( fs::File::open( &path ).unwrap() ) {
+            let packet = packet.unwrap();
+            let pid = match packet {
+                FramedPacket::Known( Packet::ProcessInfo { pid, .. } ) => Some( pid ),
+                _ => None
+            };
+
+            packet.write_to_stream( &mut data ).unwrap();
+            if let Some( pid ) = pid {
+                FramedPacket::Known( Packet::ProcessCgroup { pid, cgroup: b"/moved.scope"[..].into() } ).write_to_stream( &mut data ).unwrap();
+            }
+        }
+
+        let mut cgroups = Vec::new();
+        read_data( ReadDataArgs::from_memory( data.into() ), |event| {
+            if let EventKind::Sample( sample ) = event.kind {
+                cgroups.push( sample.process.cgroup().map( |cgroup| cgroup.to_owned() ) );
+            }
+        }).unwrap();
+
+        assert!( !cgroups.is_empty() );
+        assert!( cgroups.iter().all( |cgroup| cgroup.as_ref().map( |cgroup| cgroup.as_str() ) == Some( "/moved.scope" ) ) );
+    }
+
+    #[test]
+    fn test_round_weights() {
+        use super::round_weights;
+
+        let mut weights = HashMap::new();
+        weights.insert( "a", 1.5 );
+        weights.insert( "b", 4.0 / 3.0 );
+        let weights = round_weights( weights );
+        assert_eq!( weights[ "a" ], 2 );
+        assert_eq!( weights[ "b" ], 1 );
+    }
 }
@@ -0,0 +1,92 @@
+use speedy::Endianness;
+
+pub(crate) struct Instruction {
+    pub address: u64,
+    pub length: u64,
+    pub text: String
+}
+
+#[cfg(feature = "disassembler")]
+pub(crate) fn disassemble( architecture: &str, endianness: Endianness, code: &[u8], address: u64 ) -> Result< Vec< Instruction >, String > {
+    use capstone::prelude::*;
+    use capstone::Endian;
+
+    let endian = match endianness {
+        Endianness::LittleEndian => Endian::Little,
+        Endianness::BigEndian => Endian::Big
+    };
+
+    // On ARM the lowest bit of a function's address is set for Thumb code.
+    let is_thumb = architecture == "arm" && address & 1 != 0;
+    let address = if is_thumb { address & !1 } else { address };
+
+    let capstone = match architecture {
+        "amd64" => Capstone::new().x86().mode( arch::x86::ArchMode::Mode64 ).build(),
+        "arm" => {
+            let mode = if is_thumb { arch::arm::ArchMode::Thumb } else { arch::arm::ArchMode::Arm };
+            Capstone::new().arm().mode( mode ).endian( endian ).build()
+        },
+        "aarch64" => Capstone::new().arm64().mode( arch::arm64::ArchMode::Arm ).endian( endian ).build(),
+        "mips64" => Capstone::new().mips().mode( arch::mips::ArchMode::Mips64 ).endian( endian ).build(),
+        _ => return Err( format!( "disassembling is not supported for '{}'", architecture ) )
+    };
+
+    let capstone = capstone.map_err( |err| format!( "failed to initialize the disassembler: {}", err ) )?;
+    let instructions = capstone.disasm_all( code, address ).map_err( |err| format!( "failed to disassemble: {}", err ) )?;
+    let instructions = instructions.iter().map( |instruction| {
+        let text = match (instruction.mnemonic(), instruction.op_str()) {
+            (Some( mnemonic ), Some( operands )) if !operands.is_empty() => format!( "{} {}", mnemonic, operands ),
+            (Some( mnemonic ), _) => mnemonic.to_owned(),
+            _ => "???".to_owned()
+        };
+
+        Instruction {
+            address: instruction.address(),
+            length: instruction.bytes().len() as u64,
+            text
+        }
+    }).collect();
+
+    Ok( instructions )
+}
+
+#[cfg(not(feature = "disassembler"))]
+pub(crate) fn disassemble( _: &str, _: Endianness, _: &[u8], _: u64 ) -> Result< Vec< Instruction >, String > {
+    Err( "nperf was compiled without the `disassembler` feature".into() )
+}
+
+/// Returns the index of the instruction which contains a given `address`.
+pub(crate) fn find_instruction( instructions: &[Instruction], address: u64 ) -> Option< usize > {
+    let index = match instructions.binary_search_by_key( &address, |instruction| instruction.address ) {
+        Ok( index ) => return Some( index ),
+        Err( 0 ) => return None,
+        Err( index ) => index - 1
+    };
+
+    let instruction = &instructions[ index ];
+    if address < instruction.address + instruction.length {
+        Some( index )
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_find_instruction() {
+    let instruction = |address, length| Instruction { address, length, text: String::new() };
+    let instructions = vec![
+        instruction( 0x1000, 4 ),
+        instruction( 0x1004, 1 ),
+        instruction( 0x1005, 3 ),
+        instruction( 0x1010, 2 )
+    ];
+
+    assert_eq!( find_instruction( &instructions, 0xfff ), None );
+    assert_eq!( find_instruction( &instructions, 0x1000 ), Some( 0 ) );
+    assert_eq!( find_instruction( &instructions, 0x1003 ), Some( 0 ) );
+    assert_eq!( find_instruction( &instructions, 0x1004 ), Some( 1 ) );
+    assert_eq!( find_instruction( &instructions, 0x1007 ), Some( 2 ) );
+    assert_eq!( find_instruction( &instructions, 0x1008 ), None );
+    assert_eq!( find_instruction( &instructions, 0x1011 ), Some( 3 ) );
+    assert_eq!( find_instruction( &instructions, 0x1012 ), None );
+}
@@ -0,0 +1,246 @@
+//! Pluggable output formats.
+//!
+//! An exporter gets every collated sample, already unwound and symbolized, and writes out whatever it wants
+//! at the end; it's selected with `nperf export --exporter <name>[:<config>]`. Extra exporters can be added
+//! with `register_exporter` by whoever embeds nperf (e.g. a separate binary which then calls `cmd_export::main`)
+//! before its command line is parsed.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::sync::Mutex;
+
+use crate::args::{self, Granularity};
+use crate::interner::StringInterner;
+use crate::data_reader::{State, DecodeOpts, EventKind, EventSample, FrameKind, read_data, repack_cli_args, unwind_failure_frame_name};
+
+/// A single sample; this is also what the `json-samples` format of `collate` emits.
+#[derive(Serialize, Debug)]
+pub struct Sample< 'a > {
+    pub timestamp: u64,
+    pub pid: u32,
+    pub tid: u32,
+    pub cpu: u32,
+    pub executable: &'a str,
+    pub thread_name: Option< &'a str >,
+    /// The frames are ordered from the leaf.
+    pub frames: Vec< Frame< 'a > >
+}
+
+#[derive(Serialize, Debug)]
+pub struct Frame< 'a > {
+    pub is_kernel: bool,
+    pub is_inline: bool,
+    pub address: u64,
+    pub symbol: Option< &'a str >,
+    pub module: Option< Cow< 'a, str > >,
+    pub file: Option< &'a str >,
+    pub line: Option< u64 >
+}
+
+impl< 'a > Frame< 'a > {
+    fn new( is_kernel: bool, address: u64 ) -> Self {
+        Frame {
+            is_kernel,
+            is_inline: false,
+            address,
+            symbol: None,
+            module: None,
+            file: None,
+            line: None
+        }
+    }
+}
+
+pub trait Exporter {
+    fn sample( &mut self, sample: &Sample ) -> Result< (), Box< dyn Error > >;
+
+    /// Called when the kernel had to drop some events.
+    fn lost( &mut self, _count: u64 ) -> Result< (), Box< dyn Error > > {
+        Ok(())
+    }
+
+    /// Called after all of the samples were seen; `output` is either the stdout or the file passed in `--output`.
+    fn finish( &mut self, output: &mut dyn io::Write ) -> Result< (), Box< dyn Error > >;
+}
+
+/// Creates an exporter from the `<config>` part of its `--exporter <name>:<config>`.
+pub type ExporterFactory = fn( &str ) -> Result< Box< dyn Exporter >, String >;
+
+lazy_static! {
+    static ref FACTORIES: Mutex< HashMap< String, ExporterFactory > > = Mutex::new( HashMap::new() );
+}
+
+/// Makes an exporter selectable with `--exporter <name>:<config>`.
+pub fn register_exporter( name: &str, factory: ExporterFactory ) {
+    FACTORIES.lock().unwrap().insert( name.into(), factory );
+}
+
+pub(crate) fn create_exporter( spec: &str ) -> Result< Box< dyn Exporter >, String > {
+    let (name, config) = match spec.find( ':' ) {
+        Some( index ) => (&spec[ ..index ], &spec[ index + 1.. ]),
+        None => (spec, "")
+    };
+
+    let factory = FACTORIES.lock().unwrap().get( name ).cloned()
+        .ok_or_else( || format!( "unknown exporter: '{}'", name ) )?;
+
+    factory( config ).map_err( |error| format!( "cannot create the '{}' exporter: {}", name, error ) )
+}
+
+pub(crate) fn to_sample< 'a >(
+    state: &'a State,
+    sample: &'a EventSample,
+    frames: &[FrameKind],
+    interner: &'a StringInterner
+) -> Sample< 'a > {
+    let mut output = Vec::with_capacity( sample.kernel_backtrace.len() + frames.len() );
+    // The context markers (e.g. PERF_CONTEXT_KERNEL) are skipped.
+    for &address in sample.kernel_backtrace.iter().filter( |&&address| address < !0 - 4095 ) {
+        let mut frame = Frame::new( true, address );
+        if let Some( symbol ) = state.get_kernel_symbol_by_address( address ) {
+            frame.symbol = Some( &symbol.name );
+            frame.module = Some( match symbol.module {
+                Some( ref module ) => format!( "[linux:{}]", module ).into(),
+                None => "[linux]".into()
+            });
+        } else {
+            frame.module = Some( "[linux]".into() );
+        }
+
+        output.push( frame );
+    }
+
+    for frame in frames {
+        let frame = match *frame {
+            FrameKind::User( address ) => Frame::new( false, address ),
+            FrameKind::UserBinary( ref binary_id, address ) => {
+                let mut frame = Frame::new( false, address );
+                frame.module = Some( state.get_binary( binary_id ).basename().into() );
+                frame
+            },
+            FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, file, line } => {
+                let mut frame = Frame::new( false, address );
+                frame.is_inline = is_inline;
+                frame.symbol = interner.resolve( symbol );
+                frame.module = Some( state.get_binary( binary_id ).basename().into() );
+                frame.file = file.and_then( |file| interner.resolve( file ) );
+                frame.line = line;
+                frame
+            },
+            FrameKind::UnwindFailure( failure ) => {
+                let mut frame = Frame::new( false, 0 );
+                frame.symbol = Some( unwind_failure_frame_name( failure ) );
+                frame
+            },
+            FrameKind::Python { symbol, file, line } => {
+                let mut frame = Frame::new( false, 0 );
+                frame.symbol = interner.resolve( symbol );
+                frame.module = Some( "[python]".into() );
+                frame.file = interner.resolve( file );
+                frame.line = line;
+                frame
+            },
+            FrameKind::Jit( symbol ) => {
+                let mut frame = Frame::new( false, 0 );
+                frame.symbol = interner.resolve( symbol );
+                frame.module = Some( "[jit]".into() );
+                frame
+            },
+            _ => unreachable!()
+        };
+
+        output.push( frame );
+    }
+
+    Sample {
+        timestamp: sample.timestamp,
+        pid: sample.process.pid(),
+        tid: sample.tid,
+        cpu: sample.cpu,
+        executable: sample.process.executable(),
+        thread_name: state.get_thread_name( sample.tid ),
+        frames: output
+    }
+}
+
+/// Feeds every sample from the profiling data to the `exporter`.
+pub(crate) fn run_exporter( args: &args::SharedCollationArgs, exporter: &mut dyn Exporter, output: &mut dyn io::Write ) -> Result< (), Box< dyn Error > > {
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: false,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: true,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let mut interner = StringInterner::new();
+    let mut frames = Vec::new();
+    let mut result = Ok(());
+    read_data( read_data_args, |event| {
+        if result.is_err() {
+            return;
+        }
+
+        match event.kind {
+            EventKind::Sample( sample ) => {
+                if !sample.try_decode( &event.state, &opts, &mut interner, Some( &mut frames ) ) {
+                    return; // Was filtered out.
+                }
+
+                result = exporter.sample( &to_sample( &event.state, &sample, &frames, &interner ) );
+                frames.clear();
+            },
+            EventKind::Lost { count } => result = exporter.lost( count ),
+            _ => {}
+        }
+    })?;
+
+    result?;
+    exporter.finish( output )
+}
+
+#[cfg(test)]
+struct CountingExporter {
+    count: usize
+}
+
+#[cfg(test)]
+impl Exporter for CountingExporter {
+    fn sample( &mut self, _: &Sample ) -> Result< (), Box< dyn Error > > {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish( &mut self, output: &mut dyn io::Write ) -> Result< (), Box< dyn Error > > {
+        write!( output, "{}", self.count )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_create_exporter() {
+    register_exporter( "counting", |config| {
+        if config.is_empty() {
+            Ok( Box::new( CountingExporter { count: 0 } ) )
+        } else {
+            Err( "unexpected config".into() )
+        }
+    });
+
+    let mut exporter = create_exporter( "counting" ).unwrap();
+    let sample = Sample { timestamp: 0, pid: 1, tid: 1, cpu: 0, executable: "foo", thread_name: None, frames: Vec::new() };
+    exporter.sample( &sample ).unwrap();
+    exporter.sample( &sample ).unwrap();
+
+    let mut output = Vec::new();
+    exporter.finish( &mut output ).unwrap();
+    assert_eq!( output, b"2" );
+
+    assert_eq!( create_exporter( "counting:foo" ).err().unwrap(), "cannot create the 'counting' exporter: unexpected config" );
+    assert_eq!( create_exporter( "nonexistent" ).err().unwrap(), "unknown exporter: 'nonexistent'" );
+}
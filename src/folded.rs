@@ -0,0 +1,195 @@
+// Support for reading Brendan Gregg's collapsed stack files, e.g. as produced by `stackcollapse-perf.pl`,
+// where every line consists of the frames ordered from the root separated by `;`, followed by a sample count.
+
+use std::fs::File;
+use std::ffi::{OsStr, OsString};
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read};
+use std::collections::HashMap;
+
+use crate::anonymize::{Anonymizer, NameKind};
+use crate::args::{Granularity, SharedCollationArgs};
+use crate::merge::is_stdin;
+
+/// Splits a single line into the stack and its sample count.
+pub(crate) fn parse_line( line: &str ) -> Option< (&str, u64) > {
+    let line = line.trim_end();
+    let index = line.rfind( ' ' )?;
+    let stack = line[ ..index ].trim_end();
+    if stack.is_empty() {
+        return None;
+    }
+
+    let count = line[ index + 1.. ].parse().ok()?;
+    Some( (stack, count) )
+}
+
+/// Splits a frame as written by the `collate` subcommand into its module and symbol.
+pub(crate) fn parse_frame( frame: &str ) -> (String, String) {
+    // Kernel frames are suffixed with `_[k]`, and their modules are kept in brackets, e.g. `[linux]`.
+    let (frame, is_kernel) = match frame.strip_suffix( "_[k]" ) {
+        Some( frame ) => (frame, true),
+        None => (frame, false)
+    };
+
+    if frame.ends_with( ']' ) {
+        if let Some( index ) = frame.rfind( " [" ) {
+            let module = if is_kernel { &frame[ index + 1.. ] } else { &frame[ index + 2..frame.len() - 1 ] };
+            return (module.to_owned(), frame[ ..index ].to_owned());
+        }
+    }
+
+    let module = if is_kernel { "[linux]" } else { "[unknown]" };
+    (module.to_owned(), frame.to_owned())
+}
+
+/// Anonymizes every frame of a stack in the same way as the frames from the recordings are.
+pub(crate) fn anonymize_stack( anonymizer: &Anonymizer, stack: &str ) -> String {
+    let mut output = String::with_capacity( stack.len() );
+    for (nth_frame, frame) in stack.split( ';' ).enumerate() {
+        if nth_frame != 0 {
+            output.push( ';' );
+        }
+
+        let (frame, is_kernel) = match frame.strip_suffix( "_[k]" ) {
+            Some( frame ) => (frame, true),
+            None => (frame, false)
+        };
+
+        let (symbol, module) = match frame.rfind( " [" ) {
+            Some( index ) if frame.ends_with( ']' ) => (&frame[ ..index ], Some( &frame[ index + 2..frame.len() - 1 ] )),
+            _ => (frame, None)
+        };
+
+        if is_kernel {
+            match module {
+                // The symbols from the kernel itself are public.
+                None | Some( "linux" ) => output.push_str( frame ),
+                Some( module ) => {
+                    output.push_str( &anonymizer.anonymize( NameKind::Symbol, symbol ) );
+                    output.push_str( " [" );
+                    output.push_str( &anonymizer.anonymize( NameKind::KernelModule, module ) );
+                    output.push( ']' );
+                }
+            }
+
+            output.push_str( "_[k]" );
+        } else {
+            output.push_str( &anonymizer.anonymize( NameKind::Symbol, symbol ) );
+            if let Some( module ) = module {
+                output.push_str( " [" );
+                output.push_str( &anonymizer.anonymize( NameKind::Binary, module ) );
+                output.push( ']' );
+            }
+        }
+    }
+
+    output
+}
+
+/// Checks whether a given file is a collapsed stack file instead of an nperf recording.
+pub(crate) fn is_folded_file( path: &OsStr ) -> io::Result< bool > {
+    let mut buffer = Vec::new();
+    File::open( path )?.take( 4096 ).read_to_end( &mut buffer )?;
+
+    let line = match buffer.iter().position( |&byte| byte == b'\n' ) {
+        Some( index ) => &buffer[ ..index ],
+        None => &buffer[ .. ]
+    };
+
+    Ok( std::str::from_utf8( line ).ok().and_then( parse_line ).is_some() )
+}
+
+/// Separates the collapsed stack files from the nperf recordings.
+pub(crate) fn partition_inputs( inputs: &[OsString] ) -> Result< (Vec< OsString >, Vec< OsString >), Box< dyn Error > > {
+    let mut recordings = Vec::new();
+    let mut folded = Vec::new();
+    for input in inputs {
+        // Peeking into stdin would consume it, so it's always assumed to be a recording.
+        if is_stdin( input ) {
+            recordings.push( input.clone() );
+            continue;
+        }
+
+        if is_folded_file( input ).map_err( |err| format!( "cannot read {:?}: {}", input, err ) )? {
+            folded.push( input.clone() );
+        } else {
+            recordings.push( input.clone() );
+        }
+    }
+
+    Ok( (recordings, folded) )
+}
+
+/// The collapsed stack files are already symbolized and don't have any timestamps,
+/// so the options which filter the samples or reshape their frames can't be applied to them.
+pub(crate) fn check_supported_options( args: &SharedCollationArgs, granularity: Granularity ) -> Result< (), Box< dyn Error > > {
+    let options = [
+        (!args.omit.is_empty(), "--omit"),
+        (!args.only_matching.is_empty(), "--only-matching"),
+        (!args.exclude_matching.is_empty(), "--exclude-matching"),
+        (!args.hide_above.is_empty(), "--hide-above"),
+        (!args.hide_below.is_empty(), "--hide-below"),
+        (args.prune_rules.is_some(), "--prune-rules"),
+        (args.symbol_rules.is_some(), "--symbol-rules"),
+        (args.collapse_recursion, "--collapse-recursion"),
+        (args.collapse_recursive_cycles, "--collapse-recursive-cycles"),
+        (args.from.is_some(), "--from"),
+        (args.to.is_some(), "--to"),
+        (args.range.is_some(), "--range"),
+        (granularity != Granularity::Function, "--granularity")
+    ];
+
+    match options.iter().find( |&&(is_set, _)| is_set ) {
+        Some( &(_, option) ) => Err( format!( "`{}` cannot be used with collapsed stack files as inputs", option ).into() ),
+        None => Ok(())
+    }
+}
+
+/// Reads the collapsed stack files, summing up the counts of identical stacks.
+pub(crate) fn read_folded( inputs: &[OsString] ) -> Result< HashMap< String, u64 >, Box< dyn Error > > {
+    let mut stacks = HashMap::new();
+    for input in inputs {
+        let fp = File::open( input ).map_err( |err| format!( "cannot open {:?}: {}", input, err ) )?;
+        for (nth, line) in BufReader::new( fp ).lines().enumerate() {
+            let line = line.map_err( |err| format!( "cannot read {:?}: {}", input, err ) )?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match parse_line( &line ) {
+                Some( (stack, count) ) => *stacks.entry( stack.to_owned() ).or_insert( 0 ) += count,
+                None => warn!( "Skipping malformed line #{} in {:?}", nth + 1, input )
+            }
+        }
+    }
+
+    Ok( stacks )
+}
+
+#[test]
+fn test_parse_line() {
+    assert_eq!( parse_line( "main [app];foo [app] 12" ), Some( ("main [app];foo [app]", 12) ) );
+    assert_eq!( parse_line( "a;b 3\r" ), Some( ("a;b", 3) ) );
+    assert_eq!( parse_line( "a;b" ), None );
+    assert_eq!( parse_line( " 3" ), None );
+    assert_eq!( parse_line( "a;b three" ), None );
+
+    assert_eq!( parse_frame( "foo [libc.so.6]" ), ("libc.so.6".to_owned(), "foo".to_owned()) );
+    assert_eq!( parse_frame( "do_syscall_64 [linux]_[k]" ), ("[linux]".to_owned(), "do_syscall_64".to_owned()) );
+    assert_eq!( parse_frame( "0xFFFFFFFF81000000_[k]" ), ("[linux]".to_owned(), "0xFFFFFFFF81000000".to_owned()) );
+    assert_eq!( parse_frame( "foo" ), ("[unknown]".to_owned(), "foo".to_owned()) );
+}
+
+#[test]
+fn test_anonymize_stack() {
+    use crate::anonymize::Anonymization;
+
+    let anonymizer = Anonymizer::new( Anonymization::Strip );
+    assert_eq!(
+        anonymize_stack( &anonymizer, "main [app];foo [libsecret.so];secret;do_syscall_64 [linux]_[k];ext4_write [ext4]_[k]" ),
+        "fn_1 [bin_1];fn_2 [bin_2];fn_3;do_syscall_64 [linux]_[k];fn_4 [module_1]_[k]"
+    );
+
+    assert_eq!( anonymize_stack( &anonymizer, "main [app];secret" ), "fn_1 [bin_1];fn_3" );
+}
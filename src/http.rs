@@ -0,0 +1,182 @@
+// A minimal HTTP/1.1 client and server; just enough to talk to the profiling servers
+// and to serve the symbolization requests, without pulling in a whole HTTP stack.
+
+use std::error::Error;
+use std::net::TcpStream;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String
+}
+
+pub(crate) fn parse_url( url: &str ) -> Result< Url, String > {
+    let rest = match url.strip_prefix( "http://" ) {
+        Some( rest ) => rest,
+        None => return Err( format!( "unsupported URL '{}': only plain `http://` URLs are supported", url ) )
+    };
+
+    let (authority, path) = match rest.find( '/' ) {
+        Some( index ) => (&rest[ ..index ], rest[ index.. ].trim_end_matches( '/' )),
+        None => (rest, "")
+    };
+
+    let (host, port) = match authority.rfind( ':' ) {
+        Some( index ) => {
+            let port = authority[ index + 1.. ].parse().map_err( |_| format!( "invalid port in the URL '{}'", url ) )?;
+            (&authority[ ..index ], port)
+        },
+        None => (authority, 80)
+    };
+
+    if host.is_empty() {
+        return Err( format!( "invalid URL '{}': missing host", url ) );
+    }
+
+    Ok( Url { host: host.to_owned(), port, path: path.to_owned() } )
+}
+
+#[cfg(feature = "native")]
+pub(crate) fn url_encode( string: &str ) -> String {
+    let mut output = String::with_capacity( string.len() );
+    for byte in string.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => output.push( byte as char ),
+            _ => output.push_str( &format!( "%{:02X}", byte ) )
+        }
+    }
+
+    output
+}
+
+/// Sends a POST request and returns the body of the response.
+pub(crate) fn post( url: &Url, path_and_query: &str, content_type: &str, body: &[u8] ) -> Result< Vec< u8 >, Box< dyn Error > > {
+    let timeout = Some( Duration::from_secs( 10 ) );
+    let mut stream = TcpStream::connect( (url.host.as_str(), url.port) )?;
+    stream.set_read_timeout( timeout )?;
+    stream.set_write_timeout( timeout )?;
+
+    write!(
+        stream,
+        "POST {}{} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path,
+        path_and_query,
+        url.host,
+        url.port,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all( body )?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    stream.read_to_end( &mut response )?;
+    let (head, body) = split_head( &response ).ok_or( "server responded with a malformed response" )?;
+    let status_line = head.lines().next().unwrap_or( "" );
+    let status: u32 = status_line.split_whitespace().nth( 1 ).and_then( |status| status.parse().ok() ).unwrap_or( 0 );
+    if !(200..300).contains( &status ) {
+        return Err( format!( "server responded with '{}'", status_line ).into() );
+    }
+
+    Ok( body.to_vec() )
+}
+
+fn split_head( data: &[u8] ) -> Option< (String, &[u8]) > {
+    let index = data.windows( 4 ).position( |window| window == b"\r\n\r\n" )?;
+    Some( (String::from_utf8_lossy( &data[ ..index ] ).into_owned(), &data[ index + 4.. ]) )
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec< u8 >
+}
+
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Reads a single request from a client.
+pub(crate) fn read_request( stream: &mut dyn Read, max_body_size: usize ) -> io::Result< Request > {
+    let invalid = |message: &str| io::Error::new( io::ErrorKind::InvalidData, message.to_owned() );
+
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    let (head, mut body) = loop {
+        if let Some( (head, body) ) = split_head( &data ) {
+            break (head, body.to_vec());
+        }
+
+        if data.len() > MAX_HEAD_SIZE {
+            return Err( invalid( "request header too big" ) );
+        }
+
+        let count = stream.read( &mut buffer )?;
+        if count == 0 {
+            return Err( invalid( "unexpected end of request" ) );
+        }
+        data.extend_from_slice( &buffer[ ..count ] );
+    };
+
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or( "" ).split_whitespace();
+    let method = request_line.next().ok_or_else( || invalid( "missing request method" ) )?.to_owned();
+    let path = request_line.next().ok_or_else( || invalid( "missing request path" ) )?.to_owned();
+
+    let mut content_length = 0;
+    for line in lines {
+        if let Some( index ) = line.find( ':' ) {
+            if line[ ..index ].trim().eq_ignore_ascii_case( "content-length" ) {
+                content_length = line[ index + 1.. ].trim().parse().map_err( |_| invalid( "invalid Content-Length" ) )?;
+            }
+        }
+    }
+
+    if content_length > max_body_size {
+        return Err( invalid( "request body too big" ) );
+    }
+
+    while body.len() < content_length {
+        let count = stream.read( &mut buffer )?;
+        if count == 0 {
+            return Err( invalid( "unexpected end of request" ) );
+        }
+        body.extend_from_slice( &buffer[ ..count ] );
+    }
+    body.truncate( content_length );
+
+    Ok( Request { method, path, body } )
+}
+
+pub(crate) fn write_response( stream: &mut dyn Write, status: &str, content_type: &str, body: &[u8] ) -> io::Result< () > {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all( body )?;
+    stream.flush()
+}
+
+#[test]
+fn test_parse_url() {
+    assert_eq!( parse_url( "http://localhost:4040" ), Ok( Url { host: "localhost".to_owned(), port: 4040, path: "".to_owned() } ) );
+    assert_eq!( parse_url( "http://example.com/pyroscope/" ), Ok( Url { host: "example.com".to_owned(), port: 80, path: "/pyroscope".to_owned() } ) );
+    assert!( parse_url( "https://example.com" ).is_err() );
+    assert!( parse_url( "http://:4040" ).is_err() );
+    assert!( parse_url( "http://localhost:port" ).is_err() );
+}
+
+#[test]
+fn test_read_request() {
+    let data = b"POST /symbolize HTTP/1.1\r\nHost: localhost\r\ncontent-length: 4\r\n\r\nbody";
+    let request = read_request( &mut &data[..], 1024 ).unwrap();
+    assert_eq!( request, Request { method: "POST".to_owned(), path: "/symbolize".to_owned(), body: b"body".to_vec() } );
+
+    assert!( read_request( &mut &data[ ..data.len() - 1 ], 1024 ).is_err() );
+    assert!( read_request( &mut &data[..], 3 ).is_err() );
+}
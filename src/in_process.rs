@@ -0,0 +1,166 @@
+//! Profiling of the current process from within itself, e.g. to capture a profile
+//! of a single integration test; no external profiler has to be attached.
+//!
+//! ```no_run
+//! let profiler = nperf_core::in_process::start()?;
+//! // ... the code to profile ...
+//! profiler.dump( "profile.nperf" )?;
+//! # Ok::< (), Box< dyn std::error::Error > >(())
+//! ```
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use perf_event_open::EventSource;
+
+use crate::args;
+use crate::archive::Packet;
+use crate::cmd_record::{open_perf_group, run};
+use crate::perf_group::Sampling;
+use crate::profiler::ProfilingController;
+use crate::recorder::RecordingSummary;
+
+static COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+
+/// A profiler sampling the current process in a background thread.
+///
+/// The profiler's own threads are sampled too, and will show up in the profile.
+pub struct InProcessProfiler {
+    path: PathBuf,
+    stop_flag: Arc< AtomicBool >,
+    thread: Option< thread::JoinHandle< RecordingSummary > >
+}
+
+/// Starts profiling the current process with the default frequency.
+pub fn start() -> Result< InProcessProfiler, Box< dyn Error > > {
+    start_with_frequency( 900 )
+}
+
+/// Starts profiling the current process with a given frequency.
+pub fn start_with_frequency( frequency: u32 ) -> Result< InProcessProfiler, Box< dyn Error > > {
+    let pid = process::id();
+    let path = std::env::temp_dir().join( format!( "nperf-{}-{}.nperf", pid, COUNTER.fetch_add( 1, Ordering::Relaxed ) ) );
+    let profiler_args = args::GenericProfilerArgs {
+        output: Some( path.clone().into_os_string() ),
+        sample_count: None,
+        time_limit: None,
+        lock_memory: false,
+        offline: false,
+        keep_raw: false,
+        python: false,
+        perf_map_agent: None,
+        perf_map_agent_interval: 10.0,
+        r2r_map_dir: Vec::new(),
+        panic_on_partial_backtrace: false,
+        progress: None,
+        progress_interval: 1.0,
+        memory_stats: false,
+        memory_stats_interval: 1.0,
+        self_profile: false,
+        process_filter: args::ProcessFilter::by_pid( pid )
+    };
+
+    let stop_flag = Arc::new( AtomicBool::new( false ) );
+    let (tx, rx) = mpsc::channel();
+    let thread = {
+        let stop_flag = stop_flag.clone();
+        thread::spawn( move || {
+            // The perf events have to be opened and processed on the same thread.
+            let mut controller = match ProfilingController::new_embedded( &profiler_args, true ) {
+                Ok( controller ) => controller,
+                Err( error ) => {
+                    let _ = tx.send( Err( error.to_string() ) );
+                    return RecordingSummary { sample_count: 0, lost_event_count: 0 };
+                }
+            };
+
+            controller.write_packet( Packet::ProfilingFrequency { frequency } );
+            controller.set_stop_flag( stop_flag );
+
+            let mut perf = match open_perf_group( &mut controller, Sampling::Frequency( frequency ), 24576, None, EventSource::HwCpuCycles ) {
+                Ok( perf ) => perf,
+                Err( error ) => {
+                    let _ = tx.send( Err( error.to_string() ) );
+                    return RecordingSummary { sample_count: 0, lost_event_count: 0 };
+                }
+            };
+
+            let _ = tx.send( Ok(()) );
+
+            let mut sample_count = 0;
+            let lost_event_count = run( &mut controller, &mut perf, false, |controller, dwarf_regs, sample| {
+                sample_count += 1;
+                controller.generate_sample( dwarf_regs, sample );
+            });
+
+            controller.finish( lost_event_count );
+
+            // This waits until everything is written out.
+            drop( controller );
+            RecordingSummary { sample_count, lost_event_count }
+        })
+    };
+
+    match rx.recv() {
+        Ok( Ok(()) ) => {},
+        Ok( Err( error ) ) => {
+            let _ = thread.join();
+            return Err( format!( "failed to start the in-process profiler: {}", error ).into() );
+        },
+        Err( _ ) => {
+            let _ = thread.join();
+            return Err( "failed to start the in-process profiler: the profiling thread died".into() );
+        }
+    }
+
+    Ok( InProcessProfiler {
+        path,
+        stop_flag,
+        thread: Some( thread )
+    })
+}
+
+impl InProcessProfiler {
+    fn finish( &mut self ) -> Result< RecordingSummary, Box< dyn Error > > {
+        self.stop_flag.store( true, Ordering::Relaxed );
+        let thread = self.thread.take().expect( "the profiler was already stopped" );
+        thread.join().map_err( |_| "the profiling thread panicked".into() )
+    }
+
+    /// Stops the profiling and discards the profile.
+    pub fn stop( mut self ) -> Result< RecordingSummary, Box< dyn Error > > {
+        let summary = self.finish();
+        let _ = fs::remove_file( &self.path );
+        summary
+    }
+
+    /// Stops the profiling and writes the profile into a given file, which can then
+    /// be analyzed with any of the usual subcommands.
+    pub fn dump< P: AsRef< Path > >( mut self, path: P ) -> Result< RecordingSummary, Box< dyn Error > > {
+        let summary = self.finish()?;
+        let path = path.as_ref();
+
+        // The temporary directory might be on another filesystem.
+        if fs::rename( &self.path, path ).is_err() {
+            fs::copy( &self.path, path ).map_err( |err| format!( "cannot write {:?}: {}", path, err ) )?;
+            let _ = fs::remove_file( &self.path );
+        }
+
+        Ok( summary )
+    }
+}
+
+impl Drop for InProcessProfiler {
+    fn drop( &mut self ) {
+        if self.thread.is_some() {
+            let _ = self.finish();
+            let _ = fs::remove_file( &self.path );
+        }
+    }
+}
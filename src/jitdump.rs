@@ -0,0 +1,259 @@
+//! Support for the `jit-<pid>.dump` files in which the JIT compilers (e.g. wasmtime with `--profile=jitdump`,
+//! V8 with `--perf-prof`, or the JVM with perf's JVMTI agent) describe the code they've generated.
+//!
+//! The runtimes `mmap` the file so that it shows up in the memory maps of the process, which is how we find it.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Instant;
+
+use proc_maps::Region;
+use nwind::RangeMap;
+
+use crate::perf_map::POLL_INTERVAL;
+
+const MAGIC: u32 = 0x4A69_5444;
+// The timestamps are from the TSC instead of from `CLOCK_MONOTONIC`.
+const FLAG_ARCH_TIMESTAMP: u64 = 1;
+
+const RECORD_HEADER_SIZE: usize = 16;
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_MOVE: u32 = 1;
+
+struct Reader< 'a > {
+    data: &'a [u8],
+    is_swapped: bool
+}
+
+impl< 'a > Reader< 'a > {
+    fn u32( &self, offset: usize ) -> Option< u32 > {
+        let bytes = self.data.get( offset..offset + 4 )?;
+        let value = u32::from_le_bytes( [bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ]] );
+        Some( if self.is_swapped { value.swap_bytes() } else { value } )
+    }
+
+    fn u64( &self, offset: usize ) -> Option< u64 > {
+        let first = self.u32( offset )? as u64;
+        let second = self.u32( offset + 4 )? as u64;
+        Some( if self.is_swapped { (first << 32) | second } else { (second << 32) | first } )
+    }
+}
+
+struct Header {
+    size: usize,
+    is_swapped: bool,
+    has_arch_timestamps: bool
+}
+
+fn parse_header( data: &[u8] ) -> Option< Header > {
+    let mut reader = Reader { data, is_swapped: false };
+    match reader.u32( 0 )? {
+        MAGIC => {},
+        magic if magic == MAGIC.swap_bytes() => reader.is_swapped = true,
+        _ => return None
+    }
+
+    Some( Header {
+        size: reader.u32( 8 )? as usize,
+        is_swapped: reader.is_swapped,
+        has_arch_timestamps: reader.u64( 32 )? & FLAG_ARCH_TIMESTAMP != 0
+    })
+}
+
+/// A piece of code which was either loaded or moved.
+#[derive(PartialEq, Debug)]
+struct CodeRecord {
+    timestamp: u64,
+    address: u64,
+    size: u64,
+    name: String
+}
+
+/// Parses all of the complete records from `data`, returning how many bytes were consumed;
+/// the `names` are needed to name the moved code, which is identified only by its index.
+fn parse_records( data: &[u8], is_swapped: bool, names: &mut HashMap< u64, String >, output: &mut Vec< CodeRecord > ) -> usize {
+    let reader = Reader { data, is_swapped };
+    let mut offset = 0;
+    while let (Some( kind ), Some( size ), Some( timestamp )) = (reader.u32( offset ), reader.u32( offset + 4 ), reader.u64( offset + 8 )) {
+        let size = size as usize;
+        if size < RECORD_HEADER_SIZE || offset + size > data.len() {
+            break;
+        }
+
+        let record = &data[ offset..offset + size ];
+        let record_reader = Reader { data: record, is_swapped };
+        match kind {
+            JIT_CODE_LOAD => {
+                if let (Some( address ), Some( code_size ), Some( index )) = (record_reader.u64( 32 ), record_reader.u64( 40 ), record_reader.u64( 48 )) {
+                    let name = record.get( 56.. ).unwrap_or( &[] );
+                    let name = &name[ ..name.iter().position( |&byte| byte == 0 ).unwrap_or( name.len() ) ];
+                    let name = String::from_utf8_lossy( name ).into_owned();
+                    names.insert( index, name.clone() );
+                    output.push( CodeRecord { timestamp, address, size: code_size, name } );
+                }
+            },
+            JIT_CODE_MOVE => {
+                if let (Some( address ), Some( code_size ), Some( index )) = (record_reader.u64( 40 ), record_reader.u64( 48 ), record_reader.u64( 56 )) {
+                    if let Some( name ) = names.get( &index ) {
+                        output.push( CodeRecord { timestamp, address, size: code_size, name: name.clone() } );
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        offset += size;
+    }
+
+    offset
+}
+
+/// Periodically reads the new records of a process' jitdump file, if it has one.
+pub(crate) struct JitDumpWatcher {
+    pid: u32,
+    last_check: Option< Instant >,
+    path: Option< String >,
+    header: Option< Header >,
+    offset: u64,
+    names: HashMap< u64, String >
+}
+
+impl JitDumpWatcher {
+    pub fn new( pid: u32 ) -> Self {
+        JitDumpWatcher {
+            pid,
+            last_check: None,
+            path: None,
+            header: None,
+            offset: 0,
+            names: HashMap::new()
+        }
+    }
+
+    fn find_path( maps: &RangeMap< Region > ) -> Option< String > {
+        maps.values()
+            .map( |region| region.name.as_str() )
+            .find( |name| {
+                let filename = name.rsplit( '/' ).next().unwrap_or( name );
+                filename.starts_with( "jit-" ) && filename.ends_with( ".dump" )
+            })
+            .map( |name| name.to_owned() )
+    }
+
+    /// Returns the code which was loaded since the last time the file was checked, in the perf map format,
+    /// together with when it was loaded; the `maps` are the memory maps of the process.
+    pub fn poll( &mut self, maps: &RangeMap< Region > ) -> Option< (Option< u64 >, Vec< u8 >) > {
+        if self.last_check.map( |last_check| last_check.elapsed() < POLL_INTERVAL ).unwrap_or( false ) {
+            return None;
+        }
+
+        self.last_check = Some( Instant::now() );
+        if self.path.is_none() {
+            self.path = Self::find_path( maps );
+        }
+
+        // The path is the one which the process sees, which might be in another mount namespace.
+        let path = format!( "/proc/{}/root{}", self.pid, self.path.as_ref()? );
+        let mut fp = File::open( &path ).ok()?;
+        fp.seek( SeekFrom::Start( self.offset ) ).ok()?;
+        let mut data = Vec::new();
+        fp.read_to_end( &mut data ).ok()?;
+
+        let mut consumed = 0;
+        if self.header.is_none() {
+            match parse_header( &data ) {
+                Some( header ) => {
+                    consumed = header.size;
+                    self.header = Some( header );
+                },
+                None => {
+                    debug!( "{:?} doesn't have a valid jitdump header yet", path );
+                    return None;
+                }
+            }
+        }
+
+        let header = self.header.as_ref().unwrap();
+        let mut records = Vec::new();
+        consumed += parse_records( data.get( consumed.. ).unwrap_or( &[] ), header.is_swapped, &mut self.names, &mut records );
+        self.offset += consumed as u64;
+
+        if records.is_empty() {
+            return None;
+        }
+
+        let mut output = Vec::new();
+        for record in &records {
+            output.extend_from_slice( format!( "{:x} {:x} {}\n", record.address, record.size, record.name.replace( '\n', " " ) ).as_bytes() );
+        }
+
+        let timestamp = if header.has_arch_timestamps { None } else { records.iter().map( |record| record.timestamp ).min() };
+        Some( (timestamp, output) )
+    }
+}
+
+#[cfg(test)]
+fn build_record( kind: u32, timestamp: u64, fields: &[u64], name: &str ) -> Vec< u8 > {
+    let mut body = Vec::new();
+    body.extend_from_slice( &1234_u32.to_le_bytes() );
+    body.extend_from_slice( &1235_u32.to_le_bytes() );
+    for field in fields {
+        body.extend_from_slice( &field.to_le_bytes() );
+    }
+    if kind == JIT_CODE_LOAD {
+        body.extend_from_slice( name.as_bytes() );
+        body.push( 0 );
+        // The code itself.
+        body.extend_from_slice( &[0x90; 4] );
+    }
+
+    let mut record = Vec::new();
+    record.extend_from_slice( &kind.to_le_bytes() );
+    record.extend_from_slice( &((RECORD_HEADER_SIZE + body.len()) as u32).to_le_bytes() );
+    record.extend_from_slice( &timestamp.to_le_bytes() );
+    record.extend_from_slice( &body );
+    record
+}
+
+#[test]
+fn test_parse_header() {
+    let mut data = Vec::new();
+    data.extend_from_slice( &MAGIC.to_le_bytes() );
+    data.extend_from_slice( &1_u32.to_le_bytes() );
+    data.extend_from_slice( &40_u32.to_le_bytes() );
+    data.extend_from_slice( &[0; 20] );
+    data.extend_from_slice( &FLAG_ARCH_TIMESTAMP.to_le_bytes() );
+
+    let header = parse_header( &data ).unwrap();
+    assert_eq!( header.size, 40 );
+    assert!( !header.is_swapped );
+    assert!( header.has_arch_timestamps );
+
+    let swapped: Vec< u8 > = MAGIC.to_be_bytes().iter().chain( &40_u32.to_be_bytes() ).chain( &40_u32.to_be_bytes() ).chain( &[0; 28] ).cloned().collect();
+    let header = parse_header( &swapped ).unwrap();
+    assert_eq!( header.size, 40 );
+    assert!( header.is_swapped );
+    assert!( !header.has_arch_timestamps );
+
+    assert!( parse_header( b"garbage garbage garbage garbage garbage garbage" ).is_none() );
+}
+
+#[test]
+fn test_parse_records() {
+    let mut data = Vec::new();
+    data.extend( build_record( JIT_CODE_LOAD, 100, &[0x7000, 0x7000, 0x40, 1], "wasm[0]::function[3]::fib" ) );
+    data.extend( build_record( 7, 110, &[], "" ) );
+    data.extend( build_record( JIT_CODE_MOVE, 120, &[0x7000, 0x7000, 0x8000, 0x40, 1], "" ) );
+    let complete_length = data.len();
+    let incomplete = build_record( JIT_CODE_LOAD, 130, &[0x9000, 0x9000, 0x10, 2], "incomplete" );
+    data.extend_from_slice( &incomplete[ ..incomplete.len() - 1 ] );
+
+    let mut names = HashMap::new();
+    let mut records = Vec::new();
+    assert_eq!( parse_records( &data, false, &mut names, &mut records ), complete_length );
+    assert_eq!( records, vec![
+        CodeRecord { timestamp: 100, address: 0x7000, size: 0x40, name: "wasm[0]::function[3]::fib".to_owned() },
+        CodeRecord { timestamp: 120, address: 0x8000, size: 0x40, name: "wasm[0]::function[3]::fib".to_owned() }
+    ]);
+}
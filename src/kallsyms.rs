@@ -1,13 +1,86 @@
+#[cfg(feature = "native")]
+use std::fs;
 use std::cmp::max;
 use nwind::RangeMap;
 
-#[derive(Debug)]
+#[cfg(feature = "native")]
+use crate::capabilities::Capabilities;
+#[cfg(feature = "native")]
+use crate::reader;
+
+#[derive(Clone, Debug)]
 pub struct KernelSymbol {
     pub address: u64,
     pub name: String,
     pub module: Option< String >
 }
 
+/// Checks whether the symbols' addresses are actually visible; when they're hidden
+/// due to `kernel.kptr_restrict` (or missing privileges) every address is zero.
+pub fn has_addresses( kallsyms: &[u8] ) -> bool {
+    String::from_utf8_lossy( kallsyms ).lines().any( |line| {
+        line.split_whitespace().next().and_then( |address| u64::from_str_radix( address, 16 ).ok() ).map( |address| address != 0 ).unwrap_or( false )
+    })
+}
+
+/// Reads `/proc/kallsyms`, returning `None` if the addresses are hidden.
+#[cfg(feature = "native")]
+pub fn read() -> Option< Vec< u8 > > {
+    let kallsyms = match fs::read( "/proc/kallsyms" ) {
+        Ok( kallsyms ) => kallsyms,
+        Err( error ) => {
+            warn!( "Cannot read /proc/kallsyms: {}; kernel frames won't be symbolized", error );
+            return None;
+        }
+    };
+
+    if !has_addresses( &kallsyms ) {
+        let reason = Capabilities::detect().check_kernel_addresses().err().unwrap_or_else( || "of missing privileges".to_owned() );
+        warn!( "The addresses in /proc/kallsyms are hidden since {}; kernel frames won't be symbolized", reason );
+        warn!( "Run as root or with `sysctl kernel.kptr_restrict=0` to fix this, or pass a copy of /proc/kallsyms to `--kallsyms` when analyzing the data" );
+        return None;
+    }
+
+    Some( kallsyms )
+}
+
+/// Symbolizes the kernel frames of a sample on the fly, formatting them as `symbol [linux]`.
+#[cfg(feature = "native")]
+pub fn symbolize_backtrace( kallsyms: &RangeMap< KernelSymbol >, backtrace: &[u64], output: &mut Vec< String > ) {
+    for &address in backtrace {
+        // Skip the context markers.
+        if address >= !0 - 4095 {
+            continue;
+        }
+
+        match kallsyms.get_value( address ) {
+            Some( symbol ) => output.push( format!( "{} [linux]", symbol.name ) ),
+            None => output.push( format!( "0x{:016X} [linux]", address ) )
+        }
+    }
+}
+
+/// Symbolizes the kernel frames of a sample on the fly into structured frames.
+#[cfg(feature = "native")]
+pub fn decode_backtrace( kallsyms: &RangeMap< KernelSymbol >, backtrace: &[u64], output: &mut Vec< reader::Frame > ) {
+    for &address in backtrace {
+        if address >= !0 - 4095 {
+            continue;
+        }
+
+        let symbol = kallsyms.get_value( address );
+        output.push( reader::Frame {
+            name: symbol.map( |symbol| symbol.name.clone() ).unwrap_or_else( || format!( "0x{:016X}", address ) ),
+            binary: symbol.and_then( |symbol| symbol.module.clone() ),
+            address: Some( address ),
+            file: None,
+            line: None,
+            is_inline: false,
+            is_kernel: true
+        });
+    }
+}
+
 pub fn parse( kallsyms: &[u8] ) -> RangeMap< KernelSymbol > {
     let kallsyms = String::from_utf8_lossy( kallsyms );
     let mut symbols = Vec::new();
@@ -63,3 +136,11 @@ pub fn parse( kallsyms: &[u8] ) -> RangeMap< KernelSymbol > {
     debug!( "Loaded {} kernel symbols", syms.len() );
     RangeMap::from_vec( syms )
 }
+
+#[test]
+fn test_has_addresses() {
+    assert!( has_addresses( b"0000000000000000 A irq_stack_union\nffffffff81000000 T _text\n" ) );
+    assert!( !has_addresses( b"0000000000000000 A irq_stack_union\n0000000000000000 T _text\n" ) );
+    assert!( !has_addresses( b"(null) T _text\n" ) );
+    assert!( !has_addresses( b"" ) );
+}
@@ -7,30 +7,101 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "native"))]
 #[macro_use]
 extern crate quickcheck;
 
 mod utils;
 
 pub mod args;
+pub mod config;
+pub mod reader;
+#[cfg(feature = "native")]
+pub mod recorder;
+#[cfg(feature = "native")]
+pub mod in_process;
 mod raw_data;
+#[cfg(feature = "native")]
 mod perf_group;
+#[cfg(feature = "native")]
 mod perf_arch;
 mod archive;
+#[cfg(feature = "native")]
 mod execution_queue;
 mod kallsyms;
+#[cfg(feature = "native")]
+mod capabilities;
+#[cfg(feature = "native")]
 mod ps;
 mod stack_reader;
 mod metadata;
+#[cfg(feature = "native")]
 mod mount_info;
+#[cfg(feature = "native")]
 mod profiler;
 mod interner;
 mod data_reader;
+mod merge;
+mod folded;
+mod pprof;
+mod http;
+#[cfg(feature = "native")]
+mod push;
+#[cfg(feature = "native")]
+mod triggers;
+mod anonymize;
+#[cfg(feature = "native")]
+mod progress;
+mod self_profile;
+pub mod quality;
+#[cfg(feature = "native")]
+mod timer_sampler;
+mod speedscope;
+mod disassembler;
+mod script;
+mod python;
+mod perf_map;
+#[cfg(feature = "native")]
+mod jitdump;
+pub mod symbol_resolver;
+mod symbol_cache;
+mod unwind_check;
+pub mod exporter;
+#[cfg(feature = "native")]
 pub mod cmd_record;
+#[cfg(feature = "native")]
+pub mod cmd_top;
 #[cfg(feature = "inferno")]
 pub mod cmd_flamegraph;
 pub mod cmd_csv;
+pub mod cmd_timeline;
 pub mod cmd_collate;
+pub mod cmd_export;
+pub mod cmd_merge;
+pub mod cmd_trim;
+pub mod cmd_reprocess;
+pub mod cmd_report;
+pub mod cmd_annotate;
+pub mod cmd_analyze_core;
+pub mod cmd_dump_syms;
+pub mod cmd_show_cfi;
+pub mod cmd_dump_unwind_tables;
+pub mod cmd_verify_unwind_tables;
+pub mod cmd_symbols;
+pub mod cmd_resolve;
+pub mod cmd_symbolize_server;
+#[cfg(feature = "native")]
+pub mod cmd_backtrace;
+#[cfg(feature = "native")]
+pub mod cmd_watchdog;
+#[cfg(feature = "native")]
+pub mod cmd_stat;
+pub mod cmd_bench;
+pub mod cmd_diff;
+#[cfg(feature = "tui")]
+pub mod cmd_tui;
 pub mod cmd_metadata;
+pub mod cmd_info;
+pub mod cmd_buildid_list;
+pub mod cmd_archive;
 pub mod cmd_trace_events;
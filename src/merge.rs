@@ -0,0 +1,518 @@
+// Support for reading multiple recordings as if they were a single one.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use nwind::BinaryId;
+
+use crate::archive::{ArchiveReader, Inode, Packet};
+
+type PacketIter = Box< dyn Iterator< Item = io::Result< Packet< 'static > > > >;
+
+/// Whether the input should be read from stdin, in which case it can only be read once.
+pub(crate) fn is_stdin( path: &OsStr ) -> bool {
+    path == "-"
+}
+
+fn open( path: &OsStr ) -> Result< PacketIter, Box< dyn Error > > {
+    if is_stdin( path ) {
+        return Ok( Box::new( ArchiveReader::new( io::BufReader::new( io::stdin() ) ).validate_header()?.skip_unknown() ) );
+    }
+
+    let fp = fs::File::open( path ).map_err( |err| format!( "cannot open {:?}: {}", path, err ) )?;
+    Ok( Box::new( ArchiveReader::new( fp ).validate_header()?.skip_unknown() ) )
+}
+
+pub(crate) fn to_binary_id( inode: Inode, path: &[u8] ) -> BinaryId {
+    if inode.is_invalid() {
+        BinaryId::ByName( String::from_utf8_lossy( path ).into_owned() )
+    } else {
+        BinaryId::ByInode( inode )
+    }
+}
+
+/// The information about a single input which is necessary to merge it with the others.
+#[derive(Default)]
+struct Summary {
+    architecture: Option< String >,
+    binaries: Vec< BinaryId >,
+    build_ids: HashMap< BinaryId, Vec< u8 > >,
+    ids: HashSet< u32 >,
+    first_timestamp: Option< u64 >,
+    kernel_text_address: Option< u64 >
+}
+
+/// Returns the address of the start of the kernel's code, which
+/// changes between reboots when the kernel's address space is randomized.
+fn find_kernel_text_address( kallsyms: &[u8] ) -> Option< u64 > {
+    String::from_utf8_lossy( kallsyms ).lines().filter_map( |line| {
+        let mut iter = line.split_whitespace();
+        let address = iter.next()?;
+        let _kind = iter.next()?;
+        if iter.next()? != "_text" {
+            return None;
+        }
+
+        u64::from_str_radix( address, 16 ).ok()
+    }).next()
+}
+
+fn summarize( path: &OsStr ) -> Result< Summary, Box< dyn Error > > {
+    let mut summary = Summary::default();
+    for packet in open( path )? {
+        match packet? {
+            Packet::MachineInfo { architecture, .. } => {
+                summary.architecture = Some( architecture.into_owned() );
+            },
+            Packet::BinaryInfo { inode, path, .. } => {
+                summary.binaries.push( to_binary_id( inode, &path ) );
+            },
+            Packet::BuildId { inode, path, build_id } => {
+                summary.build_ids.insert( to_binary_id( inode, &path ), build_id );
+            },
+            Packet::ProcessInfo { pid, .. } => {
+                summary.ids.insert( pid );
+            },
+            Packet::FileBlob { ref path, ref data } if path.as_ref() == b"/proc/kallsyms" => {
+                summary.kernel_text_address = find_kernel_text_address( data );
+            },
+            Packet::ThreadName { pid, tid, .. } => {
+                summary.ids.insert( pid );
+                summary.ids.insert( tid );
+            },
+            Packet::Sample { timestamp, pid, tid, .. } | Packet::RawSample { timestamp, pid, tid, .. } => {
+                summary.ids.insert( pid );
+                summary.ids.insert( tid );
+                summary.first_timestamp = Some( summary.first_timestamp.map( |first| first.min( timestamp ) ).unwrap_or( timestamp ) );
+            },
+            _ => {}
+        }
+    }
+
+    Ok( summary )
+}
+
+/// Describes how the packets of a single input should be rewritten
+/// so that they don't conflict with the packets from the other inputs.
+#[derive(Default)]
+struct Remap {
+    is_first: bool,
+    ids: HashMap< u32, u32 >,
+    inodes: HashMap< Inode, Inode >,
+    /// Binaries which were already emitted by one of the previous inputs.
+    duplicate_binaries: HashSet< BinaryId >,
+    timestamp_delta: i64,
+    kernel_address_delta: i64
+}
+
+impl Remap {
+    fn id( &self, id: u32 ) -> u32 {
+        self.ids.get( &id ).cloned().unwrap_or( id )
+    }
+
+    fn inode( &self, inode: Inode ) -> Inode {
+        self.inodes.get( &inode ).cloned().unwrap_or( inode )
+    }
+
+    fn timestamp( &self, timestamp: u64 ) -> u64 {
+        (timestamp as i64).wrapping_add( self.timestamp_delta ) as u64
+    }
+
+    fn kernel_backtrace( &self, backtrace: Cow< 'static, [u64] > ) -> Cow< 'static, [u64] > {
+        if self.kernel_address_delta == 0 {
+            return backtrace;
+        }
+
+        backtrace.iter().map( |&address| {
+            // The addresses at the very end of the address space are used as markers.
+            if address >= !0 - 4095 {
+                address
+            } else {
+                (address as i64).wrapping_add( self.kernel_address_delta ) as u64
+            }
+        }).collect()
+    }
+
+    fn is_duplicate( &self, inode: Inode, path: &[u8] ) -> bool {
+        !self.duplicate_binaries.is_empty() && self.duplicate_binaries.contains( &to_binary_id( inode, path ) )
+    }
+
+    fn apply( &self, packet: Packet< 'static > ) -> Option< Packet< 'static > > {
+        if self.is_first {
+            return Some( packet );
+        }
+
+        let packet = match packet {
+            Packet::Header { .. } |
+            Packet::MachineInfo { .. } |
+            Packet::ProfilingFrequency { .. } => return None,
+            Packet::FileBlob { ref path, .. } if path.as_ref() == b"/proc/kallsyms" => return None,
+            Packet::BinaryInfo { ref inode, ref path, .. } |
+            Packet::StringTable { ref inode, ref path, .. } |
+            Packet::SymbolTable { ref inode, ref path, .. } |
+            Packet::BinaryBlob { ref inode, ref path, .. } |
+            Packet::BuildId { ref inode, ref path, .. } if self.is_duplicate( *inode, path ) => return None,
+            Packet::ProcessInfo { pid, executable, binary_id, cgroup } => Packet::ProcessInfo {
+                pid: self.id( pid ),
+                executable,
+                binary_id: self.inode( binary_id ),
+                cgroup
+            },
+            Packet::ProcessCgroup { pid, cgroup } => Packet::ProcessCgroup {
+                pid: self.id( pid ),
+                cgroup
+            },
+            Packet::Sample { timestamp, pid, tid, cpu, kernel_backtrace, user_backtrace, unwind_failure, python_backtrace } => Packet::Sample {
+                timestamp: self.timestamp( timestamp ),
+                pid: self.id( pid ),
+                tid: self.id( tid ),
+                cpu,
+                kernel_backtrace: self.kernel_backtrace( kernel_backtrace ),
+                user_backtrace,
+                unwind_failure,
+                python_backtrace
+            },
+            Packet::RawSample { timestamp, pid, tid, cpu, kernel_backtrace, stack, regs } => Packet::RawSample {
+                timestamp: self.timestamp( timestamp ),
+                pid: self.id( pid ),
+                tid: self.id( tid ),
+                cpu,
+                kernel_backtrace: self.kernel_backtrace( kernel_backtrace ),
+                stack,
+                regs
+            },
+            Packet::BinaryInfo { inode, is_shared_object, symbol_table_count, path, debuglink, load_headers, file_size, mtime } => Packet::BinaryInfo {
+                inode: self.inode( inode ),
+                is_shared_object,
+                symbol_table_count,
+                path,
+                debuglink,
+                load_headers,
+                file_size,
+                mtime
+            },
+            Packet::StringTable { inode, offset, data, path } => Packet::StringTable {
+                inode: self.inode( inode ),
+                offset,
+                data,
+                path
+            },
+            Packet::SymbolTable { inode, offset, string_table_offset, is_dynamic, data, path } => Packet::SymbolTable {
+                inode: self.inode( inode ),
+                offset,
+                string_table_offset,
+                is_dynamic,
+                data,
+                path
+            },
+            Packet::BinaryBlob { inode, path, data } => Packet::BinaryBlob {
+                inode: self.inode( inode ),
+                path,
+                data
+            },
+            Packet::BuildId { inode, build_id, path } => Packet::BuildId {
+                inode: self.inode( inode ),
+                build_id,
+                path
+            },
+            Packet::ThreadName { pid, tid, name } => Packet::ThreadName {
+                pid: self.id( pid ),
+                tid: self.id( tid ),
+                name
+            },
+            Packet::MemoryRegionMap { pid, range, is_read, is_write, is_executable, is_shared, file_offset, inode, major, minor, name } => {
+                let inode = self.inode( Inode { inode, dev_major: major, dev_minor: minor } );
+                Packet::MemoryRegionMap {
+                    pid: self.id( pid ),
+                    range,
+                    is_read,
+                    is_write,
+                    is_executable,
+                    is_shared,
+                    file_offset,
+                    inode: inode.inode,
+                    major: inode.dev_major,
+                    minor: inode.dev_minor,
+                    name
+                }
+            },
+            Packet::MemoryRegionUnmap { pid, range } => Packet::MemoryRegionUnmap {
+                pid: self.id( pid ),
+                range
+            },
+            Packet::Deprecated_BinaryMap { pid, inode, base_address } => Packet::Deprecated_BinaryMap {
+                pid: self.id( pid ),
+                inode: self.inode( inode ),
+                base_address
+            },
+            Packet::Deprecated_BinaryUnmap { pid, inode, base_address } => Packet::Deprecated_BinaryUnmap {
+                pid: self.id( pid ),
+                inode: self.inode( inode ),
+                base_address
+            },
+            Packet::BinaryLoaded { pid, inode, name } => Packet::BinaryLoaded {
+                pid: self.id( pid ),
+                inode: inode.map( |inode| self.inode( inode ) ),
+                name
+            },
+            Packet::BinaryUnloaded { pid, inode, name } => Packet::BinaryUnloaded {
+                pid: self.id( pid ),
+                inode: inode.map( |inode| self.inode( inode ) ),
+                name
+            },
+            Packet::ContextSwitch { pid, cpu, kind } => Packet::ContextSwitch {
+                pid: self.id( pid ),
+                cpu,
+                kind
+            },
+            Packet::Stall { timestamp, pid, tid, duration, kind, backtrace } => Packet::Stall {
+                timestamp: self.timestamp( timestamp ),
+                pid: self.id( pid ),
+                tid: self.id( tid ),
+                duration,
+                kind,
+                backtrace
+            },
+            Packet::PythonFunction { pid, id, name, file, first_line } => Packet::PythonFunction {
+                pid: self.id( pid ),
+                id,
+                name,
+                file,
+                first_line
+            },
+            Packet::PerfMap { pid, timestamp, data } => Packet::PerfMap {
+                pid: self.id( pid ),
+                timestamp: self.timestamp( timestamp ),
+                data
+            },
+            packet => packet
+        };
+
+        Some( packet )
+    }
+}
+
+fn build_remaps( summaries: &[Summary] ) -> Result< Vec< Remap >, Box< dyn Error > > {
+    let architecture = summaries[ 0 ].architecture.as_ref();
+    for summary in summaries {
+        if summary.architecture.as_ref() != architecture {
+            return Err( format!(
+                "cannot merge recordings from different architectures: {:?} and {:?}",
+                architecture.map( |name| name.as_str() ).unwrap_or( "unknown" ),
+                summary.architecture.as_ref().map( |name| name.as_str() ).unwrap_or( "unknown" )
+            ).into() );
+        }
+    }
+
+    let mut next_id = summaries.iter().flat_map( |summary| summary.ids.iter().cloned() ).max().unwrap_or( 0 ) + 1;
+    let mut next_inode = 1;
+    let mut used_ids = HashSet::new();
+    let mut known_binaries = HashSet::new();
+    let mut binary_by_build_id: HashMap< &[u8], BinaryId > = HashMap::new();
+    let base_timestamp = summaries.iter().filter_map( |summary| summary.first_timestamp ).next();
+    let base_kernel_text_address = summaries[ 0 ].kernel_text_address;
+
+    let mut remaps = Vec::with_capacity( summaries.len() );
+    for (nth, summary) in summaries.iter().enumerate() {
+        let mut remap = Remap {
+            is_first: nth == 0,
+            .. Remap::default()
+        };
+
+        if let (Some( base ), Some( first )) = (base_timestamp, summary.first_timestamp) {
+            remap.timestamp_delta = base as i64 - first as i64;
+        }
+
+        // Only the kernel symbols from the first input are used, so the kernel addresses
+        // of the other inputs have to be adjusted to account for the address space randomization.
+        match (base_kernel_text_address, summary.kernel_text_address) {
+            (Some( base ), Some( address )) => remap.kernel_address_delta = base.wrapping_sub( address ) as i64,
+            (None, None) => {},
+            _ => warn!( "Input #{} has different kernel symbols than the first input; its kernel frames might be symbolized incorrectly", nth + 1 )
+        }
+
+        for &id in &summary.ids {
+            if used_ids.contains( &id ) {
+                remap.ids.insert( id, next_id );
+                used_ids.insert( next_id );
+                next_id += 1;
+            } else {
+                used_ids.insert( id );
+            }
+        }
+
+        for binary_id in &summary.binaries {
+            let build_id = summary.build_ids.get( binary_id );
+            let existing = build_id.and_then( |build_id| binary_by_build_id.get( build_id.as_slice() ) );
+            let merged_id = match (binary_id, existing) {
+                (&BinaryId::ByInode( inode ), Some( &BinaryId::ByInode( existing ) )) => {
+                    remap.inodes.insert( inode, existing );
+                    remap.duplicate_binaries.insert( binary_id.clone() );
+                    continue;
+                },
+                (&BinaryId::ByName( ref name ), Some( &BinaryId::ByName( ref existing ) )) if name == existing => {
+                    remap.duplicate_binaries.insert( binary_id.clone() );
+                    continue;
+                },
+                (&BinaryId::ByInode( inode ), _) if known_binaries.contains( binary_id ) => {
+                    let new_inode = Inode { inode: next_inode, dev_major: !0, dev_minor: !0 };
+                    next_inode += 1;
+
+                    remap.inodes.insert( inode, new_inode );
+                    BinaryId::ByInode( new_inode )
+                },
+                (&BinaryId::ByName( ref name ), _) if known_binaries.contains( binary_id ) => {
+                    warn!( "Multiple different binaries named {:?} found in the inputs; only the first one will be used", name );
+                    remap.duplicate_binaries.insert( binary_id.clone() );
+                    continue;
+                },
+                _ => binary_id.clone()
+            };
+
+            if let Some( build_id ) = build_id {
+                binary_by_build_id.entry( build_id.as_slice() ).or_insert_with( || merged_id.clone() );
+            }
+
+            known_binaries.insert( merged_id );
+        }
+
+        remaps.push( remap );
+    }
+
+    Ok( remaps )
+}
+
+/// Reads the packets from multiple archives as if they were recorded together.
+///
+/// The binaries with the same build ID are deduplicated, the conflicting PIDs
+/// and TIDs are renumbered, and the timestamps of every input are rebased so that
+/// all of the inputs start at the same time as the first one.
+pub(crate) struct MergedReader {
+    inputs: VecDeque< (Remap, PacketIter) >
+}
+
+impl MergedReader {
+    pub fn open( paths: &[&OsStr] ) -> Result< Self, Box< dyn Error > > {
+        if paths.is_empty() {
+            return Err( "no input files were specified".into() );
+        }
+
+        if paths.len() > 1 && paths.iter().any( |&path| is_stdin( path ) ) {
+            return Err( "stdin can only be read when it's the only input".into() );
+        }
+
+        let remaps = if paths.len() == 1 {
+            vec![ Remap { is_first: true, .. Remap::default() } ]
+        } else {
+            let summaries = paths.iter().map( |&path| summarize( path ) ).collect::< Result< Vec< _ >, _ > >()?;
+            build_remaps( &summaries )?
+        };
+
+        let mut inputs = VecDeque::with_capacity( paths.len() );
+        for (remap, &path) in remaps.into_iter().zip( paths.iter() ) {
+            inputs.push_back( (remap, open( path )?) );
+        }
+
+        Ok( MergedReader { inputs } )
+    }
+
+    /// Reads a single recording which is already in memory.
+    pub fn from_memory( data: Arc< [u8] > ) -> Result< Self, Box< dyn Error > > {
+        let iter: PacketIter = Box::new( ArchiveReader::new( io::Cursor::new( data ) ).validate_header()?.skip_unknown() );
+        let mut inputs = VecDeque::with_capacity( 1 );
+        inputs.push_back( (Remap { is_first: true, .. Remap::default() }, iter) );
+
+        Ok( MergedReader { inputs } )
+    }
+}
+
+impl Iterator for MergedReader {
+    type Item = io::Result< Packet< 'static > >;
+    fn next( &mut self ) -> Option< Self::Item > {
+        loop {
+            let packet = {
+                let &mut (ref remap, ref mut iter) = self.inputs.front_mut()?;
+                match iter.next() {
+                    Some( Ok( packet ) ) => remap.apply( packet ),
+                    Some( Err( error ) ) => return Some( Err( error ) ),
+                    None => {
+                        self.inputs.pop_front();
+                        continue;
+                    }
+                }
+            };
+
+            if let Some( packet ) = packet {
+                return Some( Ok( packet ) );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_build_remaps() {
+    let inode = |inode| Inode { inode, dev_major: 8, dev_minor: 1 };
+
+    let mut first = Summary::default();
+    first.architecture = Some( "amd64".to_owned() );
+    first.binaries = vec![ BinaryId::ByInode( inode( 1 ) ), BinaryId::ByInode( inode( 2 ) ) ];
+    first.build_ids.insert( BinaryId::ByInode( inode( 1 ) ), vec![ 0xAA ] );
+    first.ids.insert( 100 );
+    first.first_timestamp = Some( 1000 );
+    first.kernel_text_address = Some( 0xFFFFFFFF82000000 );
+
+    let mut second = Summary::default();
+    second.architecture = Some( "amd64".to_owned() );
+    second.binaries = vec![ BinaryId::ByInode( inode( 3 ) ), BinaryId::ByInode( inode( 2 ) ) ];
+    second.build_ids.insert( BinaryId::ByInode( inode( 3 ) ), vec![ 0xAA ] );
+    second.ids.insert( 100 );
+    second.ids.insert( 200 );
+    second.first_timestamp = Some( 5000 );
+    second.kernel_text_address = Some( 0xFFFFFFFF81000000 );
+
+    let remaps = build_remaps( &[ first, second ] ).unwrap();
+    assert!( remaps[ 0 ].is_first );
+
+    let remap = &remaps[ 1 ];
+    assert_eq!( remap.timestamp( 5500 ), 1500 );
+    assert_eq!( remap.id( 100 ), 201 );
+    assert_eq!( remap.id( 200 ), 200 );
+    assert_eq!( &*remap.kernel_backtrace( vec![ 0xFFFFFFFF81000010, 0xFFFFFFFFFFFFFF80 ].into() ), &[ 0xFFFFFFFF82000010, 0xFFFFFFFFFFFFFF80 ] );
+
+    // Same build ID, so it's deduplicated.
+    assert_eq!( remap.inode( inode( 3 ) ), inode( 1 ) );
+    assert!( remap.is_duplicate( inode( 3 ), b"" ) );
+
+    // Same inode, but a different binary.
+    assert_ne!( remap.inode( inode( 2 ) ), inode( 2 ) );
+    assert!( !remap.is_duplicate( inode( 2 ), b"" ) );
+}
+
+#[test]
+fn test_build_remaps_different_architectures() {
+    let mut first = Summary::default();
+    first.architecture = Some( "amd64".to_owned() );
+
+    let mut second = Summary::default();
+    second.architecture = Some( "arm".to_owned() );
+
+    assert!( build_remaps( &[ first, second ] ).is_err() );
+}
+
+#[test]
+fn test_find_kernel_text_address() {
+    let kallsyms = b"0000000000000000 A irq_stack_union\nffffffff81000000 T _text\nffffffff81000000 T startup_64\n";
+    assert_eq!( find_kernel_text_address( kallsyms ), Some( 0xFFFFFFFF81000000 ) );
+    assert_eq!( find_kernel_text_address( b"" ), None );
+}
+
+#[test]
+fn test_stdin_must_be_the_only_input() {
+    let error = MergedReader::open( &[ OsStr::new( "-" ), OsStr::new( "datafile" ) ] ).err().unwrap();
+    assert_eq!( error.to_string(), "stdin can only be read when it's the only input" );
+}
@@ -16,9 +16,46 @@ pub struct Binary {
     pub build_id: Option< String >
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecordingStats {
+    pub sample_count: u64,
+    pub lost_event_count: u64,
+    pub unwound_sample_count: u64,
+    pub frame_count: u64,
+    pub truncated_stack_count: u64,
+    pub unwind_failure_count: u64,
+    pub binary_count: u64,
+    pub bytes_written: u64
+}
+
+/// The overhead of the profiler itself; only present when recorded with `--self-profile`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SelfProfile {
+    pub wall_time: u64,
+    pub cpu_time: u64,
+    pub sampling_time: u64,
+    pub unwinding_time: u64,
+    pub symbol_loading_time: u64,
+    pub io_time: u64,
+    pub max_rss: u64
+}
+
+/// The memory use of a process at a given time; only present when recorded with `--memory-stats`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MemoryStats {
+    pub pid: u32,
+    pub timestamp: u64,
+    pub rss: u64,
+    pub pss: u64,
+    pub swap: u64
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Metadata {
     pub machine_info: Option< MachineInfo >,
     pub processes: Vec< Process >,
-    pub binaries: Vec< Binary >
+    pub binaries: Vec< Binary >,
+    pub recording_stats: Option< RecordingStats >,
+    pub self_profile: Option< SelfProfile >,
+    pub memory_stats: Vec< MemoryStats >
 }
@@ -189,7 +189,14 @@ r#"222 114 0:50 / /tmp/path\040with\040a\040space\040\134x rw,relatime shared:12
 pub struct PathResolver {
     self_mount_info: Vec< MountInfo >,
     target_mount_info: Vec< MountInfo >,
-    target_root: PathBuf
+    target_root: PathBuf,
+    target_proc_root: Option< PathBuf >
+}
+
+fn is_in_same_mount_namespace( pid: u32 ) -> Option< bool > {
+    let self_namespace = fs::read_link( "/proc/self/ns/mnt" ).ok()?;
+    let target_namespace = fs::read_link( format!( "/proc/{}/ns/mnt", pid ) ).ok()?;
+    Some( self_namespace == target_namespace )
 }
 
 impl PathResolver {
@@ -203,7 +210,13 @@ impl PathResolver {
         let target_root = fs::read_link( format!( "/proc/{}/root", pid ) )
             .map_err( |err| format!( "cannot read /proc/{}/root: {}", pid, err ) )?;
 
-        Self::new_from_parts( &self_mount_info, &target_mount_info, target_root )
+        let mut resolver = Self::new_from_parts( &self_mount_info, &target_mount_info, target_root )?;
+        if is_in_same_mount_namespace( pid ) == Some( false ) {
+            debug!( "Process {} is in a different mount namespace; its files will be accessed through /proc/{}/root", pid, pid );
+            resolver.target_proc_root = Some( format!( "/proc/{}/root", pid ).into() );
+        }
+
+        Ok( resolver )
     }
 
     fn new_from_parts(
@@ -217,12 +230,21 @@ impl PathResolver {
         let resolver = PathResolver {
             self_mount_info,
             target_mount_info,
-            target_root
+            target_root,
+            target_proc_root: None
         };
 
         Ok( resolver )
     }
 
+    /// Returns a path through which a file can be accessed exactly as the target process sees it,
+    /// as long as it's in a different mount namespace (e.g. in a container) than we are.
+    pub fn resolve_through_proc_root< P: AsRef< Path > >( &self, path: P ) -> Option< PathBuf > {
+        let target_proc_root = self.target_proc_root.as_ref()?;
+        let path = path.as_ref().strip_prefix( "/" ).ok()?;
+        Some( target_proc_root.join( path ) )
+    }
+
     pub fn resolve< 'a, P: AsRef< Path > >( &'a self, path: P ) -> Option< impl Iterator< Item = PathBuf > + 'a > {
         let path = path.as_ref();
         let path = Path::new( "/" ).join( path.strip_prefix( &self.target_root ).ok()? );
@@ -316,4 +338,13 @@ fn test_path_resolver() {
         paths,
         vec![ Path::new( "/build/sdkroot/data/os/sys-root/x86_64-pc-linux-gnu/usr/lib64/libnss_files-2.28.so" ) ]
     );
+
+    assert_eq!( resolver.resolve_through_proc_root( "/lib64/libnss_files-2.28.so" ), None );
+
+    let mut resolver = resolver;
+    resolver.target_proc_root = Some( "/proc/1234/root".into() );
+    assert_eq!(
+        resolver.resolve_through_proc_root( "/lib64/libnss_files-2.28.so" ),
+        Some( PathBuf::from( "/proc/1234/root/lib64/libnss_files-2.28.so" ) )
+    );
 }
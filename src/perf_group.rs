@@ -6,15 +6,16 @@ use std::ops::{Deref, DerefMut};
 use std::cell::Cell;
 use std::vec;
 use std::mem;
+use std::time::{Duration, Instant};
+use std::process;
 
-use num_cpus;
 use libc;
 
 use proc_maps;
 
-use crate::utils::read_string_lossy;
+use crate::utils::{online_cpus, read_string_lossy};
 use crate::perf_arch;
-use perf_event_open::{Perf, Event, CommEvent, Mmap2Event, EventSource};
+use perf_event_open::{Perf, PerfBuilder, Event, CommEvent, CounterValue, Mmap2Event, EventSource};
 
 pub struct EventRef {
     pid: u32,
@@ -94,15 +95,50 @@ impl DerefMut for Member {
     }
 }
 
+/// Determines how often the samples are gathered.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sampling {
+    /// A given number of times per second; the kernel adjusts the sampling period on the fly.
+    Frequency( u32 ),
+    /// Every given number of events.
+    Period( u64 )
+}
+
 pub struct PerfGroup {
     event_buffer: Vec< EventRef >,
     members: BTreeMap< RawFd, Member >,
     poll_fds: Vec< libc::pollfd >,
-    frequency: u32,
+    sampling: Sampling,
     stack_size: u32,
+    buffer_size: Option< u32 >,
     event_source: EventSource,
+    sample_kernel: bool,
     initial_events: Vec< Event< 'static > >,
-    stopped_processes: Vec< StoppedProcess >
+    stopped_processes: Vec< StoppedProcess >,
+    closed_counters: CounterValue,
+    round_robin: Option< RoundRobin >,
+    /// The ids of the online CPUs, sorted.
+    cpus: Vec< u32 >
+}
+
+/// Only a window of the CPUs is sampled at any given time, which is moved every `interval`.
+struct RoundRobin {
+    active_cpus: u32,
+    interval: Duration,
+    offset: u32,
+    last_rotation: Instant
+}
+
+/// Whether a given CPU is within the window of `active_cpus` CPUs starting at the `offset`-th one;
+/// the CPU ids might not be contiguous (e.g. if some of the CPUs are offline), so they're indexed.
+fn is_in_round_robin_window( cpus: &[u32], cpu: u32, offset: u32, active_cpus: u32 ) -> bool {
+    let index = match cpus.binary_search( &cpu ) {
+        Ok( index ) => index as u32,
+        Err( _ ) => return false
+    };
+
+    let cpu_count = cpus.len() as u32;
+    (index + cpu_count - offset) % cpu_count < active_cpus
 }
 
 fn poll_events< 'a, I >( poll_fds: &mut Vec< libc::pollfd >, iter: I ) where I: IntoIterator< Item = &'a Member >, <I as IntoIterator>::IntoIter: Clone {
@@ -163,61 +199,76 @@ fn get_threads( pid: u32 ) -> Result< Vec< (u32, Option< Vec< u8 > >) >, io::Err
 }
 
 impl PerfGroup {
-    pub fn new( frequency: u32, stack_size: u32, event_source: EventSource ) -> Self {
+    pub fn new( sampling: Sampling, stack_size: u32, buffer_size: Option< u32 >, event_source: EventSource, sample_kernel: bool ) -> Self {
         let group = PerfGroup {
             event_buffer: Vec::new(),
             members: Default::default(),
             poll_fds: Vec::new(),
-            frequency,
+            sampling,
             stack_size,
+            buffer_size,
             event_source,
+            sample_kernel,
             initial_events: Vec::new(),
-            stopped_processes: Vec::new()
+            stopped_processes: Vec::new(),
+            closed_counters: CounterValue::default(),
+            round_robin: None,
+            cpus: online_cpus()
         };
 
         group
     }
 
-    pub fn open( pid: u32, frequency: u32, stack_size: u32, event_source: EventSource ) -> Result< Self, io::Error > {
-        let mut group = PerfGroup::new( frequency, stack_size, event_source );
+    pub fn open( pid: u32, sampling: Sampling, stack_size: u32, buffer_size: Option< u32 >, event_source: EventSource, sample_kernel: bool ) -> Result< Self, io::Error > {
+        let mut group = PerfGroup::new( sampling, stack_size, buffer_size, event_source, sample_kernel );
         group.open_process( pid )?;
         Ok( group )
     }
 
+    fn perf_builder( &self, pid: u32, cpu: u32 ) -> PerfBuilder {
+        let builder = Perf::build()
+            .pid( pid )
+            .only_cpu( cpu )
+            .sample_user_stack( self.stack_size )
+            .sample_user_regs( perf_arch::native::REG_MASK )
+            .gather_context_switches()
+            .gather_build_ids()
+            .event_source( self.event_source )
+            .inherit_to_children()
+            .start_disabled();
+
+        let builder = match self.buffer_size {
+            Some( buffer_size ) => builder.buffer_size( buffer_size ),
+            None => builder
+        };
+
+        let builder = if self.sample_kernel {
+            builder.sample_kernel()
+        } else {
+            builder
+        };
+
+        match self.sampling {
+            Sampling::Frequency( frequency ) => builder.frequency( frequency as u64 ),
+            Sampling::Period( period ) => builder.period( period )
+        }
+    }
+
     pub fn open_process( &mut self, pid: u32 ) -> Result< (), io::Error > {
-        self.stopped_processes.push( StoppedProcess::new( pid )? );
+        // A process which profiles itself obviously can't be stopped.
+        if pid != process::id() {
+            self.stopped_processes.push( StoppedProcess::new( pid )? );
+        }
         let mut perf_events = Vec::new();
         let threads = get_threads( pid )?;
 
-        for cpu in 0..num_cpus::get() as u32 {
-            let perf = Perf::build()
-                .pid( pid )
-                .only_cpu( cpu as _ )
-                .frequency( self.frequency as u64 )
-                .sample_user_stack( self.stack_size )
-                .sample_user_regs( perf_arch::native::REG_MASK )
-                .sample_kernel()
-                .gather_context_switches()
-                .event_source( self.event_source )
-                .inherit_to_children()
-                .start_disabled()
-                .open()?;
+        for &cpu in &self.cpus {
+            let perf = self.perf_builder( pid, cpu ).open()?;
 
             perf_events.push( (cpu, perf) );
 
             for &(tid, _) in &threads {
-                let perf = Perf::build()
-                    .pid( tid )
-                    .only_cpu( cpu as _ )
-                    .frequency( self.frequency as u64 )
-                    .sample_user_stack( self.stack_size )
-                    .sample_user_regs( perf_arch::native::REG_MASK )
-                    .sample_kernel()
-                    .gather_context_switches()
-                    .event_source( self.event_source )
-                    .inherit_to_children()
-                    .start_disabled()
-                    .open()?;
+                let perf = self.perf_builder( tid, cpu ).open()?;
 
                 perf_events.push( (cpu, perf) );
             }
@@ -234,7 +285,8 @@ impl PerfGroup {
             self.initial_events.push( Event::Comm( CommEvent {
                 pid,
                 tid,
-                name: name.unwrap_or( Vec::new() )
+                name: name.unwrap_or( Vec::new() ),
+                is_exec: false
             }));
         }
 
@@ -269,7 +321,8 @@ impl PerfGroup {
                 inode_generation: 0,
                 protection: protection as _,
                 flags: flags as _,
-                filename: region.name.into()
+                filename: region.name.into(),
+                build_id: None
             }));
         }
 
@@ -292,6 +345,55 @@ impl PerfGroup {
         }
 
         self.stopped_processes.clear();
+        self.apply_round_robin();
+    }
+
+    /// Makes only `active_cpus` CPUs be sampled at the same time, cycling through all of them every `interval`;
+    /// since the window wraps around every CPU ends up being sampled for the same fraction of the time.
+    pub fn set_round_robin( &mut self, active_cpus: u32, interval: Duration ) {
+        self.round_robin = Some( RoundRobin {
+            active_cpus,
+            interval,
+            offset: 0,
+            last_rotation: Instant::now()
+        });
+
+        self.apply_round_robin();
+    }
+
+    /// The number of the CPUs which are sampled.
+    pub fn cpu_count( &self ) -> u32 {
+        self.cpus.len() as u32
+    }
+
+    fn apply_round_robin( &mut self ) {
+        let round_robin = match self.round_robin {
+            Some( ref round_robin ) => round_robin,
+            None => return
+        };
+
+        for member in self.members.values_mut() {
+            if is_in_round_robin_window( &self.cpus, member.cpu, round_robin.offset, round_robin.active_cpus ) {
+                member.perf.enable();
+            } else {
+                member.perf.disable();
+            }
+        }
+    }
+
+    fn rotate_cpus_if_necessary( &mut self ) {
+        let is_due = match self.round_robin {
+            Some( ref mut round_robin ) if round_robin.last_rotation.elapsed() >= round_robin.interval => {
+                round_robin.offset = (round_robin.offset + round_robin.active_cpus) % self.cpus.len() as u32;
+                round_robin.last_rotation = Instant::now();
+                true
+            },
+            _ => false
+        };
+
+        if is_due {
+            self.apply_round_robin();
+        }
     }
 
     pub fn wait( &mut self ) {
@@ -304,7 +406,15 @@ impl PerfGroup {
         poll_events( &mut self.poll_fds, self.members.values() );
     }
 
+    /// Sums up the counters of every member, including the ones which were already closed.
+    pub fn read_counters( &self ) -> CounterValue {
+        self.members.values()
+            .filter_map( |member| member.read_counter().ok() )
+            .fold( self.closed_counters, |sum, value| sum + value )
+    }
+
     pub fn iter( &mut self ) -> vec::Drain< EventRef > {
+        self.rotate_cpus_if_necessary();
         self.event_buffer.clear();
 
         let mut fds_to_remove = Vec::new();
@@ -331,9 +441,23 @@ impl PerfGroup {
         }
 
         for fd in fds_to_remove {
-            self.members.remove( &fd );
+            if let Some( member ) = self.members.remove( &fd ) {
+                if let Ok( value ) = member.read_counter() {
+                    self.closed_counters = self.closed_counters + value;
+                }
+            }
         }
 
         self.event_buffer.drain( .. )
     }
 }
+
+#[test]
+fn test_is_in_round_robin_window() {
+    let cpus = [0, 1, 4, 5, 6];
+    let active = |offset| cpus.iter().cloned().filter( |&cpu| is_in_round_robin_window( &cpus, cpu, offset, 2 ) ).collect::< Vec< _ > >();
+    assert_eq!( active( 0 ), vec![ 0, 1 ] );
+    assert_eq!( active( 2 ), vec![ 4, 5 ] );
+    assert_eq!( active( 4 ), vec![ 0, 6 ] );
+    assert!( !is_in_round_robin_window( &cpus, 2, 2, 2 ) );
+}
@@ -0,0 +1,552 @@
+//! Support for the `/tmp/perf-<pid>.map` files in which the JIT compilers
+//! (e.g. the JVM with `perf-map-agent`, or V8 with `--perf-basic-prof`)
+//! list the names of the code they've generated, in the same format as `perf` expects:
+//!
+//! ```text
+//! START SIZE NAME
+//! ```
+//!
+//! where both `START` and `SIZE` are in hex.
+//!
+//! The .NET runtime additionally lists the images it has loaded in `/tmp/perfinfo-<pid>.map`;
+//! the code precompiled into the ReadyToRun images isn't in the perf map, but `crossgen2 --perfmap`
+//! can generate a `<image>.ni.r2rmap` for each of them, which is in the same format except
+//! that the addresses are relative to where the image was loaded.
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+#[cfg(feature = "native")]
+use std::collections::HashSet;
+#[cfg(feature = "native")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "native")]
+use std::fs;
+#[cfg(feature = "native")]
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "native")]
+use std::os::unix::fs::MetadataExt;
+#[cfg(feature = "native")]
+use std::os::unix::process::CommandExt;
+#[cfg(feature = "native")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::process::Command;
+#[cfg(feature = "native")]
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "native")]
+use std::thread;
+#[cfg(feature = "native")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "native")]
+use crate::utils::read_string_lossy;
+
+/// How often the map is checked for changes.
+#[cfg(feature = "native")]
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs( 1 );
+
+fn parse_line( line: &str ) -> Option< (u64, u64, &str) > {
+    let mut iter = line.trim_end().splitn( 3, ' ' );
+    let start = iter.next()?;
+    let size = iter.next()?;
+    let name = iter.next()?;
+    let start = u64::from_str_radix( start.trim_start_matches( "0x" ), 16 ).ok()?;
+    let size = u64::from_str_radix( size.trim_start_matches( "0x" ), 16 ).ok()?;
+    if size == 0 || name.is_empty() {
+        return None;
+    }
+
+    Some( (start, size, name) )
+}
+
+struct JitSymbol {
+    end: u64,
+    name: String,
+    // When the symbol was first seen in the map, and when it was replaced by another one.
+    added_at: u64,
+    removed_at: Option< u64 >
+}
+
+/// The JIT symbols of a single process, together with when each of them was valid.
+#[derive(Default)]
+pub(crate) struct JitSymbols {
+    // Maps the start of every symbol which was ever seen to its every incarnation.
+    symbols: BTreeMap< u64, Vec< JitSymbol > >,
+    // Maps the start of every symbol which is currently valid to its end.
+    live: BTreeMap< u64, u64 >,
+    max_size: u64
+}
+
+impl JitSymbols {
+    /// Adds the symbols from a given chunk of a perf map which was read at a given time; since the JIT compilers
+    /// reuse the memory of the code which was freed every symbol replaces those with which it overlaps.
+    pub fn add( &mut self, data: &[u8], timestamp: u64 ) {
+        let data = String::from_utf8_lossy( data );
+        for line in data.lines() {
+            let (start, size, name) = match parse_line( line ) {
+                Some( entry ) => entry,
+                None => continue
+            };
+
+            let end = start.saturating_add( size );
+            let overlapping: Vec< u64 > = self.live.range( ..end ).rev()
+                .take_while( |&(_, &symbol_end)| symbol_end > start )
+                .map( |(&symbol_start, _)| symbol_start )
+                .collect();
+
+            // The live symbols never overlap, so their ends are ordered just as their starts are.
+            for symbol_start in overlapping {
+                self.live.remove( &symbol_start );
+                if let Some( symbol ) = self.symbols.get_mut( &symbol_start ).and_then( |symbols| symbols.last_mut() ) {
+                    symbol.removed_at = Some( timestamp );
+                }
+            }
+
+            self.live.insert( start, end );
+            self.max_size = max( self.max_size, size );
+            self.symbols.entry( start ).or_default().push( JitSymbol {
+                end,
+                name: name.to_owned(),
+                added_at: timestamp,
+                removed_at: None
+            });
+        }
+    }
+
+    /// Returns the name of the code which was at a given address at a given time.
+    ///
+    /// The map is only read periodically, so if the address wasn't named yet
+    /// the first name which it got afterwards is returned.
+    pub fn get( &self, address: u64, timestamp: u64 ) -> Option< &str > {
+        let mut later: Option< &JitSymbol > = None;
+        for symbol in self.symbols.range( address.saturating_sub( self.max_size )..=address ).flat_map( |(_, symbols)| symbols ) {
+            if address >= symbol.end {
+                continue;
+            }
+
+            if symbol.added_at > timestamp {
+                if later.map( |later| symbol.added_at < later.added_at ).unwrap_or( true ) {
+                    later = Some( symbol );
+                }
+            } else if symbol.removed_at.map( |removed_at| timestamp < removed_at ).unwrap_or( true ) {
+                return Some( &symbol.name );
+            }
+        }
+
+        later.map( |symbol| symbol.name.as_str() )
+    }
+}
+
+/// Returns the PID of a given process as seen from within its own PID namespace,
+/// which is what the JIT compilers use to name their maps.
+#[cfg(feature = "native")]
+fn namespaced_pid( pid: u32 ) -> u32 {
+    let status = match read_string_lossy( format!( "/proc/{}/status", pid ) ) {
+        Ok( status ) => status,
+        Err( _ ) => return pid
+    };
+
+    status.lines()
+        .find( |line| line.starts_with( "NSpid:" ) )
+        .and_then( |line| line.split_whitespace().last() )
+        .and_then( |nspid| nspid.parse().ok() )
+        .unwrap_or( pid )
+}
+
+/// Periodically checks a perf map (or a perfinfo map) of a given process for changes.
+#[cfg(feature = "native")]
+pub(crate) struct PerfMapWatcher {
+    path: PathBuf,
+    last_check: Option< Instant >,
+    last_modification: Option< (u64, i64, i64) >,
+    seen_lines: HashSet< u64 >
+}
+
+#[cfg(feature = "native")]
+impl PerfMapWatcher {
+    /// The `kind` is either `perf` or `perfinfo`.
+    pub fn new( pid: u32, kind: &str ) -> Self {
+        // The map is in the process' own `/tmp`, which might be in another mount namespace.
+        PerfMapWatcher {
+            path: format!( "/proc/{}/root/tmp/{}-{}.map", pid, kind, namespaced_pid( pid ) ).into(),
+            last_check: None,
+            last_modification: None,
+            seen_lines: HashSet::new()
+        }
+    }
+
+    /// Returns the lines which were added to the map since the last time it was checked, if it's time to check it.
+    ///
+    /// Since some of the JIT compilers rewrite the whole map instead of appending to it
+    /// every line is only returned the first time it's seen.
+    pub fn poll( &mut self ) -> Option< Vec< u8 > > {
+        if self.last_check.map( |last_check| last_check.elapsed() < POLL_INTERVAL ).unwrap_or( false ) {
+            return None;
+        }
+
+        self.last_check = Some( Instant::now() );
+        let metadata = fs::metadata( &self.path ).ok()?;
+        let modification = (metadata.size(), metadata.mtime(), metadata.mtime_nsec());
+        if self.last_modification == Some( modification ) {
+            return None;
+        }
+
+        self.last_modification = Some( modification );
+        let data = fs::read( &self.path ).ok()?;
+        let mut output = Vec::new();
+        // The last line might be incomplete if the map is being written to right now.
+        let complete_length = data.iter().rposition( |&byte| byte == b'\n' ).map( |index| index + 1 ).unwrap_or( 0 );
+        for line in data[ ..complete_length ].split( |&byte| byte == b'\n' ) {
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut hasher = DefaultHasher::new();
+            line.hash( &mut hasher );
+            if self.seen_lines.insert( hasher.finish() ) {
+                output.extend_from_slice( line );
+                output.push( b'\n' );
+            }
+        }
+
+        if output.is_empty() {
+            return None;
+        }
+
+        Some( output )
+    }
+}
+
+/// Parses an `ImageLoad;<path>;<guid>;<base address>;` line of a perfinfo map;
+/// the older versions of the .NET runtime don't write out the base address.
+#[cfg(feature = "native")]
+fn parse_image_load( line: &str ) -> Option< (&str, &str, Option< u64 >) > {
+    let mut iter = line.trim_end().split( ';' );
+    if iter.next()? != "ImageLoad" {
+        return None;
+    }
+
+    let path = iter.next().filter( |path| !path.is_empty() )?;
+    let guid = iter.next().unwrap_or( "" );
+    let base = iter.next()
+        .and_then( |base| u64::from_str_radix( base.trim_start_matches( "0x" ), 16 ).ok() )
+        .filter( |&base| base != 0 );
+
+    Some( (path, guid, base) )
+}
+
+// The pseudo-addresses under which `crossgen2` writes out the metadata of the image.
+#[cfg(feature = "native")]
+const R2R_OUTPUT_SIGNATURE: u64 = 0xFFFF_FFFF;
+#[cfg(feature = "native")]
+const R2R_FIRST_PSEUDO_ADDRESS: u64 = 0xFFFF_FFFB;
+
+/// The symbols from a ReadyToRun map, with their addresses relative to the image base.
+#[cfg(feature = "native")]
+struct R2rMap {
+    signature: Option< String >,
+    symbols: Vec< (u64, u64, String) >
+}
+
+#[cfg(feature = "native")]
+fn parse_r2r_map( data: &str ) -> R2rMap {
+    let mut map = R2rMap {
+        signature: None,
+        symbols: Vec::new()
+    };
+
+    for line in data.lines() {
+        let mut iter = line.trim_end().splitn( 3, ' ' );
+        let address = match iter.next().and_then( |address| u64::from_str_radix( address, 16 ).ok() ) {
+            Some( address ) => address,
+            None => continue
+        };
+
+        if address >= R2R_FIRST_PSEUDO_ADDRESS {
+            if address == R2R_OUTPUT_SIGNATURE {
+                map.signature = iter.nth( 1 ).map( |signature| signature.to_owned() );
+            }
+            continue;
+        }
+
+        if let Some( (address, size, name) ) = parse_line( line ) {
+            map.symbols.push( (address, size, name.to_owned()) );
+        }
+    }
+
+    map
+}
+
+/// Checks whether the signature of a ReadyToRun map matches the GUID of the image as given
+/// in the perfinfo map, in either the textual order of the GUID or in the order of its bytes.
+#[cfg(feature = "native")]
+fn is_matching_signature( guid: &str, signature: &str ) -> bool {
+    let guid = guid.chars().filter( |ch| ch.is_ascii_hexdigit() ).collect::< String >().to_ascii_lowercase();
+    let signature = signature.chars().filter( |ch| ch.is_ascii_hexdigit() ).collect::< String >().to_ascii_lowercase();
+    if guid.len() != 32 {
+        return true;
+    }
+
+    if guid == signature {
+        return true;
+    }
+
+    // The first three fields of a GUID are stored as little endian.
+    let swap = |field: &str| -> String {
+        field.as_bytes().chunks( 2 ).rev().map( |byte| std::str::from_utf8( byte ).unwrap() ).collect()
+    };
+
+    let bytes = format!( "{}{}{}{}", swap( &guid[ 0..8 ] ), swap( &guid[ 8..12 ] ), swap( &guid[ 12..16 ] ), &guid[ 16.. ] );
+    bytes == signature
+}
+
+/// Translates the ReadyToRun maps of the images loaded by a .NET process into perf map entries.
+#[cfg(feature = "native")]
+pub(crate) struct ReadyToRunMaps {
+    pid: u32,
+    directories: Vec< PathBuf >
+}
+
+#[cfg(feature = "native")]
+impl ReadyToRunMaps {
+    /// Besides next to the images themselves the maps are also looked for in the given `directories`.
+    pub fn new( pid: u32, directories: Vec< PathBuf > ) -> Self {
+        ReadyToRunMaps {
+            pid,
+            directories
+        }
+    }
+
+    fn find_map( &self, image: &Path ) -> Option< (PathBuf, String) > {
+        let stem = image.file_stem()?.to_str()?;
+        let image_directory = image.parent().map( |directory| Path::new( &format!( "/proc/{}/root", self.pid ) ).join( directory.strip_prefix( "/" ).unwrap_or( directory ) ) );
+        for directory in image_directory.iter().chain( self.directories.iter() ) {
+            for filename in &[format!( "{}.ni.r2rmap", stem ), format!( "{}.r2rmap", stem )] {
+                let path = directory.join( filename );
+                if let Ok( data ) = fs::read( &path ) {
+                    return Some( (path, String::from_utf8_lossy( &data ).into_owned()) );
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the perf map entries for the images listed in a given chunk of a perfinfo map;
+    /// `base_address_of` is used when the perfinfo map doesn't have the base address of an image.
+    pub fn load( &self, perfinfo: &[u8], base_address_of: impl Fn( &str ) -> Option< u64 > ) -> Vec< u8 > {
+        let mut output = Vec::new();
+        let perfinfo = String::from_utf8_lossy( perfinfo );
+        for (image, guid, base_address) in perfinfo.lines().filter_map( parse_image_load ) {
+            let (path, map) = match self.find_map( Path::new( image ) ) {
+                Some( map ) => map,
+                None => {
+                    debug!( "No ReadyToRun map found for {:?}", image );
+                    continue;
+                }
+            };
+
+            let map = parse_r2r_map( &map );
+            if let Some( ref signature ) = map.signature {
+                if !is_matching_signature( guid, signature ) {
+                    warn!( "The ReadyToRun map {:?} doesn't match the loaded {:?}", path, image );
+                    continue;
+                }
+            }
+
+            let base_address = match base_address.or_else( || base_address_of( image ) ) {
+                Some( base_address ) => base_address,
+                None => {
+                    warn!( "Failed to find the base address of {:?}", image );
+                    continue;
+                }
+            };
+
+            debug!( "Loaded {} symbols from {:?} for the image at 0x{:016X}", map.symbols.len(), path, base_address );
+            for (address, size, name) in map.symbols {
+                output.extend_from_slice( format!( "{:x} {:x} {}\n", base_address + address, size, name ).as_bytes() );
+            }
+        }
+
+        output
+    }
+}
+
+/// Periodically attaches `perf-map-agent` to a JVM to make it dump its perf map,
+/// so that the code which was compiled in the meantime is also in the map.
+#[cfg(feature = "native")]
+pub(crate) struct PerfMapAgent {
+    pid: u32,
+    path: PathBuf,
+    interval: Duration,
+    last_attach: Option< Instant >,
+    is_running: Arc< AtomicBool >
+}
+
+#[cfg(feature = "native")]
+impl PerfMapAgent {
+    /// The `path` is the directory with `perf-map-agent`'s `attach-main.jar` and `libperfmap.so`.
+    pub fn new( pid: u32, path: &Path, interval: Duration ) -> Result< Self, String > {
+        if !path.join( "attach-main.jar" ).exists() {
+            return Err( format!( "no 'attach-main.jar' in {:?}", path ) );
+        }
+
+        Ok( PerfMapAgent {
+            pid,
+            path: path.into(),
+            interval,
+            last_attach: None,
+            is_running: Arc::new( AtomicBool::new( false ) )
+        })
+    }
+
+    /// Attaches the agent in the background if it's time to do so.
+    pub fn poll( &mut self ) {
+        if self.last_attach.map( |last_attach| last_attach.elapsed() < self.interval ).unwrap_or( false ) {
+            return;
+        }
+
+        if self.is_running.load( Ordering::SeqCst ) {
+            return;
+        }
+
+        self.last_attach = Some( Instant::now() );
+        let mut command = match attach_command( self.pid, &self.path ) {
+            Ok( command ) => command,
+            Err( error ) => {
+                warn!( "Failed to attach perf-map-agent to PID {}: {}", self.pid, error );
+                return;
+            }
+        };
+
+        let pid = self.pid;
+        let is_running = self.is_running.clone();
+        is_running.store( true, Ordering::SeqCst );
+        thread::spawn( move || {
+            debug!( "Attaching perf-map-agent: {:?}", command );
+            match command.output() {
+                Ok( output ) if output.status.success() => {},
+                Ok( output ) => warn!( "Failed to attach perf-map-agent to PID {}: {}", pid, String::from_utf8_lossy( &output.stderr ).trim() ),
+                Err( error ) => warn!( "Failed to attach perf-map-agent to PID {}: {}", pid, error )
+            }
+
+            is_running.store( false, Ordering::SeqCst );
+        });
+    }
+}
+
+#[cfg(feature = "native")]
+fn attach_command( pid: u32, agent_path: &Path ) -> Result< Command, String > {
+    // The JVM only accepts the attach requests from its own user, and the same JVM
+    // which is being profiled is used to run the agent so that the versions match.
+    let metadata = fs::metadata( format!( "/proc/{}", pid ) ).map_err( |err| format!( "cannot read the owner of the process: {}", err ) )?;
+    let java = fs::read_link( format!( "/proc/{}/exe", pid ) ).map_err( |err| format!( "cannot read the path of the executable: {}", err ) )?;
+    if java.file_name().map( |name| name != "java" ).unwrap_or( true ) {
+        return Err( format!( "{:?} is not a JVM", java ) );
+    }
+
+    let mut class_path = agent_path.join( "attach-main.jar" ).into_os_string();
+    // Before Java 9 the attach API is in a separate `tools.jar`.
+    if let Some( java_home ) = java.parent().and_then( |bin| bin.parent() ) {
+        for tools in &[java_home.join( "lib/tools.jar" ), java_home.join( "../lib/tools.jar" )] {
+            if tools.exists() {
+                class_path.push( ":" );
+                class_path.push( tools );
+                break;
+            }
+        }
+    }
+
+    let mut command = Command::new( format!( "/proc/{}/root{}", pid, java.display() ) );
+    command
+        .arg( "-cp" )
+        .arg( class_path )
+        .arg( "net.virtualswitch.perfmap.AttachOnce" )
+        .arg( namespaced_pid( pid ).to_string() )
+        .current_dir( agent_path )
+        .uid( metadata.uid() )
+        .gid( metadata.gid() );
+
+    Ok( command )
+}
+
+#[test]
+fn test_parse_line() {
+    assert_eq!( parse_line( "7f1c2d3e4000 1a0 Ljava/lang/String;::hashCode\n" ), Some( (0x7f1c2d3e4000, 0x1a0, "Ljava/lang/String;::hashCode") ) );
+    assert_eq!( parse_line( "0x3a0 0x10 LazyCompile:~foo bar.js:1" ), Some( (0x3a0, 0x10, "LazyCompile:~foo bar.js:1") ) );
+    assert_eq!( parse_line( "3a0 0 empty" ), None );
+    assert_eq!( parse_line( "3a0 10" ), None );
+    assert_eq!( parse_line( "garbage" ), None );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_image_load() {
+    assert_eq!(
+        parse_image_load( "ImageLoad;/usr/share/dotnet/shared/Microsoft.NETCore.App/6.0.0/System.Private.CoreLib.dll;{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f};0x7f0a12340000;\n" ),
+        Some( ("/usr/share/dotnet/shared/Microsoft.NETCore.App/6.0.0/System.Private.CoreLib.dll", "{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f}", Some( 0x7f0a12340000 )) )
+    );
+    assert_eq!( parse_image_load( "ImageLoad;/app/app.dll;{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f};" ), Some( ("/app/app.dll", "{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f}", None) ) );
+    assert_eq!( parse_image_load( "ImageLoad;;{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f};" ), None );
+    assert_eq!( parse_image_load( "RuntimeInformation;6.0.0;" ), None );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_r2r_map() {
+    let map = parse_r2r_map( "FFFFFFFF 00 3A4B0F5EC1248A4C9F3E1D2B3C4D5E6F\nFFFFFFFE 00 1\nFFFFFFFD 00 1\n00001A20 4F [System.Private.CoreLib]System.String::Concat(string,string)\n00002000 00 empty\n" );
+    assert_eq!( map.signature.as_ref().map( |signature| signature.as_str() ), Some( "3A4B0F5EC1248A4C9F3E1D2B3C4D5E6F" ) );
+    assert_eq!( map.symbols, vec![ (0x1a20, 0x4f, "[System.Private.CoreLib]System.String::Concat(string,string)".to_owned()) ] );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_is_matching_signature() {
+    let guid = "{5e0f4b3a-24c1-4c8a-9f3e-1d2b3c4d5e6f}";
+    assert!( is_matching_signature( guid, "5E0F4B3A24C14C8A9F3E1D2B3C4D5E6F" ) );
+    assert!( is_matching_signature( guid, "3A4B0F5EC1248A4C9F3E1D2B3C4D5E6F" ) );
+    assert!( !is_matching_signature( guid, "00000000000000000000000000000000" ) );
+    assert!( is_matching_signature( "", "00000000000000000000000000000000" ) );
+}
+
+#[test]
+fn test_jit_symbols() {
+    let mut symbols = JitSymbols::default();
+    symbols.add( b"1000 100 first\n1100 100 second\n2000 10 third\n", 10 );
+    assert_eq!( symbols.get( 0xfff, 10 ), None );
+    assert_eq!( symbols.get( 0x1000, 10 ), Some( "first" ) );
+    assert_eq!( symbols.get( 0x10ff, 10 ), Some( "first" ) );
+    assert_eq!( symbols.get( 0x1100, 10 ), Some( "second" ) );
+    assert_eq!( symbols.get( 0x1200, 10 ), None );
+    assert_eq!( symbols.get( 0x200f, 10 ), Some( "third" ) );
+
+    // The code of the first two was freed and reused.
+    symbols.add( b"1080 100 fourth\n", 20 );
+    assert_eq!( symbols.get( 0x1000, 20 ), None );
+    assert_eq!( symbols.get( 0x1080, 20 ), Some( "fourth" ) );
+    assert_eq!( symbols.get( 0x1180, 20 ), None );
+    assert_eq!( symbols.get( 0x2000, 20 ), Some( "third" ) );
+
+    // Before that they were still there.
+    assert_eq!( symbols.get( 0x1000, 15 ), Some( "first" ) );
+    assert_eq!( symbols.get( 0x1080, 15 ), Some( "first" ) );
+    assert_eq!( symbols.get( 0x1180, 15 ), Some( "second" ) );
+}
+
+#[test]
+fn test_jit_symbols_named_later() {
+    let mut symbols = JitSymbols::default();
+    symbols.add( b"1000 100 first\n", 10 );
+    symbols.add( b"2000 100 second\n", 20 );
+    symbols.add( b"2000 80 third\n", 30 );
+
+    // The code was already running before the map was read.
+    assert_eq!( symbols.get( 0x1000, 5 ), Some( "first" ) );
+    assert_eq!( symbols.get( 0x2000, 15 ), Some( "second" ) );
+    assert_eq!( symbols.get( 0x2000, 5 ), Some( "second" ) );
+    assert_eq!( symbols.get( 0x2000, 25 ), Some( "second" ) );
+    assert_eq!( symbols.get( 0x2000, 35 ), Some( "third" ) );
+    assert_eq!( symbols.get( 0x2090, 35 ), None );
+}
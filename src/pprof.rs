@@ -0,0 +1,299 @@
+// A minimal encoder for pprof's `profile.proto`; see:
+//   https://github.com/google/pprof/blob/master/proto/profile.proto
+
+use std::collections::HashMap;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_LENGTH_DELIMITED: u32 = 2;
+
+pub(crate) struct Encoder {
+    buffer: Vec< u8 >
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Encoder {
+            buffer: Vec::new()
+        }
+    }
+
+    fn write_varint( &mut self, mut value: u64 ) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.buffer.push( byte );
+                break;
+            }
+
+            self.buffer.push( byte | 0x80 );
+        }
+    }
+
+    fn write_key( &mut self, field: u32, wire_type: u32 ) {
+        self.write_varint( ((field << 3) | wire_type) as u64 );
+    }
+
+    fn uint64( &mut self, field: u32, value: u64 ) {
+        if value == 0 {
+            return;
+        }
+
+        self.write_key( field, WIRE_VARINT );
+        self.write_varint( value );
+    }
+
+    fn int64( &mut self, field: u32, value: i64 ) {
+        self.uint64( field, value as u64 );
+    }
+
+    fn bool( &mut self, field: u32, value: bool ) {
+        self.uint64( field, value as u64 );
+    }
+
+    pub(crate) fn bytes( &mut self, field: u32, value: &[u8] ) {
+        self.write_key( field, WIRE_LENGTH_DELIMITED );
+        self.write_varint( value.len() as u64 );
+        self.buffer.extend_from_slice( value );
+    }
+
+    fn packed( &mut self, field: u32, values: impl Iterator< Item = u64 > ) {
+        let mut inner = Encoder::new();
+        for value in values {
+            inner.write_varint( value );
+        }
+
+        if inner.buffer.is_empty() {
+            return;
+        }
+
+        self.bytes( field, &inner.buffer );
+    }
+
+    pub(crate) fn message( &mut self, field: u32, callback: impl FnOnce( &mut Encoder ) ) {
+        let mut inner = Encoder::new();
+        callback( &mut inner );
+        self.bytes( field, &inner.buffer );
+    }
+
+    #[cfg(feature = "native")]
+    pub(crate) fn into_bytes( self ) -> Vec< u8 > {
+        self.buffer
+    }
+}
+
+pub(crate) struct ValueType {
+    pub kind: i64,
+    pub unit: i64
+}
+
+pub(crate) struct Label {
+    pub key: i64,
+    pub string: i64,
+    pub number: i64
+}
+
+pub(crate) struct Sample {
+    pub location_ids: Vec< u64 >,
+    pub values: Vec< i64 >,
+    pub labels: Vec< Label >
+}
+
+#[derive(Default)]
+pub(crate) struct Mapping {
+    pub id: u64,
+    pub memory_start: u64,
+    pub memory_limit: u64,
+    pub file_offset: u64,
+    pub filename: i64,
+    pub build_id: i64,
+    pub has_functions: bool,
+    pub has_filenames: bool,
+    pub has_line_numbers: bool,
+    pub has_inline_frames: bool
+}
+
+pub(crate) struct Line {
+    pub function_id: u64,
+    pub line: i64
+}
+
+pub(crate) struct Location {
+    pub id: u64,
+    pub mapping_id: u64,
+    pub address: u64,
+    pub lines: Vec< Line >
+}
+
+pub(crate) struct Function {
+    pub id: u64,
+    pub name: i64,
+    pub system_name: i64,
+    pub filename: i64
+}
+
+pub(crate) struct Profile {
+    pub sample_types: Vec< ValueType >,
+    pub samples: Vec< Sample >,
+    pub mappings: Vec< Mapping >,
+    pub locations: Vec< Location >,
+    pub functions: Vec< Function >,
+    strings: Vec< String >,
+    string_to_index: HashMap< String, i64 >,
+    pub time_nanos: i64,
+    pub duration_nanos: i64,
+    pub period_type: Option< ValueType >,
+    pub period: i64
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        let mut profile = Profile {
+            sample_types: Vec::new(),
+            samples: Vec::new(),
+            mappings: Vec::new(),
+            locations: Vec::new(),
+            functions: Vec::new(),
+            strings: Vec::new(),
+            string_to_index: HashMap::new(),
+            time_nanos: 0,
+            duration_nanos: 0,
+            period_type: None,
+            period: 0
+        };
+
+        // The first entry of the string table must always be an empty string.
+        profile.intern( "" );
+        profile
+    }
+
+    pub fn intern( &mut self, string: &str ) -> i64 {
+        if let Some( &index ) = self.string_to_index.get( string ) {
+            return index;
+        }
+
+        let index = self.strings.len() as i64;
+        self.strings.push( string.to_owned() );
+        self.string_to_index.insert( string.to_owned(), index );
+        index
+    }
+
+    pub fn serialize( &self ) -> Vec< u8 > {
+        let mut encoder = Encoder::new();
+        for sample_type in &self.sample_types {
+            encoder.message( 1, |encoder| {
+                encoder.int64( 1, sample_type.kind );
+                encoder.int64( 2, sample_type.unit );
+            });
+        }
+
+        for sample in &self.samples {
+            encoder.message( 2, |encoder| {
+                encoder.packed( 1, sample.location_ids.iter().cloned() );
+                encoder.packed( 2, sample.values.iter().map( |&value| value as u64 ) );
+                for label in &sample.labels {
+                    encoder.message( 3, |encoder| {
+                        encoder.int64( 1, label.key );
+                        encoder.int64( 2, label.string );
+                        encoder.int64( 3, label.number );
+                    });
+                }
+            });
+        }
+
+        for mapping in &self.mappings {
+            encoder.message( 3, |encoder| {
+                encoder.uint64( 1, mapping.id );
+                encoder.uint64( 2, mapping.memory_start );
+                encoder.uint64( 3, mapping.memory_limit );
+                encoder.uint64( 4, mapping.file_offset );
+                encoder.int64( 5, mapping.filename );
+                encoder.int64( 6, mapping.build_id );
+                encoder.bool( 7, mapping.has_functions );
+                encoder.bool( 8, mapping.has_filenames );
+                encoder.bool( 9, mapping.has_line_numbers );
+                encoder.bool( 10, mapping.has_inline_frames );
+            });
+        }
+
+        for location in &self.locations {
+            encoder.message( 4, |encoder| {
+                encoder.uint64( 1, location.id );
+                encoder.uint64( 2, location.mapping_id );
+                encoder.uint64( 3, location.address );
+                for line in &location.lines {
+                    encoder.message( 4, |encoder| {
+                        encoder.uint64( 1, line.function_id );
+                        encoder.int64( 2, line.line );
+                    });
+                }
+            });
+        }
+
+        for function in &self.functions {
+            encoder.message( 5, |encoder| {
+                encoder.uint64( 1, function.id );
+                encoder.int64( 2, function.name );
+                encoder.int64( 3, function.system_name );
+                encoder.int64( 4, function.filename );
+            });
+        }
+
+        for string in &self.strings {
+            encoder.bytes( 6, string.as_bytes() );
+        }
+
+        encoder.int64( 9, self.time_nanos );
+        encoder.int64( 10, self.duration_nanos );
+        if let Some( ref period_type ) = self.period_type {
+            encoder.message( 11, |encoder| {
+                encoder.int64( 1, period_type.kind );
+                encoder.int64( 2, period_type.unit );
+            });
+        }
+        encoder.int64( 12, self.period );
+
+        encoder.buffer
+    }
+}
+
+#[test]
+fn test_varint() {
+    let mut encoder = Encoder::new();
+    encoder.write_varint( 1 );
+    encoder.write_varint( 150 );
+    encoder.write_varint( 300 );
+    assert_eq!( encoder.buffer, vec![ 0x01, 0x96, 0x01, 0xAC, 0x02 ] );
+}
+
+#[test]
+fn test_serialize_empty_profile() {
+    let profile = Profile::new();
+    assert_eq!( profile.serialize(), vec![ 0x32, 0x00 ] );
+}
+
+#[test]
+fn test_serialize_sample() {
+    let mut profile = Profile::new();
+    let kind = profile.intern( "samples" );
+    let unit = profile.intern( "count" );
+    assert_eq!( profile.intern( "samples" ), kind );
+
+    profile.sample_types.push( ValueType { kind, unit } );
+    profile.samples.push( Sample {
+        location_ids: vec![ 1, 2 ],
+        values: vec![ 3 ],
+        labels: Vec::new()
+    });
+
+    assert_eq!(
+        profile.serialize(),
+        vec![
+            0x0A, 0x04, 0x08, 0x01, 0x10, 0x02,
+            0x12, 0x07, 0x0A, 0x02, 0x01, 0x02, 0x12, 0x01, 0x03,
+            0x32, 0x00,
+            0x32, 0x07, b's', b'a', b'm', b'p', b'l', b'e', b's',
+            0x32, 0x05, b'c', b'o', b'u', b'n', b't'
+        ]
+    );
+}
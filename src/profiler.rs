@@ -1,15 +1,17 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
 use std::borrow::Cow;
 use std::slice;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::MetadataExt;
 use std::ops::{Deref, DerefMut, Range};
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use chrono::prelude::*;
 use speedy::{Writable, Endianness};
@@ -24,17 +26,29 @@ use nwind::{
     BinaryData,
     DwarfRegs,
     RangeMap,
-    BinaryId
+    BinaryId,
+    UnwindFailure,
+    UserFrame
 };
 
 use crate::args::{self, TargetProcess};
-use crate::utils::{SigintHandler, read_string_lossy, get_major, get_minor, get_ms};
+use crate::utils::{SigintHandler, fnv1a, read_string_lossy, get_major, get_minor, get_ms, read_cgroup, parse_smaps_rollup, percent};
 use crate::archive::{FramedPacket, Packet, Inode, Bitness, DwarfReg, ARCHIVE_MAGIC, ARCHIVE_VERSION};
 use crate::execution_queue::ExecutionQueue;
+use crate::kallsyms;
 use crate::ps::{wait_for_process, find_process};
 use crate::stack_reader::StackReader;
 use crate::mount_info::PathResolver;
 use crate::raw_data::CowRawData;
+use crate::triggers::Triggers;
+use crate::progress::ProgressReporter;
+use crate::quality::QualityStats;
+use crate::reader;
+use crate::self_profile::{SelfProfile, CpuTimer, Subsystem, log_self_profile};
+use crate::python::PythonSampler;
+use crate::perf_map::{PerfMapWatcher, PerfMapAgent, ReadyToRunMaps};
+use crate::jitdump::JitDumpWatcher;
+use crate::cmd_watchdog::monotonic_timestamp;
 
 fn get_vdso() -> Option< &'static [u8] > {
     let maps_str = read_string_lossy( "/proc/self/maps" ).expect( "cannot read /proc/self/maps" );
@@ -98,7 +112,7 @@ fn update_maps( maps: &mut RangeMap< Region >, new_maps: &mut Vec< Region > ) {
 
 #[cfg(test)]
 mod tests {
-    use super::update_maps;
+    use super::{update_maps, build_id_to_inode, load_binary_for_region};
 
     use nwind::{arch, RangeMap, IAddressSpace, AddressSpace, Inode, BinaryData};
     use proc_maps::Region;
@@ -239,12 +253,43 @@ mod tests {
             true
         }
     }
+
+    #[test]
+    fn load_binary_identified_by_build_id() {
+        let path = concat!( env!( "CARGO_MANIFEST_DIR" ), "/test-data/bin/amd64-pthread_cond_wait" );
+        let build_id = BinaryData::load_from_fs( path ).unwrap().build_id().unwrap().to_owned();
+
+        // This is what a region looks like when the mapped file couldn't be found by its path.
+        let mut mapped = region( 0, 0x1000, 0, path );
+        mapped.inode = build_id_to_inode( &build_id );
+
+        assert!( load_binary_for_region( &None, &mapped, Some( &build_id ) ).is_some() );
+        assert!( load_binary_for_region( &None, &mapped, Some( &[0xAA; 20] ) ).is_none() );
+        assert!( load_binary_for_region( &None, &mapped, None ).is_none() );
+        assert!( load_binary_for_region( &None, &mapped, Some( &[] ) ).is_some() );
+    }
 }
 
 fn resolve_path< 'a >( path_resolver: &Option< PathResolver >, path: &'a dyn AsRef< Path >, expected_major_minor: Option< (u32, u32) > ) -> Cow< 'a, Path > {
     let path = path.as_ref();
     trace!( "Trying to resolve {:?}...", path );
 
+    // If the process is in a container then a file with the same path might also exist outside of it,
+    // but it could be a different version, so it's preferable to access the one it sees directly.
+    if let Some( candidate ) = path_resolver.as_ref().and_then( |resolver| resolver.resolve_through_proc_root( path ) ) {
+        let is_matching = match expected_major_minor {
+            Some( (expected_major, expected_minor) ) => fs::metadata( &candidate ).map( |metadata| {
+                get_major( metadata.dev() ) == expected_major && get_minor( metadata.dev() ) == expected_minor
+            }).unwrap_or( false ),
+            None => candidate.exists()
+        };
+
+        if is_matching {
+            debug!( "Resolved {:?} into {:?}", path, candidate );
+            return candidate.into();
+        }
+    }
+
     if path.exists() {
         if let Some( (expected_major, expected_minor) ) = expected_major_minor {
             if let Ok( metadata ) = fs::metadata( &path ) {
@@ -280,11 +325,69 @@ fn resolve_path< 'a >( path_resolver: &Option< PathResolver >, path: &'a dyn AsR
     return path.into();
 }
 
+fn region_inode( region: &Region ) -> Inode {
+    Inode { inode: region.inode, dev_major: region.major, dev_minor: region.minor }
+}
+
+/// Makes up a non-zero inode number for a mapped file which can only be identified by its build ID.
+fn build_id_to_inode( build_id: &[u8] ) -> u64 {
+    fnv1a( build_id ).max( 1 )
+}
+
+/// Loads the binary which is mapped in a given region of a live process.
+///
+/// If the kernel told us the build ID of the mapped file then the loaded binary must have the same one,
+/// since otherwise the file was replaced after it was mapped.
+pub(crate) fn load_binary_for_region( path_resolver: &Option< PathResolver >, region: &Region, expected_build_id: Option< &[u8] > ) -> Option< BinaryData > {
+    if region.name == "[vdso]" {
+        return get_vdso().and_then( |vdso| BinaryData::load_from_static_slice( &region.name, vdso ).ok() );
+    }
+
+    let path = resolve_path( path_resolver, &region.name, Some( (region.major, region.minor) ) );
+    let mut data = match BinaryData::load_from_fs( &path ) {
+        Ok( data ) => data,
+        Err( error ) => {
+            if error.kind() == io::ErrorKind::InvalidData {
+                if let Some( inner_error ) = error.get_ref() {
+                    if format!( "{}", inner_error ) == "not an ELF file" {
+                        return None;
+                    }
+                }
+            }
+            error!( "Failed to load '{}' from {:?}: {}", region.name, path, error );
+            return None;
+        }
+    };
+
+    // A region which could only be identified by its build ID doesn't have a real inode to check.
+    if !region_inode( region ).is_invalid() || expected_build_id.is_none() {
+        if let Err( error ) = data.check_inode( region_inode( region ) ) {
+            error!( "{}", error );
+            return None;
+        }
+    }
+
+    if let (Some( expected_build_id ), Some( build_id )) = (expected_build_id, data.build_id()) {
+        if !expected_build_id.is_empty() && !build_id.is_empty() && build_id != expected_build_id {
+            error!( "The build ID of {:?} doesn't match the one of '{}' which was mapped; was it replaced after it was mapped?", path, region.name );
+            return None;
+        }
+    }
+
+    // The `/proc/<pid>/root` prefix is only valid while the process is alive, so the path which it sees is used instead.
+    if path.starts_with( "/proc/" ) && !region.name.starts_with( "/proc/" ) {
+        data.set_name( &region.name );
+    }
+
+    Some( data )
+}
+
 fn process_maps(
     maps: &RangeMap< Region >,
     offline: bool,
     pid: u32,
     path_resolver: &Option< PathResolver >,
+    kernel_build_ids: &HashMap< Inode, Vec< u8 > >,
     address_space: &mut AddressSpace< arch::native::Arch >,
     writer: &ExecutionQueue< PacketWriter >
 ) {
@@ -301,41 +404,10 @@ fn process_maps(
             handle.should_load_frame_descriptions( !offline );
             handle.should_load_symbols( !offline );
 
-            if region.name == "[vdso]" {
-                if let Some( vdso ) = get_vdso() {
-                    let data = match BinaryData::load_from_static_slice( &region.name, vdso ) {
-                        Ok( data ) => data,
-                        Err( _ ) => return
-                    };
-
-                    handle.set_binary( data.into() );
-                    return;
-                }
-                return;
-            }
-
-            let path = resolve_path( path_resolver, &region.name, Some( (region.major, region.minor) ) );
-            let data = match BinaryData::load_from_fs( &path ) {
-                Ok( data ) => data,
-                Err( error ) => {
-                    if error.kind() == io::ErrorKind::InvalidData {
-                        if let Some( inner_error ) = error.get_ref() {
-                            if format!( "{}", inner_error ) == "not an ELF file" {
-                                return;
-                            }
-                        }
-                    }
-                    error!( "Failed to load '{}' from {:?}: {}", region.name, path, error );
-                    return;
-                }
-            };
-
-            if let Err( error ) = data.check_inode( Inode { inode: region.inode, dev_major: region.major, dev_minor: region.minor } ) {
-                error!( "{}", error );
-                return;
+            let build_id = kernel_build_ids.get( &region_inode( region ) ).map( |build_id| build_id.as_slice() );
+            if let Some( data ) = load_binary_for_region( path_resolver, region, build_id ) {
+                handle.set_binary( data.into() );
             }
-
-            handle.set_binary( data.into() );
         })
     };
 
@@ -365,14 +437,32 @@ fn process_maps(
     });
 }
 
+/// Keeps track of how many bytes were written to the output.
+pub struct CountingWriter {
+    fp: Box< dyn Write + Send >,
+    bytes_written: Arc< AtomicU64 >
+}
+
+impl io::Write for CountingWriter {
+    fn write( &mut self, buf: &[u8] ) -> io::Result< usize > {
+        let count = self.fp.write( buf )?;
+        self.bytes_written.fetch_add( count as u64, Ordering::Relaxed );
+        Ok( count )
+    }
+
+    fn flush( &mut self ) -> io::Result< () > {
+        self.fp.flush()
+    }
+}
+
 pub struct PacketWriter {
     offline: bool,
-    fp: BufWriter< File >,
+    fp: BufWriter< CountingWriter >,
     binaries_written: HashSet< BinaryId >
 }
 
 impl Deref for PacketWriter {
-    type Target = BufWriter< File >;
+    type Target = BufWriter< CountingWriter >;
 
     #[inline]
     fn deref( &self ) -> &Self::Target {
@@ -478,13 +568,23 @@ impl PacketWriter {
             &b""[..]
         };
 
+        // This is used to match the binary with a local file when it's being analyzed and the inodes can't be relied upon.
+        // The binary could have been loaded from a different mount namespace, in which case its path
+        // points to a different file here, so the mtime is only used if the inode matches.
+        let mtime = fs::metadata( binary.name() ).ok()
+            .filter( |metadata| binary.inode().map( |inode| inode.inode == metadata.ino() ).unwrap_or( false ) )
+            .map( |metadata| metadata.mtime().max( 0 ) as u64 )
+            .unwrap_or( 0 );
+
         self.write_packet( Packet::BinaryInfo {
             inode,
             path: binary.name().as_bytes().into(),
             is_shared_object: binary.is_shared_object(),
             debuglink: debuglink.into(),
             symbol_table_count: binary.symbol_tables().len() as u16,
-            load_headers: binary.load_headers().into()
+            load_headers: binary.load_headers().into(),
+            file_size: binary.as_bytes().len() as u64,
+            mtime
         })?;
 
         if let Some( build_id ) = binary.build_id() {
@@ -535,13 +635,9 @@ impl PacketWriter {
     }
 }
 
-fn initialize(
-    sigint_handler: &SigintHandler,
-    args: &args::GenericProfilerArgs
-) -> Result< (u32, AddressSpace< arch::native::Arch >, ExecutionQueue< PacketWriter >, Option< PathResolver >, PathBuf), Box< dyn Error > >
-{
-    let offline = args.offline;
-    let target_process = args.process_filter.clone().into();
+/// Figures out the PID of the process which should be profiled, waiting for it to appear if necessary.
+pub(crate) fn find_target_process( sigint_handler: &SigintHandler, process_filter: &args::ProcessFilter ) -> Result< u32, Box< dyn Error > > {
+    let target_process = process_filter.clone().into();
     let pid = match target_process {
         TargetProcess::ByPid( pid ) => pid,
         TargetProcess::ByName( name ) => {
@@ -560,6 +656,19 @@ fn initialize(
         }
     };
 
+    Ok( pid )
+}
+
+fn initialize(
+    sigint_handler: &SigintHandler,
+    args: &args::GenericProfilerArgs,
+    write_output: bool,
+    bytes_written: Arc< AtomicU64 >
+) -> Result< (u32, AddressSpace< arch::native::Arch >, ExecutionQueue< PacketWriter >, Option< PathResolver >, PathBuf), Box< dyn Error > >
+{
+    let offline = args.offline;
+    let pid = find_target_process( sigint_handler, &args.process_filter )?;
+
     let start_timestamp = Instant::now();
 
     if args.lock_memory {
@@ -592,29 +701,15 @@ fn initialize(
         dev_minor: get_minor( exec_metadata.dev() )
     };
 
-    let output_path = if let Some( ref output_path ) = args.output {
-        output_path.to_os_string()
+    let (output_path, fp): (PathBuf, Box< dyn Write + Send >) = if !write_output {
+        (PathBuf::new(), Box::new( io::sink() ))
     } else {
-        let executable = executable.to_string_lossy();
-        let basename: String = executable[ executable.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ].chars().map( |ch| {
-            if ch.is_alphanumeric() {
-                ch
-            } else {
-                '_'
-            }
-        }).collect();
-
-        let now = Utc::now();
-        let filename = format!( "{}{:02}{:02}_{:02}{:02}{:02}_{:05}_{}.nperf", now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second(), pid, basename );
-        OsStr::new( &filename ).to_os_string()
+        open_output( args, &executable, pid )?
     };
 
-    let output_path: PathBuf = output_path.into();
-    info!( "Opening {:?} for writing...", output_path );
-    let fp = File::create( &output_path ).map_err( |err| format!( "cannot open {:?} for writing: {}", output_path, err ) )?;
     let fp = PacketWriter {
         offline,
-        fp: BufWriter::new( fp ),
+        fp: BufWriter::new( CountingWriter { fp, bytes_written } ),
         binaries_written: HashSet::new()
     };
 
@@ -623,22 +718,26 @@ fn initialize(
         fp.write_header()?;
         fp.write_machine_info()?;
 
-        debug!( "Writing kallsyms..." );
-        let kallsyms = fs::read( "/proc/kallsyms" )?;
-        fp.write_packet( Packet::FileBlob {
-            path: "/proc/kallsyms".as_bytes().into(),
-            data: kallsyms.into()
-        })?;
+        // The snapshot allows the kernel frames to be symbolized on another machine.
+        if let Some( kallsyms ) = kallsyms::read() {
+            debug!( "Writing kallsyms..." );
+            fp.write_packet( Packet::FileBlob {
+                path: "/proc/kallsyms".as_bytes().into(),
+                data: kallsyms.into()
+            })?;
+        }
 
         Ok(())
     });
 
+    let cgroup = read_cgroup( pid ).unwrap_or_default();
     writer.spawn( move |fp| {
         debug!( "Writing process info..." );
         fp.write_packet( Packet::ProcessInfo {
             pid: pid,
             executable: executable.as_os_str().as_bytes().into(),
-            binary_id: exec_ident
+            binary_id: exec_ident,
+            cgroup: cgroup.into_bytes().into()
         })?;
 
         Ok(())
@@ -658,6 +757,36 @@ fn initialize(
     Ok( (pid, address_space, writer, path_resolver, output_path) )
 }
 
+fn open_output( args: &args::GenericProfilerArgs, executable: &Path, pid: u32 ) -> Result< (PathBuf, Box< dyn Write + Send >), Box< dyn Error > > {
+    // Nothing is ever seeked in the output, so it can be piped through e.g. `ssh` or `zstd`.
+    if args.output.as_ref().map( |output| output == "-" ).unwrap_or( false ) {
+        info!( "Writing the profiling data to stdout..." );
+        return Ok( (PathBuf::from( "-" ), Box::new( io::stdout() )) );
+    }
+
+    let output_path = if let Some( ref output_path ) = args.output {
+        output_path.to_os_string()
+    } else {
+        let executable = executable.to_string_lossy();
+        let basename: String = executable[ executable.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ].chars().map( |ch| {
+            if ch.is_alphanumeric() {
+                ch
+            } else {
+                '_'
+            }
+        }).collect();
+
+        let now = Utc::now();
+        let filename = format!( "{}{:02}{:02}_{:02}{:02}{:02}_{:05}_{}.nperf", now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second(), pid, basename );
+        OsStr::new( &filename ).to_os_string()
+    };
+
+    let output_path: PathBuf = output_path.into();
+    info!( "Opening {:?} for writing...", output_path );
+    let fp = File::create( &output_path ).map_err( |err| format!( "cannot open {:?} for writing: {}", output_path, err ) )?;
+    Ok( (output_path, Box::new( fp )) )
+}
+
 pub struct ProfilingController {
     pid: u32,
     sigint: SigintHandler,
@@ -665,12 +794,36 @@ pub struct ProfilingController {
     writer: ExecutionQueue< PacketWriter >,
     path_resolver: Option< PathResolver >,
     offline: bool,
+    keep_raw: bool,
+    python: bool,
+    python_sampler: Option< PythonSampler >,
+    perf_map: PerfMapWatcher,
+    perf_map_agent: Option< PerfMapAgent >,
+    perf_info: PerfMapWatcher,
+    r2r_maps: ReadyToRunMaps,
+    jitdump: JitDumpWatcher,
     sample_count_limit: Option< u64 >,
     time_limit: Option< u64 >,
     sample_counter: u64,
+    unwound_sample_counter: u64,
+    frame_counter: u64,
+    truncated_stack_counter: u64,
+    unwind_failure_counter: u64,
     profiling_started_ts: Instant,
     maps: RangeMap< Region >,
-    output_path: PathBuf
+    output_path: PathBuf,
+    triggers: Option< Triggers >,
+    lost_event_counter: u64,
+    bytes_written: Arc< AtomicU64 >,
+    progress: Option< ProgressReporter >,
+    // The build IDs of the mapped files as reported by the kernel.
+    kernel_build_ids: HashMap< Inode, Vec< u8 > >,
+    memory_stats_interval: Option< Duration >,
+    last_memory_stats: Option< Instant >,
+    self_profile: Option< SelfProfile >,
+    stop_flag: Option< Arc< AtomicBool > >,
+    // The last cgroup written out for every process seen so far.
+    cgroups: HashMap< u32, String >
 }
 
 pub struct Sample< 'a > {
@@ -684,8 +837,52 @@ pub struct Sample< 'a > {
 
 impl ProfilingController {
     pub fn new( args: &args::GenericProfilerArgs ) -> Result< Self, Box< dyn Error > > {
-        let sigint = SigintHandler::new();
-        let (pid, address_space, writer, path_resolver, output_path) = initialize( &sigint, args )?;
+        Self::new_impl( args, true )
+    }
+
+    /// Creates a controller which doesn't write any profiling data; meant to be used
+    /// with `symbolize_sample` to process the samples on the fly.
+    pub fn new_without_output( args: &args::GenericProfilerArgs ) -> Result< Self, Box< dyn Error > > {
+        Self::new_impl( args, false )
+    }
+
+    /// Creates a controller for use within another program; unlike the other constructors
+    /// this one doesn't take over the SIGINT, so the profiling has to be stopped with `set_stop_flag`.
+    pub fn new_embedded( args: &args::GenericProfilerArgs, write_output: bool ) -> Result< Self, Box< dyn Error > > {
+        Self::new_with_sigint_handler( args, write_output, SigintHandler::new_passive() )
+    }
+
+    fn new_impl( args: &args::GenericProfilerArgs, write_output: bool ) -> Result< Self, Box< dyn Error > > {
+        Self::new_with_sigint_handler( args, write_output, SigintHandler::new() )
+    }
+
+    fn new_with_sigint_handler( args: &args::GenericProfilerArgs, write_output: bool, sigint: SigintHandler ) -> Result< Self, Box< dyn Error > > {
+        let progress = match args.progress {
+            Some( ref path ) => {
+                if !(args.progress_interval > 0.0) {
+                    return Err( "the progress interval must be greater than zero".into() );
+                }
+
+                Some( ProgressReporter::new( Path::new( path ), Duration::from_secs_f64( args.progress_interval ) )? )
+            },
+            None => None
+        };
+
+        if !(args.perf_map_agent_interval > 0.0) {
+            return Err( "the perf-map-agent interval must be greater than zero".into() );
+        }
+
+        if args.memory_stats && !(args.memory_stats_interval > 0.0) {
+            return Err( "the memory stats interval must be greater than zero".into() );
+        }
+
+        let bytes_written = Arc::new( AtomicU64::new( 0 ) );
+        let (pid, address_space, writer, path_resolver, output_path) = initialize( &sigint, args, write_output, bytes_written.clone() )?;
+
+        let perf_map_agent = match args.perf_map_agent {
+            Some( ref path ) => Some( PerfMapAgent::new( pid, Path::new( path ), Duration::from_secs_f64( args.perf_map_agent_interval ) )? ),
+            None => None
+        };
 
         Ok( ProfilingController {
             sigint,
@@ -694,17 +891,42 @@ impl ProfilingController {
             writer,
             path_resolver,
             offline: args.offline,
+            keep_raw: args.keep_raw,
+            python: args.python,
+            python_sampler: None,
+            perf_map: PerfMapWatcher::new( pid, "perf" ),
+            perf_map_agent,
+            perf_info: PerfMapWatcher::new( pid, "perfinfo" ),
+            r2r_maps: ReadyToRunMaps::new( pid, args.r2r_map_dir.iter().map( PathBuf::from ).collect() ),
+            jitdump: JitDumpWatcher::new( pid ),
             sample_count_limit: args.sample_count,
             time_limit: args.time_limit,
             sample_counter: 0,
+            unwound_sample_counter: 0,
+            frame_counter: 0,
+            truncated_stack_counter: 0,
+            unwind_failure_counter: 0,
             profiling_started_ts: Instant::now(),
             maps: RangeMap::new(),
-            output_path
+            output_path,
+            triggers: None,
+            lost_event_counter: 0,
+            bytes_written,
+            progress,
+            kernel_build_ids: HashMap::new(),
+            memory_stats_interval: if args.memory_stats { Some( Duration::from_secs_f64( args.memory_stats_interval ) ) } else { None },
+            last_memory_stats: None,
+            self_profile: if args.self_profile { Some( SelfProfile::new() ) } else { None },
+            stop_flag: None,
+            cgroups: HashMap::new()
         })
     }
 
-    pub fn output_path( &self ) -> &Path {
-        &self.output_path
+    /// Removes the output file, e.g. when the profiling couldn't be started.
+    pub fn remove_output( &self ) {
+        if self.output_path.as_os_str() != "-" && !self.output_path.as_os_str().is_empty() {
+            let _ = fs::remove_file( &self.output_path );
+        }
     }
 
     pub fn pid( &self ) -> u32 {
@@ -725,14 +947,192 @@ impl ProfilingController {
         });
     }
 
+    /// Finds out which file was mapped when the kernel gave us its build ID instead of its major, minor and inode.
+    ///
+    /// The file is only looked up by its path here, so it's checked whether it's actually
+    /// the same one when it's loaded, by comparing its build ID with the one from the kernel.
+    ///
+    /// If the file can't be found (e.g. it was deleted since it was mapped) then it's identified
+    /// by its build ID alone, or by its name if there's no build ID either.
+    pub fn identify_mapped_file( &mut self, name: &str, build_id: Option< Vec< u8 > > ) -> Inode {
+        let path = resolve_path( &self.path_resolver, &name, None );
+        let inode = match fs::metadata( &path ) {
+            Ok( metadata ) => Inode {
+                inode: metadata.ino(),
+                dev_major: get_major( metadata.dev() ),
+                dev_minor: get_minor( metadata.dev() )
+            },
+            Err( error ) => {
+                debug!( "Failed to read the metadata of {:?}: {}", path, error );
+                Inode {
+                    inode: build_id.as_ref().map( |build_id| build_id_to_inode( build_id ) ).unwrap_or( 0 ),
+                    dev_major: 0,
+                    dev_minor: 0
+                }
+            }
+        };
+
+        if let Some( build_id ) = build_id {
+            self.kernel_build_ids.insert( inode, build_id );
+        }
+
+        inode
+    }
+
     pub fn update_maps( &mut self, new_maps: &mut Vec< Region > ) {
         if new_maps.is_empty() {
             return;
         }
 
+        let timer = CpuTimer::start( &self.self_profile );
         update_maps( &mut self.maps, new_maps );
-        process_maps( &self.maps, self.offline, self.pid, &self.path_resolver, &mut self.address_space, &self.writer );
+        process_maps( &self.maps, self.offline, self.pid, &self.path_resolver, &self.kernel_build_ids, &mut self.address_space, &self.writer );
         new_maps.clear();
+
+        // The interpreter is only looked for until it's found, since it's either in the main
+        // executable or in a library which is loaded before any Python code gets to run.
+        if self.python && self.python_sampler.is_none() {
+            let regions: Vec< _ > = self.maps.values().collect();
+            let path_resolver = &self.path_resolver;
+            let kernel_build_ids = &self.kernel_build_ids;
+            match PythonSampler::locate( self.pid, &regions, |region| {
+                let build_id = kernel_build_ids.get( &region_inode( region ) ).map( |build_id| build_id.as_slice() );
+                load_binary_for_region( path_resolver, region, build_id )
+            }) {
+                Ok( sampler ) => self.python_sampler = sampler,
+                Err( error ) => {
+                    warn!( "Python stacks won't be sampled: {}", error );
+                    self.python = false;
+                }
+            }
+        }
+
+        timer.stop( &mut self.self_profile, Subsystem::SymbolLoading );
+    }
+
+    /// Records the new entries of the process' perf map, if it has one, and reattaches `perf-map-agent` if it's time to do so.
+    ///
+    /// For .NET processes the symbols from the ReadyToRun maps of the newly loaded images are recorded too,
+    /// and so is the code from the process' jitdump file (e.g. the Wasm functions compiled by wasmtime).
+    pub fn poll_perf_map( &mut self ) {
+        if let Some( ref mut agent ) = self.perf_map_agent {
+            agent.poll();
+        }
+
+        if let Some( data ) = self.perf_map.poll() {
+            debug!( "Writing {} bytes of the perf map...", data.len() );
+            self.write_perf_map( data, None );
+        }
+
+        if let Some( (timestamp, data) ) = self.jitdump.poll( &self.maps ) {
+            debug!( "Writing {} bytes of the jitdump...", data.len() );
+            self.write_perf_map( data, timestamp );
+        }
+
+        if let Some( perf_info ) = self.perf_info.poll() {
+            let maps = &self.maps;
+            let data = self.r2r_maps.load( &perf_info, |image| {
+                maps.values()
+                    .filter( |region| region.name == image && region.file_offset == 0 )
+                    .map( |region| region.start )
+                    .min()
+            });
+
+            if !data.is_empty() {
+                debug!( "Writing {} bytes of the ReadyToRun maps...", data.len() );
+                self.write_perf_map( data, None );
+            }
+        }
+    }
+
+    /// Writes out the given perf map entries; unless the `timestamp` of when they were created is given
+    /// the time when they were read is used, which is close enough to the clock of the samples.
+    fn write_perf_map( &mut self, data: Vec< u8 >, timestamp: Option< u64 > ) {
+        self.write_packet( Packet::PerfMap {
+            pid: self.pid,
+            timestamp: timestamp.unwrap_or_else( monotonic_timestamp ),
+            data: data.into()
+        });
+    }
+
+    pub fn set_triggers( &mut self, triggers: Triggers ) {
+        self.triggers = Some( triggers );
+    }
+
+    pub fn poll_triggers( &mut self ) {
+        if let Some( ref mut triggers ) = self.triggers {
+            triggers.poll();
+        }
+    }
+
+    /// Writes out the status of the recording if `--progress` was used and it's time to do so.
+    pub fn report_progress( &mut self ) {
+        if let Some( ref mut progress ) = self.progress {
+            if progress.is_due() {
+                progress.report( self.sample_counter, self.lost_event_counter, self.bytes_written.load( Ordering::Relaxed ) );
+            }
+        }
+    }
+
+    /// Writes out the memory use of the process if `--memory-stats` was used and it's time to do so.
+    pub fn poll_memory_stats( &mut self ) {
+        let interval = match self.memory_stats_interval {
+            Some( interval ) => interval,
+            None => return
+        };
+
+        if self.last_memory_stats.map( |timestamp| timestamp.elapsed() < interval ).unwrap_or( false ) {
+            return;
+        }
+
+        self.last_memory_stats = Some( Instant::now() );
+        let path = format!( "/proc/{}/smaps_rollup", self.pid );
+        match read_string_lossy( &path ).ok().and_then( |contents| parse_smaps_rollup( &contents ) ) {
+            Some( stats ) => {
+                self.write_packet( Packet::MemoryStats {
+                    pid: self.pid,
+                    timestamp: monotonic_timestamp(),
+                    rss: stats.rss,
+                    pss: stats.pss,
+                    swap: stats.swap
+                });
+            },
+            None => {
+                // This is only there since Linux 4.14.
+                warn!( "Failed to read {}; the memory use won't be recorded anymore", path );
+                self.memory_stats_interval = None;
+            }
+        }
+    }
+
+    /// Keeps track of the events which were lost, for the progress reports.
+    /// Reads the cgroup of a given process again, e.g. after it called `exec`,
+    /// and writes it out if it has changed.
+    pub fn refresh_cgroup( &mut self, pid: u32 ) {
+        let cgroup = read_cgroup( pid ).unwrap_or_default();
+        if self.cgroups.get( &pid ) == Some( &cgroup ) {
+            return;
+        }
+
+        self.cgroups.insert( pid, cgroup.clone() );
+        self.write_packet( Packet::ProcessCgroup {
+            pid,
+            cgroup: cgroup.into_bytes().into()
+        });
+    }
+
+    pub fn add_lost_events( &mut self, count: u64 ) {
+        self.lost_event_counter += count;
+    }
+
+    /// Whether the samples should be currently dropped because of the start and stop triggers.
+    pub fn is_paused( &self ) -> bool {
+        self.triggers.as_ref().map( |triggers| !triggers.is_recording() ).unwrap_or( false )
+    }
+
+    /// Makes the profiling stop once the flag is set, e.g. from another thread.
+    pub fn set_stop_flag( &mut self, flag: Arc< AtomicBool > ) {
+        self.stop_flag = Some( flag );
     }
 
     pub fn should_stop( &self ) -> bool {
@@ -740,6 +1140,14 @@ impl ProfilingController {
             return true;
         }
 
+        if self.stop_flag.as_ref().map( |flag| flag.load( Ordering::Relaxed ) ).unwrap_or( false ) {
+            return true;
+        }
+
+        if self.triggers.as_ref().map( |triggers| triggers.is_finished() ).unwrap_or( false ) {
+            return true;
+        }
+
         if let Some( limit ) = self.sample_count_limit {
             if self.sample_counter >= limit {
                 return true;
@@ -761,9 +1169,19 @@ impl ProfilingController {
     }
 
     pub fn generate_sample( &mut self, dwarf_regs: &mut DwarfRegs, event: Sample ) {
+        let mut user_backtrace = Vec::new();
+        self.generate_sample_impl( dwarf_regs, event, &mut user_backtrace );
+    }
+
+    /// Writes out the sample, leaving the user backtrace in `user_backtrace` if it was unwound.
+    fn generate_sample_impl( &mut self, dwarf_regs: &mut DwarfRegs, event: Sample, user_backtrace: &mut Vec< UserFrame > ) {
         self.sample_counter += 1;
+        if !self.cgroups.contains_key( &event.pid ) {
+            self.refresh_cgroup( event.pid );
+        }
 
-        let mut user_backtrace = Vec::new();
+        let mut python_backtrace = Vec::new();
+        let mut raw_data = None;
         let packet;
         if self.offline {
             packet = Packet::RawSample {
@@ -776,10 +1194,36 @@ impl ProfilingController {
                 regs: Cow::Owned( dwarf_regs.iter().map( |(register, value)| DwarfReg { register, value } ).collect() )
             };
         } else {
+            // This has to be done before unwinding, which clobbers the registers.
+            if self.keep_raw {
+                raw_data = Some( Packet::RawSampleData {
+                    stack: CowRawData::Borrowed( (&event.stack).into() ),
+                    regs: Cow::Owned( dwarf_regs.iter().map( |(register, value)| DwarfReg { register, value } ).collect() )
+                });
+            }
 
             let stack = (&event.stack).into();
             let reader = StackReader { stack };
-            self.address_space.unwind( dwarf_regs, &reader, &mut user_backtrace );
+            let timer = CpuTimer::start( &self.self_profile );
+            let unwind_failure = self.address_space.unwind( dwarf_regs, &reader, user_backtrace ).err();
+            timer.stop( &mut self.self_profile, Subsystem::Unwinding );
+
+            self.unwound_sample_counter += 1;
+            self.frame_counter += user_backtrace.len() as u64;
+            match unwind_failure {
+                Some( UnwindFailure::StackTruncated ) => self.truncated_stack_counter += 1,
+                Some( _ ) => self.unwind_failure_counter += 1,
+                None => {}
+            }
+
+            if let Some( ref mut sampler ) = self.python_sampler {
+                if user_backtrace.iter().any( |frame| sampler.is_interpreter_address( frame.address ) ) {
+                    let writer = &self.writer;
+                    python_backtrace = sampler.sample( event.tid, &mut |packet| {
+                        writer.spawn( move |fp| fp.write_packet( packet ) );
+                    });
+                }
+            }
 
             packet = Packet::Sample {
                 timestamp: event.timestamp,
@@ -787,18 +1231,208 @@ impl ProfilingController {
                 tid: event.tid,
                 cpu: event.cpu,
                 kernel_backtrace: event.kernel_backtrace,
-                user_backtrace: Cow::Borrowed( &user_backtrace )
+                user_backtrace: Cow::Borrowed( user_backtrace ),
+                unwind_failure,
+                python_backtrace: Cow::Borrowed( &python_backtrace )
             };
         }
 
         let framed = FramedPacket::Known( packet );
-        let bytes = framed.write_to_vec().unwrap();
+        let mut bytes = framed.write_to_vec().unwrap();
+        if let Some( raw_data ) = raw_data {
+            bytes.extend( FramedPacket::Known( raw_data ).write_to_vec().unwrap() );
+        }
+
         self.writer.spawn( move |fp| {
             fp.write_all( &bytes )
         });
 
         dwarf_regs.clear();
     }
+
+    pub(crate) fn quality_stats( &self, lost_event_count: u64 ) -> QualityStats {
+        QualityStats {
+            sample_count: self.sample_counter,
+            lost_event_count,
+            unwound_sample_count: self.unwound_sample_counter,
+            unwind_failure_count: self.truncated_stack_counter + self.unwind_failure_counter
+        }
+    }
+
+    /// Writes out a summary of the recording, and logs it.
+    pub fn finish( &mut self, lost_event_count: u64 ) {
+        let sample_count = self.sample_counter;
+        let unwound_sample_count = self.unwound_sample_counter;
+        let frame_count = self.frame_counter;
+        let truncated_stack_count = self.truncated_stack_counter;
+        let unwind_failure_count = self.unwind_failure_counter;
+        let progress = self.progress.take();
+        let self_profile = self.self_profile.as_ref().map( |self_profile| self_profile.finish() );
+        self.writer.spawn( move |fp| {
+            fp.flush()?;
+            let packet = Packet::RecordingStats {
+                sample_count,
+                lost_event_count,
+                unwound_sample_count,
+                frame_count,
+                truncated_stack_count,
+                unwind_failure_count,
+                binary_count: fp.binaries_written.len() as u64,
+                bytes_written: fp.get_ref().bytes_written.load( Ordering::Relaxed )
+            };
+
+            log_recording_stats( &packet );
+            if let Some( progress ) = progress {
+                progress.finish( sample_count, lost_event_count, fp.get_ref().bytes_written.load( Ordering::Relaxed ) );
+            }
+
+            fp.write_packet( packet )?;
+
+            if let Some( self_profile ) = self_profile {
+                let packet = self_profile();
+                log_self_profile( &packet );
+                fp.write_packet( packet )?;
+            }
+
+            Ok(())
+        });
+    }
+
+    /// Unwinds and symbolizes a sample on the fly instead of writing it out.
+    ///
+    /// The frames are ordered from the leaf and are formatted as `symbol [binary]`.
+    pub fn symbolize_sample( &mut self, dwarf_regs: &mut DwarfRegs, event: &Sample, output: &mut Vec< String > ) {
+        self.sample_counter += 1;
+        self.symbolize_sample_impl( dwarf_regs, event, output );
+    }
+
+    /// Writes out the sample just like `generate_sample` does, and also symbolizes it on the fly.
+    pub fn generate_and_symbolize_sample( &mut self, dwarf_regs: &mut DwarfRegs, event: Sample, output: &mut Vec< String > ) {
+        if self.offline {
+            let mut regs = dwarf_regs.clone();
+            self.symbolize_sample_impl( &mut regs, &event, output );
+            self.generate_sample( dwarf_regs, event );
+        } else {
+            // The sample is unwound anyway before it's written out, so just reuse its backtrace.
+            let mut user_backtrace = Vec::new();
+            self.generate_sample_impl( dwarf_regs, event, &mut user_backtrace );
+            self.symbolize_backtrace( &user_backtrace, output );
+        }
+    }
+
+    /// Unwinds and symbolizes a sample on the fly into structured frames, ordered from the leaf.
+    pub fn decode_sample( &mut self, dwarf_regs: &mut DwarfRegs, event: &Sample, output: &mut Vec< reader::Frame > ) {
+        self.sample_counter += 1;
+        self.decode_sample_impl( dwarf_regs, event, output );
+    }
+
+    /// Writes out the sample just like `generate_sample` does, and also decodes it on the fly.
+    pub fn generate_and_decode_sample( &mut self, dwarf_regs: &mut DwarfRegs, event: Sample, output: &mut Vec< reader::Frame > ) {
+        if self.offline {
+            let mut regs = dwarf_regs.clone();
+            self.decode_sample_impl( &mut regs, &event, output );
+            self.generate_sample( dwarf_regs, event );
+        } else {
+            let mut user_backtrace = Vec::new();
+            self.generate_sample_impl( dwarf_regs, event, &mut user_backtrace );
+            self.decode_backtrace( &user_backtrace, output );
+        }
+    }
+
+    fn decode_sample_impl( &mut self, dwarf_regs: &mut DwarfRegs, event: &Sample, output: &mut Vec< reader::Frame > ) {
+        let mut user_backtrace = Vec::new();
+        let stack = (&event.stack).into();
+        let reader = StackReader { stack };
+        let _ = self.address_space.unwind( dwarf_regs, &reader, &mut user_backtrace );
+        dwarf_regs.clear();
+
+        self.decode_backtrace( &user_backtrace, output );
+    }
+
+    fn decode_backtrace( &mut self, user_backtrace: &[UserFrame], output: &mut Vec< reader::Frame > ) {
+        for (nth_frame, user_frame) in user_backtrace.iter().enumerate() {
+            let address = if nth_frame == 0 { user_frame.address } else { user_frame.address - 1 };
+            let mut found = false;
+            self.address_space.decode_symbol_while( address, &mut |frame| {
+                if let Some( name ) = frame.demangled_name.take().or_else( || frame.name.take() ) {
+                    output.push( reader::Frame {
+                        name: name.into_owned(),
+                        binary: frame.library.as_ref().map( |library| library.clone().into_owned() ),
+                        address: Some( frame.absolute_address ),
+                        file: frame.file.take(),
+                        line: frame.line,
+                        is_inline: frame.is_inline,
+                        is_kernel: false
+                    });
+                    found = true;
+                }
+
+                true
+            });
+
+            if !found {
+                output.push( reader::Frame {
+                    name: format!( "0x{:016X}", user_frame.address ),
+                    binary: None,
+                    address: Some( user_frame.address ),
+                    file: None,
+                    line: None,
+                    is_inline: false,
+                    is_kernel: false
+                });
+            }
+        }
+    }
+
+    fn symbolize_sample_impl( &mut self, dwarf_regs: &mut DwarfRegs, event: &Sample, output: &mut Vec< String > ) {
+        let mut user_backtrace = Vec::new();
+        let stack = (&event.stack).into();
+        let reader = StackReader { stack };
+        let _ = self.address_space.unwind( dwarf_regs, &reader, &mut user_backtrace );
+        dwarf_regs.clear();
+
+        self.symbolize_backtrace( &user_backtrace, output );
+    }
+
+    fn symbolize_backtrace( &mut self, user_backtrace: &[UserFrame], output: &mut Vec< String > ) {
+        for (nth_frame, user_frame) in user_backtrace.iter().enumerate() {
+            let address = if nth_frame == 0 { user_frame.address } else { user_frame.address - 1 };
+            let mut found = false;
+            self.address_space.decode_symbol_while( address, &mut |frame| {
+                if let Some( name ) = frame.demangled_name.take().or_else( || frame.name.take() ) {
+                    let library = frame.library.as_ref().map( |library| library.as_ref() ).unwrap_or( "" );
+                    let basename = &library[ library.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+                    output.push( format!( "{} [{}]", name, basename ) );
+                    found = true;
+                }
+
+                true
+            });
+
+            if !found {
+                output.push( format!( "0x{:016X}", user_frame.address ) );
+            }
+        }
+    }
+}
+
+fn log_recording_stats( packet: &Packet ) {
+    let (sample_count, lost_event_count, unwound_sample_count, frame_count, truncated_stack_count, unwind_failure_count, binary_count, bytes_written) = match *packet {
+        Packet::RecordingStats { sample_count, lost_event_count, unwound_sample_count, frame_count, truncated_stack_count, unwind_failure_count, binary_count, bytes_written } =>
+            (sample_count, lost_event_count, unwound_sample_count, frame_count, truncated_stack_count, unwind_failure_count, binary_count, bytes_written),
+        _ => return
+    };
+
+    info!( "Recording summary:" );
+    info!( "  Samples captured: {}", sample_count );
+    info!( "  Events lost: {} ({:.2}%)", lost_event_count, percent( lost_event_count, sample_count + lost_event_count ) );
+    if unwound_sample_count > 0 {
+        info!( "  Average frames per sample: {:.1}", frame_count as f64 / unwound_sample_count as f64 );
+        info!( "  Truncated stacks: {} ({:.2}%)", truncated_stack_count, percent( truncated_stack_count, unwound_sample_count ) );
+        info!( "  Unwind failures: {} ({:.2}%)", unwind_failure_count, percent( unwind_failure_count, unwound_sample_count ) );
+    }
+    info!( "  Binaries: {}", binary_count );
+    info!( "  Bytes written: {} ({:.1} MB)", bytes_written, bytes_written as f64 / (1024.0 * 1024.0) );
 }
 
 impl Drop for ProfilingController {
@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Debug)]
+struct Status {
+    status: &'static str,
+    elapsed: f64,
+    samples: u64,
+    samples_per_second: f64,
+    lost_events: u64,
+    lost_percentage: f64,
+    bytes_written: u64
+}
+
+/// Periodically writes out the status of a recording as JSON, one object per line,
+/// so that it can be monitored by other programs.
+pub(crate) struct ProgressReporter {
+    fp: Box< dyn Write + Send >,
+    interval: Duration,
+    started: Instant,
+    last_report: Instant,
+    last_sample_count: u64
+}
+
+impl ProgressReporter {
+    pub fn new( path: &Path, interval: Duration ) -> Result< Self, Box< dyn Error > > {
+        let fp: Box< dyn Write + Send > = if path == Path::new( "-" ) {
+            Box::new( io::stderr() )
+        } else {
+            // This is not `File::create` since the path is most likely going to be a pipe or a `/dev/fd/N`.
+            Box::new( OpenOptions::new().write( true ).create( true ).open( path ).map_err( |err| format!( "cannot open {:?} for writing: {}", path, err ) )? )
+        };
+
+        let now = Instant::now();
+        Ok( ProgressReporter {
+            fp,
+            interval,
+            started: now,
+            last_report: now,
+            last_sample_count: 0
+        })
+    }
+
+    pub fn is_due( &self ) -> bool {
+        self.last_report.elapsed() >= self.interval
+    }
+
+    /// Writes out a status line; the rate of the samples is calculated since the previous line.
+    pub fn report( &mut self, samples: u64, lost_events: u64, bytes_written: u64 ) {
+        let now = Instant::now();
+        let samples_per_second = rate( samples.saturating_sub( self.last_sample_count ), now.duration_since( self.last_report ) );
+        self.last_report = now;
+        self.last_sample_count = samples;
+        self.write( "recording", samples, samples_per_second, lost_events, bytes_written );
+    }
+
+    /// Writes out the final status line; the rate of the samples is averaged over the whole recording.
+    pub fn finish( mut self, samples: u64, lost_events: u64, bytes_written: u64 ) {
+        let samples_per_second = rate( samples, self.started.elapsed() );
+        self.write( "finished", samples, samples_per_second, lost_events, bytes_written );
+    }
+
+    fn write( &mut self, status: &'static str, samples: u64, samples_per_second: f64, lost_events: u64, bytes_written: u64 ) {
+        let status = Status {
+            status,
+            elapsed: self.started.elapsed().as_secs_f64(),
+            samples,
+            samples_per_second,
+            lost_events,
+            lost_percentage: if samples + lost_events == 0 { 0.0 } else { lost_events as f64 / (samples + lost_events) as f64 * 100.0 },
+            bytes_written
+        };
+
+        let mut line = serde_json::to_vec( &status ).unwrap();
+        line.push( b'\n' );
+
+        // The progress is purely informational, so a reader which went away shouldn't stop the recording.
+        if let Err( error ) = self.fp.write_all( &line ).and_then( |_| self.fp.flush() ) {
+            debug!( "Failed to write the progress: {}", error );
+        }
+    }
+}
+
+fn rate( count: u64, duration: Duration ) -> f64 {
+    let seconds = duration.as_secs_f64();
+    if seconds > 0.0 {
+        count as f64 / seconds
+    } else {
+        0.0
+    }
+}
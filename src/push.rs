@@ -0,0 +1,261 @@
+// Periodic uploads of the aggregated callstacks to continuous profiling servers; see:
+//   https://pyroscope.io/docs/server-api-reference/
+//   https://github.com/parca-dev/parca/blob/main/proto/parca/profilestore/v1alpha1/profilestore.proto
+
+use std::mem;
+use std::thread;
+use std::error::Error;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+
+use crate::args;
+use crate::http::{Url, parse_url, post, url_encode};
+use crate::pprof;
+use crate::utils::{read_cgroup, read_string_lossy};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PushProtocol {
+    Pyroscope,
+    Parca
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PushFormat {
+    Folded,
+    Pprof
+}
+
+// The frames are ordered from the leaf.
+type Stacks = HashMap< Vec< String >, u64 >;
+
+fn to_folded( stacks: &Stacks ) -> String {
+    let mut lines: Vec< _ > = stacks.iter().map( |(frames, count)| {
+        let frames: Vec< &str > = frames.iter().rev().map( |frame| frame.as_str() ).collect();
+        format!( "{} {}\n", frames.join( ";" ), count )
+    }).collect();
+
+    lines.sort();
+    lines.concat()
+}
+
+/// Builds the `service{key=value,...}` application name which Pyroscope expects in the query string.
+fn to_pyroscope_name( service: &str, labels: &[(String, String)] ) -> String {
+    // The values are escaped so that e.g. a `,` in a container name doesn't split the label set.
+    let labels: Vec< _ > = labels.iter().map( |(key, value)| format!( "{}={}", key, url_encode( value ) ) ).collect();
+    format!( "{}{{{}}}", service, labels.join( "," ) )
+}
+
+fn to_pprof( stacks: &Stacks, frequency: u32, timestamp: u64, duration: u64 ) -> Vec< u8 > {
+    let mut profile = pprof::Profile::new();
+    let period = 1_000_000_000 / frequency.max( 1 ) as i64;
+
+    let samples = profile.intern( "samples" );
+    let count = profile.intern( "count" );
+    let cpu = profile.intern( "cpu" );
+    let nanoseconds = profile.intern( "nanoseconds" );
+    profile.sample_types.push( pprof::ValueType { kind: samples, unit: count } );
+    profile.sample_types.push( pprof::ValueType { kind: cpu, unit: nanoseconds } );
+    profile.period_type = Some( pprof::ValueType { kind: cpu, unit: nanoseconds } );
+    profile.period = period;
+    profile.time_nanos = timestamp as i64;
+    profile.duration_nanos = duration as i64;
+
+    // Every function gets a single location, so their IDs are the same.
+    let mut id_for_frame: HashMap< &str, u64 > = HashMap::new();
+    for (frames, &sample_count) in stacks {
+        let location_ids = frames.iter().map( |frame| {
+            if let Some( &id ) = id_for_frame.get( frame.as_str() ) {
+                return id;
+            }
+
+            let id = id_for_frame.len() as u64 + 1;
+            let name = profile.intern( frame );
+            profile.functions.push( pprof::Function { id, name, system_name: name, filename: 0 } );
+            profile.locations.push( pprof::Location { id, mapping_id: 0, address: 0, lines: vec![ pprof::Line { function_id: id, line: 0 } ] } );
+            id_for_frame.insert( frame, id );
+            id
+        }).collect();
+
+        profile.samples.push( pprof::Sample {
+            location_ids,
+            values: vec![ sample_count as i64, sample_count as i64 * period ],
+            labels: Vec::new()
+        });
+    }
+
+    profile.serialize()
+}
+
+struct PushTarget {
+    url: Url,
+    protocol: PushProtocol,
+    format: PushFormat,
+    service: String,
+    labels: Vec< (String, String) >,
+    frequency: u32
+}
+
+impl PushTarget {
+    fn push( &self, stacks: &Stacks, from: SystemTime, until: SystemTime ) -> Result< (), Box< dyn Error > > {
+        let from = from.duration_since( UNIX_EPOCH ).unwrap_or_default();
+        let until = until.duration_since( UNIX_EPOCH ).unwrap_or_default();
+        let body = match self.format {
+            PushFormat::Folded => to_folded( stacks ).into_bytes(),
+            PushFormat::Pprof => to_pprof( stacks, self.frequency, from.as_nanos() as u64, (until - from).as_nanos() as u64 )
+        };
+
+        match self.protocol {
+            PushProtocol::Pyroscope => {
+                let name = to_pyroscope_name( &self.service, &self.labels );
+                let format = match self.format {
+                    PushFormat::Folded => "folded",
+                    PushFormat::Pprof => "pprof"
+                };
+
+                let query = format!(
+                    "/ingest?name={}&from={}&until={}&format={}&sampleRate={}&spyName=nperf&units=samples&aggregationType=sum",
+                    url_encode( &name ),
+                    from.as_secs(),
+                    until.as_secs(),
+                    format,
+                    self.frequency
+                );
+
+                post( &self.url, &query, "application/octet-stream", &body ).map( |_| () )
+            },
+            PushProtocol::Parca => {
+                let mut encoder = pprof::Encoder::new();
+                encoder.message( 2, |series| {
+                    series.message( 1, |label_set| {
+                        let name = ("__name__", "process_cpu");
+                        let service = ("service", self.service.as_str());
+                        let labels = self.labels.iter().map( |(key, value)| (key.as_str(), value.as_str()) );
+                        for (key, value) in Some( name ).into_iter().chain( Some( service ) ).chain( labels ) {
+                            label_set.message( 1, |label| {
+                                label.bytes( 1, key.as_bytes() );
+                                label.bytes( 2, value.as_bytes() );
+                            });
+                        }
+                    });
+
+                    series.message( 2, |sample| {
+                        sample.bytes( 1, &body );
+                    });
+                });
+
+                post( &self.url, "/parca.profilestore.v1alpha1.ProfileStoreService/WriteRaw", "application/proto", &encoder.into_bytes() ).map( |_| () )
+            }
+        }
+    }
+}
+
+/// Aggregates the symbolized callstacks and periodically pushes them out on a background thread.
+pub(crate) struct Pusher {
+    stacks: Arc< Mutex< Stacks > >,
+    is_running: Arc< AtomicBool >,
+    thread: thread::JoinHandle< () >
+}
+
+impl Pusher {
+    pub(crate) fn new( args: &args::PushArgs, url: &str, pid: u32, frequency: u32 ) -> Result< Self, Box< dyn Error > > {
+        let format = match (args.push_protocol, args.push_format) {
+            (PushProtocol::Parca, Some( PushFormat::Folded )) => return Err( "Parca only supports pushing the data in the `pprof` format".into() ),
+            (PushProtocol::Parca, _) => PushFormat::Pprof,
+            (PushProtocol::Pyroscope, format) => format.unwrap_or( PushFormat::Folded )
+        };
+
+        let service = match args.push_service {
+            Some( ref service ) => service.clone(),
+            None => read_string_lossy( format!( "/proc/{}/comm", pid ) ).map( |comm| comm.trim().to_owned() ).unwrap_or_else( |_| "nperf".to_owned() )
+        };
+
+        let mut labels = Vec::new();
+        if let Ok( host ) = read_string_lossy( "/proc/sys/kernel/hostname" ) {
+            labels.push( ("host".to_owned(), host.trim().to_owned()) );
+        }
+
+        // The last component of the cgroup's path identifies the container, e.g. `docker-<id>.scope`.
+        if let Some( cgroup ) = read_cgroup( pid ) {
+            if let Some( container ) = cgroup.rsplit( '/' ).next().filter( |name| !name.is_empty() ) {
+                labels.push( ("container".to_owned(), container.to_owned()) );
+            }
+        }
+
+        for label in &args.push_label {
+            let index = label.find( '=' ).filter( |&index| index > 0 ).ok_or_else( || format!( "invalid label passed in `--push-label`: '{}'; expected '<key>=<value>'", label ) )?;
+            labels.push( (label[ ..index ].to_owned(), label[ index + 1.. ].to_owned()) );
+        }
+
+        let target = PushTarget {
+            url: parse_url( url )?,
+            protocol: args.push_protocol,
+            format,
+            service,
+            labels,
+            frequency
+        };
+
+        let stacks = Arc::new( Mutex::new( Stacks::new() ) );
+        let is_running = Arc::new( AtomicBool::new( true ) );
+        let interval = Duration::from_secs( args.push_interval.max( 1 ) );
+        let thread = {
+            let stacks = stacks.clone();
+            let is_running = is_running.clone();
+            thread::spawn( move || {
+                let mut from = SystemTime::now();
+                loop {
+                    let timestamp = Instant::now();
+                    while timestamp.elapsed() < interval && is_running.load( Ordering::Relaxed ) {
+                        thread::sleep( Duration::from_millis( 50 ) );
+                    }
+
+                    let until = SystemTime::now();
+                    let stacks = mem::replace( &mut *stacks.lock().unwrap(), Stacks::new() );
+                    if !stacks.is_empty() {
+                        match target.push( &stacks, from, until ) {
+                            Ok(()) => debug!( "Pushed {} unique callstacks to {}", stacks.len(), target.url.host ),
+                            Err( error ) => warn!( "Failed to push the profiling data: {}", error )
+                        }
+                    }
+
+                    from = until;
+                    if !is_running.load( Ordering::Relaxed ) {
+                        break;
+                    }
+                }
+            })
+        };
+
+        Ok( Pusher { stacks, is_running, thread } )
+    }
+
+    pub(crate) fn add( &self, frames: &[String] ) {
+        *self.stacks.lock().unwrap().entry( frames.to_vec() ).or_insert( 0 ) += 1;
+    }
+
+    /// Pushes out whatever was gathered since the last push and stops the background thread.
+    pub(crate) fn finish( self ) {
+        self.is_running.store( false, Ordering::Relaxed );
+        self.thread.join().unwrap();
+    }
+}
+
+#[test]
+fn test_to_folded() {
+    let mut stacks = Stacks::new();
+    stacks.insert( vec![ "c".to_owned(), "b".to_owned(), "a".to_owned() ], 2 );
+    stacks.insert( vec![ "a".to_owned() ], 1 );
+
+    assert_eq!( to_folded( &stacks ), "a 1\na;b;c 2\n" );
+    assert_eq!( url_encode( "app{host=a b,x=1}" ), "app%7Bhost%3Da%20b%2Cx%3D1%7D" );
+}
+
+#[test]
+fn test_to_pyroscope_name() {
+    assert_eq!( to_pyroscope_name( "app", &[] ), "app{}" );
+
+    let labels = vec![ ("host".to_owned(), "a b".to_owned()), ("container".to_owned(), "x,y=}".to_owned()) ];
+    assert_eq!( to_pyroscope_name( "app", &labels ), "app{host=a%20b,container=x%2Cy%3D%7D}" );
+}
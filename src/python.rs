@@ -0,0 +1,472 @@
+//! Sampling of the Python stacks of a CPython interpreter, similar to what `py-spy` does.
+//!
+//! The interpreter's own data structures are read straight from the memory of the profiled
+//! process while recording; the Python frames are then interleaved with the native ones when
+//! decoding, where every invocation of the interpreter loop is replaced with the Python
+//! frames which it was evaluating.
+//!
+//! The layout of those data structures changes with every release of CPython,
+//! so only the versions listed in `LAYOUTS` are supported, and only on 64-bit little-endian targets.
+
+#[cfg(feature = "native")]
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::fs::File;
+#[cfg(feature = "native")]
+use std::ops::Range;
+#[cfg(feature = "native")]
+use std::os::unix::fs::FileExt;
+
+#[cfg(feature = "native")]
+use nwind::{BinaryData, Bitness, Symbols};
+#[cfg(feature = "native")]
+use speedy::Endianness;
+#[cfg(feature = "native")]
+use proc_maps::Region;
+
+#[cfg(feature = "native")]
+use crate::archive::{Packet, PythonFrame};
+
+/// The function which runs the interpreter loop; every invocation of it evaluates
+/// a chain of Python frames, the outermost of which is marked with `is_entry`.
+pub(crate) const EVAL_FUNCTION: &str = "_PyEval_EvalFrameDefault";
+
+#[cfg(feature = "native")]
+const MAX_THREAD_COUNT: usize = 4096;
+#[cfg(feature = "native")]
+const MAX_FRAME_COUNT: usize = 1024;
+#[cfg(feature = "native")]
+const MAX_STRING_LENGTH: u64 = 4096;
+#[cfg(feature = "native")]
+const MAX_LINE_TABLE_LENGTH: u64 = 1024 * 1024;
+
+/// The offsets of the fields of the interpreter's internal structures which we need,
+/// as laid out on a 64-bit little-endian target.
+#[cfg(feature = "native")]
+struct Layout {
+    version: (u32, u32),
+    runtime_interpreters_head: u64,
+    interpreter_next: u64,
+    interpreter_threads_head: u64,
+    thread_next: u64,
+    thread_native_thread_id: u64,
+    thread_cframe: u64,
+    cframe_current_frame: u64,
+    frame_code: u64,
+    frame_previous: u64,
+    frame_prev_instr: u64,
+    frame_is_entry: u64,
+    code_firstlineno: u64,
+    code_filename: u64,
+    code_qualname: u64,
+    code_linetable: u64,
+    code_code: u64,
+    ascii_object_size: u64,
+    compact_unicode_object_size: u64,
+    bytes_size: u64,
+    bytes_data: u64
+}
+
+// Source: `Include/internal/pycore_runtime.h`, `Include/internal/pycore_interp.h`,
+//         `Include/cpython/pystate.h`, `Include/internal/pycore_frame.h`
+//         and `Include/cpython/code.h` from the CPython sources.
+#[cfg(feature = "native")]
+static LAYOUTS: &[Layout] = &[
+    Layout {
+        version: (3, 11),
+        runtime_interpreters_head: 40,
+        interpreter_next: 0,
+        interpreter_threads_head: 16,
+        thread_next: 8,
+        thread_native_thread_id: 160,
+        thread_cframe: 56,
+        cframe_current_frame: 8,
+        frame_code: 32,
+        frame_previous: 48,
+        frame_prev_instr: 56,
+        frame_is_entry: 68,
+        code_firstlineno: 72,
+        code_filename: 112,
+        code_qualname: 128,
+        code_linetable: 136,
+        code_code: 184,
+        ascii_object_size: 48,
+        compact_unicode_object_size: 72,
+        bytes_size: 16,
+        bytes_data: 32
+    }
+];
+
+#[cfg(feature = "native")]
+fn is_python_binary( name: &str ) -> bool {
+    let basename = name.rsplit( '/' ).next().unwrap_or( name );
+    basename.starts_with( "python3" ) || basename.starts_with( "libpython3" )
+}
+
+/// Translates a virtual address from a given binary into an address in the process where it's mapped.
+#[cfg(feature = "native")]
+fn to_runtime_address( data: &BinaryData, regions: &[&Region], address: u64 ) -> Option< u64 > {
+    let header = data.load_headers().iter().find( |header| address >= header.address && address < header.address + header.file_size )?;
+    let file_offset = address - header.address + header.file_offset;
+    let region = regions.iter().find( |region| file_offset >= region.file_offset && file_offset < region.file_offset + (region.end - region.start) )?;
+    Some( region.start + (file_offset - region.file_offset) )
+}
+
+/// Decodes the line number of a given instruction from the location table of a code object;
+/// the `offset` is in bytes from the start of the code.
+///
+/// Source: `Objects/locations.md` and `Objects/codeobject.c` from the CPython sources.
+#[cfg(feature = "native")]
+fn decode_line( table: &[u8], first_line: i32, offset: u64 ) -> Option< u32 > {
+    fn read_varint( table: &[u8], position: &mut usize ) -> Option< u32 > {
+        let mut byte = *table.get( *position )?;
+        *position += 1;
+        let mut value = (byte & 63) as u32;
+        let mut shift = 0;
+        while byte & 64 != 0 {
+            byte = *table.get( *position )?;
+            *position += 1;
+            shift += 6;
+            value |= ((byte & 63) as u32).checked_shl( shift )?;
+        }
+
+        Some( value )
+    }
+
+    fn read_signed_varint( table: &[u8], position: &mut usize ) -> Option< i32 > {
+        let value = read_varint( table, position )?;
+        if value & 1 != 0 {
+            Some( -((value >> 1) as i32) )
+        } else {
+            Some( (value >> 1) as i32 )
+        }
+    }
+
+    let mut line = first_line;
+    let mut address = 0;
+    let mut position = 0;
+    while position < table.len() {
+        let header = table[ position ];
+        let code = (header >> 3) & 15;
+        let length = ((header & 7) as u64 + 1) * 2;
+
+        let mut next = position + 1;
+        let line_delta = match code {
+            13 | 14 => read_signed_varint( table, &mut next )?,
+            10..=12 => code as i32 - 10,
+            _ => 0
+        };
+
+        line += line_delta;
+        address += length;
+        if offset < address {
+            if code == 15 || line < 0 {
+                return None;
+            }
+
+            return Some( line as u32 );
+        }
+
+        position += 1;
+        while position < table.len() && table[ position ] & 128 == 0 {
+            position += 1;
+        }
+    }
+
+    None
+}
+
+/// Decodes the contents of a compact `str` object, given its header and the bytes which follow it.
+#[cfg(feature = "native")]
+fn decode_string( state: u32, bytes: &[u8] ) -> Option< String > {
+    let kind = (state >> 2) & 7;
+    match kind {
+        1 => Some( bytes.iter().map( |&byte| byte as char ).collect() ),
+        2 => Some( bytes.chunks_exact( 2 ).filter_map( |chunk| std::char::from_u32( u16::from_ne_bytes( [chunk[ 0 ], chunk[ 1 ]] ) as u32 ) ).collect() ),
+        4 => Some( bytes.chunks_exact( 4 ).filter_map( |chunk| std::char::from_u32( u32::from_ne_bytes( [chunk[ 0 ], chunk[ 1 ], chunk[ 2 ], chunk[ 3 ]] ) ) ).collect() ),
+        _ => None
+    }
+}
+
+#[cfg(feature = "native")]
+struct Function {
+    id: u64,
+    first_line: i32,
+    line_table: Vec< u8 >
+}
+
+#[cfg(feature = "native")]
+pub(crate) struct PythonSampler {
+    pid: u32,
+    memory: File,
+    layout: &'static Layout,
+    runtime_address: u64,
+    eval_range: Range< u64 >,
+    functions: HashMap< (u64, u64, u64), Function >,
+    next_id: u64
+}
+
+#[cfg(feature = "native")]
+impl PythonSampler {
+    /// Looks for a CPython interpreter among the binaries mapped into a given process; it can be either
+    /// in the executable itself, or in `libpython` when the executable is linked dynamically to it.
+    ///
+    /// Returns `None` if there's no interpreter; returns an error if there is one, but it can't be sampled.
+    pub fn locate( pid: u32, regions: &[&Region], mut load_binary: impl FnMut( &Region ) -> Option< BinaryData > ) -> Result< Option< Self >, String > {
+        let mut candidates: Vec< &Region > = regions.iter().cloned().filter( |region| is_python_binary( &region.name ) ).collect();
+        candidates.dedup_by( |a, b| a.name == b.name );
+
+        let (region, data, version_address) = match candidates.into_iter().find_map( |region| {
+            let data = load_binary( region )?;
+            let address = Symbols::find_object( &data, "Py_Version" )?.start;
+            Some( (region, data, address) )
+        }) {
+            Some( result ) => result,
+            None => return Ok( None )
+        };
+
+        if data.bitness() != Bitness::B64 || data.endianness() != Endianness::LittleEndian {
+            return Err( format!( "the Python interpreter in '{}' is not a 64-bit little-endian binary, which is the only kind supported", region.name ) );
+        }
+
+        let regions: Vec< _ > = regions.iter().cloned().filter( |other| other.name == region.name ).collect();
+        let memory = File::open( format!( "/proc/{}/mem", pid ) ).map_err( |err| format!( "cannot open /proc/{}/mem: {}", pid, err ) )?;
+
+        let runtime_address_of = |address: u64| {
+            to_runtime_address( &data, &regions, address ).ok_or_else( || format!( "address 0x{:016X} from '{}' is not mapped", address, region.name ) )
+        };
+
+        let symbol_address = |name: &str| {
+            let range = Symbols::find_object( &data, name ).ok_or_else( || format!( "cannot find the '{}' symbol in '{}'", name, region.name ) )?;
+            runtime_address_of( range.start )
+        };
+
+        let mut version = [0; 4];
+        memory.read_exact_at( &mut version, runtime_address_of( version_address )? ).map_err( |err| format!( "cannot read the version of the interpreter: {}", err ) )?;
+        let version = u32::from_ne_bytes( version );
+        let version = (version >> 24, (version >> 16) & 0xFF);
+        let layout = LAYOUTS.iter().find( |layout| layout.version == version )
+            .ok_or_else( || format!( "Python {}.{} is not supported", version.0, version.1 ) )?;
+
+        let runtime_address = symbol_address( "_PyRuntime" )?;
+        let mut eval_range = None;
+        Symbols::each_from_binary_data( &data, |range, name| {
+            if eval_range.is_none() && name == EVAL_FUNCTION {
+                eval_range = Some( range );
+            }
+        });
+
+        let eval_range = eval_range.ok_or_else( || format!( "cannot find the '{}' symbol in '{}'", EVAL_FUNCTION, region.name ) )?;
+        let eval_range = runtime_address_of( eval_range.start )?..runtime_address_of( eval_range.end - 1 )? + 1;
+
+        info!( "Found a Python {}.{} interpreter in '{}'", version.0, version.1, region.name );
+        Ok( Some( PythonSampler {
+            pid,
+            memory,
+            layout,
+            runtime_address,
+            eval_range,
+            functions: HashMap::new(),
+            next_id: 1
+        }))
+    }
+
+    /// Whether a given native address is within the interpreter loop.
+    pub fn is_interpreter_address( &self, address: u64 ) -> bool {
+        address >= self.eval_range.start && address < self.eval_range.end
+    }
+
+    fn read_u64( &self, address: u64 ) -> Option< u64 > {
+        let mut buffer = [0; 8];
+        self.memory.read_exact_at( &mut buffer, address ).ok()?;
+        Some( u64::from_ne_bytes( buffer ) )
+    }
+
+    fn read_bytes( &self, address: u64, length: u64 ) -> Option< Vec< u8 > > {
+        let mut buffer = vec![ 0; length as usize ];
+        self.memory.read_exact_at( &mut buffer, address ).ok()?;
+        Some( buffer )
+    }
+
+    fn read_string( &self, address: u64 ) -> Option< String > {
+        let header = self.read_bytes( address, self.layout.ascii_object_size )?;
+        let length = u64::from_ne_bytes( [header[ 16 ], header[ 17 ], header[ 18 ], header[ 19 ], header[ 20 ], header[ 21 ], header[ 22 ], header[ 23 ]] );
+        let state = u32::from_ne_bytes( [header[ 32 ], header[ 33 ], header[ 34 ], header[ 35 ]] );
+        let is_compact = state & (1 << 5) != 0;
+        let is_ascii = state & (1 << 6) != 0;
+        if !is_compact || length > MAX_STRING_LENGTH {
+            return None;
+        }
+
+        if is_ascii {
+            let bytes = self.read_bytes( address + self.layout.ascii_object_size, length )?;
+            return String::from_utf8( bytes ).ok();
+        }
+
+        let kind = ((state >> 2) & 7) as u64;
+        let bytes = self.read_bytes( address + self.layout.compact_unicode_object_size, length * kind )?;
+        decode_string( state, &bytes )
+    }
+
+    fn read_line_table( &self, address: u64 ) -> Option< Vec< u8 > > {
+        let length = self.read_u64( address + self.layout.bytes_size )?;
+        if length > MAX_LINE_TABLE_LENGTH {
+            return None;
+        }
+
+        self.read_bytes( address + self.layout.bytes_data, length )
+    }
+
+    /// Returns the function of a given code object, reading it in if it's seen for the first time.
+    fn function( &mut self, code: u64, on_packet: &mut dyn FnMut( Packet< 'static > ) ) -> Option< (&Function, u64) > {
+        let layout = self.layout;
+        let header = self.read_bytes( code, layout.code_code )?;
+        let field = |offset: u64| {
+            let offset = offset as usize;
+            u64::from_ne_bytes( [header[ offset ], header[ offset + 1 ], header[ offset + 2 ], header[ offset + 3 ], header[ offset + 4 ], header[ offset + 5 ], header[ offset + 6 ], header[ offset + 7 ]] )
+        };
+
+        let filename = field( layout.code_filename );
+        let qualname = field( layout.code_qualname );
+
+        // The code objects can be freed and their addresses reused, so the pointers to the names are also compared.
+        let key = (code, filename, qualname);
+        if !self.functions.contains_key( &key ) {
+            let first_line = field( layout.code_firstlineno ) as u32 as i32;
+            let name = self.read_string( qualname )?;
+            let file = self.read_string( filename )?;
+            let line_table = self.read_line_table( field( layout.code_linetable ) ).unwrap_or_default();
+
+            let id = self.next_id;
+            self.next_id += 1;
+
+            on_packet( Packet::PythonFunction {
+                pid: self.pid,
+                id,
+                name: name.into(),
+                file: file.into(),
+                first_line: first_line.max( 0 ) as u32
+            });
+
+            self.functions.insert( key, Function { id, first_line, line_table } );
+        }
+
+        Some( (&self.functions[ &key ], code + layout.code_code) )
+    }
+
+    fn find_thread( &self, tid: u32 ) -> Option< u64 > {
+        let layout = self.layout;
+        let mut interpreter = self.read_u64( self.runtime_address + layout.runtime_interpreters_head )?;
+        let mut count = 0;
+        while interpreter != 0 && count < MAX_THREAD_COUNT {
+            let mut thread = self.read_u64( interpreter + layout.interpreter_threads_head )?;
+            while thread != 0 && count < MAX_THREAD_COUNT {
+                if self.read_u64( thread + layout.thread_native_thread_id )? == tid as u64 {
+                    return Some( thread );
+                }
+
+                thread = self.read_u64( thread + layout.thread_next )?;
+                count += 1;
+            }
+
+            interpreter = self.read_u64( interpreter + layout.interpreter_next )?;
+            count += 1;
+        }
+
+        None
+    }
+
+    /// Reads the Python stack of a given thread, innermost first; the `PythonFunction` packets
+    /// for the functions which weren't seen before are passed to `on_packet`.
+    ///
+    /// Since the process isn't stopped while this happens the stack can be inconsistent,
+    /// in which case nothing is returned.
+    pub fn sample( &mut self, tid: u32, on_packet: &mut dyn FnMut( Packet< 'static > ) ) -> Vec< PythonFrame > {
+        self.sample_impl( tid, on_packet ).unwrap_or_default()
+    }
+
+    fn sample_impl( &mut self, tid: u32, on_packet: &mut dyn FnMut( Packet< 'static > ) ) -> Option< Vec< PythonFrame > > {
+        let layout = self.layout;
+        let thread = self.find_thread( tid )?;
+        let cframe = self.read_u64( thread + layout.thread_cframe )?;
+        let mut frame = self.read_u64( cframe + layout.cframe_current_frame )?;
+
+        let mut frames = Vec::new();
+        while frame != 0 {
+            if frames.len() >= MAX_FRAME_COUNT {
+                return None;
+            }
+
+            let bytes = self.read_bytes( frame, layout.frame_is_entry + 1 )?;
+            let field = |offset: u64| {
+                let offset = offset as usize;
+                u64::from_ne_bytes( [bytes[ offset ], bytes[ offset + 1 ], bytes[ offset + 2 ], bytes[ offset + 3 ], bytes[ offset + 4 ], bytes[ offset + 5 ], bytes[ offset + 6 ], bytes[ offset + 7 ]] )
+            };
+
+            let code = field( layout.frame_code );
+            let prev_instr = field( layout.frame_prev_instr );
+            let is_entry = bytes[ layout.frame_is_entry as usize ] != 0;
+            let previous = field( layout.frame_previous );
+
+            let (function, code_start) = self.function( code, on_packet )?;
+            let line = if prev_instr >= code_start {
+                decode_line( &function.line_table, function.first_line, prev_instr - code_start )
+            } else {
+                None
+            };
+
+            frames.push( PythonFrame {
+                function: function.id,
+                line: line.unwrap_or( 0 ),
+                is_entry
+            });
+
+            frame = previous;
+        }
+
+        Some( frames )
+    }
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_decode_line() {
+    // def f(x):
+    //     a = x + 1
+    //
+    //     if a > 2:
+    //         return [i for i in range(a)]
+    //     return a
+    let table = [
+        0x80, 0x00, 0xd8, 0x08, 0x09, 0x88, 0x41, 0x89, 0x05, 0x80, 0x41, 0xe0, 0x07, 0x08, 0x88, 0x31,
+        0x82, 0x75, 0x80, 0x75, 0xd8, 0x0f, 0x24, 0xd0, 0x0f, 0x24, 0x9d, 0x35, 0xa0, 0x11, 0x99, 0x38,
+        0x9c, 0x38, 0xd0, 0x0f, 0x24, 0xd1, 0x0f, 0x24, 0xd4, 0x0f, 0x24, 0xd0, 0x08, 0x24, 0xd8, 0x0b,
+        0x0c, 0x80, 0x48
+    ];
+
+    assert_eq!( decode_line( &table, 1, 0 ), Some( 1 ) );
+    assert_eq!( decode_line( &table, 1, 2 ), Some( 2 ) );
+    assert_eq!( decode_line( &table, 1, 10 ), Some( 2 ) );
+    assert_eq!( decode_line( &table, 1, 12 ), Some( 4 ) );
+    assert_eq!( decode_line( &table, 1, 24 ), Some( 5 ) );
+    assert_eq!( decode_line( &table, 1, 72 ), Some( 5 ) );
+    assert_eq!( decode_line( &table, 1, 74 ), Some( 6 ) );
+    assert_eq!( decode_line( &table, 1, 77 ), Some( 6 ) );
+    assert_eq!( decode_line( &table, 1, 78 ), None );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_decode_string() {
+    assert_eq!( decode_string( 1 << 2, b"caf\xe9" ).unwrap(), "caf\u{e9}" );
+    assert_eq!( decode_string( 2 << 2, &[0x6b, 0x30] ).unwrap(), "\u{306b}" );
+    assert_eq!( decode_string( 4 << 2, &[0x00, 0xf6, 0x01, 0x00] ).unwrap(), "\u{1f600}" );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_is_python_binary() {
+    assert!( is_python_binary( "/usr/bin/python3.11" ) );
+    assert!( is_python_binary( "/usr/lib/x86_64-linux-gnu/libpython3.11.so.1.0" ) );
+    assert!( !is_python_binary( "/usr/lib/x86_64-linux-gnu/libc.so.6" ) );
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::utils::percent;
+
+/// The exit code used when the gathered data doesn't meet the quality thresholds,
+/// so that it can be told apart from other errors.
+pub const QUALITY_EXIT_CODE: i32 = 2;
+
+/// A limit on how many of something can go wrong, either as an absolute number or as a percentage.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Threshold {
+    Count( u64 ),
+    Percentage( f64 )
+}
+
+impl Threshold {
+    fn is_exceeded( self, count: u64, total: u64 ) -> bool {
+        match self {
+            Threshold::Count( limit ) => count > limit,
+            Threshold::Percentage( limit ) => total > 0 && percent( count, total ) > limit
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Threshold::Count( limit ) => write!( fmt, ">{}", limit ),
+            Threshold::Percentage( limit ) => write!( fmt, ">{}%", limit )
+        }
+    }
+}
+
+/// Parses a threshold like `>1%` or `>100`; the `>` is optional.
+pub fn parse_threshold( value: &str ) -> Result< Threshold, String > {
+    let error = || format!( "invalid threshold: '{}'; expected either a number of events (e.g. '>100') or a percentage (e.g. '>1%')", value );
+    let threshold = value.trim();
+    let threshold = threshold.strip_prefix( '>' ).unwrap_or( threshold ).trim();
+    if let Some( percentage ) = threshold.strip_suffix( '%' ) {
+        let percentage: f64 = percentage.trim().parse().map_err( |_| error() )?;
+        if !(0.0..=100.0).contains( &percentage ) {
+            return Err( error() );
+        }
+
+        Ok( Threshold::Percentage( percentage ) )
+    } else {
+        threshold.parse().map( Threshold::Count ).map_err( |_| error() )
+    }
+}
+
+/// Returned when the gathered data is of too poor quality.
+#[derive(Debug)]
+pub struct QualityError( String );
+
+impl fmt::Display for QualityError {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        fmt.write_str( &self.0 )
+    }
+}
+
+impl Error for QualityError {}
+
+/// The numbers which determine the quality of a profile.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct QualityStats {
+    pub sample_count: u64,
+    pub lost_event_count: u64,
+    pub unwound_sample_count: u64,
+    /// The number of samples whose unwinding stopped prematurely, including the truncated stacks.
+    pub unwind_failure_count: u64
+}
+
+impl QualityStats {
+    /// Checks the stats against the thresholds, and returns a `QualityError` if any of them were exceeded.
+    pub fn check( &self, fail_if_lost: Option< Threshold >, fail_if_unwind_failures: Option< Threshold > ) -> Result< (), Box< dyn Error > > {
+        let mut failures = Vec::new();
+        if let Some( threshold ) = fail_if_lost {
+            let total = self.sample_count + self.lost_event_count;
+            if threshold.is_exceeded( self.lost_event_count, total ) {
+                failures.push( format!(
+                    "{} out of {} events ({:.2}%) were lost, which exceeds the threshold of {}",
+                    self.lost_event_count,
+                    total,
+                    percent( self.lost_event_count, total ),
+                    threshold
+                ));
+            }
+        }
+
+        if let Some( threshold ) = fail_if_unwind_failures {
+            if self.unwound_sample_count == 0 && self.sample_count > 0 {
+                warn!( "The samples weren't unwound, so the unwind failure threshold can't be checked" );
+            } else if threshold.is_exceeded( self.unwind_failure_count, self.unwound_sample_count ) {
+                failures.push( format!(
+                    "{} out of {} samples ({:.2}%) weren't fully unwound, which exceeds the threshold of {}",
+                    self.unwind_failure_count,
+                    self.unwound_sample_count,
+                    percent( self.unwind_failure_count, self.unwound_sample_count ),
+                    threshold
+                ));
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        Err( Box::new( QualityError( format!( "the profile is of too poor quality: {}", failures.join( "; " ) ) ) ) )
+    }
+}
+
+#[test]
+fn test_thresholds() {
+    assert_eq!( parse_threshold( ">1%" ), Ok( Threshold::Percentage( 1.0 ) ) );
+    assert_eq!( parse_threshold( "2.5%" ), Ok( Threshold::Percentage( 2.5 ) ) );
+    assert_eq!( parse_threshold( "> 100" ), Ok( Threshold::Count( 100 ) ) );
+    assert!( parse_threshold( ">150%" ).is_err() );
+    assert!( parse_threshold( ">foo" ).is_err() );
+
+    let stats = QualityStats {
+        sample_count: 980,
+        lost_event_count: 20,
+        unwound_sample_count: 980,
+        unwind_failure_count: 49
+    };
+
+    assert!( stats.check( Some( Threshold::Percentage( 2.0 ) ), Some( Threshold::Percentage( 5.0 ) ) ).is_ok() );
+    assert!( stats.check( Some( Threshold::Percentage( 1.0 ) ), None ).unwrap_err().is::< QualityError >() );
+    assert!( stats.check( None, Some( Threshold::Count( 10 ) ) ).is_err() );
+    assert!( stats.check( Some( Threshold::Count( 20 ) ), None ).is_ok() );
+    assert!( QualityStats::default().check( Some( Threshold::Percentage( 0.0 ) ), Some( Threshold::Percentage( 0.0 ) ) ).is_ok() );
+}
@@ -0,0 +1,157 @@
+//! A programmatic interface for reading the recordings, for when shelling out
+//! to the command line tool and parsing its output isn't an option.
+
+use std::error::Error;
+
+use crate::args::{self, Granularity};
+use crate::data_reader::{DecodeOpts, EventKind, FrameKind, read_data, repack_cli_args};
+use crate::interner::StringInterner;
+
+/// A single symbolized frame of a sample.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Frame {
+    /// The name of the function, or its address formatted as hex if it couldn't be symbolized.
+    pub name: String,
+    /// The path of the binary, or the name of the kernel module, which the frame belongs to.
+    pub binary: Option< String >,
+    pub address: Option< u64 >,
+    pub file: Option< String >,
+    pub line: Option< u64 >,
+    pub is_inline: bool,
+    pub is_kernel: bool
+}
+
+/// A single symbolized sample.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Sample {
+    /// The time at which the sample was taken, in nanoseconds.
+    pub timestamp: u64,
+    pub pid: u32,
+    pub tid: u32,
+    pub cpu: u32,
+    pub executable: String,
+    pub thread_name: Option< String >,
+    /// The frames of the backtrace, ordered from the innermost one.
+    pub frames: Vec< Frame >
+}
+
+/// Reads the samples from a recording, symbolizes them, and calls the callback for every one of them.
+///
+/// Every option which the collation subcommands accept (the filters, the debug symbols,
+/// the path mappings, etc.) is respected.
+pub fn for_each_sample< F >( args: &args::SharedCollationArgs, mut on_sample: F ) -> Result< (), Box< dyn Error > >
+    where F: FnMut( Sample )
+{
+    let (filter, read_data_args) = repack_cli_args( args )?;
+    let opts = DecodeOpts {
+        filter,
+        emit_kernel_frames: true,
+        emit_thread_frames: false,
+        emit_process_frames: false,
+        emit_unwind_failure_frames: false,
+        emit_cgroup_frames: false,
+        granularity: Granularity::Address
+    };
+
+    let mut interner = StringInterner::new();
+    let mut frames = Vec::new();
+    read_data( read_data_args, |event| {
+        let sample = match event.kind {
+            EventKind::Sample( sample ) => sample,
+            _ => return
+        };
+
+        frames.clear();
+        if !sample.try_decode( event.state, &opts, &mut interner, Some( &mut frames ) ) {
+            return;
+        }
+
+        let state = event.state;
+        let hex = |address: u64| format!( "0x{:016X}", address );
+        let frames = frames.iter().map( |frame| {
+            match *frame {
+                FrameKind::KernelSymbol( index ) => {
+                    let symbol = state.get_kernel_symbol( index );
+                    Frame {
+                        name: symbol.name.clone(),
+                        binary: symbol.module.clone(),
+                        address: Some( symbol.address ),
+                        file: None,
+                        line: None,
+                        is_inline: false,
+                        is_kernel: true
+                    }
+                },
+                FrameKind::Kernel( address ) => Frame {
+                    name: hex( address ),
+                    binary: None,
+                    address: Some( address ),
+                    file: None,
+                    line: None,
+                    is_inline: false,
+                    is_kernel: true
+                },
+                FrameKind::UserByAddress { ref binary_id, is_inline, symbol, address, file, line } => Frame {
+                    name: interner.resolve( symbol ).unwrap().to_owned(),
+                    binary: Some( state.get_binary( binary_id ).path().to_owned() ),
+                    address: Some( address ),
+                    file: file.map( |file| interner.resolve( file ).unwrap().to_owned() ),
+                    line,
+                    is_inline,
+                    is_kernel: false
+                },
+                FrameKind::UserBinary( ref binary_id, address ) => Frame {
+                    name: hex( address ),
+                    binary: Some( state.get_binary( binary_id ).path().to_owned() ),
+                    address: Some( address ),
+                    file: None,
+                    line: None,
+                    is_inline: false,
+                    is_kernel: false
+                },
+                FrameKind::User( address ) => Frame {
+                    name: hex( address ),
+                    binary: None,
+                    address: Some( address ),
+                    file: None,
+                    line: None,
+                    is_inline: false,
+                    is_kernel: false
+                },
+                FrameKind::Python { symbol, file, line } => Frame {
+                    name: interner.resolve( symbol ).unwrap().to_owned(),
+                    binary: None,
+                    address: None,
+                    file: Some( interner.resolve( file ).unwrap().to_owned() ),
+                    line,
+                    is_inline: false,
+                    is_kernel: false
+                },
+                FrameKind::Jit( symbol ) => Frame {
+                    name: interner.resolve( symbol ).unwrap().to_owned(),
+                    binary: None,
+                    address: None,
+                    file: None,
+                    line: None,
+                    is_inline: false,
+                    is_kernel: false
+                },
+                _ => unreachable!()
+            }
+        }).collect();
+
+        on_sample( Sample {
+            timestamp: sample.timestamp,
+            pid: sample.process.pid(),
+            tid: sample.tid,
+            cpu: sample.cpu,
+            executable: sample.process.executable().to_owned(),
+            thread_name: state.get_thread_name( sample.tid ).map( |name| name.to_owned() ),
+            frames
+        });
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! A programmatic interface for recording, for services and test harnesses
+//! which want to profile a process without going through the command line tool.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nwind::RangeMap;
+
+use crate::args;
+use crate::archive::Packet;
+use crate::cmd_record::{open_perf_group, run};
+use crate::kallsyms::{self, KernelSymbol};
+use crate::perf_group::{PerfGroup, Sampling};
+use crate::profiler::ProfilingController;
+use crate::reader;
+
+pub use perf_event_open::EventSource;
+
+/// What and how should be recorded; the defaults are the same as the ones of `nperf record`.
+#[derive(Clone, Debug)]
+pub struct RecorderOptions {
+    pub pid: u32,
+    pub frequency: u32,
+    pub stack_size: u32,
+    pub event_source: EventSource,
+    /// If set the samples are also written out into a data file, just as with `nperf record`.
+    pub output: Option< PathBuf >,
+    /// For how many seconds to record.
+    pub time_limit: Option< u64 >,
+    /// How many samples to gather.
+    pub sample_count: Option< u64 >
+}
+
+impl RecorderOptions {
+    pub fn new( pid: u32 ) -> Self {
+        RecorderOptions {
+            pid,
+            frequency: 900,
+            stack_size: 24576,
+            event_source: EventSource::HwCpuCycles,
+            output: None,
+            time_limit: None,
+            sample_count: None
+        }
+    }
+}
+
+/// Stops a running recording; can be sent to and used from another thread.
+#[derive(Clone)]
+pub struct StopHandle( Arc< AtomicBool > );
+
+impl StopHandle {
+    pub fn stop( &self ) {
+        self.0.store( true, Ordering::Relaxed );
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RecordingSummary {
+    pub sample_count: u64,
+    pub lost_event_count: u64
+}
+
+/// Records a process with perf events, unwinding and symbolizing every sample on the fly.
+///
+/// The recording stops when the time or the sample limit is reached, when the process exits,
+/// or when it's stopped through a `StopHandle`; the SIGINT is left alone.
+pub struct Recorder {
+    controller: ProfilingController,
+    perf: PerfGroup,
+    kallsyms: RangeMap< KernelSymbol >,
+    executable: String,
+    write_output: bool,
+    stop_flag: Arc< AtomicBool >
+}
+
+impl Recorder {
+    /// Attaches to the process and starts gathering the samples; they're only processed once `run` is called.
+    pub fn start( options: &RecorderOptions ) -> Result< Self, Box< dyn Error > > {
+        let profiler_args = args::GenericProfilerArgs {
+            output: options.output.as_ref().map( |output| output.clone().into_os_string() ),
+            sample_count: options.sample_count,
+            time_limit: options.time_limit,
+            lock_memory: false,
+            offline: false,
+            keep_raw: false,
+            python: false,
+            perf_map_agent: None,
+            perf_map_agent_interval: 10.0,
+            r2r_map_dir: Vec::new(),
+            panic_on_partial_backtrace: false,
+            progress: None,
+            progress_interval: 1.0,
+            memory_stats: false,
+            memory_stats_interval: 1.0,
+            self_profile: false,
+            process_filter: args::ProcessFilter::by_pid( options.pid )
+        };
+
+        let write_output = options.output.is_some();
+        let mut controller = ProfilingController::new_embedded( &profiler_args, write_output )?;
+        if write_output {
+            controller.write_packet( Packet::ProfilingFrequency {
+                frequency: options.frequency
+            });
+        }
+
+        let stop_flag = Arc::new( AtomicBool::new( false ) );
+        controller.set_stop_flag( stop_flag.clone() );
+
+        let perf = open_perf_group( &mut controller, Sampling::Frequency( options.frequency ), options.stack_size, None, options.event_source )?;
+        let kallsyms = kallsyms::read().map( |kallsyms| kallsyms::parse( &kallsyms ) ).unwrap_or_else( RangeMap::new );
+        let executable = fs::read_link( format!( "/proc/{}/exe", options.pid ) ).map( |path| path.to_string_lossy().into_owned() ).unwrap_or_default();
+
+        Ok( Recorder {
+            controller,
+            perf,
+            kallsyms,
+            executable,
+            write_output,
+            stop_flag
+        })
+    }
+
+    pub fn pid( &self ) -> u32 {
+        self.controller.pid()
+    }
+
+    pub fn stop_handle( &self ) -> StopHandle {
+        StopHandle( self.stop_flag.clone() )
+    }
+
+    /// Processes the samples until the recording is stopped, calling `on_sample` for every one of them.
+    pub fn run< F >( mut self, mut on_sample: F ) -> RecordingSummary where F: FnMut( reader::Sample ) {
+        let kallsyms = &self.kallsyms;
+        let executable = &self.executable;
+        let write_output = self.write_output;
+        let mut thread_names: HashMap< u32, Option< String > > = HashMap::new();
+        let mut sample_count = 0;
+        let lost_event_count = run( &mut self.controller, &mut self.perf, false, |controller, dwarf_regs, sample| {
+            let (timestamp, pid, tid, cpu) = (sample.timestamp, sample.pid, sample.tid, sample.cpu);
+            let mut frames = Vec::new();
+            kallsyms::decode_backtrace( kallsyms, &sample.kernel_backtrace, &mut frames );
+            if write_output {
+                controller.generate_and_decode_sample( dwarf_regs, sample, &mut frames );
+            } else {
+                controller.decode_sample( dwarf_regs, &sample, &mut frames );
+            }
+
+            let thread_name = thread_names.entry( tid ).or_insert_with( || {
+                fs::read_to_string( format!( "/proc/{}/task/{}/comm", pid, tid ) ).ok().map( |name| name.trim_end().to_owned() )
+            }).clone();
+
+            sample_count += 1;
+            on_sample( reader::Sample {
+                timestamp,
+                pid,
+                tid,
+                cpu,
+                executable: executable.clone(),
+                thread_name,
+                frames
+            });
+        });
+
+        if write_output {
+            self.controller.finish( lost_event_count );
+        }
+
+        RecordingSummary {
+            sample_count,
+            lost_event_count
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! User scripts which can classify, relabel, reweight or drop the collapsed stacks.
+//!
+//! The script is written in [Rhai](https://rhai.rs) and has to define a `process` function which gets
+//! the frames of a single stack (starting from the root) and returns either:
+//!
+//!   * nothing, to keep the stack as it is,
+//!   * `false`, to drop it,
+//!   * a string, to put the stack under an extra root frame with that name (e.g. `"crypto"`),
+//!   * an array of strings, to replace the frames,
+//!   * a map with any of `tag` (the extra root frame), `frames` and `weight` (a multiplier for the sample count).
+//!
+//! e.g. `fn process( frames ) { if frames.some( |frame| frame.contains( "ssl_" ) ) { "crypto" } }`
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
+
+#[cfg(feature = "scripting")]
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+#[cfg(feature = "scripting")]
+pub(crate) struct Script {
+    engine: Engine,
+    ast: AST
+}
+
+#[cfg(not(feature = "scripting"))]
+pub(crate) enum Script {}
+
+#[cfg(feature = "scripting")]
+fn into_frames( value: Dynamic ) -> Result< Vec< String >, String > {
+    let frames: Array = value.into_array().map_err( |kind| format!( "expected an array of frames, got {}", kind ) )?;
+    frames.into_iter().map( |frame| frame.into_string().map_err( |kind| format!( "expected a frame name, got {}", kind ) ) ).collect()
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    pub fn load( path: &OsStr ) -> Result< Self, Box< dyn Error > > {
+        let source = std::fs::read_to_string( path ).map_err( |error| format!( "cannot read {:?}: {}", path, error ) )?;
+        Self::compile( &source ).map_err( |error| format!( "{:?}: {}", path, error ).into() )
+    }
+
+    fn compile( source: &str ) -> Result< Self, String > {
+        let mut engine = Engine::new();
+        // Everything happens inside of `process`, which by default gets only half of the nesting depth.
+        engine.set_max_expr_depths( 64, 64 );
+        let ast = engine.compile( source ).map_err( |error| error.to_string() )?;
+        if !ast.iter_functions().any( |function| function.name == "process" && function.params.len() == 1 ) {
+            return Err( "the script doesn't define a `process( frames )` function".into() );
+        }
+
+        Ok( Script { engine, ast } )
+    }
+
+    /// Runs the script on a single collapsed stack; returns `None` if it should be dropped.
+    fn process( &self, line: &str, count: u64 ) -> Result< Option< (String, u64) >, String > {
+        let frames: Array = line.split( ';' ).map( |frame| Dynamic::from( frame.to_owned() ) ).collect();
+        let result: Dynamic = self.engine.call_fn( &mut Scope::new(), &self.ast, "process", (frames,) )
+            .map_err( |error| format!( "the script failed for '{}': {}", line, error ) )?;
+
+        let (tag, frames, weight) = if result.is_unit() {
+            return Ok( Some( (line.to_owned(), count) ) );
+        } else if let Ok( keep ) = result.as_bool() {
+            return Ok( if keep { Some( (line.to_owned(), count) ) } else { None } );
+        } else if result.is_string() {
+            (Some( result.into_string().unwrap() ), None, 1.0)
+        } else if result.is_array() {
+            (None, Some( into_frames( result )? ), 1.0)
+        } else if let Some( mut map ) = result.try_cast::< Map >() {
+            let tag = match map.remove( "tag" ) {
+                Some( tag ) => Some( tag.into_string().map_err( |kind| format!( "expected the tag to be a string, got {}", kind ) )? ),
+                None => None
+            };
+
+            let frames = map.remove( "frames" ).map( into_frames ).transpose()?;
+            let weight = match map.remove( "weight" ) {
+                Some( weight ) => weight.as_float().or_else( |_| weight.as_int().map( |weight| weight as f64 ) )
+                    .map_err( |kind| format!( "expected the weight to be a number, got {}", kind ) )?,
+                None => 1.0
+            };
+
+            (tag, frames, weight)
+        } else {
+            return Err( format!( "the script returned an unexpected value for '{}'", line ) );
+        };
+
+        let mut output = tag.unwrap_or_default();
+        match frames {
+            Some( frames ) => {
+                for frame in frames {
+                    if !output.is_empty() {
+                        output.push( ';' );
+                    }
+                    output.push_str( &frame );
+                }
+            },
+            None => {
+                if !output.is_empty() {
+                    output.push( ';' );
+                }
+                output.push_str( line );
+            }
+        }
+
+        if output.is_empty() {
+            return Ok( None );
+        }
+
+        Ok( Some( (output, (count as f64 * weight).round() as u64) ) )
+    }
+
+    /// Runs the script on every collapsed stack, merging those which end up the same.
+    pub fn apply( &self, counts: HashMap< String, u64 > ) -> Result< HashMap< String, u64 >, Box< dyn Error > > {
+        let mut output = HashMap::with_capacity( counts.len() );
+        for (line, count) in counts {
+            if let Some( (line, count) ) = self.process( &line, count )? {
+                if count != 0 {
+                    *output.entry( line ).or_insert( 0 ) += count;
+                }
+            }
+        }
+
+        Ok( output )
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn load( _: &OsStr ) -> Result< Self, Box< dyn Error > > {
+        Err( "nperf was compiled without the `scripting` feature".into() )
+    }
+
+    pub fn apply( &self, _: HashMap< String, u64 > ) -> Result< HashMap< String, u64 >, Box< dyn Error > > {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "scripting")]
+#[test]
+fn test_script() {
+    let script = Script::compile( r#"
+        fn process( frames ) {
+            if frames.some( |frame| frame.contains( "ssl_" ) ) {
+                "crypto"
+            } else if frames.contains( "idle" ) {
+                false
+            } else if frames[ 0 ] == "main" {
+                #{ frames: frames.map( |frame| frame.to_upper() ), weight: 2 }
+            }
+        }
+    "# ).unwrap();
+
+    let mut counts = HashMap::new();
+    counts.insert( "main;ssl_read".to_owned(), 3 );
+    counts.insert( "start;ssl_write".to_owned(), 1 );
+    counts.insert( "main;idle".to_owned(), 5 );
+    counts.insert( "main;run".to_owned(), 7 );
+    counts.insert( "start;run".to_owned(), 11 );
+
+    let counts = script.apply( counts ).unwrap();
+    let mut counts: Vec< _ > = counts.into_iter().collect();
+    counts.sort();
+    assert_eq!( counts, vec![
+        ("MAIN;RUN".to_owned(), 14),
+        ("crypto;main;ssl_read".to_owned(), 3),
+        ("crypto;start;ssl_write".to_owned(), 1),
+        ("start;run".to_owned(), 11)
+    ]);
+
+    assert!( Script::compile( "fn other( frames ) {}" ).is_err() );
+    assert!( Script::compile( "fn process( frames ) { 1 }" ).unwrap().process( "main", 1 ).is_err() );
+}
@@ -0,0 +1,136 @@
+#[cfg(feature = "native")]
+use std::mem;
+#[cfg(feature = "native")]
+use std::time::Instant;
+
+#[cfg(feature = "native")]
+use crate::archive::Packet;
+use crate::metadata;
+use crate::utils::percent;
+
+/// Returns how much CPU time the current thread has used so far, in nanoseconds.
+#[cfg(feature = "native")]
+pub(crate) fn thread_cpu_time() -> u64 {
+    let mut timespec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime( libc::CLOCK_THREAD_CPUTIME_ID, &mut timespec );
+    }
+
+    timespec.tv_sec as u64 * 1_000_000_000 + timespec.tv_nsec as u64
+}
+
+#[cfg(feature = "native")]
+fn timeval_to_ns( timeval: libc::timeval ) -> u64 {
+    timeval.tv_sec as u64 * 1_000_000_000 + timeval.tv_usec as u64 * 1000
+}
+
+/// Returns how much CPU time the whole process has used so far, in nanoseconds,
+/// and its peak resident set size, in bytes.
+#[cfg(feature = "native")]
+fn process_usage() -> (u64, u64) {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    unsafe {
+        libc::getrusage( libc::RUSAGE_SELF, &mut usage );
+    }
+
+    (timeval_to_ns( usage.ru_utime ) + timeval_to_ns( usage.ru_stime ), usage.ru_maxrss as u64 * 1024)
+}
+
+#[cfg(feature = "native")]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Subsystem {
+    Unwinding,
+    SymbolLoading
+}
+
+/// Keeps track of how much CPU time the profiler spends on what; enabled with `--self-profile`.
+#[cfg(feature = "native")]
+pub(crate) struct SelfProfile {
+    started: Instant,
+    started_cpu_time: u64,
+    unwinding_time: u64,
+    symbol_loading_time: u64
+}
+
+/// A measurement of a single piece of work; only taken when the self-profiling is enabled.
+#[cfg(feature = "native")]
+pub(crate) struct CpuTimer( Option< u64 > );
+
+#[cfg(feature = "native")]
+impl CpuTimer {
+    pub fn start( self_profile: &Option< SelfProfile > ) -> Self {
+        CpuTimer( self_profile.as_ref().map( |_| thread_cpu_time() ) )
+    }
+
+    pub fn stop( self, self_profile: &mut Option< SelfProfile >, subsystem: Subsystem ) {
+        if let (Some( started ), Some( self_profile )) = (self.0, self_profile.as_mut()) {
+            let elapsed = thread_cpu_time().saturating_sub( started );
+            match subsystem {
+                Subsystem::Unwinding => self_profile.unwinding_time += elapsed,
+                Subsystem::SymbolLoading => self_profile.symbol_loading_time += elapsed
+            }
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl SelfProfile {
+    pub fn new() -> Self {
+        SelfProfile {
+            started: Instant::now(),
+            started_cpu_time: thread_cpu_time(),
+            unwinding_time: 0,
+            symbol_loading_time: 0
+        }
+    }
+
+    /// Gathers the measurements; has to be called on the thread which did the sampling,
+    /// and the returned closure on the thread which wrote out the data.
+    pub fn finish( &self ) -> impl FnOnce() -> Packet< 'static > + Send {
+        let wall_time = self.started.elapsed().as_nanos() as u64;
+        let main_thread_time = thread_cpu_time().saturating_sub( self.started_cpu_time );
+        let unwinding_time = self.unwinding_time;
+        let symbol_loading_time = self.symbol_loading_time;
+        let sampling_time = main_thread_time.saturating_sub( unwinding_time + symbol_loading_time );
+
+        move || {
+            let (cpu_time, max_rss) = process_usage();
+            Packet::SelfProfile {
+                wall_time,
+                cpu_time,
+                sampling_time,
+                unwinding_time,
+                symbol_loading_time,
+                io_time: thread_cpu_time(),
+                max_rss
+            }
+        }
+    }
+}
+
+/// Describes the overhead of the profiler in a human readable form, one line at a time.
+pub(crate) fn describe_self_profile( profile: &metadata::SelfProfile ) -> Vec< String > {
+    let ms = |time: u64| time as f64 / 1_000_000.0;
+    let subsystem = |name: &str, time: u64| format!( "  {}: {:.1}ms ({:.1}%)", name, ms( time ), percent( time, profile.cpu_time ) );
+    vec![
+        format!( "Wall time: {:.1}ms", ms( profile.wall_time ) ),
+        format!( "CPU time: {:.1}ms ({:.2}% of one CPU)", ms( profile.cpu_time ), percent( profile.cpu_time, profile.wall_time ) ),
+        subsystem( "Sampling", profile.sampling_time ),
+        subsystem( "Unwinding", profile.unwinding_time ),
+        subsystem( "Symbol loading", profile.symbol_loading_time ),
+        subsystem( "I/O", profile.io_time ),
+        format!( "Peak memory usage: {:.1} MB", profile.max_rss as f64 / (1024.0 * 1024.0) )
+    ]
+}
+
+/// Logs the contents of a `Packet::SelfProfile`.
+#[cfg(feature = "native")]
+pub(crate) fn log_self_profile( packet: &Packet ) {
+    if let Packet::SelfProfile { wall_time, cpu_time, sampling_time, unwinding_time, symbol_loading_time, io_time, max_rss } = *packet {
+        let profile = metadata::SelfProfile { wall_time, cpu_time, sampling_time, unwinding_time, symbol_loading_time, io_time, max_rss };
+        info!( "Profiler overhead:" );
+        for line in describe_self_profile( &profile ) {
+            info!( "  {}", line );
+        }
+    }
+}
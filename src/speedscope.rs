@@ -0,0 +1,71 @@
+// The speedscope file format; see:
+//   https://www.speedscope.app/file-format-schema.json
+
+#[derive(Debug, Serialize)]
+pub struct Frame {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option< String >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option< u64 >
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Shared {
+    pub frames: Vec< Frame >
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampledProfile {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    pub unit: &'static str,
+    pub start_value: u64,
+    pub end_value: u64,
+    pub samples: Vec< Vec< usize > >,
+    pub weights: Vec< u64 >
+}
+
+#[derive(Debug, Serialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub frame: usize,
+    pub at: u64
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventedProfile {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    pub unit: &'static str,
+    pub start_value: u64,
+    pub end_value: u64,
+    pub events: Vec< Event >
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Profile {
+    Sampled( SampledProfile ),
+    Evented( EventedProfile )
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct File {
+    #[serde(rename = "$schema")]
+    pub schema: &'static str,
+    pub shared: Shared,
+    pub profiles: Vec< Profile >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option< String >,
+    pub active_profile_index: usize,
+    pub exporter: String
+}
+
+pub const SCHEMA: &'static str = "https://www.speedscope.app/file-format-schema.json";
@@ -0,0 +1,159 @@
+//! A persistent cache of the symbolized addresses, so that collating the same recording again doesn't
+//! have to go through the debug info; every binary gets its own file named after its build ID,
+//! which is only written out when something new was resolved.
+//!
+//! What was resolved with extra symbol resolvers is kept in separate files for every configuration
+//! of the resolvers, since otherwise changing them would have no effect on the already cached addresses.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::symbol_resolver::ResolvedFrame;
+use crate::utils::{fnv1a, to_hex};
+
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    addresses: Vec< (u64, Vec< ResolvedFrame >) >
+}
+
+#[derive(Default)]
+struct Entry {
+    frames: HashMap< u64, Vec< ResolvedFrame > >,
+    is_dirty: bool
+}
+
+pub(crate) struct SymbolCache {
+    directory: PathBuf,
+    /// The hash of the description of the symbol resolvers, if there are any.
+    resolvers_hash: Option< u64 >,
+    entries: Mutex< HashMap< Vec< u8 >, Entry > >
+}
+
+impl SymbolCache {
+    /// Opens the cache for the symbol resolvers with a given description (see `SymbolResolvers::description`).
+    pub fn open( directory: &Path, resolvers: &str ) -> io::Result< Self > {
+        fs::create_dir_all( directory )?;
+        Ok( SymbolCache {
+            directory: directory.to_owned(),
+            resolvers_hash: if resolvers.is_empty() { None } else { Some( fnv1a( resolvers.as_bytes() ) ) },
+            entries: Mutex::new( HashMap::new() )
+        })
+    }
+
+    fn path( &self, build_id: &[u8] ) -> PathBuf {
+        match self.resolvers_hash {
+            Some( hash ) => self.directory.join( format!( "{}-{:016x}.json", to_hex( build_id ), hash ) ),
+            None => self.directory.join( format!( "{}.json", to_hex( build_id ) ) )
+        }
+    }
+
+    fn load( &self, build_id: &[u8] ) -> Entry {
+        let path = self.path( build_id );
+        let data = match fs::read( &path ) {
+            Ok( data ) => data,
+            Err( _ ) => return Entry::default()
+        };
+
+        match serde_json::from_slice::< CacheFile >( &data ) {
+            Ok( file ) if file.version == CACHE_VERSION => {
+                debug!( "Loaded {} cached addresses from {:?}", file.addresses.len(), path );
+                Entry {
+                    frames: file.addresses.into_iter().collect(),
+                    is_dirty: false
+                }
+            },
+            Ok( _ ) => Entry::default(),
+            Err( error ) => {
+                warn!( "Ignoring the corrupted symbol cache {:?}: {}", path, error );
+                Entry::default()
+            }
+        }
+    }
+
+    fn entry< 'a >( &self, entries: &'a mut HashMap< Vec< u8 >, Entry >, build_id: &[u8] ) -> &'a mut Entry {
+        if !entries.contains_key( build_id ) {
+            let entry = self.load( build_id );
+            entries.insert( build_id.to_owned(), entry );
+        }
+
+        entries.get_mut( build_id ).unwrap()
+    }
+
+    /// Returns the frames of an address (as it is in the binary's ELF headers) within the binary with a given build ID.
+    pub fn get( &self, build_id: &[u8], address: u64 ) -> Option< Vec< ResolvedFrame > > {
+        let mut entries = self.entries.lock().unwrap();
+        self.entry( &mut entries, build_id ).frames.get( &address ).cloned()
+    }
+
+    pub fn insert( &self, build_id: &[u8], address: u64, frames: Vec< ResolvedFrame > ) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = self.entry( &mut entries, build_id );
+        entry.frames.insert( address, frames );
+        entry.is_dirty = true;
+    }
+
+    /// Writes out every binary for which something new was resolved.
+    pub fn save( &self ) -> io::Result< () > {
+        let mut entries = self.entries.lock().unwrap();
+        for (build_id, entry) in entries.iter_mut().filter( |(_, entry)| entry.is_dirty ) {
+            let mut addresses: Vec< _ > = entry.frames.iter().map( |(&address, frames)| (address, frames.clone()) ).collect();
+            addresses.sort_by_key( |&(address, _)| address );
+
+            let file = CacheFile { version: CACHE_VERSION, addresses };
+            let path = self.path( build_id );
+            let tmp_path = path.with_extension( "json.tmp" );
+            fs::write( &tmp_path, serde_json::to_vec( &file )? )?;
+            fs::rename( &tmp_path, &path )?;
+            entry.is_dirty = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SymbolCache {
+    fn drop( &mut self ) {
+        if let Err( error ) = self.save() {
+            warn!( "Failed to save the symbol cache in {:?}: {}", self.directory, error );
+        }
+    }
+}
+
+#[test]
+fn test_symbol_cache() {
+    let directory = std::env::temp_dir().join( format!( "nperf-symbol-cache-test-{}", std::process::id() ) );
+    let _ = fs::remove_dir_all( &directory );
+
+    let frames = vec![
+        ResolvedFrame { name: "inlined".to_owned(), file: Some( "a.h".to_owned() ), line: Some( 1 ) },
+        ResolvedFrame { name: "main".to_owned(), file: Some( "a.c".to_owned() ), line: Some( 2 ) }
+    ];
+
+    {
+        let cache = SymbolCache::open( &directory, "" ).unwrap();
+        assert_eq!( cache.get( &[0x12, 0xab], 0x1000 ), None );
+        cache.insert( &[0x12, 0xab], 0x1000, frames.clone() );
+        assert_eq!( cache.get( &[0x12, 0xab], 0x1000 ), Some( frames.clone() ) );
+    }
+
+    assert!( directory.join( "12ab.json" ).exists() );
+    let cache = SymbolCache::open( &directory, "" ).unwrap();
+    assert_eq!( cache.get( &[0x12, 0xab], 0x1000 ), Some( frames ) );
+    assert_eq!( cache.get( &[0x12, 0xab], 0x2000 ), None );
+    assert_eq!( cache.get( &[0x12, 0xac], 0x1000 ), None );
+
+
+    // Differently configured symbol resolvers don't see each other's frames.
+    let other_cache = SymbolCache::open( &directory, "breakpad:/symbols None\n" ).unwrap();
+    assert_eq!( other_cache.get( &[0x12, 0xab], 0x1000 ), None );
+
+    drop( cache );
+    drop( other_cache );
+    fs::remove_dir_all( &directory ).unwrap();
+}
@@ -0,0 +1,511 @@
+//! Pluggable symbolization of the user space frames.
+//!
+//! The resolvers are selected with `--symbol-resolver <name>[:<config>]` and are consulted in the order
+//! in which they were given, where `elf` stands for the built-in symbolization of the ELF binaries;
+//! extra resolvers (e.g. for a proprietary symbol store) can be added with `register_symbol_resolver`
+//! by whoever embeds nperf before its command line is parsed.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nwind::IAddressSpace;
+
+use crate::cmd_symbolize_server::{SymbolizeRequest, SymbolizeResponse};
+use crate::http::{Url, parse_url, post};
+use crate::utils::to_hex;
+
+/// The code which a resolver is asked about.
+pub struct SymbolQuery< 'a > {
+    /// The path of the binary on the machine where it was profiled.
+    pub path: &'a str,
+    pub build_id: Option< &'a [u8] >,
+    /// The address as it'd be if the binary was loaded at the addresses from its program headers.
+    pub address: u64,
+    /// The lowest address of the part of the binary which is mapped from the start of its file.
+    pub load_address: u64
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ResolvedFrame {
+    pub name: String,
+    pub file: Option< String >,
+    pub line: Option< u64 >
+}
+
+pub trait SymbolResolver {
+    /// Returns the frames at a given address, with the innermost inlined function first
+    /// and the function into which they were inlined last, or `None` if the address is unknown.
+    fn resolve( &self, query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > >;
+}
+
+/// Creates a resolver from the `<config>` part of its `--symbol-resolver <name>:<config>`.
+pub type SymbolResolverFactory = fn( &str ) -> Result< Box< dyn SymbolResolver >, String >;
+
+lazy_static! {
+    static ref FACTORIES: Mutex< HashMap< String, SymbolResolverFactory > > = {
+        let mut factories: HashMap< String, SymbolResolverFactory > = HashMap::new();
+        factories.insert( "breakpad".into(), BreakpadResolver::create );
+        Mutex::new( factories )
+    };
+}
+
+/// Makes a resolver selectable with `--symbol-resolver <name>:<config>`.
+pub fn register_symbol_resolver( name: &str, factory: SymbolResolverFactory ) {
+    FACTORIES.lock().unwrap().insert( name.into(), factory );
+}
+
+/// The resolvers which are consulted before and after the built-in symbolization.
+#[derive(Default)]
+pub(crate) struct SymbolResolvers {
+    pub before: Vec< Box< dyn SymbolResolver > >,
+    pub after: Vec< Box< dyn SymbolResolver > >,
+    /// Describes how the resolvers were configured, so that whatever they resolved
+    /// isn't mixed up with what differently configured ones would resolve; empty if there are none.
+    pub description: String
+}
+
+impl SymbolResolvers {
+    pub fn new( specs: &[String] ) -> Result< Self, String > {
+        let mut resolvers = SymbolResolvers::default();
+        let mut is_after_builtin = false;
+        for spec in specs {
+            let (name, config) = match spec.find( ':' ) {
+                Some( index ) => (&spec[ ..index ], &spec[ index + 1.. ]),
+                None => (spec.as_str(), "")
+            };
+
+            if name == "elf" {
+                if is_after_builtin {
+                    return Err( "the `elf` symbol resolver was given more than once".into() );
+                }
+                is_after_builtin = true;
+                continue;
+            }
+
+            let factory = FACTORIES.lock().unwrap().get( name ).cloned()
+                .ok_or_else( || format!( "unknown symbol resolver: '{}'", name ) )?;
+
+            let resolver = factory( config ).map_err( |error| format!( "cannot create the '{}' symbol resolver: {}", name, error ) )?;
+            if is_after_builtin {
+                resolvers.after.push( resolver );
+            } else {
+                resolvers.before.push( resolver );
+            }
+        }
+
+        // Unless explicitly placed the built-in symbolization comes first.
+        if !is_after_builtin {
+            resolvers.after = std::mem::take( &mut resolvers.before );
+        }
+
+        if !resolvers.before.is_empty() || !resolvers.after.is_empty() {
+            for spec in specs {
+                resolvers.add_to_description( spec );
+            }
+        }
+
+        Ok( resolvers )
+    }
+
+    /// Adds the `<name>[:<config>]` of a resolver to the description of the resolvers; if its config
+    /// is a path then its modification time is included too, so that e.g. an edited symbol file counts as a change.
+    pub fn add_to_description( &mut self, spec: &str ) {
+        let config = spec.find( ':' ).map( |index| &spec[ index + 1.. ] ).unwrap_or( "" );
+        let modified = fs::metadata( config ).ok()
+            .and_then( |metadata| metadata.modified().ok() )
+            .and_then( |modified| modified.duration_since( std::time::UNIX_EPOCH ).ok() )
+            .map( |modified| modified.as_nanos() );
+
+        self.description.push_str( &format!( "{} {:?}\n", spec, modified ) );
+    }
+}
+
+/// Resolves an address through the symbols and the debug info of the binaries loaded into an address space;
+/// returns `None` unless every frame has a name, in which case the other resolvers should get a chance.
+pub(crate) fn resolve_elf( address_space: &dyn IAddressSpace, address: u64 ) -> Option< Vec< ResolvedFrame > > {
+    let mut frames = Vec::new();
+    let mut is_complete = true;
+    address_space.decode_symbol_while( address, &mut |frame| {
+        match frame.demangled_name.take().or_else( || frame.name.take() ) {
+            Some( name ) => frames.push( ResolvedFrame {
+                name: name.into_owned(),
+                file: frame.file.take(),
+                line: frame.line
+            }),
+            None => is_complete = false
+        }
+
+        is_complete
+    });
+
+    if frames.is_empty() || !is_complete {
+        None
+    } else {
+        Some( frames )
+    }
+}
+
+/// Computes the identifier under which Breakpad stores the symbols of a binary with a given build ID.
+fn breakpad_id( build_id: &[u8] ) -> String {
+    let mut guid = [0; 16];
+    let length = std::cmp::min( build_id.len(), guid.len() );
+    guid[ ..length ].copy_from_slice( &build_id[ ..length ] );
+    guid[ 0..4 ].reverse();
+    guid[ 4..6 ].reverse();
+    guid[ 6..8 ].reverse();
+
+    let mut output: String = guid.iter().map( |byte| format!( "{:02X}", byte ) ).collect();
+    output.push( '0' );
+    output
+}
+
+struct BreakpadInline {
+    depth: u32,
+    call_line: u64,
+    call_file: u32,
+    origin: u32,
+    ranges: Vec< (u64, u64) >
+}
+
+struct BreakpadFunction {
+    address: u64,
+    size: u64,
+    name: String,
+    // The address, the size, the line and the file of every line record.
+    lines: Vec< (u64, u64, u64, u32) >,
+    inlines: Vec< BreakpadInline >
+}
+
+#[derive(Default)]
+struct BreakpadSymbols {
+    files: HashMap< u32, String >,
+    inline_origins: HashMap< u32, String >,
+    // Sorted by the address.
+    functions: Vec< BreakpadFunction >,
+    // The address and the name, sorted by the address.
+    publics: Vec< (u64, String) >
+}
+
+fn parse_hex( value: Option< &str > ) -> Option< u64 > {
+    u64::from_str_radix( value?, 16 ).ok()
+}
+
+/// Splits off the given number of space separated fields, leaving the rest of the line (e.g. a name with spaces) intact.
+fn split_fields( line: &str, count: usize ) -> Vec< &str > {
+    let mut fields: Vec< &str > = line.splitn( count + 1, ' ' ).collect();
+    // The `m` marks functions which were merged by the linker.
+    if fields.get( 1 ) == Some( &"m" ) {
+        fields = line.splitn( count + 2, ' ' ).collect();
+        fields.remove( 1 );
+    }
+
+    fields
+}
+
+fn parse_breakpad_symbols( data: &str ) -> BreakpadSymbols {
+    let mut symbols = BreakpadSymbols::default();
+    for line in data.lines() {
+        let line = line.trim_end();
+        if line.starts_with( "FILE " ) {
+            let fields = split_fields( line, 2 );
+            if let (Some( index ), Some( path )) = (fields.get( 1 ).and_then( |index| index.parse().ok() ), fields.get( 2 )) {
+                symbols.files.insert( index, (*path).to_owned() );
+            }
+        } else if line.starts_with( "INLINE_ORIGIN " ) {
+            let fields = split_fields( line, 2 );
+            if let (Some( index ), Some( name )) = (fields.get( 1 ).and_then( |index| index.parse().ok() ), fields.get( 2 )) {
+                symbols.inline_origins.insert( index, (*name).to_owned() );
+            }
+        } else if line.starts_with( "FUNC " ) {
+            let fields = split_fields( line, 4 );
+            if let (Some( address ), Some( size ), Some( name )) = (parse_hex( fields.get( 1 ).cloned() ), parse_hex( fields.get( 2 ).cloned() ), fields.get( 4 )) {
+                symbols.functions.push( BreakpadFunction {
+                    address,
+                    size,
+                    name: (*name).to_owned(),
+                    lines: Vec::new(),
+                    inlines: Vec::new()
+                });
+            }
+        } else if line.starts_with( "PUBLIC " ) {
+            let fields = split_fields( line, 3 );
+            if let (Some( address ), Some( name )) = (parse_hex( fields.get( 1 ).cloned() ), fields.get( 3 )) {
+                symbols.publics.push( (address, (*name).to_owned()) );
+            }
+        } else if line.starts_with( "INLINE " ) {
+            let fields: Vec< &str > = line.split( ' ' ).collect();
+            let function = match symbols.functions.last_mut() {
+                Some( function ) => function,
+                None => continue
+            };
+
+            if let (Some( depth ), Some( call_line ), Some( call_file ), Some( origin )) = (
+                fields.get( 1 ).and_then( |value| value.parse().ok() ),
+                fields.get( 2 ).and_then( |value| value.parse().ok() ),
+                fields.get( 3 ).and_then( |value| value.parse().ok() ),
+                fields.get( 4 ).and_then( |value| value.parse().ok() )
+            ) {
+                let ranges = fields[ 5.. ].chunks( 2 )
+                    .filter_map( |range| Some( (parse_hex( range.first().cloned() )?, parse_hex( range.get( 1 ).cloned() )?) ) )
+                    .collect();
+
+                function.inlines.push( BreakpadInline { depth, call_line, call_file, origin, ranges } );
+            }
+        } else if line.starts_with( |ch: char| ch.is_ascii_hexdigit() ) {
+            let fields: Vec< &str > = line.split( ' ' ).collect();
+            let function = match symbols.functions.last_mut() {
+                Some( function ) => function,
+                None => continue
+            };
+
+            if let (Some( address ), Some( size ), Some( line ), Some( file )) = (
+                parse_hex( fields.first().cloned() ),
+                parse_hex( fields.get( 1 ).cloned() ),
+                fields.get( 2 ).and_then( |value| value.parse().ok() ),
+                fields.get( 3 ).and_then( |value| value.parse().ok() )
+            ) {
+                function.lines.push( (address, size, line, file) );
+            }
+        }
+    }
+
+    symbols.functions.sort_by_key( |function| function.address );
+    symbols.publics.sort_by_key( |&(address, _)| address );
+    symbols
+}
+
+impl BreakpadSymbols {
+    fn resolve( &self, address: u64 ) -> Option< Vec< ResolvedFrame > > {
+        let index = match self.functions.binary_search_by_key( &address, |function| function.address ) {
+            Ok( index ) => Some( index ),
+            Err( 0 ) => None,
+            Err( index ) => Some( index - 1 )
+        };
+
+        let function = match index.map( |index| &self.functions[ index ] ) {
+            Some( function ) if address < function.address + function.size => function,
+            _ => {
+                let index = match self.publics.binary_search_by_key( &address, |&(address, _)| address ) {
+                    Ok( index ) => index,
+                    Err( 0 ) => return None,
+                    Err( index ) => index - 1
+                };
+
+                return Some( vec![ ResolvedFrame {
+                    name: self.publics[ index ].1.clone(),
+                    file: None,
+                    line: None
+                }]);
+            }
+        };
+
+        let mut file = None;
+        let mut line = None;
+        if let Some( &(_, _, record_line, record_file) ) = function.lines.iter().find( |&&(start, size, _, _)| address >= start && address < start + size ) {
+            file = self.files.get( &record_file ).cloned();
+            line = Some( record_line );
+        }
+
+        let mut inlines: Vec< &BreakpadInline > = function.inlines.iter()
+            .filter( |inline| inline.ranges.iter().any( |&(start, size)| address >= start && address < start + size ) )
+            .collect();
+        inlines.sort_by_key( |inline| std::cmp::Reverse( inline.depth ) );
+
+        let mut frames = Vec::with_capacity( inlines.len() + 1 );
+        for inline in inlines {
+            frames.push( ResolvedFrame {
+                name: self.inline_origins.get( &inline.origin ).cloned().unwrap_or_else( || "<unknown>".into() ),
+                file,
+                line
+            });
+
+            file = self.files.get( &inline.call_file ).cloned();
+            line = Some( inline.call_line );
+        }
+
+        frames.push( ResolvedFrame {
+            name: function.name.clone(),
+            file,
+            line
+        });
+
+        Some( frames )
+    }
+}
+
+/// Looks up the symbols in a Breakpad symbol store, which is a directory
+/// laid out as `<binary name>/<Breakpad ID>/<binary name>.sym`, e.g. as produced by `nperf dump-syms`.
+struct BreakpadResolver {
+    directory: PathBuf,
+    cache: Mutex< HashMap< PathBuf, Option< BreakpadSymbols > > >
+}
+
+impl BreakpadResolver {
+    fn create( config: &str ) -> Result< Box< dyn SymbolResolver >, String > {
+        if config.is_empty() {
+            return Err( "no symbol store given; expected `breakpad:<directory>`".into() );
+        }
+
+        if !Path::new( config ).is_dir() {
+            return Err( format!( "{:?} is not a directory", config ) );
+        }
+
+        Ok( Box::new( BreakpadResolver {
+            directory: config.into(),
+            cache: Mutex::new( HashMap::new() )
+        }))
+    }
+}
+
+impl SymbolResolver for BreakpadResolver {
+    fn resolve( &self, query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > > {
+        let build_id = query.build_id?;
+        let name = Path::new( query.path ).file_name()?.to_str()?;
+        let path = self.directory.join( name ).join( breakpad_id( build_id ) ).join( format!( "{}.sym", name ) );
+
+        let mut cache = self.cache.lock().unwrap();
+        let symbols = cache.entry( path ).or_insert_with_key( |path| {
+            let data = fs::read( path ).ok()?;
+            debug!( "Loaded Breakpad symbols from {:?}", path );
+            Some( parse_breakpad_symbols( &String::from_utf8_lossy( &data ) ) )
+        });
+
+        symbols.as_ref()?.resolve( query.address.checked_sub( query.load_address )? )
+    }
+}
+
+/// Asks an `nperf symbolize-server` about the binaries with a build ID; what the server
+/// doesn't know (or everything, once it fails to respond) is left to the other resolvers.
+pub(crate) struct RemoteResolver {
+    url: Url,
+    has_failed: AtomicBool,
+    cache: Mutex< HashMap< (Vec< u8 >, u64), Frames > >
+}
+
+type Frames = Option< Vec< ResolvedFrame > >;
+
+impl RemoteResolver {
+    pub fn new( url: &str ) -> Result< Self, String > {
+        Ok( RemoteResolver {
+            url: parse_url( url )?,
+            has_failed: AtomicBool::new( false ),
+            cache: Mutex::new( HashMap::new() )
+        })
+    }
+
+    fn request( &self, build_id: &[u8], query: &SymbolQuery ) -> Result< Option< Vec< ResolvedFrame > >, Box< dyn Error > > {
+        let request = SymbolizeRequest {
+            build_id: to_hex( build_id ),
+            path: Some( query.path.to_owned() ),
+            load_address: Some( query.load_address ),
+            addresses: vec![ query.address ]
+        };
+
+        let response = post( &self.url, "/symbolize", "application/json", &serde_json::to_vec( &request )? )?;
+        let response: SymbolizeResponse = serde_json::from_slice( &response )?;
+        Ok( response.frames.into_iter().next().flatten() )
+    }
+}
+
+impl SymbolResolver for RemoteResolver {
+    fn resolve( &self, query: &SymbolQuery ) -> Option< Vec< ResolvedFrame > > {
+        let build_id = query.build_id?;
+        if self.has_failed.load( Ordering::Relaxed ) {
+            return None;
+        }
+
+        let key = (build_id.to_owned(), query.address);
+        if let Some( frames ) = self.cache.lock().unwrap().get( &key ) {
+            return frames.clone();
+        }
+
+        match self.request( build_id, query ) {
+            Ok( frames ) => {
+                self.cache.lock().unwrap().insert( key, frames.clone() );
+                frames
+            },
+            Err( error ) => {
+                warn!( "Failed to symbolize through {}:{}: {}; falling back to local symbolization", self.url.host, self.url.port, error );
+                self.has_failed.store( true, Ordering::Relaxed );
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn test_breakpad_id() {
+    let build_id = [0x5e, 0x0f, 0x4b, 0x3a, 0x24, 0xc1, 0x4c, 0x8a, 0x9f, 0x3e, 0x1d, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f, 0x11, 0x22, 0x33, 0x44];
+    assert_eq!( breakpad_id( &build_id ), "3A4B0F5EC1248A4C9F3E1D2B3C4D5E6F0" );
+}
+
+#[test]
+fn test_breakpad_symbols() {
+    let symbols = parse_breakpad_symbols( "\
+MODULE Linux x86_64 3A4B0F5EC1248A4C9F3E1D2B3C4D5E6F0 app
+INFO CODE_ID 5E0F4B3A24C14C8A9F3E1D2B3C4D5E6F11223344
+FILE 0 /src/main.c
+FILE 1 /src/util.h
+INLINE_ORIGIN 0 helper
+PUBLIC 900 0 _start
+FUNC m 1000 40 0 main
+INLINE 0 12 0 0 1010 10
+1000 10 10 0
+1010 10 3 1
+1020 20 14 0
+FUNC 2000 10 0 other function
+2000 10 20 0
+" );
+
+    let frame = |name: &str, file: &str, line: u64| ResolvedFrame { name: name.into(), file: Some( file.into() ), line: Some( line ) };
+    assert_eq!( symbols.resolve( 0x1004 ), Some( vec![ frame( "main", "/src/main.c", 10 ) ] ) );
+    assert_eq!( symbols.resolve( 0x1018 ), Some( vec![ frame( "helper", "/src/util.h", 3 ), frame( "main", "/src/main.c", 12 ) ] ) );
+    assert_eq!( symbols.resolve( 0x2008 ), Some( vec![ frame( "other function", "/src/main.c", 20 ) ] ) );
+    assert_eq!( symbols.resolve( 0x950 ), Some( vec![ ResolvedFrame { name: "_start".into(), file: None, line: None } ] ) );
+    assert_eq!( symbols.resolve( 0x800 ), None );
+}
+
+#[test]
+fn test_symbol_resolvers_order() {
+    let dir = std::env::temp_dir();
+    let dir = dir.to_str().unwrap();
+    let resolvers = SymbolResolvers::new( &[format!( "breakpad:{}", dir )] ).unwrap();
+    assert_eq!( (resolvers.before.len(), resolvers.after.len()), (0, 1) );
+
+    let resolvers = SymbolResolvers::new( &[format!( "breakpad:{}", dir ), "elf".into(), format!( "breakpad:{}", dir )] ).unwrap();
+    assert_eq!( (resolvers.before.len(), resolvers.after.len()), (1, 1) );
+
+    assert!( SymbolResolvers::new( &["unknown".into()] ).is_err() );
+    assert!( SymbolResolvers::new( &["breakpad".into()] ).is_err() );
+}
+
+#[test]
+fn test_remote_resolver() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let url = format!( "http://{}", listener.local_addr().unwrap() );
+    let server = std::thread::spawn( move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = crate::http::read_request( &mut stream, 1024 ).unwrap();
+        let request: SymbolizeRequest = serde_json::from_slice( &request.body ).unwrap();
+        assert_eq!( (request.build_id.as_str(), request.addresses), ("12ab", vec![ 0x1000 ]) );
+        crate::http::write_response( &mut stream, "200 OK", "application/json", br#"{"frames":[[{"name":"main","file":null,"line":null}]]}"# ).unwrap();
+    });
+
+    let resolver = RemoteResolver::new( &url ).unwrap();
+    let query = |address| SymbolQuery { path: "/usr/bin/app", build_id: Some( &[0x12, 0xab] ), address, load_address: 0 };
+    let expected = Some( vec![ ResolvedFrame { name: "main".to_owned(), file: None, line: None } ] );
+    assert_eq!( resolver.resolve( &query( 0x1000 ) ), expected );
+    server.join().unwrap();
+
+    // The answer is cached, and once the server's gone everything is left to the other resolvers.
+    assert_eq!( resolver.resolve( &query( 0x1000 ) ), expected );
+    assert_eq!( resolver.resolve( &query( 0x2000 ) ), None );
+    assert!( resolver.has_failed.load( Ordering::Relaxed ) );
+    assert_eq!( resolver.resolve( &SymbolQuery { build_id: None, .. query( 0x1000 ) } ), None );
+}
@@ -0,0 +1,333 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::time::Duration;
+
+use nwind::DwarfRegs;
+use proc_maps::Region;
+
+use crate::archive::Packet;
+use crate::cmd_analyze_core::into_dwarf_regs;
+use crate::cmd_backtrace::{MACHINE, Attached, read_thread, snapshot};
+use crate::cmd_watchdog::{monotonic_timestamp, read_stats};
+use crate::profiler::{ProfilingController, Sample};
+use crate::raw_data::CowRawData;
+use crate::utils::read_string_lossy;
+
+/// A periodic timer based on `timerfd`.
+struct Timer( libc::c_int );
+
+impl Timer {
+    fn new( interval: Duration ) -> io::Result< Self > {
+        // A zero interval would disarm the timer instead.
+        if interval == Duration::from_secs( 0 ) {
+            return Err( io::Error::new( io::ErrorKind::InvalidInput, "the interval must be greater than zero" ) );
+        }
+
+        let fd = unsafe { libc::timerfd_create( libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC ) };
+        if fd < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        let timer = Timer( fd );
+        let interval = libc::timespec {
+            tv_sec: interval.as_secs() as _,
+            tv_nsec: interval.subsec_nanos() as _
+        };
+
+        let spec = libc::itimerspec {
+            it_interval: interval,
+            it_value: interval
+        };
+
+        if unsafe { libc::timerfd_settime( fd, 0, &spec, std::ptr::null_mut() ) } < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok( timer )
+    }
+
+    /// Waits for the timer to fire; returns how many times it fired since the last call.
+    fn wait( &self ) -> io::Result< u64 > {
+        let mut expirations: u64 = 0;
+        let result = unsafe { libc::read( self.0, &mut expirations as *mut u64 as *mut libc::c_void, mem::size_of::< u64 >() ) };
+        if result != mem::size_of::< u64 >() as isize {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err( error );
+            }
+
+            // Interrupted by a signal (e.g. by a Ctrl-C), so let the caller check whether it should stop.
+            return Ok( 0 );
+        }
+
+        Ok( expirations )
+    }
+}
+
+impl Drop for Timer {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.0 );
+        }
+    }
+}
+
+/// Returns the interval of a timer which fires `frequency` times per second.
+fn timer_interval( frequency: u32 ) -> Result< Duration, String > {
+    if frequency == 0 {
+        return Err( "the frequency must be greater than zero".to_owned() );
+    }
+
+    let interval = Duration::from_secs( 1 ) / frequency;
+    if interval == Duration::from_secs( 0 ) {
+        return Err( format!( "the frequency can't be higher than {} Hz", Duration::from_secs( 1 ).as_nanos() ) );
+    }
+
+    Ok( interval )
+}
+
+/// Tells the controller about the regions which weren't there the last time.
+fn update_regions( controller: &mut ProfilingController, regions: Vec< Region >, last_regions: &mut Vec< Region > ) {
+    if regions == *last_regions {
+        return;
+    }
+
+    let mut new_maps = regions.iter()
+        .filter( |region| !region.name.is_empty() && !region.is_shared && !last_regions.contains( region ) )
+        .cloned()
+        .collect();
+
+    controller.update_maps( &mut new_maps );
+    *last_regions = regions;
+}
+
+fn write_thread_name( controller: &mut ProfilingController, named_threads: &mut HashSet< u32 >, tid: u32, name: &str ) {
+    let pid = controller.pid();
+    if tid != pid && named_threads.insert( tid ) {
+        controller.write_packet( Packet::ThreadName {
+            pid,
+            tid,
+            name: name.trim_end().as_bytes().to_owned().into()
+        });
+    }
+}
+
+/// Gathers the samples without using perf events; a timer fires `frequency` times per second
+/// and every thread which is running at that moment gets briefly stopped with ptrace so that its
+/// registers and the top of its stack can be grabbed. This is meant as a fallback for environments
+/// where perf events are forbidden; the kernel frames are never gathered, only the running threads
+/// of the profiled process are sampled, and the overhead is much higher.
+///
+/// Returns the number of timer ticks which were missed because the sampling took too long.
+pub(crate) fn run< F >( controller: &mut ProfilingController, frequency: u32, stack_size: u32, discard_all: bool, mut on_sample: F ) -> Result< u64, Box< dyn Error > >
+    where F: FnMut( &mut ProfilingController, &mut DwarfRegs, Sample )
+{
+    let machine = match MACHINE {
+        Some( machine ) => machine,
+        None => return Err( "the timer-based sampler is not supported on this architecture".into() )
+    };
+
+    let interval = timer_interval( frequency )?;
+
+    let pid = controller.pid();
+    let memory = File::open( format!( "/proc/{}/mem", pid ) ).map_err( |err| format!( "cannot open /proc/{}/mem: {}", pid, err ) )?;
+    let timer = Timer::new( interval ).map_err( |err| format!( "cannot create a timer: {}", err ) )?;
+
+    let mut last_maps = String::new();
+    let mut regions: Vec< Region > = Vec::new();
+    let mut named_threads = HashSet::new();
+    let mut dwarf_regs = DwarfRegs::new();
+    let mut missed_ticks = 0;
+
+    info!( "Running with the timer-based sampler..." );
+    loop {
+        controller.poll_triggers();
+        controller.report_progress();
+        controller.poll_perf_map();
+        controller.poll_memory_stats();
+        if controller.should_stop() {
+            break;
+        }
+
+        let expirations = timer.wait().map_err( |err| format!( "cannot wait for the timer: {}", err ) )?;
+        if expirations > 1 {
+            missed_ticks += expirations - 1;
+            controller.add_lost_events( expirations - 1 );
+        }
+
+        // This has to be read before the threads are stopped, or else they won't be seen as running.
+        let stats = read_stats( pid );
+        if stats.is_empty() && !Path::new( &format!( "/proc/{}", pid ) ).exists() {
+            info!( "The process has exited; stopping!" );
+            break;
+        }
+
+        if controller.is_paused() {
+            continue;
+        }
+
+        let mut running: Vec< _ > = stats.iter().filter( |(_, stat)| stat.state == 'R' ).map( |(&tid, stat)| (tid, stat.cpu) ).collect();
+        if running.is_empty() {
+            continue;
+        }
+
+        running.sort();
+
+        if let Ok( maps ) = read_string_lossy( format!( "/proc/{}/maps", pid ) ) {
+            if maps != last_maps {
+                update_regions( controller, proc_maps::parse( &maps ), &mut regions );
+                last_maps = maps;
+            }
+        }
+
+        for (tid, cpu) in running {
+            if controller.should_stop() {
+                break;
+            }
+
+            if discard_all {
+                controller.skip_sample();
+                continue;
+            }
+
+            if !named_threads.contains( &tid ) {
+                let name = read_string_lossy( format!( "/proc/{}/task/{}/comm", pid, tid ) ).unwrap_or_default();
+                write_thread_name( controller, &mut named_threads, tid, &name );
+            }
+
+            let (registers, stack) = {
+                let handle = match Attached::new( tid ) {
+                    Ok( handle ) => handle,
+                    Err( ref error ) if error.raw_os_error() == Some( libc::ESRCH ) => continue,
+                    Err( error ) => return Err( format!( "cannot attach to TID {}: {}", tid, error ).into() )
+                };
+
+                match read_thread( &handle, &regions, &memory, stack_size ) {
+                    Some( snapshot ) => snapshot,
+                    None => continue
+                }
+            };
+
+            into_dwarf_regs( machine, &registers, &mut dwarf_regs );
+            on_sample( controller, &mut dwarf_regs, Sample {
+                timestamp: monotonic_timestamp(),
+                pid,
+                tid,
+                cpu,
+                kernel_backtrace: Cow::Borrowed( &[] ),
+                stack: CowRawData::Owned( stack )
+            });
+        }
+
+        named_threads.retain( |tid| stats.contains_key( tid ) );
+    }
+
+    Ok( missed_ticks )
+}
+
+/// Gathers the samples without using perf events by stopping the whole process `frequency` times per second,
+/// grabbing the registers and the tops of the stacks of all of its threads (whether they're running or not)
+/// and letting it go. This works even on kernels without any perf support, but since every thread is
+/// stopped on every tick it's only meant for low frequencies.
+///
+/// Returns the number of timer ticks which were missed because the sampling took too long.
+pub(crate) fn run_snapshots< F >( controller: &mut ProfilingController, frequency: u32, stack_size: u32, discard_all: bool, mut on_sample: F ) -> Result< u64, Box< dyn Error > >
+    where F: FnMut( &mut ProfilingController, &mut DwarfRegs, Sample )
+{
+    let machine = match MACHINE {
+        Some( machine ) => machine,
+        None => return Err( "the snapshot sampler is not supported on this architecture".into() )
+    };
+
+    let interval = timer_interval( frequency )?;
+
+    if frequency > 100 {
+        warn!( "The whole process is stopped {} times per second; consider using a lower frequency", frequency );
+    }
+
+    let pid = controller.pid();
+    let timer = Timer::new( interval ).map_err( |err| format!( "cannot create a timer: {}", err ) )?;
+
+    let mut regions: Vec< Region > = Vec::new();
+    let mut named_threads = HashSet::new();
+    let mut dwarf_regs = DwarfRegs::new();
+    let mut missed_ticks = 0;
+
+    info!( "Running with the snapshot sampler..." );
+    loop {
+        controller.poll_triggers();
+        controller.report_progress();
+        controller.poll_perf_map();
+        controller.poll_memory_stats();
+        if controller.should_stop() {
+            break;
+        }
+
+        let expirations = timer.wait().map_err( |err| format!( "cannot wait for the timer: {}", err ) )?;
+        if expirations > 1 {
+            missed_ticks += expirations - 1;
+            controller.add_lost_events( expirations - 1 );
+        }
+
+        if controller.is_paused() {
+            continue;
+        }
+
+        let stats = read_stats( pid );
+        let (new_regions, threads) = match snapshot( pid, stack_size ) {
+            Ok( snapshot ) => snapshot,
+            Err( error ) => {
+                if !Path::new( &format!( "/proc/{}", pid ) ).exists() {
+                    info!( "The process has exited; stopping!" );
+                    break;
+                }
+
+                return Err( error );
+            }
+        };
+
+        let timestamp = monotonic_timestamp();
+        update_regions( controller, new_regions, &mut regions );
+
+        for thread in threads {
+            if controller.should_stop() {
+                break;
+            }
+
+            if discard_all {
+                controller.skip_sample();
+                continue;
+            }
+
+            write_thread_name( controller, &mut named_threads, thread.tid, &thread.name );
+
+            into_dwarf_regs( machine, &thread.registers, &mut dwarf_regs );
+            on_sample( controller, &mut dwarf_regs, Sample {
+                timestamp,
+                pid,
+                tid: thread.tid,
+                cpu: stats.get( &thread.tid ).map( |stat| stat.cpu ).unwrap_or( 0 ),
+                kernel_backtrace: Cow::Borrowed( &[] ),
+                stack: CowRawData::Owned( thread.stack )
+            });
+        }
+
+        named_threads.retain( |tid| stats.contains_key( tid ) );
+    }
+
+    Ok( missed_ticks )
+}
+
+#[test]
+fn test_timer_interval() {
+    assert_eq!( timer_interval( 1000 ), Ok( Duration::from_millis( 1 ) ) );
+    assert_eq!( timer_interval( 1_000_000_000 ), Ok( Duration::from_nanos( 1 ) ) );
+    assert!( timer_interval( 0 ).is_err() );
+    assert!( timer_interval( 1_000_000_001 ).is_err() );
+    assert!( timer_interval( u32::MAX ).is_err() );
+}
@@ -0,0 +1,157 @@
+// Conditions which decide when the recorder starts and stops writing out the samples,
+// e.g. `cpu>80%`, `rss>1G` or `marker`, where the marker is a `SIGUSR1` sent to the profiler.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::utils::{parse_size, parse_stat, read_string_lossy};
+
+static MARKER_COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+
+const POLL_INTERVAL: Duration = Duration::from_secs( 1 );
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Trigger {
+    /// The CPU usage of the process is above (or below) a given percentage of a single core.
+    Cpu { above: bool, percent: f64 },
+    /// The resident set size of the process is above (or below) a given number of bytes.
+    Rss { above: bool, bytes: u64 },
+    /// The profiler has received a `SIGUSR1`.
+    Marker
+}
+
+impl Trigger {
+    pub fn parse( trigger: &str ) -> Result< Self, String > {
+        let trigger: String = trigger.chars().filter( |ch| !ch.is_whitespace() ).collect();
+        if trigger == "marker" {
+            return Ok( Trigger::Marker );
+        }
+
+        let index = trigger.find( |ch| ch == '<' || ch == '>' ).ok_or( "expected either 'marker', or a '<' or '>' comparison" )?;
+        let above = &trigger[ index..index + 1 ] == ">";
+        let value = &trigger[ index + 1.. ];
+        match &trigger[ ..index ] {
+            "cpu" => {
+                let percent = value.strip_suffix( '%' ).unwrap_or( value ).parse().map_err( |_| format!( "invalid CPU usage: '{}'", value ) )?;
+                Ok( Trigger::Cpu { above, percent } )
+            },
+            "rss" => {
+                let bytes = parse_size( value ).ok_or_else( || format!( "invalid size: '{}'", value ) )?;
+                Ok( Trigger::Rss { above, bytes } )
+            },
+            name => Err( format!( "unknown metric: '{}'; expected either 'cpu' or 'rss'", name ) )
+        }
+    }
+
+    fn is_triggered( &self, cpu: Option< f64 >, rss: Option< u64 >, marker: bool ) -> bool {
+        match *self {
+            Trigger::Cpu { above, percent } => cpu.map( |cpu| if above { cpu > percent } else { cpu < percent } ).unwrap_or( false ),
+            Trigger::Rss { above, bytes } => rss.map( |rss| if above { rss > bytes } else { rss < bytes } ).unwrap_or( false ),
+            Trigger::Marker => marker
+        }
+    }
+}
+
+pub struct Triggers {
+    pid: u32,
+    start: Vec< Trigger >,
+    stop: Vec< Trigger >,
+    is_recording: bool,
+    is_finished: bool,
+    last_poll: Option< (Instant, u64) >,
+    ticks_per_second: f64,
+    page_size: u64
+}
+
+impl Triggers {
+    /// When there are no start triggers the recording starts right away; when a stop trigger
+    /// fires the recording either waits for a start trigger again, or is finished if there are none.
+    pub fn new( pid: u32, start: Vec< Trigger >, stop: Vec< Trigger > ) -> Self {
+        if start.iter().chain( stop.iter() ).any( |trigger| *trigger == Trigger::Marker ) {
+            extern "C" fn handler( _: libc::c_int ) {
+                MARKER_COUNTER.fetch_add( 1, Ordering::Relaxed );
+            }
+
+            unsafe {
+                libc::signal( libc::SIGUSR1, handler as extern "C" fn( libc::c_int ) as libc::sighandler_t );
+            }
+        }
+
+        let is_recording = start.is_empty();
+        if !is_recording {
+            info!( "Waiting for a start trigger..." );
+        }
+
+        Triggers {
+            pid,
+            start,
+            stop,
+            is_recording,
+            is_finished: false,
+            last_poll: None,
+            ticks_per_second: unsafe { libc::sysconf( libc::_SC_CLK_TCK ) } as f64,
+            page_size: unsafe { libc::sysconf( libc::_SC_PAGESIZE ) } as u64
+        }
+    }
+
+    pub fn is_recording( &self ) -> bool {
+        self.is_recording && !self.is_finished
+    }
+
+    pub fn is_finished( &self ) -> bool {
+        self.is_finished
+    }
+
+    /// Checks the triggers; the process' statistics are only read once every second.
+    pub fn poll( &mut self ) {
+        let now = Instant::now();
+        if self.is_finished || self.last_poll.map( |(timestamp, _)| now - timestamp < POLL_INTERVAL ).unwrap_or( false ) {
+            return;
+        }
+
+        let stat = read_string_lossy( format!( "/proc/{}/stat", self.pid ) ).ok().and_then( |stat| parse_stat( &stat ) );
+        let rss = stat.as_ref().map( |stat| stat.rss * self.page_size );
+        let cpu = match (self.last_poll, stat.as_ref()) {
+            (Some( (timestamp, cpu_time) ), Some( stat )) => {
+                let elapsed = (now - timestamp).as_secs_f64();
+                Some( stat.cpu_time.saturating_sub( cpu_time ) as f64 / self.ticks_per_second / elapsed * 100.0 )
+            },
+            _ => None
+        };
+
+        self.last_poll = Some( (now, stat.map( |stat| stat.cpu_time ).unwrap_or( 0 )) );
+        let marker = MARKER_COUNTER.swap( 0, Ordering::Relaxed ) > 0;
+
+        if self.is_recording {
+            if self.stop.iter().any( |trigger| trigger.is_triggered( cpu, rss, marker ) ) {
+                if self.start.is_empty() {
+                    info!( "Stop trigger fired; stopping!" );
+                    self.is_finished = true;
+                } else {
+                    info!( "Stop trigger fired; waiting for a start trigger..." );
+                    self.is_recording = false;
+                }
+            }
+        } else if self.start.iter().any( |trigger| trigger.is_triggered( cpu, rss, marker ) ) {
+            info!( "Start trigger fired; recording..." );
+            self.is_recording = true;
+        }
+    }
+}
+
+#[test]
+fn test_parse_trigger() {
+    assert_eq!( Trigger::parse( "cpu>80%" ), Ok( Trigger::Cpu { above: true, percent: 80.0 } ) );
+    assert_eq!( Trigger::parse( "cpu < 10" ), Ok( Trigger::Cpu { above: false, percent: 10.0 } ) );
+    assert_eq!( Trigger::parse( "rss>512M" ), Ok( Trigger::Rss { above: true, bytes: 512 * 1024 * 1024 } ) );
+    assert_eq!( Trigger::parse( "rss<4096" ), Ok( Trigger::Rss { above: false, bytes: 4096 } ) );
+    assert_eq!( Trigger::parse( "marker" ), Ok( Trigger::Marker ) );
+    assert!( Trigger::parse( "cpu=80%" ).is_err() );
+    assert!( Trigger::parse( "load>1" ).is_err() );
+    assert!( Trigger::parse( "rss>lots" ).is_err() );
+
+    let trigger = Trigger::Cpu { above: true, percent: 80.0 };
+    assert!( trigger.is_triggered( Some( 95.0 ), None, false ) );
+    assert!( !trigger.is_triggered( Some( 50.0 ), None, false ) );
+    assert!( !trigger.is_triggered( None, None, true ) );
+}
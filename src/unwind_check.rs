@@ -0,0 +1,515 @@
+//! Cross-checks nwind's unwinding against libunwind's, to make it practical to hunt for bugs
+//! in the unwinder, e.g. with the binaries produced by a new toolchain.
+//!
+//! Every sample is unwound a second time by libunwind's remote unwinder, which is given the same
+//! registers and stack dump and reads the CFI straight from the binaries through their `.eh_frame_hdr`
+//! (so, unlike nwind, it never looks at `.debug_frame`); every sample for which the two disagree is
+//! written out along with its registers and both backtraces.
+//!
+//! This needs the `libunwind` feature and libunwind 1.6 or newer, and only supports amd64.
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+use proc_maps::Region;
+use nwind::{BinaryData, RangeMap, UserFrame, UnwindFailure};
+
+use crate::archive::DwarfReg;
+
+#[cfg(feature = "libunwind")]
+use std::cmp::max;
+#[cfg(feature = "libunwind")]
+use std::fs::File;
+#[cfg(feature = "libunwind")]
+use std::io::{self, Write};
+#[cfg(feature = "libunwind")]
+use std::mem;
+#[cfg(feature = "libunwind")]
+use std::ops::Range;
+#[cfg(feature = "libunwind")]
+use std::os::raw::{c_int, c_void};
+#[cfg(feature = "libunwind")]
+use std::ptr;
+
+#[cfg(feature = "libunwind")]
+use nwind::BinaryId;
+#[cfg(feature = "libunwind")]
+use nwind::arch::{self, Architecture};
+
+#[cfg(feature = "libunwind")]
+use crate::data_reader::{get_basename, unwind_failure_frame_name};
+
+/// Everything about a single sample which is needed to unwind it again.
+#[cfg_attr(not(feature = "libunwind"), allow(dead_code))]
+pub(crate) struct CheckedSample< 'a > {
+    pub number: u64,
+    pub pid: u32,
+    pub tid: u32,
+    pub timestamp: u64,
+    pub regs: &'a [DwarfReg],
+    pub stack: &'a [u8],
+    pub memory_regions: &'a RangeMap< Region >,
+    pub backtrace: &'a [UserFrame],
+    pub unwind_failure: Option< UnwindFailure >
+}
+
+/// Returns the contents of the binary which is mapped in a given region, if there is one.
+pub(crate) type BinaryLookup< 'a > = &'a dyn Fn( &Region ) -> Option< Arc< BinaryData > >;
+
+#[cfg(feature = "libunwind")]
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub type unw_word_t = u64;
+    pub type unw_addr_space_t = *mut c_void;
+
+    pub const UNW_EINVAL: c_int = 8;
+    pub const UNW_ENOINFO: c_int = 10;
+    pub const UNW_REG_IP: c_int = 16;
+    pub const UNW_CACHE_NONE: c_int = 0;
+    pub const UNW_INFO_FORMAT_REMOTE_TABLE: i32 = 2;
+
+    #[repr(C)]
+    pub struct unw_cursor_t {
+        pub opaque: [unw_word_t; 127]
+    }
+
+    /// `unw_dyn_info_t` with the `unw_dyn_remote_table_info_t` variant of its union.
+    #[repr(C)]
+    pub struct unw_dyn_info_t {
+        pub next: *mut unw_dyn_info_t,
+        pub prev: *mut unw_dyn_info_t,
+        pub start_ip: unw_word_t,
+        pub end_ip: unw_word_t,
+        pub gp: unw_word_t,
+        pub format: i32,
+        pub pad: i32,
+        pub load_offset: unw_word_t,
+        pub name_ptr: unw_word_t,
+        pub segbase: unw_word_t,
+        pub table_len: unw_word_t,
+        pub table_data: unw_word_t
+    }
+
+    #[repr(C)]
+    pub struct unw_accessors_t {
+        pub find_proc_info: unsafe extern "C" fn( unw_addr_space_t, unw_word_t, *mut c_void, c_int, *mut c_void ) -> c_int,
+        pub put_unwind_info: unsafe extern "C" fn( unw_addr_space_t, *mut c_void, *mut c_void ),
+        pub get_dyn_info_list_addr: unsafe extern "C" fn( unw_addr_space_t, *mut unw_word_t, *mut c_void ) -> c_int,
+        pub access_mem: unsafe extern "C" fn( unw_addr_space_t, unw_word_t, *mut unw_word_t, c_int, *mut c_void ) -> c_int,
+        pub access_reg: unsafe extern "C" fn( unw_addr_space_t, c_int, *mut unw_word_t, c_int, *mut c_void ) -> c_int,
+        pub access_fpreg: unsafe extern "C" fn( unw_addr_space_t, c_int, *mut c_void, c_int, *mut c_void ) -> c_int,
+        pub resume: unsafe extern "C" fn( unw_addr_space_t, *mut unw_cursor_t, *mut c_void ) -> c_int,
+        pub get_proc_name: unsafe extern "C" fn( unw_addr_space_t, unw_word_t, *mut c_char, usize, *mut unw_word_t, *mut c_void ) -> c_int,
+        /// The newer versions of libunwind have a few more optional accessors at the end.
+        pub reserved: [usize; 4]
+    }
+
+    #[link(name = "unwind-x86_64")]
+    extern "C" {
+        #[link_name = "_Ux86_64_create_addr_space"]
+        pub fn unw_create_addr_space( accessors: *mut unw_accessors_t, byte_order: c_int ) -> unw_addr_space_t;
+        #[link_name = "_Ux86_64_destroy_addr_space"]
+        pub fn unw_destroy_addr_space( address_space: unw_addr_space_t );
+        #[link_name = "_Ux86_64_set_caching_policy"]
+        pub fn unw_set_caching_policy( address_space: unw_addr_space_t, policy: c_int ) -> c_int;
+        #[link_name = "_Ux86_64_init_remote"]
+        pub fn unw_init_remote( cursor: *mut unw_cursor_t, address_space: unw_addr_space_t, arg: *mut c_void ) -> c_int;
+        #[link_name = "_Ux86_64_step"]
+        pub fn unw_step( cursor: *mut unw_cursor_t ) -> c_int;
+        #[link_name = "_Ux86_64_get_reg"]
+        pub fn unw_get_reg( cursor: *mut unw_cursor_t, register: c_int, value: *mut unw_word_t ) -> c_int;
+        #[link_name = "_Ux86_64_dwarf_search_unwind_table"]
+        pub fn dwarf_search_unwind_table(
+            address_space: unw_addr_space_t,
+            ip: unw_word_t,
+            info: *mut unw_dyn_info_t,
+            proc_info: *mut c_void,
+            need_unwind_info: c_int,
+            arg: *mut c_void
+        ) -> c_int;
+    }
+}
+
+#[cfg(feature = "libunwind")]
+const MAX_FRAMES: usize = 1024;
+
+#[cfg(feature = "libunwind")]
+struct Context< 'a > {
+    sample: &'a CheckedSample< 'a >,
+    stack_pointer: u64,
+    binary_for: BinaryLookup< 'a >
+}
+
+#[cfg(feature = "libunwind")]
+impl< 'a > Context< 'a > {
+    fn register( &self, register: u16 ) -> Option< u64 > {
+        self.sample.regs.iter().find( |reg| reg.register == register ).map( |reg| reg.value )
+    }
+
+    fn read_u64( &self, address: u64 ) -> Option< u64 > {
+        let mut buffer = [0; 8];
+        if let Some( offset ) = address.checked_sub( self.stack_pointer ) {
+            let offset = offset as usize;
+            if let Some( bytes ) = self.sample.stack.get( offset..offset.saturating_add( 8 ) ) {
+                buffer.copy_from_slice( bytes );
+                return Some( u64::from_le_bytes( buffer ) );
+            }
+        }
+
+        let region = self.sample.memory_regions.get_value( address )?;
+        let binary = (self.binary_for)( region )?;
+        let offset = (address - region.start + region.file_offset) as usize;
+        buffer.copy_from_slice( binary.as_bytes().get( offset..offset.saturating_add( 8 ) )? );
+        Some( u64::from_le_bytes( buffer ) )
+    }
+
+    /// Returns the region in which a given address is mapped, the address of its binary's
+    /// `.eh_frame_hdr` and the number of entries in its lookup table.
+    fn eh_frame_hdr( &self, address: u64 ) -> Option< (Range< u64 >, u64, u64) > {
+        let region = self.sample.memory_regions.get_value( address )?;
+        let binary = (self.binary_for)( region )?;
+        let range = binary.eh_frame_hdr_range()?;
+        let fde_count = parse_eh_frame_hdr( binary.as_bytes().get( range.clone() )? )?;
+
+        let binary_id = BinaryId::from( region );
+        let offset = range.start as u64;
+        let eh_frame_hdr_address = self.sample.memory_regions.values()
+            .filter( |other| BinaryId::from( *other ) == binary_id )
+            .find( |other| offset >= other.file_offset && offset < other.file_offset + (other.end - other.start) )
+            .map( |other| other.start + offset - other.file_offset )?;
+
+        Some( (region.start..region.end, eh_frame_hdr_address, fde_count) )
+    }
+}
+
+/// Returns the number of entries in the lookup table of an `.eh_frame_hdr`
+/// if it's in the only format which libunwind can use.
+#[cfg(feature = "libunwind")]
+fn parse_eh_frame_hdr( data: &[u8] ) -> Option< u64 > {
+    const DW_EH_PE_UDATA4: u8 = 0x03;
+    const DW_EH_PE_SDATA4: u8 = 0x0b;
+    const DW_EH_PE_DATAREL: u8 = 0x30;
+
+    let is_four_bytes = |encoding: u8| encoding & 0x0f == DW_EH_PE_UDATA4 || encoding & 0x0f == DW_EH_PE_SDATA4;
+    if data.len() < 12 || data[ 0 ] != 1 || !is_four_bytes( data[ 1 ] ) || !is_four_bytes( data[ 2 ] ) || data[ 3 ] != DW_EH_PE_DATAREL | DW_EH_PE_SDATA4 {
+        return None;
+    }
+
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice( &data[ 8..12 ] );
+    Some( u32::from_le_bytes( buffer ) as u64 )
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn find_proc_info( address_space: ffi::unw_addr_space_t, ip: ffi::unw_word_t, proc_info: *mut c_void, need_unwind_info: c_int, arg: *mut c_void ) -> c_int {
+    let context = &*(arg as *const Context);
+    let (range, eh_frame_hdr_address, fde_count) = match context.eh_frame_hdr( ip ) {
+        Some( result ) => result,
+        None => return -ffi::UNW_ENOINFO
+    };
+
+    let mut info = ffi::unw_dyn_info_t {
+        next: ptr::null_mut(),
+        prev: ptr::null_mut(),
+        start_ip: range.start,
+        end_ip: range.end,
+        gp: 0,
+        format: ffi::UNW_INFO_FORMAT_REMOTE_TABLE,
+        pad: 0,
+        load_offset: 0,
+        name_ptr: 0,
+        segbase: eh_frame_hdr_address,
+        // Every entry of the table takes exactly one word.
+        table_len: fde_count,
+        table_data: eh_frame_hdr_address + 12
+    };
+
+    ffi::dwarf_search_unwind_table( address_space, ip, &mut info, proc_info, need_unwind_info, arg )
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn put_unwind_info( _: ffi::unw_addr_space_t, _: *mut c_void, _: *mut c_void ) {}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn get_dyn_info_list_addr( _: ffi::unw_addr_space_t, _: *mut ffi::unw_word_t, _: *mut c_void ) -> c_int {
+    -ffi::UNW_ENOINFO
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn access_mem( _: ffi::unw_addr_space_t, address: ffi::unw_word_t, value: *mut ffi::unw_word_t, write: c_int, arg: *mut c_void ) -> c_int {
+    let context = &*(arg as *const Context);
+    if write != 0 {
+        return -ffi::UNW_EINVAL;
+    }
+
+    match context.read_u64( address ) {
+        Some( result ) => {
+            *value = result;
+            0
+        },
+        None => -ffi::UNW_EINVAL
+    }
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn access_reg( _: ffi::unw_addr_space_t, register: c_int, value: *mut ffi::unw_word_t, write: c_int, arg: *mut c_void ) -> c_int {
+    let context = &*(arg as *const Context);
+    if write != 0 {
+        return -ffi::UNW_EINVAL;
+    }
+
+    // libunwind's register numbers for amd64 are the same as the DWARF ones.
+    match context.register( register as u16 ) {
+        Some( result ) => {
+            *value = result;
+            0
+        },
+        None => -ffi::UNW_EINVAL
+    }
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn access_fpreg( _: ffi::unw_addr_space_t, _: c_int, _: *mut c_void, _: c_int, _: *mut c_void ) -> c_int {
+    -ffi::UNW_EINVAL
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn resume( _: ffi::unw_addr_space_t, _: *mut ffi::unw_cursor_t, _: *mut c_void ) -> c_int {
+    -ffi::UNW_EINVAL
+}
+
+#[cfg(feature = "libunwind")]
+unsafe extern "C" fn get_proc_name( _: ffi::unw_addr_space_t, _: ffi::unw_word_t, _: *mut std::os::raw::c_char, _: usize, _: *mut ffi::unw_word_t, _: *mut c_void ) -> c_int {
+    -ffi::UNW_EINVAL
+}
+
+#[cfg(feature = "libunwind")]
+fn error_name( code: c_int ) -> String {
+    let name = match -code {
+        1 => "UNW_EUNSPEC",
+        2 => "UNW_ENOMEM",
+        3 => "UNW_EBADREG",
+        4 => "UNW_EREADONLYREG",
+        5 => "UNW_ESTOPUNWIND",
+        6 => "UNW_EINVALIDIP",
+        7 => "UNW_EBADFRAME",
+        8 => "UNW_EINVAL",
+        9 => "UNW_EBADVERSION",
+        10 => "UNW_ENOINFO",
+        _ => return format!( "error {}", code )
+    };
+
+    name.to_owned()
+}
+
+/// Returns the index of the first frame in which the two backtraces differ.
+#[cfg(feature = "libunwind")]
+fn first_divergence( nwind_frames: &[u64], libunwind_frames: &[u64] ) -> Option< usize > {
+    nwind_frames.iter().zip( libunwind_frames ).position( |(lhs, rhs)| lhs != rhs ).or_else( || {
+        if nwind_frames.len() != libunwind_frames.len() {
+            Some( nwind_frames.len().min( libunwind_frames.len() ) )
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(feature = "libunwind")]
+fn describe_address( memory_regions: &RangeMap< Region >, address: u64 ) -> String {
+    match memory_regions.get_value( address ) {
+        Some( region ) => format!( "0x{:016X} {}+0x{:X}", address, get_basename( &region.name ), address - region.start + region.file_offset ),
+        None => format!( "0x{:016X}", address )
+    }
+}
+
+#[cfg(feature = "libunwind")]
+fn write_divergence(
+    output: &mut dyn Write,
+    sample: &CheckedSample,
+    nwind_frames: &[u64],
+    libunwind_frames: &[u64],
+    libunwind_result: &str,
+    index: usize
+) -> io::Result< () > {
+    writeln!( output, "Sample #{} (PID {}, TID {}, timestamp {}):", sample.number, sample.pid, sample.tid, sample.timestamp )?;
+
+    write!( output, "  Registers:" )?;
+    for reg in sample.regs {
+        match arch::amd64::Arch::register_name_str( reg.register ) {
+            Some( name ) => write!( output, " {}=0x{:X}", name, reg.value )?,
+            None => write!( output, " #{}=0x{:X}", reg.register, reg.value )?
+        }
+    }
+    writeln!( output )?;
+
+    let nwind_result = match sample.unwind_failure {
+        Some( failure ) => unwind_failure_frame_name( failure ),
+        None => "finished"
+    };
+
+    writeln!( output, "  Stack: {} bytes", sample.stack.len() )?;
+    writeln!( output, "  nwind: {} frame(s), {}", nwind_frames.len(), nwind_result )?;
+    writeln!( output, "  libunwind: {} frame(s), {}", libunwind_frames.len(), libunwind_result )?;
+    writeln!( output, "        {:<50}libunwind", "nwind" )?;
+    for nth in 0..max( nwind_frames.len(), libunwind_frames.len() ) {
+        let describe = |frames: &[u64]| frames.get( nth ).map( |&address| describe_address( sample.memory_regions, address ) ).unwrap_or_default();
+        let marker = if nth >= index { "!" } else { " " };
+        writeln!( output, "  {}{:<5}{:<50}{}", marker, format!( "#{}", nth ), describe( nwind_frames ), describe( libunwind_frames ) )?;
+    }
+
+    writeln!( output )
+}
+
+#[cfg(feature = "libunwind")]
+pub(crate) struct UnwindChecker {
+    address_space: ffi::unw_addr_space_t,
+    output: io::BufWriter< File >,
+    checked_count: u64,
+    divergence_count: u64
+}
+
+#[cfg(feature = "libunwind")]
+impl UnwindChecker {
+    pub fn create( path: &OsStr ) -> Result< Self, Box< dyn Error > > {
+        let output = File::create( path ).map_err( |error| format!( "cannot create {:?}: {}", path, error ) )?;
+        let mut accessors = ffi::unw_accessors_t {
+            find_proc_info,
+            put_unwind_info,
+            get_dyn_info_list_addr,
+            access_mem,
+            access_reg,
+            access_fpreg,
+            resume,
+            get_proc_name,
+            reserved: [0; 4]
+        };
+
+        let address_space = unsafe { ffi::unw_create_addr_space( &mut accessors, 0 ) };
+        if address_space.is_null() {
+            return Err( "cannot create libunwind's address space".into() );
+        }
+
+        // The same addresses can point to completely different code in different processes.
+        unsafe {
+            ffi::unw_set_caching_policy( address_space, ffi::UNW_CACHE_NONE );
+        }
+
+        Ok( UnwindChecker {
+            address_space,
+            output: io::BufWriter::new( output ),
+            checked_count: 0,
+            divergence_count: 0
+        })
+    }
+
+    /// Returns the backtrace and why the unwinding stopped.
+    fn unwind( &self, context: &Context ) -> (Vec< u64 >, String) {
+        let mut frames = Vec::new();
+        unsafe {
+            let mut cursor: ffi::unw_cursor_t = mem::zeroed();
+            let result = ffi::unw_init_remote( &mut cursor, self.address_space, context as *const Context as *mut c_void );
+            if result < 0 {
+                return (frames, format!( "initialization failed with {}", error_name( result ) ));
+            }
+
+            loop {
+                let mut ip = 0;
+                let result = ffi::unw_get_reg( &mut cursor, ffi::UNW_REG_IP, &mut ip );
+                if result < 0 {
+                    return (frames, format!( "fetching the instruction pointer failed with {}", error_name( result ) ));
+                }
+
+                if ip == 0 {
+                    return (frames, "finished".to_owned());
+                }
+
+                frames.push( ip );
+                if frames.len() >= MAX_FRAMES {
+                    return (frames, "too many frames".to_owned());
+                }
+
+                match ffi::unw_step( &mut cursor ) {
+                    0 => return (frames, "finished".to_owned()),
+                    result if result < 0 => return (frames, format!( "stepping failed with {}", error_name( result ) )),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn check( &mut self, sample: &CheckedSample, binary_for: BinaryLookup ) -> io::Result< () > {
+        let stack_pointer = match sample.regs.iter().find( |reg| reg.register == arch::amd64::dwarf::RSP ) {
+            Some( reg ) => reg.value,
+            None => return Ok(())
+        };
+
+        let context = Context { sample, stack_pointer, binary_for };
+        let (libunwind_frames, libunwind_result) = self.unwind( &context );
+        let nwind_frames: Vec< _ > = sample.backtrace.iter().map( |frame| frame.address ).collect();
+
+        self.checked_count += 1;
+        if let Some( index ) = first_divergence( &nwind_frames, &libunwind_frames ) {
+            self.divergence_count += 1;
+            write_divergence( &mut self.output, sample, &nwind_frames, &libunwind_frames, &libunwind_result, index )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish( mut self ) -> io::Result< () > {
+        self.output.flush()?;
+        info!( "Cross-checked {} samples against libunwind; {} of them diverged", self.checked_count, self.divergence_count );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "libunwind")]
+impl Drop for UnwindChecker {
+    fn drop( &mut self ) {
+        unsafe {
+            ffi::unw_destroy_addr_space( self.address_space );
+        }
+    }
+}
+
+#[cfg(not(feature = "libunwind"))]
+pub(crate) enum UnwindChecker {}
+
+#[cfg(not(feature = "libunwind"))]
+impl UnwindChecker {
+    pub fn create( _: &OsStr ) -> Result< Self, Box< dyn Error > > {
+        Err( "nperf was compiled without the `libunwind` feature".into() )
+    }
+
+    pub fn check( &mut self, _: &CheckedSample, _: BinaryLookup ) -> std::io::Result< () > {
+        match *self {}
+    }
+
+    pub fn finish( self ) -> std::io::Result< () > {
+        match self {}
+    }
+}
+
+#[cfg(feature = "libunwind")]
+#[test]
+fn test_first_divergence() {
+    assert_eq!( first_divergence( &[1, 2, 3], &[1, 2, 3] ), None );
+    assert_eq!( first_divergence( &[1, 2, 3], &[1, 5, 3] ), Some( 1 ) );
+    assert_eq!( first_divergence( &[1, 2], &[1, 2, 3] ), Some( 2 ) );
+    assert_eq!( first_divergence( &[1, 2, 3], &[] ), Some( 0 ) );
+}
+
+#[cfg(feature = "libunwind")]
+#[test]
+fn test_parse_eh_frame_hdr() {
+    let data = [1, 0x1b, 0x03, 0x3b, 0x10, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00];
+    assert_eq!( parse_eh_frame_hdr( &data ), Some( 42 ) );
+    assert_eq!( parse_eh_frame_hdr( &data[ ..11 ] ), None );
+
+    let mut data = data;
+    data[ 3 ] = 0x1b;
+    assert_eq!( parse_eh_frame_hdr( &data ), None );
+}
@@ -1,25 +1,192 @@
+#[cfg(feature = "native")]
 use std::io;
+#[cfg(feature = "native")]
 use std::fs::read;
+#[cfg(feature = "native")]
 use std::path::Path;
+#[cfg(feature = "native")]
 use std::sync::atomic::{Ordering, AtomicBool};
 
+#[cfg(feature = "native")]
 use libc;
 
 pub use nwind::utils::*;
 
+#[cfg(feature = "native")]
 pub fn read_string_lossy< P: AsRef< Path > >( path: P ) -> io::Result< String > {
     let data = read( path )?;
     Ok( String::from_utf8_lossy( &data ).into_owned() )
 }
 
+/// Parses a size in bytes with an optional `K`, `M` or `G` suffix, e.g. `512M`.
+#[cfg(feature = "native")]
+pub fn parse_size( size: &str ) -> Option< u64 > {
+    let (size, multiplier) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => (&size[ ..size.len() - 1 ], 1024),
+        'M' => (&size[ ..size.len() - 1 ], 1024 * 1024),
+        'G' => (&size[ ..size.len() - 1 ], 1024 * 1024 * 1024),
+        _ => (size, 1)
+    };
+
+    size.parse::< u64 >().ok()?.checked_mul( multiplier )
+}
+
+/// Parses an address in hex, with or without the `0x` prefix.
+pub fn parse_hex_address( address: &str ) -> Option< u64 > {
+    u64::from_str_radix( address.trim_start_matches( "0x" ), 16 ).ok()
+}
+
+/// Parses a string of hex digits (e.g. a build ID) into bytes.
+pub fn parse_hex_bytes( string: &str ) -> Option< Vec< u8 > > {
+    if string.len() % 2 == 1 || !string.is_ascii() {
+        return None;
+    }
+
+    (0..string.len()).step_by( 2 ).map( |index| u8::from_str_radix( &string[ index..index + 2 ], 16 ).ok() ).collect()
+}
+
+/// 64-bit FNV-1a; this has to stay the same across versions, so the hasher from the standard library can't be used.
+pub fn fnv1a( data: &[u8] ) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul( 0x0000_0100_0000_01b3 );
+    }
+
+    hash
+}
+
+pub fn to_hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+}
+
+/// Returns what percentage of `total` the `value` is, or zero if the `total` is zero.
+pub fn percent( value: u64, total: u64 ) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        value as f64 * 100.0 / total as f64
+    }
+}
+
+/// Parses a list of CPUs in the format used by the kernel, e.g. `0-3,8,10-11`.
+#[cfg(feature = "native")]
+pub fn parse_cpu_list( list: &str ) -> Option< Vec< u32 > > {
+    let mut cpus = Vec::new();
+    for range in list.trim().split( ',' ) {
+        match range.find( '-' ) {
+            Some( index ) => {
+                let first: u32 = range[ ..index ].parse().ok()?;
+                let last: u32 = range[ index + 1.. ].parse().ok()?;
+                cpus.extend( first..=last );
+            },
+            None => cpus.push( range.parse().ok()? )
+        }
+    }
+
+    Some( cpus )
+}
+
+/// Returns the ids of the CPUs which are online; these are not necessarily contiguous.
+#[cfg(feature = "native")]
+pub fn online_cpus() -> Vec< u32 > {
+    read_string_lossy( "/sys/devices/system/cpu/online" ).ok()
+        .and_then( |list| parse_cpu_list( &list ) )
+        .unwrap_or_else( || (0..num_cpus::get() as u32).collect() )
+}
+
+/// Extracts the unified (v2) hierarchy's path from the contents of `/proc/<pid>/cgroup`.
+#[cfg(feature = "native")]
+pub fn parse_cgroup_v2_path( contents: &str ) -> Option< &str > {
+    contents.lines()
+        .filter_map( |line| line.strip_prefix( "0::" ) )
+        .map( |path| path.trim() )
+        .find( |path| !path.is_empty() )
+}
+
+/// Returns the cgroup (v2) of a given process, if the system uses the unified hierarchy.
+#[cfg(feature = "native")]
+pub fn read_cgroup( pid: u32 ) -> Option< String > {
+    let contents = read_string_lossy( format!( "/proc/{}/cgroup", pid ) ).ok()?;
+    parse_cgroup_v2_path( &contents ).map( |path| path.to_owned() )
+}
+
+/// The interesting bits of `/proc/<pid>/stat` or `/proc/<pid>/task/<tid>/stat`.
+#[cfg(feature = "native")]
+#[derive(PartialEq, Debug)]
+pub struct ProcStat {
+    pub state: char,
+    /// The user and system time, in clock ticks.
+    pub cpu_time: u64,
+    /// The CPU on which it last ran.
+    pub cpu: u32,
+    /// The resident set size, in pages.
+    pub rss: u64
+}
+
+#[cfg(feature = "native")]
+pub fn parse_stat( stat: &str ) -> Option< ProcStat > {
+    // The name can contain anything, including spaces and parentheses,
+    // so the fields are counted from the last closing parenthesis.
+    let fields: Vec< &str > = stat[ stat.rfind( ')' )? + 1.. ].split_whitespace().collect();
+    let state = fields.get( 0 )?.chars().next()?;
+    let utime: u64 = fields.get( 11 )?.parse().ok()?;
+    let stime: u64 = fields.get( 12 )?.parse().ok()?;
+    let rss = fields.get( 21 ).and_then( |rss| rss.parse().ok() ).unwrap_or( 0 );
+    let cpu = fields.get( 36 ).and_then( |cpu| cpu.parse().ok() ).unwrap_or( 0 );
+
+    Some( ProcStat { state, cpu_time: utime + stime, cpu, rss } )
+}
+
+/// The memory use of a process, in bytes.
+#[cfg(feature = "native")]
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct MemoryStats {
+    pub rss: u64,
+    pub pss: u64,
+    pub swap: u64
+}
+
+/// Parses the contents of `/proc/<pid>/smaps_rollup`.
+#[cfg(feature = "native")]
+pub fn parse_smaps_rollup( contents: &str ) -> Option< MemoryStats > {
+    let mut stats = MemoryStats::default();
+    let mut found = false;
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some( "Rss:" ) => &mut stats.rss,
+            Some( "Pss:" ) => &mut stats.pss,
+            Some( "Swap:" ) => &mut stats.swap,
+            _ => continue
+        };
+
+        let value: u64 = fields.next()?.parse().ok()?;
+        *field = match fields.next() {
+            Some( "kB" ) => value * 1024,
+            _ => value
+        };
+        found = true;
+    }
+
+    if found {
+        Some( stats )
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "native")]
 lazy_static! {
     static ref SIGINT_FLAG: AtomicBool = AtomicBool::new( false );
 }
 
+#[cfg(feature = "native")]
 #[derive(Clone)]
 pub struct SigintHandler {
 }
 
+#[cfg(feature = "native")]
 impl SigintHandler {
     pub fn new() -> Self {
         SIGINT_FLAG.store( false, Ordering::Relaxed ); // To initialize the `lazy_static`.
@@ -34,7 +201,103 @@ impl SigintHandler {
         SigintHandler {}
     }
 
+    /// Doesn't install the signal handler, so that the SIGINT is left to whatever program the profiler is embedded in.
+    pub fn new_passive() -> Self {
+        SigintHandler {}
+    }
+
     pub fn was_triggered( &self ) -> bool {
         SIGINT_FLAG.load( Ordering::Relaxed )
     }
 }
+
+/// Compares the output of a test with the one saved in `test-data/golden`; run the tests
+/// with `NPERF_UPDATE_GOLDEN=1` to save the current output instead after an intentional change.
+#[cfg(test)]
+pub(crate) fn assert_golden( name: &str, output: &[u8] ) {
+    let path = std::path::Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "golden" ).join( name );
+    if std::env::var_os( "NPERF_UPDATE_GOLDEN" ).is_some() {
+        std::fs::create_dir_all( path.parent().unwrap() ).unwrap();
+        std::fs::write( &path, output ).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read( &path ).unwrap_or_else( |error| panic!( "cannot read {:?}: {}", path, error ) );
+    if expected != output {
+        let actual_path = std::env::temp_dir().join( format!( "nperf-{}-{}", std::process::id(), name ) );
+        std::fs::write( &actual_path, output ).unwrap();
+        panic!( "the output doesn't match {:?}; it was saved into {:?}", path, actual_path );
+    }
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_stat() {
+    let stat = "1234 (a (weird) name) R 1 1234 1234 0 -1 4194304 100 0 0 0 250 30 0 0 20 0 3 0 12345 1000000 200 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 5 0 0 0 0 0\n";
+    assert_eq!( parse_stat( stat ), Some( ProcStat { state: 'R', cpu_time: 280, cpu: 5, rss: 200 } ) );
+    assert_eq!( parse_stat( "1234 (name" ), None );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_smaps_rollup() {
+    let contents = "\
+55d4c9a3e000-7ffd3f1fc000 ---p 00000000 00:00 0                          [rollup]
+Rss:                2048 kB
+Pss:                1024 kB
+Pss_Anon:            512 kB
+Shared_Clean:        256 kB
+Swap:                 16 kB
+SwapPss:               8 kB
+";
+
+    assert_eq!( parse_smaps_rollup( contents ), Some( MemoryStats { rss: 2048 * 1024, pss: 1024 * 1024, swap: 16 * 1024 } ) );
+    assert_eq!( parse_smaps_rollup( "" ), None );
+    assert_eq!( parse_smaps_rollup( "Rss: lots kB" ), None );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_cgroup_v2_path() {
+    assert_eq!( parse_cgroup_v2_path( "0::/system.slice/sshd.service\n" ), Some( "/system.slice/sshd.service" ) );
+    assert_eq!( parse_cgroup_v2_path( "12:pids:/user.slice\n1:name=systemd:/user.slice\n0::/user.slice/session-1.scope\n" ), Some( "/user.slice/session-1.scope" ) );
+    assert_eq!( parse_cgroup_v2_path( "12:pids:/user.slice\n1:name=systemd:/user.slice\n" ), None );
+    assert_eq!( parse_cgroup_v2_path( "" ), None );
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!( parse_size( "123" ), Some( 123 ) );
+    assert_eq!( parse_size( "4k" ), Some( 4096 ) );
+    assert_eq!( parse_size( "2M" ), Some( 2 * 1024 * 1024 ) );
+    assert_eq!( parse_size( "1G" ), Some( 1024 * 1024 * 1024 ) );
+    assert_eq!( parse_size( "18446744073709551615G" ), None );
+    assert_eq!( parse_size( "" ), None );
+    assert_eq!( parse_size( "K" ), None );
+}
+
+#[test]
+fn test_parse_hex_address() {
+    assert_eq!( parse_hex_address( "0x1F0" ), Some( 0x1F0 ) );
+    assert_eq!( parse_hex_address( "7fff0000" ), Some( 0x7fff0000 ) );
+    assert_eq!( parse_hex_address( "0xzz" ), None );
+    assert_eq!( parse_hex_address( "" ), None );
+}
+
+#[test]
+fn test_parse_hex_bytes() {
+    assert_eq!( parse_hex_bytes( "00ff1A" ), Some( vec![ 0x00, 0xff, 0x1a ] ) );
+    assert_eq!( parse_hex_bytes( "" ), Some( vec![] ) );
+    assert_eq!( parse_hex_bytes( "abc" ), None );
+    assert_eq!( parse_hex_bytes( "zz" ), None );
+    assert_eq!( to_hex( &[ 0x00, 0xff, 0x1a ] ), "00ff1a" );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_parse_cpu_list() {
+    assert_eq!( parse_cpu_list( "0-3,8,10-11\n" ), Some( vec![ 0, 1, 2, 3, 8, 10, 11 ] ) );
+    assert_eq!( parse_cpu_list( "0" ), Some( vec![ 0 ] ) );
+    assert_eq!( parse_cpu_list( "" ), None );
+    assert_eq!( parse_cpu_list( "0-x" ), None );
+}